@@ -0,0 +1,89 @@
+//! Measure how long it takes to print large synthetic docs of a few shapes: in full, as when
+//! saving, and partially, as when displaying a screenful around the cursor.
+//!
+//! Run with `cargo bench`, optionally followed by a filter: `cargo bench -- long_list`.
+
+use partial_pretty_printer as ppp;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use synless::{
+    make_synthetic_json, CaptureWindow, ColorTheme, DocName, Engine, Settings, SyntheticShape,
+};
+
+const JSON_PATH: &str = "data/json_lang.ron";
+const WINDOW_SIZE: ppp::Size = ppp::Size {
+    width: 120,
+    height: 40,
+};
+/// Run each benchmark for at least this long, and at least `MIN_ITERATIONS` times.
+const MIN_BENCH_TIME: Duration = Duration::from_secs(2);
+const MIN_ITERATIONS: usize = 5;
+
+fn main() {
+    // Cargo passes `--bench`; anything else is a filter on the benchmark names.
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let shapes = [
+        ("deep_nesting", SyntheticShape::DeepNesting { depth: 200 }),
+        ("long_list", SyntheticShape::LongList { len: 10_000 }),
+        ("long_text", SyntheticShape::LongText { len: 100_000 }),
+    ];
+    for (shape_name, shape) in shapes {
+        let mut engine = Engine::new(Settings::default());
+        let json_lang_ron = fs::read_to_string(JSON_PATH).unwrap();
+        engine
+            .load_language_ron(Path::new(JSON_PATH), &json_lang_ron)
+            .unwrap();
+        let root = make_synthetic_json(engine.raw_storage_mut(), shape).unwrap();
+        let doc_name = DocName::Auxilliary(shape_name.to_owned());
+        engine.add_doc(&doc_name, root, true).unwrap();
+        engine.set_visible_doc(&doc_name).unwrap();
+
+        run(&filter, shape_name, "print_source", &mut || {
+            engine.print_source(&doc_name).unwrap();
+        });
+        run(&filter, shape_name, "display_start", &mut || {
+            display(&engine)
+        });
+
+        // Partial printing has to work harder to display the end of the doc.
+        let s = engine.raw_storage();
+        let mut last = root;
+        while let Some(child) = last.last_child(s) {
+            last = child;
+        }
+        engine.goto_node(last).unwrap();
+        run(&filter, shape_name, "display_end", &mut || display(&engine));
+    }
+}
+
+fn display(engine: &Engine) {
+    let mut window = CaptureWindow::new(ColorTheme::default_dark(), WINDOW_SIZE);
+    engine.display_visible_doc(&mut window).unwrap();
+}
+
+fn run(filter: &Option<String>, shape_name: &str, bench_name: &str, f: &mut dyn FnMut()) {
+    let name = format!("{shape_name}/{bench_name}");
+    match filter {
+        Some(filter) if !name.contains(filter.as_str()) => (),
+        _ => bench(&name, f),
+    }
+}
+
+fn bench(name: &str, f: &mut dyn FnMut()) {
+    let mut times = Vec::new();
+    let start = Instant::now();
+    while times.len() < MIN_ITERATIONS || start.elapsed() < MIN_BENCH_TIME {
+        let iteration_start = Instant::now();
+        f();
+        times.push(iteration_start.elapsed());
+    }
+    times.sort();
+    let mean = times.iter().sum::<Duration>() / times.len() as u32;
+    let median = times[times.len() / 2];
+    println!(
+        "{name:<28} {:>6} iterations   mean {mean:>10.2?}   median {median:>10.2?}   min {:>10.2?}",
+        times.len(),
+        times[0],
+    );
+}
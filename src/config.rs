@@ -0,0 +1,265 @@
+//! The user's config file, `config.ron` in [`config_dir()`]. It's loaded on startup, and again
+//! whenever it changes or `s::reload_config()` is called.
+
+use crate::engine::LanguageSettings;
+use crate::util::{error, SynlessError};
+use partial_pretty_printer as ppp;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const CONFIG_FILE_NAME: &str = "config.ron";
+const USER_INIT_FILE_NAME: &str = "init.rhai";
+
+/// The contents of `config.ron`. Every field is optional, and settings that are left out keep
+/// their current values. For example:
+///
+/// ```ron
+/// Config(
+///     max_source_width: Some(80),
+///     theme: Some("gruvbox"),
+///     keybinding_profile: Some("vim"),
+///     languages: {
+///         "json": LanguageSettings(
+///             max_source_width: Some(120),
+///             display_notation: Some("compact"),
+///         ),
+///     },
+/// )
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The width to print docs at when saving them.
+    pub max_source_width: Option<ppp::Width>,
+    /// The widest to display the visible doc at.
+    pub max_display_width: Option<ppp::Width>,
+    /// How far down the screen to keep the cursor, as a fraction of the doc pane's height.
+    pub focus_height: Option<f32>,
+    pub show_scrollbar: Option<bool>,
+    /// The name of a color theme, as passed to `s::set_theme()`.
+    pub theme: Option<String>,
+    /// "default", "vim", or "emacs". Applied by `init.rhai`, in a `config_changed` hook.
+    pub keybinding_profile: Option<String>,
+    /// The path of a hunspell-style `.dic` file to spell check prose with. Without one, spell
+    /// checking is off.
+    pub dictionary: Option<String>,
+    /// Overrides for docs in particular languages, by language name.
+    pub languages: HashMap<String, LanguageSettings>,
+}
+
+impl Config {
+    /// Read the config file at `path`. If there isn't one, that's the same as an empty config.
+    pub fn load(path: &Path) -> Result<Config, SynlessError> {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => {
+                return Err(error!(
+                    FileSystem,
+                    "Failed to read config file '{}' ({err})",
+                    path.display()
+                ))
+            }
+        };
+        let config = ron::from_str::<Config>(&source)
+            .map_err(|err| error!(Parse, "Invalid config file '{}': {err}", path.display()))?;
+        if let Some(focus_height) = config.focus_height {
+            if !(0.0..=1.0).contains(&focus_height) {
+                return Err(error!(
+                    Parse,
+                    "Invalid focus_height {focus_height} in '{}': it must be between 0 and 1",
+                    path.display()
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The config as a rhai map, with `()` for each setting that isn't given. This is what's
+    /// passed to `config_changed` hooks.
+    pub fn to_rhai_map(&self) -> rhai::Map {
+        let mut languages = rhai::Map::new();
+        for (name, language_settings) in &self.languages {
+            languages.insert(
+                name.as_str().into(),
+                language_settings_to_rhai_map(language_settings).into(),
+            );
+        }
+
+        let mut map = rhai::Map::new();
+        map.insert("max_source_width".into(), width(self.max_source_width));
+        map.insert("max_display_width".into(), width(self.max_display_width));
+        map.insert(
+            "focus_height".into(),
+            opt(self.focus_height.map(|h| h as rhai::FLOAT)),
+        );
+        map.insert("show_scrollbar".into(), opt(self.show_scrollbar));
+        map.insert("theme".into(), opt(self.theme.clone()));
+        map.insert(
+            "keybinding_profile".into(),
+            opt(self.keybinding_profile.clone()),
+        );
+        map.insert("dictionary".into(), opt(self.dictionary.clone()));
+        map.insert("languages".into(), languages.into());
+        map
+    }
+}
+
+fn language_settings_to_rhai_map(settings: &LanguageSettings) -> rhai::Map {
+    let mut map = rhai::Map::new();
+    map.insert("max_source_width".into(), width(settings.max_source_width));
+    map.insert(
+        "max_display_width".into(),
+        width(settings.max_display_width),
+    );
+    map.insert(
+        "display_notation".into(),
+        opt(settings.display_notation.clone()),
+    );
+    map.insert(
+        "source_notation".into(),
+        opt(settings.source_notation.clone()),
+    );
+    map
+}
+
+fn opt<T: Into<rhai::Dynamic>>(value: Option<T>) -> rhai::Dynamic {
+    value.map(Into::into).unwrap_or(rhai::Dynamic::UNIT)
+}
+
+fn width(width: Option<ppp::Width>) -> rhai::Dynamic {
+    opt(width.map(|w| w as rhai::INT))
+}
+
+/// Where the user's configuration lives: `$XDG_CONFIG_HOME/synless`, or `~/.config/synless`.
+/// `None` if neither environment variable is set.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("synless"))
+}
+
+/// The path of the config file, if there's a config directory.
+pub fn config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join(CONFIG_FILE_NAME))
+}
+
+/// The path of the user's init script, if there's a config directory. It's run after the builtin
+/// `init.rhai`, so functions defined in it can be bound to keys.
+pub fn user_init_path() -> Option<PathBuf> {
+    Some(config_dir()?.join(USER_INIT_FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::util::ErrorCategory;
+
+    /// Write `source` to a config file for the test `test_name`, and load it.
+    fn load(test_name: &str, source: &str) -> Result<Config, SynlessError> {
+        let dir = TempDir::new(test_name);
+        let path = dir.path().join(CONFIG_FILE_NAME);
+        std::fs::write(&path, source).unwrap();
+        Config::load(&path)
+    }
+
+    #[test]
+    fn test_load_config() {
+        let config = load(
+            "test_load_config",
+            r#"Config(
+                max_source_width: Some(80),
+                focus_height: Some(0.25),
+                theme: Some("gruvbox"),
+                keybinding_profile: Some("vim"),
+                languages: {
+                    "json": LanguageSettings(
+                        max_source_width: Some(120),
+                        display_notation: Some("compact"),
+                    ),
+                },
+            )"#,
+        )
+        .unwrap();
+        assert_eq!(config.max_source_width, Some(80));
+        assert_eq!(config.max_display_width, None);
+        assert_eq!(config.focus_height, Some(0.25));
+        assert_eq!(config.show_scrollbar, None);
+        assert_eq!(config.theme.as_deref(), Some("gruvbox"));
+        assert_eq!(config.keybinding_profile.as_deref(), Some("vim"));
+        assert_eq!(config.dictionary, None);
+        let json = &config.languages["json"];
+        assert_eq!(json.max_source_width, Some(120));
+        assert_eq!(json.max_display_width, None);
+        assert_eq!(json.display_notation.as_deref(), Some("compact"));
+        assert_eq!(json.source_notation, None);
+
+        // Every field is optional.
+        let config = load("test_load_empty_config", "Config()").unwrap();
+        assert_eq!(config.max_source_width, None);
+        assert!(config.languages.is_empty());
+    }
+
+    #[test]
+    fn test_missing_config() {
+        let dir = TempDir::new("test_missing_config");
+        let path = dir.path().join(CONFIG_FILE_NAME);
+        let config = Config::load(&path).unwrap();
+        assert_eq!(config.theme, None);
+        assert!(config.languages.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let err = load("test_invalid_config", "Config(theme: 5)").unwrap_err();
+        assert_eq!(err.category, ErrorCategory::Parse);
+        assert!(err.message.starts_with("Invalid config file"));
+
+        for focus_height in ["-0.5", "1.5"] {
+            let source = format!("Config(focus_height: Some({focus_height}))");
+            let err = load("test_invalid_focus_height", &source).unwrap_err();
+            assert_eq!(err.category, ErrorCategory::Parse);
+            assert!(err.message.starts_with("Invalid focus_height"));
+        }
+        for focus_height in ["0.0", "1.0"] {
+            let source = format!("Config(focus_height: Some({focus_height}))");
+            load("test_valid_focus_height", &source).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_config_to_rhai_map() {
+        let config = load(
+            "test_config_to_rhai_map",
+            r#"Config(
+                max_display_width: Some(100),
+                show_scrollbar: Some(false),
+                dictionary: Some("en_US.dic"),
+                languages: { "json": LanguageSettings(source_notation: Some("pretty")) },
+            )"#,
+        )
+        .unwrap();
+        let map = config.to_rhai_map();
+        assert_eq!(map["max_display_width"].as_int(), Ok(100));
+        assert!(map["max_source_width"].is_unit());
+        assert!(map["focus_height"].is_unit());
+        assert_eq!(map["show_scrollbar"].as_bool(), Ok(false));
+        assert!(map["theme"].is_unit());
+        assert_eq!(
+            map["dictionary"].clone().into_string().as_deref(),
+            Ok("en_US.dic")
+        );
+
+        let languages = map["languages"].clone().cast::<rhai::Map>();
+        let json = languages["json"].clone().cast::<rhai::Map>();
+        assert_eq!(
+            json["source_notation"].clone().into_string().as_deref(),
+            Ok("pretty")
+        );
+        assert!(json["display_notation"].is_unit());
+        assert!(json["max_source_width"].is_unit());
+    }
+}
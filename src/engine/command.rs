@@ -1,6 +1,7 @@
 use super::search::Search;
 use crate::language::Storage;
-use crate::tree::Node;
+use crate::tree::{Node, NodeRemapping};
+use crate::util::SynlessBug;
 
 #[derive(Debug)]
 pub enum Command {
@@ -21,6 +22,8 @@ pub enum NavCommand {
     Text(TextNavCommand),
     Bookmark(BookmarkCommand),
     Search(SearchCommand),
+    Selection(SelectionCommand),
+    Cursors(CursorsCommand),
 }
 
 #[derive(Debug)]
@@ -28,6 +31,9 @@ pub enum TreeEdCommand {
     /// In a listy sequence, insert the given node after the cursor. In a fixed sequence, replace
     /// the node at the cursor with the given node. Either way, move the cursor to the new node.
     Insert(Node),
+    /// In a listy sequence, insert the given nodes after the cursor, in order, and move the
+    /// cursor to the last of them.
+    InsertMany(Vec<Node>),
     /// Replace the node at the cursor with the given node.
     Replace(Node),
     /// In a listy sequence, delete the node at the cursor and move the cursor to the left. In a
@@ -36,29 +42,75 @@ pub enum TreeEdCommand {
     /// In a listy sequence, delete the node at the cursor and move the cursor to the right. In a
     /// fixed sequence, replace the node at the cursor with a hole.
     Delete,
+    /// In a listy sequence, delete the given number of nodes, starting with the one at the cursor
+    /// and going right, and move the cursor to just before where they were.
+    DeleteMany(usize),
+    /// Delete the node at the cursor, putting its only child (not counting holes) in its place.
+    Unwrap,
+    /// In a table (a listy sequence of fixed nodes), replace every other cell in the cursor's
+    /// column with a copy of the node at the cursor.
+    FillColumn,
+    /// Replace the node at the cursor with the given node, and put the replaced node inside of
+    /// it. The given node must be either fixed, in which case the replaced node goes in its first
+    /// hole that allows it, or listy and empty.
+    Wrap(Node),
+    /// Like `Wrap`, but put the replaced node at the given index in the given node. This is the
+    /// inverse of `Unwrap`.
+    WrapAt(Node, usize),
+    /// Swap the node at the cursor with its previous sibling, keeping the cursor on it. While
+    /// there's a selection, instead rotate the selected nodes one place earlier, moving the first
+    /// to the end.
+    SwapPrev,
+    /// Swap the node at the cursor with its next sibling, keeping the cursor on it. While there's
+    /// a selection, instead rotate the selected nodes one place later, moving the last to the
+    /// start.
+    SwapNext,
+    /// Split the list that the cursor is in into two sibling lists, after the node at the cursor.
+    /// Move the cursor to the new second list.
+    SplitList,
+    /// Join the list at the cursor with the next sibling list of the same construct.
+    JoinLists,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum TextEdCommand {
     /// Insert the given character at the cursor position, moving the cursor after the
     /// new character.
     Insert(char),
-    /// Delete the character immediately before the cursor.
+    /// Delete the character immediately before the cursor, together with the rest of its grapheme
+    /// cluster (like the letter an accent is on).
     Backspace,
-    /// Delete the character immediately after the cursor.
+    /// Delete the character immediately after the cursor, together with the rest of its grapheme
+    /// cluster.
     Delete,
+    /// Delete just the one `char` before the cursor, even if it's part of a larger grapheme
+    /// cluster. This is how insertions are undone.
+    BackspaceChar,
+    /// Delete just the one `char` after the cursor, even if it's part of a larger grapheme
+    /// cluster.
+    DeleteChar,
+    /// Delete from the start of the word before the cursor up to the cursor.
+    BackspaceWord,
+    /// Delete from the cursor up to the end of the word after it.
+    DeleteWord,
 }
 
 // TODO: cut=copy,backspace  paste-copy=dup,paste
+/// Each entry on the clipboard stack is a sequence of nodes: usually just one, but copying a
+/// selection copies all of the selected nodes into a single entry.
 #[derive(Debug)]
 pub enum ClipboardCommand {
-    /// Copy the node at the cursor and push it onto the clipboard stack.
+    /// Copy the node at the cursor (or the selected nodes) and push it onto the clipboard stack.
     Copy,
-    /// Pop the top node from the clipboard stack, and insert it at the cursor (in the same manner
-    /// as [`TreeEdCommand::Insert`]).
+    /// Pop the top entry from the clipboard stack, and insert it at the cursor (in the same manner
+    /// as [`TreeEdCommand::Insert`]). An entry with several nodes can only be inserted into a
+    /// listy sequence.
     Paste,
     /// Swap the top node in the clipboard stack with the node at the cursor.
     PasteSwap,
+    /// Replace the node at the cursor with a copy of the top node in the clipboard stack, then
+    /// push the replaced node onto the clipboard stack.
+    PasteReplace,
     /// Duplicate the top node in the clipboard stack.
     Dup,
     /// Discard the top node in the clipboard stack.
@@ -66,7 +118,7 @@ pub enum ClipboardCommand {
 }
 
 // TODO: First set of user nav commands to try: down-left & down-right
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum TreeNavCommand {
     /// Move the cursor back one node.
     Prev,
@@ -93,15 +145,27 @@ pub enum TreeNavCommand {
     PrevText,
     /// Move the cursor to the next texty node.
     NextText,
+    /// Move the cursor to the previous hole.
+    PrevHole,
+    /// Move the cursor to the next hole.
+    NextHole,
+    /// In a table (a listy sequence of fixed nodes), move the cursor to the same column of the
+    /// previous row.
+    CellAbove,
+    /// In a table (a listy sequence of fixed nodes), move the cursor to the same column of the
+    /// next row.
+    CellBelow,
     /// If the node at the cursor is texty, enter text mode, placing the cursor at the
     /// end of the text.
     EnterText,
     /// Use this when the node at the cursor has just been `Insert`ed, to move the cursor to a
     /// convenient editing location.
     FirstInsertLoc,
+    /// Move the cursor to the given node, which must be in the same document (and not its root).
+    GotoNode(Node),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum TextNavCommand {
     /// Move the cursor back one character.
     Left,
@@ -111,6 +175,10 @@ pub enum TextNavCommand {
     Beginning,
     /// Move the cursor to the end of the text.
     End,
+    /// Move the cursor to the start of the word before it.
+    WordLeft,
+    /// Move the cursor to the end of the word after it.
+    WordRight,
     /// Exit text mode, keeping the edits.
     ExitText,
 }
@@ -124,6 +192,33 @@ pub enum BookmarkCommand {
     Goto(char),
 }
 
+/// A selection is a contiguous range of siblings, from the node where it was begun to the node
+/// at the cursor. While there's a selection, `Backspace`, `Delete`, `Wrap`, and `Copy` act on all
+/// of the selected nodes, and `SwapPrev` and `SwapNext` rotate them. Any other edit (other than a
+/// rotation), or moving the cursor away from the selected siblings, ends the selection.
+#[derive(Debug)]
+pub enum SelectionCommand {
+    /// Begin a selection at the node at the cursor. Moving the cursor among its siblings then
+    /// extends or shrinks the selection.
+    Begin,
+    /// End the selection, without changing the document.
+    Clear,
+}
+
+/// Besides the (primary) cursor, a doc can have any number of secondary cursors. Tree and text
+/// commands (both navigation and editing) are executed at every cursor. Other commands, like
+/// clipboard and search commands, only act at the primary cursor.
+#[derive(Debug)]
+pub enum CursorsCommand {
+    /// Leave a secondary cursor at the cursor's position, so that the cursor can then be moved
+    /// elsewhere.
+    Add,
+    /// Add a secondary cursor at every match of the current search.
+    AddAtMatches,
+    /// Remove all of the secondary cursors.
+    Clear,
+}
+
 #[derive(Debug)]
 pub enum SearchCommand {
     /// Set the search pattern.
@@ -136,13 +231,111 @@ pub enum SearchCommand {
     Next,
 }
 
+impl Command {
+    /// Whether executing this command could change the contents of the document (as opposed to
+    /// only the cursor or clipboard).
+    pub fn modifies_doc(&self) -> bool {
+        match self {
+            Command::Ed(_) => true,
+            Command::Clipboard(cmd) => match cmd {
+                ClipboardCommand::Copy | ClipboardCommand::Dup | ClipboardCommand::Pop => false,
+                ClipboardCommand::Paste
+                | ClipboardCommand::PasteSwap
+                | ClipboardCommand::PasteReplace => true,
+            },
+            Command::Nav(_) => false,
+        }
+    }
+
+    /// Make a copy of this command to execute at a secondary cursor, or `None` if it should only
+    /// be executed at the primary cursor (see [`CursorsCommand`]). Nodes are deep-copied.
+    pub fn duplicate(&self, s: &mut Storage) -> Option<Command> {
+        use TreeEdCommand::*;
+
+        match self {
+            Command::Ed(EdCommand::Tree(cmd)) => {
+                let copy = match cmd {
+                    Insert(node) => Insert(node.deep_copy(s)),
+                    InsertMany(nodes) => {
+                        InsertMany(nodes.iter().map(|node| node.deep_copy(s)).collect())
+                    }
+                    Replace(node) => Replace(node.deep_copy(s)),
+                    Wrap(node) => Wrap(node.deep_copy(s)),
+                    WrapAt(node, index) => WrapAt(node.deep_copy(s), *index),
+                    Backspace => Backspace,
+                    Delete => Delete,
+                    DeleteMany(count) => DeleteMany(*count),
+                    Unwrap => Unwrap,
+                    FillColumn => FillColumn,
+                    SwapPrev => SwapPrev,
+                    SwapNext => SwapNext,
+                    SplitList => SplitList,
+                    JoinLists => JoinLists,
+                };
+                Some(copy.into())
+            }
+            Command::Ed(EdCommand::Text(cmd)) => Some((*cmd).into()),
+            Command::Nav(NavCommand::Tree(TreeNavCommand::GotoNode(_))) => None,
+            Command::Nav(NavCommand::Tree(cmd)) => Some((*cmd).into()),
+            Command::Nav(NavCommand::Text(cmd)) => Some((*cmd).into()),
+            Command::Nav(_) | Command::Clipboard(_) => None,
+        }
+    }
+
+    /// The detached nodes that this command would put into the document, if any. If the command
+    /// fails, the nodes stay detached and must be deleted by the caller.
+    pub fn detached_nodes(&self) -> Vec<Node> {
+        match self {
+            Command::Ed(cmd) => cmd.detached_nodes(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// What kind of command this is, for timing commands by kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Command::Ed(EdCommand::Tree(_)) => "command: tree_ed",
+            Command::Ed(EdCommand::Text(_)) => "command: text_ed",
+            Command::Clipboard(_) => "command: clipboard",
+            Command::Nav(NavCommand::Tree(_)) => "command: tree_nav",
+            Command::Nav(NavCommand::Text(_)) => "command: text_nav",
+            Command::Nav(NavCommand::Bookmark(_)) => "command: bookmark",
+            Command::Nav(NavCommand::Search(_)) => "command: search",
+            Command::Nav(NavCommand::Selection(_)) => "command: selection",
+            Command::Nav(NavCommand::Cursors(_)) => "command: cursors",
+        }
+    }
+}
+
 impl EdCommand {
+    /// The detached nodes that this command would put into the document, if any.
+    pub fn detached_nodes(&self) -> Vec<Node> {
+        use TreeEdCommand::*;
+
+        match self {
+            EdCommand::Tree(Insert(node) | Replace(node) | Wrap(node) | WrapAt(node, _)) => {
+                vec![*node]
+            }
+            EdCommand::Tree(InsertMany(nodes)) => nodes.clone(),
+            _ => Vec::new(),
+        }
+    }
+
     pub fn delete_trees(self, s: &mut Storage) {
         match self {
             EdCommand::Tree(cmd) => cmd.delete_trees(s),
             EdCommand::Text(_) => (),
         }
     }
+
+    /// Update the command's nodes after the nodes were compacted. See
+    /// `Storage::compact_nodes()`.
+    pub fn remap(&mut self, remapping: &NodeRemapping) {
+        match self {
+            EdCommand::Tree(cmd) => cmd.remap(remapping),
+            EdCommand::Text(_) => (),
+        }
+    }
 }
 
 impl TreeEdCommand {
@@ -150,8 +343,33 @@ impl TreeEdCommand {
         use TreeEdCommand::*;
 
         match self {
-            Insert(node) | Replace(node) => node.delete_root(s),
-            Backspace | Delete => (),
+            Insert(node) | Replace(node) | Wrap(node) | WrapAt(node, _) => {
+                node.delete_root(s).bug()
+            }
+            InsertMany(nodes) => {
+                for node in nodes {
+                    node.delete_root(s).bug();
+                }
+            }
+            Backspace | Delete | DeleteMany(_) | Unwrap | FillColumn | SwapPrev | SwapNext
+            | SplitList | JoinLists => (),
+        }
+    }
+
+    fn remap(&mut self, remapping: &NodeRemapping) {
+        use TreeEdCommand::*;
+
+        match self {
+            Insert(node) | Replace(node) | Wrap(node) | WrapAt(node, _) => {
+                *node = remapping.get(*node).bug()
+            }
+            InsertMany(nodes) => {
+                for node in nodes {
+                    *node = remapping.get(*node).bug();
+                }
+            }
+            Backspace | Delete | DeleteMany(_) | Unwrap | FillColumn | SwapPrev | SwapNext
+            | SplitList | JoinLists => (),
         }
     }
 }
@@ -245,3 +463,27 @@ impl From<SearchCommand> for Command {
         Command::Nav(NavCommand::Search(cmd))
     }
 }
+
+impl From<SelectionCommand> for NavCommand {
+    fn from(cmd: SelectionCommand) -> NavCommand {
+        NavCommand::Selection(cmd)
+    }
+}
+
+impl From<SelectionCommand> for Command {
+    fn from(cmd: SelectionCommand) -> Command {
+        Command::Nav(NavCommand::Selection(cmd))
+    }
+}
+
+impl From<CursorsCommand> for NavCommand {
+    fn from(cmd: CursorsCommand) -> NavCommand {
+        NavCommand::Cursors(cmd)
+    }
+}
+
+impl From<CursorsCommand> for Command {
+    fn from(cmd: CursorsCommand) -> Command {
+        Command::Nav(NavCommand::Cursors(cmd))
+    }
+}
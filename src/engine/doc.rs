@@ -1,13 +1,29 @@
 use super::command::{
-    BookmarkCommand, ClipboardCommand, Command, EdCommand, NavCommand, SearchCommand,
-    TextEdCommand, TextNavCommand, TreeEdCommand, TreeNavCommand,
+    BookmarkCommand, ClipboardCommand, Command, CursorsCommand, EdCommand, NavCommand,
+    SearchCommand, SelectionCommand, TextEdCommand, TextNavCommand, TreeEdCommand, TreeNavCommand,
 };
+use super::json_schema::JsonSchema;
+use super::lint::Diagnostic;
 use super::search::Search;
-use crate::language::Storage;
+use super::spell_check::Misspelling;
+use super::undo_cache::{SavedEdCommand, SavedHistory, SavedHistoryState, SavedUndoGroup};
+use crate::language::{Arity, Language, Storage};
 use crate::pretty_doc::DocRef;
-use crate::tree::{Bookmark, Location, Mode, Node};
-use crate::util::{bug_assert, error, SynlessBug, SynlessError};
-use std::collections::HashMap;
+use crate::tree::{
+    Bookmark, Location, MergeConflict, Mode, Node, NodeChange, NodeId, NodeRemapping,
+    SavedLocation, TreeError,
+};
+use crate::util::{bug, bug_assert, error, SynlessBug, SynlessError};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime};
+
+/// Used to give every version of every doc a distinct number. See [`Doc::version`].
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
 
 /// A set of changes that can be undone/redone all at once.
 #[derive(Debug)]
@@ -15,8 +31,56 @@ pub struct UndoGroup {
     /// The position of the cursor before the first command was executed (where it should be
     /// restored to after undo-ing).
     restore_loc: Location,
-    /// To undo using a (loc, cmd) pair, goto loc then execute cmd. Stored as a stack.
-    commands: Vec<(Location, EdCommand)>,
+    /// `restore_loc` in a form that can be saved to disk, recorded when the group was started.
+    saved_restore_loc: SavedLocation,
+    /// Stored as a stack.
+    commands: Vec<Undo>,
+    /// When the edits in this group were originally made. Preserved across undo/redo.
+    time: Instant,
+}
+
+/// One command of an undo group: to execute it, goto `loc` then execute `cmd`.
+#[derive(Debug)]
+struct Undo {
+    loc: Location,
+    cmd: EdCommand,
+    /// `loc` and `cmd` in a form that can be saved to disk. They're saved as soon as the undo is
+    /// made, since that's when the tree is as it will be when the undo is executed.
+    saved: (SavedLocation, SavedEdCommand),
+}
+
+/// A summary of one undo group, for browsing a document's edit history.
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryEntry {
+    /// When the edits were originally made.
+    pub time: Instant,
+    pub num_tree_edits: usize,
+    pub num_text_edits: usize,
+}
+
+/// One state in a document's undo history tree, for visualizing it. See [`Doc::history_tree`].
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryTreeNode {
+    /// Identifies the state, for [`Doc::goto_history_state`].
+    pub id: usize,
+    pub parent: Option<usize>,
+    /// The edits that led to this state from its parent. `None` for the original state.
+    pub entry: Option<HistoryEntry>,
+}
+
+/// A state in a document's undo history. The states form a tree rooted at the state the document
+/// was in when it was opened, so that making edits after undoing starts a new branch instead of
+/// discarding the edits that were undone.
+#[derive(Debug)]
+struct HistoryState {
+    parent: Option<usize>,
+    /// The edits between the parent state and this one. If the document is in this state or one
+    /// of its descendants, this is the group that undoes them. Otherwise it's the group that
+    /// redoes them. `None` only for the root state.
+    group: Option<UndoGroup>,
+    children: Vec<usize>,
+    /// The child that `redo()` goes to: whichever was most recently made or undone from.
+    redo_child: Option<usize>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -29,6 +93,12 @@ pub enum EditError {
     NothingToUndo,
     #[error("Nothing to redo")]
     NothingToRedo,
+    #[error("No other branch of the undo history here")]
+    NoOtherBranch,
+    #[error("No such state in the undo history")]
+    NoSuchHistoryState,
+    #[error("Saved undo history doesn't match the document")]
+    InvalidSavedHistory,
     #[error("Cannot move there")]
     CannotMove,
     #[error("No previous match")]
@@ -43,12 +113,34 @@ pub enum EditError {
     CannotDeleteChar,
     #[error("Cannot place that node here")]
     CannotPlaceNode,
+    #[error("Not in a table")]
+    NotInTable,
+    #[error("Can only split a list between two of its elements, inside of another list")]
+    CannotSplitList,
+    #[error("Can only join a list with the next one, if they're the same construct")]
+    CannotJoinLists,
     #[error("No node to act on here")]
     NoNodeHere,
+    #[error("Not at a search match")]
+    NoMatchHere,
     #[error("Clipboard is empty")]
     EmptyClipboard,
+    #[error("Clipboard holds several nodes, but only one can go here")]
+    SeveralNodesOnClipboard,
+    #[error("Document is read-only")]
+    ReadOnly,
     #[error("Text is invalid. Either fix it or revert.")]
     InvalidText,
+    #[error("No dictionary to spell check with")]
+    NoDictionary,
+    #[error("No misspelled word here")]
+    NoMisspellingHere,
+    #[error("Already searching incrementally")]
+    AlreadySearching,
+    #[error("No incremental search in progress")]
+    NoIncrementalSearch,
+    #[error("{0}")]
+    Tree(#[from] TreeError),
 }
 
 impl From<EditError> for SynlessError {
@@ -62,24 +154,61 @@ impl From<EditError> for SynlessError {
 enum SavePoint {
     /// Not saved.
     None,
-    /// Saved before the n'th UndoGroup in the `undo_stack`.
-    Undo(usize),
+    /// Saved in the n'th state of the `history`.
+    State(usize),
     /// Saved after the edits in the `recent` UndoGroup.
     /// INVARIANT: Doc::recent must be Some(_).
     Recent,
-    /// Saved before the n'th UndoGroup in the `redo_stack`.
-    Redo(usize),
+}
+
+/// How things were when an incremental search began, to put back if it's cancelled.
+#[derive(Debug)]
+struct IncrementalSearch {
+    origin: Location,
+    prev_search: Option<Search>,
 }
 
 #[derive(Debug)]
 pub struct Doc {
     cursor: Location,
-    undo_stack: Vec<UndoGroup>,
+    /// Every state the document has been in. The root, the original state, is at index 0.
+    history: Vec<HistoryState>,
+    /// The index of the state the document is in, not counting the `recent` edits.
+    state: usize,
     recent: Option<UndoGroup>,
-    redo_stack: Vec<UndoGroup>,
     bookmarks: HashMap<char, Bookmark>,
     save_point: SavePoint,
     search: Option<Search>,
+    /// Set while an incremental search is in progress. Meanwhile, `search` is the search being
+    /// previewed.
+    incremental_search: Option<IncrementalSearch>,
+    /// A node to highlight as the place where a node being dragged with the mouse would go.
+    drop_target: Option<Node>,
+    /// Where the selection began, if there is one. See [`SelectionCommand`].
+    selection_anchor: Option<Node>,
+    /// Additional cursors, at which tree and text commands are also executed. See
+    /// [`CursorsCommand`]. Never contains the primary cursor or duplicates.
+    secondary_cursors: Vec<Location>,
+    /// If set, only commands that don't modify the document may be executed.
+    read_only: bool,
+    /// The problems found by the most recent run of the lint rules, in document order. They stay
+    /// attached to their nodes as the doc is edited, until the rules are run again.
+    diagnostics: Vec<Diagnostic>,
+    /// If set, the doc is validated against this schema whenever its diagnostics are set.
+    json_schema: Option<JsonSchema>,
+    /// The misspelled words found by the most recent spell check, in document order. Like
+    /// diagnostics, they're only updated when the doc is checked again.
+    misspellings: Vec<Misspelling>,
+    /// The nodes that `misspellings` are in, to look them up quickly when printing.
+    misspelled_nodes: HashSet<NodeId>,
+    /// How the doc's nodes differ from another version of it (its git `HEAD` version, or the
+    /// other side of a diff), as of when they were last compared. Empty if they aren't being
+    /// shown.
+    changes: HashMap<NodeId, NodeChange>,
+    /// If the doc is the result of a merge, the conflicts that the merge found. They're always
+    /// marked in `changes`.
+    conflicts: Vec<MergeConflict>,
+    version: u64,
 }
 
 impl Doc {
@@ -94,15 +223,32 @@ impl Doc {
             cursor: Location::before_children(s, root_node)
                 .bug_msg("Root constructs must be able to have at least 1 child"),
             recent: None,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+            history: vec![HistoryState {
+                parent: None,
+                group: None,
+                children: Vec::new(),
+                redo_child: None,
+            }],
+            state: 0,
             bookmarks: HashMap::new(),
             save_point: if is_saved {
-                SavePoint::Undo(0)
+                SavePoint::State(0)
             } else {
                 SavePoint::None
             },
             search: None,
+            incremental_search: None,
+            drop_target: None,
+            selection_anchor: None,
+            secondary_cursors: Vec::new(),
+            read_only: false,
+            diagnostics: Vec::new(),
+            json_schema: None,
+            misspellings: Vec::new(),
+            misspelled_nodes: HashSet::new(),
+            changes: HashMap::new(),
+            conflicts: Vec::new(),
+            version: next_version(),
         })
     }
 
@@ -115,37 +261,429 @@ impl Doc {
         DocRef::new_source(s, opt_cursor, self.cursor.root_node(s))
     }
 
-    pub fn doc_ref_display<'d>(&'d self, s: &'d Storage, highlight_cursor: bool) -> DocRef<'d> {
+    pub fn doc_ref_display<'d>(
+        &'d self,
+        s: &'d Storage,
+        highlight_cursor: bool,
+        show_hidden: bool,
+    ) -> DocRef<'d> {
         let opt_cursor = if highlight_cursor {
             Some(self.cursor)
         } else {
             None
         };
-        DocRef::new_display(s, opt_cursor, self.cursor.root_node(s), &self.search)
+        let opt_selection = if highlight_cursor {
+            self.selection(s)
+                .map(|nodes| (nodes[0], *nodes.last().bug()))
+        } else {
+            None
+        };
+        DocRef::new_display(
+            s,
+            opt_cursor,
+            self.cursor.root_node(s),
+            &self.search,
+            self.drop_target,
+            show_hidden,
+        )
+        .with_selection(opt_selection)
+        .with_secondary_cursors(if highlight_cursor {
+            &self.secondary_cursors
+        } else {
+            &[]
+        })
+        .with_diagnostics(&self.diagnostics)
+        .with_misspellings(&self.misspelled_nodes)
+        .with_changes(&self.changes)
     }
 
     pub fn cursor(&self) -> Location {
         self.cursor
     }
 
+    /// The language of the doc's root node.
+    pub fn language(&self, s: &Storage) -> Language {
+        self.cursor.root_node(s).language(s)
+    }
+
+    pub fn search(&self) -> Option<&Search> {
+        self.search.as_ref()
+    }
+
+    /// Begin an incremental search from the cursor, which must be in tree mode. Until it's ended
+    /// with [`Doc::end_incremental_search()`], searches are tried out with
+    /// [`Doc::preview_search()`].
+    pub fn begin_incremental_search(&mut self) -> Result<(), EditError> {
+        if self.incremental_search.is_some() {
+            return Err(EditError::AlreadySearching);
+        }
+        if self.mode() != Mode::Tree {
+            return Err(EditError::NotInTreeMode);
+        }
+        self.incremental_search = Some(IncrementalSearch {
+            origin: self.cursor,
+            prev_search: self.search.take(),
+        });
+        Ok(())
+    }
+
+    /// Replace the search being previewed with `search` (or with no search at all), and move the
+    /// cursor to its nearest match: the first one after where the incremental search began, or
+    /// else the last one before it. If there's no match, the cursor goes back to where the
+    /// incremental search began. Returns whether there's a match.
+    pub fn preview_search(
+        &mut self,
+        s: &mut Storage,
+        search: Option<Search>,
+    ) -> Result<bool, EditError> {
+        let origin = match &self.incremental_search {
+            Some(incremental_search) => incremental_search.origin,
+            None => {
+                if let Some(search) = search {
+                    search.delete(s);
+                }
+                return Err(EditError::NoIncrementalSearch);
+            }
+        };
+        if let Some(old_search) = self.search.take() {
+            old_search.delete(s);
+        }
+        let nearest_match = search.as_ref().and_then(|search| {
+            let is_match = |node| search.matches(s, node);
+            origin
+                .next_match(s, is_match)
+                .or_else(|| origin.prev_match(s, is_match))
+        });
+        self.cursor = nearest_match.unwrap_or(origin);
+        self.search = search;
+        Ok(nearest_match.is_some())
+    }
+
+    /// End the incremental search. If `commit`, the previewed search becomes the doc's search,
+    /// and the cursor stays at its match (though if no search is being previewed, the search from
+    /// before is kept instead). Otherwise the search and the cursor go back to how they were when
+    /// the incremental search began.
+    pub fn end_incremental_search(
+        &mut self,
+        s: &mut Storage,
+        commit: bool,
+    ) -> Result<(), EditError> {
+        let incremental_search = self
+            .incremental_search
+            .take()
+            .ok_or(EditError::NoIncrementalSearch)?;
+        if commit && self.search.is_some() {
+            if let Some(prev_search) = incremental_search.prev_search {
+                prev_search.delete(s);
+            }
+        } else {
+            if let Some(search) = self.search.take() {
+                search.delete(s);
+            }
+            self.search = incremental_search.prev_search;
+        }
+        if !commit {
+            self.cursor = incremental_search.origin;
+        }
+        Ok(())
+    }
+
     pub fn node_at_cursor(&self, s: &Storage) -> Result<Node, EditError> {
         self.cursor.at_node(s).ok_or(EditError::NoNodeHere)
     }
 
+    pub fn secondary_cursors(&self) -> &[Location] {
+        &self.secondary_cursors
+    }
+
+    /// The selected nodes, in order, if there's a selection.
+    pub fn selection(&self, s: &Storage) -> Option<Vec<Node>> {
+        self.cursor.selection(s, self.selection_anchor?)
+    }
+
     pub fn mode(&self) -> Mode {
         self.cursor.mode()
     }
 
-    /// Executes a single command. Clears the redo stack if it was an editing command (but not if
-    /// it was a navigation command).
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// A number that changes whenever the document's contents change. No two docs ever share a
+    /// version, so it can be used to tell whether something computed from a doc is stale.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Add `nodes` as the last children of `container`, which is in this doc. This is part of
+    /// loading the doc, not an edit: it can't be undone, and doesn't make the doc unsaved.
+    pub fn append_loaded_nodes(&mut self, s: &mut Storage, container: Node, nodes: Vec<Node>) {
+        let num_children = container.num_children(s).bug();
+        container
+            .insert_children(s, num_children, &nodes)
+            .bug_msg("append_loaded_nodes: wrong arity");
+        self.version = next_version();
+    }
+
+    /// Move the cursor to the very end of the document.
+    pub fn goto_end(&mut self, s: &Storage) {
+        let root = self.cursor.root_node(s);
+        self.cursor = Location::after_children(s, root)
+            .bug_msg("Root constructs must be able to have at least 1 child");
+    }
+
+    pub fn set_drop_target(&mut self, drop_target: Option<Node>) {
+        self.drop_target = drop_target;
+    }
+
+    /// Find the node in this document with the given id, if any.
+    pub fn find_node(&self, s: &Storage, id: NodeId) -> Option<Node> {
+        let mut node = self.cursor.root_node(s);
+        loop {
+            if node.id(s) == id {
+                return Some(node);
+            }
+            node = node.next_inorder(s)?;
+        }
+    }
+
+    /// The number of holes anywhere in the document.
+    pub fn num_holes(&self, s: &Storage) -> usize {
+        let mut count = 0;
+        let mut node = self.cursor.root_node(s);
+        while let Some(next_node) = node.next_inorder(s) {
+            if next_node.is_hole(s) {
+                count += 1;
+            }
+            node = next_node;
+        }
+        count
+    }
+
+    /// Every node in the document (except the root), in document order.
+    pub fn nodes(&self, s: &Storage) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        let mut node = self.cursor.root_node(s);
+        while let Some(next_node) = node.next_inorder(s) {
+            nodes.push(next_node);
+            node = next_node;
+        }
+        nodes
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn set_diagnostics(&mut self, diagnostics: Vec<Diagnostic>) {
+        self.diagnostics = diagnostics;
+    }
+
+    pub fn set_changes(&mut self, changes: HashMap<NodeId, NodeChange>) {
+        self.changes = changes;
+        for conflict in &self.conflicts {
+            self.changes.insert(conflict.node, NodeChange::Conflict);
+        }
+    }
+
+    pub fn conflicts(&self) -> &[MergeConflict] {
+        &self.conflicts
+    }
+
+    /// Replace the doc's merge conflicts, deleting the old ones.
+    pub fn set_conflicts(&mut self, s: &mut Storage, conflicts: Vec<MergeConflict>) {
+        for conflict in self.conflicts.drain(..) {
+            self.changes.remove(&conflict.node);
+            conflict.delete(s);
+        }
+        self.conflicts = conflicts;
+        for conflict in &self.conflicts {
+            self.changes.insert(conflict.node, NodeChange::Conflict);
+        }
+    }
+
+    pub fn json_schema(&self) -> Option<&JsonSchema> {
+        self.json_schema.as_ref()
+    }
+
+    pub fn set_json_schema(&mut self, schema: Option<JsonSchema>) {
+        self.json_schema = schema;
+    }
+
+    pub fn misspellings(&self) -> &[Misspelling] {
+        &self.misspellings
+    }
+
+    pub fn set_misspellings(&mut self, misspellings: Vec<Misspelling>) {
+        self.misspelled_nodes = misspellings
+            .iter()
+            .map(|misspelling| misspelling.node)
+            .collect();
+        self.misspellings = misspellings;
+    }
+
+    /// The misspelled word that the cursor is in (or just after), or else the first one in the
+    /// node at the cursor. Misspellings whose text has since been edited don't count.
+    pub fn misspelling_at_cursor(&self, s: &Storage) -> Option<&Misspelling> {
+        let is_current = |misspelling: &Misspelling, node: Node| {
+            let text = node.text(s).bug();
+            misspelling.start <= text.num_chars()
+                && text
+                    .as_split_str(misspelling.start)
+                    .1
+                    .starts_with(&misspelling.word)
+        };
+        let (node, char_index) = match self.cursor.text_pos() {
+            Some((node, char_index)) => (node, Some(char_index)),
+            None => (self.cursor.at_node(s)?, None),
+        };
+        let id = node.id(s);
+        self.misspellings.iter().find(|misspelling| {
+            let is_under_cursor = match char_index {
+                Some(char_index) => {
+                    misspelling.start <= char_index
+                        && char_index <= misspelling.start + misspelling.word.chars().count()
+                }
+                None => true,
+            };
+            misspelling.node == id && is_under_cursor && is_current(misspelling, node)
+        })
+    }
+
+    /// Every node in the document whose construct is hidden, in document order.
+    pub fn hidden_nodes(&self, s: &Storage) -> Vec<Node> {
+        let mut hidden = Vec::new();
+        let mut node = self.cursor.root_node(s);
+        while let Some(next_node) = node.next_inorder(s) {
+            if next_node.is_hidden(s) {
+                hidden.push(next_node);
+            }
+            node = next_node;
+        }
+        hidden
+    }
+
+    /// Executes a single command. If it was an editing command, its edits start a new branch of
+    /// the undo history (or continue the `recent` group).
+    ///
+    /// Tree and text commands are then executed at each secondary cursor too, and all of the
+    /// resulting edits go in the same undo group. If the command fails at the primary cursor,
+    /// nothing happens. If it fails at a secondary cursor, that cursor is left where it was.
     pub fn execute(
         &mut self,
         s: &mut Storage,
         cmd: Command,
-        clipboard: &mut Vec<Node>,
+        clipboard: &mut Vec<Vec<Node>>,
+    ) -> Result<(), EditError> {
+        if self.read_only && cmd.modifies_doc() {
+            return Err(EditError::ReadOnly);
+        }
+        let mut copies = Vec::new();
+        for _ in &self.secondary_cursors {
+            match cmd.duplicate(s) {
+                Some(copy) => copies.push(copy),
+                None => break,
+            }
+        }
+        let restore_loc = self.cursor;
+        let starts_undo_group = self.recent.is_none();
+        let saved_restore_loc =
+            (starts_undo_group && !copies.is_empty()).then(|| restore_loc.save(s));
+        if let Err(err) = self.execute_at_cursor(s, cmd, clipboard) {
+            for copy in copies {
+                delete_detached_nodes(s, copy);
+            }
+            return Err(err);
+        }
+        if copies.is_empty() {
+            return Ok(());
+        }
+
+        let selection_anchor = self.selection_anchor.take();
+        let mut secondary_cursors = std::mem::take(&mut self.secondary_cursors);
+        for (secondary_cursor, copy) in secondary_cursors.iter_mut().zip(copies) {
+            // Earlier edits may have moved or deleted the node this cursor was at.
+            let loc = match self
+                .cursor
+                .validate_bookmark(s, secondary_cursor.bookmark())
+            {
+                Some(loc) => loc,
+                None => {
+                    delete_detached_nodes(s, copy);
+                    continue;
+                }
+            };
+            let primary_cursor = std::mem::replace(&mut self.cursor, loc);
+            let detached_nodes = copy.detached_nodes();
+            if self.execute_at_cursor(s, copy, clipboard).is_err() {
+                for node in detached_nodes {
+                    node.delete_root(s).bug();
+                }
+            }
+            *secondary_cursor = self.cursor;
+            self.cursor = self
+                .cursor
+                .validate_bookmark(s, primary_cursor.bookmark())
+                .unwrap_or(self.cursor);
+        }
+        self.secondary_cursors = secondary_cursors;
+        self.selection_anchor = selection_anchor;
+        self.retain_valid_cursors(s);
+
+        // Undoing should put the cursor back where the primary cursor was, no matter which cursor
+        // made the first edit.
+        if starts_undo_group {
+            if let Some(recent) = &mut self.recent {
+                recent.restore_loc = restore_loc;
+                recent.saved_restore_loc = saved_restore_loc.bug();
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute a command at the (primary) cursor only.
+    fn execute_at_cursor(
+        &mut self,
+        s: &mut Storage,
+        cmd: Command,
+        clipboard: &mut Vec<Vec<Node>>,
     ) -> Result<(), EditError> {
         let restore_loc = self.cursor;
+        let saved_restore_loc = match cmd {
+            Command::Ed(_) | Command::Clipboard(_) if self.recent.is_none() => {
+                Some(restore_loc.save(s))
+            }
+            _ => None,
+        };
+        let selection = self.selection(s);
         let undos = match cmd {
+            Command::Ed(EdCommand::Tree(cmd)) if selection.is_some() => {
+                let selection = selection.bug();
+                // Rotating keeps the same places selected, so that it can be repeated.
+                let rotated_anchor = match cmd {
+                    TreeEdCommand::SwapPrev | TreeEdCommand::SwapNext if selection.len() > 1 => {
+                        let anchor = self.selection_anchor.bug();
+                        let parent = anchor.parent(s).bug();
+                        Some((parent, anchor.sibling_index(s)))
+                    }
+                    _ => None,
+                };
+                self.selection_anchor = None;
+                let undos = execute_tree_ed_on_selection(s, cmd, &mut self.cursor, selection)?;
+                if let Some((parent, index)) = rotated_anchor {
+                    self.selection_anchor = parent.nth_child(s, index);
+                }
+                undos
+            }
+            Command::Clipboard(ClipboardCommand::Copy) if selection.is_some() => {
+                let copies = selection.bug().into_iter().map(|node| node.deep_copy(s));
+                clipboard.push(copies.collect());
+                Vec::new()
+            }
             Command::Ed(cmd) => execute_ed(s, cmd, &mut self.cursor)?,
             Command::Clipboard(cmd) => execute_clipboard(s, cmd, &mut self.cursor, clipboard)?,
             Command::Nav(cmd) => {
@@ -155,18 +693,23 @@ impl Doc {
                     &mut self.cursor,
                     &mut self.bookmarks,
                     &mut self.search,
+                    &mut self.selection_anchor,
+                    &mut self.secondary_cursors,
                 )?;
                 Vec::new()
             }
         };
+        if !undos.is_empty() || self.selection(s).is_none() {
+            self.selection_anchor = None;
+        }
         if undos.is_empty() {
             return Ok(());
         }
-        self.clear_redos(s);
+        self.version = next_version();
         if let Some(recent) = &mut self.recent {
             recent.commands.extend(undos);
         } else {
-            self.recent = Some(UndoGroup::new(restore_loc, undos));
+            self.recent = Some(UndoGroup::new(restore_loc, saved_restore_loc.bug(), undos));
         }
         if self.save_point == SavePoint::Recent {
             // Someone managed to save in between two edits in an undo group.
@@ -175,14 +718,84 @@ impl Doc {
         Ok(())
     }
 
+    /// Replace the contents of the doc with the children of `new_root`, as a single undoable edit.
+    /// `new_root` must be a root of the same construct as the doc's root; it's deleted. The cursor
+    /// stays where it was, if that location still exists.
+    pub fn replace_contents(&mut self, s: &mut Storage, new_root: Node) -> Result<(), EditError> {
+        if self.read_only {
+            new_root.delete_root(s).bug();
+            return Err(EditError::ReadOnly);
+        }
+        let root = self.cursor.root_node(s);
+        bug_assert!(
+            new_root.construct(s) == root.construct(s),
+            "replace_contents: different root constructs"
+        );
+        self.end_undo_group();
+        let saved_cursor = self.cursor.save(s);
+        self.selection_anchor = None;
+        self.secondary_cursors.clear();
+
+        let mut new_children = Vec::new();
+        let mut opt_child = new_root.first_child(s);
+        while let Some(child) = opt_child {
+            new_children.push(child.deep_copy(s));
+            opt_child = child.next_sibling(s);
+        }
+        new_root.delete_root(s).bug();
+
+        let mut clipboard = Vec::new();
+        match root.arity(s) {
+            Arity::Fixed(_) => {
+                for (i, new_child) in new_children.into_iter().enumerate() {
+                    self.cursor = Location::at(s, root.nth_child(s, i).bug());
+                    let cmd = TreeEdCommand::Replace(new_child).into();
+                    self.execute_at_cursor(s, cmd, &mut clipboard)
+                        .bug_msg("replace_contents: failed to replace child");
+                }
+            }
+            Arity::Listy(_) => {
+                while let Some(child) = root.first_child(s) {
+                    self.cursor = Location::at(s, child);
+                    self.execute_at_cursor(s, TreeEdCommand::Delete.into(), &mut clipboard)
+                        .bug_msg("replace_contents: failed to delete child");
+                }
+                self.cursor = Location::before_children(s, root).bug();
+                for new_child in new_children {
+                    let cmd = TreeEdCommand::Insert(new_child).into();
+                    self.execute_at_cursor(s, cmd, &mut clipboard)
+                        .bug_msg("replace_contents: failed to insert child");
+                }
+            }
+            Arity::Texty => bug!("replace_contents: texty root"),
+        }
+        self.end_undo_group();
+
+        self.cursor = Location::restore(s, root, &saved_cursor)
+            .or_else(|| Location::before_children(s, root))
+            .bug();
+        self.version = next_version();
+        Ok(())
+    }
+
     /// Groups together all editing commands that have been `.execute()`ed since the last call to
     /// `.end_undo_group()`. They will be treated as a single unit ("undo group") by calls to
-    /// `.undo()` and `.redo()`.
+    /// `.undo()` and `.redo()`, and become a new state in the undo history.
     pub fn end_undo_group(&mut self) {
         if let Some(recent) = self.recent.take() {
-            self.undo_stack.push(recent);
+            let id = self.history.len();
+            self.history.push(HistoryState {
+                parent: Some(self.state),
+                group: Some(recent),
+                children: Vec::new(),
+                redo_child: None,
+            });
+            let parent = &mut self.history[self.state];
+            parent.children.push(id);
+            parent.redo_child = Some(id);
+            self.state = id;
             if self.save_point == SavePoint::Recent {
-                self.save_point = SavePoint::Undo(self.undo_stack.len());
+                self.save_point = SavePoint::State(id);
             }
         }
     }
@@ -192,6 +805,7 @@ impl Doc {
         if let Some(recent) = self.recent.take() {
             let redos = recent.execute(s, &mut self.cursor);
             redos.delete_trees(s);
+            self.version = next_version();
             if self.save_point == SavePoint::Recent {
                 self.save_point = SavePoint::None;
             }
@@ -200,37 +814,38 @@ impl Doc {
         if let Some(new_cursor) = self.cursor.exit_text() {
             self.cursor = new_cursor;
         }
+        self.retain_valid_cursors(s);
     }
 
-    /// Undoes the last undo group on the undo stack and moves it to the redo stack.
-    /// Returns `Err(EditError::NothingToUndo)` if the undo stack is empty.
+    /// Undoes the edits that led to the current state, moving to its parent in the undo history.
+    /// Returns `Err(EditError::NothingToUndo)` if in the original state.
     /// If there were recent edits _not_ completed with a call to end_undo_group(),
     /// the group is automatically ended and then undone.
     pub fn undo(&mut self, s: &mut Storage) -> Result<(), EditError> {
         self.end_undo_group();
 
-        let undo_group = self.undo_stack.pop().ok_or(EditError::NothingToUndo)?;
-        let redo_group = undo_group.execute(s, &mut self.cursor);
-        self.redo_stack.push(redo_group);
-        if self.save_point == SavePoint::Undo(self.undo_stack.len() + 1) {
-            self.save_point = SavePoint::Redo(self.redo_stack.len() - 1);
-        }
+        let state = self.state;
+        let parent = self.history[state].parent.ok_or(EditError::NothingToUndo)?;
+        self.execute_history_group(s, state);
+        self.history[parent].redo_child = Some(state);
+        self.state = parent;
+        self.version = next_version();
+        self.retain_valid_cursors(s);
         Ok(())
     }
 
-    /// Redoes the last undo group on the redo stack and moves it to the undo stack.
-    /// Returns EditError::NothingToRedo if the redo stack is empty.
+    /// Redoes the edits that lead to the most recently visited child of the current state in the
+    /// undo history. Returns EditError::NothingToRedo if it has no children.
     pub fn redo(&mut self, s: &mut Storage) -> Result<(), EditError> {
-        let redo_group = self.redo_stack.pop().ok_or(EditError::NothingToRedo)?;
-        bug_assert!(
-            self.recent.is_none(),
-            "redo: recent edits should have cleared the redo stack"
-        );
-        let undo_group = redo_group.execute(s, &mut self.cursor);
-        self.undo_stack.push(undo_group);
-        if self.save_point == SavePoint::Redo(self.redo_stack.len()) {
-            self.save_point = SavePoint::Undo(self.undo_stack.len());
-        }
+        self.end_undo_group();
+
+        let child = self.history[self.state]
+            .redo_child
+            .ok_or(EditError::NothingToRedo)?;
+        self.execute_history_group(s, child);
+        self.state = child;
+        self.version = next_version();
+        self.retain_valid_cursors(s);
         Ok(())
     }
 
@@ -238,69 +853,457 @@ impl Doc {
         self.save_point = if self.recent.is_some() {
             SavePoint::Recent
         } else {
-            SavePoint::Undo(self.undo_stack.len())
+            SavePoint::State(self.state)
         };
     }
 
+    /// Record that the doc no longer matches its file, for example because the file was changed
+    /// by another program.
+    pub fn mark_as_unsaved(&mut self) {
+        self.save_point = SavePoint::None;
+    }
+
     pub fn has_unsaved_changes(&self) -> bool {
         if self.recent.is_some() {
             self.save_point != SavePoint::Recent
         } else {
-            self.save_point != SavePoint::Undo(self.undo_stack.len())
+            self.save_point != SavePoint::State(self.state)
+        }
+    }
+
+    /// The undo groups along the current branch of the document's history, oldest first: those
+    /// that led to the current state, then those that `redo()` would redo. See
+    /// [`Doc::history_position`].
+    pub fn history(&self) -> Vec<HistoryEntry> {
+        let mut entries = self
+            .current_branch()
+            .into_iter()
+            .filter_map(|id| self.history[id].group.as_ref())
+            .map(UndoGroup::summary)
+            .collect::<Vec<_>>();
+        if let Some(recent) = &self.recent {
+            // The recent edits will start a new branch, so nothing after them can be redone.
+            entries.truncate(self.history_position() - 1);
+            entries.push(recent.summary());
+        }
+        entries
+    }
+
+    /// How many of the groups in [`Doc::history`] are currently applied.
+    pub fn history_position(&self) -> usize {
+        self.ancestors(self.state).len() - 1 + self.recent.iter().count()
+    }
+
+    /// Undo or redo until exactly `position` groups of [`Doc::history`] are applied.
+    pub fn goto_history_position(
+        &mut self,
+        s: &mut Storage,
+        position: usize,
+    ) -> Result<(), EditError> {
+        self.end_undo_group();
+        let branch = self.current_branch();
+        let target = *branch.get(position).ok_or(EditError::NothingToRedo)?;
+        self.goto_history_state(s, target)
+    }
+
+    /// Every state in the document's undo history tree, in the order they were made (so parents
+    /// come before their children). Uncompleted recent edits are included as a child of the
+    /// current state.
+    pub fn history_tree(&self) -> Vec<HistoryTreeNode> {
+        let mut nodes = self
+            .history
+            .iter()
+            .enumerate()
+            .map(|(id, state)| HistoryTreeNode {
+                id,
+                parent: state.parent,
+                entry: state.group.as_ref().map(UndoGroup::summary),
+            })
+            .collect::<Vec<_>>();
+        if let Some(recent) = &self.recent {
+            nodes.push(HistoryTreeNode {
+                id: self.history.len(),
+                parent: Some(self.state),
+                entry: Some(recent.summary()),
+            });
+        }
+        nodes
+    }
+
+    /// The id of the current state in [`Doc::history_tree`].
+    pub fn history_state(&self) -> usize {
+        if self.recent.is_some() {
+            self.history.len()
+        } else {
+            self.state
         }
     }
 
+    /// Undo and redo until the document is in the given state of [`Doc::history_tree`], which may
+    /// be on another branch.
+    pub fn goto_history_state(&mut self, s: &mut Storage, id: usize) -> Result<(), EditError> {
+        self.end_undo_group();
+        if id >= self.history.len() {
+            return Err(EditError::NoSuchHistoryState);
+        }
+        let path = self.ancestors(id);
+        while !path.contains(&self.state) {
+            self.undo(s)?;
+        }
+        let start = path
+            .iter()
+            .position(|ancestor| *ancestor == self.state)
+            .bug();
+        for child in path[..start].iter().rev() {
+            self.history[self.state].redo_child = Some(*child);
+            self.redo(s)?;
+        }
+        Ok(())
+    }
+
+    /// Move to the previous (if `forward` is false) or next sibling of the current state in the
+    /// undo history, wrapping around: that is, to the same point on another branch.
+    pub fn switch_history_branch(
+        &mut self,
+        s: &mut Storage,
+        forward: bool,
+    ) -> Result<(), EditError> {
+        self.end_undo_group();
+        let parent = self.history[self.state]
+            .parent
+            .ok_or(EditError::NoOtherBranch)?;
+        let siblings = &self.history[parent].children;
+        if siblings.len() < 2 {
+            return Err(EditError::NoOtherBranch);
+        }
+        let index = siblings.iter().position(|id| *id == self.state).bug();
+        let new_index = if forward {
+            (index + 1) % siblings.len()
+        } else {
+            (index + siblings.len() - 1) % siblings.len()
+        };
+        let target = siblings[new_index];
+        self.goto_history_state(s, target)
+    }
+
+    /// Save the whole undo history, so that it can be restored with `restore_history()` into a doc
+    /// with the same contents. Each undo group is saved in the form it's stored in now: the groups
+    /// between the current state and the root undo their edits, and the rest redo them. Ends the
+    /// current undo group.
+    pub fn save_history(&mut self) -> SavedHistory {
+        self.end_undo_group();
+        let states = self
+            .history
+            .iter()
+            .map(|state| SavedHistoryState {
+                parent: state.parent,
+                redo_child: state.redo_child,
+                group: state.group.as_ref().map(UndoGroup::save),
+            })
+            .collect();
+        SavedHistory {
+            states,
+            current: self.state,
+        }
+    }
+
+    /// Replace the (empty) undo history of a freshly opened doc with one saved by
+    /// `save_history()`. The doc must have the same contents it had when the history was saved.
+    /// If this fails, the doc may have been partially modified, and should be discarded.
+    pub fn restore_history(
+        &mut self,
+        s: &mut Storage,
+        saved: &SavedHistory,
+    ) -> Result<(), EditError> {
+        bug_assert!(
+            self.history.len() == 1 && self.recent.is_none(),
+            "restore_history: doc already has history"
+        );
+        let is_valid = saved.current < saved.states.len()
+            && saved.states.iter().enumerate().all(|(id, state)| {
+                match (id, state.parent, &state.group) {
+                    (0, None, None) => true,
+                    (_, Some(parent), Some(_)) => parent < id,
+                    _ => false,
+                }
+            });
+        if !is_valid {
+            return Err(EditError::InvalidSavedHistory);
+        }
+        let cursor = self.cursor;
+        self.history = saved
+            .states
+            .iter()
+            .map(|state| HistoryState {
+                parent: state.parent,
+                group: None,
+                children: Vec::new(),
+                redo_child: None,
+            })
+            .collect();
+        for id in 1..self.history.len() {
+            let parent = self.history[id].parent.bug();
+            self.history[parent].children.push(id);
+        }
+        self.state = saved.current;
+
+        // The groups were saved in the form they were in then: first undo from the current state
+        // to the root...
+        while let Some(parent) = self.history[self.state].parent {
+            let group = saved.states[self.state].group.as_ref().bug();
+            self.history[self.state].group = Some(self.execute_saved_group(s, group)?);
+            self.state = parent;
+        }
+        // ...then redo into each other state, from its parent.
+        for id in 1..self.history.len() {
+            if self.history[id].group.is_none() {
+                let parent = self.history[id].parent.bug();
+                self.goto_history_state(s, parent)?;
+                let group = saved.states[id].group.as_ref().bug();
+                self.history[id].group = Some(self.execute_saved_group(s, group)?);
+                self.state = id;
+            }
+        }
+        self.goto_history_state(s, saved.current)?;
+
+        for (state, saved_state) in self.history.iter_mut().zip(&saved.states) {
+            state.redo_child = saved_state
+                .redo_child
+                .filter(|child| state.children.contains(child))
+                .or(state.children.last().copied());
+        }
+        self.cursor = cursor;
+        if self.save_point == SavePoint::State(0) {
+            self.save_point = SavePoint::State(saved.current);
+        }
+        self.version = next_version();
+        Ok(())
+    }
+
     /// Deletes the document and all of its nodes.
     pub fn delete(mut self, s: &mut Storage) {
-        self.clear_undos(s);
-        self.clear_redos(s);
+        self.clear_history(s);
         let root = self.cursor.root_node(s);
-        root.delete_root(s);
+        root.delete_root(s).bug();
         if let Some(search) = self.search {
             search.delete(s);
         }
+        if let Some(IncrementalSearch {
+            prev_search: Some(prev_search),
+            ..
+        }) = self.incremental_search
+        {
+            prev_search.delete(s);
+        }
+        for conflict in self.conflicts {
+            conflict.delete(s);
+        }
     }
 
-    fn clear_redos(&mut self, s: &mut Storage) {
-        for group in self.redo_stack.drain(..) {
-            group.delete_trees(s);
+    /// Update every node this doc refers to after the nodes were compacted, including those in
+    /// its undo history. See `Storage::compact_nodes()`. Bookmarks to nodes that had already been
+    /// deleted are dropped.
+    pub fn remap(&mut self, remapping: &NodeRemapping) {
+        self.cursor = self.cursor.remap(remapping).bug();
+        for state in &mut self.history {
+            if let Some(group) = &mut state.group {
+                group.remap(remapping);
+            }
         }
-        if let SavePoint::Redo(_) = self.save_point {
-            self.save_point = SavePoint::None;
+        if let Some(group) = &mut self.recent {
+            group.remap(remapping);
+        }
+        self.bookmarks = self
+            .bookmarks
+            .drain()
+            .filter_map(|(key, mark)| Some((key, mark.remap(remapping)?)))
+            .collect();
+        if let Some(search) = &mut self.search {
+            search.remap(remapping);
+        }
+        if let Some(incremental_search) = &mut self.incremental_search {
+            incremental_search.origin = incremental_search.origin.remap(remapping).bug();
+            if let Some(prev_search) = &mut incremental_search.prev_search {
+                prev_search.remap(remapping);
+            }
+        }
+        self.drop_target = self.drop_target.and_then(|node| remapping.get(node));
+        self.selection_anchor = self.selection_anchor.and_then(|node| remapping.get(node));
+        self.secondary_cursors = self
+            .secondary_cursors
+            .iter()
+            .filter_map(|loc| loc.remap(remapping))
+            .collect();
+        for conflict in &mut self.conflicts {
+            conflict.remap(remapping);
         }
     }
 
-    fn clear_undos(&mut self, s: &mut Storage) {
-        for group in self.undo_stack.drain(..) {
-            group.delete_trees(s);
+    /// Drop secondary cursors whose nodes are no longer in the document, as well as any that have
+    /// ended up at the same location as another cursor.
+    fn retain_valid_cursors(&mut self, s: &Storage) {
+        let cursor = self.cursor;
+        let mut valid_cursors = Vec::new();
+        for loc in self.secondary_cursors.drain(..) {
+            if let Some(loc) = cursor.validate_bookmark(s, loc.bookmark()) {
+                if loc != cursor && !valid_cursors.contains(&loc) {
+                    valid_cursors.push(loc);
+                }
+            }
+        }
+        self.secondary_cursors = valid_cursors;
+    }
+
+    /// Execute the group stored in state `id` (which undoes or redoes the edits between it and its
+    /// parent), and store the group that reverses it in its place. Doesn't change `self.state`.
+    fn execute_history_group(&mut self, s: &mut Storage, id: usize) {
+        let group = self.history[id].group.take().bug();
+        let reverse_group = group.execute(s, &mut self.cursor);
+        self.history[id].group = Some(reverse_group);
+        self.version = next_version();
+    }
+
+    /// Execute a group saved by `save_history()`, returning the group that reverses it.
+    fn execute_saved_group(
+        &mut self,
+        s: &mut Storage,
+        saved: &SavedUndoGroup,
+    ) -> Result<UndoGroup, EditError> {
+        let root = self.cursor.root_node(s);
+        let language = root.language(s);
+        let mut redo_restore_loc = None;
+        let mut redos = Vec::new();
+        for (saved_loc, saved_cmd) in &saved.commands {
+            let result = Location::restore(s, root, saved_loc)
+                .ok_or(EditError::InvalidSavedHistory)
+                .and_then(|loc| Ok((loc, saved_cmd.restore(s, language)?)));
+            let result = result.and_then(|(loc, cmd)| {
+                let detached_nodes = cmd.detached_nodes();
+                self.cursor = loc;
+                let result = execute_ed(s, cmd, &mut self.cursor);
+                if result.is_err() {
+                    for node in detached_nodes {
+                        node.delete_root(s).bug();
+                    }
+                }
+                Ok((loc, result?))
+            });
+            match result {
+                Ok((loc, undos)) => {
+                    redo_restore_loc.get_or_insert((loc, saved_loc.clone()));
+                    redos.extend(undos);
+                }
+                Err(err) => {
+                    for undo in redos {
+                        undo.cmd.delete_trees(s);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let restore_loc = Location::restore(s, root, &saved.restore_loc);
+        let (restore_loc, (redo_restore_loc, saved_redo_restore_loc)) =
+            match (restore_loc, redo_restore_loc) {
+                (Some(restore_loc), Some(redo_restore_loc)) if !redos.is_empty() => {
+                    (restore_loc, redo_restore_loc)
+                }
+                _ => {
+                    for undo in redos {
+                        undo.cmd.delete_trees(s);
+                    }
+                    return Err(EditError::InvalidSavedHistory);
+                }
+            };
+        self.cursor = restore_loc;
+        if let Some(new_cursor) = self.cursor.exit_text() {
+            self.cursor = new_cursor;
+        }
+        Ok(UndoGroup {
+            time: instant_from_system_time(saved.time),
+            ..UndoGroup::new(redo_restore_loc, saved_redo_restore_loc, redos)
+        })
+    }
+
+    /// The states from `id` up to the root of the undo history, inclusive.
+    fn ancestors(&self, id: usize) -> Vec<usize> {
+        let mut ancestors = vec![id];
+        while let Some(parent) = self.history[*ancestors.last().bug()].parent {
+            ancestors.push(parent);
+        }
+        ancestors
+    }
+
+    /// The states from the root of the undo history to the current state, and then on through
+    /// each state's `redo_child`.
+    fn current_branch(&self) -> Vec<usize> {
+        let mut branch = self.ancestors(self.state);
+        branch.reverse();
+        while let Some(child) = self.history[*branch.last().bug()].redo_child {
+            branch.push(child);
+        }
+        branch
+    }
+
+    fn clear_history(&mut self, s: &mut Storage) {
+        for state in self.history.drain(..) {
+            if let Some(group) = state.group {
+                group.delete_trees(s);
+            }
         }
         if let Some(group) = self.recent.take() {
             group.delete_trees(s);
         }
-        if matches!(self.save_point, SavePoint::Undo(_) | SavePoint::Recent) {
-            self.save_point = SavePoint::None;
-        }
+        self.save_point = SavePoint::None;
     }
 }
 
 impl UndoGroup {
-    fn new(restore_loc: Location, commands: Vec<(Location, EdCommand)>) -> UndoGroup {
+    fn new(
+        restore_loc: Location,
+        saved_restore_loc: SavedLocation,
+        commands: Vec<Undo>,
+    ) -> UndoGroup {
         bug_assert!(!commands.is_empty(), "empty undo group");
         UndoGroup {
             restore_loc,
+            saved_restore_loc,
             commands,
+            time: Instant::now(),
         }
     }
 
+    fn summary(&self) -> HistoryEntry {
+        let num_tree_edits = self
+            .commands
+            .iter()
+            .filter(|undo| matches!(undo.cmd, EdCommand::Tree(_)))
+            .count();
+        HistoryEntry {
+            time: self.time,
+            num_tree_edits,
+            num_text_edits: self.commands.len() - num_tree_edits,
+        }
+    }
+
+    fn remap(&mut self, remapping: &NodeRemapping) {
+        self.restore_loc = self.restore_loc.remap(remapping).bug();
+        for undo in &mut self.commands {
+            undo.loc = undo.loc.remap(remapping).bug();
+            undo.cmd.remap(remapping);
+        }
+    }
+
+    /// Execute the group, returning the group that reverses it.
     fn execute(self, s: &mut Storage, cursor: &mut Location) -> UndoGroup {
         let mut redo_restore_loc = None;
         let mut redos = Vec::new();
-        for (loc, cmd) in self.commands.into_iter().rev() {
+        for undo in self.commands.into_iter().rev() {
             if redo_restore_loc.is_none() {
-                redo_restore_loc = Some(loc);
+                redo_restore_loc = Some((undo.loc, undo.saved.0));
             }
-            jump_to(s, cursor, loc);
-            redos.extend(execute_ed(s, cmd, cursor).bug_msg("Failed to undo/redo"));
+            jump_to(s, cursor, undo.loc);
+            redos.extend(execute_ed(s, undo.cmd, cursor).bug_msg("Failed to undo/redo"));
         }
 
         jump_to(s, cursor, self.restore_loc);
@@ -308,16 +1311,61 @@ impl UndoGroup {
         if let Some(new_cursor) = cursor.exit_text() {
             *cursor = new_cursor;
         }
-        UndoGroup::new(redo_restore_loc.bug(), redos)
+        let (redo_restore_loc, saved_redo_restore_loc) = redo_restore_loc.bug();
+        UndoGroup {
+            time: self.time,
+            ..UndoGroup::new(redo_restore_loc, saved_redo_restore_loc, redos)
+        }
+    }
+
+    /// The group in a form that can be saved to disk, made from the saved forms recorded along
+    /// with its commands.
+    fn save(&self) -> SavedUndoGroup {
+        SavedUndoGroup {
+            restore_loc: self.saved_restore_loc.clone(),
+            commands: self
+                .commands
+                .iter()
+                .rev()
+                .map(|undo| undo.saved.clone())
+                .collect(),
+            time: system_time_from_instant(self.time),
+        }
     }
 
     fn delete_trees(self, s: &mut Storage) {
-        for (_loc, cmd) in self.commands {
-            cmd.delete_trees(s);
+        for undo in self.commands {
+            undo.cmd.delete_trees(s);
         }
     }
 }
 
+impl Undo {
+    fn new(s: &Storage, loc: Location, cmd: impl Into<EdCommand>) -> Undo {
+        let cmd = cmd.into();
+        let saved = (loc.save(s), SavedEdCommand::save(s, &cmd));
+        Undo { loc, cmd, saved }
+    }
+}
+
+fn system_time_from_instant(instant: Instant) -> SystemTime {
+    SystemTime::now()
+        .checked_sub(instant.elapsed())
+        .unwrap_or_else(SystemTime::now)
+}
+
+fn instant_from_system_time(time: SystemTime) -> Instant {
+    let age = SystemTime::now().duration_since(time).unwrap_or_default();
+    Instant::now().checked_sub(age).unwrap_or_else(Instant::now)
+}
+
+/// Delete the nodes that `cmd` would have put into the document, now that it won't be executed.
+fn delete_detached_nodes(s: &mut Storage, cmd: Command) {
+    for node in cmd.detached_nodes() {
+        node.delete_root(s).bug();
+    }
+}
+
 fn jump_to(s: &Storage, cursor: &mut Location, loc: Location) {
     bug_assert!(
         cursor.validate_bookmark(s, loc.bookmark()).is_some(),
@@ -330,7 +1378,7 @@ fn execute_ed(
     s: &mut Storage,
     cmd: EdCommand,
     cursor: &mut Location,
-) -> Result<Vec<(Location, EdCommand)>, EditError> {
+) -> Result<Vec<Undo>, EditError> {
     match cmd {
         EdCommand::Tree(cmd) => execute_tree_ed(s, cmd, cursor),
         EdCommand::Text(cmd) => execute_text_ed(s, cmd, cursor),
@@ -343,12 +1391,16 @@ fn execute_nav(
     cursor: &mut Location,
     bookmarks: &mut HashMap<char, Bookmark>,
     search: &mut Option<Search>,
+    selection_anchor: &mut Option<Node>,
+    secondary_cursors: &mut Vec<Location>,
 ) -> Result<(), EditError> {
     match cmd {
         NavCommand::Tree(cmd) => execute_tree_nav(s, cmd, cursor),
         NavCommand::Text(cmd) => execute_text_nav(s, cmd, cursor),
         NavCommand::Bookmark(cmd) => execute_bookmark(s, cmd, cursor, bookmarks),
         NavCommand::Search(cmd) => execute_search(s, cmd, cursor, search),
+        NavCommand::Selection(cmd) => execute_selection(s, cmd, cursor, selection_anchor),
+        NavCommand::Cursors(cmd) => execute_cursors(s, cmd, cursor, secondary_cursors, search),
     }
 }
 
@@ -356,7 +1408,7 @@ fn execute_tree_ed(
     s: &mut Storage,
     cmd: TreeEdCommand,
     cursor: &mut Location,
-) -> Result<Vec<(Location, EdCommand)>, EditError> {
+) -> Result<Vec<Undo>, EditError> {
     use TreeEdCommand::*;
 
     if cursor.mode() != Mode::Tree {
@@ -365,112 +1417,459 @@ fn execute_tree_ed(
 
     match cmd {
         Insert(node) => match cursor.insert(s, node) {
-            Ok(None) => Ok(vec![(*cursor, Backspace.into())]),
-            Ok(Some(detached_node)) => Ok(vec![(*cursor, Insert(detached_node).into())]),
-            Err(()) => Err(EditError::CannotPlaceNode),
+            Ok(None) => Ok(vec![Undo::new(s, *cursor, Backspace)]),
+            Ok(Some(detached_node)) => Ok(vec![Undo::new(s, *cursor, Insert(detached_node))]),
+            Err(err) => Err(err.into()),
         },
+        InsertMany(nodes) => {
+            cursor.insert_many(s, &nodes)?;
+            let first = Location::at(s, nodes[0]);
+            Ok(vec![Undo::new(s, first, DeleteMany(nodes.len()))])
+        }
         Replace(new_node) => {
             let old_node = cursor.at_node(s).ok_or(EditError::NoNodeHere)?;
-            if old_node.swap(s, new_node) {
-                *cursor = Location::at(s, new_node);
-                Ok(vec![(*cursor, Replace(old_node).into())])
-            } else {
-                Err(EditError::CannotPlaceNode)
-            }
+            old_node.swap(s, new_node)?;
+            *cursor = Location::at(s, new_node);
+            Ok(vec![Undo::new(s, *cursor, Replace(old_node))])
         }
         Backspace => {
             let (old_node, undo_location) = cursor.delete(s, true).ok_or(EditError::NoNodeHere)?;
-            Ok(vec![(undo_location, Insert(old_node).into())])
+            Ok(vec![Undo::new(s, undo_location, Insert(old_node))])
         }
         Delete => {
             let (old_node, undo_location) = cursor.delete(s, false).ok_or(EditError::NoNodeHere)?;
-            Ok(vec![(undo_location, Insert(old_node).into())])
+            Ok(vec![Undo::new(s, undo_location, Insert(old_node))])
+        }
+        DeleteMany(count) => {
+            let (old_nodes, undo_location) =
+                cursor.delete_many(s, count).ok_or(EditError::NoNodeHere)?;
+            Ok(vec![Undo::new(s, undo_location, InsertMany(old_nodes))])
+        }
+        Unwrap => {
+            let (old_node, index) = cursor.unwrap_node(s)?;
+            Ok(vec![Undo::new(s, *cursor, WrapAt(old_node, index))])
+        }
+        FillColumn => {
+            let node = cursor.at_node(s).ok_or(EditError::NoNodeHere)?;
+            let cells = cursor.column_cells(s).ok_or(EditError::NotInTable)?;
+            let mut undos = Vec::new();
+            for cell in cells {
+                if cell == node {
+                    continue;
+                }
+                let copy = node.deep_copy(s);
+                if cell.swap(s, copy).is_ok() {
+                    undos.push(Undo::new(s, Location::at(s, copy), Replace(cell)));
+                } else {
+                    copy.delete_root(s).bug();
+                }
+            }
+            Ok(undos)
+        }
+        Wrap(wrapper) => {
+            cursor.wrap_node(s, wrapper)?;
+            Ok(vec![Undo::new(s, *cursor, Unwrap)])
+        }
+        WrapAt(wrapper, index) => {
+            cursor.wrap_node_at(s, wrapper, index)?;
+            Ok(vec![Undo::new(s, *cursor, Unwrap)])
+        }
+        SwapPrev => swap_sibling(s, cursor, false),
+        SwapNext => swap_sibling(s, cursor, true),
+        SplitList => split_list(s, cursor),
+        JoinLists => join_lists(s, cursor),
+    }
+}
+
+/// Execute a tree editing command while there's a selection. `Backspace`, `Delete`, and `Wrap`
+/// act on every selected node, and `SwapPrev` and `SwapNext` rotate them; other commands act on
+/// just the node at the cursor, as usual.
+fn execute_tree_ed_on_selection(
+    s: &mut Storage,
+    cmd: TreeEdCommand,
+    cursor: &mut Location,
+    selection: Vec<Node>,
+) -> Result<Vec<Undo>, EditError> {
+    use TreeEdCommand::*;
+
+    let in_listy = match selection[0].parent(s) {
+        Some(parent) => matches!(parent.arity(s), Arity::Listy(_)),
+        None => false,
+    };
+    match cmd {
+        Backspace | Delete if in_listy => {
+            // Delete them all at once, leaving the cursor where deleting them one at a time would.
+            *cursor = Location::at(s, selection[0]);
+            let undos = execute_tree_ed(s, DeleteMany(selection.len()), cursor)?;
+            if matches!(cmd, Delete) {
+                if let Some(next_loc) = cursor.next_sibling(s) {
+                    *cursor = next_loc;
+                }
+            }
+            Ok(undos)
+        }
+        Backspace | Delete => {
+            let move_left = matches!(cmd, Backspace);
+            let nodes: Vec<Node> = if move_left {
+                selection.into_iter().rev().collect()
+            } else {
+                selection
+            };
+            let mut undos = Vec::new();
+            for node in nodes {
+                *cursor = Location::at(s, node);
+                let cmd = if move_left { Backspace } else { Delete };
+                // Nodes that can't be deleted (like holes) are skipped.
+                if let Ok(node_undos) = execute_tree_ed(s, cmd, cursor) {
+                    undos.extend(node_undos);
+                }
+            }
+            if undos.is_empty() {
+                return Err(EditError::NoNodeHere);
+            }
+            Ok(undos)
+        }
+        Wrap(wrapper) if selection.len() > 1 => wrap_selection(s, wrapper, cursor, selection),
+        SwapPrev if selection.len() > 1 => rotate_selection(s, cursor, selection, false),
+        SwapNext if selection.len() > 1 => rotate_selection(s, cursor, selection, true),
+        cmd => execute_tree_ed(s, cmd, cursor),
+    }
+}
+
+/// Wrap several siblings in `wrapper`, which must be listy and empty. This is done with the
+/// ordinary commands, so that it can be undone: wrap the first node, delete the others, and then
+/// insert copies of them into the wrapper.
+fn wrap_selection(
+    s: &mut Storage,
+    wrapper: Node,
+    cursor: &mut Location,
+    selection: Vec<Node>,
+) -> Result<Vec<Undo>, EditError> {
+    use TreeEdCommand::{DeleteMany, InsertMany, Wrap};
+
+    let parent = selection[0].parent(s).bug();
+    if !matches!(parent.arity(s), Arity::Listy(_)) {
+        return Err(EditError::CannotPlaceNode);
+    }
+    let sort = match wrapper.arity(s) {
+        Arity::Listy(sort) => sort,
+        Arity::Texty | Arity::Fixed(_) => {
+            let name = wrapper.construct(s).name(s).to_owned();
+            return Err(TreeError::InvalidWrapper(name).into());
+        }
+    };
+    for node in &selection {
+        let construct = node.construct(s);
+        if !sort.accepts(s, construct) {
+            return Err(TreeError::NotAllowedHere(construct.name(s).to_owned()).into());
+        }
+    }
+
+    *cursor = Location::at(s, selection[0]);
+    let mut undos = execute_tree_ed(s, Wrap(wrapper), cursor)?;
+    let copies = selection[1..]
+        .iter()
+        .map(|node| node.deep_copy(s))
+        .collect::<Vec<_>>();
+    *cursor = Location::at(s, selection[1]);
+    let delete = DeleteMany(copies.len());
+    undos.extend(execute_tree_ed(s, delete, cursor).bug_msg("wrap_selection: delete"));
+    *cursor = Location::at(s, selection[0]);
+    let insert = InsertMany(copies);
+    undos.extend(execute_tree_ed(s, insert, cursor).bug_msg("wrap_selection: insert"));
+    *cursor = Location::at(s, wrapper);
+    Ok(undos)
+}
+
+/// Swap the node at the cursor with its next sibling (if `forwards`) or previous sibling.
+fn swap_sibling(
+    s: &mut Storage,
+    cursor: &mut Location,
+    forwards: bool,
+) -> Result<Vec<Undo>, EditError> {
+    use TreeEdCommand::{SwapNext, SwapPrev};
+
+    let node = cursor.at_node(s).ok_or(EditError::NoNodeHere)?;
+    let sibling = if forwards {
+        node.next_sibling(s)
+    } else {
+        node.prev_sibling(s)
+    };
+    node.swap(s, sibling.ok_or(EditError::CannotMove)?)?;
+    let undo = if forwards { SwapPrev } else { SwapNext };
+    Ok(vec![Undo::new(s, *cursor, undo)])
+}
+
+/// Rotate the selected siblings by one place: later (if `forwards`), moving the last node to the
+/// start, or earlier, moving the first node to the end. This is done by repeatedly swapping the
+/// node that wraps around with its neighbor. Afterwards the cursor is at whichever node is now in
+/// its place.
+fn rotate_selection(
+    s: &mut Storage,
+    cursor: &mut Location,
+    selection: Vec<Node>,
+    forwards: bool,
+) -> Result<Vec<Undo>, EditError> {
+    // In a fixed sequence, every node must be allowed in every selected position.
+    for node in &selection {
+        for place in &selection {
+            if !Location::at(s, *place).can_insert(s, *node) {
+                let name = node.construct(s).name(s).to_owned();
+                return Err(TreeError::NotAllowedHere(name).into());
+            }
         }
     }
+
+    let cursor_index = cursor
+        .at_node(s)
+        .and_then(|node| selection.iter().position(|sel| *sel == node))
+        .bug_msg("rotate_selection: cursor not in selection");
+    let len = selection.len();
+    let (moving_node, offset) = if forwards {
+        (selection[len - 1], len - 1)
+    } else {
+        (selection[0], 1)
+    };
+    *cursor = Location::at(s, moving_node);
+    let mut undos = Vec::new();
+    for _ in 1..len {
+        undos.extend(swap_sibling(s, cursor, !forwards).bug_msg("rotate_selection: swap"));
+    }
+    *cursor = Location::at(s, selection[(cursor_index + offset) % len]);
+    Ok(undos)
+}
+
+/// Split the list containing the cursor in two, after the node at the cursor. This is done with
+/// the ordinary commands, so that it can be undone: delete the nodes after the cursor, insert a
+/// new empty list after the old one, and then insert copies of them into it.
+fn split_list(s: &mut Storage, cursor: &mut Location) -> Result<Vec<Undo>, EditError> {
+    use TreeEdCommand::{DeleteMany, Insert, InsertMany};
+
+    let node = cursor.at_node(s).ok_or(EditError::CannotSplitList)?;
+    let list = node.parent(s).ok_or(EditError::CannotSplitList)?;
+    let outer_list = list.parent(s).ok_or(EditError::CannotSplitList)?;
+    if !matches!(list.arity(s), Arity::Listy(_)) || !matches!(outer_list.arity(s), Arity::Listy(_))
+    {
+        return Err(EditError::CannotSplitList);
+    }
+    let mut rest = Vec::new();
+    let mut sibling = node.next_sibling(s);
+    while let Some(node) = sibling {
+        rest.push(node);
+        sibling = node.next_sibling(s);
+    }
+    if rest.is_empty() {
+        return Err(EditError::CannotSplitList);
+    }
+
+    let copies = rest
+        .iter()
+        .map(|node| node.deep_copy(s))
+        .collect::<Vec<_>>();
+    *cursor = Location::at(s, rest[0]);
+    let delete = DeleteMany(rest.len());
+    let mut undos = execute_tree_ed(s, delete, cursor).bug_msg("split_list: delete");
+    let new_list = Node::new(s, list.construct(s));
+    *cursor = Location::at(s, list);
+    undos.extend(execute_tree_ed(s, Insert(new_list), cursor).bug_msg("split_list: insert list"));
+    *cursor = Location::before_children(s, new_list).bug();
+    let insert = InsertMany(copies);
+    undos.extend(execute_tree_ed(s, insert, cursor).bug_msg("split_list: insert"));
+    *cursor = Location::at(s, new_list);
+    Ok(undos)
+}
+
+/// Join the list at the cursor with the list after it, which must be the same construct. This is
+/// the inverse of `split_list`, and is done the same way: delete the second list, and then insert
+/// copies of its elements at the end of the first.
+fn join_lists(s: &mut Storage, cursor: &mut Location) -> Result<Vec<Undo>, EditError> {
+    use TreeEdCommand::{Delete, InsertMany};
+
+    let list = cursor.at_node(s).ok_or(EditError::CannotJoinLists)?;
+    let next_list = list.next_sibling(s).ok_or(EditError::CannotJoinLists)?;
+    if !matches!(list.arity(s), Arity::Listy(_)) || list.construct(s) != next_list.construct(s) {
+        return Err(EditError::CannotJoinLists);
+    }
+
+    let mut copies = Vec::new();
+    let mut child = next_list.first_child(s);
+    while let Some(node) = child {
+        copies.push(node.deep_copy(s));
+        child = node.next_sibling(s);
+    }
+    *cursor = Location::at(s, next_list);
+    let mut undos = execute_tree_ed(s, Delete, cursor).bug_msg("join_lists: delete");
+    if !copies.is_empty() {
+        *cursor = Location::after_children(s, list).bug();
+        let insert = InsertMany(copies);
+        undos.extend(execute_tree_ed(s, insert, cursor).bug_msg("join_lists: insert"));
+    }
+    *cursor = Location::at(s, list);
+    Ok(undos)
 }
 
 fn execute_text_ed(
     s: &mut Storage,
     cmd: TextEdCommand,
     cursor: &mut Location,
-) -> Result<Vec<(Location, EdCommand)>, EditError> {
-    use TextEdCommand::{Backspace, Delete, Insert};
+) -> Result<Vec<Undo>, EditError> {
+    use TextEdCommand::{
+        Backspace, BackspaceChar, BackspaceWord, Delete, DeleteChar, DeleteWord, Insert,
+    };
 
     let (node, char_index) = cursor.text_pos_mut().ok_or(EditError::NotInTextMode)?;
     let text = node.text_mut(s).bug();
 
-    match cmd {
+    // Grapheme and word edits are a series of single-char edits, so that they can be undone the
+    // same way.
+    let (num_chars, char_cmd) = match cmd {
         Insert(ch) => {
             text.insert(*char_index, ch);
             *char_index += 1;
-            Ok(vec![(*cursor, Backspace.into())])
+            return Ok(vec![Undo::new(s, *cursor, BackspaceChar)]);
         }
-        Backspace => {
+        BackspaceChar => {
             if *char_index == 0 {
                 return Err(EditError::CannotDeleteChar);
             }
             let ch = text.delete(*char_index - 1);
             *char_index -= 1;
-            Ok(vec![(*cursor, Insert(ch).into())])
+            return Ok(vec![Undo::new(s, *cursor, Insert(ch))]);
         }
-        Delete => {
+        DeleteChar => {
             let text_len = text.num_chars();
             if *char_index == text_len {
                 return Err(EditError::CannotDeleteChar);
             }
             let ch = text.delete(*char_index);
-            Ok(vec![(*cursor, Insert(ch).into())])
+            return Ok(vec![Undo::new(s, *cursor, Insert(ch))]);
         }
+        Backspace => (
+            *char_index - text.prev_grapheme_boundary(*char_index),
+            BackspaceChar,
+        ),
+        Delete => (
+            text.next_grapheme_boundary(*char_index) - *char_index,
+            DeleteChar,
+        ),
+        BackspaceWord => (
+            *char_index - text.prev_word_start(*char_index),
+            BackspaceChar,
+        ),
+        DeleteWord => (text.next_word_end(*char_index) - *char_index, DeleteChar),
+    };
+    if num_chars == 0 {
+        return Err(EditError::CannotDeleteChar);
     }
+    let mut undos = Vec::new();
+    for _ in 0..num_chars {
+        undos.extend(execute_text_ed(s, char_cmd, cursor)?);
+    }
+    Ok(undos)
 }
 
 fn execute_clipboard(
     s: &mut Storage,
     cmd: ClipboardCommand,
     cursor: &mut Location,
-    clipboard: &mut Vec<Node>,
-) -> Result<Vec<(Location, EdCommand)>, EditError> {
+    clipboard: &mut Vec<Vec<Node>>,
+) -> Result<Vec<Undo>, EditError> {
     use ClipboardCommand::*;
 
     match cmd {
         Copy => {
             let node = cursor.at_node(s).ok_or(EditError::NoNodeHere)?;
-            clipboard.push(node.deep_copy(s));
+            clipboard.push(vec![node.deep_copy(s)]);
             Ok(Vec::new())
         }
         Paste => {
-            let node = clipboard.pop().ok_or(EditError::EmptyClipboard)?;
-            let result = execute_tree_ed(s, TreeEdCommand::Insert(node), cursor);
+            let nodes = clipboard.pop().ok_or(EditError::EmptyClipboard)?;
+            let result = if let [node] = nodes[..] {
+                execute_tree_ed(s, TreeEdCommand::Insert(node), cursor)
+            } else {
+                paste_several(s, &nodes, cursor)
+            };
             if result.is_err() {
-                clipboard.push(node);
+                clipboard.push(nodes);
             }
             result
         }
         PasteSwap => {
-            let clip_node = clipboard.pop().ok_or(EditError::EmptyClipboard)?;
             let doc_node = cursor.at_node(s).ok_or(EditError::NoNodeHere)?;
-            if doc_node.swap(s, clip_node) {
-                *cursor = Location::at(s, clip_node);
-                clipboard.push(doc_node.deep_copy(s));
-                Ok(vec![(*cursor, TreeEdCommand::Replace(doc_node).into())])
-            } else {
-                clipboard.push(clip_node);
-                Err(EditError::CannotPlaceNode)
+            let clip_node = single_node(clipboard.last())?;
+            doc_node.swap(s, clip_node)?;
+            clipboard.pop();
+            *cursor = Location::at(s, clip_node);
+            clipboard.push(vec![doc_node.deep_copy(s)]);
+            Ok(vec![Undo::new(
+                s,
+                *cursor,
+                TreeEdCommand::Replace(doc_node),
+            )])
+        }
+        PasteReplace => {
+            let doc_node = cursor.at_node(s).ok_or(EditError::NoNodeHere)?;
+            let clip_node = single_node(clipboard.last())?;
+            let new_node = clip_node.deep_copy(s);
+            if let Err(err) = doc_node.swap(s, new_node) {
+                new_node.delete_root(s).bug();
+                return Err(err.into());
             }
+            *cursor = Location::at(s, new_node);
+            clipboard.push(vec![doc_node.deep_copy(s)]);
+            Ok(vec![Undo::new(
+                s,
+                *cursor,
+                TreeEdCommand::Replace(doc_node),
+            )])
         }
         Dup => {
-            let clip_node = clipboard.last().ok_or(EditError::EmptyClipboard)?;
-            clipboard.push(clip_node.deep_copy(s));
+            let nodes = clipboard.last().ok_or(EditError::EmptyClipboard)?;
+            let copies = nodes.iter().map(|node| node.deep_copy(s)).collect();
+            clipboard.push(copies);
             Ok(Vec::new())
         }
         Pop => {
-            let clip_node = clipboard.pop().ok_or(EditError::EmptyClipboard)?;
-            clip_node.delete_root(s);
+            let nodes = clipboard.pop().ok_or(EditError::EmptyClipboard)?;
+            for node in nodes {
+                node.delete_root(s).bug();
+            }
             Ok(Vec::new())
         }
     }
 }
 
+/// The only node in a clipboard entry.
+fn single_node(entry: Option<&Vec<Node>>) -> Result<Node, EditError> {
+    match entry.map(|nodes| &nodes[..]) {
+        None => Err(EditError::EmptyClipboard),
+        Some([node]) => Ok(*node),
+        Some(_) => Err(EditError::SeveralNodesOnClipboard),
+    }
+}
+
+/// Insert all of `nodes` at once, which requires that the cursor be in a listy sequence that
+/// accepts all of them. Does nothing if it isn't.
+fn paste_several(
+    s: &mut Storage,
+    nodes: &[Node],
+    cursor: &mut Location,
+) -> Result<Vec<Undo>, EditError> {
+    let in_listy = cursor
+        .parent_node(s)
+        .map(|parent| matches!(parent.arity(s), Arity::Listy(_)))
+        .unwrap_or(false);
+    if !in_listy {
+        return Err(EditError::SeveralNodesOnClipboard);
+    }
+    if !nodes.iter().all(|node| cursor.can_insert(s, *node)) {
+        return Err(EditError::CannotPlaceNode);
+    }
+    let undos = execute_tree_ed(s, TreeEdCommand::InsertMany(nodes.to_vec()), cursor)
+        .bug_msg("paste_several: insert");
+    Ok(undos)
+}
+
 fn execute_tree_nav(
     s: &Storage,
     cmd: TreeNavCommand,
@@ -491,6 +1890,10 @@ fn execute_tree_nav(
         NextLeaf => cursor.next_leaf(s),
         PrevText => cursor.prev_text(s),
         NextText => cursor.next_text(s),
+        PrevHole => cursor.prev_match(s, |node| node.is_hole(s)),
+        NextHole => cursor.next_match(s, |node| node.is_hole(s)),
+        CellAbove => cursor.cell_above(s),
+        CellBelow => cursor.cell_below(s),
         Parent => cursor.parent(s),
         FirstChild => cursor.at_node(s).and_then(|node| {
             Location::at_first_child(s, node).or_else(|| Location::before_children(s, node))
@@ -507,6 +1910,13 @@ fn execute_tree_nav(
         FirstInsertLoc => cursor
             .at_node(s)
             .map(|node| Location::first_insert_loc(s, node)),
+        GotoNode(node) => {
+            if node.is_valid(s) && !node.is_root(s) && node.root(s) == cursor.root_node(s) {
+                Some(Location::at(s, node))
+            } else {
+                None
+            }
+        }
     };
 
     if let Some(new_loc) = new_loc {
@@ -528,20 +1938,33 @@ fn execute_text_nav(
     let text = node.text(s).bug();
 
     match cmd {
+        // Move by grapheme cluster, so that accents and the like move together with their base char.
         Left => {
             if *char_index == 0 {
                 return Err(EditError::CannotMove);
             }
-            *char_index -= 1;
+            *char_index = text.prev_grapheme_boundary(*char_index);
         }
         Right => {
             if *char_index >= text.num_chars() {
                 return Err(EditError::CannotMove);
             }
-            *char_index += 1;
+            *char_index = text.next_grapheme_boundary(*char_index);
         }
         Beginning => *char_index = 0,
         End => *char_index = text.num_chars(),
+        WordLeft => {
+            if *char_index == 0 {
+                return Err(EditError::CannotMove);
+            }
+            *char_index = text.prev_word_start(*char_index);
+        }
+        WordRight => {
+            if *char_index >= text.num_chars() {
+                return Err(EditError::CannotMove);
+            }
+            *char_index = text.next_word_end(*char_index);
+        }
         ExitText => {
             if node.is_invalid_text(s) {
                 return Err(EditError::InvalidText);
@@ -578,6 +2001,57 @@ fn execute_bookmark(
     }
 }
 
+fn execute_selection(
+    s: &Storage,
+    cmd: SelectionCommand,
+    cursor: &mut Location,
+    selection_anchor: &mut Option<Node>,
+) -> Result<(), EditError> {
+    match cmd {
+        SelectionCommand::Begin => {
+            *selection_anchor = Some(cursor.at_node(s).ok_or(EditError::NoNodeHere)?);
+        }
+        SelectionCommand::Clear => *selection_anchor = None,
+    }
+    Ok(())
+}
+
+fn execute_cursors(
+    s: &Storage,
+    cmd: CursorsCommand,
+    cursor: &mut Location,
+    secondary_cursors: &mut Vec<Location>,
+    search: &Option<Search>,
+) -> Result<(), EditError> {
+    match cmd {
+        CursorsCommand::Add => {
+            if !secondary_cursors.contains(cursor) {
+                secondary_cursors.push(*cursor);
+            }
+        }
+        CursorsCommand::AddAtMatches => {
+            let search = search.as_ref().ok_or(EditError::NoSearch)?;
+            let mut any_matches = false;
+            let mut node = cursor.root_node(s);
+            while let Some(next_node) = node.next_inorder(s) {
+                if search.matches(s, next_node) {
+                    any_matches = true;
+                    let loc = Location::at(s, next_node);
+                    if loc != *cursor && !secondary_cursors.contains(&loc) {
+                        secondary_cursors.push(loc);
+                    }
+                }
+                node = next_node;
+            }
+            if !any_matches {
+                return Err(EditError::NoNextMatch);
+            }
+        }
+        CursorsCommand::Clear => secondary_cursors.clear(),
+    }
+    Ok(())
+}
+
 fn execute_search(
     s: &mut Storage,
     cmd: SearchCommand,
@@ -2,6 +2,7 @@ use super::doc::Doc;
 use super::Settings;
 use crate::language::Storage;
 use crate::pretty_doc::DocRef;
+use crate::tree::NodeRemapping;
 use crate::util::bug_assert;
 use partial_pretty_printer as ppp;
 use partial_pretty_printer::pane;
@@ -49,6 +50,8 @@ pub enum DocDisplayLabel {
     Metadata(String),
     /// An auto-generated doc used to implement UI elements like menus.
     Auxilliary(String),
+    /// A doc generated by a script, like a report or a picker.
+    Virtual(String),
 }
 
 /// A unique name for a document.
@@ -57,6 +60,11 @@ pub enum DocName {
     File(PathBuf),
     Metadata(String),
     Auxilliary(String),
+    /// A doc generated by a script. It isn't backed by a file.
+    Virtual(String),
+    /// A throwaway doc for jotting things down, in the named language. See
+    /// [`Engine::open_scratch_doc`](super::Engine::open_scratch_doc).
+    Scratch(String),
 }
 
 impl fmt::Display for DocName {
@@ -67,6 +75,8 @@ impl fmt::Display for DocName {
             File(path) => write!(f, "{}", path.to_string_lossy()),
             Metadata(name) => write!(f, "metadata:{}", name),
             Auxilliary(name) => write!(f, "auxilliary:{}", name),
+            Virtual(name) => write!(f, "virtual:{}", name),
+            Scratch(language_name) => write!(f, "scratch:{}", language_name),
         }
     }
 }
@@ -101,6 +111,19 @@ impl DocSet {
         true
     }
 
+    /// Replace the doc named `doc_name` with `doc`, deleting the old one. It keeps its place in
+    /// the doc switching order, and stays visible if it was. Returns false if there was no such
+    /// doc.
+    #[must_use]
+    pub fn replace_doc(&mut self, s: &mut Storage, doc_name: &DocName, doc: Doc) -> bool {
+        if let Some((old_doc, _)) = self.docs.get_mut(doc_name) {
+            std::mem::replace(old_doc, doc).delete(s);
+            true
+        } else {
+            false
+        }
+    }
+
     #[must_use]
     pub fn delete_doc(&mut self, s: &mut Storage, doc_name: &DocName) -> bool {
         let deleted = if let Some((doc, _)) = self.docs.remove(doc_name) {
@@ -128,6 +151,13 @@ impl DocSet {
         self.visible_doc = None;
     }
 
+    /// Update every doc's nodes after the nodes were compacted. See `Storage::compact_nodes()`.
+    pub fn remap(&mut self, remapping: &NodeRemapping) {
+        for (doc, _) in self.docs.values_mut() {
+            doc.remap(remapping);
+        }
+    }
+
     #[must_use]
     pub fn set_visible_doc(&mut self, doc_name: &DocName) -> bool {
         if let Some((_, timestamp)) = self.docs.get_mut(doc_name) {
@@ -168,6 +198,23 @@ impl DocSet {
         self.docs.get_mut(doc_name).map(|(doc, _)| doc)
     }
 
+    /// The paths of all open file docs, sorted, for display in a tab bar.
+    pub fn file_doc_paths(&self) -> Vec<&Path> {
+        let mut paths = self
+            .docs
+            .keys()
+            .filter_map(|name| match name {
+                DocName::File(path) => Some(path.as_ref()),
+                DocName::Metadata(_)
+                | DocName::Auxilliary(_)
+                | DocName::Virtual(_)
+                | DocName::Scratch(_) => None,
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
     /// Docs that can become the visible doc. Excludes the current visible doc, and sorts by most
     /// recently visible.
     pub fn doc_switching_candidates(&self) -> Vec<&Path> {
@@ -185,7 +232,10 @@ impl DocSet {
                 }
                 match name {
                     DocName::File(path) => Some(path.as_ref()),
-                    DocName::Metadata(_) | DocName::Auxilliary(_) => None,
+                    DocName::Metadata(_)
+                    | DocName::Auxilliary(_)
+                    | DocName::Virtual(_)
+                    | DocName::Scratch(_) => None,
                 }
             })
             .collect::<Vec<_>>()
@@ -205,28 +255,42 @@ impl DocSet {
             set_focus: false,
         };
 
-        let (doc, opts, highlight_cursor) = match label {
+        let doc = match label {
             DocDisplayLabel::Visible => {
-                let doc = self.get_doc(self.visible_doc_name()?)?;
-                let (focus_path, focus_target) = doc.cursor().path_from_root(s);
-                let options = pane::PrintingOptions {
-                    focus_path,
-                    focus_target,
-                    focus_height: settings.focus_height,
-                    width_strategy: pane::WidthStrategy::NoMoreThan(settings.max_display_width),
-                    set_focus: doc.cursor().at_node(s).is_none(),
-                };
-                (doc, options, true)
-            }
-            DocDisplayLabel::Metadata(name) => {
-                let doc = self.get_doc(&DocName::Metadata(name))?;
-                (doc, meta_and_aux_options, false)
-            }
-            DocDisplayLabel::Auxilliary(name) => {
-                let doc = self.get_doc(&DocName::Auxilliary(name))?;
-                (doc, meta_and_aux_options, false)
+                return self.get_doc_content(s, self.visible_doc_name()?, settings);
             }
+            DocDisplayLabel::Metadata(name) => self.get_doc(&DocName::Metadata(name))?,
+            DocDisplayLabel::Auxilliary(name) => self.get_doc(&DocName::Auxilliary(name))?,
+            DocDisplayLabel::Virtual(name) => self.get_doc(&DocName::Virtual(name))?,
+        };
+        Some((
+            doc.doc_ref_display(s, false, settings.show_hidden),
+            meta_and_aux_options,
+        ))
+    }
+
+    /// Get the content of the named doc, displayed the way the visible doc is: with its cursor
+    /// highlighted and in focus.
+    pub fn get_doc_content<'s>(
+        &'s self,
+        s: &'s Storage,
+        doc_name: &DocName,
+        settings: &Settings,
+    ) -> Option<(DocRef<'s>, pane::PrintingOptions)> {
+        let doc = self.get_doc(doc_name)?;
+        let (focus_path, focus_target) = doc.cursor().path_from_root(s);
+        let options = pane::PrintingOptions {
+            focus_path,
+            focus_target,
+            focus_height: settings.focus_height,
+            width_strategy: pane::WidthStrategy::NoMoreThan(
+                settings.display_width(doc.language(s).name(s)),
+            ),
+            set_focus: doc.cursor().at_node(s).is_none(),
         };
-        Some((doc.doc_ref_display(s, highlight_cursor), opts))
+        let doc_ref = doc
+            .doc_ref_display(s, true, settings.show_hidden)
+            .with_shading(settings.shading);
+        Some((doc_ref, options))
     }
 }
@@ -1,21 +1,55 @@
 #![allow(clippy::module_inception)]
 
-use super::command::Command;
-use super::doc::Doc;
+use super::command::{Command, TextEdCommand, TextNavCommand, TreeEdCommand, TreeNavCommand};
+use super::doc::{Doc, EditError, HistoryEntry, HistoryTreeNode};
 use super::doc_set::{DocDisplayLabel, DocName, DocSet};
-use super::Settings;
-use crate::language::{Language, LanguageSpec, NotationSetSpec, Storage};
-use crate::parsing::{self, Parse, ParseError};
-use crate::pretty_doc::DocRef;
-use crate::style::Base16Color;
-use crate::tree::{Mode, Node};
-use crate::util::{bug, error, log, SynlessBug, SynlessError};
+use super::json_schema::JsonSchema;
+use super::lint::{builtin_diagnostics, Diagnostic};
+use super::search::{instantiate_template, Search};
+use super::spell_check::{Dictionary, Misspelling};
+use super::undo_cache::UndoCache;
+use super::{LanguageSettings, LineNumbers, Settings};
+use crate::config::Config;
+use crate::language::{
+    Arity, Construct, HoleSyntax, Language, LanguageSpec, NotationSetSpec, Storage,
+};
+use crate::parsing::{
+    self, BackgroundParseFn, JsonParser, Parse, ParseError, ParsedTree, SplitSource,
+};
+use crate::pretty_doc::{DocRef, NodeLines, NodeLinesRecorder};
+use crate::style::{Base16Color, Shading, Style};
+use crate::tree::{merge, DiffOp, Location, Mode, Node, NodeChange, NodeId, NodeRemapping, Visit};
+use crate::util::{
+    bug, bug_assert, error, log, LogLevel, Profiler, SynlessBug, SynlessError, TimingStats,
+};
 use partial_pretty_printer as ppp;
 use partial_pretty_printer::pane;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
 const STRING_LANGUAGE_NAME: &str = "string";
+const TAB_BAR_LANGUAGE_NAME: &str = "tab_bar";
+const SCROLLBAR_LANGUAGE_NAME: &str = "scrollbar";
+const LINE_NUMBERS_LANGUAGE_NAME: &str = "line_numbers";
+const UNDO_TREE_LANGUAGE_NAME: &str = "undo_tree";
+const LINT_RESULTS_LANGUAGE_NAME: &str = "lint_results";
+const NOTIFICATIONS_LANGUAGE_NAME: &str = "notifications";
+const JSON_LANGUAGE_NAME: &str = "json";
+/// The width to print construct previews at. Only their first line is used.
+const CONSTRUCT_PREVIEW_WIDTH: ppp::Width = 40;
+/// How many elements of a lazily loaded doc to parse when it's opened. Enough to fill the screen.
+const NUM_EAGER_ELEMENTS: usize = 256;
+/// How many elements of a lazily loaded doc to parse at a time after that.
+const LAZY_LOAD_CHUNK_SIZE: usize = 64;
+const BACKGROUND_PARSE_DIED: &str = "The thread parsing the doc died";
+/// Node memory isn't worth reclaiming until there's room for at least this many nodes.
+const MIN_COMPACTION_CAPACITY: usize = 1 << 16;
+/// The height of the offscreen window that docs are laid out in. Taller than any doc, but with
+/// room to spare so that positions within it can't overflow.
+const LAYOUT_HEIGHT: ppp::Height = ppp::Height::MAX / 2;
 
 #[derive(thiserror::Error, Debug)]
 pub enum DocError {
@@ -35,13 +69,32 @@ impl From<DocError> for SynlessError {
     }
 }
 
+/// What to compare the children of a list by, when sorting them with `Engine::sort_children()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortChildrenBy {
+    /// Each child's pretty-printed source.
+    Source,
+    /// The text of each child's first texty descendant (in document order), like a JSON key.
+    Text,
+}
+
 #[derive(Debug)]
 pub struct Engine {
     storage: Storage,
     doc_set: DocSet,
     parsers: HashMap<String, Box<dyn Parse + 'static>>,
-    clipboard: Vec<Node>,
+    /// A stack of entries, each holding one or more nodes.
+    clipboard: Vec<Vec<Node>>,
     settings: Settings,
+    undo_cache: Option<UndoCache>,
+    layout_cache: LayoutCache,
+    profiler: Profiler,
+    /// The words to spell check prose against, if spell checking is on.
+    dictionary: Option<Dictionary>,
+    /// The parts of lazily loaded docs that haven't been parsed yet.
+    pending_loads: HashMap<DocName, PendingLoad>,
+    /// Docs that are being parsed on other threads.
+    background_parses: HashMap<DocName, BackgroundParse>,
 }
 
 impl Engine {
@@ -52,6 +105,12 @@ impl Engine {
             parsers: HashMap::new(),
             clipboard: Vec::new(),
             settings,
+            undo_cache: None,
+            layout_cache: LayoutCache::new(),
+            profiler: Profiler::default(),
+            dictionary: None,
+            pending_loads: HashMap::new(),
+            background_parses: HashMap::new(),
         }
     }
 
@@ -68,9 +127,14 @@ impl Engine {
             .map_err(|err| ParseError::from_ron_error(filepath, err))?;
         let language_name = language_spec.name.clone();
         self.storage.add_language(language_spec)?;
+        self.apply_language_notations(&language_name)?;
         Ok(language_name)
     }
 
+    pub fn set_undo_cache(&mut self, cache: UndoCache) {
+        self.undo_cache = Some(cache);
+    }
+
     pub fn load_notation_ron(
         &mut self,
         language_name: &str,
@@ -82,6 +146,8 @@ impl Engine {
         let notation_name = notation_spec.name.clone();
         let lang = self.storage.language(language_name)?;
         lang.add_notation(&mut self.storage, notation_spec)?;
+        self.layout_cache.clear();
+        self.apply_language_notations(language_name)?;
         Ok(notation_name)
     }
 
@@ -96,6 +162,7 @@ impl Engine {
     ) -> Result<(), SynlessError> {
         let lang = self.storage.language(language_name)?;
         lang.set_display_notation(&mut self.storage, notation_name)?;
+        self.layout_cache.clear();
         Ok(())
     }
 
@@ -139,6 +206,11 @@ impl Engine {
             .insert(language_name.to_owned(), Box::new(parser));
     }
 
+    /// Add the parsers that come with Synless, each for the language it parses.
+    pub fn add_builtin_parsers(&mut self) {
+        self.add_parser(JSON_LANGUAGE_NAME, JsonParser);
+    }
+
     /******************
      * Doc Management *
      ******************/
@@ -171,7 +243,101 @@ impl Engine {
         Ok(())
     }
 
+    /// Move the node at the cursor into a new doc named `doc_name`, whose language must be the
+    /// node's language. If the language has an include construct, the node is replaced by an
+    /// include of the new doc's path. Otherwise it's just deleted. The visible doc doesn't change.
+    pub fn extract_to_doc(
+        &mut self,
+        doc_name: &DocName,
+        language_name: &str,
+    ) -> Result<(), SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let node = doc.node_at_cursor(&self.storage)?;
+        let language = node.language(&self.storage);
+        if language.name(&self.storage) != language_name {
+            return Err(error!(
+                Doc,
+                "Can't move a node in language '{}' into a doc in language '{}'",
+                language.name(&self.storage),
+                language_name
+            ));
+        }
+        if self.doc_set.contains_doc(doc_name) {
+            return Err(DocError::DocAlreadyOpen(doc_name.to_owned()).into());
+        }
+
+        let copy = node.deep_copy(&mut self.storage);
+        let root_construct = language.root_construct(&self.storage);
+        let root = match Node::with_children(&mut self.storage, root_construct, [copy]) {
+            Ok(root) => root,
+            Err(_) => {
+                copy.delete_root(&mut self.storage).bug();
+                return Err(error!(Doc, "That node can't be the root of a doc"));
+            }
+        };
+        self.add_doc(doc_name, root, false)?;
+
+        let include = match (language.include_construct(&self.storage), doc_name) {
+            (Some(construct), DocName::File(path)) => {
+                let path = path.to_string_lossy().into_owned();
+                Node::with_text(&mut self.storage, construct, path).ok()
+            }
+            _ => None,
+        };
+        let mut result = Err(EditError::CannotPlaceNode.into());
+        if let Some(include) = include {
+            result = self.execute(TreeEdCommand::Replace(include));
+            if result.is_err() {
+                include.delete_root(&mut self.storage).bug();
+            }
+        }
+        if result.is_err() {
+            result = self.execute(TreeEdCommand::Delete);
+        }
+        if result.is_err() {
+            self.delete_doc(doc_name)?;
+        }
+        result
+    }
+
+    /// Set the contents of the virtual doc `name` to `root_node`, creating the doc if it doesn't
+    /// exist yet. Virtual docs are read-only, and never have unsaved changes.
+    pub fn set_virtual_doc(&mut self, name: &str, root_node: Node) -> Result<(), SynlessError> {
+        let doc_name = DocName::Virtual(name.to_owned());
+        let mut doc = Doc::new(&self.storage, root_node, true).ok_or(DocError::InvalidRootNode)?;
+        doc.set_read_only(true);
+        if self.doc_set.contains_doc(&doc_name) {
+            bug_assert!(self.doc_set.replace_doc(&mut self.storage, &doc_name, doc));
+        } else {
+            bug_assert!(self.doc_set.add_doc(doc_name, doc));
+        }
+        Ok(())
+    }
+
+    /// Get the name of the scratch doc for `language_name`, creating the doc if it isn't open yet.
+    /// There's at most one scratch doc per language. A new scratch doc is parsed from
+    /// `saved_source` if it's given (e.g. to restore it from a previous session), and is empty
+    /// otherwise.
+    pub fn open_scratch_doc(
+        &mut self,
+        language_name: &str,
+        saved_source: Option<&str>,
+    ) -> Result<DocName, SynlessError> {
+        let doc_name = DocName::Scratch(language_name.to_owned());
+        if !self.doc_set.contains_doc(&doc_name) {
+            match saved_source {
+                Some(source) => {
+                    self.load_doc_from_source(doc_name.clone(), language_name, source)?
+                }
+                None => self.add_empty_doc(&doc_name, language_name)?,
+            }
+        }
+        Ok(doc_name)
+    }
+
     pub fn delete_doc(&mut self, doc_name: &DocName) -> Result<(), SynlessError> {
+        self.pending_loads.remove(doc_name);
+        self.background_parses.remove(doc_name);
         if self.doc_set.delete_doc(&mut self.storage, doc_name) {
             Err(DocError::DocNotFound(doc_name.to_owned()))?;
         }
@@ -206,7 +372,12 @@ impl Engine {
         }
     }
 
+    /// Whether the visible doc has unsaved changes. Scratch docs never do: they're either
+    /// throwaway, or saved automatically.
     pub fn has_unsaved_changes(&self) -> bool {
+        if let Some(DocName::Scratch(_)) = self.doc_set.visible_doc_name() {
+            return false;
+        }
         self.doc_set
             .visible_doc()
             .map(|doc| doc.has_unsaved_changes())
@@ -236,6 +407,36 @@ impl Engine {
         self.doc_set.doc_switching_candidates()
     }
 
+    /// The names of every open file doc, in tab bar order.
+    pub fn file_doc_names(&self) -> Vec<DocName> {
+        self.doc_set
+            .file_doc_paths()
+            .into_iter()
+            .map(|path| DocName::File(path.to_owned()))
+            .collect()
+    }
+
+    /// Make the next (or previous, if `!forward`) doc in the tab bar visible, wrapping around.
+    pub fn switch_to_adjacent_doc(&mut self, forward: bool) -> Result<(), SynlessError> {
+        let paths = self.doc_set.file_doc_paths();
+        if paths.is_empty() {
+            return Err(DocError::NoVisibleDoc.into());
+        }
+        let current = match self.doc_set.visible_doc_name() {
+            Some(DocName::File(visible_path)) => paths
+                .iter()
+                .position(|path| *path == visible_path.as_path()),
+            _ => None,
+        };
+        let index = match (current, forward) {
+            (None, _) => 0,
+            (Some(i), true) => (i + 1) % paths.len(),
+            (Some(i), false) => (i + paths.len() - 1) % paths.len(),
+        };
+        let doc_name = DocName::File(paths[index].to_owned());
+        self.set_visible_doc(&doc_name)
+    }
+
     pub fn mode(&self) -> Mode {
         self.doc_set
             .visible_doc()
@@ -265,152 +466,2061 @@ impl Engine {
         language_name: &str,
         source: &str,
     ) -> Result<(), SynlessError> {
-        let parser = self
-            .parsers
-            .get_mut(language_name)
-            .ok_or_else(|| error!(Language, "No parser for language {}", language_name))?;
-        let hole_syntax = self
-            .storage
-            .language(language_name)?
-            .hole_syntax(&self.storage)
-            .ok_or_else(|| {
-                error!(
-                    Language,
-                    "No hole syntax for language {}, but it's required for loading from source",
-                    language_name
-                )
-            })?
-            .to_owned();
-
-        let source = &parsing::preprocess(source, &hole_syntax.invalid, &hole_syntax.valid);
-        let root_node = parser.parse(&mut self.storage, &doc_name.to_string(), source)?;
-        parsing::postprocess(&mut self.storage, root_node, &hole_syntax.text);
-
-        let doc = Doc::new(&self.storage, root_node, true).bug_msg("Invalid root");
+        let doc = self.parse_doc_source(&doc_name, language_name, source)?;
         if !self.doc_set.add_doc(doc_name.clone(), doc) {
             return Err(DocError::DocAlreadyOpen(doc_name).into());
         }
         Ok(())
     }
 
-    pub fn print_source(&self, doc_name: &DocName) -> Result<String, SynlessError> {
-        // TODO (optimization): consider returning an iterator of lines for memory efficiency
-        let doc = self
-            .doc_set
-            .get_doc(doc_name)
-            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
-        let doc_ref = doc.doc_ref_source(&self.storage, false);
-        let source = ppp::pretty_print_to_string(doc_ref, self.settings.max_source_width)?;
-        Ok(source)
+    /// Like `load_doc_from_source()`, but if `source` is at least `lazy_load_size` bytes long,
+    /// open it without parsing all of it now:
+    ///
+    /// - If the language's parser can split it into the elements of a top-level list, only parse
+    ///   its first few elements. The rest are parsed by `load_pending_elements()`.
+    /// - Otherwise, if the parser can run on another thread, parse it there. Until it's done, the
+    ///   doc is a placeholder, and `poll_background_parses()` swaps in the parsed doc.
+    ///
+    /// Until it's loaded the doc is read-only, so that it can't be edited or saved while only
+    /// partly there. `finish_loading()` waits for it. Returns whether it's still loading.
+    pub fn load_doc_lazily(
+        &mut self,
+        doc_name: DocName,
+        language_name: &str,
+        source: &str,
+    ) -> Result<bool, SynlessError> {
+        let lazy_load_size = self.settings.lazy_load_size;
+        if lazy_load_size == 0 || source.len() < lazy_load_size {
+            self.load_doc_from_source(doc_name, language_name, source)?;
+            return Ok(false);
+        }
+        if self.doc_set.contains_doc(&doc_name) {
+            return Err(DocError::DocAlreadyOpen(doc_name).into());
+        }
+        let hole_syntax = self.hole_syntax(language_name)?;
+        let source = parsing::preprocess(source, &hole_syntax.invalid, &hole_syntax.valid);
+        let split = self
+            .parsers
+            .get(language_name)
+            .and_then(|parser| parser.split_source(&source));
+        let split = match split {
+            Some(split) if split.elements.len() > NUM_EAGER_ELEMENTS => split,
+            _ => {
+                let parse_fn = self
+                    .parsers
+                    .get(language_name)
+                    .and_then(|parser| parser.background_parse_fn());
+                if let Some(parse_fn) = parse_fn {
+                    self.start_background_parse(doc_name, language_name, source, parse_fn)?;
+                    return Ok(true);
+                }
+                // Don't preprocess the source twice.
+                let root_node =
+                    self.parse_preprocessed_source(&doc_name, language_name, &source)?;
+                self.add_doc(&doc_name, root_node, true)?;
+                return Ok(false);
+            }
+        };
+
+        let head = split.join_elements(&source, 0..NUM_EAGER_ELEMENTS);
+        let root_node = self.parse_preprocessed_source(&doc_name, language_name, &head)?;
+        let container = root_node
+            .first_child(&self.storage)
+            .bug_msg("split_source: no top-level list");
+        let mut doc = Doc::new(&self.storage, root_node, true).ok_or(DocError::InvalidRootNode)?;
+        doc.set_read_only(true);
+        bug_assert!(self.doc_set.add_doc(doc_name.clone(), doc));
+        self.pending_loads.insert(
+            doc_name,
+            PendingLoad {
+                language_name: language_name.to_owned(),
+                source,
+                split,
+                next_element: NUM_EAGER_ELEMENTS,
+                container,
+                read_only: false,
+            },
+        );
+        Ok(true)
     }
 
-    pub fn get_content(&self, label: DocDisplayLabel) -> Option<(DocRef, pane::PrintingOptions)> {
-        self.doc_set
-            .get_content(&self.storage, label, &self.settings)
+    /// Whether any docs are still being loaded lazily.
+    pub fn has_pending_loads(&self) -> bool {
+        !self.pending_loads.is_empty()
     }
 
-    pub fn make_string_doc(&mut self, string: String, bg_color: Option<Base16Color>) -> Node {
-        let lang = self
-            .storage
-            .language(STRING_LANGUAGE_NAME)
-            .bug_msg("Missing String lang");
-        let c_root = lang.root_construct(&self.storage);
-        let c_string = lang.construct(&self.storage, "String").bug();
-        let string_node = Node::with_text(&mut self.storage, c_string, string).bug();
-        let node = if let Some(color) = bg_color {
-            let c_color = match color {
-                Base16Color::Base08 => lang.construct(&self.storage, "BgBase08").bug(),
-                Base16Color::Base0B => lang.construct(&self.storage, "BgBase0B").bug(),
-                _ => bug!("make_string_doc: specified bg color not yet supported"),
-            };
-            Node::with_children(&mut self.storage, c_color, [string_node]).bug()
+    /// Whether any docs are still being parsed on other threads.
+    pub fn has_background_parses(&self) -> bool {
+        !self.background_parses.is_empty()
+    }
+
+    /// Whether `doc_name` is still being loaded lazily or parsed on another thread.
+    pub fn is_loading(&self, doc_name: &DocName) -> bool {
+        self.pending_loads.contains_key(doc_name) || self.background_parses.contains_key(doc_name)
+    }
+
+    /// Parse more of the docs that are being loaded lazily, until they're done or `budget` runs
+    /// out. Returns the names of the docs that finished loading.
+    pub fn load_pending_elements(
+        &mut self,
+        budget: Duration,
+    ) -> Result<Vec<DocName>, SynlessError> {
+        let start = Instant::now();
+        let mut finished = Vec::new();
+        let doc_names = self.pending_loads.keys().cloned().collect::<Vec<_>>();
+        for doc_name in doc_names {
+            while start.elapsed() < budget {
+                if self.load_next_chunk(&doc_name)? {
+                    finished.push(doc_name);
+                    break;
+                }
+            }
+        }
+        Ok(finished)
+    }
+
+    /// Parse the rest of `doc_name` if it's being loaded lazily, or wait for it to be parsed if
+    /// it's being parsed on another thread.
+    pub fn finish_loading(&mut self, doc_name: &DocName) -> Result<(), SynlessError> {
+        if let Some(parse) = self.background_parses.remove(doc_name) {
+            let result = parse.receiver.recv().map_err(|_| BACKGROUND_PARSE_DIED);
+            self.finish_background_parse(doc_name, parse, result)?;
+        }
+        while self.pending_loads.contains_key(doc_name) {
+            self.load_next_chunk(doc_name)?;
+        }
+        Ok(())
+    }
+
+    /// Start parsing `source`, whose holes have already been preprocessed, on another thread.
+    /// Meanwhile, `doc_name` is a read-only placeholder.
+    fn start_background_parse(
+        &mut self,
+        doc_name: DocName,
+        language_name: &str,
+        source: String,
+        parse_fn: BackgroundParseFn,
+    ) -> Result<(), SynlessError> {
+        let placeholder = if self.storage.language(STRING_LANGUAGE_NAME).is_ok() {
+            self.make_string_doc(format!("Loading {doc_name}..."), None)
         } else {
-            string_node
+            let lang = self.storage.language(language_name)?;
+            Node::new(&mut self.storage, lang.root_construct(&self.storage))
         };
-        Node::with_children(&mut self.storage, c_root, [node]).bug()
-    }
+        let mut doc =
+            Doc::new(&self.storage, placeholder, true).ok_or(DocError::InvalidRootNode)?;
+        doc.set_read_only(true);
+        bug_assert!(self.doc_set.add_doc(doc_name.clone(), doc));
 
-    /*************
-     * Accessing *
-     *************/
+        let (sender, receiver) = mpsc::channel();
+        let file_name = doc_name.to_string();
+        std::thread::spawn(move || {
+            // If the doc was closed, there's no one to receive it.
+            let _ = sender.send(parse_fn(&file_name, &source));
+        });
+        self.background_parses.insert(
+            doc_name,
+            BackgroundParse {
+                language_name: language_name.to_owned(),
+                receiver,
+                read_only: false,
+            },
+        );
+        Ok(())
+    }
 
-    pub fn node_at_cursor(&mut self, deep_copy: bool) -> Result<Node, SynlessError> {
-        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
-        let mut node = doc.node_at_cursor(&self.storage)?;
-        if deep_copy {
-            node = node.deep_copy(&mut self.storage);
+    /// Swap in the docs that have finished being parsed on other threads, replacing their
+    /// placeholders. Returns the name of each, and whether it parsed. Docs that failed to parse
+    /// are closed.
+    pub fn poll_background_parses(&mut self) -> Vec<(DocName, Result<(), SynlessError>)> {
+        let mut finished = Vec::new();
+        let doc_names = self.background_parses.keys().cloned().collect::<Vec<_>>();
+        for doc_name in doc_names {
+            let result = match self.background_parses[&doc_name].receiver.try_recv() {
+                Ok(result) => Ok(result),
+                Err(mpsc::TryRecvError::Empty) => continue,
+                Err(mpsc::TryRecvError::Disconnected) => Err(BACKGROUND_PARSE_DIED),
+            };
+            let parse = self.background_parses.remove(&doc_name).bug();
+            let result = self.finish_background_parse(&doc_name, parse, result);
+            finished.push((doc_name, result));
         }
-        Ok(node)
+        finished
     }
 
-    /***********
-     * Editing *
-     ***********/
+    fn finish_background_parse(
+        &mut self,
+        doc_name: &DocName,
+        parse: BackgroundParse,
+        result: Result<Result<Vec<ParsedTree>, ParseError>, &str>,
+    ) -> Result<(), SynlessError> {
+        let root_node = match result {
+            Ok(Ok(children)) => {
+                let lang = self.storage.language(&parse.language_name)?;
+                parsing::build_doc(&mut self.storage, lang, children)
+            }
+            Ok(Err(err)) => Err(err.into()),
+            Err(message) => Err(error!(Parse, "{message}")),
+        };
+        let root_node = match root_node {
+            Ok(root_node) => root_node,
+            Err(err) => {
+                bug_assert!(self.doc_set.delete_doc(&mut self.storage, doc_name));
+                return Err(err);
+            }
+        };
+        let hole_syntax = self.hole_syntax(&parse.language_name)?;
+        parsing::postprocess(&mut self.storage, root_node, &hole_syntax.text);
+        let mut doc = Doc::new(&self.storage, root_node, true).ok_or(DocError::InvalidRootNode)?;
+        doc.set_read_only(parse.read_only);
+        bug_assert!(self.doc_set.replace_doc(&mut self.storage, doc_name, doc));
+        Ok(())
+    }
 
-    pub fn execute(&mut self, cmd: impl Into<Command>) -> Result<(), SynlessError> {
+    /// Parse the next few elements of the lazily loaded doc `doc_name` and append them to its
+    /// top-level list. Returns whether it's now finished loading. If they fail to parse, the doc
+    /// stops loading and is left read-only.
+    fn load_next_chunk(&mut self, doc_name: &DocName) -> Result<bool, SynlessError> {
+        let pending = match self.pending_loads.get_mut(doc_name) {
+            Some(pending) => pending,
+            None => return Ok(true),
+        };
+        let start = pending.next_element;
+        let end = (start + LAZY_LOAD_CHUNK_SIZE).min(pending.split.elements.len());
+        pending.next_element = end;
+        let chunk_source = pending.split.join_elements(&pending.source, start..end);
+        let language_name = pending.language_name.clone();
+        let container = pending.container;
+        let is_done = end == pending.split.elements.len();
+        let read_only = pending.read_only;
+
+        let chunk_root =
+            match self.parse_preprocessed_source(doc_name, &language_name, &chunk_source) {
+                Ok(chunk_root) => chunk_root,
+                Err(err) => {
+                    self.pending_loads.remove(doc_name);
+                    return Err(error!(
+                        Parse,
+                        "Stopped loading '{doc_name}', so it's read-only: {}", err.message
+                    ));
+                }
+            };
+        let s = &mut self.storage;
+        let nodes = match chunk_root.first_child(s) {
+            Some(list) => {
+                let num_elements = list.num_children(s).bug();
+                list.drain_children(s, 0..num_elements).bug()
+            }
+            None => Vec::new(),
+        };
+        chunk_root.delete_root(s).bug();
         let doc = self
             .doc_set
-            .visible_doc_mut()
-            .ok_or(DocError::NoVisibleDoc)?;
-        doc.execute(&mut self.storage, cmd.into(), &mut self.clipboard)?;
+            .get_doc_mut(doc_name)
+            .bug_msg("load_next_chunk: missing doc");
+        doc.append_loaded_nodes(s, container, nodes);
+        if is_done {
+            doc.set_read_only(read_only);
+            self.pending_loads.remove(doc_name);
+        }
+        Ok(is_done)
+    }
+
+    /// Remember the undo history of `doc_name`, which was just saved to `path` with contents
+    /// `source`, so that it can be restored when that file is next opened. Does nothing if there's
+    /// no undo cache.
+    pub fn save_undo_history(
+        &mut self,
+        doc_name: &DocName,
+        path: &Path,
+        source: &str,
+    ) -> Result<(), SynlessError> {
+        if let Some(cache) = &self.undo_cache {
+            let doc = self
+                .doc_set
+                .get_doc_mut(doc_name)
+                .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
+            cache.insert(path, source, doc.save_history());
+        }
         Ok(())
     }
 
-    pub fn undo(&mut self) -> Result<(), SynlessError> {
+    /// Restore the undo history that was remembered when the file at `path` was last saved, into
+    /// the freshly opened doc `doc_name` that was loaded from it with contents `source`. Does
+    /// nothing if there's no remembered history, or the file has changed since. If the history
+    /// can't be restored, the doc is reloaded with no history and an error is returned.
+    pub fn restore_undo_history(
+        &mut self,
+        doc_name: &DocName,
+        path: &Path,
+        language_name: &str,
+        source: &str,
+    ) -> Result<(), SynlessError> {
+        let saved = match self
+            .undo_cache
+            .as_ref()
+            .and_then(|cache| cache.get(path, source))
+        {
+            Some(saved) => saved,
+            None => return Ok(()),
+        };
         let doc = self
             .doc_set
-            .visible_doc_mut()
-            .ok_or(DocError::NoVisibleDoc)?;
-        doc.undo(&mut self.storage)?;
+            .get_doc_mut(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
+        if let Err(err) = doc.restore_history(&mut self.storage, &saved) {
+            let doc = self.parse_doc_source(doc_name, language_name, source)?;
+            bug_assert!(self.doc_set.replace_doc(&mut self.storage, doc_name, doc));
+            return Err(error!(
+                Doc,
+                "Failed to restore undo history of '{}' ({err})",
+                path.display()
+            ));
+        }
         Ok(())
     }
 
-    pub fn redo(&mut self) -> Result<(), SynlessError> {
+    /// Replace the contents of the open doc `doc_name` by parsing `source`, discarding its undo
+    /// history. It stays read-only if it was. If `goto_end` is set, its cursor moves to the end
+    /// (so that a doc following a growing file shows the newest content).
+    pub fn reload_doc_from_source(
+        &mut self,
+        doc_name: &DocName,
+        language_name: &str,
+        source: &str,
+        goto_end: bool,
+    ) -> Result<(), SynlessError> {
+        let read_only = self
+            .doc_set
+            .get_doc(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?
+            .is_read_only();
+        let mut doc = self.parse_doc_source(doc_name, language_name, source)?;
+        doc.set_read_only(read_only);
+        self.pending_loads.remove(doc_name);
+        self.background_parses.remove(doc_name);
+        if goto_end {
+            doc.goto_end(&self.storage);
+        }
+        bug_assert!(self.doc_set.replace_doc(&mut self.storage, doc_name, doc));
+        Ok(())
+    }
+
+    /// Add the nodes parsed from `source`, which was appended to the file of the open doc
+    /// `doc_name`, to the end of the doc. Like reloading, this isn't an edit: it can't be undone,
+    /// and works on read-only docs. If `goto_end` is set, the cursor moves to the end. Fails
+    /// without changing the doc if its root isn't a list, or if `source` doesn't parse on its own,
+    /// in which case the whole file should be reloaded instead.
+    pub fn append_doc_from_source(
+        &mut self,
+        doc_name: &DocName,
+        language_name: &str,
+        source: &str,
+        goto_end: bool,
+    ) -> Result<(), SynlessError> {
+        self.finish_loading(doc_name)?;
         let doc = self
             .doc_set
-            .visible_doc_mut()
-            .ok_or(DocError::NoVisibleDoc)?;
-        doc.redo(&mut self.storage)?;
+            .get_doc(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
+        let root = doc.cursor().root_node(&self.storage);
+        if !matches!(root.arity(&self.storage), Arity::Listy(_)) {
+            return Err(error!(
+                Doc,
+                "Can't append to '{doc_name}', because its root isn't a list"
+            ));
+        }
+        let new_root = self.parse_source(doc_name, language_name, source)?;
+        let s = &mut self.storage;
+        let mut nodes = Vec::new();
+        while let Some(child) = new_root.first_child(s) {
+            child.detach(s).bug();
+            nodes.push(child);
+        }
+        new_root.delete_root(s).bug();
+        let doc = self.doc_set.get_doc_mut(doc_name).bug();
+        doc.append_loaded_nodes(s, root, nodes);
+        if goto_end {
+            doc.goto_end(s);
+        }
         Ok(())
     }
 
-    pub fn end_undo_group(&mut self) -> Result<(), SynlessError> {
+    /// Replace the contents of the open doc `doc_name` by parsing `source`, as a single edit that
+    /// can be undone. The doc must not have unsaved changes, since `source` is taken to be the new
+    /// contents of its file: the doc is still considered saved afterwards.
+    pub fn merge_doc_from_source(
+        &mut self,
+        doc_name: &DocName,
+        language_name: &str,
+        source: &str,
+    ) -> Result<(), SynlessError> {
+        self.finish_loading(doc_name)?;
+        let has_unsaved_changes = self
+            .doc_set
+            .get_doc(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?
+            .has_unsaved_changes();
+        if has_unsaved_changes {
+            return Err(error!(
+                Doc,
+                "Can't merge into '{doc_name}' because it has unsaved changes"
+            ));
+        }
+        let new_root = self.parse_source(doc_name, language_name, source)?;
+        let doc = self.doc_set.get_doc_mut(doc_name).bug();
+        doc.replace_contents(&mut self.storage, new_root)?;
+        doc.mark_as_saved();
+        Ok(())
+    }
+
+    /// Record that the doc `doc_name` no longer matches its file.
+    pub fn mark_doc_as_unsaved(&mut self, doc_name: &DocName) -> Result<(), SynlessError> {
         let doc = self
             .doc_set
-            .visible_doc_mut()
-            .ok_or(DocError::NoVisibleDoc)?;
-        doc.end_undo_group();
+            .get_doc_mut(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
+        doc.mark_as_unsaved();
         Ok(())
     }
 
-    pub fn revert_undo_group(&mut self) -> Result<(), SynlessError> {
+    pub fn set_doc_read_only(
+        &mut self,
+        doc_name: &DocName,
+        read_only: bool,
+    ) -> Result<(), SynlessError> {
         let doc = self
             .doc_set
-            .visible_doc_mut()
-            .ok_or(DocError::NoVisibleDoc)?;
-        doc.revert_undo_group(&mut self.storage);
+            .get_doc_mut(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
+        // It has to stay read-only until it's loaded.
+        if let Some(pending) = self.pending_loads.get_mut(doc_name) {
+            pending.read_only = read_only;
+        } else if let Some(parse) = self.background_parses.get_mut(doc_name) {
+            parse.read_only = read_only;
+        } else {
+            doc.set_read_only(read_only);
+        }
         Ok(())
     }
 
-    /**********************
-     * Raw Storage Access *
-     **********************/
+    fn parse_doc_source(
+        &mut self,
+        doc_name: &DocName,
+        language_name: &str,
+        source: &str,
+    ) -> Result<Doc, SynlessError> {
+        let root_node = self.parse_source(doc_name, language_name, source)?;
+        Ok(Doc::new(&self.storage, root_node, true).bug_msg("Invalid root"))
+    }
 
-    pub fn raw_storage(&self) -> &Storage {
-        &self.storage
+    /// Parse `source` into a new root node.
+    fn parse_source(
+        &mut self,
+        doc_name: &DocName,
+        language_name: &str,
+        source: &str,
+    ) -> Result<Node, SynlessError> {
+        let hole_syntax = self.hole_syntax(language_name)?;
+        let source = &parsing::preprocess(source, &hole_syntax.invalid, &hole_syntax.valid);
+        self.parse_preprocessed_source(doc_name, language_name, source)
     }
 
-    pub fn raw_storage_mut(&mut self) -> &mut Storage {
-        &mut self.storage
+    /// Parse `source`, whose holes have already been converted to their valid syntax, into a new
+    /// root node.
+    fn parse_preprocessed_source(
+        &mut self,
+        doc_name: &DocName,
+        language_name: &str,
+        source: &str,
+    ) -> Result<Node, SynlessError> {
+        let hole_syntax = self.hole_syntax(language_name)?;
+        let parser = self
+            .parsers
+            .get_mut(language_name)
+            .ok_or_else(|| error!(Language, "No parser for language {}", language_name))?;
+        let root_node = parser.parse(&mut self.storage, &doc_name.to_string(), source)?;
+        parsing::postprocess(&mut self.storage, root_node, &hole_syntax.text);
+        Ok(root_node)
     }
-}
+
+    fn hole_syntax(&self, language_name: &str) -> Result<HoleSyntax, SynlessError> {
+        Ok(self
+            .storage
+            .language(language_name)?
+            .hole_syntax(&self.storage)
+            .ok_or_else(|| {
+                error!(
+                    Language,
+                    "No hole syntax for language {}, but it's required for loading from source",
+                    language_name
+                )
+            })?
+            .to_owned())
+    }
+
+    pub fn print_source(&self, doc_name: &DocName) -> Result<String, SynlessError> {
+        if self.is_loading(doc_name) {
+            return Err(error!(Doc, "'{doc_name}' hasn't finished loading"));
+        }
+        // TODO (optimization): consider returning an iterator of lines for memory efficiency
+        let doc = self
+            .doc_set
+            .get_doc(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
+        let doc_ref = doc.doc_ref_source(&self.storage, false);
+        let s = &self.storage;
+        let width = self.settings.source_width(doc.language(s).name(s));
+        let source = ppp::pretty_print_to_string(doc_ref, width)?;
+        Ok(source)
+    }
+
+    /// Display the whole of the doc `doc_name`, as it's shown on screen but without its cursor,
+    /// into a window created by `make_window` to be exactly its size. It's printed at `width`, or
+    /// at the usual display width if `None`. Used to export docs with their colors.
+    pub fn display_whole_doc<W>(
+        &self,
+        doc_name: &DocName,
+        width: Option<ppp::Width>,
+        make_window: impl FnOnce(ppp::Size) -> W,
+    ) -> Result<W, SynlessError>
+    where
+        W: pane::PrettyWindow<Style = Style>,
+        W::Error: std::error::Error + 'static,
+    {
+        let doc = self
+            .doc_set
+            .get_doc(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
+        let s = &self.storage;
+        let width = width.unwrap_or_else(|| self.settings.display_width(doc.language(s).name(s)));
+        let doc_ref = doc.doc_ref_display(s, false, self.settings.show_hidden);
+        let height = ppp::pretty_print_to_string(doc_ref, width)?
+            .lines()
+            .count()
+            .max(1);
+
+        let mut window = make_window(ppp::Size {
+            width,
+            height: height as ppp::Height,
+        });
+        let notation = pane::PaneNotation::Doc {
+            label: DocDisplayLabel::Visible,
+        };
+        let get_content = |_label| {
+            let options = pane::PrintingOptions {
+                focus_path: vec![],
+                focus_target: ppp::FocusTarget::Start,
+                focus_height: 0.0,
+                width_strategy: pane::WidthStrategy::NoMoreThan(width),
+                set_focus: false,
+            };
+            Some((doc_ref, options))
+        };
+        pane::display_pane(&mut window, &notation, &Style::default(), &get_content)?;
+        Ok(window)
+    }
+
+    /// Display the visible doc into `window`, exactly as it's shown in a pane of the same size
+    /// (including its cursor).
+    pub fn display_visible_doc<W>(&self, window: &mut W) -> Result<(), SynlessError>
+    where
+        W: pane::PrettyWindow<Style = Style>,
+        W::Error: std::error::Error + 'static,
+    {
+        if self.doc_set.visible_doc().is_none() {
+            Err(DocError::NoVisibleDoc)?;
+        }
+        let notation = pane::PaneNotation::Doc {
+            label: DocDisplayLabel::Visible,
+        };
+        let get_content = |label| self.get_content(label);
+        pane::display_pane(window, &notation, &Style::default(), &get_content)?;
+        Ok(())
+    }
+
+    pub fn get_content(&self, label: DocDisplayLabel) -> Option<(DocRef, pane::PrintingOptions)> {
+        self.doc_set
+            .get_content(&self.storage, label, &self.settings)
+    }
+
+    /// Get the content of the named doc, displayed as if it were the visible doc.
+    pub fn get_doc_content(&self, doc_name: &DocName) -> Option<(DocRef, pane::PrintingOptions)> {
+        self.doc_set
+            .get_doc_content(&self.storage, doc_name, &self.settings)
+    }
+
+    /// Toggle between displaying the visible doc at its usual width, and at the width it will be
+    /// printed at when saved. Returns whether the preview is now on.
+    pub fn toggle_width_preview(&mut self) -> bool {
+        let s = &self.storage;
+        let source_width = match self.doc_set.visible_doc() {
+            Some(doc) => self.settings.source_width(doc.language(s).name(s)),
+            None => self.settings.max_source_width,
+        };
+        self.settings.preview_width = match self.settings.preview_width {
+            Some(_) => None,
+            None => Some(source_width),
+        };
+        self.settings.preview_width.is_some()
+    }
+
+    /// Make a doc listing every open file doc, with the visible doc highlighted and unsaved docs
+    /// marked with `*`. Returns `None` if there are no open file docs.
+    pub fn make_tab_bar_doc(&mut self) -> Option<Node> {
+        let tabs = self
+            .doc_set
+            .file_doc_paths()
+            .into_iter()
+            .map(|path| {
+                let doc_name = DocName::File(path.to_owned());
+                let is_visible = self.doc_set.visible_doc_name() == Some(&doc_name);
+                let os_str = path.file_name().unwrap_or(path.as_os_str());
+                let mut label = os_str.to_string_lossy().into_owned();
+                if self
+                    .doc_set
+                    .get_doc(&doc_name)
+                    .map(|doc| doc.has_unsaved_changes())
+                    .unwrap_or(false)
+                {
+                    label.push('*');
+                }
+                (label, is_visible)
+            })
+            .collect::<Vec<_>>();
+        if tabs.is_empty() {
+            return None;
+        }
+        let rows = tabs.into_iter().map(|(label, is_visible)| {
+            let construct = if is_visible { "VisibleTab" } else { "Tab" };
+            (construct, Some(label))
+        });
+        Some(self.make_rows_doc(TAB_BAR_LANGUAGE_NAME, rows))
+    }
+
+    /// Toggle whether nodes with hidden constructs are displayed. Returns whether they're now shown.
+    pub fn toggle_hidden_nodes(&mut self) -> bool {
+        self.settings.show_hidden = !self.settings.show_hidden;
+        self.settings.show_hidden
+    }
+
+    /// Toggle whether to show a scrollbar next to the visible doc. Returns whether it's now shown.
+    pub fn toggle_scrollbar(&mut self) -> bool {
+        self.settings.show_scrollbar = !self.settings.show_scrollbar;
+        self.settings.show_scrollbar
+    }
+
+    /// Make a doc `height` rows tall, showing which part of the visible doc is in view when it's
+    /// displayed in a pane `width` columns wide and `height` rows tall. Returns `None` if the
+    /// scrollbar is turned off or there's no visible doc. The thumb is placed using the doc's
+    /// cached layout (see `visible_doc_extent()`), so the doc isn't printed again unless it's
+    /// changed.
+    pub fn make_scrollbar_doc(
+        &mut self,
+        width: ppp::Width,
+        height: usize,
+    ) -> Result<Option<Node>, SynlessError> {
+        if !self.settings.show_scrollbar || height == 0 {
+            return Ok(None);
+        }
+        let extent = match self.visible_doc_extent(width, height)? {
+            Some(extent) => extent,
+            None => return Ok(None),
+        };
+        let total_height = extent.total_height;
+        let (thumb_start, thumb_len) = if total_height <= height {
+            (0, height)
+        } else {
+            // Scale the scroll position to the track that the thumb moves along, so that the
+            // thumb touches the bottom exactly when the doc is scrolled to its end.
+            let thumb_len = (height * height / total_height).max(1);
+            let max_top_line = total_height - height;
+            let thumb_start =
+                extent.top_line.min(max_top_line) * (height - thumb_len) / max_top_line;
+            (thumb_start, thumb_len)
+        };
+        let rows = (0..height).map(|row| {
+            let construct = if (thumb_start..thumb_start + thumb_len).contains(&row) {
+                "Thumb"
+            } else {
+                "Track"
+            };
+            (construct, None)
+        });
+        Ok(Some(self.make_rows_doc(SCROLLBAR_LANGUAGE_NAME, rows)))
+    }
+
+    /// Set which nodes of the visible doc to shade the background of.
+    pub fn set_shading(&mut self, shading: Shading) {
+        self.settings.shading = shading;
+    }
+
+    /// Set how to number the lines of the visible doc.
+    pub fn set_line_numbers(&mut self, line_numbers: LineNumbers) {
+        self.settings.line_numbers = line_numbers;
+    }
+
+    /// Record that something labeled `label` took `duration`, to be reported by `timing_stats()`.
+    pub fn record_timing(&mut self, label: &'static str, duration: Duration) {
+        self.profiler.record(label, duration);
+    }
+
+    /// How long commands (by kind), layouts of the visible doc, and anything else recorded with
+    /// `record_timing()` have taken.
+    pub fn timing_stats(&self) -> Vec<TimingStats> {
+        self.profiler.stats()
+    }
+
+    pub fn clear_timing_stats(&mut self) {
+        self.profiler.clear();
+    }
+
+    /// Apply the settings from the user's config file, and forget any layouts that were computed
+    /// with the old ones.
+    pub fn apply_config(&mut self, config: &Config) -> Result<(), SynlessError> {
+        self.settings.apply_config(config);
+        self.layout_cache.clear();
+        for language_name in config.languages.keys() {
+            self.apply_language_notations(language_name)?;
+        }
+        Ok(())
+    }
+
+    /// Override the settings for docs in the language `language_name`, replacing any earlier
+    /// overrides for it.
+    pub fn set_language_settings(
+        &mut self,
+        language_name: &str,
+        settings: LanguageSettings,
+    ) -> Result<(), SynlessError> {
+        self.settings
+            .language_settings
+            .insert(language_name.to_owned(), settings);
+        self.layout_cache.clear();
+        self.apply_language_notations(language_name)
+    }
+
+    /// Switch the language `language_name` to the notation sets named in its settings, if it's
+    /// been loaded and has notation sets by those names. Called whenever either might change.
+    fn apply_language_notations(&mut self, language_name: &str) -> Result<(), SynlessError> {
+        let settings = match self.settings.language_settings(language_name) {
+            Some(settings) => settings.clone(),
+            None => return Ok(()),
+        };
+        let language = match self.storage.language(language_name) {
+            Ok(language) => language,
+            Err(_) => return Ok(()),
+        };
+        if let Some(name) = &settings.display_notation {
+            if language.notation(&self.storage, name).is_some() {
+                self.set_display_notation(language_name, name)?;
+            }
+        }
+        if let Some(name) = &settings.source_notation {
+            if language.notation(&self.storage, name).is_some() {
+                self.set_source_notation(language_name, name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The width to print `node` at in its source notation, which depends on its language.
+    fn source_width(&self, node: Node) -> ppp::Width {
+        let s = &self.storage;
+        self.settings.source_width(node.language(s).name(s))
+    }
+
+    /// Set how many widths to remember the visible doc's layout at. 0 turns off the cache.
+    pub fn set_layout_cache_size(&mut self, size: usize) {
+        self.settings.layout_cache_size = size;
+        self.layout_cache.truncate(size);
+    }
+
+    /// Set how many bytes long a file must be to be loaded lazily. 0 turns off lazy loading.
+    pub fn set_lazy_load_size(&mut self, size: usize) {
+        self.settings.lazy_load_size = size;
+    }
+
+    /// The number of columns to set aside to the left of the visible doc for line numbers.
+    pub fn line_number_gutter_width(&self) -> ppp::Width {
+        self.settings.line_number_gutter_width()
+    }
+
+    /// The width of the scrollbar beside the visible doc: zero if it's hidden.
+    pub fn scrollbar_width(&self) -> ppp::Width {
+        if self.settings.show_scrollbar {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Make a doc with one line per row, for drawing the undo history tree. Each row is its text
+    /// and whether it's the current state.
+    pub fn make_undo_tree_doc(&mut self, rows: Vec<(String, bool)>) -> Node {
+        let rows = rows.into_iter().map(|(text, is_current)| {
+            let construct = if is_current { "CurrentState" } else { "State" };
+            (construct, Some(text))
+        });
+        self.make_rows_doc(UNDO_TREE_LANGUAGE_NAME, rows)
+    }
+
+    /// Make a doc with one line per notification, styled by its level.
+    pub fn make_notifications_doc(&mut self, rows: Vec<(LogLevel, String)>) -> Node {
+        let rows = rows.into_iter().map(|(level, text)| {
+            let construct = match level {
+                LogLevel::Trace | LogLevel::Debug | LogLevel::Info => "Info",
+                LogLevel::Warn => "Warning",
+                LogLevel::Error | LogLevel::Bug => "Error",
+            };
+            (construct, Some(text))
+        });
+        self.make_rows_doc(NOTIFICATIONS_LANGUAGE_NAME, rows)
+    }
+
+    /// Make a doc listing the problems found in the visible doc by the last run of the lint rules,
+    /// one per line. Returns `None` if there's no visible doc.
+    pub fn make_lint_results_doc(&mut self) -> Option<Node> {
+        let doc = self.doc_set.visible_doc()?;
+        let s = &self.storage;
+        let rows = if doc.diagnostics().is_empty() {
+            vec![("Message", Some("No problems found".to_owned()))]
+        } else {
+            doc.diagnostics()
+                .iter()
+                .map(|diag| {
+                    let place = match doc.find_node(s, diag.node) {
+                        Some(node) => node.summary(s),
+                        None => "(deleted)".to_owned(),
+                    };
+                    let text = format!("{place}: {} [{}]", diag.message, diag.rule);
+                    ("Problem", Some(text))
+                })
+                .collect()
+        };
+        Some(self.make_rows_doc(LINT_RESULTS_LANGUAGE_NAME, rows))
+    }
+
+    /// Make a doc `height` rows tall, numbering the lines of the visible doc when it's displayed
+    /// in a pane `width` columns wide and `height` rows tall. Each line is padded to the full
+    /// gutter width. Returns `None` if line numbers are off or there's no visible doc.
+    pub fn make_line_numbers_doc(
+        &mut self,
+        width: ppp::Width,
+        height: usize,
+    ) -> Result<Option<Node>, SynlessError> {
+        let gutter_width = self.settings.line_number_gutter_width() as usize;
+        if gutter_width == 0 || height == 0 {
+            return Ok(None);
+        }
+        let extent = match self.visible_doc_extent(width, height)? {
+            Some(extent) => extent,
+            None => return Ok(None),
+        };
+        let relative = self.settings.line_numbers == LineNumbers::Relative;
+        let rows = (extent.top_line..extent.top_line + height).map(|line| {
+            let number = if line >= extent.total_height {
+                String::new()
+            } else if relative && line != extent.cursor_line {
+                line.abs_diff(extent.cursor_line).to_string()
+            } else {
+                (line + 1).to_string()
+            };
+            let construct = if line == extent.cursor_line {
+                "CursorLine"
+            } else {
+                "Line"
+            };
+            // Right-align the number, leaving a space before the doc.
+            let text = format!("{:>1$} ", number, gutter_width - 1);
+            (construct, Some(text))
+        });
+        Ok(Some(self.make_rows_doc(LINE_NUMBERS_LANGUAGE_NAME, rows)))
+    }
+
+    /// Make a doc in the builtin language `language_name` with one child of the root per row.
+    /// Each row is the name of its construct, and its text if the construct is texty.
+    fn make_rows_doc(
+        &mut self,
+        language_name: &str,
+        rows: impl IntoIterator<Item = (&'static str, Option<String>)>,
+    ) -> Node {
+        let s = &mut self.storage;
+        let lang = s.language(language_name).bug_msg("Missing builtin lang");
+        let root = Node::new(s, lang.root_construct(s));
+        for (construct_name, text) in rows {
+            let construct = lang.construct(s, construct_name).bug();
+            let row = match text {
+                Some(text) => Node::with_text(s, construct, text).bug(),
+                None => Node::new(s, construct),
+            };
+            root.insert_last_child(s, row).bug();
+        }
+        root
+    }
+
+    /// Where the visible doc's lines fall when it's displayed in a pane `width` columns wide and
+    /// `height` rows tall. Returns `None` if there's no visible doc.
+    ///
+    /// This prints the whole doc, unless its layout at this width is in the layout cache. The cache
+    /// is keyed on the doc's version, so any edit to the doc means printing all of it again. Moving
+    /// the cursor doesn't change the layout, so the cursor's line is looked up from the line of
+    /// the node it's on. It assumes that the pane puts the cursor at `focus_height`, except where
+    /// that would leave blank rows at the top or bottom of the pane.
+    fn visible_doc_extent(
+        &mut self,
+        width: ppp::Width,
+        height: usize,
+    ) -> Result<Option<DocExtent>, SynlessError> {
+        let doc = match self.doc_set.visible_doc() {
+            Some(doc) => doc,
+            None => return Ok(None),
+        };
+        let s = &self.storage;
+        let print_width = width.min(self.settings.display_width(doc.language(s).name(s)));
+        let key = LayoutKey {
+            doc_version: doc.version(),
+            width: print_width,
+            show_hidden: self.settings.show_hidden,
+        };
+        // TODO (optimization): after an edit, re-measure only the edited node and its ancestors,
+        // reusing every other node's measurements. ppp measures notations inside
+        // `display_pane()` and doesn't let a subtree's measurements be reused, so that needs
+        // support from ppp. Until then, any edit means printing this whole layout again.
+        let layout = match self.layout_cache.get(&key) {
+            Some(layout) => layout,
+            None => {
+                let start = Instant::now();
+                let layout = Rc::new(self.layout_visible_doc(print_width)?.bug());
+                self.profiler.record("layout", start.elapsed());
+                self.layout_cache
+                    .insert(key, layout.clone(), self.settings.layout_cache_size);
+                layout
+            }
+        };
+        let cursor = doc.cursor();
+        let cursor_node = cursor
+            .at_node(s)
+            .or_else(|| cursor.in_text_node(s))
+            .or_else(|| cursor.parent_node(s))
+            .bug();
+        let cursor_line = layout.node_lines.line_of(s, cursor_node);
+
+        let focus_row = (self.settings.focus_height * height as f32) as usize;
+        let top_line = cursor_line
+            .saturating_sub(focus_row)
+            .min(layout.total_height.saturating_sub(height));
+        Ok(Some(DocExtent {
+            total_height: layout.total_height,
+            top_line,
+            cursor_line,
+        }))
+    }
+
+    /// Print the whole visible doc at `print_width`, to find its height and the line of each of
+    /// its nodes. Returns `None` if there's no visible doc.
+    ///
+    /// The doc is printed once, into an offscreen window taller than any doc could be; its height
+    /// is the number of lines that anything was drawn on.
+    fn layout_visible_doc(
+        &self,
+        print_width: ppp::Width,
+    ) -> Result<Option<DocLayout>, SynlessError> {
+        let doc = match self.doc_set.visible_doc() {
+            Some(doc) => doc,
+            None => return Ok(None),
+        };
+        let s = &self.storage;
+
+        let doc_ref = doc.doc_ref_display(s, false, self.settings.show_hidden);
+        let mut recorder = NodeLinesRecorder::new(ppp::Size {
+            width: print_width,
+            height: LAYOUT_HEIGHT,
+        });
+        let notation = pane::PaneNotation::Doc {
+            label: DocDisplayLabel::Visible,
+        };
+        let get_content = |_label| {
+            let options = pane::PrintingOptions {
+                focus_path: vec![],
+                focus_target: ppp::FocusTarget::Start,
+                focus_height: 0.0,
+                width_strategy: pane::WidthStrategy::NoMoreThan(print_width),
+                set_focus: false,
+            };
+            Some((doc_ref, options))
+        };
+        pane::display_pane(&mut recorder, &notation, &Style::default(), &get_content)?;
+        let node_lines = recorder.finish();
+        Ok(Some(DocLayout {
+            total_height: node_lines.num_lines(),
+            node_lines,
+        }))
+    }
+
+    pub fn make_string_doc(&mut self, string: String, bg_color: Option<Base16Color>) -> Node {
+        let lang = self
+            .storage
+            .language(STRING_LANGUAGE_NAME)
+            .bug_msg("Missing String lang");
+        let c_root = lang.root_construct(&self.storage);
+        let c_string = lang.construct(&self.storage, "String").bug();
+        let string_node = Node::with_text(&mut self.storage, c_string, string).bug();
+        let node = if let Some(color) = bg_color {
+            let c_color = match color {
+                Base16Color::Base08 => lang.construct(&self.storage, "BgBase08").bug(),
+                Base16Color::Base0B => lang.construct(&self.storage, "BgBase0B").bug(),
+                _ => bug!("make_string_doc: specified bg color not yet supported"),
+            };
+            Node::with_children(&mut self.storage, c_color, [string_node]).bug()
+        } else {
+            string_node
+        };
+        Node::with_children(&mut self.storage, c_root, [node]).bug()
+    }
+
+    /*************
+     * Accessing *
+     *************/
+
+    pub fn node_at_cursor(&mut self, deep_copy: bool) -> Result<Node, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let mut node = doc.node_at_cursor(&self.storage)?;
+        if deep_copy {
+            node = node.deep_copy(&mut self.storage);
+        }
+        Ok(node)
+    }
+
+    /// The path to the node at the cursor in the visible doc. See [`Node::path`].
+    pub fn path_at_cursor(&self) -> Result<String, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        Ok(doc.node_at_cursor(&self.storage)?.path(&self.storage))
+    }
+
+    /// Move the cursor in the doc `doc_name` to the node at `path`. See [`Node::at_path`].
+    pub fn goto_path(&mut self, doc_name: &DocName, path: &str) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .get_doc_mut(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
+        let node = doc
+            .cursor()
+            .root_node(&self.storage)
+            .at_path(&self.storage, path)?;
+        let cmd = TreeNavCommand::GotoNode(node).into();
+        doc.execute(&mut self.storage, cmd, &mut self.clipboard)?;
+        Ok(())
+    }
+
+    pub fn visible_doc_root(&self) -> Result<Node, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        Ok(doc.cursor().root_node(&self.storage))
+    }
+
+    /// Move the cursor to `node`, which must be in the visible doc.
+    pub fn goto_node(&mut self, node: Node) -> Result<(), SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let s = &self.storage;
+        if !node.is_valid(s) || node.root(s) != doc.cursor().root_node(s) {
+            return Err(error!(Edit, "That node isn't in the visible doc"));
+        }
+        self.execute(TreeNavCommand::GotoNode(node))
+    }
+
+    /// Replace `node` in the visible doc with `new_node`, which must be a root that isn't in any
+    /// doc, leaving the cursor on it.
+    pub fn replace_node(&mut self, node: Node, new_node: Node) -> Result<(), SynlessError> {
+        self.goto_node(node)?;
+        self.execute(TreeEdCommand::Replace(new_node))
+    }
+
+    /// Pretty-print the node at the cursor in the visible doc, in its source notation, at `width`
+    /// (or if `None`, at the source width for its language).
+    pub fn print_node_at_cursor(&self, width: Option<ppp::Width>) -> Result<String, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let node = doc.node_at_cursor(&self.storage)?;
+        let doc_ref = DocRef::new_source(&self.storage, None, node);
+        let width = width.unwrap_or_else(|| self.source_width(node));
+        Ok(ppp::pretty_print_to_string(doc_ref, width)?)
+    }
+
+    /// The constructs that the grammar allows to be inserted at the cursor in the visible doc.
+    pub fn insertable_constructs(&self) -> Result<Vec<Construct>, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let sort = doc
+            .cursor()
+            .insertion_sort(&self.storage)
+            .ok_or_else(|| error!(Edit, "Can't insert a node here"))?;
+        Ok(sort.matching_constructs(&self.storage).collect())
+    }
+
+    /// The constructs that the node at the cursor in the visible doc can be wrapped in: those that
+    /// the grammar allows in its place, and that have a child position that allows it.
+    pub fn wrapping_constructs(&self) -> Result<Vec<Construct>, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let s = &self.storage;
+        let construct = doc.node_at_cursor(s)?.construct(s);
+        let mut constructs = self.insertable_constructs()?;
+        constructs.retain(|wrapper| match wrapper.arity(s) {
+            Arity::Texty => false,
+            Arity::Fixed(sorts) => {
+                (0..sorts.len(s)).any(|i| sorts.get(s, i).bug().accepts(s, construct))
+            }
+            Arity::Listy(sort) => sort.accepts(s, construct),
+        });
+        Ok(constructs)
+    }
+
+    /// A one-line preview of how a new node of this construct is displayed.
+    pub fn construct_preview(&mut self, construct: Construct) -> Result<String, SynlessError> {
+        let node = Node::new_with_auto_fill(&mut self.storage, construct);
+        let no_search = None;
+        let doc_ref = DocRef::new_display(&self.storage, None, node, &no_search, None, true);
+        let printed = ppp::pretty_print_to_string(doc_ref, CONSTRUCT_PREVIEW_WIDTH);
+        node.delete_root(&mut self.storage).bug();
+        let printed = printed?;
+        let mut lines = printed.lines();
+        let first_line = lines.next().unwrap_or_default().trim_end();
+        if lines.next().is_some() {
+            Ok(format!("{first_line} …"))
+        } else {
+            Ok(first_line.to_owned())
+        }
+    }
+
+    /// Every node with a hidden construct in the visible doc, in document order.
+    pub fn hidden_nodes(&self) -> Result<Vec<Node>, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        Ok(doc.hidden_nodes(&self.storage))
+    }
+
+    /// Every node in the visible doc (except the root), in document order.
+    pub fn visible_doc_nodes(&self) -> Result<Vec<Node>, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        Ok(doc.nodes(&self.storage))
+    }
+
+    /// The problems found in the visible doc by the last run of the lint rules, in document order.
+    pub fn diagnostics(&self) -> Result<&[Diagnostic], SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        Ok(doc.diagnostics())
+    }
+
+    /// Replace the visible doc's diagnostics with the given ones, found by lint rules written in
+    /// rhai, together with those found by the built-in rules and the doc's JSON schema (if any).
+    /// Returns how many there are in all.
+    pub fn set_diagnostics(
+        &mut self,
+        mut diagnostics: Vec<Diagnostic>,
+    ) -> Result<usize, SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        let s = &self.storage;
+        let root = doc.cursor().root_node(s);
+        diagnostics.extend(builtin_diagnostics(s, root));
+        if let Some(schema) = doc.json_schema() {
+            diagnostics.extend(schema.validate(s, root));
+        }
+        let positions = doc
+            .nodes(s)
+            .into_iter()
+            .enumerate()
+            .map(|(i, node)| (node.id(s), i))
+            .collect::<HashMap<_, _>>();
+        diagnostics.sort_by_key(|diag| positions.get(&diag.node).copied());
+        let count = diagnostics.len();
+        doc.set_diagnostics(diagnostics);
+        Ok(count)
+    }
+
+    /// Set the JSON schema to validate the visible doc against when its diagnostics are next set,
+    /// or stop validating it if `None`. The doc must be in the json language.
+    pub fn set_json_schema(&mut self, schema: Option<JsonSchema>) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        let s = &self.storage;
+        let language_name = doc.cursor().root_node(s).language(s).name(s);
+        if schema.is_some() && language_name != JSON_LANGUAGE_NAME {
+            return Err(error!(
+                Language,
+                "Can only validate json docs against a JSON schema, not {language_name} docs"
+            ));
+        }
+        doc.set_json_schema(schema);
+        Ok(())
+    }
+
+    /// The node of the first diagnostic after the cursor in the visible doc, wrapping around to
+    /// the first diagnostic in the doc. Skips diagnostics whose nodes have been deleted.
+    pub fn next_diagnostic_node(&self) -> Result<Option<Node>, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let ids = doc
+            .diagnostics()
+            .iter()
+            .map(|diag| diag.node)
+            .collect::<Vec<_>>();
+        Ok(self.next_node_with_id(doc, &ids))
+    }
+
+    /// Spell check prose against `dictionary`, or turn spell checking off if `None` (which clears
+    /// the visible doc's misspellings).
+    pub fn set_dictionary(&mut self, dictionary: Option<Dictionary>) {
+        if dictionary.is_none() {
+            if let Some(doc) = self.doc_set.visible_doc_mut() {
+                doc.set_misspellings(Vec::new());
+            }
+        }
+        self.dictionary = dictionary;
+    }
+
+    /// Replace the visible doc's misspellings with the misspelled words in its prose nodes.
+    /// Returns how many there are.
+    pub fn spell_check(&mut self) -> Result<usize, SynlessError> {
+        let dictionary = self.dictionary.as_ref().ok_or(EditError::NoDictionary)?;
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        let s = &self.storage;
+        let misspellings = dictionary.check(s, doc.cursor().root_node(s));
+        let count = misspellings.len();
+        doc.set_misspellings(misspellings);
+        Ok(count)
+    }
+
+    /// The misspelled words found in the visible doc by the last spell check, in document order.
+    pub fn misspellings(&self) -> Result<&[Misspelling], SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        Ok(doc.misspellings())
+    }
+
+    /// The misspelled word at the cursor in the visible doc, and the dictionary's suggested
+    /// corrections for it, closest first.
+    pub fn spelling_suggestions(&self) -> Result<(String, Vec<String>), SynlessError> {
+        let dictionary = self.dictionary.as_ref().ok_or(EditError::NoDictionary)?;
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let misspelling = doc
+            .misspelling_at_cursor(&self.storage)
+            .ok_or(EditError::NoMisspellingHere)?;
+        let suggestions = dictionary.suggestions(&misspelling.word);
+        Ok((misspelling.word.clone(), suggestions))
+    }
+
+    /// Replace the misspelled word at the cursor in the visible doc with `correction`, then spell
+    /// check the doc again. This replaces the whole text node, leaving the cursor on it in tree
+    /// mode, so that it's undone in one step.
+    pub fn correct_spelling(&mut self, correction: &str) -> Result<(), SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let s = &self.storage;
+        let misspelling = doc
+            .misspelling_at_cursor(s)
+            .ok_or(EditError::NoMisspellingHere)?;
+        let node = doc.find_node(s, misspelling.node).bug();
+        let (before, after) = node.text(s).bug().as_split_str(misspelling.start);
+        let new_text = format!("{before}{correction}{}", &after[misspelling.word.len()..]);
+        let construct = node.construct(s);
+        let in_text_mode = doc.cursor().mode() == Mode::Text;
+
+        if in_text_mode {
+            self.execute(TextNavCommand::ExitText)?;
+        }
+        let replacement = Node::with_text(&mut self.storage, construct, new_text).bug();
+        if let Err(err) = self.execute(TreeEdCommand::Replace(replacement)) {
+            replacement.delete_root(&mut self.storage).bug();
+            return Err(err);
+        }
+        self.spell_check()?;
+        Ok(())
+    }
+
+    /// The node of the first misspelling after the cursor in the visible doc, wrapping around to
+    /// the first misspelling in the doc.
+    pub fn next_misspelling_node(&self) -> Result<Option<Node>, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let ids = doc
+            .misspellings()
+            .iter()
+            .map(|misspelling| misspelling.node)
+            .collect::<Vec<_>>();
+        Ok(self.next_node_with_id(doc, &ids))
+    }
+
+    /// The first node after the cursor in `doc` whose id is in `ids`, wrapping around to the
+    /// first such node in the doc.
+    fn next_node_with_id(&self, doc: &Doc, ids: &[NodeId]) -> Option<Node> {
+        let s = &self.storage;
+        let cursor = doc.cursor();
+        let cursor_node = cursor
+            .at_node(s)
+            .or_else(|| cursor.in_text_node(s))
+            .or_else(|| cursor.parent_node(s));
+        let nodes = doc.nodes(s);
+        let positions_by_id = nodes
+            .iter()
+            .enumerate()
+            .map(|(pos, node)| (node.id(s), pos))
+            .collect::<HashMap<_, _>>();
+        let cursor_pos = cursor_node.and_then(|node| positions_by_id.get(&node.id(s)).copied());
+        let mut positions = ids
+            .iter()
+            .filter_map(|id| positions_by_id.get(id).copied())
+            .collect::<Vec<_>>();
+        positions.sort_unstable();
+        let next = positions
+            .iter()
+            .find(|pos| Some(**pos) > cursor_pos)
+            .or(positions.first());
+        next.map(|pos| nodes[*pos])
+    }
+
+    /// Mark the nodes of `doc_name` that were added or modified since the version of it in
+    /// `old_source`, which is parsed with the same parser, so that they're highlighted. If
+    /// `old_source` is `None`, clear the marks instead. Returns how many nodes are marked.
+    pub fn mark_changes_since(
+        &mut self,
+        doc_name: &DocName,
+        old_source: Option<&str>,
+    ) -> Result<usize, SynlessError> {
+        let doc = self
+            .doc_set
+            .get_doc(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
+        let root = doc.cursor().root_node(&self.storage);
+        let changes = if let Some(old_source) = old_source {
+            let language_name = root.language(&self.storage).name(&self.storage).to_owned();
+            let old_root = self.parse_source(doc_name, &language_name, old_source)?;
+            let s = &self.storage;
+            let changes = root
+                .changes_since(s, old_root)
+                .into_iter()
+                .map(|(node, change)| (node.id(s), change))
+                .collect::<HashMap<_, _>>();
+            old_root.delete_root(&mut self.storage).bug();
+            changes
+        } else {
+            HashMap::new()
+        };
+        let count = changes.len();
+        self.doc_set
+            .get_doc_mut(doc_name)
+            .bug()
+            .set_changes(changes);
+        Ok(count)
+    }
+
+    /// A tree-aware edit script that turns `doc_a` into `doc_b`. See [`Node::edit_script`].
+    pub fn diff(&self, doc_a: &DocName, doc_b: &DocName) -> Result<Vec<DiffOp>, SynlessError> {
+        let s = &self.storage;
+        let root_a = self.doc_root(doc_a)?;
+        let root_b = self.doc_root(doc_b)?;
+        Ok(root_a.edit_script(s, root_b))
+    }
+
+    /// Mark the differences between `doc_a` and `doc_b` so that they're highlighted: deleted and
+    /// modified nodes in `doc_a`, and inserted and modified nodes in `doc_b`. Replaces any other
+    /// marks on them. Returns the number of differences.
+    pub fn mark_diff(&mut self, doc_a: &DocName, doc_b: &DocName) -> Result<usize, SynlessError> {
+        let ops = self.diff(doc_a, doc_b)?;
+        let s = &self.storage;
+        let mut changes_a = HashMap::new();
+        let mut changes_b = HashMap::new();
+        for op in &ops {
+            match *op {
+                DiffOp::Insert(new) => {
+                    changes_b.insert(new.id(s), NodeChange::Added);
+                }
+                DiffOp::Delete(old) => {
+                    changes_a.insert(old.id(s), NodeChange::Deleted);
+                }
+                DiffOp::Modify { old, new } => {
+                    changes_a.insert(old.id(s), NodeChange::Modified);
+                    changes_b.insert(new.id(s), NodeChange::Modified);
+                }
+            }
+        }
+        self.doc_set.get_doc_mut(doc_a).bug().set_changes(changes_a);
+        self.doc_set.get_doc_mut(doc_b).bug().set_changes(changes_b);
+        Ok(ops.len())
+    }
+
+    /// Open a new doc `doc_name` that merges `ours_source` and `theirs_source`, two edited
+    /// versions of `base_source` in the language `language_name`. See [`merge`]. The doc's
+    /// conflicts are highlighted until they're resolved with `resolve_conflict()`. Returns how
+    /// many there are.
+    pub fn merge_sources(
+        &mut self,
+        doc_name: &DocName,
+        language_name: &str,
+        base_source: &str,
+        ours_source: &str,
+        theirs_source: &str,
+    ) -> Result<usize, SynlessError> {
+        if self.doc_set.get_doc(doc_name).is_some() {
+            Err(DocError::DocAlreadyOpen(doc_name.to_owned()))?;
+        }
+        let mut roots = Vec::new();
+        for source in [base_source, ours_source, theirs_source] {
+            match self.parse_source(doc_name, language_name, source) {
+                Ok(root) => roots.push(root),
+                Err(err) => {
+                    for root in roots {
+                        root.delete_root(&mut self.storage).bug();
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        let (merged, conflicts) = merge(&mut self.storage, roots[0], roots[1], roots[2]);
+        for root in roots {
+            root.delete_root(&mut self.storage).bug();
+        }
+        let count = conflicts.len();
+        let mut doc = Doc::new(&self.storage, merged, false).bug_msg("Invalid root");
+        doc.set_conflicts(&mut self.storage, conflicts);
+        bug_assert!(self.doc_set.add_doc(doc_name.to_owned(), doc));
+        Ok(count)
+    }
+
+    /// The nodes of the visible doc's merge conflicts that are still unresolved, in document
+    /// order.
+    pub fn unresolved_conflicts(&self) -> Result<Vec<Node>, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let s = &self.storage;
+        let conflict_ids = doc
+            .conflicts()
+            .iter()
+            .map(|c| c.node)
+            .collect::<HashSet<_>>();
+        Ok(doc
+            .nodes(s)
+            .into_iter()
+            .filter(|node| conflict_ids.contains(&node.id(s)))
+            .collect())
+    }
+
+    /// The node of the first unresolved merge conflict after the cursor in the visible doc,
+    /// wrapping around to the first one in the doc.
+    pub fn next_conflict_node(&self) -> Result<Option<Node>, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let ids = doc.conflicts().iter().map(|c| c.node).collect::<Vec<_>>();
+        Ok(self.next_node_with_id(doc, &ids))
+    }
+
+    /// Resolve the merge conflict at the cursor, or at its nearest ancestor that has one, by
+    /// replacing it with our version of the node if `take_ours` or their version if not. This is
+    /// an ordinary edit, so it can be undone.
+    pub fn resolve_conflict(&mut self, take_ours: bool) -> Result<(), SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let s = &self.storage;
+        let cursor = doc.cursor();
+        let mut opt_node = cursor
+            .at_node(s)
+            .or_else(|| cursor.in_text_node(s))
+            .or_else(|| cursor.parent_node(s));
+        let (node, conflict) = loop {
+            let node = opt_node.ok_or_else(|| error!(Edit, "No merge conflict at the cursor"))?;
+            if let Some(conflict) = doc.conflicts().iter().find(|c| c.node == node.id(s)) {
+                break (node, *conflict);
+            }
+            opt_node = node.parent(s);
+        };
+        let version = if take_ours {
+            conflict.ours
+        } else {
+            conflict.theirs
+        };
+        let replacement = version.deep_copy(&mut self.storage);
+        self.execute(TreeNavCommand::GotoNode(node))?;
+        self.execute(TreeEdCommand::Replace(replacement))
+    }
+
+    fn doc_root(&self, doc_name: &DocName) -> Result<Node, SynlessError> {
+        let doc = self
+            .doc_set
+            .get_doc(doc_name)
+            .ok_or_else(|| DocError::DocNotFound(doc_name.to_owned()))?;
+        Ok(doc.cursor().root_node(&self.storage))
+    }
+
+    /// Find the node with the given id in the visible doc, if any.
+    pub fn visible_node_with_id(&self, id: NodeId) -> Option<Node> {
+        let doc = self.doc_set.visible_doc()?;
+        doc.find_node(&self.storage, id)
+    }
+
+    /***********
+     * Editing *
+     ***********/
+
+    pub fn execute(&mut self, cmd: impl Into<Command>) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        let cmd = cmd.into();
+        let kind = cmd.kind();
+        let start = Instant::now();
+        let result = doc.execute(&mut self.storage, cmd, &mut self.clipboard);
+        self.profiler.record(kind, start.elapsed());
+        result?;
+        Ok(())
+    }
+
+    pub fn undo(&mut self) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        doc.undo(&mut self.storage)?;
+        Ok(())
+    }
+
+    pub fn redo(&mut self) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        doc.redo(&mut self.storage)?;
+        Ok(())
+    }
+
+    /// The visible doc's undo history, oldest first, together with how many of those entries are
+    /// currently applied.
+    pub fn undo_history(&self) -> Result<(Vec<HistoryEntry>, usize), SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        Ok((doc.history(), doc.history_position()))
+    }
+
+    /// Undo or redo the visible doc until exactly `position` entries of its history are applied.
+    pub fn goto_undo_history(&mut self, position: usize) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        doc.goto_history_position(&mut self.storage, position)?;
+        Ok(())
+    }
+
+    /// The visible doc's whole undo history tree, together with the id of its current state.
+    pub fn undo_history_tree(&self) -> Result<(Vec<HistoryTreeNode>, usize), SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        Ok((doc.history_tree(), doc.history_state()))
+    }
+
+    /// Undo and redo the visible doc until it's in the state with the given id in its undo
+    /// history tree.
+    pub fn goto_undo_state(&mut self, id: usize) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        doc.goto_history_state(&mut self.storage, id)?;
+        Ok(())
+    }
+
+    /// Move the visible doc to the same point on the previous or next branch of its undo history.
+    pub fn switch_undo_branch(&mut self, forward: bool) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        doc.switch_history_branch(&mut self.storage, forward)?;
+        Ok(())
+    }
+
+    pub fn end_undo_group(&mut self) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        doc.end_undo_group();
+        Ok(())
+    }
+
+    pub fn revert_undo_group(&mut self) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        doc.revert_undo_group(&mut self.storage);
+        Ok(())
+    }
+
+    /// Replace the node at the cursor, which must match the current search, with a copy of the
+    /// node on top of the clipboard. Any `$name` placeholders in the copy are filled in with what
+    /// the search pattern captured as `name`.
+    pub fn replace_search_match(&mut self) -> Result<(), SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let node = doc.node_at_cursor(&self.storage)?;
+        let search = doc.search().ok_or(EditError::NoSearch)?;
+        let captures = search
+            .captures(&self.storage, node)
+            .ok_or(EditError::NoMatchHere)?;
+        let template = self.clipboard_top()?;
+        let replacement = instantiate_template(&mut self.storage, template, &captures);
+        let result = self.execute(TreeEdCommand::Replace(replacement));
+        if result.is_err() {
+            replacement.delete_root(&mut self.storage).bug();
+        }
+        result
+    }
+
+    /// Do `replace_search_match()` on every match of the current search in the visible doc, as a
+    /// single undo group. Matches inside of other matches are skipped, since they get replaced
+    /// along with the outer match. Returns the number of replacements made.
+    pub fn replace_all_search_matches(&mut self) -> Result<usize, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let search = doc.search().ok_or(EditError::NoSearch)?;
+        let s = &self.storage;
+        let mut matches: Vec<Node> = Vec::new();
+        let root = doc.cursor().root_node(s);
+        root.visit(s, |node| {
+            if node != root && search.matches(s, node) {
+                matches.push(node);
+                Visit::SkipChildren
+            } else {
+                Visit::Continue
+            }
+        });
+        if matches.is_empty() {
+            return Err(EditError::NoNextMatch.into());
+        }
+
+        self.end_undo_group()?;
+        for node in &matches {
+            let result = self
+                .execute(TreeNavCommand::GotoNode(*node))
+                .and_then(|()| self.replace_search_match());
+            if let Err(err) = result {
+                self.revert_undo_group()?;
+                return Err(err);
+            }
+        }
+        self.end_undo_group()?;
+        Ok(matches.len())
+    }
+
+    /// Begin an incremental search in the visible doc. See [`Doc::begin_incremental_search()`].
+    pub fn begin_incremental_search(&mut self) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        doc.begin_incremental_search()?;
+        Ok(())
+    }
+
+    /// Highlight the matches of `search` in the visible doc, and move the cursor to the nearest
+    /// one. See [`Doc::preview_search()`].
+    pub fn preview_search(&mut self, search: Option<Search>) -> Result<bool, SynlessError> {
+        let doc = match self.doc_set.visible_doc_mut() {
+            Some(doc) => doc,
+            None => {
+                if let Some(search) = search {
+                    search.delete(&mut self.storage);
+                }
+                return Err(DocError::NoVisibleDoc.into());
+            }
+        };
+        Ok(doc.preview_search(&mut self.storage, search)?)
+    }
+
+    /// End the incremental search in the visible doc, keeping the previewed search if `commit`.
+    /// See [`Doc::end_incremental_search()`].
+    pub fn end_incremental_search(&mut self, commit: bool) -> Result<(), SynlessError> {
+        let doc = self
+            .doc_set
+            .visible_doc_mut()
+            .ok_or(DocError::NoVisibleDoc)?;
+        doc.end_incremental_search(&mut self.storage, commit)?;
+        Ok(())
+    }
+
+    /// The structural differences between the node at the cursor in the visible doc and the node
+    /// at the cursor in the open doc `other_doc_name`, as described by [`Node::diff`]. Use
+    /// `replace_from_doc()` to apply the other doc's version.
+    pub fn diff_with_doc(&self, other_doc_name: &DocName) -> Result<Vec<String>, SynlessError> {
+        let (node, other_node) = self.nodes_at_cursors(other_doc_name)?;
+        Ok(node.diff(&self.storage, other_node))
+    }
+
+    /// Replace the node at the cursor in the visible doc with a copy of the node at the cursor in
+    /// the open doc `other_doc_name`. This is an ordinary edit, so it can be undone.
+    pub fn replace_from_doc(&mut self, other_doc_name: &DocName) -> Result<(), SynlessError> {
+        let (_, other_node) = self.nodes_at_cursors(other_doc_name)?;
+        let copy = other_node.deep_copy(&mut self.storage);
+        if let Err(err) = self.execute(TreeEdCommand::Replace(copy)) {
+            copy.delete_root(&mut self.storage).bug();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// The nodes at the cursor in the visible doc and in the open doc `other_doc_name`.
+    fn nodes_at_cursors(&self, other_doc_name: &DocName) -> Result<(Node, Node), SynlessError> {
+        let s = &self.storage;
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let other_doc = self
+            .doc_set
+            .get_doc(other_doc_name)
+            .ok_or_else(|| DocError::DocNotFound(other_doc_name.to_owned()))?;
+        Ok((doc.node_at_cursor(s)?, other_doc.node_at_cursor(s)?))
+    }
+
+    /// The node on top of the clipboard, which must be the only node in its entry.
+    fn clipboard_top(&self) -> Result<Node, EditError> {
+        match self.clipboard.last().map(|nodes| &nodes[..]) {
+            None => Err(EditError::EmptyClipboard),
+            Some([node]) => Ok(*node),
+            Some(_) => Err(EditError::SeveralNodesOnClipboard),
+        }
+    }
+
+    /// A summary of each entry on the clipboard stack, from the top down. See [`Node::summary`].
+    pub fn clipboard_summaries(&self) -> Vec<String> {
+        self.clipboard
+            .iter()
+            .rev()
+            .map(|nodes| {
+                nodes
+                    .iter()
+                    .map(|node| node.summary(&self.storage))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .collect()
+    }
+
+    /*****************
+     * Drag and Drop *
+     *****************/
+
+    /// Whether `source` could be moved onto `target` with `move_node`. That requires that they're
+    /// both in the visible doc, `target` isn't inside of `source`, and either `target` is in a
+    /// listy sequence that accepts `source`, or `target` is a hole whose position accepts
+    /// `source`.
+    pub fn can_move_node(&self, source: Node, target: Node) -> bool {
+        let s = &self.storage;
+        let doc = match self.doc_set.visible_doc() {
+            Some(doc) => doc,
+            None => return false,
+        };
+        let root = doc.cursor().root_node(s);
+        if !source.is_valid(s) || !target.is_valid(s) {
+            return false;
+        }
+        if source.is_root(s) || source.root(s) != root || target.root(s) != root {
+            return false;
+        }
+        let mut ancestor = Some(target);
+        while let Some(node) = ancestor {
+            if node == source {
+                return false;
+            }
+            ancestor = node.parent(s);
+        }
+        let target_is_listy_child = target
+            .parent(s)
+            .map(|parent| matches!(parent.arity(s), Arity::Listy(_)))
+            .unwrap_or(false);
+        (target_is_listy_child || target.is_hole(s))
+            && Location::at(s, target).can_insert(s, source)
+    }
+
+    /// Move `source` onto `target`: after it if `target` is in a listy sequence, or in its place
+    /// if it's a hole. The move is a single undo group, and leaves the clipboard alone.
+    pub fn move_node(&mut self, source: Node, target: Node) -> Result<(), SynlessError> {
+        if !self.can_move_node(source, target) {
+            return Err(EditError::CannotPlaceNode.into());
+        }
+        self.end_undo_group()?;
+        // The deleted `source` belongs to the undo history, so a copy of it is what's inserted.
+        let copy = source.deep_copy(&mut self.storage);
+        let result = self
+            .execute(TreeNavCommand::GotoNode(source))
+            .and_then(|()| self.execute(TreeEdCommand::Delete))
+            .and_then(|()| self.execute(TreeNavCommand::GotoNode(target)))
+            .and_then(|()| self.execute(TreeEdCommand::Insert(copy)));
+        if result.is_err() {
+            self.revert_undo_group()?;
+            copy.delete_root(&mut self.storage).bug();
+        }
+        result?;
+        self.end_undo_group()
+    }
+
+    /// Sort the children of the list at the cursor (or if the node at the cursor isn't listy, of
+    /// its parent) by the given key. Equal children keep their order. The sort is a single undo
+    /// group.
+    pub fn sort_children(&mut self, by: SortChildrenBy) -> Result<(), SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let s = &self.storage;
+        let node = doc.node_at_cursor(s)?;
+        let list = if matches!(node.arity(s), Arity::Listy(_)) {
+            node
+        } else {
+            node.parent(s)
+                .filter(|parent| matches!(parent.arity(s), Arity::Listy(_)))
+                .ok_or_else(|| error!(Edit, "Not in a list"))?
+        };
+
+        let mut children = Vec::new();
+        let mut child = list.first_child(s);
+        while let Some(node) = child {
+            let key = match by {
+                SortChildrenBy::Source => {
+                    let doc_ref = DocRef::new_source(s, None, node);
+                    ppp::pretty_print_to_string(doc_ref, self.source_width(node))?
+                }
+                SortChildrenBy::Text => first_text(s, node).unwrap_or_default().to_owned(),
+            };
+            children.push((key, node));
+            child = node.next_sibling(s);
+        }
+        let mut sorted = children.clone();
+        sorted.sort_by(|(key_1, _), (key_2, _)| key_1.cmp(key_2));
+        if sorted
+            .iter()
+            .map(|(_, node)| node)
+            .eq(children.iter().map(|(_, node)| node))
+        {
+            return Ok(());
+        }
+
+        // Replace each child with a copy of the child that belongs in its place.
+        let copies = sorted
+            .iter()
+            .map(|(_, node)| node.deep_copy(&mut self.storage))
+            .collect::<Vec<_>>();
+        self.end_undo_group()?;
+        let mut result = Ok(());
+        for (i, ((_, old_child), copy)) in children.iter().zip(copies.iter()).enumerate() {
+            result = self
+                .execute(TreeNavCommand::GotoNode(*old_child))
+                .and_then(|()| self.execute(TreeEdCommand::Replace(*copy)));
+            if result.is_err() {
+                for copy in &copies[i..] {
+                    copy.delete_root(&mut self.storage).bug();
+                }
+                self.revert_undo_group()?;
+                break;
+            }
+        }
+        result?;
+        self.execute(TreeNavCommand::GotoNode(list))?;
+        self.end_undo_group()
+    }
+
+    /// Paste `text` at the cursor in the visible doc. In text mode, it's inserted into the text. In
+    /// tree mode, it's parsed with the doc's parser and the resulting nodes are inserted, or if
+    /// that doesn't produce nodes that fit at the cursor, it's inserted as a single texty node
+    /// instead. Returns the number of nodes inserted. The paste is a single undo group.
+    pub fn paste_text(&mut self, text: &str) -> Result<usize, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        if doc.cursor().mode() == Mode::Text {
+            self.end_undo_group()?;
+            for ch in text.chars().filter(|ch| !ch.is_control()) {
+                if let Err(err) = self.execute(TextEdCommand::Insert(ch)) {
+                    self.revert_undo_group()?;
+                    return Err(err);
+                }
+            }
+            self.end_undo_group()?;
+            return Ok(0);
+        }
+
+        let nodes = self.parse_fragment(text, true)?;
+        let cmd = match nodes[..] {
+            [node] => TreeEdCommand::Insert(node),
+            _ => TreeEdCommand::InsertMany(nodes.clone()),
+        };
+        self.end_undo_group()?;
+        if let Err(err) = self.execute(cmd) {
+            for node in nodes {
+                node.delete_root(&mut self.storage).bug();
+            }
+            self.revert_undo_group()?;
+            return Err(err);
+        }
+        self.end_undo_group()?;
+        Ok(nodes.len())
+    }
+
+    /// Replace `target`, which must be in the visible doc, by parsing `source`, as a single edit
+    /// that can be undone. If `target` is the doc's root, all of the doc's contents are replaced.
+    /// This is for reading back the output of external programs, like formatters, so `target` is
+    /// the node that was given to the program: it fails if that node has since been deleted or
+    /// moved out of the doc, instead of replacing whatever the cursor is on by then.
+    pub fn replace_with_source(&mut self, target: Node, source: &str) -> Result<(), SynlessError> {
+        let doc_name = self
+            .doc_set
+            .visible_doc_name()
+            .ok_or(DocError::NoVisibleDoc)?
+            .to_owned();
+        let doc = self.doc_set.visible_doc().bug();
+        let s = &self.storage;
+        let root = doc.cursor().root_node(s);
+        if target == root {
+            let language_name = root.language(s).name(s).to_owned();
+            let new_root = self.parse_source(&doc_name, &language_name, source)?;
+            let doc = self.doc_set.visible_doc_mut().bug();
+            doc.replace_contents(&mut self.storage, new_root)?;
+            return Ok(());
+        }
+
+        self.goto_node(target)?;
+        let nodes = self.parse_fragment(source, false)?;
+        if nodes.len() != 1 {
+            for node in nodes {
+                node.delete_root(&mut self.storage).bug();
+            }
+            return Err(error!(Parse, "Expected a single node, but found several"));
+        }
+        let result = self.execute(TreeEdCommand::Replace(nodes[0]));
+        if result.is_err() {
+            nodes[0].delete_root(&mut self.storage).bug();
+        }
+        result
+    }
+
+    /// Parse `text` into new root nodes that can be inserted at the cursor in the visible doc
+    /// (which must be in tree mode). Tries parsing it on its own, then in each of the parser's
+    /// fragment wrappers. If none of those fit, falls back to a texty node containing `text` if
+    /// `fallback_to_text`, or else fails.
+    fn parse_fragment(
+        &mut self,
+        text: &str,
+        fallback_to_text: bool,
+    ) -> Result<Vec<Node>, SynlessError> {
+        let doc = self.doc_set.visible_doc().ok_or(DocError::NoVisibleDoc)?;
+        let doc_name = self.doc_set.visible_doc_name().bug().to_owned();
+        let s = &self.storage;
+        let cursor = doc.cursor();
+        let (sort, parent) = match (cursor.insertion_sort(s), cursor.parent_node(s)) {
+            (Some(sort), Some(parent)) => (sort, parent),
+            _ => return Err(error!(Edit, "Can't paste here")),
+        };
+        let in_list = matches!(parent.arity(s), Arity::Listy(_));
+        let language_name = parent.language(s).name(s).to_owned();
+        let wrappers = match self.parsers.get(&language_name) {
+            Some(parser) => parser.fragment_wrappers().to_vec(),
+            None => Vec::new(),
+        };
+
+        for (prefix, suffix) in std::iter::once(("", "")).chain(wrappers) {
+            let source = format!("{prefix}{text}{suffix}");
+            let root = match self.parse_source(&doc_name, &language_name, &source) {
+                Ok(root) => root,
+                Err(_) => continue,
+            };
+            let s = &mut self.storage;
+            let container = if prefix.is_empty() && suffix.is_empty() {
+                Some(root)
+            } else {
+                root.first_child(s)
+            };
+            let mut nodes = Vec::new();
+            let mut child = container.and_then(|node| node.first_child(s));
+            while let Some(node) = child {
+                nodes.push(node);
+                child = node.next_sibling(s);
+            }
+            let fits = !nodes.is_empty()
+                && (in_list || nodes.len() == 1)
+                && nodes.iter().all(|node| sort.accepts(s, node.construct(s)));
+            if fits {
+                nodes = container.bug().drain_children(s, 0..nodes.len()).bug();
+            } else {
+                nodes.clear();
+            }
+            root.delete_root(s).bug();
+            if !nodes.is_empty() {
+                return Ok(nodes);
+            }
+        }
+
+        if !fallback_to_text {
+            return Err(error!(Parse, "Doesn't parse as something that fits here"));
+        }
+        let s = &mut self.storage;
+        let texty_constructs = sort
+            .matching_constructs(s)
+            .filter(|construct| matches!(construct.arity(s), Arity::Texty))
+            .collect::<Vec<_>>();
+        let construct = texty_constructs
+            .iter()
+            .find(|construct| match construct.text_validation_regex(s) {
+                Some(regex) => regex.is_match(text),
+                None => true,
+            })
+            .or(texty_constructs.first())
+            .ok_or_else(|| error!(Edit, "Can't paste text here"))?;
+        Ok(vec![Node::with_text(s, *construct, text.to_owned()).bug()])
+    }
+
+    /// Highlight `target` as the place that a node being dragged would be moved to.
+    pub fn set_drop_target(&mut self, target: Option<Node>) {
+        if let Some(doc) = self.doc_set.visible_doc_mut() {
+            doc.set_drop_target(target);
+        }
+    }
+
+    /*************************
+     * Node Memory Reclaiming *
+     *************************/
+
+    /// Release the memory held by deleted nodes, and update every node that the engine refers
+    /// to: in its docs (including their undo histories and bookmarks), on the clipboard, and in
+    /// docs that are still loading. Returns the remapping, to update any nodes held elsewhere.
+    /// Nodes that aren't updated become invalid, as if they'd been deleted.
+    pub fn compact_nodes(&mut self) -> NodeRemapping {
+        let remapping = self.storage.compact_nodes();
+        self.doc_set.remap(&remapping);
+        for entry in &mut self.clipboard {
+            for node in entry {
+                *node = remapping.get(*node).bug();
+            }
+        }
+        for pending in self.pending_loads.values_mut() {
+            pending.container = remapping.get(pending.container).bug();
+        }
+        remapping
+    }
+
+    /// Like `compact_nodes()`, but only if most of the memory set aside for nodes is going
+    /// unused, like after a big doc was closed. Returns `None` if it didn't compact.
+    pub fn compact_nodes_if_sparse(&mut self) -> Option<NodeRemapping> {
+        let capacity = self.storage.node_capacity();
+        if capacity < MIN_COMPACTION_CAPACITY || capacity < 4 * self.storage.num_nodes() {
+            return None;
+        }
+        Some(self.compact_nodes())
+    }
+
+    /**********************
+     * Raw Storage Access *
+     **********************/
+
+    pub fn raw_storage(&self) -> &Storage {
+        &self.storage
+    }
+
+    pub fn raw_storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
 
 impl Drop for Engine {
     fn drop(&mut self) {
         // Delete all nodes that we know about.
         self.doc_set.delete_all_docs(&mut self.storage);
-        for node in self.clipboard.drain(..) {
-            node.delete_root(&mut self.storage);
+        for node in self.clipboard.drain(..).flatten() {
+            node.delete_root(&mut self.storage).bug();
         }
 
         // Check that there are no remaining nodes.
@@ -420,3 +2530,92 @@ impl Drop for Engine {
         }
     }
 }
+
+/// The lines of the visible doc, as displayed in a pane. See `Engine::visible_doc_extent()`.
+struct DocExtent {
+    /// The number of lines in the whole doc.
+    total_height: usize,
+    /// The line shown at the top of the pane.
+    top_line: usize,
+    /// The first line containing the cursor.
+    cursor_line: usize,
+}
+
+/// The rest of a doc that's being loaded lazily. See `Engine::load_doc_lazily()`.
+#[derive(Debug)]
+struct PendingLoad {
+    language_name: String,
+    /// The doc's whole source, with its holes already preprocessed.
+    source: String,
+    split: SplitSource,
+    /// The index of the first element that hasn't been parsed yet.
+    next_element: usize,
+    /// The doc's top-level list, to append elements to.
+    container: Node,
+    /// Whether the doc should be read-only once it's loaded.
+    read_only: bool,
+}
+
+/// A doc that's being parsed on another thread. See `Engine::load_doc_lazily()`.
+#[derive(Debug)]
+struct BackgroundParse {
+    language_name: String,
+    receiver: mpsc::Receiver<Result<Vec<ParsedTree>, ParseError>>,
+    /// Whether the doc should be read-only once it's loaded.
+    read_only: bool,
+}
+
+/// Where the visible doc's lines fall when printed at some width.
+#[derive(Debug)]
+struct DocLayout {
+    /// The number of lines in the whole doc.
+    total_height: usize,
+    node_lines: NodeLines,
+}
+
+/// Everything that a [`DocLayout`] depends on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LayoutKey {
+    doc_version: u64,
+    width: ppp::Width,
+    show_hidden: bool,
+}
+
+/// The most recently used layouts of the visible doc, most recent first.
+#[derive(Debug)]
+struct LayoutCache(VecDeque<(LayoutKey, Rc<DocLayout>)>);
+
+impl LayoutCache {
+    fn new() -> LayoutCache {
+        LayoutCache(VecDeque::new())
+    }
+
+    fn get(&mut self, key: &LayoutKey) -> Option<Rc<DocLayout>> {
+        let index = self.0.iter().position(|(k, _)| k == key)?;
+        let entry = self.0.remove(index).bug();
+        let layout = entry.1.clone();
+        self.0.push_front(entry);
+        Some(layout)
+    }
+
+    /// Add a layout, evicting the least recently used ones to stay within `capacity`.
+    fn insert(&mut self, key: LayoutKey, layout: Rc<DocLayout>, capacity: usize) {
+        self.0.push_front((key, layout));
+        self.truncate(capacity);
+    }
+
+    fn truncate(&mut self, capacity: usize) {
+        self.0.truncate(capacity);
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// The text of the first texty node at or under `node`, in document order.
+fn first_text(s: &Storage, node: Node) -> Option<&str> {
+    node.preorder(s)
+        .find_map(|node| node.text(s))
+        .map(|text| text.as_str())
+}
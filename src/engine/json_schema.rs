@@ -0,0 +1,364 @@
+use super::lint::Diagnostic;
+use crate::language::Storage;
+use crate::tree::Node;
+use crate::util::{error, SynlessError};
+use serde_json::{Map, Value};
+
+const RULE_NAME: &str = "json_schema";
+/// Give up on following `$ref`s that don't lead anywhere (like `{"$ref": "#"}`) after this many.
+const MAX_REF_DEPTH: usize = 32;
+
+/// A JSON Schema to validate json docs against. Supports a subset of the spec:
+///
+/// - `type`, `enum`, and `const`
+/// - `properties`, `required`, and `additionalProperties`
+/// - `items`, `minItems`, `maxItems`, and `uniqueItems`
+/// - `minLength`, `maxLength`, and `pattern`
+/// - `minimum`, `maximum`, `exclusiveMinimum`, and `exclusiveMaximum`
+/// - `allOf`, `anyOf`, `oneOf`, and `not`
+/// - `$ref`s to other parts of the same schema, like `#/definitions/name`
+///
+/// Other keywords are ignored.
+#[derive(Debug, Clone)]
+pub struct JsonSchema(Value);
+
+impl JsonSchema {
+    pub fn parse(source: &str) -> Result<JsonSchema, SynlessError> {
+        let schema = serde_json::from_str(source)
+            .map_err(|err| error!(Parse, "Invalid JSON schema ({err})"))?;
+        Ok(JsonSchema(schema))
+    }
+
+    /// Validate the json tree under `root`, returning a diagnostic for each violation, attached to
+    /// the offending node. Holes aren't checked.
+    pub fn validate(&self, s: &Storage, root: Node) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        if let Some(value) = root.first_child(s) {
+            self.check(s, &self.0, value, 0, &mut diagnostics);
+        }
+        diagnostics
+    }
+
+    fn is_valid(&self, s: &Storage, schema: &Value, node: Node, ref_depth: usize) -> bool {
+        let mut diagnostics = Vec::new();
+        self.check(s, schema, node, ref_depth, &mut diagnostics);
+        diagnostics.is_empty()
+    }
+
+    fn check(
+        &self,
+        s: &Storage,
+        schema: &Value,
+        node: Node,
+        ref_depth: usize,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let mut report = |node: Node, message: String| {
+            diagnostics.push(Diagnostic {
+                node: node.id(s),
+                rule: RULE_NAME.to_owned(),
+                message,
+            })
+        };
+
+        if node.is_hole(s) {
+            return;
+        }
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => return report(node, "Not allowed by the schema".to_owned()),
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            let target = reference
+                .strip_prefix('#')
+                .and_then(|pointer| self.0.pointer(pointer));
+            if let Some(target) = target {
+                if ref_depth < MAX_REF_DEPTH {
+                    self.check(s, target, node, ref_depth + 1, diagnostics);
+                }
+            }
+            return;
+        }
+
+        let type_name = json_type_name(s, node);
+        if let Some(expected) = schema.get("type") {
+            let expected_names = match expected {
+                Value::String(name) => vec![name.as_str()],
+                Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !expected_names.is_empty()
+                && !expected_names
+                    .iter()
+                    .any(|expected| has_type(s, node, type_name, expected))
+            {
+                let expected = expected_names.join(" or ");
+                return report(node, format!("Expected {expected}, found {type_name}"));
+            }
+        }
+
+        if let Some(Value::Array(options)) = schema.get("enum") {
+            if let Some(value) = node_to_json(s, node) {
+                if !options.contains(&value) {
+                    report(node, "Not one of the allowed values".to_owned());
+                }
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if let Some(value) = node_to_json(s, node) {
+                if &value != expected {
+                    report(node, format!("Expected {expected}"));
+                }
+            }
+        }
+
+        match type_name {
+            "string" => check_string(s, schema, node, &mut report),
+            "number" => check_number(s, schema, node, &mut report),
+            "array" => {
+                let len = node.num_children(s).unwrap_or(0);
+                if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                    if (len as u64) < min {
+                        report(node, format!("Expected at least {min} items"));
+                    }
+                }
+                if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                    if (len as u64) > max {
+                        report(node, format!("Expected at most {max} items"));
+                    }
+                }
+                if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+                    let mut seen = Vec::new();
+                    for item in (0..len).filter_map(|i| node.nth_child(s, i)) {
+                        if let Some(value) = node_to_json(s, item) {
+                            if seen.contains(&value) {
+                                report(item, "Duplicate item".to_owned());
+                            } else {
+                                seen.push(value);
+                            }
+                        }
+                    }
+                }
+                for i in 0..len {
+                    let item_schema = match schema.get("items") {
+                        Some(Value::Array(schemas)) => schemas.get(i),
+                        item_schema => item_schema,
+                    };
+                    if let (Some(item_schema), Some(item)) = (item_schema, node.nth_child(s, i)) {
+                        self.check(s, item_schema, item, 0, diagnostics);
+                    }
+                }
+            }
+            "object" => self.check_object(s, schema, node, diagnostics),
+            _ => (),
+        }
+
+        if let Some(Value::Array(schemas)) = schema.get("allOf") {
+            for sub_schema in schemas {
+                self.check(s, sub_schema, node, ref_depth, diagnostics);
+            }
+        }
+        let mut report_here = |message: &str| {
+            diagnostics.push(Diagnostic {
+                node: node.id(s),
+                rule: RULE_NAME.to_owned(),
+                message: message.to_owned(),
+            })
+        };
+        if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+            if !schemas
+                .iter()
+                .any(|sub| self.is_valid(s, sub, node, ref_depth))
+            {
+                report_here("Doesn't match any of the allowed schemas");
+            }
+        }
+        if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+            let matches = schemas
+                .iter()
+                .filter(|sub| self.is_valid(s, sub, node, ref_depth))
+                .count();
+            if matches != 1 {
+                report_here("Doesn't match exactly one of the allowed schemas");
+            }
+        }
+        if let Some(sub_schema) = schema.get("not") {
+            if self.is_valid(s, sub_schema, node, ref_depth) {
+                report_here("Matches a schema that isn't allowed");
+            }
+        }
+    }
+
+    fn check_object(
+        &self,
+        s: &Storage,
+        schema: &Map<String, Value>,
+        node: Node,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let mut keys = Vec::new();
+        for i in 0..node.num_children(s).unwrap_or(0) {
+            let pair = match node.nth_child(s, i) {
+                Some(pair) if !pair.is_hole(s) => pair,
+                _ => continue,
+            };
+            let (key, value) = match (pair.nth_child(s, 0), pair.nth_child(s, 1)) {
+                (Some(key), Some(value)) => (key, value),
+                _ => continue,
+            };
+            let key = match key.text(s) {
+                Some(text) => text.as_str().to_owned(),
+                None => continue,
+            };
+            let property_schema = properties.and_then(|props| props.get(&key));
+            match (property_schema, schema.get("additionalProperties")) {
+                (Some(property_schema), _) | (None, Some(property_schema)) => {
+                    if property_schema == &Value::Bool(false) {
+                        diagnostics.push(Diagnostic {
+                            node: pair.id(s),
+                            rule: RULE_NAME.to_owned(),
+                            message: format!("Property '{key}' is not allowed"),
+                        });
+                    } else {
+                        self.check(s, property_schema, value, 0, diagnostics);
+                    }
+                }
+                (None, None) => (),
+            }
+            keys.push(key);
+        }
+
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !keys.iter().any(|key| key == name) {
+                    diagnostics.push(Diagnostic {
+                        node: node.id(s),
+                        rule: RULE_NAME.to_owned(),
+                        message: format!("Missing required property '{name}'"),
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn check_string(
+    s: &Storage,
+    schema: &Map<String, Value>,
+    node: Node,
+    report: &mut impl FnMut(Node, String),
+) {
+    let text = node.text(s).map(|text| text.as_str()).unwrap_or("");
+    let len = text.chars().count() as u64;
+    if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+        if len < min {
+            report(node, format!("Expected at least {min} characters"));
+        }
+    }
+    if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+        if len > max {
+            report(node, format!("Expected at most {max} characters"));
+        }
+    }
+    if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+        // An invalid pattern is the schema's problem, not the doc's, so it's ignored.
+        if let Ok(regex) = regex::Regex::new(pattern) {
+            if !regex.is_match(text) {
+                report(node, format!("Doesn't match the pattern '{pattern}'"));
+            }
+        }
+    }
+}
+
+fn check_number(
+    s: &Storage,
+    schema: &Map<String, Value>,
+    node: Node,
+    report: &mut impl FnMut(Node, String),
+) {
+    let number = match node
+        .text(s)
+        .and_then(|text| text.as_str().parse::<f64>().ok())
+    {
+        Some(number) => number,
+        // Invalid numbers are reported by the built-in `invalid_text` rule.
+        None => return,
+    };
+    let bound = |name: &str| schema.get(name).and_then(Value::as_f64);
+    if let Some(min) = bound("minimum") {
+        if number < min {
+            report(node, format!("Expected at least {min}"));
+        }
+    }
+    if let Some(max) = bound("maximum") {
+        if number > max {
+            report(node, format!("Expected at most {max}"));
+        }
+    }
+    if let Some(min) = bound("exclusiveMinimum") {
+        if number <= min {
+            report(node, format!("Expected more than {min}"));
+        }
+    }
+    if let Some(max) = bound("exclusiveMaximum") {
+        if number >= max {
+            report(node, format!("Expected less than {max}"));
+        }
+    }
+}
+
+/// The JSON Schema type of a json node. Integers are reported as "number"; see `has_type()`.
+fn json_type_name(s: &Storage, node: Node) -> &'static str {
+    match node.construct(s).name(s) {
+        "Null" => "null",
+        "True" | "False" => "boolean",
+        "String" => "string",
+        "Number" => "number",
+        "Array" => "array",
+        "Object" => "object",
+        _ => "unknown",
+    }
+}
+
+fn has_type(s: &Storage, node: Node, type_name: &str, expected: &str) -> bool {
+    if expected == "integer" && type_name == "number" {
+        node.text(s)
+            .and_then(|text| text.as_str().parse::<f64>().ok())
+            .map(|number| number.fract() == 0.0)
+            .unwrap_or(false)
+    } else {
+        expected == type_name
+    }
+}
+
+/// Convert a json node to a JSON value, for comparing with `enum` and `const`. Returns `None` if
+/// it contains any holes or invalid numbers.
+fn node_to_json(s: &Storage, node: Node) -> Option<Value> {
+    let text = || node.text(s).map(|text| text.as_str().to_owned());
+    let children = || (0..node.num_children(s).unwrap_or(0)).filter_map(|i| node.nth_child(s, i));
+    Some(match node.construct(s).name(s) {
+        "Null" => Value::Null,
+        "True" => Value::Bool(true),
+        "False" => Value::Bool(false),
+        "String" => Value::String(text()?),
+        "Number" => Value::Number(text()?.parse().ok()?),
+        "Array" => Value::Array(
+            children()
+                .map(|child| node_to_json(s, child))
+                .collect::<Option<_>>()?,
+        ),
+        "Object" => Value::Object(
+            children()
+                .map(|pair| {
+                    let key = pair.nth_child(s, 0)?.text(s)?.as_str().to_owned();
+                    Some((key, node_to_json(s, pair.nth_child(s, 1)?)?))
+                })
+                .collect::<Option<_>>()?,
+        ),
+        _ => return None,
+    })
+}
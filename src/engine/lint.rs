@@ -0,0 +1,41 @@
+use crate::language::Storage;
+use crate::tree::{Node, NodeId};
+
+/// A problem found in a doc by a lint rule, attached to the node that it's about.
+///
+/// Lint rules are either built in (see [`builtin_diagnostics`]) or written in rhai, in which case
+/// they're run by `run_lints()` in the base module.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub node: NodeId,
+    /// The name of the rule that found the problem.
+    pub rule: String,
+    pub message: String,
+}
+
+/// Run the built-in lint rules on every node under `root`, in document order. They are:
+///
+/// - `hole`: a hole that has yet to be filled in.
+/// - `invalid_text`: text that doesn't match its construct's regex.
+pub fn builtin_diagnostics(s: &Storage, root: Node) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for node in root.preorder(s).skip(1) {
+        let (rule, message) = if node.is_hole(s) {
+            ("hole", "Unfilled hole".to_owned())
+        } else if node.is_invalid_text(s) {
+            let construct_name = node.construct(s).name(s);
+            (
+                "invalid_text",
+                format!("Invalid text for '{construct_name}'"),
+            )
+        } else {
+            continue;
+        };
+        diagnostics.push(Diagnostic {
+            node: node.id(s),
+            rule: rule.to_owned(),
+            message,
+        });
+    }
+    diagnostics
+}
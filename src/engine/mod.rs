@@ -2,32 +2,156 @@ mod command;
 mod doc;
 mod doc_set;
 mod engine;
+mod json_schema;
+mod lint;
 mod search;
+mod spell_check;
+mod undo_cache;
 
+use crate::config::Config;
+use crate::style::Shading;
 use partial_pretty_printer as ppp;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::default::Default;
 
 pub use command::{
-    BookmarkCommand, ClipboardCommand, SearchCommand, TextEdCommand, TextNavCommand, TreeEdCommand,
-    TreeNavCommand,
+    BookmarkCommand, ClipboardCommand, CursorsCommand, SearchCommand, SelectionCommand,
+    TextEdCommand, TextNavCommand, TreeEdCommand, TreeNavCommand,
 };
+pub use doc::{HistoryEntry, HistoryTreeNode};
 pub use doc_set::{DocDisplayLabel, DocName};
-pub use engine::Engine;
+pub use engine::{Engine, SortChildrenBy};
+pub use json_schema::JsonSchema;
+pub use lint::Diagnostic;
 pub use search::Search;
+pub use spell_check::{Dictionary, Misspelling};
+pub use undo_cache::UndoCache;
 
 #[derive(Debug, Clone)]
 pub struct Settings {
     max_source_width: ppp::Width,
     max_display_width: ppp::Width,
+    /// Overrides for docs in particular languages, by language name.
+    language_settings: HashMap<String, LanguageSettings>,
     focus_height: f32,
+    /// If set, display the visible doc at this width instead of `max_display_width`, to preview
+    /// how it will look when printed.
+    preview_width: Option<ppp::Width>,
+    /// Whether to display nodes whose construct is hidden.
+    show_hidden: bool,
+    /// Whether to show a scrollbar next to the visible doc. Off by default, because it requires
+    /// printing the whole doc on every frame.
+    show_scrollbar: bool,
+    /// Whether to show line numbers in a gutter to the left of the visible doc. Like the
+    /// scrollbar, this requires printing the whole doc on every frame.
+    line_numbers: LineNumbers,
+    /// Which nodes of the visible doc to shade the background of. The colors come from the
+    /// `ColorTheme`.
+    shading: Shading,
+    /// How many widths to remember the visible doc's layout at, so that switching between a few
+    /// pane sizes doesn't require printing the whole doc each time. 0 turns off the cache.
+    layout_cache_size: usize,
+    /// Files at least this many bytes long are loaded lazily: only enough of them to fill the
+    /// screen is parsed when they're opened, and the rest between keypresses. 0 turns it off.
+    lazy_load_size: usize,
 }
 
+/// Settings for the docs in one language, overriding the general ones. `None` means to use the
+/// general setting.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LanguageSettings {
+    pub max_source_width: Option<ppp::Width>,
+    pub max_display_width: Option<ppp::Width>,
+    /// The name of the notation set to display docs in, instead of the language's default. It
+    /// takes effect once the language has a notation set by that name.
+    pub display_notation: Option<String>,
+    /// The name of the notation set to print docs in when saving them. Like `display_notation`,
+    /// it takes effect once the language has a notation set by that name.
+    pub source_notation: Option<String>,
+}
+
+/// How to number the lines of the visible doc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineNumbers {
+    #[default]
+    Off,
+    /// Number lines from 1 at the top of the doc.
+    Absolute,
+    /// Number lines by their distance from the cursor's line. The cursor's own line shows its
+    /// absolute line number.
+    Relative,
+}
+
+/// The width of the line number gutter, including a column of space between it and the doc.
+const LINE_NUMBER_GUTTER_WIDTH: ppp::Width = 6;
+
 impl Default for Settings {
     fn default() -> Settings {
         Settings {
             max_source_width: 100,
             max_display_width: 120,
+            language_settings: HashMap::new(),
             focus_height: 0.25,
+            preview_width: None,
+            show_hidden: false,
+            show_scrollbar: false,
+            line_numbers: LineNumbers::Off,
+            shading: Shading::default(),
+            layout_cache_size: 4,
+            lazy_load_size: 1 << 20,
+        }
+    }
+}
+
+impl Settings {
+    /// Overwrite the settings that `config` gives. The per-language overrides are replaced
+    /// wholesale.
+    pub fn apply_config(&mut self, config: &Config) {
+        if let Some(width) = config.max_source_width {
+            self.max_source_width = width;
+        }
+        if let Some(width) = config.max_display_width {
+            self.max_display_width = width;
+        }
+        if let Some(focus_height) = config.focus_height {
+            self.focus_height = focus_height;
         }
+        if let Some(show_scrollbar) = config.show_scrollbar {
+            self.show_scrollbar = show_scrollbar;
+        }
+        self.language_settings = config.languages.clone();
+    }
+
+    fn language_settings(&self, language_name: &str) -> Option<&LanguageSettings> {
+        self.language_settings.get(language_name)
+    }
+
+    /// The width to print docs in the language `language_name` at, in their source notation.
+    fn source_width(&self, language_name: &str) -> ppp::Width {
+        self.language_settings(language_name)
+            .and_then(|lang| lang.max_source_width)
+            .unwrap_or(self.max_source_width)
+    }
+
+    /// The number of columns taken up by line numbers, or 0 if they're off.
+    fn line_number_gutter_width(&self) -> ppp::Width {
+        match self.line_numbers {
+            LineNumbers::Off => 0,
+            LineNumbers::Absolute | LineNumbers::Relative => LINE_NUMBER_GUTTER_WIDTH,
+        }
+    }
+
+    /// The maximum width to display the visible doc at, if it's in the language `language_name`.
+    /// The line number gutter (if any) comes out of `max_display_width`, so that turning on line
+    /// numbers doesn't make the doc wider overall.
+    fn display_width(&self, language_name: &str) -> ppp::Width {
+        let max_display_width = self
+            .language_settings(language_name)
+            .and_then(|lang| lang.max_display_width)
+            .unwrap_or(self.max_display_width);
+        self.preview_width
+            .unwrap_or(max_display_width.saturating_sub(self.line_number_gutter_width()))
     }
 }
@@ -0,0 +1,359 @@
+mod query;
+
+use crate::language::{Construct, Language, Storage};
+use crate::tree::{Node, NodeRemapping, Visit};
+use crate::util::{error, SynlessBug, SynlessError};
+use query::Query;
+use regex::{self, Regex};
+use std::collections::HashMap;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SearchError {
+    #[error("{}", .0)]
+    InvalidRegex(regex::Error),
+    #[error("Invalid query: {}", .0)]
+    InvalidQuery(String),
+}
+
+impl From<SearchError> for SynlessError {
+    fn from(error: SearchError) -> SynlessError {
+        error!(Edit, "{}", error)
+    }
+}
+
+#[derive(Debug)]
+pub struct Search {
+    pattern: SearchPattern,
+    pub highlight: bool,
+}
+
+#[derive(Debug)]
+enum SearchPattern {
+    /// Matches nodes of the given construct.
+    Construct(Construct),
+    /// Matches nodes that are identical to the given node, including children.
+    Node(Node),
+    /// Like `Node`, except that holes in the pattern match anything, and a texty node whose text
+    /// is `$name` matches anything and captures it as `name`. Every capture with the same name
+    /// must match identical nodes.
+    Pattern(Node),
+    /// Matches texty nodes whose text contains the given substring.
+    Substring(String),
+    /// Matches texty nodes whose text matches the given regex.
+    Regex(Regex),
+    /// Matches nodes that satisfy the given query. See the `query` module for the syntax.
+    Query(Query),
+}
+
+impl Search {
+    pub fn new_construct(construct: Construct) -> Search {
+        Search {
+            pattern: SearchPattern::Construct(construct),
+            highlight: true,
+        }
+    }
+
+    pub fn new_node(node: Node) -> Search {
+        Search {
+            pattern: SearchPattern::Node(node),
+            highlight: true,
+        }
+    }
+
+    pub fn new_pattern(node: Node) -> Search {
+        Search {
+            pattern: SearchPattern::Pattern(node),
+            highlight: true,
+        }
+    }
+
+    pub fn new_substring(substring: String) -> Search {
+        Search {
+            pattern: SearchPattern::Substring(substring),
+            highlight: true,
+        }
+    }
+
+    pub fn new_regex(regex_pattern: &str) -> Result<Search, SearchError> {
+        let regex = Regex::new(regex_pattern).map_err(SearchError::InvalidRegex)?;
+        Ok(Search {
+            pattern: SearchPattern::Regex(regex),
+            highlight: true,
+        })
+    }
+
+    /// Parse a query in the syntax described in the `query` module, looking up construct names
+    /// in `language`.
+    pub fn new_query(s: &Storage, language: Language, query: &str) -> Result<Search, SearchError> {
+        Ok(Search {
+            pattern: SearchPattern::Query(Query::parse(s, language, query)?),
+            highlight: true,
+        })
+    }
+
+    pub fn matches(&self, s: &Storage, node: Node) -> bool {
+        match &self.pattern {
+            SearchPattern::Construct(construct) => node.construct(s) == *construct,
+            SearchPattern::Node(expected_node) => expected_node.equals(s, node),
+            SearchPattern::Pattern(pattern) => {
+                match_pattern(s, *pattern, node, &mut HashMap::new())
+            }
+            SearchPattern::Substring(substring) => node
+                .text(s)
+                .map(|text| text.as_str().contains(substring))
+                .unwrap_or(false),
+            SearchPattern::Regex(regex) => node
+                .text(s)
+                .map(|text| regex.is_match(text.as_str()))
+                .unwrap_or(false),
+            SearchPattern::Query(query) => query.matches(s, node),
+        }
+    }
+
+    /// If `node` matches this search, return what each named capture in the pattern matched.
+    pub fn captures(&self, s: &Storage, node: Node) -> Option<HashMap<String, Node>> {
+        let mut captures = HashMap::new();
+        let is_match = match &self.pattern {
+            SearchPattern::Pattern(pattern) => match_pattern(s, *pattern, node, &mut captures),
+            _ => self.matches(s, node),
+        };
+        if is_match {
+            Some(captures)
+        } else {
+            None
+        }
+    }
+
+    pub fn delete(self, s: &mut Storage) {
+        use SearchPattern as P;
+
+        match self.pattern {
+            P::Node(node) | P::Pattern(node) => node.delete_root(s).bug(),
+            P::Construct(_) | P::Substring(_) | P::Regex(_) | P::Query(_) => (),
+        }
+    }
+
+    /// Update the search's pattern node, if any, after the nodes were compacted. See
+    /// `Storage::compact_nodes()`.
+    pub fn remap(&mut self, remapping: &NodeRemapping) {
+        use SearchPattern as P;
+
+        match &mut self.pattern {
+            P::Node(node) | P::Pattern(node) => *node = remapping.get(*node).bug(),
+            P::Construct(_) | P::Substring(_) | P::Regex(_) | P::Query(_) => (),
+        }
+    }
+}
+
+/// Make a copy of `template`, with each `$name` placeholder replaced by a copy of the node captured
+/// as `name`. Placeholders that weren't captured, or whose capture can't go in their place, are
+/// left as they are.
+pub fn instantiate_template(
+    s: &mut Storage,
+    template: Node,
+    captures: &HashMap<String, Node>,
+) -> Node {
+    if let Some(captured) = capture_name(s, template).and_then(|name| captures.get(name)) {
+        return captured.deep_copy(s);
+    }
+
+    let copy = template.deep_copy(s);
+    let mut placeholders = Vec::new();
+    copy.visit_mut(s, |s, node| {
+        match capture_name(s, node).and_then(|name| captures.get(name)) {
+            Some(captured) => {
+                placeholders.push((node, *captured));
+                Visit::SkipChildren
+            }
+            None => Visit::Continue,
+        }
+    });
+    for (placeholder, captured) in placeholders {
+        let replacement = captured.deep_copy(s);
+        if placeholder.swap(s, replacement).is_ok() {
+            placeholder.delete_root(s).bug();
+        } else {
+            replacement.delete_root(s).bug();
+        }
+    }
+    copy
+}
+
+/// If `node` is a capture placeholder of the form `$name`, return `name`.
+fn capture_name(s: &Storage, node: Node) -> Option<&str> {
+    let name = node.text(s)?.as_str().strip_prefix('$')?;
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+fn match_pattern(
+    s: &Storage,
+    pattern: Node,
+    node: Node,
+    captures: &mut HashMap<String, Node>,
+) -> bool {
+    if pattern.is_hole(s) {
+        return true;
+    }
+    if let Some(name) = capture_name(s, pattern) {
+        return match captures.get(name) {
+            Some(captured) => captured.equals(s, node),
+            None => {
+                captures.insert(name.to_owned(), node);
+                true
+            }
+        };
+    }
+    if pattern.construct(s) != node.construct(s)
+        || pattern.text(s).map(|text| text.as_str()) != node.text(s).map(|text| text.as_str())
+        || pattern.num_children(s) != node.num_children(s)
+    {
+        return false;
+    }
+    let mut pattern_child = pattern.first_child(s);
+    let mut node_child = node.first_child(s);
+    while let (Some(p), Some(n)) = (pattern_child, node_child) {
+        if !match_pattern(s, p, n, captures) {
+            return false;
+        }
+        pattern_child = p.next_sibling(s);
+        node_child = n.next_sibling(s);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{JsonParser, Parse};
+    use crate::testing::{json_storage, JSON_LANGUAGE_NAME};
+
+    /// Parse a json value, returning it without its `Root` node.
+    fn parse_value(s: &mut Storage, source: &str) -> Node {
+        let root = JsonParser.parse(s, "<testing>", source).unwrap();
+        let value = root.first_child(s).unwrap();
+        let json_lang = s.language(JSON_LANGUAGE_NAME).unwrap();
+        let hole = Node::new_hole(s, json_lang);
+        value.swap(s, hole).unwrap();
+        root.delete_root(s).unwrap();
+        value
+    }
+
+    fn text(s: &Storage, node: Node) -> &str {
+        node.text(s).unwrap().as_str()
+    }
+
+    #[test]
+    fn test_match_pattern() {
+        let mut s = json_storage();
+        let pattern = parse_value(&mut s, r#"{"k": "$v"}"#);
+        let search = Search::new_pattern(pattern);
+
+        let one = parse_value(&mut s, r#"{"k": 1}"#);
+        let captures = search.captures(&s, one).unwrap();
+        assert_eq!(captures.len(), 1);
+        assert_eq!(text(&s, captures["v"]), "1");
+        assert!(search.matches(&s, one));
+
+        let array = parse_value(&mut s, r#"{"k": [true]}"#);
+        let captures = search.captures(&s, array).unwrap();
+        assert_eq!(
+            captures["v"],
+            array.first_child(&s).unwrap().last_child(&s).unwrap()
+        );
+
+        // The construct, the text of texty nodes, and the number of children must all match.
+        for source in [r#"[{"k": 1}]"#, r#"{"j": 1}"#, r#"{"k": 1, "j": 2}"#, "{}"] {
+            let node = parse_value(&mut s, source);
+            assert!(search.captures(&s, node).is_none());
+            assert!(!search.matches(&s, node));
+        }
+        search.delete(&mut s);
+    }
+
+    #[test]
+    fn test_repeated_captures() {
+        let mut s = json_storage();
+        let search = Search::new_pattern(parse_value(&mut s, r#"["$x", "$y", "$x"]"#));
+
+        let node = parse_value(&mut s, r#"[[1, {}], 2, [1, {}]]"#);
+        let captures = search.captures(&s, node).unwrap();
+        assert_eq!(captures.len(), 2);
+        assert_eq!(captures["x"], node.first_child(&s).unwrap());
+        assert_eq!(text(&s, captures["y"]), "2");
+
+        // Every capture with the same name must match identical nodes.
+        for source in ["[1, 2, 3]", "[[1, {}], 2, [1, []]]", "[1, 1]"] {
+            let node = parse_value(&mut s, source);
+            assert!(search.captures(&s, node).is_none());
+        }
+        // `$` on its own isn't a capture.
+        let search = Search::new_pattern(parse_value(&mut s, r#"["$", "$"]"#));
+        let [same, numbers] = [r#"["$", "$"]"#, "[1, 1]"].map(|source| parse_value(&mut s, source));
+        assert!(search.matches(&s, same));
+        assert!(!search.matches(&s, numbers));
+    }
+
+    #[test]
+    fn test_pattern_holes() {
+        let mut s = json_storage();
+        let pattern = parse_value(&mut s, r#"[null, "$x"]"#);
+        let json_lang = s.language(JSON_LANGUAGE_NAME).unwrap();
+        let hole = Node::new_hole(&mut s, json_lang);
+        let null = pattern.first_child(&s).unwrap();
+        null.swap(&mut s, hole).unwrap();
+        null.delete_root(&mut s).unwrap();
+        let search = Search::new_pattern(pattern);
+
+        // Holes match anything, and don't capture it.
+        let node = parse_value(&mut s, r#"[{"a": [1]}, "y"]"#);
+        let captures = search.captures(&s, node).unwrap();
+        assert_eq!(captures.len(), 1);
+        assert_eq!(text(&s, captures["x"]), "y");
+        let [pair, single] = ["[true, false]", "[true]"].map(|source| parse_value(&mut s, source));
+        assert!(search.matches(&s, pair));
+        assert!(!search.matches(&s, single));
+    }
+
+    #[test]
+    fn test_instantiate_template() {
+        let mut s = json_storage();
+        let search = Search::new_pattern(parse_value(&mut s, r#"{"k": "$v"}"#));
+        let node = parse_value(&mut s, r#"{"k": [1, 2]}"#);
+        let captures = search.captures(&s, node).unwrap();
+        let captured = captures["v"];
+
+        // Each placeholder gets its own copy of the captured node, and uncaptured placeholders
+        // are left as they are.
+        let template = parse_value(&mut s, r#"["$v", ["$v"], "$missing"]"#);
+        let result = instantiate_template(&mut s, template, &captures);
+        assert_ne!(result, template);
+        let [first, second, third] = [0, 1, 2].map(|i| result.nth_child(&s, i).unwrap());
+        assert!(first.equals(&s, captured));
+        assert_ne!(first, captured);
+        assert!(second.first_child(&s).unwrap().equals(&s, captured));
+        assert_eq!(text(&s, third), "$missing");
+        assert_eq!(captured.parent(&s), node.first_child(&s));
+
+        // The template is left untouched.
+        let expected = parse_value(&mut s, r#"["$v", ["$v"], "$missing"]"#);
+        assert!(template.equals(&s, expected));
+
+        // A placeholder whose capture can't go in its place is left as it is.
+        let template = parse_value(&mut s, r#"{"$v": "$v"}"#);
+        let result = instantiate_template(&mut s, template, &captures);
+        let pair = result.first_child(&s).unwrap();
+        assert_eq!(text(&s, pair.first_child(&s).unwrap()), "$v");
+        assert!(pair.last_child(&s).unwrap().equals(&s, captured));
+
+        // A template that is just a placeholder is replaced entirely.
+        let template = parse_value(&mut s, r#""$v""#);
+        let result = instantiate_template(&mut s, template, &captures);
+        assert!(result.equals(&s, captured));
+        assert_ne!(result, captured);
+        assert!(result.parent(&s).is_none());
+    }
+}
@@ -0,0 +1,556 @@
+//! A small query language for picking out nodes, used by [`Search::new_query`]. A query is a
+//! condition on a single node:
+//!
+//! - `*` matches any node, `hole` matches holes, and `Name` matches nodes of the construct `Name`.
+//! - `text == "str"` and `text != "str"` compare the text of a texty node, and `contains("str")`,
+//!   `startswith("str")`, `endswith("str")`, and `test("regex")` check part of it. These never
+//!   match nodes that aren't texty.
+//! - `child(q)`, `descendant(q)`, `parent(q)`, and `ancestor(q)` match nodes with a relative that
+//!   matches the query `q`.
+//! - `a > b` matches nodes that match `b` and whose parent matches `a`. Likewise `a >> b`, for an
+//!   ancestor instead of the parent.
+//! - `not`, `and`, `or`, and parentheses combine queries, binding in that order (`>` and `>>` bind
+//!   tighter than all of them).
+//!
+//! For example, in json, `Array >> String and startswith("http")` matches strings that start
+//! with "http" anywhere inside of an array, and `Object and not child(*)` matches empty objects.
+//!
+//! [`Search::new_query`]: super::Search::new_query
+
+use super::SearchError;
+use crate::language::{Construct, Language, Storage};
+use crate::tree::Node;
+use regex::Regex;
+use std::fmt;
+
+/// How deeply parentheses, function arguments, and `not`s can nest, to keep the recursive descent
+/// parser (and matching) from overflowing the stack.
+const MAX_NESTING_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub struct Query(Expr);
+
+#[derive(Debug)]
+enum Expr {
+    Any,
+    Hole,
+    Construct(Construct),
+    TextEq(String),
+    TextNe(String),
+    Contains(String),
+    StartsWith(String),
+    EndsWith(String),
+    Regex(Regex),
+    Child(Box<Expr>),
+    Descendant(Box<Expr>),
+    Parent(Box<Expr>),
+    Ancestor(Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Query {
+    /// Parse a query. Construct names in it are looked up in `language`.
+    pub fn parse(s: &Storage, language: Language, source: &str) -> Result<Query, SearchError> {
+        let mut parser = Parser {
+            s,
+            language,
+            tokens: tokenize(source)?,
+            index: 0,
+            depth: 0,
+            end_col: source.chars().count() + 1,
+        };
+        let expr = parser.parse_or()?;
+        if let Some((token, col)) = parser.peek() {
+            return Err(invalid(col, format!("Unexpected {token}")));
+        }
+        Ok(Query(expr))
+    }
+
+    pub fn matches(&self, s: &Storage, node: Node) -> bool {
+        self.0.matches(s, node)
+    }
+}
+
+impl Expr {
+    fn matches(&self, s: &Storage, node: Node) -> bool {
+        let text_matches = |pred: &dyn Fn(&str) -> bool| match node.text(s) {
+            Some(text) => pred(text.as_str()),
+            None => false,
+        };
+        match self {
+            Expr::Any => true,
+            Expr::Hole => node.is_hole(s),
+            Expr::Construct(construct) => node.construct(s) == *construct,
+            Expr::TextEq(string) => text_matches(&|text| text == string.as_str()),
+            Expr::TextNe(string) => text_matches(&|text| text != string.as_str()),
+            Expr::Contains(string) => text_matches(&|text| text.contains(string.as_str())),
+            Expr::StartsWith(string) => text_matches(&|text| text.starts_with(string.as_str())),
+            Expr::EndsWith(string) => text_matches(&|text| text.ends_with(string.as_str())),
+            Expr::Regex(regex) => text_matches(&|text| regex.is_match(text)),
+            Expr::Child(expr) => children(s, node).any(|child| expr.matches(s, child)),
+            Expr::Descendant(expr) => node
+                .preorder(s)
+                .skip(1)
+                .any(|descendant| expr.matches(s, descendant)),
+            Expr::Parent(expr) => match node.parent(s) {
+                Some(parent) => expr.matches(s, parent),
+                None => false,
+            },
+            Expr::Ancestor(expr) => {
+                let mut ancestor = node.parent(s);
+                while let Some(node) = ancestor {
+                    if expr.matches(s, node) {
+                        return true;
+                    }
+                    ancestor = node.parent(s);
+                }
+                false
+            }
+            Expr::Not(expr) => !expr.matches(s, node),
+            Expr::And(expr_1, expr_2) => expr_1.matches(s, node) && expr_2.matches(s, node),
+            Expr::Or(expr_1, expr_2) => expr_1.matches(s, node) || expr_2.matches(s, node),
+        }
+    }
+}
+
+fn children(s: &Storage, node: Node) -> impl Iterator<Item = Node> + '_ {
+    std::iter::successors(node.first_child(s), |child| child.next_sibling(s))
+}
+
+fn invalid(col: usize, message: String) -> SearchError {
+    SearchError::InvalidQuery(format!("{message} at column {col}"))
+}
+
+/**********
+ * Tokens *
+ **********/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Star,
+    Gt,
+    GtGt,
+    EqEq,
+    NotEq,
+    OpenParen,
+    CloseParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{name}'"),
+            Token::Str(string) => write!(f, "string {string:?}"),
+            Token::Star => write!(f, "'*'"),
+            Token::Gt => write!(f, "'>'"),
+            Token::GtGt => write!(f, "'>>'"),
+            Token::EqEq => write!(f, "'=='"),
+            Token::NotEq => write!(f, "'!='"),
+            Token::OpenParen => write!(f, "'('"),
+            Token::CloseParen => write!(f, "')'"),
+        }
+    }
+}
+
+/// Split `source` into tokens, each paired with the (1-indexed) column it starts at.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, SearchError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().zip(1..).peekable();
+    while let Some((ch, col)) = chars.next() {
+        let token = match ch {
+            _ if ch.is_whitespace() => continue,
+            '*' => Token::Star,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '>' => {
+                if chars.next_if(|(ch, _)| *ch == '>').is_some() {
+                    Token::GtGt
+                } else {
+                    Token::Gt
+                }
+            }
+            '=' | '!' => {
+                if chars.next_if(|(ch, _)| *ch == '=').is_none() {
+                    return Err(invalid(col, format!("Expected '=' after '{ch}'")));
+                }
+                if ch == '=' {
+                    Token::EqEq
+                } else {
+                    Token::NotEq
+                }
+            }
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(invalid(col, "Unterminated string".to_owned())),
+                        Some(('"', _)) => break,
+                        Some(('\\', escape_col)) => match chars.next() {
+                            Some(('n', _)) => string.push('\n'),
+                            Some(('t', _)) => string.push('\t'),
+                            Some((ch @ ('"' | '\\'), _)) => string.push(ch),
+                            _ => return Err(invalid(escape_col, "Invalid escape".to_owned())),
+                        },
+                        Some((ch, _)) => string.push(ch),
+                    }
+                }
+                Token::Str(string)
+            }
+            _ if ch.is_alphanumeric() || ch == '_' => {
+                let mut name = ch.to_string();
+                while let Some((ch, _)) =
+                    chars.next_if(|(ch, _)| ch.is_alphanumeric() || *ch == '_')
+                {
+                    name.push(ch);
+                }
+                Token::Ident(name)
+            }
+            _ => return Err(invalid(col, format!("Unexpected character '{ch}'"))),
+        };
+        tokens.push((token, col));
+    }
+    Ok(tokens)
+}
+
+/**********
+ * Parser *
+ **********/
+
+struct Parser<'a> {
+    s: &'a Storage,
+    language: Language,
+    tokens: Vec<(Token, usize)>,
+    index: usize,
+    /// How many parentheses, function arguments, and `not`s we're currently inside of.
+    depth: usize,
+    /// The column just past the end of the source, for errors about running out of tokens.
+    end_col: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<(&Token, usize)> {
+        self.tokens
+            .get(self.index)
+            .map(|(token, col)| (token, *col))
+    }
+
+    fn peek_ident(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some((Token::Ident(name), _)) if name == keyword)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<(Token, usize), SearchError> {
+        match self.tokens.get(self.index) {
+            Some(token) => {
+                self.index += 1;
+                Ok(token.clone())
+            }
+            None => Err(invalid(self.end_col, format!("Expected {expected}"))),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), SearchError> {
+        let (token, col) = self.next(&expected.to_string())?;
+        if token == expected {
+            Ok(())
+        } else {
+            Err(invalid(col, format!("Expected {expected}, found {token}")))
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<String, SearchError> {
+        match self.next("a string")? {
+            (Token::Str(string), _) => Ok(string),
+            (token, col) => Err(invalid(col, format!("Expected a string, found {token}"))),
+        }
+    }
+
+    /// Run `parse` one level of nesting deeper, failing if that's too deep.
+    fn parse_nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Expr, SearchError>,
+    ) -> Result<Expr, SearchError> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            let col = self.peek().map(|(_, col)| col).unwrap_or(self.end_col);
+            return Err(invalid(col, "Query is nested too deeply".to_owned()));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    /// Parse `(query)`, as the argument to a function.
+    fn parse_arg(&mut self) -> Result<Expr, SearchError> {
+        self.expect(Token::OpenParen)?;
+        let expr = self.parse_nested(Self::parse_or)?;
+        self.expect(Token::CloseParen)?;
+        Ok(expr)
+    }
+
+    /// Parse `("string")`, as the argument to a function.
+    fn parse_string_arg(&mut self) -> Result<String, SearchError> {
+        self.expect(Token::OpenParen)?;
+        let string = self.expect_string()?;
+        self.expect(Token::CloseParen)?;
+        Ok(string)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, SearchError> {
+        let mut expr = self.parse_and()?;
+        while self.peek_ident("or") {
+            self.index += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, SearchError> {
+        let mut expr = self.parse_not()?;
+        while self.peek_ident("and") {
+            self.index += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_not()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr, SearchError> {
+        if self.peek_ident("not") {
+            self.index += 1;
+            Ok(Expr::Not(Box::new(self.parse_nested(Self::parse_not)?)))
+        } else {
+            self.parse_path()
+        }
+    }
+
+    fn parse_path(&mut self) -> Result<Expr, SearchError> {
+        let mut expr = self.parse_atom()?;
+        loop {
+            let relation: fn(Box<Expr>) -> Expr = match self.peek() {
+                Some((Token::Gt, _)) => Expr::Parent,
+                Some((Token::GtGt, _)) => Expr::Ancestor,
+                _ => return Ok(expr),
+            };
+            self.index += 1;
+            let inner = self.parse_atom()?;
+            expr = Expr::And(Box::new(inner), Box::new(relation(Box::new(expr))));
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, SearchError> {
+        let (token, col) = self.next("a query")?;
+        let name = match token {
+            Token::Star => return Ok(Expr::Any),
+            Token::OpenParen => {
+                let expr = self.parse_nested(Self::parse_or)?;
+                self.expect(Token::CloseParen)?;
+                return Ok(expr);
+            }
+            Token::Ident(name) => name,
+            token => return Err(invalid(col, format!("Expected a query, found {token}"))),
+        };
+        Ok(match name.as_str() {
+            "hole" => Expr::Hole,
+            "text" => {
+                let text_expr: fn(String) -> Expr = match self.next("'==' or '!='")? {
+                    (Token::EqEq, _) => Expr::TextEq,
+                    (Token::NotEq, _) => Expr::TextNe,
+                    (op, op_col) => {
+                        let message = format!("Expected '==' or '!=', found {op}");
+                        return Err(invalid(op_col, message));
+                    }
+                };
+                text_expr(self.expect_string()?)
+            }
+            "contains" => Expr::Contains(self.parse_string_arg()?),
+            "startswith" => Expr::StartsWith(self.parse_string_arg()?),
+            "endswith" => Expr::EndsWith(self.parse_string_arg()?),
+            "test" => {
+                let regex =
+                    Regex::new(&self.parse_string_arg()?).map_err(SearchError::InvalidRegex)?;
+                Expr::Regex(regex)
+            }
+            "child" => Expr::Child(Box::new(self.parse_arg()?)),
+            "descendant" => Expr::Descendant(Box::new(self.parse_arg()?)),
+            "parent" => Expr::Parent(Box::new(self.parse_arg()?)),
+            "ancestor" => Expr::Ancestor(Box::new(self.parse_arg()?)),
+            "and" | "or" | "not" => {
+                return Err(invalid(col, format!("Expected a query, found '{name}'")));
+            }
+            _ => match self.language.construct(self.s, &name) {
+                Some(construct) => Expr::Construct(construct),
+                None => {
+                    let language_name = self.language.name(self.s);
+                    let message = format!("No construct '{name}' in language '{language_name}'");
+                    return Err(invalid(col, message));
+                }
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{JsonParser, Parse};
+    use crate::testing::{json_storage, JSON_LANGUAGE_NAME};
+
+    const SOURCE: &str = r#"{"a": ["http://x", "ftp://y", ""], "b": null, "c": {}}"#;
+
+    fn parse_json(source: &str) -> (Storage, Node) {
+        let mut s = json_storage();
+        let root = JsonParser.parse(&mut s, "<testing>", source).unwrap();
+        (s, root)
+    }
+
+    fn parse_query(s: &Storage, query: &str) -> Result<Query, SearchError> {
+        Query::parse(s, s.language(JSON_LANGUAGE_NAME).unwrap(), query)
+    }
+
+    /// The text (or else the construct name) of each node under `root` that matches `query`, in
+    /// document order.
+    fn matches(s: &Storage, root: Node, query: &str) -> Vec<String> {
+        let query = parse_query(s, query).unwrap();
+        root.preorder(s)
+            .filter(|node| query.matches(s, *node))
+            .map(|node| match node.text(s) {
+                Some(text) => text.as_str().to_owned(),
+                None => node.construct(s).name(s).to_owned(),
+            })
+            .collect()
+    }
+
+    fn error(s: &Storage, query: &str) -> String {
+        parse_query(s, query).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_predicates() {
+        let (s, root) = parse_json(SOURCE);
+        let strings = ["http://x", "ftp://y", ""];
+
+        assert_eq!(matches(&s, root, "*").len(), root.preorder(&s).count());
+        assert!(matches(&s, root, "hole").is_empty());
+        assert_eq!(matches(&s, root, "Null"), ["Null"]);
+        assert_eq!(matches(&s, root, "Key"), ["a", "b", "c"]);
+        assert_eq!(matches(&s, root, "text == \"http://x\""), ["http://x"]);
+        assert_eq!(matches(&s, root, "String and text != \"\""), strings[..2]);
+        assert_eq!(matches(&s, root, "contains(\"://\")"), strings[..2]);
+        assert_eq!(matches(&s, root, "startswith(\"ftp\")"), ["ftp://y"]);
+        assert_eq!(matches(&s, root, "endswith(\"x\")"), ["http://x"]);
+        assert_eq!(matches(&s, root, "test(\"^[a-z]+:\")"), strings[..2]);
+        // Text predicates never match nodes that aren't texty.
+        assert_eq!(
+            matches(&s, root, "text != \"x\" and Null"),
+            Vec::<String>::new()
+        );
+
+        assert_eq!(matches(&s, root, "Object and not child(*)"), ["Object"]);
+        assert_eq!(
+            matches(&s, root, "descendant(Null)"),
+            ["Root", "Object", "ObjectPair"]
+        );
+        assert_eq!(matches(&s, root, "parent(Array)"), strings);
+        assert_eq!(matches(&s, root, "ancestor(Array)"), strings);
+        assert_eq!(matches(&s, root, "ancestor(Root) and Array"), ["Array"]);
+        assert!(matches(&s, root, "parent(*) and Root").is_empty());
+    }
+
+    #[test]
+    fn test_precedence() {
+        let (s, root) = parse_json(SOURCE);
+        let num_nodes = root.preorder(&s).count();
+
+        // `not` binds tighter than `and`, which binds tighter than `or`.
+        assert_eq!(matches(&s, root, "not String and Key"), ["a", "b", "c"]);
+        assert_eq!(matches(&s, root, "not (String and Key)").len(), num_nodes);
+        assert_eq!(matches(&s, root, "Key or Null and String"), ["a", "b", "c"]);
+        assert!(matches(&s, root, "(Key or Null) and String").is_empty());
+        assert_eq!(matches(&s, root, "not not Null"), ["Null"]);
+
+        // `>` and `>>` bind tighter than all of them, and associate to the left.
+        assert_eq!(
+            matches(&s, root, "Array > String and startswith(\"ftp\")"),
+            ["ftp://y"]
+        );
+        assert_eq!(matches(&s, root, "not Array > String").len(), num_nodes - 3);
+        assert!(matches(&s, root, "ObjectPair > String").is_empty());
+        assert_eq!(matches(&s, root, "ObjectPair >> String").len(), 3);
+        assert_eq!(matches(&s, root, "Root >> ObjectPair > Array > *").len(), 3);
+        assert_eq!(
+            matches(&s, root, "ObjectPair > Array or Null"),
+            ["Array", "Null"]
+        );
+    }
+
+    #[test]
+    fn test_escapes() {
+        let (s, root) = parse_json(r#"["a\"b", "c\\d", "e\nf", "g\th"]"#);
+
+        assert_eq!(matches(&s, root, r#"text == "a\"b""#), ["a\"b"]);
+        assert_eq!(matches(&s, root, r#"text == "c\\d""#), ["c\\d"]);
+        assert_eq!(matches(&s, root, r#"contains("\n")"#), ["e\nf"]);
+        assert_eq!(matches(&s, root, r#"contains("\t")"#), ["g\th"]);
+        assert_eq!(matches(&s, root, r#"test("\\\\")"#), ["c\\d"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let (s, _root) = parse_json(SOURCE);
+        let expect_error = |query: &str, message: &str| {
+            assert_eq!(error(&s, query), format!("Invalid query: {message}"));
+        };
+
+        expect_error("", "Expected a query at column 1");
+        expect_error("String and", "Expected a query at column 11");
+        expect_error("String )", "Unexpected ')' at column 8");
+        expect_error("and", "Expected a query, found 'and' at column 1");
+        expect_error("Null or )", "Expected a query, found ')' at column 9");
+        expect_error(
+            "Strin",
+            "No construct 'Strin' in language 'json' at column 1",
+        );
+        expect_error("text < \"a\"", "Unexpected character '<' at column 6");
+        expect_error("text = \"a\"", "Expected '=' after '=' at column 6");
+        expect_error("not ! Null", "Expected '=' after '!' at column 5");
+        expect_error(
+            "text \"a\"",
+            "Expected '==' or '!=', found string \"a\" at column 6",
+        );
+        expect_error("text == \"abc", "Unterminated string at column 9");
+        expect_error("text == \"a\\qb\"", "Invalid escape at column 11");
+        expect_error(
+            "contains(String)",
+            "Expected a string, found 'String' at column 10",
+        );
+        expect_error("child(*", "Expected ')' at column 8");
+        expect_error("child *", "Expected '(', found '*' at column 7");
+        assert!(matches!(
+            parse_query(&s, "test(\"(\")"),
+            Err(SearchError::InvalidRegex(_))
+        ));
+    }
+
+    #[test]
+    fn test_nesting_depth() {
+        let (s, root) = parse_json(SOURCE);
+        let nested = |depth: usize| format!("{}Null{}", "(".repeat(depth), ")".repeat(depth));
+
+        assert_eq!(matches(&s, root, &nested(MAX_NESTING_DEPTH)), ["Null"]);
+        let too_deep = format!("{}Null", "not ".repeat(MAX_NESTING_DEPTH + 1));
+        assert_eq!(
+            error(&s, &too_deep),
+            format!(
+                "Invalid query: Query is nested too deeply at column {}",
+                4 * (MAX_NESTING_DEPTH + 1) + 1
+            )
+        );
+        // Far too deep to parse recursively.
+        assert!(error(&s, &nested(100_000)).contains("Query is nested too deeply"));
+        let args = format!("{}*{}", "child(".repeat(100_000), ")".repeat(100_000));
+        assert!(error(&s, &args).contains("Query is nested too deeply"));
+    }
+}
@@ -0,0 +1,459 @@
+use crate::language::Storage;
+use crate::tree::{Node, NodeId};
+use std::collections::{HashMap, HashSet};
+use unicode_width::UnicodeWidthChar;
+
+/// Suggest at most this many corrections for a misspelled word.
+const MAX_SUGGESTIONS: usize = 8;
+/// Only suggest words that are at most this many single-character edits away.
+const MAX_EDIT_DISTANCE: usize = 2;
+
+/// A misspelled word in the text of a node whose construct is prose (see
+/// [`ConstructSpec::is_prose`](crate::ConstructSpec::is_prose)).
+#[derive(Debug, Clone)]
+pub struct Misspelling {
+    pub node: NodeId,
+    /// The char index into the node's text at which the word starts.
+    pub start: usize,
+    pub word: String,
+}
+
+/// The correctly spelled words, as read from a hunspell-style `.dic` file: a line with the number
+/// of words, then one word per line, each optionally followed by a `/` and affix flags.
+///
+/// The flags refer to the prefix and suffix rules (`PFX` and `SFX`) of the accompanying `.aff`
+/// file, and each word is expanded into all of the forms that its rules allow when the dictionary
+/// is loaded. Continuation classes and the other `.aff` options (besides `FLAG`) aren't supported.
+#[derive(Debug, Clone, Default)]
+pub struct Dictionary {
+    words: HashSet<String>,
+}
+
+impl Dictionary {
+    /// Read a dictionary from the source of its `.dic` file and, if it has one, its `.aff` file.
+    /// Without an `.aff` file, the affix flags are ignored, and words are only recognized in the
+    /// forms that are listed.
+    pub fn parse(dic_source: &str, aff_source: Option<&str>) -> Dictionary {
+        let affixes = aff_source.map(Affixes::parse).unwrap_or_default();
+        let mut lines = dic_source.lines().peekable();
+        // The word count is optional, since plain word lists are fine too.
+        if lines
+            .peek()
+            .is_some_and(|line| line.trim().parse::<usize>().is_ok())
+        {
+            lines.next();
+        }
+        let mut words = HashSet::new();
+        for line in lines {
+            // Anything after whitespace is morphological info.
+            let entry = match line.split_whitespace().next() {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let (word, flags) = entry.split_once('/').unwrap_or((entry, ""));
+            if word.is_empty() {
+                continue;
+            }
+            words.extend(affixes.expand(word, flags));
+        }
+        Dictionary { words }
+    }
+
+    pub fn num_words(&self) -> usize {
+        self.words.len()
+    }
+
+    /// Whether `word` is spelled correctly. Capitalized and all-caps versions of listed words are
+    /// correct too (as at the start of a sentence), but lowercase versions of capitalized words
+    /// (like names) aren't.
+    pub fn is_correct(&self, word: &str) -> bool {
+        if self.words.contains(word) {
+            return true;
+        }
+        let lowercase = word.to_lowercase();
+        if is_all_caps(word) {
+            self.words.contains(&lowercase) || self.words.contains(&capitalize(&lowercase))
+        } else if is_capitalized(word) {
+            self.words.contains(&lowercase)
+        } else {
+            false
+        }
+    }
+
+    /// Find the misspelled words in the text of each prose node under `root`, in document order.
+    pub fn check(&self, s: &Storage, root: Node) -> Vec<Misspelling> {
+        let mut misspellings = Vec::new();
+        for node in root.preorder(s) {
+            if !node.construct(s).is_prose(s) {
+                continue;
+            }
+            let text = match node.text(s) {
+                Some(text) => text,
+                None => continue,
+            };
+            for (start, word) in words(text.as_str()) {
+                if !self.is_correct(word) {
+                    misspellings.push(Misspelling {
+                        node: node.id(s),
+                        start,
+                        word: word.to_owned(),
+                    });
+                }
+            }
+        }
+        misspellings
+    }
+
+    /// Correctly spelled words that `word` might be a misspelling of, closest first. They're
+    /// capitalized the same way as `word`.
+    pub fn suggestions(&self, word: &str) -> Vec<String> {
+        let lowercase = word
+            .chars()
+            .flat_map(char::to_lowercase)
+            .collect::<Vec<_>>();
+        let mut candidates = self
+            .words
+            .iter()
+            .filter(|candidate| {
+                candidate.chars().count().abs_diff(lowercase.len()) <= MAX_EDIT_DISTANCE
+            })
+            .filter_map(|candidate| {
+                let candidate_chars = candidate
+                    .chars()
+                    .flat_map(char::to_lowercase)
+                    .collect::<Vec<_>>();
+                let distance = edit_distance(&lowercase, &candidate_chars);
+                if distance <= MAX_EDIT_DISTANCE {
+                    Some((distance, candidate))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        candidates.sort();
+
+        let mut suggestions = Vec::new();
+        for (_, candidate) in candidates {
+            let suggestion = match_case(word, candidate);
+            if suggestion != word && !suggestions.contains(&suggestion) {
+                suggestions.push(suggestion);
+            }
+            if suggestions.len() == MAX_SUGGESTIONS {
+                break;
+            }
+        }
+        suggestions
+    }
+}
+
+/// How the affix flags of a `.aff` file are written, as set by its `FLAG` option.
+#[derive(Debug, Clone, Copy, Default)]
+enum FlagKind {
+    /// Each char is a flag. This is the default, and is also what `FLAG UTF-8` means.
+    #[default]
+    Char,
+    /// Each pair of chars is a flag (`FLAG long`).
+    Long,
+    /// Flags are decimal numbers separated by commas (`FLAG num`).
+    Num,
+}
+
+impl FlagKind {
+    fn split(self, flags: &str) -> Vec<String> {
+        match self {
+            FlagKind::Char => flags.chars().map(String::from).collect(),
+            FlagKind::Long => {
+                let chars = flags.chars().collect::<Vec<_>>();
+                chars.chunks(2).map(|pair| pair.iter().collect()).collect()
+            }
+            FlagKind::Num => flags
+                .split(',')
+                .filter(|flag| !flag.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+/// The prefix and suffix rules of a `.aff` file, by the flag that enables them.
+#[derive(Debug, Default)]
+struct Affixes {
+    flag_kind: FlagKind,
+    classes: HashMap<String, AffixClass>,
+}
+
+/// All of the rules for one flag.
+#[derive(Debug)]
+struct AffixClass {
+    is_prefix: bool,
+    /// Whether this affix can be combined with affixes of the other kind (a prefix with a suffix).
+    cross_product: bool,
+    rules: Vec<AffixRule>,
+}
+
+/// Remove `strip` from the start (for a prefix) or end (for a suffix) of a word and put `add` there
+/// instead, but only if that end of the word matches `condition`.
+#[derive(Debug)]
+struct AffixRule {
+    strip: String,
+    add: String,
+    condition: Vec<CharPattern>,
+}
+
+/// One position of an affix rule's condition.
+#[derive(Debug)]
+enum CharPattern {
+    /// `.`
+    Any,
+    /// `[abc]`, or a single char.
+    OneOf(Vec<char>),
+    /// `[^abc]`
+    NoneOf(Vec<char>),
+}
+
+impl Affixes {
+    /// Lines that aren't prefix or suffix rules (or the `FLAG` option) are ignored, as are
+    /// malformed ones.
+    fn parse(source: &str) -> Affixes {
+        let mut affixes = Affixes::default();
+        for line in source.lines() {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields.as_slice() {
+                ["FLAG", kind, ..] => {
+                    affixes.flag_kind = match *kind {
+                        "long" => FlagKind::Long,
+                        "num" => FlagKind::Num,
+                        _ => FlagKind::Char,
+                    }
+                }
+                [kind @ ("PFX" | "SFX"), flag, rest @ ..] => {
+                    if let Some(class) = affixes.classes.get_mut(*flag) {
+                        // A rule: strip, add, and an optional condition.
+                        if let [strip, add, condition @ ..] = rest {
+                            class.rules.push(AffixRule::new(
+                                strip,
+                                add,
+                                condition.first().copied().unwrap_or("."),
+                            ));
+                        }
+                    } else if let [cross_product, _count, ..] = rest {
+                        // The header: whether it combines, and the number of rules.
+                        affixes.classes.insert(
+                            (*flag).to_owned(),
+                            AffixClass {
+                                is_prefix: *kind == "PFX",
+                                cross_product: *cross_product == "Y",
+                                rules: Vec::new(),
+                            },
+                        );
+                    }
+                }
+                _ => (),
+            }
+        }
+        affixes
+    }
+
+    /// `word` and all of the forms of it that `flags` allow.
+    fn expand(&self, word: &str, flags: &str) -> Vec<String> {
+        let classes = self
+            .flag_kind
+            .split(flags)
+            .into_iter()
+            .filter_map(|flag| self.classes.get(&flag))
+            .collect::<Vec<_>>();
+        let mut forms = vec![word.to_owned()];
+        // Suffixed forms that prefixes may also be added to.
+        let mut combinable = Vec::new();
+        for class in classes.iter().filter(|class| !class.is_prefix) {
+            for rule in &class.rules {
+                if let Some(form) = rule.apply(false, word) {
+                    if class.cross_product {
+                        combinable.push(form.clone());
+                    }
+                    forms.push(form);
+                }
+            }
+        }
+        for class in classes.iter().filter(|class| class.is_prefix) {
+            for rule in &class.rules {
+                forms.extend(rule.apply(true, word));
+                if class.cross_product {
+                    forms.extend(combinable.iter().filter_map(|form| rule.apply(true, form)));
+                }
+            }
+        }
+        forms
+    }
+}
+
+impl AffixRule {
+    fn new(strip: &str, add: &str, condition: &str) -> AffixRule {
+        // `0` means the empty string. Anything after a `/` in `add` is continuation flags.
+        let strip = if strip == "0" { "" } else { strip };
+        let add = add.split('/').next().unwrap_or("");
+        let add = if add == "0" { "" } else { add };
+        AffixRule {
+            strip: strip.to_owned(),
+            add: add.to_owned(),
+            condition: CharPattern::parse_all(condition),
+        }
+    }
+
+    fn apply(&self, is_prefix: bool, word: &str) -> Option<String> {
+        let chars = word.chars().collect::<Vec<_>>();
+        if self.condition.len() > chars.len() {
+            return None;
+        }
+        let end = if is_prefix {
+            &chars[..self.condition.len()]
+        } else {
+            &chars[chars.len() - self.condition.len()..]
+        };
+        let matches = end
+            .iter()
+            .zip(&self.condition)
+            .all(|(ch, pattern)| pattern.matches(*ch));
+        if !matches {
+            return None;
+        }
+        if is_prefix {
+            let stem = word.strip_prefix(self.strip.as_str())?;
+            Some(format!("{}{}", self.add, stem))
+        } else {
+            let stem = word.strip_suffix(self.strip.as_str())?;
+            Some(format!("{}{}", stem, self.add))
+        }
+    }
+}
+
+impl CharPattern {
+    fn parse_all(condition: &str) -> Vec<CharPattern> {
+        if condition == "." {
+            return Vec::new();
+        }
+        let mut patterns = Vec::new();
+        let mut chars = condition.chars();
+        while let Some(ch) = chars.next() {
+            let pattern = match ch {
+                '.' => CharPattern::Any,
+                '[' => {
+                    let mut set = chars
+                        .by_ref()
+                        .take_while(|ch| *ch != ']')
+                        .collect::<Vec<_>>();
+                    if set.first() == Some(&'^') {
+                        set.remove(0);
+                        CharPattern::NoneOf(set)
+                    } else {
+                        CharPattern::OneOf(set)
+                    }
+                }
+                _ => CharPattern::OneOf(vec![ch]),
+            };
+            patterns.push(pattern);
+        }
+        patterns
+    }
+
+    fn matches(&self, ch: char) -> bool {
+        match self {
+            CharPattern::Any => true,
+            CharPattern::OneOf(set) => set.contains(&ch),
+            CharPattern::NoneOf(set) => !set.contains(&ch),
+        }
+    }
+}
+
+/// The words in `text`, with the char index each starts at. Words are runs of letters, possibly
+/// joined by apostrophes (like "don't"). Runs that contain digits or underscores (like "utf8" or
+/// "max_width") are skipped, since they're more likely to be identifiers than prose.
+fn words(text: &str) -> Vec<(usize, &str)> {
+    let chars = text.char_indices().collect::<Vec<_>>();
+    let is_apostrophe_in_word = |i: usize| {
+        matches!(chars[i].1, '\'' | '’')
+            && i > 0
+            && is_word_char(chars[i - 1].1)
+            && chars.get(i + 1).is_some_and(|(_, ch)| is_word_char(*ch))
+    };
+
+    let mut words = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_word_char(chars[i].1) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && (is_word_char(chars[i].1) || is_apostrophe_in_word(i)) {
+            i += 1;
+        }
+        let start_byte = chars[start].0;
+        let end_byte = chars.get(i).map(|(byte, _)| *byte).unwrap_or(text.len());
+        let word = &text[start_byte..end_byte];
+        if !word.chars().any(|ch| ch.is_numeric() || ch == '_') {
+            words.push((start, word));
+        }
+    }
+    words
+}
+
+/// Combining characters (like accents) are part of the word they're attached to.
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || (ch.width() == Some(0) && !ch.is_control())
+}
+
+fn is_all_caps(word: &str) -> bool {
+    word.chars().filter(|ch| ch.is_alphabetic()).count() > 1
+        && !word.chars().any(char::is_lowercase)
+}
+
+fn is_capitalized(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(char::is_uppercase) && !chars.any(char::is_uppercase)
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// `suggestion`, capitalized like `word` if `word` is capitalized or all caps.
+fn match_case(word: &str, suggestion: &str) -> String {
+    if is_all_caps(word) {
+        suggestion.to_uppercase()
+    } else if is_capitalized(word) {
+        capitalize(suggestion)
+    } else {
+        suggestion.to_owned()
+    }
+}
+
+/// The number of single-char insertions, deletions, substitutions, and swaps of adjacent chars it
+/// takes to turn `a` into `b` (without editing any part of it twice).
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    // `rows[i][j]` is the distance between the first `i` chars of `a` and the first `j` of `b`.
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in rows[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution_cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + substitution_cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
@@ -0,0 +1,293 @@
+use super::command::{EdCommand, TextEdCommand, TreeEdCommand};
+use crate::language::{Language, Storage};
+use crate::tree::{Node, SavedLocation, SavedNode, TreeError};
+use crate::util::{StableHasher, SynlessBug};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Remembers, on disk, the undo history of each file-backed doc as of when it was last saved, so
+/// that it can be restored when the file is next opened (even in a later session).
+///
+/// There's one cache file per doc path. It also records a hash of the doc's source as it was
+/// saved, and is ignored if the file has changed since. The cache is best-effort: if it can't be
+/// read or written, docs simply start with no history.
+#[derive(Debug, Clone)]
+pub struct UndoCache {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UndoCacheEntry {
+    source_hash: String,
+    history: SavedHistory,
+}
+
+/// A doc's whole undo history, in a form that can be saved to disk. See [`Doc::save_history`].
+///
+/// [`Doc::save_history`]: super::doc::Doc::save_history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedHistory {
+    /// Indexed by state id. The original state comes first, and parents come before their
+    /// children.
+    pub states: Vec<SavedHistoryState>,
+    /// The state that the doc was in when its history was saved.
+    pub current: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedHistoryState {
+    pub parent: Option<usize>,
+    pub redo_child: Option<usize>,
+    /// The edits between the parent state and this one. If this state is the current state or
+    /// one of its ancestors, this group undoes them; otherwise it redoes them. `None` only for
+    /// the original state.
+    pub group: Option<SavedUndoGroup>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedUndoGroup {
+    /// Where to put the cursor after executing the commands.
+    pub restore_loc: SavedLocation,
+    /// In the order that they should be executed. Each location is only valid once the commands
+    /// before it have been executed.
+    pub commands: Vec<(SavedLocation, SavedEdCommand)>,
+    pub time: SystemTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SavedEdCommand {
+    TreeInsert(SavedNode),
+    TreeInsertMany(Vec<SavedNode>),
+    TreeReplace(SavedNode),
+    TreeBackspace,
+    TreeDelete,
+    TreeDeleteMany(usize),
+    TreeUnwrap,
+    TreeFillColumn,
+    TreeWrap(SavedNode),
+    TreeWrapAt(SavedNode, usize),
+    TreeSwapPrev,
+    TreeSwapNext,
+    TreeSplitList,
+    TreeJoinLists,
+    TextInsert(char),
+    TextBackspace,
+    TextDelete,
+    TextBackspaceChar,
+    TextDeleteChar,
+    TextBackspaceWord,
+    TextDeleteWord,
+}
+
+impl UndoCache {
+    pub fn new(dir: PathBuf) -> UndoCache {
+        UndoCache { dir }
+    }
+
+    /// Remember `history` as the history of the doc at `doc_path`, which was just saved with
+    /// contents `source`.
+    pub fn insert(&self, doc_path: &Path, source: &str, history: SavedHistory) {
+        let entry = UndoCacheEntry {
+            source_hash: source_hash(source),
+            history,
+        };
+        if let Ok(ron_string) = ron::to_string(&entry) {
+            if std::fs::create_dir_all(&self.dir).is_ok() {
+                let _ = std::fs::write(self.entry_path(doc_path), ron_string);
+            }
+        }
+    }
+
+    /// The history of the doc at `doc_path`, if one was remembered when it was last saved and the
+    /// file still has the same contents `source`.
+    pub fn get(&self, doc_path: &Path, source: &str) -> Option<SavedHistory> {
+        let ron_string = std::fs::read_to_string(self.entry_path(doc_path)).ok()?;
+        let entry = ron::from_str::<UndoCacheEntry>(&ron_string).ok()?;
+        if entry.source_hash == source_hash(source) {
+            Some(entry.history)
+        } else {
+            None
+        }
+    }
+
+    fn entry_path(&self, doc_path: &Path) -> PathBuf {
+        let doc_path = std::fs::canonicalize(doc_path).unwrap_or_else(|_| doc_path.to_owned());
+        let mut hasher = StableHasher::new();
+        hasher.write_bytes(doc_path.as_os_str().as_encoded_bytes());
+        self.dir.join(format!("{}.ron", hasher.finish_hex()))
+    }
+}
+
+fn source_hash(source: &str) -> String {
+    let mut hasher = StableHasher::new();
+    // The saved history format may change between releases, and an entry written by another
+    // release should be treated as stale rather than misread.
+    hasher.write_str(env!("CARGO_PKG_VERSION"));
+    hasher.write_str(source);
+    hasher.finish_hex()
+}
+
+impl SavedEdCommand {
+    pub fn save(s: &Storage, cmd: &EdCommand) -> SavedEdCommand {
+        use SavedEdCommand::*;
+
+        match cmd {
+            EdCommand::Tree(TreeEdCommand::Insert(node)) => TreeInsert(node.save(s)),
+            EdCommand::Tree(TreeEdCommand::InsertMany(nodes)) => {
+                TreeInsertMany(nodes.iter().map(|node| node.save(s)).collect())
+            }
+            EdCommand::Tree(TreeEdCommand::Replace(node)) => TreeReplace(node.save(s)),
+            EdCommand::Tree(TreeEdCommand::Backspace) => TreeBackspace,
+            EdCommand::Tree(TreeEdCommand::Delete) => TreeDelete,
+            EdCommand::Tree(TreeEdCommand::DeleteMany(count)) => TreeDeleteMany(*count),
+            EdCommand::Tree(TreeEdCommand::Unwrap) => TreeUnwrap,
+            EdCommand::Tree(TreeEdCommand::FillColumn) => TreeFillColumn,
+            EdCommand::Tree(TreeEdCommand::SwapPrev) => TreeSwapPrev,
+            EdCommand::Tree(TreeEdCommand::SwapNext) => TreeSwapNext,
+            EdCommand::Tree(TreeEdCommand::SplitList) => TreeSplitList,
+            EdCommand::Tree(TreeEdCommand::JoinLists) => TreeJoinLists,
+            EdCommand::Tree(TreeEdCommand::Wrap(node)) => TreeWrap(node.save(s)),
+            EdCommand::Tree(TreeEdCommand::WrapAt(node, index)) => TreeWrapAt(node.save(s), *index),
+            EdCommand::Text(TextEdCommand::Insert(ch)) => TextInsert(*ch),
+            EdCommand::Text(TextEdCommand::Backspace) => TextBackspace,
+            EdCommand::Text(TextEdCommand::Delete) => TextDelete,
+            EdCommand::Text(TextEdCommand::BackspaceChar) => TextBackspaceChar,
+            EdCommand::Text(TextEdCommand::DeleteChar) => TextDeleteChar,
+            EdCommand::Text(TextEdCommand::BackspaceWord) => TextBackspaceWord,
+            EdCommand::Text(TextEdCommand::DeleteWord) => TextDeleteWord,
+        }
+    }
+
+    /// Rebuild the command, with any node it contains in `language`.
+    pub fn restore(&self, s: &mut Storage, language: Language) -> Result<EdCommand, TreeError> {
+        use SavedEdCommand::*;
+
+        Ok(match self {
+            TreeInsert(node) => TreeEdCommand::Insert(Node::restore(s, language, node)?).into(),
+            TreeInsertMany(saved_nodes) => {
+                let mut nodes = Vec::with_capacity(saved_nodes.len());
+                for saved_node in saved_nodes {
+                    match Node::restore(s, language, saved_node) {
+                        Ok(node) => nodes.push(node),
+                        Err(err) => {
+                            for node in nodes {
+                                node.delete_root(s).bug();
+                            }
+                            return Err(err);
+                        }
+                    }
+                }
+                TreeEdCommand::InsertMany(nodes).into()
+            }
+            TreeReplace(node) => TreeEdCommand::Replace(Node::restore(s, language, node)?).into(),
+            TreeBackspace => TreeEdCommand::Backspace.into(),
+            TreeDelete => TreeEdCommand::Delete.into(),
+            TreeDeleteMany(count) => TreeEdCommand::DeleteMany(*count).into(),
+            TreeUnwrap => TreeEdCommand::Unwrap.into(),
+            TreeFillColumn => TreeEdCommand::FillColumn.into(),
+            TreeSwapPrev => TreeEdCommand::SwapPrev.into(),
+            TreeSwapNext => TreeEdCommand::SwapNext.into(),
+            TreeSplitList => TreeEdCommand::SplitList.into(),
+            TreeJoinLists => TreeEdCommand::JoinLists.into(),
+            TreeWrap(node) => TreeEdCommand::Wrap(Node::restore(s, language, node)?).into(),
+            TreeWrapAt(node, index) => {
+                TreeEdCommand::WrapAt(Node::restore(s, language, node)?, *index).into()
+            }
+            TextInsert(ch) => TextEdCommand::Insert(*ch).into(),
+            TextBackspace => TextEdCommand::Backspace.into(),
+            TextDelete => TextEdCommand::Delete.into(),
+            TextBackspaceChar => TextEdCommand::BackspaceChar.into(),
+            TextDeleteChar => TextEdCommand::DeleteChar.into(),
+            TextBackspaceWord => TextEdCommand::BackspaceWord.into(),
+            TextDeleteWord => TextEdCommand::DeleteWord.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{DocName, Engine};
+    use crate::testing::{json_engine, TempDir, JSON_LANGUAGE_NAME};
+
+    /// An engine with the json language and an undo cache in `dir`, showing the doc `doc_name`
+    /// loaded from `source`, and with whatever history the cache has for `path`.
+    fn open_doc(dir: &Path, doc_name: &DocName, path: &Path, source: &str) -> Engine {
+        let mut engine = json_engine();
+        engine.set_undo_cache(UndoCache::new(dir.to_owned()));
+        engine
+            .load_doc_from_source(doc_name.clone(), JSON_LANGUAGE_NAME, source)
+            .unwrap();
+        engine
+            .restore_undo_history(doc_name, path, JSON_LANGUAGE_NAME, source)
+            .unwrap();
+        engine.set_visible_doc(doc_name).unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_save_and_restore_history() {
+        let temp_dir = TempDir::new("test_save_and_restore_history");
+        let dir = temp_dir.path();
+        let path = dir.join("doc.json");
+        let doc_name = DocName::File(path.clone());
+
+        let mut engine = open_doc(dir, &doc_name, &path, "[1]");
+        engine.paste_text("[true, 2]").unwrap();
+        engine.paste_text("null").unwrap();
+        engine.undo().unwrap();
+        engine.paste_text("{}").unwrap();
+        let source = engine.print_source(&doc_name).unwrap();
+        assert_eq!(source, "{}");
+        engine.save_undo_history(&doc_name, &path, &source).unwrap();
+
+        // The whole history comes back, including the branch that was undone.
+        let mut engine = open_doc(dir, &doc_name, &path, &source);
+        let (states, current) = engine.undo_history_tree().unwrap();
+        assert_eq!((states.len(), current), (4, 3));
+        engine.undo().unwrap();
+        assert_eq!(engine.print_source(&doc_name).unwrap(), "[true, 2]");
+        engine.undo().unwrap();
+        assert_eq!(engine.print_source(&doc_name).unwrap(), "[1]");
+        assert!(engine.undo().is_err());
+        engine.redo().unwrap();
+        engine.redo().unwrap();
+        assert_eq!(engine.print_source(&doc_name).unwrap(), "{}");
+        assert!(engine.redo().is_err());
+        engine.goto_undo_state(2).unwrap();
+        assert_eq!(engine.print_source(&doc_name).unwrap(), "null");
+    }
+
+    #[test]
+    fn test_stale_history() {
+        let temp_dir = TempDir::new("test_stale_history");
+        let dir = temp_dir.path();
+        let cache = UndoCache::new(dir.to_owned());
+        let path = dir.join("doc.json");
+        let other_path = dir.join("other.json");
+        let history = SavedHistory {
+            states: vec![SavedHistoryState {
+                parent: None,
+                redo_child: None,
+                group: None,
+            }],
+            current: 0,
+        };
+
+        assert!(cache.get(&path, "[1]").is_none());
+        cache.insert(&path, "[1]", history);
+        assert!(cache.get(&path, "[1]").is_some());
+        assert!(cache.get(&path, "[1, 2]").is_none());
+        assert!(cache.get(&other_path, "[1]").is_none());
+
+        // A doc whose file changed since its history was saved starts with no history.
+        let doc_name = DocName::File(path.clone());
+        let mut engine = open_doc(dir, &doc_name, &path, "[1]");
+        engine.paste_text("[2]").unwrap();
+        engine.save_undo_history(&doc_name, &path, "[2]").unwrap();
+        let mut engine = open_doc(dir, &doc_name, &path, "[3]");
+        assert!(engine.undo().is_err());
+        assert_eq!(engine.print_source(&doc_name).unwrap(), "[3]");
+    }
+}
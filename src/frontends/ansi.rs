@@ -0,0 +1,40 @@
+//! Export a captured window as text with ANSI escape codes, for printing to a terminal (or to
+//! `less -R`).
+
+use super::capture::{CaptureWindow, CapturedCell};
+use super::terminal::queue_style;
+use crate::util::SynlessBug;
+
+use crossterm::style::{Attribute, ResetColor, SetAttribute};
+use crossterm::QueueableCommand;
+
+impl CaptureWindow {
+    /// The window's contents, one line per row, with escape codes to set the style. Blank cells at
+    /// the end of each row are left off, and the style is reset at the end of each line.
+    pub fn to_ansi_string(&self) -> String {
+        let blank_style = self.blank_style();
+        let mut out = Vec::new();
+        for row in &self.cells {
+            let mut current_style = None;
+            for cell in CaptureWindow::trimmed_row(row) {
+                let (text, style) = match cell {
+                    CapturedCell::Blank => (" ", blank_style),
+                    CapturedCell::Text(text, style) => (text.as_str(), *style),
+                    CapturedCell::Covered => continue,
+                };
+                if current_style != Some(style) {
+                    // Writing to a Vec can't fail.
+                    queue_style(&mut out, style).bug();
+                    current_style = Some(style);
+                }
+                out.extend_from_slice(text.as_bytes());
+            }
+            if current_style.is_some() {
+                out.queue(ResetColor).bug();
+                out.queue(SetAttribute(Attribute::Reset)).bug();
+            }
+            out.push(b'\n');
+        }
+        String::from_utf8(out).bug_msg("Invalid UTF-8 in ANSI output")
+    }
+}
@@ -0,0 +1,113 @@
+//! Render to an in-memory grid of styled characters, to be exported to other formats. See
+//! `ansi.rs` and `svg.rs`.
+
+use crate::style::{ColorTheme, ConcreteStyle, Style};
+
+use partial_pretty_printer::pane::PrettyWindow;
+use partial_pretty_printer::{Pos, Size};
+
+use unicode_width::UnicodeWidthChar;
+
+/// A window that remembers everything displayed in it, with the colors of its theme, so that it
+/// can be exported with [`CaptureWindow::to_ansi_string`] or [`CaptureWindow::to_svg_string`].
+pub struct CaptureWindow {
+    pub(super) color_theme: ColorTheme,
+    size: Size,
+    /// The grid of cells, row by row.
+    pub(super) cells: Vec<Vec<CapturedCell>>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CaptureWindowError {
+    #[error("Character position outside window boundary")]
+    OutOfBounds,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum CapturedCell {
+    /// Nothing has been displayed here.
+    Blank,
+    /// A char, followed by any zero-width chars (like accents) drawn on top of it.
+    Text(String, ConcreteStyle),
+    /// The right half of the full-width char to the left.
+    Covered,
+}
+
+impl CaptureWindow {
+    pub fn new(theme: ColorTheme, size: Size) -> CaptureWindow {
+        CaptureWindow {
+            color_theme: theme,
+            size,
+            cells: vec![vec![CapturedCell::Blank; size.width as usize]; size.height as usize],
+        }
+    }
+
+    /// The style that blank cells are shown in.
+    pub(super) fn blank_style(&self) -> ConcreteStyle {
+        self.color_theme.concrete_style(&Style::default())
+    }
+
+    /// The cells of `row` up to the last one that isn't blank.
+    pub(super) fn trimmed_row(row: &[CapturedCell]) -> &[CapturedCell] {
+        let len = row
+            .iter()
+            .rposition(|cell| !matches!(cell, CapturedCell::Blank))
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        &row[..len]
+    }
+
+    fn cell_mut(&mut self, pos: Pos) -> Option<&mut CapturedCell> {
+        self.cells
+            .get_mut(pos.row as usize)?
+            .get_mut(pos.col as usize)
+    }
+}
+
+impl PrettyWindow for CaptureWindow {
+    type Error = CaptureWindowError;
+    type Style = Style;
+
+    fn size(&self) -> Result<Size, CaptureWindowError> {
+        Ok(self.size)
+    }
+
+    fn display_char(
+        &mut self,
+        ch: char,
+        pos: Pos,
+        style: &Self::Style,
+        full_width: bool,
+    ) -> Result<(), Self::Error> {
+        if ch.width() == Some(0) {
+            // Draw it on top of the preceding char, like a terminal would.
+            if pos.col > 0 {
+                let prev_pos = Pos {
+                    row: pos.row,
+                    col: pos.col - 1,
+                };
+                if let Some(CapturedCell::Text(text, _)) = self.cell_mut(prev_pos) {
+                    text.push(ch);
+                }
+            }
+            return Ok(());
+        }
+        let concrete_style = self.color_theme.concrete_style(style);
+        *self.cell_mut(pos).ok_or(CaptureWindowError::OutOfBounds)? =
+            CapturedCell::Text(ch.to_string(), concrete_style);
+        if full_width {
+            let covered_pos = Pos {
+                row: pos.row,
+                col: pos.col + 1,
+            };
+            *self
+                .cell_mut(covered_pos)
+                .ok_or(CaptureWindowError::OutOfBounds)? = CapturedCell::Covered;
+        }
+        Ok(())
+    }
+
+    fn set_focus(&mut self, _pos: Pos) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
+use crate::tree::NodeId;
 use crate::util::SynlessBug;
 use partial_pretty_printer as ppp;
+use std::cmp::Ordering;
 use std::fmt;
 use std::str::FromStr;
 use std::time::Duration;
@@ -12,6 +14,8 @@ pub trait Frontend: Sized + ppp::pane::PrettyWindow {
     /// Set the color theme. Must not be called between `start_frame()` and `end_frame()`.
     fn set_color_theme(&mut self, theme: ColorTheme) -> Result<(), Self::Error>;
 
+    fn color_theme(&self) -> &ColorTheme;
+
     /// Block until an event (eg. keypress) occurs, then return it. `None` means that no event
     /// occurred before the timeout elapsed.
     fn next_event(&mut self, timeout: Duration) -> Result<Option<Event>, Self::Error>;
@@ -21,6 +25,13 @@ pub trait Frontend: Sized + ppp::pane::PrettyWindow {
 
     /// Show the modified frame to the user. This must be called after pretty-printing.
     fn end_frame(&mut self) -> Result<(), Self::Error>;
+
+    /// The id of the innermost document node that was drawn at the given position in the most
+    /// recent frame, if any. Used to determine what the mouse is pointing at.
+    fn node_at(&self, pos: ppp::Pos) -> Option<NodeId>;
+
+    /// Put `text` on the system clipboard, so that it can be pasted into other programs.
+    fn copy_to_system_clipboard(&mut self, text: &str) -> Result<(), Self::Error>;
 }
 
 /// An input event.
@@ -35,9 +46,19 @@ pub enum Event {
 
 pub struct MouseEvent {
     /// A character grid position, relative to the window.
-    pub click_pos: ppp::Pos,
-    /// Which mouse button was clicked.
+    pub pos: ppp::Pos,
+    /// Which mouse button was involved.
     pub button: MouseButton,
+    pub kind: MouseEventKind,
+}
+
+pub enum MouseEventKind {
+    /// The button was pressed.
+    Press,
+    /// The mouse was moved while the button was held down.
+    Drag,
+    /// The button was released.
+    Release,
 }
 
 pub enum MouseButton {
@@ -180,6 +201,50 @@ impl FromStr for Key {
     }
 }
 
+/// Keys are ordered the way they're listed in the key hints: keys without modifiers first, then
+/// characters (alphabetically, with each lowercase letter just before its uppercase), then named
+/// keys, then function keys.
+impl Ord for Key {
+    fn cmp(&self, other: &Key) -> Ordering {
+        fn modifiers_rank(modifiers: KeyModifiers) -> (bool, bool, bool) {
+            (modifiers.ctrl, modifiers.alt, modifiers.shift)
+        }
+
+        fn code_rank(code: KeyCode) -> (u8, char, bool, u8) {
+            use KeyCode::*;
+
+            match code {
+                Char(ch) => (0, ch.to_ascii_lowercase(), ch.is_ascii_uppercase(), 0),
+                F(num) => (2, ' ', false, num),
+                Backspace => (1, ' ', false, 0),
+                Enter => (1, ' ', false, 1),
+                Left => (1, ' ', false, 2),
+                Right => (1, ' ', false, 3),
+                Up => (1, ' ', false, 4),
+                Down => (1, ' ', false, 5),
+                Home => (1, ' ', false, 6),
+                End => (1, ' ', false, 7),
+                PageUp => (1, ' ', false, 8),
+                PageDown => (1, ' ', false, 9),
+                Tab => (1, ' ', false, 10),
+                Delete => (1, ' ', false, 11),
+                Insert => (1, ' ', false, 12),
+                Esc => (1, ' ', false, 13),
+            }
+        }
+
+        modifiers_rank(self.modifiers)
+            .cmp(&modifiers_rank(other.modifiers))
+            .then_with(|| code_rank(self.code).cmp(&code_rank(other.code)))
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Key) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl fmt::Display for Key {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use KeyCode::*;
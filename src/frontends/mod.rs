@@ -1,8 +1,14 @@
+mod ansi;
+mod capture;
 mod frontend;
+mod overlay;
 mod screen_buf;
+mod svg;
 mod terminal;
 
-pub use frontend::{Event, Frontend, Key};
+pub use capture::CaptureWindow;
+pub use frontend::{Event, Frontend, Key, KeyCode, MouseButton, MouseEvent, MouseEventKind};
+pub use overlay::Overlay;
 pub use terminal::Terminal;
 
 use crate::util::{error, SynlessError};
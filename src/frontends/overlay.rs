@@ -0,0 +1,75 @@
+use partial_pretty_printer::pane::PrettyWindow;
+use partial_pretty_printer::{Col, Height, Pos, Row, Size, Width};
+
+/// A rectangular region of an underlying window, which can be drawn to as if it were a window of
+/// its own. Anything drawn to it is drawn on top of whatever the underlying window already
+/// contains there, so it can be used to float panes (like menus) over the document.
+pub struct Overlay<'w, W: PrettyWindow> {
+    window: &'w mut W,
+    /// The position of the overlay's top-left corner in the underlying window.
+    offset: Pos,
+    size: Size,
+}
+
+impl<'w, W: PrettyWindow> Overlay<'w, W> {
+    /// Make an overlay at `offset` in `window`. Its size will be shrunk if necessary to fit
+    /// inside the window.
+    pub fn new(window: &'w mut W, offset: Pos, size: Size) -> Result<Overlay<'w, W>, W::Error> {
+        let window_size = window.size()?;
+        let size = Size {
+            width: size
+                .width
+                .min(window_size.width.saturating_sub(offset.col as Width)),
+            height: size
+                .height
+                .min(window_size.height.saturating_sub(offset.row as Height)),
+        };
+        Ok(Overlay {
+            window,
+            offset,
+            size,
+        })
+    }
+
+    /// Blank out the whole overlay, hiding whatever was beneath it.
+    pub fn clear(&mut self, style: &W::Style) -> Result<(), W::Error> {
+        for row in 0..self.size.height as Row {
+            for col in 0..self.size.width as Col {
+                self.display_char(' ', Pos { row, col }, style, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn underlying_pos(&self, pos: Pos) -> Pos {
+        Pos {
+            row: self.offset.row + pos.row,
+            col: self.offset.col + pos.col,
+        }
+    }
+}
+
+impl<'w, W: PrettyWindow> PrettyWindow for Overlay<'w, W> {
+    type Error = W::Error;
+    type Style = W::Style;
+
+    fn size(&self) -> Result<Size, Self::Error> {
+        Ok(self.size)
+    }
+
+    fn display_char(
+        &mut self,
+        ch: char,
+        pos: Pos,
+        style: &Self::Style,
+        full_width: bool,
+    ) -> Result<(), Self::Error> {
+        let pos = self.underlying_pos(pos);
+        self.window.display_char(ch, pos, style, full_width)
+    }
+
+    fn set_focus(&mut self, pos: Pos) -> Result<(), Self::Error> {
+        let pos = self.underlying_pos(pos);
+        self.window.set_focus(pos)
+    }
+}
@@ -31,6 +31,8 @@ struct CharCell {
     ch: char,
     style: ConcreteStyle,
     width: CharWidth,
+    /// Zero-width characters (like accents) that are drawn on top of `ch`.
+    combining: Vec<char>,
 }
 
 /// Instructions for how to update a screen.
@@ -53,6 +55,9 @@ pub enum ScreenOp {
     /// The `ScreenBuf` will never instruct you to `Print` multiple characters within a single
     /// frame that would overwrite each other.
     Print(char, CharWidth),
+    /// Instructs you to print a zero-width combining character, which attaches to the character
+    /// that was just printed and does not move the cursor.
+    Combine(char),
     /// Set a persistent style that will apply to anything printed, until a new style is applied.
     Style(ConcreteStyle),
     /// Set the cursor position.
@@ -71,6 +76,9 @@ pub struct ScreenBufIter<'a> {
     /// Which cell the iterator is considering (NOT the position of the
     /// screen's cursor). None means we're past the end / done iterating.
     buffer_pos: Option<Pos>,
+    /// Combining characters of the last printed cell that have yet to be printed, in reverse
+    /// order.
+    pending_combining: Vec<char>,
 }
 
 impl Buffer {
@@ -79,6 +87,7 @@ impl Buffer {
             ch: ' ',
             style: blank_style,
             width: 1,
+            combining: Vec::new(),
         };
         Buffer {
             cells: vec![blank_cell; (size.width as usize) * (size.height as usize)],
@@ -119,10 +128,12 @@ impl ScreenBuf {
         }
     }
 
-    /// Sets the style to use for empty space, and clears the buffer.
+    /// Sets the style to use for empty space, and clears the buffer. Since every blank cell
+    /// changes, the next call to `drain_changes()` will redraw the whole screen.
     pub fn set_blank_style(&mut self, blank_style: ConcreteStyle) {
         self.blank_style = blank_style;
-        self.drain_changes();
+        self.new_buffer = Buffer::new(self.size, blank_style);
+        self.old_buffer = None;
     }
 
     /// Get `ScreenOp` instructions that describe all changes to the screen buffer since the last
@@ -143,6 +154,7 @@ impl ScreenBuf {
             screen_style: None,
             screen_pos: None,
             buffer_pos: Some(Pos::zero()),
+            pending_combining: Vec::new(),
         }
     }
 
@@ -159,7 +171,8 @@ impl ScreenBuf {
         self.size
     }
 
-    /// Returns false if out of bounds.
+    /// Returns false if out of bounds. A full-width character that would hang off the right edge
+    /// of the screen is drawn as a space instead.
     #[must_use]
     pub fn display_char(
         &mut self,
@@ -168,10 +181,48 @@ impl ScreenBuf {
         style: ConcreteStyle,
         width: CharWidth,
     ) -> bool {
+        let (ch, width) = if pos.col + width as Width > self.size.width {
+            (' ', 1)
+        } else {
+            (ch, width)
+        };
         if let Some(cell) = self.new_buffer.get_mut(pos) {
             cell.ch = ch;
             cell.style = style;
             cell.width = width;
+            cell.combining.clear();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Attach a zero-width combining character to the character that ends just before `pos`.
+    /// Returns false if there is no such character.
+    #[must_use]
+    pub fn combine_char(&mut self, ch: char, pos: Pos) -> bool {
+        let owner_col = if pos.col >= 2
+            && self
+                .new_buffer
+                .get(Pos {
+                    row: pos.row,
+                    col: pos.col - 2,
+                })
+                .map(|cell| cell.width == 2)
+                .unwrap_or(false)
+        {
+            pos.col - 2
+        } else if pos.col >= 1 {
+            pos.col - 1
+        } else {
+            return false;
+        };
+        let owner_pos = Pos {
+            row: pos.row,
+            col: owner_col,
+        };
+        if let Some(cell) = self.new_buffer.get_mut(owner_pos) {
+            cell.combining.push(ch);
             true
         } else {
             false
@@ -207,6 +258,9 @@ impl Iterator for ScreenBufIter<'_> {
     type Item = ScreenOp;
 
     fn next(&mut self) -> Option<ScreenOp> {
+        if let Some(ch) = self.pending_combining.pop() {
+            return Some(ScreenOp::Combine(ch));
+        }
         loop {
             let pos = match self.buffer_pos {
                 None => return None,
@@ -235,6 +289,7 @@ impl Iterator for ScreenBufIter<'_> {
                 // 3. Write char
                 self.screen_pos.as_mut().unwrap().col += new_cell.width as Width;
                 self.buffer_pos = self.next_pos(pos, new_cell.width);
+                self.pending_combining = new_cell.combining.iter().rev().copied().collect();
                 return Some(ScreenOp::Print(new_cell.ch, new_cell.width));
             } else if let Some(next_pos) = self.next_pos(pos, new_cell.width) {
                 self.buffer_pos = Some(next_pos);
@@ -264,6 +319,11 @@ mod screen_buf_tests {
         },
         bold: false,
         underlined: false,
+        undercurled: false,
+        underline_color: None,
+        italic: false,
+        dim: false,
+        strikethrough: false,
     };
 
     const STYLE_RED: ConcreteStyle = ConcreteStyle {
@@ -279,6 +339,11 @@ mod screen_buf_tests {
         },
         bold: false,
         underlined: false,
+        undercurled: false,
+        underline_color: None,
+        italic: false,
+        dim: false,
+        strikethrough: false,
     };
 
     const STYLE_GREEN: ConcreteStyle = ConcreteStyle {
@@ -294,6 +359,11 @@ mod screen_buf_tests {
         },
         bold: false,
         underlined: false,
+        undercurled: false,
+        underline_color: None,
+        italic: false,
+        dim: false,
+        strikethrough: false,
     };
 
     fn new_buf(width: Width, height: Height) -> ScreenBuf {
@@ -581,6 +651,91 @@ mod screen_buf_tests {
         );
     }
 
+    #[test]
+    fn test_full_width_at_edge() {
+        let mut buf = new_buf(3, 1);
+        display(&mut buf, "ab", Pos::zero(), STYLE_RED, 1);
+        assert!(buf.display_char('一', Pos { col: 2, row: 0 }, STYLE_RED, 2));
+        let actual_ops = buf.drain_changes().collect::<Vec<_>>();
+        assert_eq!(
+            actual_ops,
+            vec![
+                ScreenOp::Goto(Pos::zero()),
+                ScreenOp::Style(STYLE_RED),
+                ScreenOp::Print('a', 1),
+                ScreenOp::Print('b', 1),
+                ScreenOp::Print(' ', 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_combining() {
+        let mut buf = new_buf(4, 1);
+        assert!(!buf.combine_char('\u{301}', Pos::zero()));
+        assert!(buf.display_char('e', Pos::zero(), STYLE_RED, 1));
+        assert!(buf.combine_char('\u{301}', Pos { col: 1, row: 0 }));
+        assert!(buf.display_char('一', Pos { col: 1, row: 0 }, STYLE_RED, 2));
+        assert!(buf.combine_char('\u{302}', Pos { col: 3, row: 0 }));
+        let actual_ops = buf.drain_changes().collect::<Vec<_>>();
+        assert_eq!(
+            actual_ops,
+            vec![
+                ScreenOp::Goto(Pos::zero()),
+                ScreenOp::Style(STYLE_RED),
+                ScreenOp::Print('e', 1),
+                ScreenOp::Combine('\u{301}'),
+                ScreenOp::Print('一', 2),
+                ScreenOp::Combine('\u{302}'),
+                ScreenOp::Style(STYLE_DEFAULT),
+                ScreenOp::Print(' ', 1),
+            ]
+        );
+
+        // Removing just the accent redraws the character it was attached to.
+        assert!(buf.display_char('e', Pos::zero(), STYLE_RED, 1));
+        assert!(buf.display_char('一', Pos { col: 1, row: 0 }, STYLE_RED, 2));
+        assert!(buf.combine_char('\u{302}', Pos { col: 3, row: 0 }));
+        let actual_ops = buf.drain_changes().collect::<Vec<_>>();
+        assert_eq!(
+            actual_ops,
+            vec![
+                ScreenOp::Goto(Pos::zero()),
+                ScreenOp::Style(STYLE_RED),
+                ScreenOp::Print('e', 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_blank_style() {
+        let mut buf = new_buf(2, 1);
+        display(&mut buf, "x", Pos::zero(), STYLE_RED, 1);
+        buf.drain_changes();
+        buf.set_blank_style(STYLE_GREEN);
+        display(&mut buf, "x", Pos::zero(), STYLE_RED, 1);
+        let actual_ops = buf.drain_changes().collect::<Vec<_>>();
+        assert_eq!(
+            actual_ops,
+            vec![
+                ScreenOp::Goto(Pos::zero()),
+                ScreenOp::Style(STYLE_RED),
+                ScreenOp::Print('x', 1),
+                ScreenOp::Style(STYLE_GREEN),
+                ScreenOp::Print(' ', 1),
+            ]
+        );
+        let actual_ops = buf.drain_changes().collect::<Vec<_>>();
+        assert_eq!(
+            actual_ops,
+            vec![
+                ScreenOp::Goto(Pos::zero()),
+                ScreenOp::Style(STYLE_GREEN),
+                ScreenOp::Print(' ', 1),
+            ]
+        );
+    }
+
     #[test]
     fn test_complex() {
         let mut buf = new_buf(3, 4);
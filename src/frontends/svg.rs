@@ -0,0 +1,146 @@
+//! Export a captured window as an SVG image: each row of the monospace grid becomes `<text>`
+//! elements, drawn over `<rect>`s for any backgrounds that differ from the theme's.
+
+use super::capture::{CaptureWindow, CapturedCell};
+use crate::style::ConcreteStyle;
+
+/// The font size, in pixels.
+const FONT_SIZE: f32 = 14.0;
+/// The width of a cell of the grid, in pixels. Monospace fonts are usually about 0.6em wide.
+const CELL_WIDTH: f32 = 8.4;
+/// The height of a cell of the grid, in pixels.
+const CELL_HEIGHT: f32 = 18.0;
+/// The distance from the top of a cell to the baseline of its text, in pixels.
+const BASELINE: f32 = 14.0;
+/// How opaque dim text is.
+const DIM_OPACITY: f32 = 0.6;
+
+/// A horizontal run of cells that all have the same style.
+struct Run {
+    col: usize,
+    /// The number of cells in the run. Full-width chars take up two.
+    width: usize,
+    text: String,
+    style: ConcreteStyle,
+}
+
+impl CaptureWindow {
+    /// The window's contents as a standalone SVG image, using the theme's background color. Each
+    /// run of text is stretched to exactly fill its cells, so that the grid lines up whatever
+    /// monospace font the viewer has.
+    pub fn to_svg_string(&self) -> String {
+        let blank_style = self.blank_style();
+        let num_cols = self.cells.first().map(|row| row.len()).unwrap_or(0);
+        let width = num_cols as f32 * CELL_WIDTH;
+        let height = self.cells.len() as f32 * CELL_HEIGHT;
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+             viewBox=\"0 0 {width} {height}\" font-family=\"monospace\" \
+             font-size=\"{FONT_SIZE}\">\n"
+        );
+        svg.push_str(&format!(
+            "<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>\n",
+            blank_style.bg_color.to_hex()
+        ));
+        for (row_index, row) in self.cells.iter().enumerate() {
+            let y = row_index as f32 * CELL_HEIGHT;
+            for run in runs(CaptureWindow::trimmed_row(row), blank_style) {
+                let x = run.col as f32 * CELL_WIDTH;
+                let run_width = run.width as f32 * CELL_WIDTH;
+                if run.style.bg_color != blank_style.bg_color {
+                    svg.push_str(&format!(
+                        "<rect x=\"{x}\" y=\"{y}\" width=\"{run_width}\" height=\"{CELL_HEIGHT}\" \
+                         fill=\"{}\"/>\n",
+                        run.style.bg_color.to_hex()
+                    ));
+                }
+                let has_decoration =
+                    run.style.underlined || run.style.undercurled || run.style.strikethrough;
+                if run.text.trim().is_empty() && !has_decoration {
+                    continue;
+                }
+                svg.push_str(&format!(
+                    "<text x=\"{x}\" y=\"{}\" textLength=\"{run_width}\" \
+                     lengthAdjust=\"spacingAndGlyphs\" xml:space=\"preserve\" \
+                     fill=\"{}\"{}>{}</text>\n",
+                    y + BASELINE,
+                    run.style.fg_color.to_hex(),
+                    text_attributes(run.style),
+                    escape_xml(&run.text)
+                ));
+            }
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// Split a row into runs of cells with the same style.
+fn runs(row: &[CapturedCell], blank_style: ConcreteStyle) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    for (col, cell) in row.iter().enumerate() {
+        let (text, style) = match cell {
+            CapturedCell::Blank => (" ", blank_style),
+            CapturedCell::Text(text, style) => (text.as_str(), *style),
+            CapturedCell::Covered => {
+                if let Some(run) = runs.last_mut() {
+                    run.width += 1;
+                }
+                continue;
+            }
+        };
+        match runs.last_mut() {
+            Some(run) if run.style == style => {
+                run.width += 1;
+                run.text.push_str(text);
+            }
+            _ => runs.push(Run {
+                col,
+                width: 1,
+                text: text.to_owned(),
+                style,
+            }),
+        }
+    }
+    runs
+}
+
+/// The SVG attributes for a style's text attributes (but not its colors), each with a leading
+/// space.
+fn text_attributes(style: ConcreteStyle) -> String {
+    let mut attributes = String::new();
+    if style.bold {
+        attributes.push_str(" font-weight=\"bold\"");
+    }
+    if style.italic {
+        attributes.push_str(" font-style=\"italic\"");
+    }
+    if style.dim {
+        attributes.push_str(&format!(" opacity=\"{DIM_OPACITY}\""));
+    }
+    let mut decorations = Vec::new();
+    if style.underlined || style.undercurled {
+        decorations.push("underline");
+    }
+    if style.strikethrough {
+        decorations.push("line-through");
+    }
+    if !decorations.is_empty() {
+        attributes.push_str(&format!(" text-decoration=\"{}\"", decorations.join(" ")));
+    }
+    attributes
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
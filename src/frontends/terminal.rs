@@ -1,20 +1,27 @@
 //! Render to and receive events from a terminal emulator.
 
-use super::frontend::{Event, Frontend, Key, KeyCode, KeyModifiers, MouseButton, MouseEvent};
+use super::frontend::{
+    Event, Frontend, Key, KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
 use super::screen_buf::{ScreenBuf, ScreenOp};
-use crate::style::{ColorTheme, Rgb, Style};
+use crate::style::{ColorTheme, ConcreteStyle, Rgb, Style};
+use crate::tree::NodeId;
 
 use partial_pretty_printer::pane::PrettyWindow;
 use partial_pretty_printer::{Col, Height, Pos, Row, Size};
 
 use std::io::{self, stdout, Write};
 use std::time::{Duration, Instant};
+use unicode_width::UnicodeWidthChar;
 
 use crossterm::cursor;
-use crossterm::event as ct_event;
+use crossterm::event::{
+    self as ct_event, DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste,
+    EnableMouseCapture,
+};
 use crossterm::style::{
     Attribute, Attributes, Color, ResetColor, SetAttribute, SetAttributes, SetBackgroundColor,
-    SetForegroundColor,
+    SetForegroundColor, SetUnderlineColor,
 };
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, size as ct_size, BeginSynchronizedUpdate,
@@ -30,6 +37,10 @@ pub struct Terminal {
     buf: ScreenBuf,
     /// Where to place the terminal cursor. If `None`, hide the cursor.
     focus_pos: Option<Pos>,
+    /// Where the terminal cursor was placed in the last frame that was sent to the terminal.
+    shown_focus_pos: Option<Pos>,
+    /// The id of the node drawn in each cell of the current frame, stored row by row.
+    node_ids: Vec<Option<NodeId>>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -45,15 +56,27 @@ impl Terminal {
     pub fn new(theme: ColorTheme) -> Result<Terminal, TerminalError> {
         let default_concrete_style = theme.concrete_style(&Style::default());
 
+        let size = Terminal::terminal_window_size()?;
         let mut term = Terminal {
             color_theme: theme,
-            buf: ScreenBuf::new(Terminal::terminal_window_size()?, default_concrete_style),
+            buf: ScreenBuf::new(size, default_concrete_style),
             focus_pos: None,
+            shown_focus_pos: None,
+            node_ids: vec![None; size.width as usize * size.height as usize],
         };
         term.enter()?;
         Ok(term)
     }
 
+    fn node_id_index(&self, pos: Pos) -> Option<usize> {
+        let size = self.buf.size();
+        if pos.col < size.width && pos.row < size.height {
+            Some(pos.row as usize * size.width as usize + pos.col as usize)
+        } else {
+            None
+        }
+    }
+
     /// Get the current size of the actual terminal window in characters. This may be different
     /// than the current size of the ScreenBuf.
     fn terminal_window_size() -> Result<Size, TerminalError> {
@@ -69,6 +92,8 @@ impl Terminal {
         enable_raw_mode()?;
         stdout()
             .queue(EnterAlternateScreen)?
+            .queue(EnableMouseCapture)?
+            .queue(EnableBracketedPaste)?
             .queue(cursor::SetCursorStyle::SteadyBar)?
             .queue(cursor::Hide)?;
         stdout().flush()
@@ -78,6 +103,8 @@ impl Terminal {
     fn exit(&mut self) -> Result<(), io::Error> {
         disable_raw_mode()?;
         stdout()
+            .queue(DisableMouseCapture)?
+            .queue(DisableBracketedPaste)?
             .queue(LeaveAlternateScreen)?
             .queue(cursor::SetCursorStyle::DefaultUserShape)?
             .queue(cursor::Show)?
@@ -104,13 +131,27 @@ impl PrettyWindow for Terminal {
         style: &Self::Style,
         full_width: bool,
     ) -> Result<(), Self::Error> {
+        if ch.width() == Some(0) {
+            // The terminal will draw a zero-width character (like an accent) on top of the
+            // preceding character, instead of advancing the cursor.
+            let _ = self.buf.combine_char(ch, pos);
+            return Ok(());
+        }
         let width = if full_width { 2 } else { 1 };
         let concrete_style = self.color_theme.concrete_style(style);
-        if self.buf.display_char(ch, pos, concrete_style, width) {
-            Ok(())
-        } else {
-            Err(TerminalError::OutOfBounds)
+        if !self.buf.display_char(ch, pos, concrete_style, width) {
+            return Err(TerminalError::OutOfBounds);
+        }
+        for i in 0..width {
+            let cell_pos = Pos {
+                row: pos.row,
+                col: pos.col + i as Col,
+            };
+            if let Some(index) = self.node_id_index(cell_pos) {
+                self.node_ids[index] = style.node;
+            }
         }
+        Ok(())
     }
 
     fn set_focus(&mut self, pos: Pos) -> Result<(), Self::Error> {
@@ -127,6 +168,10 @@ impl Frontend for Terminal {
         Ok(())
     }
 
+    fn color_theme(&self) -> &ColorTheme {
+        &self.color_theme
+    }
+
     fn next_event(&mut self, timeout: Duration) -> Result<Option<Event>, TerminalError> {
         let deadline = Instant::now() + timeout;
         let mut remaining = timeout;
@@ -153,6 +198,9 @@ impl Frontend for Terminal {
         if size != self.buf.size() {
             self.buf.resize(size);
         }
+        self.node_ids.clear();
+        self.node_ids
+            .resize(size.width as usize * size.height as usize, None);
         Ok(())
     }
 
@@ -161,35 +209,29 @@ impl Frontend for Terminal {
             cursor::MoveTo(pos.col, pos.row as u16)
         }
 
+        let focus_pos = self.focus_pos.take();
+        let mut changes = self.buf.drain_changes().peekable();
+        if changes.peek().is_none() && focus_pos == self.shown_focus_pos {
+            // Nothing changed since the last frame, so don't send anything to the terminal.
+            return Ok(());
+        }
+        self.shown_focus_pos = focus_pos;
+
         let mut out = stdout().lock();
         out.queue(BeginSynchronizedUpdate)?;
 
-        for op in self.buf.drain_changes() {
+        for op in changes {
             match op {
                 // Assuming that ppp and the terminal agree about char width!
                 ScreenOp::Print(ch, _) => write!(out, "{}", ch)?,
+                ScreenOp::Combine(ch) => write!(out, "{}", ch)?,
                 ScreenOp::Goto(pos) => {
                     out.queue(move_to(pos))?;
                 }
-                ScreenOp::Style(style) => {
-                    let mut attributes = Attributes::default();
-                    if style.bold {
-                        attributes.set(Attribute::Bold);
-                    } else {
-                        attributes.set(Attribute::NormalIntensity);
-                    }
-                    if style.underlined {
-                        attributes.set(Attribute::Underlined);
-                    } else {
-                        attributes.set(Attribute::NoUnderline);
-                    }
-                    out.queue(SetAttributes(attributes))?;
-                    out.queue(SetForegroundColor(style.fg_color.into()))?;
-                    out.queue(SetBackgroundColor(style.bg_color.into()))?;
-                }
+                ScreenOp::Style(style) => queue_style(&mut out, style)?,
             }
         }
-        if let Some(pos) = self.focus_pos.take() {
+        if let Some(pos) = focus_pos {
             out.queue(move_to(pos))?;
             out.queue(cursor::Show)?;
         } else {
@@ -200,6 +242,62 @@ impl Frontend for Terminal {
         out.flush()?;
         Ok(())
     }
+
+    fn node_at(&self, pos: Pos) -> Option<NodeId> {
+        self.node_ids[self.node_id_index(pos)?]
+    }
+
+    /// Uses the OSC 52 escape sequence, which most terminal emulators support (though some only
+    /// once it's enabled in their settings). It also works over ssh.
+    fn copy_to_system_clipboard(&mut self, text: &str) -> Result<(), TerminalError> {
+        let mut out = stdout().lock();
+        write!(out, "\x1b]52;c;{}\x07", base64_encode(text.as_bytes()))?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Queue the escape codes that switch the terminal to `style`.
+pub(super) fn queue_style(out: &mut impl Write, style: ConcreteStyle) -> io::Result<()> {
+    let mut attributes = Attributes::default();
+    // Bold and dim share a single "normal intensity" reset.
+    if !style.bold && !style.dim {
+        attributes.set(Attribute::NormalIntensity);
+    }
+    if style.bold {
+        attributes.set(Attribute::Bold);
+    }
+    if style.dim {
+        attributes.set(Attribute::Dim);
+    }
+    if style.undercurled {
+        attributes.set(Attribute::Undercurled);
+    } else if style.underlined {
+        attributes.set(Attribute::Underlined);
+    } else {
+        attributes.set(Attribute::NoUnderline);
+    }
+    if style.italic {
+        attributes.set(Attribute::Italic);
+    } else {
+        attributes.set(Attribute::NoItalic);
+    }
+    if style.strikethrough {
+        attributes.set(Attribute::CrossedOut);
+    } else {
+        attributes.set(Attribute::NotCrossedOut);
+    }
+    out.queue(SetAttributes(attributes))?;
+    out.queue(SetForegroundColor(style.fg_color.into()))?;
+    out.queue(SetBackgroundColor(style.bg_color.into()))?;
+    // Terminals that don't support underline colors ignore this.
+    out.queue(SetUnderlineColor(
+        style
+            .underline_color
+            .map(Color::from)
+            .unwrap_or(Color::Reset),
+    ))?;
+    Ok(())
 }
 
 impl Drop for Terminal {
@@ -209,6 +307,25 @@ impl Drop for Terminal {
     }
 }
 
+/// Encode `bytes` in standard base64, with padding.
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let byte = |i: usize| chunk.get(i).copied().unwrap_or(0) as u32;
+        let n = (byte(0) << 16) | (byte(1) << 8) | byte(2);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
 /// Converts synless's `Rgb` to crossterm's `Color`
 impl From<Rgb> for Color {
     fn from(rgb: Rgb) -> Color {
@@ -240,22 +357,25 @@ impl TryInto<MouseEvent> for ct_event::MouseEvent {
 
     /// Returns `Err` if the event is irrelevant to us.
     fn try_into(self) -> Result<MouseEvent, ()> {
-        if let ct_event::MouseEventKind::Down(ct_button) = self.kind {
-            let button = match ct_button {
-                ct_event::MouseButton::Left => MouseButton::Left,
-                ct_event::MouseButton::Right => MouseButton::Right,
-                ct_event::MouseButton::Middle => MouseButton::Middle,
-            };
-            Ok(MouseEvent {
-                click_pos: Pos {
-                    row: self.row as Row,
-                    col: self.column as Col,
-                },
-                button,
-            })
-        } else {
-            Err(())
-        }
+        let (kind, ct_button) = match self.kind {
+            ct_event::MouseEventKind::Down(ct_button) => (MouseEventKind::Press, ct_button),
+            ct_event::MouseEventKind::Drag(ct_button) => (MouseEventKind::Drag, ct_button),
+            ct_event::MouseEventKind::Up(ct_button) => (MouseEventKind::Release, ct_button),
+            _ => return Err(()),
+        };
+        let button = match ct_button {
+            ct_event::MouseButton::Left => MouseButton::Left,
+            ct_event::MouseButton::Right => MouseButton::Right,
+            ct_event::MouseButton::Middle => MouseButton::Middle,
+        };
+        Ok(MouseEvent {
+            pos: Pos {
+                row: self.row as Row,
+                col: self.column as Col,
+            },
+            button,
+            kind,
+        })
     }
 }
 
@@ -289,10 +409,10 @@ impl TryInto<Key> for ct_event::KeyEvent {
             ct_event::KeyCode::Insert => KeyCode::Insert,
             ct_event::KeyCode::F(num) => KeyCode::F(num),
             ct_event::KeyCode::Char(c) => {
-                if c.is_uppercase() {
-                    // Remove redundant "shift", for normalization.
-                    modifiers.shift = false;
-                }
+                // Remove redundant "shift", for normalization. The character already reflects
+                // it. This isn't limited to uppercase letters because some platforms (notably
+                // Windows) also report shift for symbols like `?` and `{`.
+                modifiers.shift = false;
                 KeyCode::Char(c)
             }
             ct_event::KeyCode::Esc => KeyCode::Esc,
@@ -3,7 +3,7 @@
 use crate::frontends::Key;
 use crate::language::Storage;
 use crate::tree::Node;
-use crate::util::{bug_assert, error, OrderedMap, SynlessBug};
+use crate::util::{error, OrderedMap, SynlessBug};
 
 const KEYHINTS_LANGUAGE_NAME: &str = "keyhints";
 
@@ -15,11 +15,13 @@ const KEYHINTS_LANGUAGE_NAME: &str = "keyhints";
 #[derive(Debug, Clone)]
 pub enum Candidate {
     /// A candidate created from [`bind_key_for_special_candidate`].
-    Special { display: String },
+    Special { display: String, key: Option<Key> },
     /// A candidate created from [`add_regular_candidate`].
     Regular {
         display: String,
         value: rhai::Dynamic,
+        annotation: String,
+        key: Option<Key>,
     },
     /// A new candidate created from the custom string the user typed.
     Custom { input: String },
@@ -32,16 +34,24 @@ impl Candidate {
         }
     }
 
-    fn new_regular(display: &str, value: &rhai::Dynamic) -> Candidate {
+    fn new_regular(
+        display: &str,
+        value: &rhai::Dynamic,
+        annotation: &str,
+        key: Option<Key>,
+    ) -> Candidate {
         Candidate::Regular {
             display: display.to_owned(),
             value: value.to_owned(),
+            annotation: annotation.to_owned(),
+            key,
         }
     }
 
-    fn new_special(display: &str) -> Candidate {
+    fn new_special(display: &str, key: Option<Key>) -> Candidate {
         Candidate::Special {
             display: display.to_owned(),
+            key,
         }
     }
 
@@ -49,12 +59,32 @@ impl Candidate {
         use Candidate::{Custom, Regular, Special};
 
         match self {
-            Special { display } => display,
+            Special { display, .. } => display,
             Regular { display, .. } => display,
             Custom { input } => input,
         }
     }
 
+    /// The key that selects this candidate from anywhere in the menu, if any.
+    pub fn key(&self) -> Option<Key> {
+        use Candidate::{Custom, Regular, Special};
+
+        match self {
+            Special { key, .. } | Regular { key, .. } => *key,
+            Custom { .. } => None,
+        }
+    }
+
+    /// Extra information to display alongside the candidate. Empty if there is none.
+    pub fn annotation(&self) -> &str {
+        use Candidate::{Custom, Regular, Special};
+
+        match self {
+            Regular { annotation, .. } => annotation,
+            Special { .. } | Custom { .. } => "",
+        }
+    }
+
     fn value(&self) -> Option<rhai::Dynamic> {
         use Candidate::{Custom, Regular, Special};
 
@@ -81,24 +111,99 @@ struct KeyProgSpec {
     hint: String,
     close_menu: bool,
     prog: rhai::FnPtr,
+    kind: BindingKind,
+}
+
+/// How a general binding combines with the bindings around it. See "Operators and Motions" in the
+/// [`Keymap`] docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BindingKind {
+    Command,
+    Operator,
+    Motion,
 }
 
 #[derive(Debug, Clone)]
 pub struct KeyProg {
     close_menu: bool,
     prog: rhai::FnPtr,
+    /// If this is a motion that was typed after an operator, the operator's program. It should be
+    /// applied to whatever the motion moves over.
+    operator: Option<rhai::FnPtr>,
+    kind: BindingKind,
+    /// A description of what the program does, for the command history: the binding's hint,
+    /// followed by the selected candidate if it's used.
+    label: String,
+    /// Whether this is deferred work, like a timer's callback, instead of a key's program.
+    deferred: bool,
 }
 
 impl KeyProgSpec {
     // If this KeyProgSpec is from a general binding, `candidate` should be None.
     fn to_key_prog(&self, candidate: Option<&Candidate>) -> KeyProg {
         let mut prog = self.prog.clone();
-        if let Some(arg) = candidate.and_then(|candidate| candidate.value()) {
-            prog.add_curry(arg);
+        let mut label = self.hint.clone();
+        if let Some(candidate) = candidate {
+            if let Some(arg) = candidate.value() {
+                prog.add_curry(arg);
+            }
+            label = format!("{} {}", label, candidate.display_str());
         }
         KeyProg {
             close_menu: self.close_menu,
             prog,
+            operator: None,
+            kind: self.kind,
+            label,
+            deferred: false,
+        }
+    }
+}
+
+impl KeyProg {
+    /// Work that's ready to be done in place of a key's program, like the callback of a timer
+    /// that's gone off.
+    pub fn deferred(prog: rhai::FnPtr, label: &str) -> KeyProg {
+        KeyProg {
+            close_menu: false,
+            prog,
+            operator: None,
+            kind: BindingKind::Command,
+            label: label.to_owned(),
+            deferred: true,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Whether this is an operator, that should wait for a motion to apply it to.
+    pub fn is_operator(&self) -> bool {
+        self.kind == BindingKind::Operator
+    }
+
+    pub fn is_motion(&self) -> bool {
+        self.kind == BindingKind::Motion
+    }
+
+    /// Combine a motion with the operator that was typed before it, like `d` followed by `j`.
+    pub fn with_operator(self, operator: KeyProg) -> KeyProg {
+        KeyProg {
+            close_menu: self.close_menu,
+            label: format!("{} {}", operator.label, self.label),
+            prog: self.prog,
+            operator: Some(operator.prog),
+            kind: BindingKind::Command,
+            deferred: false,
+        }
+    }
+
+    /// Apply an operator to just the node at the cursor, like `d` followed by another `d`.
+    pub fn operator_on_cursor(self) -> KeyProg {
+        KeyProg {
+            kind: BindingKind::Command,
+            ..self
         }
     }
 }
@@ -116,10 +221,32 @@ impl rhai::CustomType for KeyProg {
                 "prog",
                 |kp: &mut KeyProg| -> rhai::FnPtr { kp.prog.clone() },
                 |kp: &mut KeyProg, prog: rhai::FnPtr| kp.prog = prog,
-            );
+            )
+            .with_get("deferred", |kp: &mut KeyProg| -> bool { kp.deferred })
+            .with_get("operator", |kp: &mut KeyProg| -> rhai::Dynamic {
+                match &kp.operator {
+                    Some(operator) => rhai::Dynamic::from(operator.clone()),
+                    None => rhai::Dynamic::UNIT,
+                }
+            });
     }
 }
 
+/**********
+ * Prefix *
+ **********/
+
+/// The first key of one or more key sequences, like the `g` in `g g`. Pressing it waits for the
+/// next key, which is looked up in `keymap`.
+#[derive(Debug, Clone, Default)]
+struct Prefix {
+    hint: String,
+    keymap: Keymap,
+}
+
+/// The hint shown for a prefix key, unless it's given one with [`Keymap::name_prefix()`].
+const DEFAULT_PREFIX_HINT: &str = "+prefix";
+
 /**********
  * KeyMap *
  **********/
@@ -213,6 +340,41 @@ impl rhai::CustomType for KeyProg {
 ///
 /// - If you add a general binding and candidate-specific binding with the same key, the
 ///   candidate-specific binding takes priority.
+///
+/// - A key given to a candidate with [`Keymap::bind_candidate_key()`] takes priority over all
+///   bindings. Since plain characters are used to filter the candidates, such keys should
+///   usually have a modifier.
+///
+/// - A key can't be both bound on its own and used as the first key of a sequence. Whichever
+///   binding was added last wins.
+///
+/// ### Key Sequences
+///
+/// General bindings can be for a sequence of keys, like `g g`, with
+/// [`Keymap::bind_key_sequence()`]. The first key becomes a _prefix_: pressing it shows the keys
+/// that can follow it in the key hints pane, and waits for one of them to be pressed. The
+/// [`LayerManager`](super::LayerManager) keeps track of the keys pressed so far.
+///
+/// ### Operators and Motions
+///
+/// An _operator_ (added with [`Keymap::bind_operator()`]), like "cut", doesn't run right away.
+/// Instead it waits for the user to type a _motion_ (added with [`Keymap::bind_motion()`]), like
+/// "next sibling", and is then applied to the nodes that the motion moves over. Typing the
+/// operator's key again applies it to just the node at the cursor. Any other key cancels it. This
+/// way each operator works with every motion, without binding every pair.
+///
+/// ### Candidate Columns
+///
+/// Each candidate is shown alongside the key that selects it (if any), and its annotation (if
+/// any), in columns that the menu pane lines up with the candidate list:
+///
+/// ```text
+///     +--------------------------+
+///     |      prompt>             |
+///     | A-1  * baz.rs      2.1kb |
+///     | A-2  * foobar.rs     9kb |
+///     +--------------------------+
+/// ```
 #[derive(Debug, Clone, Default)]
 pub struct Keymap {
     /// If the user types `Key`, execute `KeyProgSpec`.
@@ -222,12 +384,17 @@ pub struct Keymap {
     /// If the user types `Key` while any of `regular_candidates` is selected, invoke `KeyProgSpec`
     /// with the regular candidate's `rhai::Dynamic`.
     regular_bindings: OrderedMap<Key, KeyProgSpec>,
-    /// The set of regular candidates. Each has a display label and a value.
+    /// The set of regular candidates. Each has a display label, a value, and an annotation.
     // TODO: Regular candidate insertion is quadratic. Make an efficient OrderedSet instead.
-    regular_candidates: Vec<(String, rhai::Dynamic)>,
+    regular_candidates: Vec<(String, rhai::Dynamic, String)>,
+    /// If the user types `Key`, select the regular or special candidate with display label
+    /// `String` and run its first binding.
+    candidate_keys: OrderedMap<Key, String>,
     /// If the user types `Key` while a custom candidate is selected, invoke `KeyProgSpec` with the
     /// user's input string.
     custom_bindings: OrderedMap<Key, KeyProgSpec>,
+    /// If the user types `Key`, wait for the next key and look it up in the prefix's keymap.
+    prefixes: OrderedMap<Key, Prefix>,
 }
 
 impl Keymap {
@@ -242,6 +409,9 @@ impl Keymap {
     /// Take the union of the two keymaps, with bindings in `other` overriding those in `self`.
     pub fn append(&mut self, other: Keymap) {
         // general_bindings
+        for key in other.general_bindings.keys() {
+            self.prefixes.remove(key);
+        }
         self.general_bindings.append(other.general_bindings);
 
         // special_bindings
@@ -257,12 +427,28 @@ impl Keymap {
         self.regular_bindings.append(other.regular_bindings);
 
         // regular candidatess
-        for (display, value) in other.regular_candidates {
-            self.add_regular_candidate(display, value);
+        for (display, value, annotation) in other.regular_candidates {
+            self.add_regular_candidate(display, value, annotation);
         }
 
+        // candidate_keys
+        self.candidate_keys.append(other.candidate_keys);
+
         // custom_bindings
         self.custom_bindings.append(other.custom_bindings);
+
+        // prefixes
+        for (key, prefix) in other.prefixes {
+            self.general_bindings.remove(&key);
+            if let Some(self_prefix) = self.prefixes.get_mut(&key) {
+                if prefix.hint != DEFAULT_PREFIX_HINT {
+                    self_prefix.hint = prefix.hint;
+                }
+                self_prefix.keymap.append(prefix.keymap);
+            } else {
+                self.prefixes.insert(key, prefix);
+            }
+        }
     }
 
     /****************
@@ -272,16 +458,102 @@ impl Keymap {
     /// Add a general binding: if the user types `key`, execute `prog`, potentially after exiting
     /// the menu.  Use `hint` when displaying this binding.
     pub fn bind_key(&mut self, key: Key, hint: String, prog: rhai::FnPtr, close_menu: bool) {
+        self.prefixes.remove(&key);
         self.general_bindings.insert(
             key,
             KeyProgSpec {
                 hint,
                 close_menu,
                 prog,
+                kind: BindingKind::Command,
             },
         );
     }
 
+    /// Add a general binding for a sequence of keys: if the user types each of `keys` in order,
+    /// execute `prog`. Every key but the last becomes a prefix. Does nothing if `keys` is empty.
+    pub fn bind_key_sequence(
+        &mut self,
+        keys: &[Key],
+        hint: String,
+        prog: rhai::FnPtr,
+        close_menu: bool,
+    ) {
+        let spec = KeyProgSpec {
+            hint,
+            close_menu,
+            prog,
+            kind: BindingKind::Command,
+        };
+        self.bind_spec_sequence(keys, spec);
+    }
+
+    /// Bind an operator to a sequence of keys. When it's typed, nothing happens until the user
+    /// types a motion; then `prog` is applied to what the motion moves over.
+    pub fn bind_operator(&mut self, keys: &[Key], hint: String, prog: rhai::FnPtr) {
+        let spec = KeyProgSpec {
+            hint,
+            close_menu: true,
+            prog,
+            kind: BindingKind::Operator,
+        };
+        self.bind_spec_sequence(keys, spec);
+    }
+
+    /// Bind a motion to a sequence of keys. It runs `prog` when typed on its own, and can also
+    /// follow an operator.
+    pub fn bind_motion(&mut self, keys: &[Key], hint: String, prog: rhai::FnPtr) {
+        let spec = KeyProgSpec {
+            hint,
+            close_menu: true,
+            prog,
+            kind: BindingKind::Motion,
+        };
+        self.bind_spec_sequence(keys, spec);
+    }
+
+    fn bind_spec_sequence(&mut self, keys: &[Key], spec: KeyProgSpec) {
+        match keys {
+            [] => (),
+            [key] => {
+                self.prefixes.remove(key);
+                self.general_bindings.insert(*key, spec);
+            }
+            [first, rest @ ..] => {
+                self.general_bindings.remove(first);
+                if !self.prefixes.contains_key(first) {
+                    self.prefixes.insert(
+                        *first,
+                        Prefix {
+                            hint: DEFAULT_PREFIX_HINT.to_owned(),
+                            keymap: Keymap::new(),
+                        },
+                    );
+                }
+                self.prefixes[first].keymap.bind_spec_sequence(rest, spec);
+            }
+        }
+    }
+
+    /// Set the hint shown for the prefix `keys` (like `g`, or `space f`), which must already be
+    /// the start of some key sequence binding. Returns `false` and does nothing if it isn't.
+    pub fn name_prefix(&mut self, keys: &[Key], hint: String) -> bool {
+        match keys {
+            [] => false,
+            [key] => match self.prefixes.get_mut(key) {
+                Some(prefix) => {
+                    prefix.hint = hint;
+                    true
+                }
+                None => false,
+            },
+            [first, rest @ ..] => match self.prefixes.get_mut(first) {
+                Some(prefix) => prefix.keymap.name_prefix(rest, hint),
+                None => false,
+            },
+        }
+    }
+
     /// Add a special binding: if the user types `key` while `candidate` is selected, execute
     /// `prog`, potentially after exiting the menu. Use `hint` when displaying this binding.
     pub fn bind_key_for_special_candidate(
@@ -296,6 +568,7 @@ impl Keymap {
             hint,
             close_menu,
             prog,
+            kind: BindingKind::Command,
         };
         if !self.special_bindings.contains_key(&candidate) {
             self.special_bindings
@@ -305,15 +578,29 @@ impl Keymap {
     }
 
     /// Add a regular candidate to the list of candidates (used together with
-    /// [`Keymap::bind_key_for_regular_candidates`]).
-    pub fn add_regular_candidate(&mut self, display: String, value: rhai::Dynamic) {
-        for (existing_display, existing_value) in &mut self.regular_candidates {
+    /// [`Keymap::bind_key_for_regular_candidates`]). The `annotation` is displayed alongside it,
+    /// and may be empty.
+    pub fn add_regular_candidate(
+        &mut self,
+        display: String,
+        value: rhai::Dynamic,
+        annotation: String,
+    ) {
+        for (existing_display, existing_value, existing_annotation) in &mut self.regular_candidates
+        {
             if existing_display == &display {
                 *existing_value = value;
+                *existing_annotation = annotation;
                 return;
             }
         }
-        self.regular_candidates.push((display, value));
+        self.regular_candidates.push((display, value, annotation));
+    }
+
+    /// If the user types `key`, select the regular or special candidate whose display label is
+    /// `candidate`, and execute its first binding.
+    pub fn bind_candidate_key(&mut self, key: Key, candidate: String) {
+        self.candidate_keys.insert(key, candidate);
     }
 
     /// If the user types `Key` while one of the regular candidates is selected, pass that
@@ -332,6 +619,7 @@ impl Keymap {
                 hint,
                 close_menu,
                 prog,
+                kind: BindingKind::Command,
             },
         );
     }
@@ -352,6 +640,7 @@ impl Keymap {
                 hint,
                 close_menu,
                 prog,
+                kind: BindingKind::Command,
             },
         );
     }
@@ -370,17 +659,22 @@ impl Keymap {
         let regular_iter = self
             .regular_candidates
             .iter()
-            .map(|(display, value)| Candidate::new_regular(display, value));
+            .map(|(display, value, annotation)| {
+                Candidate::new_regular(display, value, annotation, self.candidate_key(display))
+            });
         let special_iter = self
             .special_bindings
             .keys()
-            .map(|display| Candidate::new_special(display));
+            .map(|display| Candidate::new_special(display, self.candidate_key(display)));
 
         regular_iter.chain(special_iter)
     }
 
     /// Returns the program to execute if `key` is pressed while `candidate` is selected.
     pub fn lookup(&self, key: Key, mut candidate: Option<&Candidate>) -> Option<KeyProg> {
+        if let Some(key_prog) = self.lookup_candidate_key(key) {
+            return Some(key_prog);
+        }
         for (bound_key, keyprog, use_candidate) in self.available_keys_impl(candidate) {
             if bound_key == key {
                 if !use_candidate {
@@ -393,12 +687,69 @@ impl Keymap {
     }
 
     /// Iterates over all `(key, hint)` pairs that are available, given that `candidate` is selected.
+    /// This includes prefix keys.
     pub fn available_keys(
         &self,
         candidate: Option<&Candidate>,
     ) -> impl Iterator<Item = (Key, &str)> + '_ {
+        let prefix_iter = self
+            .prefixes
+            .iter()
+            .map(|(key, prefix)| (*key, prefix.hint.as_ref()));
         self.available_keys_impl(candidate)
             .map(|(key, keyprog, _)| (key, keyprog.hint.as_ref()))
+            .chain(prefix_iter)
+    }
+
+    /// Whether `key` is the first key of a key sequence binding.
+    pub fn is_prefix(&self, key: Key) -> bool {
+        self.prefixes.contains_key(&key)
+    }
+
+    /// The keymap to look up the key after the sequence `keys` in, if `keys` is a prefix. Returns
+    /// `self` if `keys` is empty.
+    pub fn prefix_keymap(&self, keys: &[Key]) -> Option<&Keymap> {
+        match keys {
+            [] => Some(self),
+            [first, rest @ ..] => self.prefixes.get(first)?.keymap.prefix_keymap(rest),
+        }
+    }
+
+    /// Whether any motion can be typed with this keymap, possibly after a prefix.
+    fn has_motions(&self) -> bool {
+        self.general_bindings
+            .iter()
+            .any(|(_, spec)| spec.kind == BindingKind::Motion)
+            || self
+                .prefixes
+                .iter()
+                .any(|(_, prefix)| prefix.keymap.has_motions())
+    }
+
+    fn candidate_key(&self, display: &str) -> Option<Key> {
+        self.candidate_keys
+            .iter()
+            .find(|(_, candidate)| candidate.as_str() == display)
+            .map(|(key, _)| *key)
+    }
+
+    /// If `key` selects a candidate, returns that candidate's first binding.
+    fn lookup_candidate_key(&self, key: Key) -> Option<KeyProg> {
+        let display = self.candidate_keys.get(&key)?;
+        let candidate = if let Some((_, value, annotation)) = self
+            .regular_candidates
+            .iter()
+            .find(|(regular_display, _, _)| regular_display == display)
+        {
+            Candidate::new_regular(display, value, annotation, Some(key))
+        } else if self.special_bindings.contains_key(display) {
+            Candidate::new_special(display, Some(key))
+        } else {
+            return None;
+        };
+        self.available_keys_impl(Some(&candidate))
+            .find(|(_, _, use_candidate)| *use_candidate)
+            .map(|(_, keyprog, _)| keyprog.to_key_prog(Some(&candidate)))
     }
 
     // Returns iterator of (key, prog_to_run_if_pressed, use_candidate)
@@ -409,7 +760,7 @@ impl Keymap {
         use Candidate::{Custom, Regular, Special};
 
         let ordered_map_opt = candidate.map(|candidate| match candidate {
-            Special { display } => &self.special_bindings[display],
+            Special { display, .. } => &self.special_bindings[display],
             Regular { .. } => &self.regular_bindings,
             Custom { .. } => &self.custom_bindings,
         });
@@ -429,25 +780,92 @@ impl Keymap {
      * KeyHint Doc *
      ***************/
 
-    pub fn make_keyhint_doc(&self, s: &mut Storage, candidate: Option<&Candidate>) -> Node {
+    /// Make a doc listing the available keys, for the key hints pane. `pending_keys` are the keys
+    /// typed so far of a key sequence that this keymap continues, if any. If `motions_only`, an
+    /// operator is waiting for a motion, so only motions (and prefixes that lead to them) are
+    /// listed.
+    ///
+    /// The keys are sorted, and grouped into: bindings for the selected candidate, general
+    /// bindings, and prefixes. Group headers are only shown if there's more than one group.
+    pub fn make_keyhint_doc(
+        &self,
+        s: &mut Storage,
+        candidate: Option<&Candidate>,
+        pending_keys: &[Key],
+        motions_only: bool,
+    ) -> Node {
         // Lookup SelectionMenu language and constructs
         let lang = s
             .language(KEYHINTS_LANGUAGE_NAME)
             .bug_msg("Missing keyhints lang");
         let c_root = lang.root_construct(s);
         let c_entry = lang.construct(s, "Entry").bug();
+        let c_header = lang.construct(s, "Header").bug();
         let c_key = lang.construct(s, "Key").bug();
         let c_hint = lang.construct(s, "Hint").bug();
 
+        // Group the (key, hint) entries. If a key has multiple bindings, only the one that
+        // `lookup()` would use is shown.
+        let mut candidate_entries = Vec::new();
+        let mut general_entries = Vec::new();
+        for (key, keyprog, use_candidate) in self.available_keys_impl(candidate) {
+            if motions_only && keyprog.kind != BindingKind::Motion {
+                continue;
+            }
+            let is_shadowed = candidate_entries
+                .iter()
+                .chain(&general_entries)
+                .any(|(k, _)| *k == key);
+            if !is_shadowed {
+                let entries = if use_candidate {
+                    &mut candidate_entries
+                } else {
+                    &mut general_entries
+                };
+                entries.push((key, keyprog.hint.as_str()));
+            }
+        }
+        let prefix_entries = self
+            .prefixes
+            .iter()
+            .filter(|(_, prefix)| !motions_only || prefix.keymap.has_motions())
+            .map(|(key, prefix)| (*key, prefix.hint.as_str()))
+            .collect::<Vec<_>>();
+        let mut groups = [
+            ("Selection", candidate_entries),
+            ("Keys", general_entries),
+            ("Prefixes", prefix_entries),
+        ];
+        let show_headers = groups.iter().filter(|(_, e)| !e.is_empty()).count() > 1;
+
         // Construct root node
         let root = Node::new(s, c_root);
 
-        // Add (key, hint) entries
-        for (key, hint) in self.available_keys(candidate) {
-            let key_node = Node::with_text(s, c_key, key.to_string()).bug();
-            let hint_node = Node::with_text(s, c_hint, hint.to_owned()).bug();
-            let entry_node = Node::with_children(s, c_entry, [key_node, hint_node]).bug();
-            bug_assert!(root.insert_last_child(s, entry_node));
+        if !pending_keys.is_empty() {
+            let keys = pending_keys
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let header_node = Node::with_text(s, c_header, format!("{keys} …")).bug();
+            root.insert_last_child(s, header_node).bug();
+        }
+
+        for (group_name, entries) in &mut groups {
+            if entries.is_empty() {
+                continue;
+            }
+            if show_headers {
+                let header_node = Node::with_text(s, c_header, (*group_name).to_owned()).bug();
+                root.insert_last_child(s, header_node).bug();
+            }
+            entries.sort_by_key(|(key, _)| *key);
+            for (key, hint) in entries.iter() {
+                let key_node = Node::with_text(s, c_key, key.to_string()).bug();
+                let hint_node = Node::with_text(s, c_hint, (*hint).to_owned()).bug();
+                let entry_node = Node::with_children(s, c_entry, [key_node, hint_node]).bug();
+                root.insert_last_child(s, entry_node).bug();
+            }
         }
 
         root
@@ -462,6 +880,19 @@ impl rhai::CustomType for Keymap {
             Key::from_str(key_str).map_err(|err| error!(Keymap, "{err}: {key_str}").into())
         }
 
+        /// Parse a whitespace-separated key sequence, like `"g g"` or `"space f s"`.
+        fn parse_keys(keys_str: &str) -> Result<Vec<Key>, Box<rhai::EvalAltResult>> {
+            let keys = keys_str
+                .split_whitespace()
+                .map(parse_key)
+                .collect::<Result<Vec<_>, _>>()?;
+            if keys.is_empty() {
+                Err(error!(Keymap, "Empty key sequence").into())
+            } else {
+                Ok(keys)
+            }
+        }
+
         // TODO add the other bind methods
         builder
             .with_name("Keymap")
@@ -473,7 +904,7 @@ impl rhai::CustomType for Keymap {
                  hint: String,
                  prog: rhai::FnPtr|
                  -> Result<(), Box<rhai::EvalAltResult>> {
-                    keymap.bind_key(parse_key(key_str)?, hint, prog, true);
+                    keymap.bind_key_sequence(&parse_keys(key_str)?, hint, prog, true);
                     Ok(())
                 },
             )
@@ -485,10 +916,45 @@ impl rhai::CustomType for Keymap {
                  prog: rhai::FnPtr,
                  close_menu: bool|
                  -> Result<(), Box<rhai::EvalAltResult>> {
-                    keymap.bind_key(parse_key(key_str)?, hint, prog, close_menu);
+                    keymap.bind_key_sequence(&parse_keys(key_str)?, hint, prog, close_menu);
                     Ok(())
                 },
             )
+            .with_fn(
+                "bind_operator",
+                |keymap: &mut Keymap,
+                 keys_str: &str,
+                 hint: String,
+                 prog: rhai::FnPtr|
+                 -> Result<(), Box<rhai::EvalAltResult>> {
+                    keymap.bind_operator(&parse_keys(keys_str)?, hint, prog);
+                    Ok(())
+                },
+            )
+            .with_fn(
+                "bind_motion",
+                |keymap: &mut Keymap,
+                 keys_str: &str,
+                 hint: String,
+                 prog: rhai::FnPtr|
+                 -> Result<(), Box<rhai::EvalAltResult>> {
+                    keymap.bind_motion(&parse_keys(keys_str)?, hint, prog);
+                    Ok(())
+                },
+            )
+            .with_fn(
+                "name_prefix",
+                |keymap: &mut Keymap,
+                 keys_str: &str,
+                 hint: String|
+                 -> Result<(), Box<rhai::EvalAltResult>> {
+                    if keymap.name_prefix(&parse_keys(keys_str)?, hint) {
+                        Ok(())
+                    } else {
+                        Err(error!(Keymap, "Not the start of any key sequence: {keys_str}").into())
+                    }
+                },
+            )
             .with_fn(
                 "bind_key_for_special_candidate",
                 |keymap: &mut Keymap,
@@ -527,10 +993,26 @@ impl rhai::CustomType for Keymap {
                 },
             )
             .with_fn("add_regular_candidate", Keymap::add_regular_candidate)
+            .with_fn(
+                "add_regular_candidate",
+                |keymap: &mut Keymap, display: String, value: rhai::Dynamic| {
+                    keymap.add_regular_candidate(display, value, String::new())
+                },
+            )
             .with_fn(
                 "add_regular_candidate",
                 |keymap: &mut Keymap, value: rhai::Dynamic| {
-                    keymap.add_regular_candidate(value.to_string(), value)
+                    keymap.add_regular_candidate(value.to_string(), value, String::new())
+                },
+            )
+            .with_fn(
+                "bind_candidate_key",
+                |keymap: &mut Keymap,
+                 key_str: &str,
+                 candidate: String|
+                 -> Result<(), Box<rhai::EvalAltResult>> {
+                    keymap.bind_candidate_key(parse_key(key_str)?, candidate);
+                    Ok(())
                 },
             )
             .with_fn(
@@ -1,15 +1,20 @@
 use super::keymap::{KeyProg, Keymap};
-use super::menu::{Menu, MenuKind, MenuName, MenuSelectionCmd};
+use super::menu::{CandidateColumn, Menu, MenuKind, MenuName, MenuSelectionCmd};
 use crate::engine::DocName;
-use crate::frontends::Key;
+use crate::frontends::{Key, KeyCode};
 use crate::language::Storage;
 use crate::tree::Mode;
 use crate::tree::Node;
 use crate::util::{error, IndexedMap, SynlessError};
 use std::collections::HashMap;
+use std::mem;
+use std::time::{Duration, Instant};
 
 type LayerIndex = usize;
 
+/// How long to wait for the next key of a key sequence before giving up on it.
+const PENDING_KEYS_TIMEOUT: Duration = Duration::from_millis(1500);
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum KeymapLabel {
     Menu(MenuName),
@@ -100,12 +105,24 @@ impl rhai::CustomType for Layer {
 /// documents, as well as a local stack of layers for each individual document. When different
 /// layers have conflicting bindings, layers higher in the stack take priority over lower layers,
 /// and local layers take priority over global layers.
+///
+/// While the user is partway through typing a key sequence (like `g g`), the keys typed so far
+/// are pending. They're dropped if the user presses `esc`, presses a key that doesn't continue
+/// the sequence, or waits longer than `PENDING_KEYS_TIMEOUT`.
+///
+/// Likewise, after the user types an operator it's pending until they type a motion to apply it
+/// to. Operators don't time out.
 pub struct LayerManager {
     global_layers: Vec<LayerIndex>,
     local_layers: HashMap<DocName, Vec<LayerIndex>>,
     layers: IndexedMap<Layer>,
     active_menu: Option<Menu>,
     cached_composite_layers: HashMap<Vec<LayerIndex>, Layer>,
+    pending_keys: Vec<Key>,
+    /// When the last pending key was pressed.
+    pending_since: Instant,
+    /// An operator waiting for a motion, and the (last) key that was pressed to type it.
+    pending_operator: Option<(Key, KeyProg)>,
 }
 
 impl LayerManager {
@@ -116,6 +133,9 @@ impl LayerManager {
             layers: IndexedMap::new(),
             active_menu: None,
             cached_composite_layers: HashMap::new(),
+            pending_keys: Vec::new(),
+            pending_since: Instant::now(),
+            pending_operator: None,
         }
     }
 
@@ -220,11 +240,15 @@ impl LayerManager {
             (None, Some(keymap)) => keymap.to_owned(),
         };
         self.active_menu = Some(Menu::new(menu_name, description, keymap, kind));
+        self.pending_keys.clear();
+        self.pending_operator = None;
         Ok(())
     }
 
     pub fn close_menu(&mut self) {
         self.active_menu = None;
+        self.pending_keys.clear();
+        self.pending_operator = None;
     }
 
     /// Manipulate the menu's candidate selection. Returns `false` and does nothing if there is no
@@ -250,6 +274,10 @@ impl LayerManager {
         self.active_menu.as_ref().map(|menu| menu.description())
     }
 
+    pub fn menu_input_string(&self) -> Option<&str> {
+        self.active_menu.as_ref()?.input_string()
+    }
+
     /*********
      * Input *
      *********/
@@ -262,10 +290,57 @@ impl LayerManager {
         doc_name: Option<&DocName>,
         key: Key,
     ) -> Option<KeyLookupResult> {
+        let result = self.lookup_key_ignoring_operators(mode, doc_name, key);
+
+        if let Some((operator_key, operator)) = self.pending_operator.take() {
+            return Some(match result {
+                Some(KeyLookupResult::KeyProg(key_prog)) if key_prog.is_motion() => {
+                    KeyLookupResult::KeyProg(key_prog.with_operator(operator))
+                }
+                Some(KeyLookupResult::KeyProg(key_prog))
+                    if key_prog.is_operator() && key == operator_key =>
+                {
+                    KeyLookupResult::KeyProg(operator.operator_on_cursor())
+                }
+                _ => {
+                    // Keep waiting if the user is partway through typing a motion's key sequence.
+                    // Otherwise, cancel the operator.
+                    if self.has_pending_keys() {
+                        self.pending_operator = Some((operator_key, operator));
+                    }
+                    KeyLookupResult::Redisplay
+                }
+            });
+        }
+
+        match result {
+            Some(KeyLookupResult::KeyProg(key_prog)) if key_prog.is_operator() => {
+                self.pending_operator = Some((key, key_prog));
+                Some(KeyLookupResult::Redisplay)
+            }
+            result => result,
+        }
+    }
+
+    fn lookup_key_ignoring_operators(
+        &mut self,
+        mode: Mode,
+        doc_name: Option<&DocName>,
+        key: Key,
+    ) -> Option<KeyLookupResult> {
+        self.expire_pending_keys();
+        if !self.pending_keys.is_empty() {
+            return Some(self.lookup_pending_key(mode, doc_name, key));
+        }
+
         if let Some(menu) = &mut self.active_menu {
             if let Some(key_prog) = menu.lookup(key) {
                 return Some(KeyLookupResult::KeyProg(key_prog));
             }
+            if menu.keymap().is_prefix(key) {
+                self.push_pending_key(key);
+                return Some(KeyLookupResult::Redisplay);
+            }
             if let Some(ch) = key.as_plain_char() {
                 if menu.execute(MenuSelectionCmd::Insert(ch)) {
                     return Some(KeyLookupResult::Redisplay);
@@ -277,6 +352,10 @@ impl LayerManager {
             if let Some(key_prog) = keymap.lookup(key, None) {
                 return Some(KeyLookupResult::KeyProg(key_prog));
             }
+            if keymap.is_prefix(key) {
+                self.push_pending_key(key);
+                return Some(KeyLookupResult::Redisplay);
+            }
             if mode == Mode::Text {
                 if let Some(ch) = key.as_plain_char() {
                     return Some(KeyLookupResult::InsertChar(ch));
@@ -286,6 +365,32 @@ impl LayerManager {
         None
     }
 
+    /// Whether the user is partway through typing a key sequence.
+    pub fn has_pending_keys(&self) -> bool {
+        !self.pending_keys.is_empty()
+    }
+
+    /// The keys that the user has typed so far of the key sequence they're partway through,
+    /// starting with the pending operator if there is one. Empty if there's nothing pending.
+    pub fn pending_key_sequence(&self) -> Vec<Key> {
+        let operator_key = self.pending_operator.as_ref().map(|(key, _)| *key);
+        operator_key
+            .into_iter()
+            .chain(self.pending_keys.iter().copied())
+            .collect()
+    }
+
+    /// Drop the pending keys if the user has waited too long to continue the key sequence.
+    /// Returns `true` if they were dropped (so the key hints need to be redisplayed).
+    pub fn expire_pending_keys(&mut self) -> bool {
+        if !self.pending_keys.is_empty() && self.pending_since.elapsed() > PENDING_KEYS_TIMEOUT {
+            self.pending_keys.clear();
+            true
+        } else {
+            false
+        }
+    }
+
     /***********
      * Display *
      ***********/
@@ -296,18 +401,40 @@ impl LayerManager {
             .and_then(|menu| menu.make_candidate_selection_doc(s))
     }
 
+    pub fn make_candidate_column_doc(
+        &self,
+        s: &mut Storage,
+        column: CandidateColumn,
+    ) -> Option<Node> {
+        self.active_menu
+            .as_ref()
+            .and_then(|menu| menu.make_candidate_column_doc(s, column))
+    }
+
     pub fn make_keyhint_doc(
         &mut self,
         s: &mut Storage,
         mode: Mode,
         doc_name: Option<&DocName>,
     ) -> Option<Node> {
-        if let Some(menu) = &self.active_menu {
+        if !self.pending_keys.is_empty() || self.pending_operator.is_some() {
+            let pending_keys = self.pending_keys.clone();
+            let mut header_keys = Vec::new();
+            if let Some((operator_key, _)) = &self.pending_operator {
+                header_keys.push(*operator_key);
+            }
+            header_keys.extend(pending_keys.iter().copied());
+            let motions_only = self.pending_operator.is_some();
+            let keymap = self
+                .active_keymap(mode, doc_name)?
+                .prefix_keymap(&pending_keys)?;
+            Some(keymap.make_keyhint_doc(s, None, &header_keys, motions_only))
+        } else if let Some(menu) = &self.active_menu {
             Some(menu.make_keyhint_doc(s))
         } else {
             let layer = self.composite_layer(doc_name);
             let keymap = layer.keymaps.get(&KeymapLabel::Mode(mode))?;
-            Some(keymap.make_keyhint_doc(s, None))
+            Some(keymap.make_keyhint_doc(s, None, &[], false))
         }
     }
 
@@ -315,6 +442,49 @@ impl LayerManager {
      * Private *
      ***********/
 
+    /// Lookup `key` as the continuation of the pending key sequence.
+    fn lookup_pending_key(
+        &mut self,
+        mode: Mode,
+        doc_name: Option<&DocName>,
+        key: Key,
+    ) -> KeyLookupResult {
+        let pending_keys = mem::take(&mut self.pending_keys);
+        if key.code() == KeyCode::Esc {
+            return KeyLookupResult::Redisplay;
+        }
+        let (key_prog, is_prefix) = match self
+            .active_keymap(mode, doc_name)
+            .and_then(|keymap| keymap.prefix_keymap(&pending_keys))
+        {
+            Some(keymap) => (keymap.lookup(key, None), keymap.is_prefix(key)),
+            None => (None, false),
+        };
+        if let Some(key_prog) = key_prog {
+            KeyLookupResult::KeyProg(key_prog)
+        } else {
+            if is_prefix {
+                self.pending_keys = pending_keys;
+                self.push_pending_key(key);
+            }
+            KeyLookupResult::Redisplay
+        }
+    }
+
+    fn push_pending_key(&mut self, key: Key) {
+        self.pending_keys.push(key);
+        self.pending_since = Instant::now();
+    }
+
+    /// The keymap that keys are looked up in: the open menu's, or else the one for `mode`.
+    fn active_keymap(&mut self, mode: Mode, doc_name: Option<&DocName>) -> Option<&Keymap> {
+        if self.active_menu.is_some() {
+            return self.active_menu.as_ref().map(|menu| menu.keymap());
+        }
+        let layer = self.composite_layer(doc_name);
+        layer.keymaps.get(&KeymapLabel::Mode(mode))
+    }
+
     /// Get a composite layer that merges together all active layers. It is cached.
     fn composite_layer(&mut self, doc_name: Option<&DocName>) -> &Layer {
         let layer_indices = self.active_layers(doc_name).collect::<Vec<_>>();
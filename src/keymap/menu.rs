@@ -2,7 +2,7 @@ use super::keymap::{Candidate, KeyProg, Keymap};
 use crate::frontends::Key;
 use crate::language::Storage;
 use crate::tree::Node;
-use crate::util::{bug_assert, fuzzy_search, SynlessBug};
+use crate::util::{fuzzy_search, SynlessBug};
 
 const SELECTION_LANGUAGE_NAME: &str = "selection_menu";
 
@@ -24,6 +24,16 @@ pub struct Menu {
     state: MenuState,
 }
 
+/// A column of extra information that is displayed alongside a candidate menu's candidates, with
+/// one row per candidate.
+#[derive(Debug, Clone, Copy)]
+pub enum CandidateColumn {
+    /// The key that selects each candidate.
+    Keys,
+    /// Each candidate's annotation.
+    Annotations,
+}
+
 #[derive(Debug, Clone)]
 pub enum MenuKind {
     Char,
@@ -71,6 +81,14 @@ impl Menu {
         &self.description
     }
 
+    /// What's been typed into the menu so far, if it's an input string menu.
+    pub fn input_string(&self) -> Option<&str> {
+        match &self.state {
+            MenuState::InputString(menu) => Some(&menu.input),
+            MenuState::Char | MenuState::Candidate(_) => None,
+        }
+    }
+
     /// Returns true if this kind of menu can (ever) execute that command
     #[must_use]
     pub fn execute(&mut self, cmd: MenuSelectionCmd) -> bool {
@@ -81,6 +99,10 @@ impl Menu {
         }
     }
 
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
     pub fn lookup(&self, key: Key) -> Option<KeyProg> {
         self.keymap.lookup(key, self.selected_candidate().as_ref())
     }
@@ -93,9 +115,21 @@ impl Menu {
         }
     }
 
+    /// Returns `None` if no candidate has anything to show in that column.
+    pub fn make_candidate_column_doc(
+        &self,
+        s: &mut Storage,
+        column: CandidateColumn,
+    ) -> Option<Node> {
+        match &self.state {
+            MenuState::Candidate(menu) => menu.make_candidate_column_doc(s, column),
+            MenuState::Char | MenuState::InputString(_) => None,
+        }
+    }
+
     pub fn make_keyhint_doc(&self, s: &mut Storage) -> Node {
         self.keymap
-            .make_keyhint_doc(s, self.selected_candidate().as_ref())
+            .make_keyhint_doc(s, self.selected_candidate().as_ref(), &[], false)
     }
 
     fn selected_candidate(&self) -> Option<Candidate> {
@@ -143,7 +177,7 @@ impl InputStringMenu {
 
         let root = Node::new(s, c_root);
         let input_node = Node::with_text(s, c_input, self.input.clone()).bug();
-        bug_assert!(root.insert_last_child(s, input_node));
+        root.insert_last_child(s, input_node).bug();
         root
     }
 }
@@ -241,7 +275,7 @@ impl CandidateMenu {
 
         // Add input entry
         let input_node = Node::with_text(s, c_input, self.input.clone()).bug();
-        bug_assert!(root.insert_last_child(s, input_node));
+        root.insert_last_child(s, input_node).bug();
 
         // Add candidate entries, highlighting the one at self.index
         for (i, candidate) in self.filtered_candidates.iter().enumerate() {
@@ -254,9 +288,53 @@ impl CandidateMenu {
             if i == self.index {
                 node = Node::with_children(s, c_selected, [node]).bug();
             }
-            bug_assert!(root.insert_last_child(s, node));
+            root.insert_last_child(s, node).bug();
         }
 
         root
     }
+
+    fn make_candidate_column_doc(&self, s: &mut Storage, column: CandidateColumn) -> Option<Node> {
+        let cells = self
+            .filtered_candidates
+            .iter()
+            .map(|candidate| match column {
+                CandidateColumn::Keys => candidate
+                    .key()
+                    .map(|key| key.to_string())
+                    .unwrap_or_default(),
+                CandidateColumn::Annotations => candidate.annotation().to_owned(),
+            })
+            .collect::<Vec<_>>();
+        if cells.iter().all(|cell| cell.is_empty()) {
+            return None;
+        }
+
+        // Lookup selection menu language and constructs
+        let lang = s
+            .language(SELECTION_LANGUAGE_NAME)
+            .bug_msg("Missing selection menu lang");
+        let c_root = lang.root_construct(s);
+        let c_selected = lang.construct(s, "Selected").bug();
+        let c_cell = match column {
+            CandidateColumn::Keys => lang.construct(s, "Key").bug(),
+            CandidateColumn::Annotations => lang.construct(s, "Annotation").bug(),
+        };
+
+        // Construct root node, with an empty row to line up with the input entry
+        let root = Node::new(s, c_root);
+        let input_row = Node::with_text(s, c_cell, String::new()).bug();
+        root.insert_last_child(s, input_row).bug();
+
+        // Add a row for each candidate, highlighting the one at self.index
+        for (i, cell) in cells.into_iter().enumerate() {
+            let mut node = Node::with_text(s, c_cell, cell).bug();
+            if i == self.index {
+                node = Node::with_children(s, c_selected, [node]).bug();
+            }
+            root.insert_last_child(s, node).bug();
+        }
+
+        Some(root)
+    }
 }
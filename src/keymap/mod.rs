@@ -4,4 +4,4 @@ mod menu;
 
 pub use keymap::{KeyProg, Keymap};
 pub use layer::{KeyLookupResult, Layer, LayerManager};
-pub use menu::{MenuKind, MenuSelectionCmd};
+pub use menu::{CandidateColumn, MenuKind, MenuSelectionCmd};
@@ -1,8 +1,9 @@
 use super::specs::{
-    AritySpec, ConstructSpec, GrammarSpec, HoleSyntax, LanguageSpec, NotationSetSpec, SortSpec,
+    AritySpec, ConstructSpec, GrammarSpec, HoleSyntax, LanguageSpec, NavOrder, NotationSetSpec,
+    SortSpec,
 };
 use crate::language::LanguageError;
-use crate::style::ValidNotation;
+use crate::style::{Notation, Style, ValidNotation};
 use crate::util::{IndexedMap, SynlessBug};
 use bit_set::BitSet;
 use partial_pretty_printer as ppp;
@@ -25,7 +26,16 @@ pub struct ConstructCompiled {
     pub name: String,
     pub arity: ArityCompiled,
     pub is_comment_or_ws: bool,
+    pub is_hidden: bool,
+    pub is_prose: bool,
     pub key: Option<char>,
+    /// For each child of a fixed-arity construct, the construct to fill it with (`None` means a
+    /// hole).
+    pub auto_fill: Option<Vec<Option<ConstructId>>>,
+    /// Set at runtime by the user, to restyle this construct without editing its notation.
+    pub style_override: Option<Style>,
+    /// See [`ConstructSpec::summary`]. Can be changed at runtime.
+    pub summary: Option<String>,
 }
 
 #[derive(Debug)]
@@ -63,6 +73,13 @@ pub struct LanguageCompiled {
     pub hole_syntax: Option<HoleSyntax>,
     pub hole_source_notation: Option<ValidNotation>,
     pub hole_display_notation: ValidNotation,
+    /// A texty construct that refers to another file by path.
+    pub include_construct: Option<ConstructId>,
+    /// How to display hidden nodes, when they're not being shown.
+    pub hidden_display_notation: ValidNotation,
+    pub listy_nav_order: NavOrder,
+    /// Surround name -> the construct to wrap nodes in
+    pub surrounds: Vec<(String, ConstructId)>,
 }
 
 #[derive(Debug)]
@@ -113,6 +130,43 @@ pub fn compile_language(language_spec: LanguageSpec) -> Result<LanguageCompiled,
 
         (source_notation, display_notation)
     };
+    let hidden_display_notation = Notation::Empty.validate().bug();
+
+    let include_construct = if let Some(name) = language_spec.include_construct {
+        let id = grammar
+            .constructs
+            .id(&name)
+            .ok_or_else(|| LanguageError::UndefinedConstruct(name.clone()))?;
+        if !matches!(grammar.constructs[id].arity, ArityCompiled::Texty(_)) {
+            return Err(LanguageError::IncludeNotTexty(name));
+        }
+        Some(id)
+    } else {
+        None
+    };
+
+    let mut surrounds: Vec<(String, ConstructId)> = Vec::new();
+    for (surround_name, construct_name) in language_spec.surrounds {
+        if surrounds.iter().any(|(name, _)| name == &surround_name) {
+            return Err(LanguageError::DuplicateSurround(surround_name));
+        }
+        let id = grammar
+            .constructs
+            .id(&construct_name)
+            .ok_or_else(|| LanguageError::UndefinedConstruct(construct_name.clone()))?;
+        let can_wrap = match &grammar.constructs[id].arity {
+            ArityCompiled::Texty(_) => false,
+            ArityCompiled::Fixed(sorts) => sorts.len() == 1,
+            ArityCompiled::Listy(_, _) => true,
+        };
+        if !can_wrap {
+            return Err(LanguageError::InvalidSurround(
+                surround_name,
+                construct_name,
+            ));
+        }
+        surrounds.push((surround_name, id));
+    }
 
     Ok(LanguageCompiled {
         name: language_spec.name,
@@ -124,6 +178,10 @@ pub fn compile_language(language_spec: LanguageSpec) -> Result<LanguageCompiled,
         hole_syntax: language_spec.hole_syntax,
         hole_source_notation,
         hole_display_notation,
+        include_construct,
+        hidden_display_notation,
+        listy_nav_order: language_spec.listy_nav_order,
+        surrounds,
     })
 }
 
@@ -251,7 +309,11 @@ impl GrammarCompiler {
             name: HOLE_NAME.to_owned(),
             arity: AritySpec::Fixed(Vec::new()),
             is_comment_or_ws: false,
+            is_hidden: false,
+            is_prose: false,
             key: Some(HOLE_KEY),
+            auto_fill: None,
+            summary: None,
         })
     }
 
@@ -285,6 +347,7 @@ impl GrammarCompiler {
             let construct = &self.constructs[id];
             self.compile_construct(&mut grammar, id, construct)?;
         }
+        check_auto_fill_cycles(&grammar)?;
 
         Ok(grammar)
     }
@@ -366,6 +429,11 @@ impl GrammarCompiler {
             }
         };
 
+        let auto_fill = match &construct.auto_fill {
+            None => None,
+            Some(fills) => Some(self.compile_auto_fill(grammar, construct, &arity, fills)?),
+        };
+
         if let Some(key) = construct.key {
             if let Some(other_id) = grammar.keymap.get(&key) {
                 return Err(LanguageError::DuplicateKey(
@@ -384,9 +452,130 @@ impl GrammarCompiler {
                 name: construct.name.clone(),
                 arity,
                 is_comment_or_ws: construct.is_comment_or_ws,
+                is_hidden: construct.is_hidden,
+                is_prose: construct.is_prose,
                 key: construct.key,
+                auto_fill,
+                style_override: None,
+                summary: construct.summary.clone(),
             },
         );
         Ok(())
     }
+
+    fn compile_auto_fill(
+        &self,
+        grammar: &GrammarCompiled,
+        construct: &ConstructSpec,
+        arity: &ArityCompiled,
+        fills: &[Option<String>],
+    ) -> Result<Vec<Option<ConstructId>>, LanguageError> {
+        let sorts = match arity {
+            ArityCompiled::Fixed(sorts) => sorts,
+            ArityCompiled::Texty(_) | ArityCompiled::Listy(_, _) => {
+                return Err(LanguageError::AutoFillNotFixed(construct.name.clone()))
+            }
+        };
+        if sorts.len() != fills.len() {
+            return Err(LanguageError::AutoFillWrongLength(
+                construct.name.clone(),
+                fills.len(),
+                sorts.len(),
+            ));
+        }
+
+        let mut compiled_fills = Vec::new();
+        for ((sort_id, _), fill) in sorts.iter().zip(fills) {
+            let fill_name = match fill {
+                Some(fill_name) => fill_name,
+                None => {
+                    compiled_fills.push(None);
+                    continue;
+                }
+            };
+            let fill_id = self
+                .constructs
+                .id(fill_name)
+                .ok_or_else(|| LanguageError::UndefinedConstruct(fill_name.to_owned()))?;
+            if !grammar.sorts[*sort_id].0.contains(fill_id) {
+                return Err(LanguageError::AutoFillWrongSort(
+                    construct.name.clone(),
+                    fill_name.to_owned(),
+                ));
+            }
+            compiled_fills.push(Some(fill_id));
+        }
+        Ok(compiled_fills)
+    }
+}
+
+/// The constructs that a node of this construct would have its children filled with by
+/// `Node::new_with_auto_fill()`. Mirrors `FixedSort::auto_fill()`.
+fn auto_fill_children(grammar: &GrammarCompiled, construct: ConstructId) -> Vec<ConstructId> {
+    let sorts = match &grammar.constructs[construct].arity {
+        ArityCompiled::Fixed(sorts) => sorts,
+        ArityCompiled::Texty(_) | ArityCompiled::Listy(_, _) => return Vec::new(),
+    };
+    match &grammar.constructs[construct].auto_fill {
+        Some(fills) => fills.iter().flatten().copied().collect(),
+        None => sorts
+            .iter()
+            .filter_map(|(sort_id, _)| {
+                let mut non_holes = grammar.sorts[*sort_id]
+                    .0
+                    .iter()
+                    .filter(|id| *id != grammar.hole_construct);
+                match (non_holes.next(), non_holes.next()) {
+                    (Some(id), None) => Some(id),
+                    _ => None,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Auto-filling a node creates its children with auto-fill too, so a construct that (directly or
+/// indirectly) auto-fills itself would never finish being created.
+fn check_auto_fill_cycles(grammar: &GrammarCompiled) -> Result<(), LanguageError> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Mark {
+        Unvisited,
+        InProgress,
+        Done,
+    }
+
+    let num_constructs = grammar.constructs.len();
+    let children = (0..num_constructs)
+        .map(|id| auto_fill_children(grammar, id))
+        .collect::<Vec<_>>();
+    let mut marks = vec![Mark::Unvisited; num_constructs];
+    for start in 0..num_constructs {
+        if marks[start] != Mark::Unvisited {
+            continue;
+        }
+        // Stack of (construct, index of the next child to visit)
+        let mut stack = vec![(start, 0)];
+        marks[start] = Mark::InProgress;
+        while let Some((id, child_index)) = stack.last_mut() {
+            if let Some(child) = children[*id].get(*child_index).copied() {
+                *child_index += 1;
+                match marks[child] {
+                    Mark::Unvisited => {
+                        marks[child] = Mark::InProgress;
+                        stack.push((child, 0));
+                    }
+                    Mark::InProgress => {
+                        return Err(LanguageError::AutoFillCycle(
+                            grammar.constructs[child].name.clone(),
+                        ))
+                    }
+                    Mark::Done => (),
+                }
+            } else {
+                marks[*id] = Mark::Done;
+                stack.pop();
+            }
+        }
+    }
+    Ok(())
 }
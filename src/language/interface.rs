@@ -2,11 +2,12 @@ use super::compiled::{
     compile_notation_set, ArityCompiled, ConstructId, GrammarCompiled, LanguageId, NotationSetId,
     SortId,
 };
-use super::specs::NotationSetSpec;
+use super::specs::{NavOrder, NotationSetSpec};
 use super::storage::Storage;
 use super::{HoleSyntax, LanguageError};
-use crate::style::ValidNotation;
-use crate::util::bug;
+use crate::style::{Style, ValidNotation};
+use crate::tree::Node;
+use crate::util::{bug, OrderedMap, SynlessBug};
 use regex::Regex;
 
 // NOTE: Why all the wrapper types, instead of using indexes? Two reasons:
@@ -211,6 +212,78 @@ impl Language {
         s.languages[self.language].hole_source_notation.as_ref()
     }
 
+    pub fn include_construct(self, s: &Storage) -> Option<Construct> {
+        s.languages[self.language]
+            .include_construct
+            .map(|id| Construct {
+                language: self.language,
+                construct: id,
+            })
+    }
+
+    pub fn hidden_display_notation(self, s: &Storage) -> &ValidNotation {
+        &s.languages[self.language].hidden_display_notation
+    }
+
+    pub fn listy_nav_order(self, s: &Storage) -> NavOrder {
+        s.languages[self.language].listy_nav_order
+    }
+
+    /// The names of this language's surrounds, in the order they were declared.
+    pub fn surround_names(self, s: &Storage) -> impl Iterator<Item = &str> + '_ {
+        s.languages[self.language]
+            .surrounds
+            .iter()
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// The names of this language's templates, in the order they were added.
+    pub fn template_names(self, s: &Storage) -> impl Iterator<Item = &str> + '_ {
+        s.templates
+            .get(&self.language)
+            .into_iter()
+            .flat_map(|templates| templates.keys().map(|name| name.as_str()))
+    }
+
+    /// The root of the template named `name`. It must be copied before being put in a doc.
+    pub fn template(self, s: &Storage, name: &str) -> Option<Node> {
+        s.templates.get(&self.language)?.get(name).copied()
+    }
+
+    /// Add a template named `name`, replacing any existing template of that name. `template`
+    /// must be a root node of this language, and is owned by the language from now on.
+    pub fn add_template(self, s: &mut Storage, name: String, template: Node) {
+        let old_template = s
+            .templates
+            .entry(self.language)
+            .or_insert_with(OrderedMap::new)
+            .insert(name, template);
+        if let Some(old_template) = old_template {
+            old_template.delete_root(s).bug();
+        }
+    }
+
+    /// The construct that the surround named `name` wraps nodes in.
+    pub fn surround(self, s: &Storage, name: &str) -> Option<Construct> {
+        s.languages[self.language]
+            .surrounds
+            .iter()
+            .find(|(surround_name, _)| surround_name == name)
+            .map(|(_, id)| Construct {
+                language: self.language,
+                construct: *id,
+            })
+    }
+
+    /// Whether `construct` is used by any of this language's surrounds.
+    pub fn is_surround_construct(self, s: &Storage, construct: Construct) -> bool {
+        construct.language == self.language
+            && s.languages[self.language]
+                .surrounds
+                .iter()
+                .any(|(_, id)| *id == construct.construct)
+    }
+
     fn notation_id(self, s: &Storage, notation_set_name: &str) -> Result<usize, LanguageError> {
         if let Some(id) = s.languages[self.language]
             .notation_sets
@@ -324,6 +397,14 @@ impl Construct {
         grammar(s, self.language).constructs[self.construct].is_comment_or_ws
     }
 
+    pub fn is_hidden(self, s: &Storage) -> bool {
+        grammar(s, self.language).constructs[self.construct].is_hidden
+    }
+
+    pub fn is_prose(self, s: &Storage) -> bool {
+        grammar(s, self.language).constructs[self.construct].is_prose
+    }
+
     pub fn is_hole(self, s: &Storage) -> bool {
         grammar(s, self.language).hole_construct == self.construct
     }
@@ -332,6 +413,31 @@ impl Construct {
         grammar(s, self.language).root_construct == self.construct
     }
 
+    /// A style to apply to every node of this construct, taking precedence over the (low
+    /// priority) styles given by its notation.
+    pub fn style_override(self, s: &Storage) -> Option<&Style> {
+        grammar(s, self.language).constructs[self.construct]
+            .style_override
+            .as_ref()
+    }
+
+    pub fn set_style_override(self, s: &mut Storage, style: Option<Style>) {
+        s.languages[self.language].grammar.constructs[self.construct].style_override = style;
+    }
+
+    /// The template for summarizing nodes of this construct. See [`Node::summary`].
+    ///
+    /// [`Node::summary`]: crate::tree::Node::summary
+    pub fn summary_template(self, s: &Storage) -> Option<&str> {
+        grammar(s, self.language).constructs[self.construct]
+            .summary
+            .as_deref()
+    }
+
+    pub fn set_summary_template(self, s: &mut Storage, template: Option<String>) {
+        s.languages[self.language].grammar.constructs[self.construct].summary = template;
+    }
+
     /// This construct must never be used!
     pub(crate) fn invalid_dummy() -> Construct {
         Construct {
@@ -352,6 +458,18 @@ impl FixedSorts {
         }
     }
 
+    /// The construct to fill the `i`'th child with when a node of this construct is created, or
+    /// `None` if it should be left as a hole.
+    pub fn auto_fill(self, s: &Storage, i: usize) -> Option<Construct> {
+        match &grammar(s, self.language).constructs[self.construct].auto_fill {
+            Some(fills) => fills.get(i).copied().flatten().map(|id| Construct {
+                language: self.language,
+                construct: id,
+            }),
+            None => self.get(s, i)?.unique_construct(s),
+        }
+    }
+
     pub fn get(self, s: &Storage, i: usize) -> Option<Sort> {
         if let ArityCompiled::Fixed(sorts) =
             &grammar(s, self.language).constructs[self.construct].arity
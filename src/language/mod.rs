@@ -7,9 +7,10 @@ use crate::util::{error, SynlessError};
 use partial_pretty_printer as ppp;
 use std::fmt;
 
-pub use interface::{Arity, Construct, Language};
+pub use interface::{Arity, Construct, Language, Sort};
 pub use specs::{
-    AritySpec, ConstructSpec, GrammarSpec, HoleSyntax, LanguageSpec, NotationSetSpec, SortSpec,
+    AritySpec, ConstructSpec, GrammarSpec, HoleSyntax, LanguageSpec, NavOrder, NotationSetSpec,
+    SortSpec,
 };
 pub use storage::Storage;
 
@@ -32,9 +33,20 @@ pub enum LanguageError {
     TextyRoot(String),
     #[error("Failed to compile regex '{0}' for construct {1}: {2}")]
     InvalidRegex(String, String, String),
-    // TODO: Check for cycles
-    // #[error("Sort '{0}' refers to itself")]
-    // InfiniteSort(String),
+    #[error("Construct '{0}' has an auto_fill, but only fixed constructs can be auto-filled")]
+    AutoFillNotFixed(String),
+    #[error("Construct '{0}' has an auto_fill of length {1}, but it has {2} children")]
+    AutoFillWrongLength(String, usize, usize),
+    #[error("Construct '{0}' is auto-filled with '{1}', which is not allowed in that position")]
+    AutoFillWrongSort(String, String),
+    #[error("Construct '{0}' auto-fills itself, directly or through its children")]
+    AutoFillCycle(String),
+    #[error("Include construct '{0}' must be texty")]
+    IncludeNotTexty(String),
+    #[error("Duplicate name '{0}' used for two surrounds")]
+    DuplicateSurround(String),
+    #[error("Surround '{0}' uses construct '{1}', which can't wrap a node: it must be listy or have exactly one child")]
+    InvalidSurround(String, String),
 
     // Notation sets
     #[error("The language '{0}' already has a notation set named '{1}'")]
@@ -9,9 +9,29 @@ pub struct ConstructSpec {
     pub arity: AritySpec,
     #[serde(default)]
     pub is_comment_or_ws: bool,
+    /// Hidden nodes (like ids or metadata) are stored and saved, but are not displayed unless the
+    /// user asks to see them.
+    #[serde(default)]
+    pub is_hidden: bool,
+    /// Whether the text of this construct's nodes is natural language, like a comment or a
+    /// paragraph, rather than code. Prose is spell checked.
+    #[serde(default)]
+    pub is_prose: bool,
     // TODO: https://github.com/justinpombrio/synless/issues/88
     #[serde(default)]
     pub key: Option<char>,
+    /// For fixed-arity constructs: what to fill each child with when a node of this construct is
+    /// inserted. `None` means to leave that child as a hole. If `auto_fill` is omitted, each child
+    /// whose sort contains a single construct is filled with that construct, and the rest are
+    /// left as holes.
+    #[serde(default)]
+    pub auto_fill: Option<Vec<Option<String>>>,
+    /// A template for a short, one-line label for nodes of this construct, used wherever a node
+    /// needs to be named (like the cursor path). `$text` is replaced by the node's text, and `$0`
+    /// through `$9` by the summaries of its children. If omitted, texty nodes are summarized by
+    /// their text and other nodes by their construct name.
+    #[serde(default)]
+    pub summary: Option<String>,
 }
 
 /// A set of constructs. Can both include and be included by other sorts.
@@ -66,6 +86,31 @@ pub struct LanguageSpec {
     /// Load files with these extensions using this language. Must include the `.`.
     pub file_extensions: Vec<String>,
     pub hole_syntax: Option<HoleSyntax>,
+    /// A texty construct that refers to another file by path, like an `#include`. When a subtree
+    /// is extracted into its own file, it's replaced by one of these.
+    #[serde(default)]
+    pub include_construct: Option<String>,
+    /// The order in which to move between the children of listy nodes.
+    #[serde(default)]
+    pub listy_nav_order: NavOrder,
+    /// Named ways to wrap a node in another construct, like wrapping a value in a list. Maps a
+    /// surround name to the name of the wrapping construct, which must either be listy or have
+    /// exactly one child.
+    #[serde(default)]
+    pub surrounds: Vec<(String, String)>,
+}
+
+/// The order in which to move between the children of a listy node, when going to the previous or
+/// next sibling.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NavOrder {
+    /// The order of the children in the document.
+    #[default]
+    Document,
+    /// The order in which the children appear on screen: top to bottom, then left to right. This
+    /// differs from document order when a notation wraps children across lines in columns rather
+    /// than in rows. Falls back to document order when some of the children are off screen.
+    Visual,
 }
 
 /// The syntax to use when saving and loading holes.
@@ -1,9 +1,9 @@
-use super::compiled::{compile_language, LanguageCompiled};
+use super::compiled::{compile_language, LanguageCompiled, LanguageId};
 use super::interface::Language;
 use super::specs::LanguageSpec;
 use super::LanguageError;
-use crate::tree::NodeForest;
-use crate::util::IndexedMap;
+use crate::tree::{Node, NodeForest, NodeRemapping};
+use crate::util::{IndexedMap, OrderedMap, SynlessBug};
 use std::collections::HashMap;
 
 /// Stores all documents and languages.
@@ -13,6 +13,9 @@ pub struct Storage {
     pub(crate) node_forest: NodeForest,
     /// Map from file extension (including the `.`) to language.
     file_extensions: HashMap<String, Language>,
+    /// Each language's named templates, in the order they were added. Each template is the root
+    /// of a tree that isn't part of any doc; it gets copied whenever it's inserted.
+    pub(super) templates: HashMap<LanguageId, OrderedMap<String, Node>>,
 }
 
 impl Storage {
@@ -21,6 +24,7 @@ impl Storage {
             languages: IndexedMap::new(),
             node_forest: NodeForest::new(),
             file_extensions: HashMap::new(),
+            templates: HashMap::new(),
         }
     }
 
@@ -58,6 +62,31 @@ impl Storage {
     pub fn num_nodes(&self) -> usize {
         self.node_forest.num_nodes()
     }
+
+    /// How many nodes there's room for before more memory needs to be allocated. Deleting nodes
+    /// doesn't lower this; see `compact_nodes()`.
+    pub fn node_capacity(&self) -> usize {
+        self.node_forest.capacity()
+    }
+
+    /// Make room for at least `additional` more nodes, so that building a big tree doesn't
+    /// reallocate repeatedly.
+    pub fn reserve_nodes(&mut self, additional: usize) {
+        self.node_forest.reserve(additional);
+    }
+
+    /// Release the memory held by deleted nodes. This moves every node, so every `Node` and
+    /// `WeakNode` held outside of this `Storage` must be updated with the returned remapping, or
+    /// dropped. Old handles that aren't updated become invalid, as if their nodes were deleted.
+    pub fn compact_nodes(&mut self) -> NodeRemapping {
+        let remapping = self.node_forest.compact();
+        for templates in self.templates.values_mut() {
+            for (_, template) in templates.iter_mut() {
+                *template = remapping.get(*template).bug();
+            }
+        }
+        remapping
+    }
 }
 
 impl Default for Storage {
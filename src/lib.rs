@@ -1,27 +1,41 @@
 // TODO: temporary #[allow(dead_code)]
 #![allow(dead_code)]
 
+mod config;
 mod engine;
 mod frontends;
 mod keymap;
 mod language;
+mod plugin;
 mod pretty_doc;
 mod runtime;
 mod style;
+mod synthetic;
+#[cfg(test)]
+mod testing;
 mod tree;
 mod util;
 
 pub mod parsing;
 
-pub use engine::{DocName, Engine, Settings};
-pub use frontends::Terminal;
+pub use config::{config_dir, config_path, user_init_path, Config};
+pub use engine::{
+    BookmarkCommand, ClipboardCommand, Dictionary, DocName, Engine, JsonSchema, LanguageSettings,
+    Search, Settings, TreeEdCommand,
+};
+pub use frontends::{CaptureWindow, Terminal};
 pub use keymap::{KeyProg, Keymap, Layer};
 pub use language::{
-    AritySpec, Construct, ConstructSpec, GrammarSpec, Language, LanguageSpec, NotationSetSpec,
-    SortSpec, Storage,
+    AritySpec, Construct, ConstructSpec, GrammarSpec, Language, LanguageError, LanguageSpec,
+    NavOrder, NotationSetSpec, SortSpec, Storage,
 };
+pub use plugin::{discover_plugins, plugins_dir, Plugin, PluginManifest};
 pub use pretty_doc::DocRef;
-pub use runtime::Runtime;
-pub use style::ColorTheme;
-pub use tree::{Location, Node};
+pub use runtime::{PaneBuilder, Runtime};
+pub use style::{ColorTheme, ColorThemeSpec, Shading, ShadingMode};
+pub use synthetic::{make_synthetic_json, SyntheticShape};
+pub use tree::{
+    merge, DiffOp, Location, MergeConflict, Node, NodeChange, NodeRemapping, TreeError, Visit,
+    WeakNode,
+};
 pub use util::{Log, LogEntry, LogLevel, SynlessBug, SynlessError};
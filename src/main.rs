@@ -1,21 +1,59 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::panic;
+use std::path::Path;
 use std::rc::Rc;
-use synless::{log, ColorTheme, Log, Runtime, Settings, SynlessBug, SynlessError, Terminal};
+use synless::{
+    config_path, discover_plugins, error, log, plugins_dir, user_init_path, CaptureWindow,
+    ColorTheme, Config, DocName, Engine, Log, Plugin, Runtime, Settings, SynlessBug, SynlessError,
+    Terminal,
+};
 
 // TODO: Make this work if you start in a different cwd
 const BASE_MODULE_PATH: &str = "scripts/base_module.rhai";
 const INTERNALS_MODULE_PATH: &str = "scripts/internals_module.rhai";
 const INIT_PATH: &str = "scripts/init.rhai";
 const MAIN_PATH: &str = "scripts/main.rhai";
+const LANGUAGE_DIR: &str = "data";
 
 /// Synless tree editor
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct CliArgs {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
     /// Optional file to open
     file_path: Option<String>,
+    /// Three-way merge BASE with the edited versions OURS and THEIRS, opening the result to be
+    /// saved at FILE_PATH. For use as a git mergetool.
+    #[arg(long, num_args = 3, value_names = ["BASE", "OURS", "THEIRS"], requires = "file_path")]
+    merge: Option<Vec<String>>,
+    /// Write the log to `synless.log` in the state directory ($XDG_STATE_HOME/synless or
+    /// ~/.local/state/synless), rotating it as it grows, instead of to `log.txt`
+    #[arg(long)]
+    log_file: bool,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    /// Pretty-print a file in its source notation, without starting the editor
+    Fmt {
+        /// File to format. Its language is determined by its extension.
+        file_path: String,
+        /// Overwrite the file instead of printing to stdout
+        #[arg(short, long)]
+        in_place: bool,
+    },
+    /// Print a file the way the editor displays it, colored with ANSI escape codes (for
+    /// `less -R`), without starting the editor
+    Render {
+        /// File to render. Its language is determined by its extension.
+        file_path: String,
+        /// Width to print at, instead of the editor's display width
+        #[arg(short, long)]
+        width: Option<u16>,
+    },
 }
 
 impl CliArgs {
@@ -24,6 +62,10 @@ impl CliArgs {
         if let Some(file_path) = &self.file_path {
             map.insert("file_path".into(), rhai::Dynamic::from(file_path.clone()));
         }
+        if let Some(paths) = &self.merge {
+            let paths = paths.iter().cloned().map(rhai::Dynamic::from);
+            map.insert("merge".into(), paths.collect::<rhai::Array>().into());
+        }
         map
     }
 }
@@ -54,6 +96,8 @@ fn make_engine() -> rhai::Engine {
     engine.build_type::<synless::SynlessError>();
     engine.build_type::<synless::Construct>();
     engine.build_type::<synless::Language>();
+    engine.build_type::<synless::Node>();
+    engine.build_type::<synless::PaneBuilder>();
     engine
 }
 
@@ -87,7 +131,7 @@ fn run(args: CliArgs) -> Result<(), Box<rhai::EvalAltResult>> {
 
     Runtime::register_internal_methods(runtime.clone(), &mut internals_mod);
     engine.register_static_module("synless_internals", internals_mod.into());
-    Runtime::register_external_methods(runtime, &mut base_mod);
+    Runtime::register_external_methods(runtime.clone(), &mut base_mod);
     engine.register_static_module("s", base_mod.into());
 
     // Can't set this before modules are registered, as they reference each other
@@ -100,6 +144,21 @@ fn run(args: CliArgs) -> Result<(), Box<rhai::EvalAltResult>> {
     };
     engine.register_global_module(init_mod.into());
 
+    // Then the user's own init script, if they have one, so that its functions can be bound to
+    // keys too. A broken one is logged rather than stopping the editor from starting.
+    if let Err(err) = load_user_init(&mut engine) {
+        let message = error_message(&err);
+        log!(Error, "Failed to load user init script: {message}");
+    }
+
+    // Load plugins after init.rhai, so that their keybindings take priority over the defaults.
+    load_plugins(&mut engine, &runtime);
+
+    // Load the config last, so that its settings and theme win over the defaults.
+    if let Err(err) = runtime.borrow_mut().load_config() {
+        log!(Error, "{err}");
+    }
+
     // Load main.rhai
     let main_ast = engine.compile_file(MAIN_PATH.into())?;
     engine.run_ast(&main_ast)?;
@@ -107,20 +166,200 @@ fn run(args: CliArgs) -> Result<(), Box<rhai::EvalAltResult>> {
     Ok(())
 }
 
+/// Load `user_init_path()` as a global module, if it exists.
+fn load_user_init(engine: &mut rhai::Engine) -> Result<(), Box<rhai::EvalAltResult>> {
+    let path = match user_init_path() {
+        Some(path) if path.exists() => path,
+        _ => return Ok(()),
+    };
+    let ast = engine.compile_file(path)?;
+    let module = rhai::Module::eval_ast_as_new(rhai::Scope::new(), &ast, engine)?;
+    engine.register_global_module(module.into());
+    Ok(())
+}
+
+/// Load every plugin in `plugins_dir()`. A plugin that fails to load is logged and skipped.
+fn load_plugins(engine: &mut rhai::Engine, runtime: &Rc<RefCell<Runtime<Terminal>>>) {
+    let dir = match plugins_dir() {
+        Some(dir) => dir,
+        None => return,
+    };
+    for plugin in discover_plugins(&dir) {
+        match plugin {
+            Ok(plugin) => {
+                if let Err(err) = load_plugin(engine, runtime, &plugin) {
+                    let (name, message) = (plugin.name(), error_message(&err));
+                    log!(Error, "Failed to load plugin '{name}': {message}");
+                }
+            }
+            Err(err) => log!(Error, "Failed to load plugin: {err}"),
+        }
+    }
+}
+
+/// Load the plugin's languages, then its rhai module (registered under the plugin's name), then
+/// its keybindings and commands.
+fn load_plugin(
+    engine: &mut rhai::Engine,
+    runtime: &Rc<RefCell<Runtime<Terminal>>>,
+    plugin: &Plugin,
+) -> Result<(), Box<rhai::EvalAltResult>> {
+    for path in plugin.language_paths() {
+        runtime
+            .borrow_mut()
+            .load_language(&path.to_string_lossy())?;
+    }
+
+    let ast = engine.compile_file(plugin.module_path())?;
+    let module = rhai::Module::eval_ast_as_new(rhai::Scope::new(), &ast, engine)?;
+    engine.register_static_module(plugin.name(), module.into());
+
+    let mut progs = HashMap::new();
+    for command in &plugin.manifest.commands {
+        let prog = engine.eval::<rhai::FnPtr>(&plugin.command_closure_source(&command.function))?;
+        progs.insert(command.function.clone(), prog);
+    }
+    runtime.borrow_mut().add_plugin(plugin, &progs)?;
+    Ok(())
+}
+
+/// Format a file without constructing a frontend or running any rhai scripts. The languages the
+/// editor would load are loaded, and the file is printed at the source width from the config file.
+fn format_file(file_path: &str, in_place: bool) -> Result<(), SynlessError> {
+    let (engine, doc_name) = load_file_without_editor(file_path)?;
+    let formatted = engine.print_source(&doc_name)?;
+
+    if in_place {
+        std::fs::write(file_path, formatted)
+            .map_err(|err| error!(FileSystem, "Failed to write to file '{file_path}' ({err})"))?;
+    } else {
+        print!("{formatted}");
+    }
+    Ok(())
+}
+
+/// Print a file in its display notation to stdout, with the default color theme, like
+/// `format_file()` does in its source notation.
+fn render_file(file_path: &str, width: Option<u16>) -> Result<(), SynlessError> {
+    let (engine, doc_name) = load_file_without_editor(file_path)?;
+    let window = engine.display_whole_doc(&doc_name, width, |size| {
+        CaptureWindow::new(ColorTheme::default_dark(), size)
+    })?;
+    print!("{}", window.to_ansi_string());
+    Ok(())
+}
+
+/// Load the languages the editor would (every language in `LANGUAGE_DIR`, and those of the
+/// plugins in `plugins_dir()`) into a new engine with the builtin parsers and the settings from
+/// the config file, then open the file at `file_path` in it, in the language for its extension.
+fn load_file_without_editor(file_path: &str) -> Result<(Engine, DocName), SynlessError> {
+    let mut engine = Engine::new(Settings::default());
+    if let Some(path) = config_path() {
+        engine.apply_config(&Config::load(&path)?)?;
+    }
+    engine.add_builtin_parsers();
+
+    let entries = std::fs::read_dir(LANGUAGE_DIR).map_err(|err| {
+        error!(
+            FileSystem,
+            "Failed to read directory '{LANGUAGE_DIR}' ({err})"
+        )
+    })?;
+    for entry in entries {
+        let path = entry
+            .map_err(|err| {
+                error!(
+                    FileSystem,
+                    "Failed to read directory '{LANGUAGE_DIR}' ({err})"
+                )
+            })?
+            .path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("ron") {
+            continue;
+        }
+        load_language_file(&mut engine, &path)?;
+    }
+    let plugins = match plugins_dir() {
+        Some(dir) => discover_plugins(&dir),
+        None => Vec::new(),
+    };
+    for plugin in plugins.into_iter().flatten() {
+        for path in plugin.language_paths() {
+            load_language_file(&mut engine, &path)?;
+        }
+    }
+
+    let extension = Path::new(file_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .ok_or_else(|| {
+            error!(
+                Doc,
+                "Can't determine language of '{file_path}' because it doesn't have an extension"
+            )
+        })?;
+    let language_name = engine
+        .lookup_file_extension(&format!(".{extension}"))
+        .ok_or_else(|| error!(Doc, "No language registered for extension '{extension}'"))?
+        .to_owned();
+
+    let source = std::fs::read_to_string(file_path)
+        .map_err(|err| error!(FileSystem, "Failed to read file at '{file_path}' ({err})"))?;
+    let doc_name = DocName::File(file_path.into());
+    engine.load_doc_from_source(doc_name.clone(), &language_name, &source)?;
+    Ok((engine, doc_name))
+}
+
+fn load_language_file(engine: &mut Engine, path: &Path) -> Result<(), SynlessError> {
+    let ron_string = std::fs::read_to_string(path).map_err(|err| {
+        error!(
+            FileSystem,
+            "Failed to read file at '{}' ({err})",
+            path.display()
+        )
+    })?;
+    engine.load_language_ron(path, &ron_string)?;
+    Ok(())
+}
+
 fn display_error(error: Box<rhai::EvalAltResult>) {
-    if let rhai::EvalAltResult::ErrorRuntime(value, _) = error.as_ref() {
+    log!(Error, "Uncaught error in main: {}", error_message(&error));
+}
+
+fn error_message(error: &rhai::EvalAltResult) -> String {
+    if let rhai::EvalAltResult::ErrorRuntime(value, _) = error {
         if let Some(synless_error) = value.clone().try_cast::<SynlessError>() {
-            log!(Error, "Uncaught error in main: {synless_error}");
-            return;
+            return synless_error.to_string();
         }
     }
-    log!(Error, "Uncaught error in main: {error}");
+    error.to_string()
 }
 
 fn main() {
+    let args = CliArgs::parse();
+
+    if args.log_file {
+        match Log::log_to_state_dir() {
+            Ok(path) => log!(Info, "Logging to '{}'", path.display()),
+            Err(err) => log!(Error, "{err}"),
+        }
+    }
     log!(Info, "Synless is starting");
 
-    let args = CliArgs::parse();
+    if let Some(command) = &args.command {
+        let result = match command {
+            CliCommand::Fmt {
+                file_path,
+                in_place,
+            } => format_file(file_path, *in_place),
+            CliCommand::Render { file_path, width } => render_file(file_path, *width),
+        };
+        if let Err(err) = result {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
     // Set up panic handling. We can't simply print the panic message to stderr,
     // because it would be swallowed by the terminal's alternate screen. Instead,
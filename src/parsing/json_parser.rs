@@ -1,7 +1,7 @@
-use super::{Parse, ParseError};
-use crate::language::{Language, Storage};
+use super::{build_doc, BackgroundParseFn, Parse, ParseError, ParsedTree, SplitSource};
+use crate::language::Storage;
 use crate::tree::Node;
-use crate::util::{bug_assert, error, SynlessBug, SynlessError};
+use crate::util::SynlessError;
 use partial_pretty_printer as ppp;
 
 const LANGUAGE_NAME: &str = "json";
@@ -21,83 +21,107 @@ impl Parse for JsonParser {
         file_name: &str,
         source: &str,
     ) -> Result<Node, SynlessError> {
-        // Serde json uses 1-indexed positions; we use 0-indexed positions.
-        let json = serde_json::from_str(source).map_err(|err| ParseError {
-            pos: Some(ppp::Pos {
-                row: (err.line() as ppp::Row).saturating_sub(1),
-                col: (err.column() as ppp::Col).saturating_sub(1),
-            }),
-            file_name: file_name.to_owned(),
-            message: format!("{}", err),
-        })?;
-
+        let children = parse_json(file_name, source)?;
         let json_lang = s.language(LANGUAGE_NAME)?;
-        let json_node = json_to_node(s, json, json_lang).map_err(|construct| {
-            error!(
-                Parse,
-                "Construct '{}' missing from json language spec", construct
-            )
-        })?;
-        let root_node = Node::with_children(s, json_lang.root_construct(s), [json_node])
-            .ok_or_else(|| error!(Parse, "Bug in json parser: root node arity mismatch"))?;
-        Ok(root_node)
+        build_doc(s, json_lang, children)
     }
-}
 
-fn json_to_node(
-    s: &mut Storage,
-    json: serde_json::Value,
-    json_lang: Language,
-) -> Result<Node, &'static str> {
-    use serde_json::Value::{Array, Bool, Null, Number, Object, String};
+    fn fragment_wrappers(&self) -> &[(&'static str, &'static str)] {
+        // A sequence of values, or of key-value pairs.
+        &[("[", "]"), ("{", "}")]
+    }
 
-    let make_node = |s: &mut Storage, construct_name: &'static str| -> Result<Node, &'static str> {
-        let construct = json_lang
-            .construct(s, construct_name)
-            .ok_or(construct_name)?;
-        Ok(Node::new(s, construct))
-    };
+    fn split_source(&self, source: &str) -> Option<SplitSource> {
+        // Check that it's valid up front, since the elements won't be parsed until later.
+        serde_json::from_str::<serde::de::IgnoredAny>(source).ok()?;
 
-    match json {
-        Null => make_node(s, "Null"),
-        Bool(false) => make_node(s, "False"),
-        Bool(true) => make_node(s, "True"),
-        String(string) => {
-            let node = make_node(s, "String")?;
-            node.text_mut(s).unwrap().set(string);
-            Ok(node)
-        }
-        Number(n) => {
-            let node = make_node(s, "Number")?;
-            node.text_mut(s).unwrap().set(n.to_string());
-            Ok(node)
-        }
-        Array(array) => {
-            let node = make_node(s, "Array")?;
-            for value in array {
-                let child = json_to_node(s, value, json_lang)?;
-                bug_assert!(
-                    node.insert_last_child(s, child),
-                    "Wrong arity in json Array"
-                );
+        let start = source.find(|ch: char| !ch.is_whitespace())?;
+        let wrapper = match source.as_bytes()[start] {
+            b'[' => ("[", "]"),
+            b'{' => ("{", "}"),
+            _ => return None,
+        };
+        // Since the source is valid json, only commas directly inside the top-level list (and
+        // not inside strings) separate elements.
+        let mut elements = Vec::new();
+        let mut element_start = start + 1;
+        let mut depth = 0;
+        let mut in_string = false;
+        let mut escaped = false;
+        for (i, byte) in source.bytes().enumerate().skip(start) {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if byte == b'\\' {
+                    escaped = true;
+                } else if byte == b'"' {
+                    in_string = false;
+                }
+                continue;
             }
-            Ok(node)
-        }
-        Object(object) => {
-            let node = make_node(s, "Object")?;
-            for (key, value) in object {
-                let key_node = make_node(s, "Key")?;
-                key_node.text_mut(s).unwrap().set(key);
-                let value_node = json_to_node(s, value, json_lang)?;
-                let pair_construct = json_lang.construct(s, "ObjectPair").ok_or("ObjectPair")?;
-                let child = Node::with_children(s, pair_construct, [key_node, value_node])
-                    .bug_msg("Wrong arity in json ObjectPair");
-                bug_assert!(
-                    node.insert_last_child(s, child),
-                    "Wrong arity in json Object"
-                );
+            match byte {
+                b'"' => in_string = true,
+                b'[' | b'{' => depth += 1,
+                b']' | b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        if !source[element_start..i].trim().is_empty() {
+                            elements.push(element_start..i);
+                        }
+                        break;
+                    }
+                }
+                b',' if depth == 1 => {
+                    elements.push(element_start..i);
+                    element_start = i + 1;
+                }
+                _ => (),
             }
-            Ok(node)
         }
+        Some(SplitSource {
+            wrapper,
+            separator: ",",
+            elements,
+        })
+    }
+
+    fn background_parse_fn(&self) -> Option<BackgroundParseFn> {
+        Some(parse_json)
+    }
+}
+
+fn parse_json(file_name: &str, source: &str) -> Result<Vec<ParsedTree>, ParseError> {
+    // Serde json uses 1-indexed positions; we use 0-indexed positions.
+    let json = serde_json::from_str(source).map_err(|err| ParseError {
+        pos: Some(ppp::Pos {
+            row: (err.line() as ppp::Row).saturating_sub(1),
+            col: (err.column() as ppp::Col).saturating_sub(1),
+        }),
+        file_name: file_name.to_owned(),
+        message: format!("{}", err),
+    })?;
+    Ok(vec![json_to_tree(json)])
+}
+
+fn json_to_tree(json: serde_json::Value) -> ParsedTree {
+    use serde_json::Value::{Array, Bool, Null, Number, Object, String};
+
+    match json {
+        Null => ParsedTree::Branch("Null", Vec::new()),
+        Bool(false) => ParsedTree::Branch("False", Vec::new()),
+        Bool(true) => ParsedTree::Branch("True", Vec::new()),
+        String(string) => ParsedTree::Texty("String", string),
+        Number(n) => ParsedTree::Texty("Number", n.to_string()),
+        Array(array) => ParsedTree::Branch("Array", array.into_iter().map(json_to_tree).collect()),
+        Object(object) => ParsedTree::Branch(
+            "Object",
+            object
+                .into_iter()
+                .map(|(key, value)| {
+                    let key_tree = ParsedTree::Texty("Key", key);
+                    ParsedTree::Branch("ObjectPair", vec![key_tree, json_to_tree(value)])
+                })
+                .collect(),
+        ),
     }
 }
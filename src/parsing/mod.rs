@@ -1,10 +1,11 @@
 mod json_parser;
 
-use crate::language::{Arity, Storage};
+use crate::language::{Arity, Language, Storage};
 use crate::tree::Node;
-use crate::util::{bug, error, SynlessError};
+use crate::util::{error, SynlessBug, SynlessError};
 use partial_pretty_printer as ppp;
 use std::fmt;
+use std::ops::Range;
 use std::path::Path;
 
 pub use json_parser::JsonParser;
@@ -18,6 +19,116 @@ pub trait Parse: fmt::Debug {
         file_name: &str,
         source: &str,
     ) -> Result<Node, SynlessError>;
+
+    /// Ways to wrap a fragment of source that isn't a whole document on its own (like `1, 2` in
+    /// json) so that it parses, as `(prefix, suffix)` pairs. The fragment's nodes are then the
+    /// children of the parsed root's first child. Used when pasting text.
+    fn fragment_wrappers(&self) -> &[(&'static str, &'static str)] {
+        &[]
+    }
+
+    /// Split `source` into the elements of its top-level list (like the items of a json array),
+    /// so that a huge doc can be parsed a few elements at a time. Returns `None` if the source
+    /// isn't a single top-level list, or isn't valid: every element must parse on its own once
+    /// wrapped.
+    fn split_source(&self, _source: &str) -> Option<SplitSource> {
+        None
+    }
+
+    /// A function that parses a whole doc without needing a `Storage`, so that it can run on
+    /// another thread. Returns `None` if the parser can't do that.
+    fn background_parse_fn(&self) -> Option<BackgroundParseFn> {
+        None
+    }
+}
+
+/// Parse the source of a doc (whose file name is given for error messages) into the children of
+/// its root. See [`Parse::background_parse_fn`].
+pub type BackgroundParseFn =
+    fn(file_name: &str, source: &str) -> Result<Vec<ParsedTree>, ParseError>;
+
+/// A tree that's been parsed, but not built in a `Storage` yet. Nodes are given by construct name.
+#[derive(Debug, Clone)]
+pub enum ParsedTree {
+    Texty(&'static str, String),
+    Branch(&'static str, Vec<ParsedTree>),
+}
+
+impl ParsedTree {
+    /// The number of nodes in the tree.
+    pub fn num_nodes(&self) -> usize {
+        match self {
+            ParsedTree::Texty(_, _) => 1,
+            ParsedTree::Branch(_, children) => {
+                1 + children.iter().map(ParsedTree::num_nodes).sum::<usize>()
+            }
+        }
+    }
+
+    /// Build the tree in `s`, out of the constructs of `lang`.
+    pub fn build(self, s: &mut Storage, lang: Language) -> Result<Node, SynlessError> {
+        let construct_name = match &self {
+            ParsedTree::Texty(name, _) | ParsedTree::Branch(name, _) => *name,
+        };
+        let construct = lang.construct(s, construct_name).ok_or_else(|| {
+            error!(
+                Parse,
+                "Construct '{construct_name}' missing from {} language spec",
+                lang.name(s)
+            )
+        })?;
+        match self {
+            ParsedTree::Texty(_, text) => Ok(Node::with_text(s, construct, text)?),
+            ParsedTree::Branch(_, children) => {
+                let children = children
+                    .into_iter()
+                    .map(|child| child.build(s, lang))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Node::with_children(s, construct, children)?)
+            }
+        }
+    }
+}
+
+/// Build a doc in `s` out of the children of its root.
+pub fn build_doc(
+    s: &mut Storage,
+    lang: Language,
+    children: Vec<ParsedTree>,
+) -> Result<Node, SynlessError> {
+    s.reserve_nodes(1 + children.iter().map(ParsedTree::num_nodes).sum::<usize>());
+    let children = children
+        .into_iter()
+        .map(|child| child.build(s, lang))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Node::with_children(s, lang.root_construct(s), children)?)
+}
+
+/// A doc's source, split into the elements of its top-level list. See [`Parse::split_source`].
+#[derive(Debug, Clone)]
+pub struct SplitSource {
+    /// How to wrap a run of elements so that it parses as a whole doc, with the elements as the
+    /// children of the root's first child. Like one of the [`Parse::fragment_wrappers`].
+    pub wrapper: (&'static str, &'static str),
+    /// What to put between elements in a run.
+    pub separator: &'static str,
+    /// The byte range of each element in the source.
+    pub elements: Vec<Range<usize>>,
+}
+
+impl SplitSource {
+    /// The source of a doc containing just the elements in `range`.
+    pub fn join_elements(&self, source: &str, range: Range<usize>) -> String {
+        let mut joined = self.wrapper.0.to_owned();
+        for (i, element) in self.elements[range].iter().enumerate() {
+            if i != 0 {
+                joined.push_str(self.separator);
+            }
+            joined.push_str(&source[element.clone()]);
+        }
+        joined.push_str(self.wrapper.1);
+        joined
+    }
 }
 
 /// Convert holes in `source` from `invalid_hole_syntax` to `valid_hole_syntax`, so that they can
@@ -39,13 +150,12 @@ pub fn postprocess(s: &mut Storage, root: Node, hole_text: &str) {
                     false
                 };
                 if should_delete {
-                    let _ = node.detach(s);
-                    node.delete_root(s);
+                    node.detach(s).bug();
+                    node.delete_root(s).bug();
                 } else {
                     let hole = Node::new_hole(s, node.language(s));
-                    if !node.swap(s, hole) {
-                        bug!("Failed to replace node with hole in parser postprocess()")
-                    }
+                    node.swap(s, hole)
+                        .bug_msg("Failed to replace node with hole in parser postprocess()");
                 }
             }
         }
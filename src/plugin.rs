@@ -0,0 +1,437 @@
+//! Plugins are directories in [`plugins_dir()`], each containing a `plugin.ron` manifest (see
+//! [`PluginManifest`]), a rhai module, and optionally some languages. They're loaded on startup,
+//! after `init.rhai`.
+
+use crate::config::config_dir;
+use crate::frontends::Key;
+use crate::keymap::{Keymap, Layer};
+use crate::tree::Mode;
+use crate::util::{error, SynlessError};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const MANIFEST_FILE_NAME: &str = "plugin.ron";
+
+/// A plugin's `plugin.ron`. For example:
+///
+/// ```ron
+/// PluginManifest(
+///     name: "sorting",
+///     description: "Sort the elements of lists",
+///     languages: ["csv_lang.ron"],
+///     commands: [
+///         PluginCommand(function: "sort_list", hint: "SortList"),
+///     ],
+///     keybindings: [
+///         PluginKeybinding(mode: Some("Tree"), keys: "space S", command: "sort_list"),
+///     ],
+/// )
+/// ```
+///
+/// The rhai module is registered under the plugin's name, so other scripts can call its functions
+/// like `sorting::sort_list()`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginManifest {
+    /// Must be a valid rhai identifier, since it's the name of the module.
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// The rhai module's path, relative to the plugin's directory.
+    #[serde(default = "default_module_path")]
+    pub module: String,
+    /// Paths of languages to load, relative to the plugin's directory.
+    #[serde(default)]
+    pub languages: Vec<String>,
+    #[serde(default)]
+    pub commands: Vec<PluginCommand>,
+    #[serde(default)]
+    pub keybindings: Vec<PluginKeybinding>,
+}
+
+/// A function in the plugin's module, taking no arguments, that the user can run from the plugin
+/// command menu or a keybinding.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginCommand {
+    pub function: String,
+    /// A short name for the command, shown in menus and key hints.
+    pub hint: String,
+}
+
+/// Bind a key sequence (like `"C-s"` or `"space S"`) to one of the plugin's commands, in the
+/// keymap for either a mode (`"Tree"` or `"Text"`) or a menu.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginKeybinding {
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub menu: Option<String>,
+    pub keys: String,
+    /// The `function` of one of the plugin's commands.
+    pub command: String,
+}
+
+fn default_module_path() -> String {
+    "plugin.rhai".to_owned()
+}
+
+#[derive(Debug, Clone)]
+pub struct Plugin {
+    pub dir: PathBuf,
+    pub manifest: PluginManifest,
+}
+
+impl Plugin {
+    /// Read and check the manifest of the plugin in `dir`.
+    pub fn load(dir: &Path) -> Result<Plugin, SynlessError> {
+        let path = dir.join(MANIFEST_FILE_NAME);
+        let source = std::fs::read_to_string(&path).map_err(|err| {
+            error!(
+                FileSystem,
+                "Failed to read plugin manifest '{}' ({err})",
+                path.display()
+            )
+        })?;
+        let manifest = ron::from_str::<PluginManifest>(&source)
+            .map_err(|err| error!(Parse, "Invalid plugin manifest '{}': {err}", path.display()))?;
+
+        if !is_identifier(&manifest.name) {
+            return Err(error!(
+                Parse,
+                "Invalid plugin name '{}' in '{}': it must be an identifier",
+                manifest.name,
+                path.display()
+            ));
+        }
+        for command in &manifest.commands {
+            if !is_identifier(&command.function) {
+                return Err(error!(
+                    Parse,
+                    "Invalid command function '{}' in '{}': it must be an identifier",
+                    command.function,
+                    path.display()
+                ));
+            }
+        }
+        Ok(Plugin {
+            dir: dir.to_owned(),
+            manifest,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.manifest.name
+    }
+
+    pub fn module_path(&self) -> PathBuf {
+        self.dir.join(&self.manifest.module)
+    }
+
+    pub fn language_paths(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.manifest
+            .languages
+            .iter()
+            .map(|path| self.dir.join(path))
+    }
+
+    /// The rhai expression for a closure that runs the command `function`. It can only be
+    /// evaluated once the plugin's module has been registered.
+    pub fn command_closure_source(&self, function: &str) -> String {
+        format!("|| {}::{}()", self.manifest.name, function)
+    }
+
+    /// The name of the layer holding the plugin's keybindings.
+    pub fn layer_name(&self) -> String {
+        format!("plugin:{}", self.manifest.name)
+    }
+
+    /// The layer holding the plugin's keybindings. `progs` maps each command's function name to
+    /// the program that runs it.
+    pub fn make_layer(&self, progs: &HashMap<String, rhai::FnPtr>) -> Result<Layer, SynlessError> {
+        let mut mode_keymaps = HashMap::<Mode, Keymap>::new();
+        let mut menu_keymaps = HashMap::<String, Keymap>::new();
+        for binding in &self.manifest.keybindings {
+            let command = self
+                .manifest
+                .commands
+                .iter()
+                .find(|command| command.function == binding.command)
+                .ok_or_else(|| {
+                    error!(
+                        Keymap,
+                        "Plugin '{}' binds '{}' to unknown command '{}'",
+                        self.name(),
+                        binding.keys,
+                        binding.command
+                    )
+                })?;
+            let function = &command.function;
+            let prog = progs
+                .get(function)
+                .cloned()
+                .ok_or_else(|| error!(Keymap, "Plugin command '{function}' wasn't loaded"))?;
+            let keys = binding
+                .keys
+                .split_whitespace()
+                .map(|key_str| {
+                    Key::from_str(key_str).map_err(|err| error!(Keymap, "{err}: {key_str}"))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if keys.is_empty() {
+                return Err(error!(
+                    Keymap,
+                    "Empty key sequence in plugin '{}'",
+                    self.name()
+                ));
+            }
+
+            let keymap = match (&binding.mode, &binding.menu) {
+                (Some(mode_str), None) => {
+                    let mode = Mode::from_str(mode_str)
+                        .map_err(|err| error!(Keymap, "{err}: {mode_str}"))?;
+                    mode_keymaps.entry(mode).or_default()
+                }
+                (None, Some(menu_name)) => menu_keymaps.entry(menu_name.clone()).or_default(),
+                _ => {
+                    return Err(error!(
+                        Keymap,
+                        "Plugin '{}' binds '{}' without exactly one of a mode or menu",
+                        self.name(),
+                        binding.keys
+                    ))
+                }
+            };
+            keymap.bind_key_sequence(&keys, command.hint.clone(), prog, true);
+        }
+
+        let mut layer = Layer::new(self.layer_name());
+        for (mode, keymap) in mode_keymaps {
+            layer.add_mode_keymap(mode, keymap);
+        }
+        for (menu_name, keymap) in menu_keymaps {
+            layer.add_menu_keymap(menu_name, keymap);
+        }
+        Ok(layer)
+    }
+}
+
+/// Where plugins are installed: the `plugins` subdirectory of [`config_dir()`].
+pub fn plugins_dir() -> Option<PathBuf> {
+    Some(config_dir()?.join("plugins"))
+}
+
+/// Find the plugins in the subdirectories of `dir`, in order by directory name. If `dir` doesn't
+/// exist, there are no plugins.
+pub fn discover_plugins(dir: &Path) -> Vec<Result<Plugin, SynlessError>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(err) => {
+            return vec![Err(error!(
+                FileSystem,
+                "Failed to read plugin directory '{}' ({err})",
+                dir.display()
+            ))]
+        }
+    };
+    let mut plugin_dirs = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    plugin_dirs.sort();
+
+    let mut plugins = Vec::new();
+    let mut names = Vec::new();
+    for plugin_dir in plugin_dirs {
+        let plugin = match Plugin::load(&plugin_dir) {
+            Ok(plugin) => plugin,
+            Err(err) => {
+                plugins.push(Err(err));
+                continue;
+            }
+        };
+        if names.contains(&plugin.manifest.name) {
+            plugins.push(Err(error!(
+                Parse,
+                "Plugin name '{}' in '{}' is already taken",
+                plugin.manifest.name,
+                plugin_dir.display()
+            )));
+            continue;
+        }
+        names.push(plugin.manifest.name.clone());
+        plugins.push(Ok(plugin));
+    }
+    plugins
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => {
+            (first.is_ascii_alphabetic() || first == '_')
+                && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::util::ErrorCategory;
+
+    const MANIFEST: &str = r#"
+        PluginManifest(
+            name: "sorting",
+            description: "Sort the elements of lists",
+            languages: ["csv_lang.ron"],
+            commands: [
+                PluginCommand(function: "sort_list", hint: "SortList"),
+            ],
+            keybindings: [
+                PluginKeybinding(mode: Some("Tree"), keys: "space S", command: "sort_list"),
+                PluginKeybinding(menu: Some("sort"), keys: "s", command: "sort_list"),
+            ],
+        )
+    "#;
+
+    /// Make a plugin directory `dir/name` containing the manifest `manifest`.
+    fn write_plugin(dir: &Path, name: &str, manifest: &str) -> PathBuf {
+        let plugin_dir = dir.join(name);
+        std::fs::create_dir_all(&plugin_dir).unwrap();
+        std::fs::write(plugin_dir.join(MANIFEST_FILE_NAME), manifest).unwrap();
+        plugin_dir
+    }
+
+    fn load_error(dir: &Path, manifest: &str) -> SynlessError {
+        let plugin_dir = write_plugin(dir, "bad", manifest);
+        Plugin::load(&plugin_dir).unwrap_err()
+    }
+
+    #[test]
+    fn test_load_manifest() {
+        let temp_dir = TempDir::new("test_load_manifest");
+        let dir = temp_dir.path();
+        let plugin_dir = write_plugin(dir, "sorting", MANIFEST);
+        let plugin = Plugin::load(&plugin_dir).unwrap();
+
+        assert_eq!(plugin.name(), "sorting");
+        assert_eq!(plugin.manifest.description, "Sort the elements of lists");
+        assert_eq!(plugin.module_path(), plugin_dir.join("plugin.rhai"));
+        assert_eq!(
+            plugin.language_paths().collect::<Vec<_>>(),
+            [plugin_dir.join("csv_lang.ron")]
+        );
+        assert_eq!(plugin.manifest.commands[0].hint, "SortList");
+        assert_eq!(plugin.manifest.keybindings[0].mode.as_deref(), Some("Tree"));
+        assert_eq!(plugin.manifest.keybindings[0].menu, None);
+        assert_eq!(plugin.manifest.keybindings[1].menu.as_deref(), Some("sort"));
+        assert_eq!(
+            plugin.command_closure_source("sort_list"),
+            "|| sorting::sort_list()"
+        );
+        assert_eq!(plugin.layer_name(), "plugin:sorting");
+
+        // Everything but the name is optional.
+        let plugin_dir = write_plugin(dir, "minimal", r#"PluginManifest(name: "minimal")"#);
+        let plugin = Plugin::load(&plugin_dir).unwrap();
+        assert_eq!(plugin.manifest.description, "");
+        assert_eq!(plugin.module_path(), plugin_dir.join("plugin.rhai"));
+        assert_eq!(plugin.language_paths().count(), 0);
+        assert!(plugin.manifest.commands.is_empty());
+        assert!(plugin.manifest.keybindings.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_manifests() {
+        let temp_dir = TempDir::new("test_invalid_manifests");
+        let dir = temp_dir.path();
+
+        let err = Plugin::load(&dir.join("missing")).unwrap_err();
+        assert_eq!(err.category, ErrorCategory::FileSystem);
+
+        let err = load_error(dir, "PluginManifest(description: \"no name\")");
+        assert_eq!(err.category, ErrorCategory::Parse);
+        assert!(err.message.starts_with("Invalid plugin manifest"));
+
+        let err = load_error(dir, "PluginManifest(name: \"my-plugin\")");
+        assert_eq!(err.category, ErrorCategory::Parse);
+        assert!(err.message.starts_with("Invalid plugin name 'my-plugin'"));
+
+        let err = load_error(
+            dir,
+            r#"PluginManifest(
+                name: "ok",
+                commands: [PluginCommand(function: "2nd", hint: "Second")],
+            )"#,
+        );
+        assert_eq!(err.category, ErrorCategory::Parse);
+        assert!(err.message.starts_with("Invalid command function '2nd'"));
+    }
+
+    #[test]
+    fn test_make_layer() {
+        let temp_dir = TempDir::new("test_make_layer");
+        let dir = temp_dir.path();
+        let progs = HashMap::from([(
+            "sort_list".to_owned(),
+            rhai::FnPtr::new("sort_list").unwrap(),
+        )]);
+        let make_layer = |keybinding: &str| {
+            let manifest = format!(
+                r#"PluginManifest(
+                    name: "sorting",
+                    commands: [PluginCommand(function: "sort_list", hint: "SortList")],
+                    keybindings: [{keybinding}],
+                )"#
+            );
+            let plugin_dir = write_plugin(dir, "sorting", &manifest);
+            Plugin::load(&plugin_dir).unwrap().make_layer(&progs)
+        };
+
+        let plugin = Plugin::load(&write_plugin(dir, "sorting", MANIFEST)).unwrap();
+        plugin.make_layer(&progs).unwrap();
+        assert!(plugin.make_layer(&HashMap::new()).is_err());
+
+        let errors = [
+            r#"PluginKeybinding(mode: Some("Tree"), keys: "s", command: "nope")"#,
+            r#"PluginKeybinding(mode: Some("Tree"), keys: "", command: "sort_list")"#,
+            r#"PluginKeybinding(mode: Some("Tree"), keys: "NotAKey", command: "sort_list")"#,
+            r#"PluginKeybinding(mode: Some("Nope"), keys: "s", command: "sort_list")"#,
+            r#"PluginKeybinding(keys: "s", command: "sort_list")"#,
+            r#"PluginKeybinding(mode: Some("Tree"), menu: Some("m"), keys: "s", command: "sort_list")"#,
+        ];
+        for keybinding in errors {
+            let err = make_layer(keybinding).unwrap_err();
+            assert_eq!(err.category, ErrorCategory::Keymap, "{keybinding}");
+        }
+    }
+
+    #[test]
+    fn test_discover_plugins() {
+        let temp_dir = TempDir::new("test_discover_plugins");
+        let dir = temp_dir.path();
+        assert!(discover_plugins(&dir.join("missing")).is_empty());
+
+        write_plugin(dir, "b", r#"PluginManifest(name: "second")"#);
+        write_plugin(dir, "a", r#"PluginManifest(name: "first")"#);
+        write_plugin(dir, "c", r#"PluginManifest(name: "first")"#);
+        write_plugin(dir, "d", "not a manifest");
+        std::fs::write(dir.join("e"), "not a directory").unwrap();
+
+        let plugins = discover_plugins(dir);
+        assert_eq!(plugins.len(), 4);
+        assert_eq!(plugins[0].as_ref().unwrap().name(), "first");
+        assert_eq!(plugins[1].as_ref().unwrap().name(), "second");
+        assert!(plugins[2]
+            .as_ref()
+            .unwrap_err()
+            .message
+            .starts_with("Plugin name 'first'"));
+        assert!(plugins[3].is_err());
+    }
+}
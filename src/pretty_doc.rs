@@ -1,9 +1,11 @@
-use crate::engine::Search;
+use crate::engine::{Diagnostic, Search};
 use crate::language::Storage;
-use crate::style::{Condition, CursorKind, Style, StyleLabel, ValidNotation};
-use crate::tree::{Location, Node, NodeId};
+use crate::style::{Condition, CursorKind, Shading, ShadingMode, Style, StyleLabel, ValidNotation};
+use crate::tree::{Location, Node, NodeChange, NodeId};
 use crate::util::{error, SynlessBug, SynlessError};
 use partial_pretty_printer as ppp;
+use partial_pretty_printer::pane;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
 #[derive(thiserror::Error, Debug)]
@@ -19,6 +21,18 @@ pub struct DocRef<'d> {
     node: Node,
     use_source_notation: bool,
     search: Option<&'d Search>,
+    drop_target: Option<Node>,
+    /// The first and last nodes of the selection, if any.
+    selection: Option<(Node, Node)>,
+    secondary_cursors: &'d [Location],
+    diagnostics: &'d [Diagnostic],
+    /// The nodes whose text contains a misspelled word.
+    misspelled_nodes: Option<&'d HashSet<NodeId>>,
+    /// How nodes differ from another version of the doc, if that's being shown.
+    changes: Option<&'d HashMap<NodeId, NodeChange>>,
+    /// Whether to display nodes whose construct is hidden.
+    show_hidden: bool,
+    shading: Shading,
 }
 
 impl<'d> DocRef<'d> {
@@ -27,6 +41,8 @@ impl<'d> DocRef<'d> {
         cursor_loc: Option<Location>,
         node: Node,
         search: &'d Option<Search>,
+        drop_target: Option<Node>,
+        show_hidden: bool,
     ) -> DocRef<'d> {
         DocRef {
             storage,
@@ -34,6 +50,14 @@ impl<'d> DocRef<'d> {
             node,
             use_source_notation: false,
             search: search.as_ref(),
+            drop_target,
+            selection: None,
+            secondary_cursors: &[],
+            diagnostics: &[],
+            misspelled_nodes: None,
+            changes: None,
+            show_hidden,
+            shading: Shading::default(),
         }
     }
 
@@ -48,6 +72,112 @@ impl<'d> DocRef<'d> {
             node,
             use_source_notation: true,
             search: None,
+            drop_target: None,
+            selection: None,
+            secondary_cursors: &[],
+            diagnostics: &[],
+            misspelled_nodes: None,
+            changes: None,
+            show_hidden: true,
+            shading: Shading::default(),
+        }
+    }
+
+    pub fn with_shading(self, shading: Shading) -> DocRef<'d> {
+        DocRef { shading, ..self }
+    }
+
+    pub fn with_selection(self, selection: Option<(Node, Node)>) -> DocRef<'d> {
+        DocRef { selection, ..self }
+    }
+
+    pub fn with_secondary_cursors(self, secondary_cursors: &'d [Location]) -> DocRef<'d> {
+        DocRef {
+            secondary_cursors,
+            ..self
+        }
+    }
+
+    pub fn with_diagnostics(self, diagnostics: &'d [Diagnostic]) -> DocRef<'d> {
+        DocRef {
+            diagnostics,
+            ..self
+        }
+    }
+
+    pub fn with_misspellings(self, misspelled_nodes: &'d HashSet<NodeId>) -> DocRef<'d> {
+        DocRef {
+            misspelled_nodes: Some(misspelled_nodes),
+            ..self
+        }
+    }
+
+    pub fn with_changes(self, changes: &'d HashMap<NodeId, NodeChange>) -> DocRef<'d> {
+        DocRef {
+            changes: Some(changes),
+            ..self
+        }
+    }
+
+    /// Whether one of the secondary cursors is at (or in the text of) this node.
+    fn is_secondary_cursor(self) -> bool {
+        let s = self.storage;
+        self.secondary_cursors
+            .iter()
+            .any(|loc| loc.at_node(s) == Some(self.node) || loc.in_text_node(s) == Some(self.node))
+    }
+
+    /// Whether this node is one of the selected siblings.
+    fn is_selected(self) -> bool {
+        let s = self.storage;
+        match self.selection {
+            None => false,
+            Some((first, last)) => {
+                if first.parent(s) != self.node.parent(s) {
+                    return false;
+                }
+                let index = self.node.sibling_index(s);
+                first.sibling_index(s) <= index && index <= last.sibling_index(s)
+            }
+        }
+    }
+
+    /// This node's background shade level, if it should be shaded.
+    fn shade_level(self) -> Option<usize> {
+        let s = self.storage;
+        match self.shading.mode {
+            ShadingMode::Off => None,
+            ShadingMode::CursorAncestors => {
+                let cursor = self.cursor_loc?;
+                let mut ancestor = match cursor.at_node(s) {
+                    Some(node) => node.parent(s),
+                    None => cursor.parent_node(s),
+                };
+                for level in 0..self.shading.levels {
+                    let node = ancestor?;
+                    if node == self.node {
+                        return Some(level);
+                    }
+                    ancestor = node.parent(s);
+                }
+                None
+            }
+            ShadingMode::Depth => {
+                if self.shading.levels == 0 {
+                    return None;
+                }
+                let mut depth = 0;
+                let mut node = self.node;
+                while let Some(parent) = node.parent(s) {
+                    depth += 1;
+                    node = parent;
+                }
+                if depth == 0 {
+                    None
+                } else {
+                    Some((depth - 1) % self.shading.levels)
+                }
+            }
         }
     }
 }
@@ -79,6 +209,8 @@ impl<'d> ppp::PrettyDoc<'d> for DocRef<'d> {
         } else {
             if construct.is_hole(s) {
                 Ok(lang.hole_display_notation(s))
+            } else if construct.is_hidden(s) && !self.show_hidden {
+                Ok(lang.hidden_display_notation(s))
             } else {
                 Ok(lang.display_notation(s).notation(s, construct))
             }
@@ -129,21 +261,52 @@ impl<'d> ppp::PrettyDoc<'d> for DocRef<'d> {
                 }
             }
             StyleLabel::Close => Style::default(),
+            StyleLabel::Delimiter => {
+                let mut depth = 0;
+                let mut node = self.node;
+                while let Some(parent) = node.parent(self.storage) {
+                    depth += 1;
+                    node = parent;
+                }
+                Style {
+                    delimiter_depth: Some(depth),
+                    ..Style::const_default()
+                }
+            }
             StyleLabel::Properties {
                 fg_color,
                 bg_color,
                 bold,
                 underlined,
+                undercurled,
+                underline_color,
+                italic,
+                dim,
+                strikethrough,
                 priority,
             } => Style {
                 fg_color: fg_color.map(|c| (c, priority)),
                 bg_color: bg_color.map(|c| (c, priority)),
                 bold: bold.map(|b| (b, priority)),
                 underlined: underlined.map(|b| (b, priority)),
+                undercurled: undercurled.map(|b| (b, priority)),
+                underline_color: underline_color.map(|c| (c, priority)),
+                italic: italic.map(|b| (b, priority)),
+                dim: dim.map(|b| (b, priority)),
+                strikethrough: strikethrough.map(|b| (b, priority)),
                 cursor: None,
                 is_hole: false,
                 is_highlighted: false,
+                is_selected: false,
+                is_secondary_cursor: false,
                 is_invalid: false,
+                is_drop_target: false,
+                has_diagnostic: false,
+                is_misspelled: false,
+                change: None,
+                node: None,
+                shade: None,
+                delimiter_depth: None,
             },
         })
     }
@@ -164,14 +327,42 @@ impl<'d> ppp::PrettyDoc<'d> for DocRef<'d> {
             .map(|search| search.highlight && search.matches(self.storage, self.node))
             .unwrap_or(false);
         let is_invalid = self.node.is_invalid_text(self.storage);
+        let is_drop_target = self.drop_target == Some(self.node);
+        let id = self.node.id(self.storage);
+        let has_diagnostic = self.diagnostics.iter().any(|diag| diag.node == id);
+        let is_misspelled = self
+            .misspelled_nodes
+            .is_some_and(|nodes| nodes.contains(&id));
+        let change = self.changes.and_then(|changes| changes.get(&id).copied());
 
-        Ok(Style {
+        let mut style = Style {
             cursor,
             is_hole,
             is_highlighted,
+            is_selected: self.is_selected(),
+            is_secondary_cursor: self.is_secondary_cursor(),
             is_invalid,
+            is_drop_target,
+            has_diagnostic,
+            is_misspelled,
+            change,
+            node: Some(id),
+            shade: self.shade_level(),
             ..Style::const_default()
-        })
+        };
+        let construct = self.node.construct(self.storage);
+        if let Some(style_override) = construct.style_override(self.storage) {
+            style.fg_color = style_override.fg_color;
+            style.bg_color = style_override.bg_color;
+            style.bold = style_override.bold;
+            style.underlined = style_override.underlined;
+            style.undercurled = style_override.undercurled;
+            style.underline_color = style_override.underline_color;
+            style.italic = style_override.italic;
+            style.dim = style_override.dim;
+            style.strikethrough = style_override.strikethrough;
+        }
+        Ok(style)
     }
 
     fn num_children(self) -> Result<Option<usize>, Self::Error> {
@@ -179,7 +370,12 @@ impl<'d> ppp::PrettyDoc<'d> for DocRef<'d> {
     }
 
     fn unwrap_text(self) -> Result<&'d str, Self::Error> {
-        Ok(self.node.text(self.storage).bug().as_str())
+        let text = self.node.text(self.storage).bug();
+        if self.use_source_notation {
+            Ok(text.as_str())
+        } else {
+            Ok(text.as_display_str())
+        }
     }
 
     fn unwrap_child(self, n: usize) -> Result<Self, Self::Error> {
@@ -214,6 +410,101 @@ impl fmt::Debug for DocRef<'_> {
     }
 }
 
+/// The line that each node of a doc starts on, when the whole doc was printed at some width.
+/// This lets the line of any node be found without printing the doc again, as long as the doc
+/// hasn't changed. Any edit makes all of it stale, even the lines of nodes far from the edit.
+/// Made by drawing the doc into a [`NodeLinesRecorder`].
+#[derive(Debug, Default)]
+pub struct NodeLines {
+    /// The first line on which each node's own text (not counting its children's text) was
+    /// drawn. Nodes that don't draw any text of their own are missing.
+    first_lines: HashMap<NodeId, usize>,
+    /// The number of lines that any node's text was drawn on.
+    num_lines: usize,
+}
+
+impl NodeLines {
+    /// The first line that `node` or any of its descendants was drawn on, if any of them were.
+    pub fn first_line(&self, s: &Storage, node: Node) -> Option<usize> {
+        // Children are drawn in order, so only the first child can start above the node's own
+        // text.
+        let own_line = self.first_lines.get(&node.id(s)).copied();
+        let child_line = node
+            .first_child(s)
+            .and_then(|child| self.first_line(s, child));
+        match (own_line, child_line) {
+            (Some(own), Some(child)) => Some(own.min(child)),
+            (line, None) | (None, line) => line,
+        }
+    }
+
+    /// The number of lines the doc took up. Always at least one.
+    pub fn num_lines(&self) -> usize {
+        self.num_lines.max(1)
+    }
+
+    /// The first line of `node`, or else of its nearest ancestor that was drawn at all.
+    pub fn line_of(&self, s: &Storage, node: Node) -> usize {
+        let mut node = node;
+        loop {
+            if let Some(line) = self.first_line(s, node) {
+                return line;
+            }
+            match node.parent(s) {
+                Some(parent) => node = parent,
+                None => return 0,
+            }
+        }
+    }
+}
+
+/// A window that draws nothing, but records the first line that each node was drawn on.
+pub struct NodeLinesRecorder {
+    size: ppp::Size,
+    node_lines: NodeLines,
+}
+
+impl NodeLinesRecorder {
+    pub fn new(size: ppp::Size) -> NodeLinesRecorder {
+        NodeLinesRecorder {
+            size,
+            node_lines: NodeLines::default(),
+        }
+    }
+
+    pub fn finish(self) -> NodeLines {
+        self.node_lines
+    }
+}
+
+impl pane::PrettyWindow for NodeLinesRecorder {
+    type Error = std::convert::Infallible;
+    type Style = Style;
+
+    fn size(&self) -> Result<ppp::Size, Self::Error> {
+        Ok(self.size)
+    }
+
+    fn display_char(
+        &mut self,
+        _ch: char,
+        pos: ppp::Pos,
+        style: &Self::Style,
+        _full_width: bool,
+    ) -> Result<(), Self::Error> {
+        if let Some(id) = style.node {
+            let row = pos.row as usize;
+            self.node_lines.first_lines.entry(id).or_insert(row);
+            self.node_lines.num_lines = self.node_lines.num_lines.max(row + 1);
+        }
+        Ok(())
+    }
+
+    fn set_focus(&mut self, _pos: ppp::Pos) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
 impl From<ppp::PrintingError<PrettyDocError>> for SynlessError {
     fn from(error: ppp::PrintingError<PrettyDocError>) -> SynlessError {
         if let ppp::PrintingError::PrettyDoc(err) = &error {
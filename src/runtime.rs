@@ -1,42 +1,368 @@
+use crate::config::{config_path, user_init_path, Config};
 use crate::engine::{
-    BookmarkCommand, ClipboardCommand, DocDisplayLabel, DocName, Engine, Search, SearchCommand,
-    Settings, TextEdCommand, TextNavCommand, TreeEdCommand, TreeNavCommand,
+    BookmarkCommand, ClipboardCommand, CursorsCommand, Diagnostic, Dictionary, DocDisplayLabel,
+    DocName, Engine, HistoryTreeNode, JsonSchema, LanguageSettings, LineNumbers, Search,
+    SearchCommand, SelectionCommand, Settings, SortChildrenBy, TextEdCommand, TextNavCommand,
+    TreeEdCommand, TreeNavCommand, UndoCache,
+};
+use crate::frontends::{
+    CaptureWindow, Event, Frontend, Key, MouseButton, MouseEvent, MouseEventKind, Overlay,
 };
-use crate::frontends::{Event, Frontend, Key};
 use crate::keymap::{
-    KeyLookupResult, KeyProg, Keymap, Layer, LayerManager, MenuKind, MenuSelectionCmd,
+    CandidateColumn, KeyLookupResult, KeyProg, Keymap, Layer, LayerManager, MenuKind,
+    MenuSelectionCmd,
 };
-use crate::language::{Construct, Language};
-use crate::style::Style;
-use crate::tree::{Mode, Node};
-use crate::util::{error, fs_util, log, LogEntry, LogLevel, SynlessBug, SynlessError};
-use partial_pretty_printer::pane;
+use crate::language::{Arity, Construct, Language, Storage};
+use crate::plugin::Plugin;
+use crate::style::{Base16Color, ColorTheme, Priority, Shading, ShadingMode, Style};
+use crate::tree::{Location, Mode, Node, NodeId};
+use crate::util::{error, fs_util, git, log, LogEntry, LogLevel, SynlessBug, SynlessError};
+use partial_pretty_printer::{pane, Col, Height, Pos, Row, Size, Width};
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::process;
 use std::rc::Rc;
-use std::time::Duration;
+use std::sync::mpsc;
+use std::time::{Duration, Instant, SystemTime};
 
 // TODO: Rename Runtime -> Editor, put it in src/editor.rs?
 
 const KEYHINTS_DOC_LABEL: &str = "keyhints";
 const CANDIDATE_SELECTION_DOC_LABEL: &str = "selection_menu";
+const CANDIDATE_KEYS_LABEL: &str = "candidate_keys";
+const CANDIDATE_ANNOTATIONS_LABEL: &str = "candidate_annotations";
 const MENU_NAME_LABEL: &str = "menu_name";
 const MODE_LABEL: &str = "mode";
 const FILENAME_LABEL: &str = "filename";
 const SIBLING_INDEX_LABEL: &str = "sibling_index";
+const HOLE_COUNT_LABEL: &str = "hole_count";
+const LANGUAGE_LABEL: &str = "language";
+const CURSOR_PATH_LABEL: &str = "cursor_path";
+const PENDING_KEYS_LABEL: &str = "pending_keys";
+
+/// The docs that a status bar set from a script may display.
+const STATUS_BAR_DOC_LABELS: &[&str] = &[
+    MODE_LABEL,
+    FILENAME_LABEL,
+    LANGUAGE_LABEL,
+    CURSOR_PATH_LABEL,
+    SIBLING_INDEX_LABEL,
+    HOLE_COUNT_LABEL,
+    PENDING_KEYS_LABEL,
+    LAST_LOG_LABEL,
+];
 const LAST_LOG_LABEL: &str = "last_log";
+const NOTIFICATIONS_LABEL: &str = "notifications";
+const TAB_BAR_LABEL: &str = "tab_bar";
+const SCROLLBAR_LABEL: &str = "scrollbar";
+const LINE_NUMBERS_LABEL: &str = "line_numbers";
+const UNDO_TREE_LABEL: &str = "undo_tree";
+const LINT_RESULTS_LABEL: &str = "lint_results";
+const TREE_PROMPT_LABEL: &str = "tree_prompt";
+/// The doc shown above the scratch doc, while the scratch split is open.
+const SCRATCH_SPLIT_LABEL: &str = "scratch_split";
+/// The language of scratch docs, until one is set with `Runtime::set_scratch_language()`.
+const DEFAULT_SCRATCH_LANGUAGE: &str = "json";
+/// The layer added to a tree prompt's doc, which should bind keys to confirm or cancel it.
+const TREE_PROMPT_LAYER_NAME: &str = "tree_prompt";
+const COMMAND_HISTORY_DOC_NAME: &str = "command_history";
+const COMMAND_HISTORY_LANGUAGE_NAME: &str = "command_history";
+const COMMAND_HISTORY_LAYER_NAME: &str = "command_history";
+/// Older commands are forgotten.
+const MAX_COMMAND_HISTORY_LEN: usize = 500;
+/// Less recently opened files are forgotten.
+const MAX_RECENT_FILES: usize = 30;
+const FILE_BROWSER_DOC_NAME: &str = "file_browser";
+const FILE_BROWSER_LANGUAGE_NAME: &str = "file_tree";
+const FILE_BROWSER_LAYER_NAME: &str = "file_browser";
+/// The doc shown beside the file browser, while it's open.
+const FILE_BROWSER_SPLIT_LABEL: &str = "file_browser_split";
+/// The older doc of a diff, shown to the left of the visible doc while the diff is open.
+const DIFF_SPLIT_LABEL: &str = "diff_split";
+/// The menu opened when an open file is changed on disk by another program. Its keymap should
+/// call `reload_changed_doc()`, `keep_changed_doc()`, or `merge_changed_doc()`.
+const FILE_CHANGED_MENU_NAME: &str = "file_changed";
 
 const KEYHINTS_PANE_WIDTH: usize = 15;
+const UNDO_TREE_PANE_WIDTH: usize = 24;
+const LINT_RESULTS_PANE_HEIGHT: usize = 6;
+const FILE_BROWSER_PANE_WIDTH: usize = 30;
+/// The floating menu is at most this many rows tall, including its title bar.
+const MENU_OVERLAY_MAX_HEIGHT: Height = 16;
+/// The number of columns of the document left visible on either side of the floating menu.
+const MENU_OVERLAY_MARGIN: Width = 2;
 
 const LOG_LEVEL_TO_DISPLAY: LogLevel = LogLevel::Info;
+/// How long a notification is shown for, unless it's posted with `notify_for()`.
+const NOTIFICATION_TIMEOUT: Duration = Duration::from_secs(5);
+/// The most notifications to show at once. Past this, the oldest are dropped.
+const MAX_NOTIFICATIONS: usize = 4;
+
+// TODO: Make this work if you start in a different cwd
+const THEME_DIR: &str = "themes";
+/// The name of the built-in theme, which doesn't need a file.
+const DEFAULT_THEME_NAME: &str = "default_dark";
+const THEME_FILE_EXTENSIONS: &[&str] = &["ron", "yaml", "yml"];
 
 pub struct Runtime<F: Frontend<Style = Style>> {
     engine: Engine,
-    default_pane_notation: pane::PaneNotation<DocDisplayLabel, Style>,
+    pane_notation: pane::PaneNotation<DocDisplayLabel, Style>,
+    /// Kept so that `pane_notation` can be rebuilt when the scratch split opens or closes.
+    status_bar: pane::PaneNotation<DocDisplayLabel, Style>,
+    /// Drawn on top of `pane_notation`, in the rectangle given by `menu_overlay_rect()`, when a
+    /// menu is open.
     menu_pane_notation: pane::PaneNotation<DocDisplayLabel, Style>,
+    /// Drawn in the same place as `menu_pane_notation`, when a tree prompt is open.
+    tree_prompt_pane_notation: pane::PaneNotation<DocDisplayLabel, Style>,
     frontend: F,
     layers: LayerManager,
     last_log: Option<LogEntry>,
+    /// The messages posted with `notify()` that haven't expired yet, oldest first.
+    notifications: Vec<Notification>,
     cli_args: rhai::Map,
+    /// The node that was under the mouse when the left button was pressed, if any.
+    drag_source: Option<Node>,
+    /// The nodes of the visible doc by id, for finding the node under the mouse while dragging.
+    /// Made on the first drag event, and dropped when the button is released.
+    drag_nodes: Option<HashMap<NodeId, Node>>,
+    /// The number of holes in the visible doc, and the doc version it was counted at, so that
+    /// the doc is only walked again after it changes.
+    hole_count: Option<(u64, usize)>,
+    /// The files of open docs, to notice when they're changed on disk.
+    tracked_files: HashMap<std::path::PathBuf, TrackedFile>,
+    /// The file that the `file_changed` menu was most recently opened for.
+    changed_file: Option<std::path::PathBuf>,
+    tree_prompt: Option<TreePrompt>,
+    incremental_search: Option<IncrementalSearch>,
+    scratch_language: String,
+    /// Where scratch docs are saved between sessions, if they are.
+    scratch_dir: Option<std::path::PathBuf>,
+    scratch_split: Option<ScratchSplit>,
+    /// The programs run by keypresses, most recent first.
+    command_history: VecDeque<CommandRecord>,
+    /// Whether editing commands that are executed should be recorded in the most recent
+    /// `CommandRecord`.
+    recording_commands: bool,
+    command_history_view: Option<CommandHistoryView>,
+    /// Whether to show the visible doc's undo history tree in a pane beside it.
+    show_undo_tree: bool,
+    /// Whether to highlight the nodes of file docs that differ from their git `HEAD` version.
+    show_git_changes: bool,
+    /// Whether to show the problems found by the lint rules in a pane below the visible doc.
+    show_lint_results: bool,
+    /// Panes added with `add_pane_provider()`, in the order they were added.
+    dynamic_panes: Vec<DynamicPane>,
+    /// The roots of the trees that scripts have built with `new_node()` or `copy_node()`. Only
+    /// these may be modified directly; a tree stops being the script's once it's placed in a doc
+    /// with `replace_node()`.
+    script_trees: HashSet<NodeId>,
+    lint_rules: Vec<LintRule>,
+    /// Rhai functions to call with the doc before it's saved by `save_doc()` in the base module.
+    before_save_hooks: Vec<rhai::FnPtr>,
+    /// Rhai functions to call with the doc after it's saved by `save_doc()` in the base module.
+    after_save_hooks: Vec<rhai::FnPtr>,
+    /// Rhai functions to call when editor events happen, by event name. See `on_event()`.
+    event_hooks: HashMap<String, Vec<rhai::FnPtr>>,
+    /// The state of the editor as of the last call to `take_events()`.
+    event_snapshot: EventSnapshot,
+    /// The user's config file, as of when it was last loaded.
+    config: Config,
+    /// The config file's modification time and size when it was last loaded, or `None` if it
+    /// didn't exist. Used to reload it when it changes.
+    config_stamp: Option<(SystemTime, u64)>,
+    config_version: u64,
+    /// The timers added with `set_timeout()` and `set_interval()` that haven't been cleared.
+    timers: Vec<Timer>,
+    next_timer_id: rhai::INT,
+    /// The external commands started with `spawn_command()` whose callbacks haven't been called.
+    commands: Vec<RunningCommand>,
+    next_command_id: rhai::INT,
+    /// The commands declared by loaded plugins, in the order they were loaded.
+    plugin_commands: Vec<PluginCommandEntry>,
+    file_browser: Option<FileBrowser>,
+    diff_view: Option<DiffView>,
+    /// The paths of recently opened files, most recent first.
+    recent_files: VecDeque<String>,
+    /// Where `recent_files` is saved between sessions, if it is.
+    recent_files_path: Option<std::path::PathBuf>,
+}
+
+/// A check on the nodes of docs in one language, added with `add_lint_rule()`. It's a rhai
+/// function, so it's called from `run_lints()` in the base module rather than from Rust.
+struct LintRule {
+    language_name: String,
+    name: String,
+    check: rhai::FnPtr,
+}
+
+/// A pane showing a virtual doc whose contents are made by a rhai function, added with
+/// `add_pane_provider()`. The function is called by `refresh_panes()` in the base module.
+struct DynamicPane {
+    name: String,
+    language_name: String,
+    provider: rhai::FnPtr,
+    /// Where the pane is shown, and its width (or height, for the top and bottom), if it's shown.
+    placement: Option<(PaneSide, usize)>,
+    /// Whether the provider needs to be called again before the pane is next displayed.
+    stale: bool,
+}
+
+/// Which side of the visible doc a dynamic pane is shown on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PaneSide {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// A rhai function to call later, added with `set_timeout()` or `set_interval()`.
+struct Timer {
+    id: rhai::INT,
+    due: Instant,
+    /// How often to repeat, for timers added with `set_interval()`.
+    interval: Option<Duration>,
+    callback: rhai::FnPtr,
+}
+
+/// An external command started with `spawn_command()`, running in the background.
+struct RunningCommand {
+    id: rhai::INT,
+    /// Receives the command's output from the thread waiting on it, once it exits.
+    receiver: mpsc::Receiver<io::Result<process::Output>>,
+    /// The output, once it's been received.
+    output: Option<io::Result<process::Output>>,
+    callback: rhai::FnPtr,
+}
+
+/// A command declared in a plugin's manifest, ready to run.
+struct PluginCommandEntry {
+    plugin: String,
+    hint: String,
+    prog: rhai::FnPtr,
+}
+
+/// A message posted with `notify()`, shown above the status bar until it expires.
+struct Notification {
+    level: LogLevel,
+    message: String,
+    expires: Instant,
+}
+
+/// The longest to wait for an input event before doing periodic work, like checking whether open
+/// files have been changed on disk.
+const MAX_EVENT_WAIT: Duration = Duration::from_secs(1);
+/// How often to check whether external commands, and docs being parsed in the background, have
+/// finished.
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long to spend loading lazily loaded docs before checking for input again.
+const LAZY_LOAD_BUDGET: Duration = Duration::from_millis(20);
+
+/// The names of the events that hooks can be added for with `on_event()`.
+const EVENT_NAMES: [&str; 6] = [
+    "doc_opened",
+    "doc_closed",
+    "cursor_moved",
+    "node_edited",
+    "mode_changed",
+    "config_changed",
+];
+
+/// The parts of the editor's state that events are about. Events are found by comparing
+/// snapshots, so that they don't have to be reported from everywhere that could cause them.
+#[derive(Debug, Default)]
+struct EventSnapshot {
+    file_docs: Vec<DocName>,
+    /// The visible doc, if it's a file doc, with its cursor, mode, and version.
+    visible_doc: Option<(DocName, Location, Mode, u64)>,
+    /// How many times the config file has been loaded.
+    config_version: u64,
+}
+
+/// A small doc that's being edited in a popup, to ask the user for a tree. While it's open, it's
+/// the visible doc.
+struct TreePrompt {
+    description: String,
+    /// The doc that was visible before the prompt was opened, which is shown underneath it.
+    prev_doc_name: Option<DocName>,
+}
+
+/// A search of the visible doc that's updated as its input is typed into a menu, started with
+/// `begin_incremental_search()`.
+struct IncrementalSearch {
+    kind: SearchKind,
+    /// The menu input that the engine's previewed search was made from.
+    input: String,
+}
+
+#[derive(Clone, Copy)]
+enum SearchKind {
+    Substring,
+    Regex,
+    Query,
+}
+
+/// Two docs being compared side by side, opened with `open_diff()`.
+struct DiffView {
+    old_doc_name: DocName,
+    new_doc_name: DocName,
+}
+
+/// The scratch doc, opened with `toggle_scratch()`. While it's open it's the visible doc, and the
+/// doc that was visible before is shown above it.
+struct ScratchSplit {
+    doc_name: DocName,
+    prev_doc_name: Option<DocName>,
+}
+
+/// A keypress that ran a program, as shown in the command history.
+#[derive(Clone)]
+struct CommandRecord {
+    key: Key,
+    label: String,
+    /// The description of the menu the key was pressed in, if any.
+    menu: Option<String>,
+    prog: KeyProg,
+    /// The editing commands that the program executed, as calls to rhai functions in the `s`
+    /// module. Other functions (like those that open menus) aren't recorded.
+    commands: Vec<String>,
+}
+
+impl CommandRecord {
+    fn entry_text(&self) -> String {
+        let key = self.key.to_string();
+        match &self.menu {
+            Some(menu) => format!("{:<8} {} ({})", key, self.label, menu),
+            None => format!("{:<8} {}", key, self.label),
+        }
+    }
+}
+
+/// The command history doc, opened with `open_command_history()`. While it's open, it's the
+/// visible doc.
+struct CommandHistoryView {
+    prev_doc_name: Option<DocName>,
+}
+
+/// The file browser, opened with `toggle_file_browser()`. While it's open its doc is the visible
+/// doc, shown in a pane to the left of the doc that was visible before.
+struct FileBrowser {
+    root_dir: std::path::PathBuf,
+    /// The directories whose contents are shown.
+    expanded: std::collections::HashSet<std::path::PathBuf>,
+    /// The path of each `Dir` and `File` node in the browser's doc.
+    paths: HashMap<NodeId, std::path::PathBuf>,
+    prev_doc_name: Option<DocName>,
+}
+
+struct TrackedFile {
+    language_name: String,
+    /// The file's modification time and length when it was last loaded or saved.
+    stamp: (SystemTime, u64),
+    /// Whether to reload the doc whenever the file changes (for read-only docs opened with
+    /// `watch_doc()`), instead of asking what to do.
+    auto_reload: bool,
 }
 
 impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
@@ -44,16 +370,67 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         let mut engine = Engine::new(settings);
 
         // Magic initialization
-        engine.add_parser("json", crate::parsing::JsonParser);
+        engine.add_builtin_parsers();
+        if let Some(dir) = undo_cache_dir() {
+            engine.set_undo_cache(UndoCache::new(dir));
+        }
+
+        let recent_files_path = recent_files_path();
+        let recent_files = load_recent_files(recent_files_path.as_deref());
 
         Runtime {
             engine,
-            default_pane_notation: make_pane_notation(false),
-            menu_pane_notation: make_pane_notation(true),
+            pane_notation: make_pane_notation(
+                make_default_status_bar(),
+                false,
+                false,
+                false,
+                false,
+                false,
+            ),
+            status_bar: make_default_status_bar(),
+            menu_pane_notation: make_menu_pane_notation(),
+            tree_prompt_pane_notation: make_tree_prompt_pane_notation(),
             frontend,
             layers: LayerManager::new(),
             last_log: None,
+            notifications: Vec::new(),
             cli_args,
+            drag_source: None,
+            drag_nodes: None,
+            hole_count: None,
+            tracked_files: HashMap::new(),
+            changed_file: None,
+            tree_prompt: None,
+            incremental_search: None,
+            scratch_language: DEFAULT_SCRATCH_LANGUAGE.to_owned(),
+            scratch_dir: None,
+            scratch_split: None,
+            command_history: VecDeque::new(),
+            recording_commands: false,
+            command_history_view: None,
+            show_undo_tree: false,
+            show_lint_results: false,
+            dynamic_panes: Vec::new(),
+            script_trees: HashSet::new(),
+            show_git_changes: false,
+            lint_rules: Vec::new(),
+            before_save_hooks: Vec::new(),
+            after_save_hooks: Vec::new(),
+            event_hooks: HashMap::new(),
+            event_snapshot: EventSnapshot::default(),
+            config: Config::default(),
+            config_stamp: None,
+            config_version: 0,
+            timers: Vec::new(),
+            next_timer_id: 1,
+            commands: Vec::new(),
+            next_command_id: 1,
+            plugin_commands: Vec::new(),
+            file_browser: None,
+            diff_view: None,
+            recent_files,
+            recent_files_path,
         }
     }
 
@@ -88,6 +465,72 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         self.layers.close_menu();
     }
 
+    /// Open a popup containing an empty doc in the given language, and make it the visible doc so
+    /// that it can be edited with the usual commands. The `tree_prompt` layer is added to it, and
+    /// should have bindings that call `confirm_tree_prompt()` or `cancel_tree_prompt()`. Scripts
+    /// will usually want to call `s::tree_prompt()` instead, which waits for the result.
+    pub fn open_tree_prompt(
+        &mut self,
+        description: String,
+        language_name: &str,
+    ) -> Result<(), SynlessError> {
+        if self.tree_prompt.is_some() {
+            return Err(error!(Doc, "A tree prompt is already open"));
+        }
+        let doc_name = DocName::Auxilliary(TREE_PROMPT_LABEL.to_owned());
+        self.engine.add_empty_doc(&doc_name, language_name)?;
+        if let Err(err) = self
+            .layers
+            .add_local_layer(&doc_name, TREE_PROMPT_LAYER_NAME)
+        {
+            let _ = self.engine.delete_doc(&doc_name);
+            return Err(err);
+        }
+        let prev_doc_name = self.engine.visible_doc_name().cloned();
+        self.engine.set_visible_doc(&doc_name).bug();
+        self.tree_prompt = Some(TreePrompt {
+            description,
+            prev_doc_name,
+        });
+        Ok(())
+    }
+
+    pub fn has_tree_prompt(&self) -> bool {
+        self.tree_prompt.is_some()
+    }
+
+    /// Close the tree prompt, returning its doc's root node in the form accepted by
+    /// `set_virtual_doc()`.
+    pub fn confirm_tree_prompt(&mut self) -> Result<rhai::Dynamic, SynlessError> {
+        if self.tree_prompt.is_none() {
+            return Err(error!(Doc, "No tree prompt is open"));
+        }
+        let doc_name = DocName::Auxilliary(TREE_PROMPT_LABEL.to_owned());
+        let tree = self.engine.get_doc(&doc_name).map(|doc| {
+            let s = self.engine.raw_storage();
+            virtual_node_to_dynamic(s, doc.cursor().root_node(s))
+        });
+        self.cancel_tree_prompt();
+        tree.ok_or_else(|| error!(Doc, "The tree prompt's doc was closed"))
+    }
+
+    /// Close the tree prompt, if any, discarding its doc.
+    pub fn cancel_tree_prompt(&mut self) {
+        let prompt = match self.tree_prompt.take() {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        let doc_name = DocName::Auxilliary(TREE_PROMPT_LABEL.to_owned());
+        let _ = self
+            .layers
+            .remove_local_layer(&doc_name, TREE_PROMPT_LAYER_NAME);
+        let _ = self.engine.delete_doc(&doc_name);
+        if let Some(prev_doc_name) = prompt.prev_doc_name {
+            // The previous doc might have been closed in the meantime.
+            let _ = self.engine.set_visible_doc(&prev_doc_name);
+        }
+    }
+
     pub fn menu_selection_up(&mut self) -> Result<(), SynlessError> {
         self.layers.edit_menu_selection(MenuSelectionCmd::Up)
     }
@@ -97,7 +540,10 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
     }
 
     pub fn menu_selection_backspace(&mut self) -> Result<(), SynlessError> {
-        self.layers.edit_menu_selection(MenuSelectionCmd::Backspace)
+        self.layers
+            .edit_menu_selection(MenuSelectionCmd::Backspace)?;
+        self.update_incremental_search();
+        Ok(())
     }
 
     /****************
@@ -113,9 +559,14 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         use std::str::FromStr;
 
         let ctrl_c = Key::from_str("C-c").bug();
+        self.compact_nodes_if_sparse();
 
         loop {
-            match self.next_event()? {
+            let event = match self.next_event()? {
+                Some(event) => event,
+                None => return Ok(self.take_deferred().bug()),
+            };
+            match event {
                 // TODO: Remove Ctrl-c. It's only for testing.
                 Event::Key(key) if key == ctrl_c => {
                     return Err(error!(Abort, "I was rudely interrupted by Ctrl-C"));
@@ -127,12 +578,44 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
                     // wait for another key press
                 }
                 Event::Resize => self.display()?,
-                Event::Mouse(_) => (),
-                Event::Paste(_) => (), // TODO: OS paste support
+                Event::Mouse(mouse_event) => {
+                    // A drag that can't be dropped where it was released is an ordinary mistake,
+                    // not a reason to stop waiting for keys.
+                    if let Err(err) = self.handle_mouse(mouse_event) {
+                        self.log_error(err.to_string());
+                        self.display()?;
+                    }
+                }
+                Event::Paste(text) => {
+                    // Outside of text mode, a paste while a menu is open would land in the doc
+                    // behind it.
+                    if !self.layers.has_open_menu() || self.engine.mode() == Mode::Text {
+                        self.paste_text(text)?;
+                        self.display()?;
+                    }
+                }
             }
         }
     }
 
+    /// Reclaim the memory held by deleted nodes, if there's a lot of it. This makes any nodes
+    /// that scripts are holding on to invalid, so it's skipped while scripts have trees of their
+    /// own, or are waiting on external commands (which are often given a node to replace).
+    fn compact_nodes_if_sparse(&mut self) {
+        if !self.script_trees.is_empty() || !self.commands.is_empty() {
+            return;
+        }
+        if let Some(remapping) = self.engine.compact_nodes_if_sparse() {
+            self.drag_source = self.drag_source.and_then(|node| remapping.get(node));
+            self.drag_nodes = None;
+            self.event_snapshot.visible_doc = self.event_snapshot.visible_doc.take().and_then(
+                |(doc_name, loc, mode, version)| {
+                    Some((doc_name, loc.remap(&remapping)?, mode, version))
+                },
+            );
+        }
+    }
+
     /***********
      * Logging *
      ***********/
@@ -175,11 +658,104 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         self.last_log = None;
     }
 
+    /*****************
+     * Notifications *
+     *****************/
+
+    /// Show `message` above the status bar for a few seconds, styled by `level`: "info",
+    /// "warning", or "error". It's also logged.
+    pub fn notify(&mut self, level: &str, message: String) -> Result<(), SynlessError> {
+        let level = notification_level(level)?;
+        self.post_notification(level, message, NOTIFICATION_TIMEOUT);
+        Ok(())
+    }
+
+    /// Like `notify()`, but show the message for `ms` milliseconds.
+    pub fn notify_for(
+        &mut self,
+        level: &str,
+        message: String,
+        ms: rhai::INT,
+    ) -> Result<(), SynlessError> {
+        let level = notification_level(level)?;
+        let ms = u64::try_from(ms)
+            .map_err(|_| error!(Frontend, "Invalid notification timeout: {ms}ms"))?;
+        let timeout = Duration::from_millis(ms);
+        self.post_notification(level, message, timeout);
+        Ok(())
+    }
+
+    /// Show `message` above the status bar until `timeout` has passed, and log it.
+    pub fn post_notification(&mut self, level: LogLevel, message: String, timeout: Duration) {
+        LogEntry::new(level, message.clone()).log();
+        if self.notifications.len() >= MAX_NOTIFICATIONS {
+            self.notifications.remove(0);
+        }
+        self.notifications.push(Notification {
+            level,
+            message,
+            expires: Instant::now() + timeout,
+        });
+    }
+
+    /// Hide all notifications, without waiting for them to expire.
+    pub fn dismiss_notifications(&mut self) {
+        self.notifications.clear();
+    }
+
+    /// Remove the notifications that have expired. Returns whether there were any.
+    fn expire_notifications(&mut self) -> bool {
+        let now = Instant::now();
+        let len = self.notifications.len();
+        self.notifications
+            .retain(|notification| notification.expires > now);
+        self.notifications.len() != len
+    }
+
+    fn next_notification_expiry(&self) -> Option<Instant> {
+        self.notifications.iter().map(|n| n.expires).min()
+    }
+
+    /*************
+     * Profiling *
+     *************/
+
+    /// How long things have been taking, as `#{label, count, mean_us, p95_us}` maps, in
+    /// microseconds. The labels are `display` (redrawing the screen), `layout` (printing the whole
+    /// visible doc, when its layout isn't cached), and `command: KIND` for each kind of editing
+    /// command.
+    pub fn timing_stats(&self) -> Vec<rhai::Dynamic> {
+        let micros = |duration: Duration| duration.as_micros() as rhai::INT;
+        self.engine
+            .timing_stats()
+            .into_iter()
+            .map(|stats| {
+                let mut map = rhai::Map::new();
+                map.insert("label".into(), stats.label.into());
+                map.insert("count".into(), (stats.count as rhai::INT).into());
+                map.insert("mean_us".into(), micros(stats.mean).into());
+                map.insert("p95_us".into(), micros(stats.p95).into());
+                rhai::Dynamic::from(map)
+            })
+            .collect()
+    }
+
+    pub fn clear_timing_stats(&mut self) {
+        self.engine.clear_timing_stats();
+    }
+
     /***********
      * Display *
      ***********/
 
     pub fn display(&mut self) -> Result<(), SynlessError> {
+        let start = Instant::now();
+        let result = self.draw();
+        self.engine.record_timing("display", start.elapsed());
+        result
+    }
+
+    fn draw(&mut self) -> Result<(), SynlessError> {
         self.update_auxilliary_docs();
 
         self.frontend
@@ -187,27 +763,550 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
             .map_err(|err| error!(Frontend, "{}", err))?;
 
         let get_content = |doc_label| self.engine.get_content(doc_label);
-        let note = if self.layers.has_open_menu() {
-            &self.menu_pane_notation
-        } else {
-            &self.default_pane_notation
+        // While a tree prompt is open it's the visible doc, but the doc that was visible before it
+        // is shown underneath.
+        let prev_doc_name = self
+            .tree_prompt
+            .as_ref()
+            .and_then(|prompt| prompt.prev_doc_name.as_ref());
+        let split_doc_name = self
+            .scratch_split
+            .as_ref()
+            .and_then(|split| split.prev_doc_name.as_ref())
+            .or_else(|| {
+                self.file_browser
+                    .as_ref()
+                    .and_then(|browser| browser.prev_doc_name.as_ref())
+            })
+            .or_else(|| self.diff_view.as_ref().map(|diff| &diff.old_doc_name));
+        let get_base_content = |doc_label| match (&doc_label, prev_doc_name) {
+            (DocDisplayLabel::Auxilliary(label), _)
+                if label == SCRATCH_SPLIT_LABEL
+                    || label == FILE_BROWSER_SPLIT_LABEL
+                    || label == DIFF_SPLIT_LABEL =>
+            {
+                // The visible doc (below it) should have the terminal's focus, not this one.
+                let (doc_ref, options) = self.engine.get_doc_content(split_doc_name?)?;
+                let options = pane::PrintingOptions {
+                    set_focus: false,
+                    ..options
+                };
+                Some((doc_ref, options))
+            }
+            (DocDisplayLabel::Visible, Some(doc_name)) => self.engine.get_doc_content(doc_name),
+            (DocDisplayLabel::Visible, None) if self.tree_prompt.is_some() => None,
+            _ => self.engine.get_content(doc_label),
         };
-        pane::display_pane(&mut self.frontend, note, &Style::default(), &get_content)?;
+        pane::display_pane(
+            &mut self.frontend,
+            &self.pane_notation,
+            &Style::default(),
+            &get_base_content,
+        )?;
+
+        if self.tree_prompt.is_some() {
+            let window_size = self
+                .frontend
+                .size()
+                .map_err(|err| error!(Frontend, "{}", err))?;
+            let (offset, size) = menu_overlay_rect(window_size, self.notifications.len());
+            let mut overlay = Overlay::new(&mut self.frontend, offset, size)
+                .map_err(|err| error!(Frontend, "{}", err))?;
+            overlay
+                .clear(&Style::default())
+                .map_err(|err| error!(Frontend, "{}", err))?;
+            pane::display_pane(
+                &mut overlay,
+                &self.tree_prompt_pane_notation,
+                &Style::default(),
+                &get_content,
+            )?;
+        }
+
+        if self.layers.has_open_menu() {
+            let window_size = self
+                .frontend
+                .size()
+                .map_err(|err| error!(Frontend, "{}", err))?;
+            let (offset, size) = menu_overlay_rect(window_size, self.notifications.len());
+            let mut overlay = Overlay::new(&mut self.frontend, offset, size)
+                .map_err(|err| error!(Frontend, "{}", err))?;
+            overlay
+                .clear(&Style::default())
+                .map_err(|err| error!(Frontend, "{}", err))?;
+            pane::display_pane(
+                &mut overlay,
+                &self.menu_pane_notation,
+                &Style::default(),
+                &get_content,
+            )?;
+        }
 
         self.frontend
             .end_frame()
             .map_err(|err| error!(Frontend, "{}", err))
     }
 
+    /// Replace the status bar at the bottom of the window. It may only display the docs listed in
+    /// `STATUS_BAR_DOC_LABELS`.
+    pub fn set_status_bar(&mut self, status_bar: PaneBuilder) -> Result<(), SynlessError> {
+        for label in &status_bar.doc_labels {
+            if !STATUS_BAR_DOC_LABELS.contains(&label.as_str()) {
+                return Err(error!(
+                    Frontend,
+                    "The status bar can't display '{label}'. Try one of: {}",
+                    STATUS_BAR_DOC_LABELS.join(", ")
+                ));
+            }
+        }
+        self.status_bar = status_bar.notation;
+        self.update_pane_notation();
+        Ok(())
+    }
+
+    fn update_pane_notation(&mut self) {
+        let show_split = self
+            .scratch_split
+            .as_ref()
+            .map(|split| split.prev_doc_name.is_some())
+            .unwrap_or(false);
+        let show_file_browser = self
+            .file_browser
+            .as_ref()
+            .map(|browser| browser.prev_doc_name.is_some())
+            .unwrap_or(false);
+        self.pane_notation = make_pane_notation(
+            self.status_bar.clone(),
+            show_split,
+            self.show_undo_tree,
+            self.show_lint_results,
+            &self.dynamic_pane_placements(),
+            self.diff_view.is_some(),
+            show_file_browser,
+        );
+    }
+
+    /// Toggle showing a scrollbar next to the visible doc.
+    pub fn toggle_scrollbar(&mut self) {
+        if self.engine.toggle_scrollbar() {
+            self.log_info("Showing scrollbar".to_owned());
+        } else {
+            self.log_info("Hiding scrollbar".to_owned());
+        }
+    }
+
+    /// Set how to number the lines of the visible doc: one of `"off"`, `"absolute"`, or
+    /// `"relative"` (to the cursor's line).
+    pub fn set_line_numbers(&mut self, mode: &str) -> Result<(), SynlessError> {
+        let line_numbers = match mode {
+            "off" => LineNumbers::Off,
+            "absolute" => LineNumbers::Absolute,
+            "relative" => LineNumbers::Relative,
+            _ => return Err(error!(Frontend, "Unknown line number mode '{mode}'")),
+        };
+        self.engine.set_line_numbers(line_numbers);
+        Ok(())
+    }
+
+    /// Set which nodes of the visible doc to shade the background of: `"off"`,
+    /// `"cursor_ancestors"`, or `"depth"`, with `levels` shade levels.
+    pub fn set_shading(&mut self, mode: &str, levels: rhai::INT) -> Result<(), SynlessError> {
+        let mode = match mode {
+            "off" => ShadingMode::Off,
+            "cursor_ancestors" => ShadingMode::CursorAncestors,
+            "depth" => ShadingMode::Depth,
+            _ => return Err(error!(Frontend, "Unknown shading mode '{mode}'")),
+        };
+        let levels = usize::try_from(levels)
+            .map_err(|_| error!(Frontend, "Invalid number of shade levels: {levels}"))?;
+        self.engine.set_shading(Shading { mode, levels });
+        Ok(())
+    }
+
+    /// Set how many widths to remember the visible doc's layout at, to make switching between
+    /// pane sizes faster when the scrollbar or line numbers are shown. 0 turns off the cache.
+    pub fn set_layout_cache_size(&mut self, size: rhai::INT) -> Result<(), SynlessError> {
+        let size = usize::try_from(size)
+            .map_err(|_| error!(Frontend, "Invalid layout cache size: {size}"))?;
+        self.engine.set_layout_cache_size(size);
+        Ok(())
+    }
+
+    /// Set how many bytes long a file must be to be loaded lazily when it's opened, parsing just
+    /// enough of it to fill the screen at first. 0 turns off lazy loading.
+    pub fn set_lazy_load_size(&mut self, size: rhai::INT) -> Result<(), SynlessError> {
+        let size = usize::try_from(size)
+            .map_err(|_| error!(Frontend, "Invalid lazy load size: {size}"))?;
+        self.engine.set_lazy_load_size(size);
+        Ok(())
+    }
+
+    /// Override settings for docs in the language `language_name`. `settings` is a map with any of
+    /// `max_source_width`, `max_display_width`, `display_notation`, and `source_notation`. This
+    /// replaces the language's earlier overrides, including any from the config file.
+    pub fn set_language_settings(
+        &mut self,
+        language_name: &str,
+        settings: rhai::Map,
+    ) -> Result<(), SynlessError> {
+        let mut language_settings = LanguageSettings::default();
+        for (key, value) in settings {
+            let type_error =
+                || error!(Language, "set_language_settings: invalid value for '{key}'");
+            match key.as_str() {
+                "max_source_width" | "max_display_width" => {
+                    let width = value
+                        .as_int()
+                        .ok()
+                        .and_then(|width| Width::try_from(width).ok())
+                        .ok_or_else(type_error)?;
+                    if key.as_str() == "max_source_width" {
+                        language_settings.max_source_width = Some(width);
+                    } else {
+                        language_settings.max_display_width = Some(width);
+                    }
+                }
+                "display_notation" => {
+                    let name = value.into_string().map_err(|_| type_error())?;
+                    language_settings.display_notation = Some(name);
+                }
+                "source_notation" => {
+                    let name = value.into_string().map_err(|_| type_error())?;
+                    language_settings.source_notation = Some(name);
+                }
+                _ => {
+                    return Err(error!(
+                        Language,
+                        "set_language_settings: unknown setting '{key}'"
+                    ))
+                }
+            }
+        }
+        self.engine
+            .set_language_settings(language_name, language_settings)?;
+        self.display()
+    }
+
+    /// Style every node of the given construct with `style`, overriding what its notation
+    /// specifies. `style` is a map in the same format as for `pane_style()`.
+    pub fn set_construct_style(
+        &mut self,
+        language_name: &str,
+        construct_name: &str,
+        style: rhai::Map,
+    ) -> Result<(), SynlessError> {
+        let construct = self.get_construct(language_name, construct_name)?;
+        // High priority, so that it beats the styles in the notation.
+        let style = style_from_map("set_construct_style", style, Priority::High)?;
+        construct.set_style_override(self.engine.raw_storage_mut(), Some(style));
+        Ok(())
+    }
+
+    /// Change how nodes of the given construct are summarized, overriding the language's
+    /// `summary`. See [`ConstructSpec::summary`](crate::language::ConstructSpec::summary) for the
+    /// template format.
+    pub fn set_construct_summary(
+        &mut self,
+        language_name: &str,
+        construct_name: &str,
+        template: String,
+    ) -> Result<(), SynlessError> {
+        let construct = self.get_construct(language_name, construct_name)?;
+        construct.set_summary_template(self.engine.raw_storage_mut(), Some(template));
+        Ok(())
+    }
+
+    /// Undo `set_construct_style`, going back to the style given by the construct's notation.
+    pub fn clear_construct_style(
+        &mut self,
+        language_name: &str,
+        construct_name: &str,
+    ) -> Result<(), SynlessError> {
+        let construct = self.get_construct(language_name, construct_name)?;
+        construct.set_style_override(self.engine.raw_storage_mut(), None);
+        Ok(())
+    }
+
+    fn get_construct(
+        &self,
+        language_name: &str,
+        construct_name: &str,
+    ) -> Result<Construct, SynlessError> {
+        let language = self.engine.get_language(language_name)?;
+        let s = self.engine.raw_storage();
+        language.construct(s, construct_name).ok_or_else(|| {
+            error!(
+                Language,
+                "No construct '{construct_name}' in language '{language_name}'"
+            )
+        })
+    }
+
+    /// Switch to the color theme called `name`, loaded from a `.ron` file or a Base16 `.yaml`
+    /// file in the theme directory, and redisplay with it.
+    pub fn set_theme(&mut self, name: &str) -> Result<(), SynlessError> {
+        self.use_theme(name)?;
+        self.display()
+    }
+
+    fn use_theme(&mut self, name: &str) -> Result<(), SynlessError> {
+        let theme = if name == DEFAULT_THEME_NAME {
+            ColorTheme::default_dark()
+        } else {
+            load_theme(name)?
+        };
+        self.frontend
+            .set_color_theme(theme)
+            .map_err(|err| error!(Frontend, "{}", err))
+    }
+
+    /// The names of the available color themes: the built-in one, and one per file in the theme
+    /// directory.
+    pub fn theme_names(&self) -> Vec<rhai::Dynamic> {
+        let mut names = vec![DEFAULT_THEME_NAME.to_owned()];
+        if let Ok(entries) = std::fs::read_dir(THEME_DIR) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_theme = path
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| THEME_FILE_EXTENSIONS.contains(&ext));
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    if is_theme && !names.iter().any(|n| n == name) {
+                        names.push(name.to_owned());
+                    }
+                }
+            }
+        }
+        names[1..].sort();
+        names.into_iter().map(rhai::Dynamic::from).collect()
+    }
+
+    /// Toggle displaying nodes whose construct is hidden.
+    pub fn toggle_hidden_nodes(&mut self) {
+        if self.engine.toggle_hidden_nodes() {
+            self.log_info("Showing hidden nodes".to_owned());
+        } else {
+            self.log_info("Hiding hidden nodes".to_owned());
+        }
+    }
+
+    /// The visible doc's hidden nodes, as `#{label, index}` maps. Pass an `index` to
+    /// `goto_hidden_node()` to jump to that node.
+    pub fn hidden_nodes(&self) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        let s = self.engine.raw_storage();
+        Ok(self
+            .engine
+            .hidden_nodes()?
+            .into_iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let mut map = rhai::Map::new();
+                let label = format!("{}: {}", i, node.display(s));
+                map.insert("label".into(), label.into());
+                map.insert("index".into(), (i as rhai::INT).into());
+                map.into()
+            })
+            .collect())
+    }
+
+    pub fn goto_hidden_node(&mut self, index: rhai::INT) -> Result<(), SynlessError> {
+        let node = usize::try_from(index)
+            .ok()
+            .and_then(|i| self.engine.hidden_nodes().ok()?.get(i).copied())
+            .ok_or_else(|| error!(Edit, "No hidden node at index {index}"))?;
+        self.engine.execute(TreeNavCommand::GotoNode(node))
+    }
+
+    /// Compare the file at `new_path` to the older version of it at `old_path`, side by side. Both
+    /// are opened if they aren't already, and `new_path` becomes the visible doc. Deleted nodes
+    /// are highlighted in red, inserted ones in green, and modified ones in cyan. Closes any
+    /// other diff first.
+    pub fn open_diff(&mut self, old_path: &str, new_path: &str) -> Result<(), SynlessError> {
+        use std::path::PathBuf;
+
+        let old_doc_name = DocName::File(PathBuf::from(old_path));
+        let new_doc_name = DocName::File(PathBuf::from(new_path));
+        if old_doc_name == new_doc_name {
+            return Err(error!(Doc, "Can't diff '{old_path}' with itself"));
+        }
+        self.close_diff();
+        self.open_or_switch_to_doc(old_path)?;
+        self.open_or_switch_to_doc(new_path)?;
+        self.engine.finish_loading(&old_doc_name)?;
+        self.engine.finish_loading(&new_doc_name)?;
+        let count = self.engine.mark_diff(&old_doc_name, &new_doc_name)?;
+        self.diff_view = Some(DiffView {
+            old_doc_name,
+            new_doc_name,
+        });
+        self.update_pane_notation();
+        if count == 0 {
+            self.log_info("No differences".to_owned());
+        } else {
+            self.log_info(format!("{count} differences"));
+        }
+        Ok(())
+    }
+
+    /// Close the diff opened with `open_diff()`, if any, and clear its highlighting. Returns
+    /// whether there was one.
+    pub fn close_diff(&mut self) -> bool {
+        let diff = match self.diff_view.take() {
+            Some(diff) => diff,
+            None => return false,
+        };
+        // Either doc may have been closed since.
+        let _ = self.engine.mark_changes_since(&diff.old_doc_name, None);
+        let _ = self.engine.mark_changes_since(&diff.new_doc_name, None);
+        self.update_pane_notation();
+        true
+    }
+
+    /// Merge the files at `ours_path` and `theirs_path`, which were both edited starting from the
+    /// file at `base_path`, into a new doc for `output_path` (which isn't read), and make it the
+    /// visible doc. All four paths must have the same language. Conflicts are highlighted in
+    /// purple, holding our version until resolved with `resolve_conflict()`.
+    pub fn open_merge(
+        &mut self,
+        base_path: &str,
+        ours_path: &str,
+        theirs_path: &str,
+        output_path: &str,
+    ) -> Result<(), SynlessError> {
+        use std::path::PathBuf;
+
+        let output_path_buf = PathBuf::from(output_path);
+        let language_name = self.language_name_from_file_extension(&output_path_buf)?;
+        let read = |path: &str| {
+            std::fs::read_to_string(path)
+                .map_err(|err| error!(FileSystem, "Failed to read file at '{path}' ({err})"))
+        };
+        let base_source = read(base_path)?;
+        let ours_source = read(ours_path)?;
+        let theirs_source = read(theirs_path)?;
+        let doc_name = DocName::File(output_path_buf);
+        let count = self.engine.merge_sources(
+            &doc_name,
+            &language_name,
+            &base_source,
+            &ours_source,
+            &theirs_source,
+        )?;
+        self.engine.set_visible_doc(&doc_name)?;
+        if count == 0 {
+            self.log_info("Merged without conflicts".to_owned());
+        } else {
+            self.log_info(format!("{count} conflicts"));
+            self.goto_next_conflict()?;
+        }
+        Ok(())
+    }
+
+    /// Move the cursor to the next unresolved merge conflict in the visible doc.
+    pub fn goto_next_conflict(&mut self) -> Result<(), SynlessError> {
+        let node = self
+            .engine
+            .next_conflict_node()?
+            .ok_or_else(|| error!(Edit, "No unresolved conflicts"))?;
+        self.engine.execute(TreeNavCommand::GotoNode(node))
+    }
+
+    /// Resolve the merge conflict at the cursor by taking one side's version, either `"ours"` or
+    /// `"theirs"`.
+    pub fn resolve_conflict(&mut self, side: &str) -> Result<(), SynlessError> {
+        let take_ours = match side {
+            "ours" => true,
+            "theirs" => false,
+            _ => {
+                return Err(error!(
+                    Edit,
+                    "Conflict side must be 'ours' or 'theirs', not '{side}'"
+                ))
+            }
+        };
+        self.engine.resolve_conflict(take_ours)?;
+        match self.engine.unresolved_conflicts()?.len() {
+            0 => self.log_info("All conflicts resolved".to_owned()),
+            count => self.log_info(format!("{count} conflicts remaining")),
+        }
+        Ok(())
+    }
+
+    /// The number of merge conflicts in the visible doc that haven't been resolved.
+    pub fn num_unresolved_conflicts(&self) -> Result<rhai::INT, SynlessError> {
+        Ok(self.engine.unresolved_conflicts()?.len() as rhai::INT)
+    }
+
+    /// Toggle highlighting the nodes of file docs that were added (in green) or modified (in
+    /// cyan) since the git `HEAD` version of the file. They're compared when the file is opened or
+    /// saved.
+    pub fn toggle_git_changes(&mut self) {
+        self.show_git_changes = !self.show_git_changes;
+        for doc_name in self.engine.file_doc_names() {
+            self.update_git_changes(&doc_name);
+        }
+        if self.show_git_changes {
+            self.log_info("Showing git changes".to_owned());
+        } else {
+            self.log_info("Hiding git changes".to_owned());
+        }
+    }
+
+    /// Compare the doc to its git `HEAD` version, if git changes are being shown. Failures are
+    /// logged rather than returned, so that they don't get in the way of opening or saving.
+    fn update_git_changes(&mut self, doc_name: &DocName) {
+        let old_source = match doc_name {
+            DocName::File(path) if self.show_git_changes => match git::head_source(path) {
+                Ok(old_source) => old_source,
+                Err(err) => {
+                    log!(Warn, "{}", err);
+                    None
+                }
+            },
+            _ => None,
+        };
+        if let Err(err) = self
+            .engine
+            .mark_changes_since(doc_name, old_source.as_deref())
+        {
+            log!(Warn, "Failed to compare with git HEAD: {}", err);
+        }
+    }
+
+    /// Toggle previewing the visible doc at the width it will be saved at.
+    pub fn toggle_width_preview(&mut self) {
+        if self.engine.toggle_width_preview() {
+            self.log_info("Previewing saved width".to_owned());
+        } else {
+            self.log_info("Stopped previewing saved width".to_owned());
+        }
+    }
+
     fn update_auxilliary_docs(&mut self) {
         for (name, node) in [
             self.make_keyhint_doc(),
             self.make_candidate_selection_doc(),
+            self.make_candidate_column_doc(CANDIDATE_KEYS_LABEL, CandidateColumn::Keys),
+            self.make_candidate_column_doc(
+                CANDIDATE_ANNOTATIONS_LABEL,
+                CandidateColumn::Annotations,
+            ),
             self.make_menu_name_doc(),
             self.make_mode_doc(),
             self.make_filename_doc(),
             self.make_sibling_index_doc(),
+            self.make_language_doc(),
+            self.make_cursor_path_doc(),
+            self.make_hole_count_doc(),
+            self.make_pending_keys_doc(),
             self.make_last_log_doc(),
+            self.make_notifications_doc(),
+            self.make_tab_bar_doc(),
+            self.make_scrollbar_doc(),
+            self.make_line_numbers_doc(),
+            self.make_undo_tree_doc(),
+            self.make_lint_results_doc(),
         ] {
             let _ = self.engine.delete_doc(&name);
             if let Some(node) = node {
@@ -227,6 +1326,16 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         )
     }
 
+    fn make_candidate_column_doc(
+        &mut self,
+        label: &str,
+        column: CandidateColumn,
+    ) -> (DocName, Option<Node>) {
+        let storage = self.engine.raw_storage_mut();
+        let node = self.layers.make_candidate_column_doc(storage, column);
+        (DocName::Auxilliary(label.to_owned()), node)
+    }
+
     fn make_keyhint_doc(&mut self) -> (DocName, Option<Node>) {
         let visible_doc_name = self.engine.visible_doc_name().cloned();
         let mode = self.engine.mode();
@@ -238,10 +1347,12 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
     }
 
     fn make_menu_name_doc(&mut self) -> (DocName, Option<Node>) {
-        let opt_node = self
-            .layers
-            .menu_description()
-            .map(|menu_name| self.engine.make_string_doc(menu_name.to_owned(), None));
+        let opt_description = self.layers.menu_description().or(self
+            .tree_prompt
+            .as_ref()
+            .map(|prompt| prompt.description.as_str()));
+        let opt_node = opt_description
+            .map(|description| self.engine.make_string_doc(description.to_owned(), None));
         (DocName::Auxilliary(MENU_NAME_LABEL.to_owned()), opt_node)
     }
 
@@ -269,7 +1380,9 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
                 }
             }
             DocName::Metadata(label) => format!("metadata:{}", label),
+            DocName::Virtual(name) => name.to_owned(),
             DocName::Auxilliary(label) => format!("auxilliary:{}", label),
+            DocName::Scratch(language_name) => format!("scratch:{}", language_name),
         });
         let opt_node = opt_label.map(|label| self.engine.make_string_doc(label, None));
         (DocName::Auxilliary(FILENAME_LABEL.to_owned()), opt_node)
@@ -289,25 +1402,316 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         )
     }
 
-    fn make_last_log_doc(&mut self) -> (DocName, Option<Node>) {
-        let opt_message = self.last_log.as_ref().map(|entry| entry.to_string());
-        let opt_node = opt_message.map(|msg| self.engine.make_string_doc(msg, None));
-        (DocName::Auxilliary(LAST_LOG_LABEL.to_owned()), opt_node)
+    fn make_hole_count_doc(&mut self) -> (DocName, Option<Node>) {
+        let opt_num_holes = self.engine.visible_doc().map(|doc| {
+            let num_holes = match self.hole_count {
+                Some((version, num_holes)) if version == doc.version() => num_holes,
+                _ => doc.num_holes(self.engine.raw_storage()),
+            };
+            self.hole_count = Some((doc.version(), num_holes));
+            num_holes
+        });
+        let opt_label = opt_num_holes
+            .filter(|num_holes| *num_holes > 0)
+            .map(|num_holes| format!("holes {}", num_holes));
+        let opt_node = opt_label.map(|label| self.engine.make_string_doc(label, None));
+        (DocName::Auxilliary(HOLE_COUNT_LABEL.to_owned()), opt_node)
     }
 
-    /******************
-     * Doc Management *
-     ******************/
+    /// The keys typed so far of a key sequence (like `g g`), while the user is partway through it.
+    fn make_pending_keys_doc(&mut self) -> (DocName, Option<Node>) {
+        let keys = self.layers.pending_key_sequence();
+        let opt_node = if keys.is_empty() {
+            None
+        } else {
+            let label = keys
+                .iter()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            Some(self.engine.make_string_doc(label, None))
+        };
+        (DocName::Auxilliary(PENDING_KEYS_LABEL.to_owned()), opt_node)
+    }
 
-    /// If there is a visible doc, return the directory it's in. Fall back to the cwd.
-    pub fn current_dir(&self) -> Result<String, SynlessError> {
-        if let Some(DocName::File(path)) = self.engine.visible_doc_name() {
-            if let Some(parent_path) = path.parent() {
-                return fs_util::path_to_string(parent_path);
+    fn make_language_doc(&mut self) -> (DocName, Option<Node>) {
+        let opt_label = self.engine.visible_doc().map(|doc| {
+            let s = self.engine.raw_storage();
+            let root = doc.cursor().root_node(s);
+            root.language(s).name(s).to_owned()
+        });
+        let opt_node = opt_label.map(|label| self.engine.make_string_doc(label, None));
+        (DocName::Auxilliary(LANGUAGE_LABEL.to_owned()), opt_node)
+    }
+
+    /// The summaries of the node at the cursor and its ancestors, like `Root > Array > Null`.
+    fn make_cursor_path_doc(&mut self) -> (DocName, Option<Node>) {
+        let opt_label = self.engine.visible_doc().map(|doc| {
+            let s = self.engine.raw_storage();
+            let mut summaries = Vec::new();
+            let mut opt_node = doc
+                .node_at_cursor(s)
+                .ok()
+                .or_else(|| doc.cursor().parent_node(s));
+            while let Some(node) = opt_node {
+                summaries.push(node.summary(s));
+                opt_node = node.parent(s);
             }
-        }
+            summaries.reverse();
+            summaries.join(" > ")
+        });
+        let opt_node = opt_label.map(|label| self.engine.make_string_doc(label, None));
+        (DocName::Auxilliary(CURSOR_PATH_LABEL.to_owned()), opt_node)
+    }
 
-        let cwd = std::env::current_dir().map_err(|err| {
+    fn make_scrollbar_doc(&mut self) -> (DocName, Option<Node>) {
+        let opt_node = self.main_doc_pane_size().and_then(|(width, height)| {
+            // A failure to print the doc will show up when displaying it, so don't report it here.
+            self.engine
+                .make_scrollbar_doc(width, height)
+                .unwrap_or(None)
+        });
+        (DocName::Auxilliary(SCROLLBAR_LABEL.to_owned()), opt_node)
+    }
+
+    fn make_line_numbers_doc(&mut self) -> (DocName, Option<Node>) {
+        let opt_node = self.main_doc_pane_size().and_then(|(width, height)| {
+            self.engine
+                .make_line_numbers_doc(width, height)
+                .unwrap_or(None)
+        });
+        (DocName::Auxilliary(LINE_NUMBERS_LABEL.to_owned()), opt_node)
+    }
+
+    /// Draw the visible doc's undo history tree, if it's being shown. Only the rows around the
+    /// current state are included if it's too tall for the pane.
+    fn make_undo_tree_doc(&mut self) -> (DocName, Option<Node>) {
+        let doc_name = DocName::Auxilliary(UNDO_TREE_LABEL.to_owned());
+        if !self.show_undo_tree {
+            return (doc_name, None);
+        }
+        let (nodes, current) = match self.engine.undo_history_tree() {
+            Ok(tree) => tree,
+            Err(_) => return (doc_name, None),
+        };
+        let mut rows = undo_tree_rows(&nodes, current, Instant::now());
+        if let Some((_, height)) = self.main_doc_pane_size() {
+            if rows.len() > height {
+                let current_row = rows.iter().position(|(_, is_current)| *is_current);
+                let start = current_row
+                    .unwrap_or(0)
+                    .saturating_sub(height / 2)
+                    .min(rows.len() - height);
+                rows = rows.drain(start..start + height).collect();
+            }
+        }
+        (doc_name, Some(self.engine.make_undo_tree_doc(rows)))
+    }
+
+    fn make_lint_results_doc(&mut self) -> (DocName, Option<Node>) {
+        let doc_name = DocName::Auxilliary(LINT_RESULTS_LABEL.to_owned());
+        if !self.show_lint_results {
+            return (doc_name, None);
+        }
+        (doc_name, self.engine.make_lint_results_doc())
+    }
+
+    /// The width and height of the pane that the visible doc is displayed in. The doc sits between
+    /// the line numbers and the scrollbar (and the undo tree, if shown), and spans the main region
+    /// of the window (less the lint results, if shown). See `main_region_rect()` and
+    /// `make_pane_notation()`.
+    fn main_doc_pane_size(&self) -> Option<(Width, usize)> {
+        let window_size = self.frontend.size().ok()?;
+        let (_, region_size) = main_region_rect(window_size, self.notifications.len());
+        let mut width = region_size
+            .width
+            .saturating_sub(self.engine.line_number_gutter_width() + self.engine.scrollbar_width());
+        if self.diff_view.is_some() {
+            // Roughly half of it goes to the older doc, and one column to the divider.
+            width = width.saturating_sub(1) / 2;
+        }
+        if self.show_undo_tree {
+            width = width.saturating_sub(UNDO_TREE_PANE_WIDTH as Width + 1);
+        }
+        if self.file_browser.is_some() {
+            width = width.min(
+                (FILE_BROWSER_PANE_WIDTH as Width)
+                    .saturating_sub(self.engine.line_number_gutter_width()),
+            );
+        }
+        let mut height = region_size.height as usize;
+        if self.scratch_split.is_some() {
+            // Roughly half of it goes to the doc above the scratch doc, and one row to the divider.
+            height = height.saturating_sub(1) / 2;
+        }
+        if self.show_lint_results {
+            height = height.saturating_sub(LINT_RESULTS_PANE_HEIGHT + 1);
+        }
+        for (_, side, size) in self.dynamic_pane_placements() {
+            match side {
+                PaneSide::Left | PaneSide::Right => {
+                    width = width.saturating_sub(size as Width + 1);
+                }
+                PaneSide::Top | PaneSide::Bottom => height = height.saturating_sub(size + 1),
+            }
+        }
+        Some((width, height))
+    }
+
+    /// The names of the dynamic panes that are shown, with where they're shown.
+    fn dynamic_pane_placements(&self) -> Vec<(String, PaneSide, usize)> {
+        self.dynamic_panes
+            .iter()
+            .filter_map(|pane| {
+                let (side, size) = pane.placement?;
+                Some((pane.name.clone(), side, size))
+            })
+            .collect()
+    }
+
+    fn make_tab_bar_doc(&mut self) -> (DocName, Option<Node>) {
+        let opt_node = self.engine.make_tab_bar_doc();
+        (DocName::Auxilliary(TAB_BAR_LABEL.to_owned()), opt_node)
+    }
+
+    fn make_last_log_doc(&mut self) -> (DocName, Option<Node>) {
+        let opt_message = self.last_log.as_ref().map(|entry| entry.to_string());
+        let opt_node = opt_message.map(|msg| self.engine.make_string_doc(msg, None));
+        (DocName::Auxilliary(LAST_LOG_LABEL.to_owned()), opt_node)
+    }
+
+    fn make_notifications_doc(&mut self) -> (DocName, Option<Node>) {
+        let doc_name = DocName::Auxilliary(NOTIFICATIONS_LABEL.to_owned());
+        if self.notifications.is_empty() {
+            return (doc_name, None);
+        }
+        let rows = self
+            .notifications
+            .iter()
+            .map(|notification| (notification.level, notification.message.clone()))
+            .collect();
+        (doc_name, Some(self.engine.make_notifications_doc(rows)))
+    }
+
+    /*****************
+     * Dynamic Panes *
+     *****************/
+
+    /// Add a pane named `name` whose contents are made by calling `provider`, which should return
+    /// a tree in `language_name` described the same way as for `set_virtual_doc()`. The pane is
+    /// hidden until `show_pane()` is called. The provider is called again only when the pane is
+    /// first shown and after `refresh_pane()`, so it's up to the script to say when its contents
+    /// might have changed (say, from an event hook). Replaces any existing provider for `name`.
+    pub fn add_pane_provider(
+        &mut self,
+        name: &str,
+        language_name: &str,
+        provider: rhai::FnPtr,
+    ) -> Result<(), SynlessError> {
+        self.engine.get_language(language_name)?;
+        let pane = DynamicPane {
+            name: name.to_owned(),
+            language_name: language_name.to_owned(),
+            provider,
+            placement: None,
+            stale: true,
+        };
+        match self.dynamic_panes.iter_mut().find(|pane| pane.name == name) {
+            Some(existing) => {
+                *existing = DynamicPane {
+                    placement: existing.placement,
+                    ..pane
+                }
+            }
+            None => self.dynamic_panes.push(pane),
+        }
+        Ok(())
+    }
+
+    /// Show the dynamic pane `name` on the given `side` of the visible doc (`"left"`, `"right"`,
+    /// `"top"`, or `"bottom"`), with the given width or height.
+    pub fn show_pane(
+        &mut self,
+        name: &str,
+        side: &str,
+        size: rhai::INT,
+    ) -> Result<(), SynlessError> {
+        let side = match side {
+            "left" => PaneSide::Left,
+            "right" => PaneSide::Right,
+            "top" => PaneSide::Top,
+            "bottom" => PaneSide::Bottom,
+            _ => return Err(error!(Frontend, "show_pane: unknown side '{side}'")),
+        };
+        let size =
+            usize::try_from(size).map_err(|_| error!(Frontend, "Invalid pane size: {size}"))?;
+        let pane = self.dynamic_pane_mut(name)?;
+        pane.placement = Some((side, size));
+        pane.stale = true;
+        self.update_pane_notation();
+        Ok(())
+    }
+
+    pub fn is_pane_shown(&self, name: &str) -> Result<bool, SynlessError> {
+        self.dynamic_panes
+            .iter()
+            .find(|pane| pane.name == name)
+            .map(|pane| pane.placement.is_some())
+            .ok_or_else(|| error!(Frontend, "No pane provider named '{name}'"))
+    }
+
+    pub fn hide_pane(&mut self, name: &str) -> Result<(), SynlessError> {
+        self.dynamic_pane_mut(name)?.placement = None;
+        self.update_pane_notation();
+        Ok(())
+    }
+
+    /// Call the provider of the dynamic pane `name` again before it's next displayed.
+    pub fn refresh_pane(&mut self, name: &str) -> Result<(), SynlessError> {
+        self.dynamic_pane_mut(name)?.stale = true;
+        Ok(())
+    }
+
+    /// The shown dynamic panes whose providers need to be called, as maps `#{name, language,
+    /// provider}`. They're no longer considered stale.
+    pub fn take_stale_panes(&mut self) -> rhai::Array {
+        let mut stale_panes = rhai::Array::new();
+        for pane in &mut self.dynamic_panes {
+            if pane.stale && pane.placement.is_some() {
+                pane.stale = false;
+                let mut map = rhai::Map::new();
+                map.insert("name".into(), pane.name.clone().into());
+                map.insert("language".into(), pane.language_name.clone().into());
+                map.insert(
+                    "provider".into(),
+                    rhai::Dynamic::from(pane.provider.clone()),
+                );
+                stale_panes.push(map.into());
+            }
+        }
+        stale_panes
+    }
+
+    fn dynamic_pane_mut(&mut self, name: &str) -> Result<&mut DynamicPane, SynlessError> {
+        self.dynamic_panes
+            .iter_mut()
+            .find(|pane| pane.name == name)
+            .ok_or_else(|| error!(Frontend, "No pane provider named '{name}'"))
+    }
+
+    /******************
+     * Doc Management *
+     ******************/
+
+    /// If there is a visible doc, return the directory it's in. Fall back to the cwd.
+    pub fn current_dir(&self) -> Result<String, SynlessError> {
+        if let Some(DocName::File(path)) = self.engine.visible_doc_name() {
+            if let Some(parent_path) = path.parent() {
+                return fs_util::path_to_string(parent_path);
+            }
+        }
+
+        let cwd = std::env::current_dir().map_err(|err| {
             error!(
                 FileSystem,
                 "Failed to get current working directory ({err})"
@@ -333,20 +1737,299 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         self.engine.set_visible_doc(&doc_name)
     }
 
+    /// Move the node at the cursor into a new doc at `path`, without switching to it.
+    pub fn extract_to_doc(&mut self, path: &str) -> Result<(), SynlessError> {
+        use std::path::PathBuf;
+
+        let path_buf = PathBuf::from(path);
+        if path_buf.exists() {
+            return Err(error!(
+                FileSystem,
+                "File already exists: {}",
+                path_buf.display()
+            ));
+        }
+        let language_name = self.language_name_from_file_extension(&path_buf)?;
+        let doc_name = DocName::File(path_buf);
+        self.engine.extract_to_doc(&doc_name, &language_name)
+    }
+
     pub fn open_doc(&mut self, path: &str) -> Result<(), SynlessError> {
         use std::fs::read_to_string;
-        use std::path::PathBuf;
+        use std::path::{Path, PathBuf};
 
         let source = read_to_string(path)
             .map_err(|err| error!(FileSystem, "Failed to read file at '{path}' ({err})"))?;
         let path_buf = PathBuf::from(path);
         let language_name = self.language_name_from_file_extension(&path_buf)?;
-        let doc_name = DocName::File(path_buf);
-        self.engine
-            .load_doc_from_source(doc_name.clone(), &language_name, &source)?;
+        let stamp = file_stamp(&path_buf)?;
+        let doc_name = DocName::File(path_buf.clone());
+        let is_lazy = self
+            .engine
+            .load_doc_lazily(doc_name.clone(), &language_name, &source)?;
+        self.tracked_files.insert(
+            path_buf,
+            TrackedFile {
+                language_name: language_name.clone(),
+                stamp,
+                auto_reload: false,
+            },
+        );
+        self.record_recent_file(path);
+        if is_lazy {
+            // The undo history and git changes are for the whole doc, so they have to wait.
+            self.post_notification(
+                LogLevel::Info,
+                format!("Loading {path} (read-only until it's done)"),
+                NOTIFICATION_TIMEOUT,
+            );
+        } else {
+            if let Err(err) = self.engine.restore_undo_history(
+                &doc_name,
+                Path::new(path),
+                &language_name,
+                &source,
+            ) {
+                log!(Warn, "{}", err);
+            }
+            self.update_git_changes(&doc_name);
+        }
         self.engine.set_visible_doc(&doc_name)
     }
 
+    /// Parse more of the docs that are being loaded lazily, for up to `LAZY_LOAD_BUDGET`, and swap
+    /// in any that have finished being parsed in the background. Returns whether any of them
+    /// finished (or failed) loading.
+    fn load_pending_docs(&mut self) -> bool {
+        let mut finished = Vec::new();
+        let mut errors = Vec::new();
+        for (doc_name, result) in self.engine.poll_background_parses() {
+            match result {
+                Ok(()) => finished.push(doc_name),
+                Err(err) => errors.push(err),
+            }
+        }
+        if self.engine.has_pending_loads() {
+            match self.engine.load_pending_elements(LAZY_LOAD_BUDGET) {
+                Ok(doc_names) => finished.extend(doc_names),
+                Err(err) => errors.push(err),
+            }
+        }
+        let changed = !finished.is_empty() || !errors.is_empty();
+        for err in errors {
+            self.post_notification(LogLevel::Error, err.to_string(), NOTIFICATION_TIMEOUT);
+        }
+        for doc_name in &finished {
+            self.update_git_changes(doc_name);
+            self.post_notification(
+                LogLevel::Info,
+                format!("Finished loading {doc_name}"),
+                NOTIFICATION_TIMEOUT,
+            );
+        }
+        changed
+    }
+
+    /// Open the file at `path`, or switch to it if it's already open.
+    pub fn open_or_switch_to_doc(&mut self, path: &str) -> Result<(), SynlessError> {
+        use std::path::PathBuf;
+
+        if self
+            .engine
+            .get_doc(&DocName::File(PathBuf::from(path)))
+            .is_some()
+        {
+            self.record_recent_file(path);
+            self.switch_to_doc(path)
+        } else {
+            self.open_doc(path)
+        }
+    }
+
+    /// The paths of recently opened files that still exist, most recent first.
+    pub fn recent_files(&self) -> rhai::Array {
+        self.recent_files
+            .iter()
+            .filter(|path| std::path::Path::new(path).is_file())
+            .map(|path| rhai::Dynamic::from(path.clone()))
+            .collect()
+    }
+
+    /// Move `path` to the front of the recent files list, and save the list.
+    fn record_recent_file(&mut self, path: &str) {
+        let path = match fs_util::path_to_string(std::path::Path::new(path)) {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        self.recent_files.retain(|recent| *recent != path);
+        self.recent_files.push_front(path);
+        self.recent_files.truncate(MAX_RECENT_FILES);
+        if let Some(list_path) = &self.recent_files_path {
+            let mut contents = String::new();
+            for recent in &self.recent_files {
+                contents.push_str(recent);
+                contents.push('\n');
+            }
+            // Best-effort: if it can't be saved, it's only remembered for this session.
+            if let Some(dir) = list_path.parent() {
+                let _ = std::fs::create_dir_all(dir);
+            }
+            let _ = std::fs::write(list_path, contents);
+        }
+    }
+
+    /// Open the file at `path` as a read-only doc, and keep it in sync with the file as other
+    /// programs write to it, like `tail -f`.
+    pub fn watch_doc(&mut self, path: &str) -> Result<(), SynlessError> {
+        use std::path::PathBuf;
+
+        self.open_doc(path)?;
+        let path_buf = PathBuf::from(path);
+        self.engine
+            .set_doc_read_only(&DocName::File(path_buf.clone()), true)?;
+        if let Some(tracked) = self.tracked_files.get_mut(&path_buf) {
+            tracked.auto_reload = true;
+        }
+        Ok(())
+    }
+
+    /// Reload every watched doc whose file has changed since it was last loaded, and open the
+    /// `file_changed` menu if any other open doc's file has changed. Returns whether anything
+    /// needs to be redrawn.
+    fn poll_tracked_files(&mut self) -> bool {
+        let mut changed = false;
+        let mut errors = Vec::new();
+        let mut changed_files = Vec::new();
+        let engine = &mut self.engine;
+        self.tracked_files.retain(|path, tracked| {
+            let doc_name = DocName::File(path.to_owned());
+            if engine.get_doc(&doc_name).is_none() {
+                // The doc was closed, so stop tracking it.
+                return false;
+            }
+            let stamp = match file_stamp(path) {
+                Ok(stamp) => stamp,
+                // The file might be in the middle of being replaced. Try again later.
+                Err(_) => return true,
+            };
+            if stamp == tracked.stamp {
+                return true;
+            }
+            if !tracked.auto_reload {
+                // Keep the old stamp until the user decides what to do.
+                changed_files.push(path.to_owned());
+                return true;
+            }
+            // Usually the file has only grown, so try adding just its new lines to the doc.
+            let (_, old_len) = tracked.stamp;
+            let (_, new_len) = stamp;
+            let appended = if new_len > old_len {
+                read_appended_lines(path, old_len)
+            } else {
+                None
+            };
+            if appended.as_deref() == Some("") {
+                // The writer is partway through a line. Wait for the rest of it.
+                return true;
+            }
+            let language_name = &tracked.language_name;
+            let was_appended = appended.is_some_and(|lines| {
+                let result = engine.append_doc_from_source(&doc_name, language_name, &lines, true);
+                if result.is_ok() {
+                    // Any partial line after them is still unread.
+                    tracked.stamp = (stamp.0, old_len + lines.len() as u64);
+                }
+                result.is_ok()
+            });
+            let result = if was_appended {
+                Ok(())
+            } else {
+                tracked.stamp = stamp;
+                read_file(path).and_then(|source| {
+                    engine.reload_doc_from_source(&doc_name, language_name, &source, true)
+                })
+            };
+            match result {
+                Ok(()) => changed = true,
+                Err(err) => errors.push(err),
+            }
+            true
+        });
+        for err in &errors {
+            self.log_warn(err.to_string());
+        }
+        // Don't interrupt a menu or prompt that's already open; ask again on a later poll.
+        if let Some(path) = changed_files.into_iter().next() {
+            if !self.layers.has_open_menu() && self.tree_prompt.is_none() {
+                let menu = make_menu(
+                    FILE_CHANGED_MENU_NAME.to_owned(),
+                    format!("'{}' changed on disk", path.display()),
+                );
+                match self.open_menu(menu) {
+                    Ok(()) => {
+                        self.changed_file = Some(path);
+                        changed = true;
+                    }
+                    Err(err) => self.log_warn(err.to_string()),
+                }
+            }
+        }
+        changed || !errors.is_empty()
+    }
+
+    /// The file that the `file_changed` menu was opened for, and its tracking info.
+    fn changed_file(&mut self) -> Result<(std::path::PathBuf, &mut TrackedFile), SynlessError> {
+        let path = self
+            .changed_file
+            .take()
+            .ok_or_else(|| error!(Doc, "No file has changed on disk"))?;
+        match self.tracked_files.get_mut(&path) {
+            Some(tracked) => Ok((path, tracked)),
+            None => Err(error!(Doc, "'{}' is no longer open", path.display())),
+        }
+    }
+
+    /// Replace the doc whose file changed on disk with the file's new contents, discarding its
+    /// undo history and any unsaved changes.
+    pub fn reload_changed_doc(&mut self) -> Result<(), SynlessError> {
+        let (path, tracked) = self.changed_file()?;
+        let language_name = tracked.language_name.clone();
+        tracked.stamp = file_stamp(&path)?;
+        let source = read_file(&path)?;
+        self.engine
+            .reload_doc_from_source(&DocName::File(path), &language_name, &source, false)
+    }
+
+    /// Keep the version of the doc whose file changed on disk. It's marked as having unsaved
+    /// changes, since it no longer matches the file.
+    pub fn keep_changed_doc(&mut self) -> Result<(), SynlessError> {
+        let (path, tracked) = self.changed_file()?;
+        tracked.stamp = file_stamp(&path)?;
+        self.engine.mark_doc_as_unsaved(&DocName::File(path))
+    }
+
+    /// Replace the contents of the doc whose file changed on disk with the file's new contents,
+    /// as an edit that can be undone. Only allowed if the doc has no unsaved changes.
+    pub fn merge_changed_doc(&mut self) -> Result<(), SynlessError> {
+        let (path, tracked) = self.changed_file()?;
+        let language_name = tracked.language_name.clone();
+        let stamp = file_stamp(&path)?;
+        let source = read_file(&path)?;
+        let doc_name = DocName::File(path.clone());
+        if let Err(err) = self
+            .engine
+            .merge_doc_from_source(&doc_name, &language_name, &source)
+        {
+            // Let the user pick another option.
+            self.changed_file = Some(path);
+            return Err(err);
+        }
+        if let Some(tracked) = self.tracked_files.get_mut(&path) {
+            tracked.stamp = stamp;
+        }
+        Ok(())
+    }
+
     fn language_name_from_file_extension(
         &self,
         path: &std::path::Path,
@@ -383,6 +2066,14 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
             .collect::<Result<Vec<_>, _>>()
     }
 
+    pub fn next_doc(&mut self) -> Result<(), SynlessError> {
+        self.engine.switch_to_adjacent_doc(true)
+    }
+
+    pub fn prev_doc(&mut self) -> Result<(), SynlessError> {
+        self.engine.switch_to_adjacent_doc(false)
+    }
+
     pub fn switch_to_doc(&mut self, path: &str) -> Result<(), SynlessError> {
         use std::path::PathBuf;
 
@@ -399,9 +2090,18 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
     }
 
     pub fn force_close_visible_doc(&mut self) -> Result<(), SynlessError> {
+        if let Some(doc_name @ DocName::Scratch(_)) = self.engine.visible_doc_name().cloned() {
+            self.save_scratch_doc(&doc_name)?;
+            if self.scratch_split.as_ref().map(|split| &split.doc_name) == Some(&doc_name) {
+                self.scratch_split = None;
+                self.update_pane_notation();
+            }
+        }
         self.engine.close_visible_doc()
     }
 
+    /// Save the visible doc to its file, without running any save hooks. `save_doc()` in the base
+    /// module runs them.
     pub fn save_doc(&mut self) -> Result<(), SynlessError> {
         self.save_doc_impl(None)
     }
@@ -410,8 +2110,57 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         self.save_doc_impl(Some(path))
     }
 
+    /// Call `hook` with the doc (as given by `visible_doc_info()`) whenever a doc is about to be
+    /// saved. It may edit the doc. If it throws, the error is logged and the save goes ahead.
+    pub fn on_before_save(&mut self, hook: rhai::FnPtr) {
+        self.before_save_hooks.push(hook);
+    }
+
+    /// Call `hook` with the doc (as given by `visible_doc_info()`) whenever a doc has been saved.
+    pub fn on_after_save(&mut self, hook: rhai::FnPtr) {
+        self.after_save_hooks.push(hook);
+    }
+
+    /// The hooks added with `on_before_save()`, in the order they were added.
+    pub fn before_save_hooks(&self) -> Vec<rhai::Dynamic> {
+        self.before_save_hooks
+            .iter()
+            .map(|hook| rhai::Dynamic::from(hook.clone()))
+            .collect()
+    }
+
+    /// The hooks added with `on_after_save()`, in the order they were added.
+    pub fn after_save_hooks(&self) -> Vec<rhai::Dynamic> {
+        self.after_save_hooks
+            .iter()
+            .map(|hook| rhai::Dynamic::from(hook.clone()))
+            .collect()
+    }
+
+    /// The visible doc, as a `#{path, language}` map. `path` is `()` if the doc isn't a file.
+    pub fn visible_doc_info(&self) -> Result<rhai::Map, SynlessError> {
+        let doc_name = self
+            .engine
+            .visible_doc_name()
+            .ok_or_else(|| error!(Doc, "No visible doc"))?;
+        let path = match doc_name {
+            DocName::File(path_buf) => fs_util::path_to_string(path_buf)?.into(),
+            _ => rhai::Dynamic::UNIT,
+        };
+        let doc = self.engine.visible_doc().bug();
+        let s = self.engine.raw_storage();
+        let language_name = doc.cursor().root_node(s).language(s).name(s);
+        let mut map = rhai::Map::new();
+        map.insert("path".into(), path);
+        map.insert("language".into(), language_name.into());
+        Ok(map)
+    }
+
     fn save_doc_impl(&mut self, path: Option<String>) -> Result<(), SynlessError> {
+        use std::path::Path;
+
         if let Some(doc_name) = self.engine.visible_doc_name().cloned() {
+            self.engine.finish_loading(&doc_name)?;
             let source = self.engine.print_source(&doc_name)?;
             let path = if let Some(path) = path {
                 path
@@ -420,83 +2169,1653 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
             } else {
                 return Err(error!(Doc, "Document does not have a path. Try save-as."));
             };
-            std::fs::write(&path, source)
+            std::fs::write(&path, &source)
                 .map_err(|err| error!(FileSystem, "Failed to write to file '{path}' ({err})"))?;
+            if let Some(tracked) = self.tracked_files.get_mut(Path::new(&path)) {
+                tracked.stamp = file_stamp(Path::new(&path))?;
+            }
+            self.engine
+                .save_undo_history(&doc_name, Path::new(&path), &source)?;
+            self.update_git_changes(&doc_name);
             self.engine.mark_doc_as_saved(&doc_name)
         } else {
             Err(error!(Doc, "No open document"))
         }
     }
 
-    /*************
-     * Languages *
-     *************/
-
-    pub fn load_language(&mut self, path: &str) -> Result<String, SynlessError> {
-        use std::fs::read_to_string;
-        use std::path::Path;
-
-        let ron_string = read_to_string(path)
-            .map_err(|err| error!(FileSystem, "Failed to read file at '{path}' ({err})"))?;
-        self.engine.load_language_ron(Path::new(path), &ron_string)
+    /// Write the whole visible doc to the file at `path`, colored the way it's displayed (but
+    /// without the cursor) using ANSI escape codes. It can be viewed with `less -R`.
+    pub fn export_ansi(&mut self, path: &str) -> Result<(), SynlessError> {
+        let doc_name = self
+            .engine
+            .visible_doc_name()
+            .cloned()
+            .ok_or_else(|| error!(Doc, "No open document"))?;
+        let theme = self.frontend.color_theme().clone();
+        let window = self
+            .engine
+            .display_whole_doc(&doc_name, None, |size| CaptureWindow::new(theme, size))?;
+        std::fs::write(path, window.to_ansi_string())
+            .map_err(|err| error!(FileSystem, "Failed to write to file '{path}' ({err})"))?;
+        self.log_info(format!("Exported to '{path}'"));
+        Ok(())
     }
 
-    pub fn get_language(&mut self, language_name: &str) -> Result<Language, SynlessError> {
-        self.engine.get_language(language_name)
+    /// Write an SVG image of the visible doc to the file at `path`, drawn in the current color
+    /// theme. If `whole_doc` is set it shows the whole doc (without the cursor), and otherwise just
+    /// the part that's on screen.
+    pub fn export_svg(&mut self, path: &str, whole_doc: bool) -> Result<(), SynlessError> {
+        let doc_name = self
+            .engine
+            .visible_doc_name()
+            .cloned()
+            .ok_or_else(|| error!(Doc, "No open document"))?;
+        let theme = self.frontend.color_theme().clone();
+        let window = if whole_doc {
+            self.engine
+                .display_whole_doc(&doc_name, None, |size| CaptureWindow::new(theme, size))?
+        } else {
+            let (width, height) = self
+                .main_doc_pane_size()
+                .ok_or_else(|| error!(Frontend, "Can't determine the size of the doc pane"))?;
+            let size = Size {
+                width,
+                height: height as Height,
+            };
+            let mut window = CaptureWindow::new(theme, size);
+            self.engine.display_visible_doc(&mut window)?;
+            window
+        };
+        std::fs::write(path, window.to_svg_string())
+            .map_err(|err| error!(FileSystem, "Failed to write to file '{path}' ({err})"))?;
+        self.log_info(format!("Exported to '{path}'"));
+        Ok(())
     }
 
-    pub fn language_constructs(&mut self, language: Language) -> Vec<rhai::Dynamic> {
-        language
-            .constructs(self.engine.raw_storage())
-            .map(rhai::Dynamic::from)
-            .collect()
+    /// Set the contents of the virtual doc `name`, creating it if necessary. Virtual docs aren't
+    /// backed by a file. They're read-only, and can be shown with `show_virtual_doc()` or placed in
+    /// a pane with `pane_virtual_doc()`.
+    ///
+    /// `tree` describes the doc's root node (or, if it isn't a root construct, the single child
+    /// of the root). Each node is a map with a `construct` name, and either `text` (for texty
+    /// constructs) or an array of `children`.
+    pub fn set_virtual_doc(
+        &mut self,
+        name: &str,
+        language_name: &str,
+        tree: rhai::Dynamic,
+    ) -> Result<(), SynlessError> {
+        let language = self.engine.get_language(language_name)?;
+        let s = self.engine.raw_storage_mut();
+        let node = build_virtual_node(s, language, tree, "set_virtual_doc")?;
+        let root_construct = language.root_construct(s);
+        let root = if node.construct(s) == root_construct {
+            node
+        } else {
+            match Node::with_children(s, root_construct, [node]) {
+                Ok(root) => root,
+                Err(err) => {
+                    let construct_name = node.construct(s).name(s).to_owned();
+                    node.delete_root(s).bug();
+                    return Err(error!(
+                        Doc,
+                        "set_virtual_doc: construct '{construct_name}' can't be the root of a doc ({err})"
+                    ));
+                }
+            }
+        };
+        self.engine.set_virtual_doc(name, root)
     }
 
-    pub fn construct_name(&self, construct: Construct) -> String {
-        construct.name(self.engine.raw_storage()).to_owned()
+    /// Make the virtual doc `name` the visible doc.
+    pub fn show_virtual_doc(&mut self, name: &str) -> Result<(), SynlessError> {
+        self.engine
+            .set_visible_doc(&DocName::Virtual(name.to_owned()))
     }
 
-    pub fn construct_key(&self, construct: Construct) -> String {
-        construct
-            .key(self.engine.raw_storage())
-            .map(|c| c.to_string())
-            .unwrap_or_default()
+    pub fn close_virtual_doc(&mut self, name: &str) -> Result<(), SynlessError> {
+        self.engine.delete_doc(&DocName::Virtual(name.to_owned()))
     }
 
-    /***********
-     * Editing *
-     ***********/
+    /**********
+     * Config *
+     **********/
 
-    pub fn undo(&mut self) -> Result<(), SynlessError> {
-        self.engine.undo()
+    /// Load the user's config file (see [`Config`]) and apply its settings and color theme,
+    /// without redisplaying. Its keybinding profile is left to `config_changed` hooks. Does
+    /// nothing if there's no config directory.
+    pub fn load_config(&mut self) -> Result<(), SynlessError> {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        // Record the stamp even if loading fails, so that a broken config isn't reported again
+        // until it's edited.
+        self.config_stamp = file_stamp(&path).ok();
+        let config = Config::load(&path)?;
+        self.engine.apply_config(&config)?;
+        if let Some(theme_name) = &config.theme {
+            self.use_theme(theme_name)?;
+        }
+        if let Some(path) = &config.dictionary {
+            self.load_dictionary(path)?;
+        }
+        self.config = config;
+        self.config_version += 1;
+        Ok(())
     }
 
-    pub fn redo(&mut self) -> Result<(), SynlessError> {
-        self.engine.redo()
+    /// Load the user's config file again, and redisplay with its settings. This also happens on
+    /// its own whenever the file changes.
+    pub fn reload_config(&mut self) -> Result<(), SynlessError> {
+        if config_path().is_none() {
+            return Err(error!(
+                FileSystem,
+                "No config directory: neither XDG_CONFIG_HOME nor HOME is set"
+            ));
+        }
+        self.load_config()?;
+        self.log_info("Reloaded config".to_owned());
+        self.display()
     }
 
-    pub fn revert(&mut self) -> Result<(), SynlessError> {
-        self.engine.revert_undo_group()
+    /// The settings from the config file, as a map with `()` for each one it doesn't give. See
+    /// [`Config`] for the fields.
+    pub fn config(&self) -> rhai::Map {
+        self.config.to_rhai_map()
     }
 
-    pub fn insert_node(&mut self, construct: Construct) -> Result<(), SynlessError> {
-        let node = Node::new_with_auto_fill(self.engine.raw_storage_mut(), construct);
-        self.engine.execute(TreeEdCommand::Insert(node))?;
-        self.engine.execute(TreeNavCommand::FirstInsertLoc)
+    /// Reload the config file if it's been created, changed, or deleted since it was last
+    /// loaded. Returns whether it was reloaded.
+    fn poll_config_file(&mut self) -> bool {
+        let stamp = match config_path() {
+            Some(path) => file_stamp(&path).ok(),
+            None => return false,
+        };
+        if stamp == self.config_stamp {
+            return false;
+        }
+        match self.load_config() {
+            Ok(()) => self.post_notification(
+                LogLevel::Info,
+                "Config file changed; reloaded it".to_owned(),
+                NOTIFICATION_TIMEOUT,
+            ),
+            Err(err) => {
+                self.post_notification(LogLevel::Warn, err.to_string(), NOTIFICATION_TIMEOUT)
+            }
+        }
+        true
     }
 
-    pub fn search_for_construct(&mut self, construct: Construct) -> Result<(), SynlessError> {
-        let search = Search::new_construct(construct);
-        self.engine.execute(SearchCommand::Set(search))
-    }
+    /***************
+     * Event Hooks *
+     ***************/
 
-    pub fn search_for_node_at_cursor(&mut self) -> Result<(), SynlessError> {
-        let node = self.engine.node_at_cursor(true)?; // deep copy
-        let search = Search::new_node(node);
-        self.engine.execute(SearchCommand::Set(search))
+    /// Call `hook` whenever the event `event_name` happens, with a map describing it:
+    ///
+    /// - `doc_opened` and `doc_closed`: `#{doc}`, for file docs.
+    /// - `cursor_moved` and `node_edited`: `#{doc, path}`, where `path` is the path to the node at
+    ///   (or just around) the cursor, as given by `path_at_cursor()`.
+    /// - `mode_changed`: `#{doc, mode}`, where `mode` is "Tree" or "Text".
+    /// - `config_changed`: the new config, as returned by `config()`. Also reported once on
+    ///   startup, after the config is first loaded.
+    ///
+    /// `cursor_moved`, `node_edited`, and `mode_changed` are only reported for file docs. Hooks are
+    /// run between keypresses by `block()` in the base module. If a hook throws, the error is
+    /// logged.
+    pub fn on_event(&mut self, event_name: &str, hook: rhai::FnPtr) -> Result<(), SynlessError> {
+        if !EVENT_NAMES.contains(&event_name) {
+            return Err(error!(
+                Keymap,
+                "Unknown event '{event_name}'. Expected one of: {}",
+                EVENT_NAMES.join(", ")
+            ));
+        }
+        self.event_hooks
+            .entry(event_name.to_owned())
+            .or_default()
+            .push(hook);
+        Ok(())
     }
 
-    pub fn search_for_substring(&mut self, substring: String) -> Result<(), SynlessError> {
+    /// The hooks added for `event_name` with `on_event()`, in the order they were added.
+    pub fn event_hooks(&self, event_name: &str) -> Vec<rhai::Dynamic> {
+        self.event_hooks
+            .get(event_name)
+            .into_iter()
+            .flatten()
+            .map(|hook| rhai::Dynamic::from(hook.clone()))
+            .collect()
+    }
+
+    /// The events that have happened since the last call, as `#{name, data}` maps, where `data`
+    /// is what's passed to the event's hooks. They're found by comparing against the state at the
+    /// last call, so something that's undone before then (like a doc that's opened and closed
+    /// again) isn't reported.
+    pub fn take_events(&mut self) -> Vec<rhai::Dynamic> {
+        let new = self.make_event_snapshot();
+        let old = std::mem::replace(&mut self.event_snapshot, new);
+        let new = &self.event_snapshot;
+        if self.event_hooks.is_empty() {
+            return Vec::new();
+        }
+
+        let mut events = Vec::new();
+        let mut push_event = |name: &str, doc_name: &DocName, extra: Option<(&str, String)>| {
+            let mut data = rhai::Map::new();
+            data.insert("doc".into(), doc_name.to_string().into());
+            if let Some((key, value)) = extra {
+                data.insert(key.into(), value.into());
+            }
+            let mut event = rhai::Map::new();
+            event.insert("name".into(), name.into());
+            event.insert("data".into(), data.into());
+            events.push(rhai::Dynamic::from(event));
+        };
+        for doc_name in &new.file_docs {
+            if !old.file_docs.contains(doc_name) {
+                push_event("doc_opened", doc_name, None);
+            }
+        }
+        for doc_name in &old.file_docs {
+            if !new.file_docs.contains(doc_name) {
+                push_event("doc_closed", doc_name, None);
+            }
+        }
+        if let (Some((old_doc_name, old_cursor, old_mode, old_version)), Some(visible_doc)) =
+            (&old.visible_doc, &new.visible_doc)
+        {
+            let (doc_name, cursor, mode, version) = visible_doc;
+            if doc_name == old_doc_name {
+                let s = self.engine.raw_storage();
+                let path = cursor
+                    .at_node(s)
+                    .or_else(|| cursor.in_text_node(s))
+                    .or_else(|| cursor.parent_node(s))
+                    .map(|node| node.path(s))
+                    .unwrap_or_default();
+                if version != old_version {
+                    push_event("node_edited", doc_name, Some(("path", path.clone())));
+                }
+                if cursor != old_cursor {
+                    push_event("cursor_moved", doc_name, Some(("path", path)));
+                }
+                if mode != old_mode {
+                    push_event(
+                        "mode_changed",
+                        doc_name,
+                        Some(("mode", format!("{mode:?}"))),
+                    );
+                }
+            }
+        }
+        if new.config_version != old.config_version {
+            let mut event = rhai::Map::new();
+            event.insert("name".into(), "config_changed".into());
+            event.insert("data".into(), self.config.to_rhai_map().into());
+            events.push(rhai::Dynamic::from(event));
+        }
+        events
+    }
+
+    fn make_event_snapshot(&self) -> EventSnapshot {
+        let file_docs = self.engine.file_doc_names();
+        let visible_doc = self
+            .engine
+            .visible_doc_name()
+            .filter(|doc_name| file_docs.contains(doc_name))
+            .map(|doc_name| {
+                let doc = self.engine.visible_doc().bug();
+                (doc_name.to_owned(), doc.cursor(), doc.mode(), doc.version())
+            });
+        EventSnapshot {
+            file_docs,
+            visible_doc,
+            config_version: self.config_version,
+        }
+    }
+
+    /**********
+     * Timers *
+     **********/
+
+    /// Call `callback` once, after `ms` milliseconds. It's run between keypresses, so it's
+    /// delayed while a key's program is running. Returns an id for `clear_timer()`.
+    pub fn set_timeout(
+        &mut self,
+        ms: rhai::INT,
+        callback: rhai::FnPtr,
+    ) -> Result<rhai::INT, SynlessError> {
+        let delay = timer_duration(ms)?;
+        Ok(self.add_timer(delay, None, callback))
+    }
+
+    /// Call `callback` every `ms` milliseconds, until the timer is cleared with `clear_timer()`.
+    /// Returns its id.
+    pub fn set_interval(
+        &mut self,
+        ms: rhai::INT,
+        callback: rhai::FnPtr,
+    ) -> Result<rhai::INT, SynlessError> {
+        let interval = timer_duration(ms)?;
+        if interval.is_zero() {
+            return Err(error!(Edit, "Interval must be positive"));
+        }
+        Ok(self.add_timer(interval, Some(interval), callback))
+    }
+
+    /// Stop the timer with the given id from going off. Does nothing if there is no such timer
+    /// (for example because it already went off).
+    pub fn clear_timer(&mut self, id: rhai::INT) {
+        self.timers.retain(|timer| timer.id != id);
+    }
+
+    fn add_timer(
+        &mut self,
+        delay: Duration,
+        interval: Option<Duration>,
+        callback: rhai::FnPtr,
+    ) -> rhai::INT {
+        let id = self.next_timer_id;
+        self.next_timer_id += 1;
+        self.timers.push(Timer {
+            id,
+            due: Instant::now() + delay,
+            interval,
+            callback,
+        });
+        id
+    }
+
+    /***********
+     * Plugins *
+     ***********/
+
+    /// Add a plugin whose module has already been loaded: its keybindings become a global layer
+    /// on top of the stack, and its commands are listed by `plugin_commands()`. `progs` maps the
+    /// function of each of its commands to a program that runs it.
+    pub fn add_plugin(
+        &mut self,
+        plugin: &Plugin,
+        progs: &HashMap<String, rhai::FnPtr>,
+    ) -> Result<(), SynlessError> {
+        let layer = plugin.make_layer(progs)?;
+        self.layers.register_layer(layer);
+        self.layers.add_global_layer(&plugin.layer_name())?;
+        for command in &plugin.manifest.commands {
+            if let Some(prog) = progs.get(&command.function) {
+                self.plugin_commands.push(PluginCommandEntry {
+                    plugin: plugin.name().to_owned(),
+                    hint: command.hint.clone(),
+                    prog: prog.clone(),
+                });
+            }
+        }
+        self.log_info(format!("Loaded plugin '{}'", plugin.name()));
+        Ok(())
+    }
+
+    /// The commands of every loaded plugin, as maps `#{plugin, hint, prog}`.
+    pub fn plugin_commands(&self) -> rhai::Array {
+        self.plugin_commands
+            .iter()
+            .map(|command| {
+                let mut map = rhai::Map::new();
+                map.insert("plugin".into(), command.plugin.clone().into());
+                map.insert("hint".into(), command.hint.clone().into());
+                map.insert("prog".into(), rhai::Dynamic::from(command.prog.clone()));
+                map.into()
+            })
+            .collect()
+    }
+
+    /*********************
+     * External Commands *
+     *********************/
+
+    /// Run the external command `argv` (a program followed by its arguments) in the background,
+    /// with `input` written to its stdin. Once it exits, `callback` is called with a map
+    /// `#{id, status, stdout, stderr}`, where `status` is its exit code, or `()` if it was killed
+    /// by a signal. Returns the id.
+    pub fn spawn_command(
+        &mut self,
+        argv: rhai::Array,
+        input: String,
+        callback: rhai::FnPtr,
+    ) -> Result<rhai::INT, SynlessError> {
+        use std::io::Write;
+
+        let argv = argv
+            .into_iter()
+            .map(|arg| {
+                arg.into_string()
+                    .map_err(|ty| error!(Edit, "spawn_command: expected a string, found {ty}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (program, args) = argv
+            .split_first()
+            .ok_or_else(|| error!(Edit, "spawn_command: no program given"))?;
+        let mut child = process::Command::new(program)
+            .args(args)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .map_err(|err| error!(FileSystem, "Failed to run '{program}' ({err})"))?;
+
+        let mut stdin = child.stdin.take().bug();
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            // Write the input on a separate thread, so that a command that writes its output
+            // before it's done reading its input can't deadlock.
+            let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+            let output = child.wait_with_output();
+            let _ = writer.join();
+            let _ = sender.send(output);
+        });
+
+        let id = self.next_command_id;
+        self.next_command_id += 1;
+        self.commands.push(RunningCommand {
+            id,
+            receiver,
+            output: None,
+            callback,
+        });
+        Ok(id)
+    }
+
+    /// The visible doc, printed in its source notation.
+    pub fn doc_source(&self) -> Result<String, SynlessError> {
+        let doc_name = self
+            .engine
+            .visible_doc_name()
+            .ok_or_else(|| error!(Doc, "No visible doc"))?;
+        self.engine.print_source(doc_name)
+    }
+
+    /// The node at the cursor, printed in its source notation.
+    pub fn node_source(&self) -> Result<String, SynlessError> {
+        self.engine.print_node_at_cursor(None)
+    }
+
+    /// Replace `node` (or if it's the root, the visible doc's contents) by parsing `source`, like
+    /// the output of a formatter that was given `node_source()` or `doc_source()`. Since the
+    /// formatter runs in the background, `node` should be recorded before it's started. Fails if
+    /// `node` has since been deleted or isn't in the visible doc anymore.
+    pub fn replace_with_source(&mut self, node: Node, source: String) -> Result<(), SynlessError> {
+        let node = self.valid_node(node)?;
+        self.engine.replace_with_source(node, &source)
+    }
+
+    /****************
+     * Scratch Docs *
+     ****************/
+
+    /// Set the language of the scratch doc opened by `toggle_scratch()`.
+    pub fn set_scratch_language(&mut self, language_name: &str) -> Result<(), SynlessError> {
+        self.engine.get_language(language_name)?;
+        self.scratch_language = language_name.to_owned();
+        Ok(())
+    }
+
+    /// Set whether scratch docs are saved in the data dir when closed, and restored from there
+    /// when next opened (even in a later session).
+    pub fn set_scratch_persistence(&mut self, persist: bool) {
+        self.scratch_dir = if persist { scratch_dir() } else { None };
+    }
+
+    /// Open the scratch doc in a split below the visible doc, and make it the visible doc. If it's
+    /// already open, close the split and return to the doc that was visible before.
+    pub fn toggle_scratch(&mut self) -> Result<(), SynlessError> {
+        self.close_file_browser();
+        if let Some(split) = self.scratch_split.take() {
+            self.update_pane_notation();
+            if self.engine.get_doc(&split.doc_name).is_some() {
+                self.save_scratch_doc(&split.doc_name)?;
+            }
+            if let Some(prev_doc_name) = split.prev_doc_name {
+                if self.engine.get_doc(&prev_doc_name).is_some() {
+                    self.engine.set_visible_doc(&prev_doc_name)?;
+                }
+            }
+            return Ok(());
+        }
+
+        let language_name = self.scratch_language.clone();
+        let saved_source = self
+            .scratch_dir
+            .as_ref()
+            .and_then(|dir| std::fs::read_to_string(dir.join(&language_name)).ok());
+        let doc_name = match self
+            .engine
+            .open_scratch_doc(&language_name, saved_source.as_deref())
+        {
+            Ok(doc_name) => doc_name,
+            Err(err) => {
+                log!(
+                    Warn,
+                    "Failed to restore scratch doc, starting afresh ({err})"
+                );
+                self.engine.open_scratch_doc(&language_name, None)?
+            }
+        };
+        let prev_doc_name = self
+            .engine
+            .visible_doc_name()
+            .filter(|name| **name != doc_name)
+            .cloned();
+        self.engine.set_visible_doc(&doc_name)?;
+        self.scratch_split = Some(ScratchSplit {
+            doc_name,
+            prev_doc_name,
+        });
+        self.update_pane_notation();
+        Ok(())
+    }
+
+    /// Save the scratch doc to the data dir, if scratch docs are persisted.
+    fn save_scratch_doc(&mut self, doc_name: &DocName) -> Result<(), SynlessError> {
+        let (dir, language_name) = match (&self.scratch_dir, doc_name) {
+            (Some(dir), DocName::Scratch(language_name)) => (dir, language_name),
+            _ => return Ok(()),
+        };
+        let source = self.engine.print_source(doc_name)?;
+        let path = dir.join(language_name);
+        std::fs::create_dir_all(dir)
+            .and_then(|()| std::fs::write(&path, source))
+            .map_err(|err| {
+                error!(
+                    FileSystem,
+                    "Failed to save scratch doc to '{}' ({err})",
+                    path.display()
+                )
+            })
+    }
+
+    /*******************
+     * Command History *
+     *******************/
+
+    /// Show the programs that keypresses have run, most recent first, in a virtual doc. It's made
+    /// the visible doc, and the `command_history` layer is added to it.
+    pub fn open_command_history(&mut self) -> Result<(), SynlessError> {
+        if self.command_history_view.is_some() {
+            return Ok(());
+        }
+        let language = self.engine.get_language(COMMAND_HISTORY_LANGUAGE_NAME)?;
+        let s = self.engine.raw_storage_mut();
+        let c_entry = language
+            .construct(s, "Entry")
+            .ok_or_else(|| error!(Language, "command_history lang is missing 'Entry'"))?;
+        let root = Node::new(s, language.root_construct(s));
+        for record in &self.command_history {
+            let entry = Node::with_text(s, c_entry, record.entry_text()).bug();
+            root.insert_last_child(s, entry).bug();
+        }
+        self.engine
+            .set_virtual_doc(COMMAND_HISTORY_DOC_NAME, root)?;
+
+        let doc_name = DocName::Virtual(COMMAND_HISTORY_DOC_NAME.to_owned());
+        if let Err(err) = self
+            .layers
+            .add_local_layer(&doc_name, COMMAND_HISTORY_LAYER_NAME)
+        {
+            let _ = self.engine.delete_doc(&doc_name);
+            return Err(err);
+        }
+        let prev_doc_name = self.engine.visible_doc_name().cloned();
+        self.engine.set_visible_doc(&doc_name).bug();
+        self.command_history_view = Some(CommandHistoryView { prev_doc_name });
+        Ok(())
+    }
+
+    /// Close the command history doc, if it's open, and return to the doc that was visible
+    /// before.
+    pub fn close_command_history(&mut self) {
+        let view = match self.command_history_view.take() {
+            Some(view) => view,
+            None => return,
+        };
+        let doc_name = DocName::Virtual(COMMAND_HISTORY_DOC_NAME.to_owned());
+        let _ = self
+            .layers
+            .remove_local_layer(&doc_name, COMMAND_HISTORY_LAYER_NAME);
+        let _ = self.engine.delete_doc(&doc_name);
+        if let Some(prev_doc_name) = view.prev_doc_name {
+            let _ = self.engine.set_visible_doc(&prev_doc_name);
+        }
+    }
+
+    /// Close the command history and return the program of the entry under the cursor, to be run
+    /// again. It's recorded in the history again, too.
+    pub fn rerun_command(&mut self) -> Result<KeyProg, SynlessError> {
+        let record = self.selected_command_record()?.clone();
+        self.close_command_history();
+        let prog = record.prog.clone();
+        self.push_command_record(record);
+        Ok(prog)
+    }
+
+    /// Append the editing commands run by the entry under the cursor to the user's init script
+    /// (see [`user_init_path()`]), as a function that can be bound to a key. It's named after the
+    /// entry, with a number added if the script already defines a function by that name.
+    pub fn yank_command(&mut self) -> Result<(), SynlessError> {
+        use std::io::Write;
+
+        let path = user_init_path()
+            .ok_or_else(|| error!(FileSystem, "No config directory to put the init script in"))?;
+        let record = self.selected_command_record()?;
+        if record.commands.is_empty() {
+            return Err(error!(
+                Edit,
+                "'{}' didn't run any editing commands that can be yanked", record.label
+            ));
+        }
+        let existing = match std::fs::read_to_string(&path) {
+            Ok(existing) => existing,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(error!(
+                    FileSystem,
+                    "Failed to read '{}' ({err})",
+                    path.display()
+                ))
+            }
+        };
+        let ast = rhai::Engine::new_raw().compile(&existing).map_err(|err| {
+            error!(
+                Edit,
+                "Can't yank to '{}', because it doesn't parse ({err})",
+                path.display()
+            )
+        })?;
+        let defined = ast
+            .iter_functions()
+            .map(|func| func.name.to_owned())
+            .collect::<HashSet<_>>();
+        let base_name = format!("recorded_{}", rhai_ident(&record.label));
+        let mut fn_name = base_name.clone();
+        let mut suffix = 2;
+        while defined.contains(&fn_name) {
+            fn_name = format!("{base_name}_{suffix}");
+            suffix += 1;
+        }
+
+        let mut script = format!(
+            "\n// Recorded from the command history: {}\n",
+            record.entry_text().trim()
+        );
+        script.push_str(&format!("fn {fn_name}() {{\n"));
+        for command in &record.commands {
+            script.push_str(&format!("    s::{command};\n"));
+        }
+        script.push_str("}\n");
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| {
+                error!(FileSystem, "Failed to create '{}' ({err})", dir.display())
+            })?;
+        }
+        std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(script.as_bytes()))
+            .map_err(|err| {
+                error!(
+                    FileSystem,
+                    "Failed to append to '{}' ({err})",
+                    path.display()
+                )
+            })?;
+        self.log_info(format!("Yanked '{fn_name}' to {}", path.display()));
+        Ok(())
+    }
+
+    fn selected_command_record(&self) -> Result<&CommandRecord, SynlessError> {
+        if self.command_history_view.is_none() {
+            return Err(error!(Edit, "The command history isn't open"));
+        }
+        let doc_name = DocName::Virtual(COMMAND_HISTORY_DOC_NAME.to_owned());
+        let s = self.engine.raw_storage();
+        self.engine
+            .get_doc(&doc_name)
+            .and_then(|doc| {
+                let cursor = doc.cursor();
+                let node = cursor.at_node(s).or_else(|| cursor.in_text_node(s))?;
+                // The root isn't an entry.
+                node.parent(s)?;
+                self.command_history.get(node.sibling_index(s))
+            })
+            .ok_or_else(|| error!(Edit, "No command under the cursor"))
+    }
+
+    fn push_command_record(&mut self, record: CommandRecord) {
+        self.command_history.push_front(record);
+        self.command_history.truncate(MAX_COMMAND_HISTORY_LEN);
+    }
+
+    /// Record that an editing command was executed, as a call like `tree_nav_next()`.
+    fn record_command(&mut self, call: String) {
+        if self.recording_commands {
+            if let Some(record) = self.command_history.front_mut() {
+                record.commands.push(call);
+            }
+        }
+    }
+
+    /***********
+     * Linting *
+     ***********/
+
+    /// Add a lint rule called `name` for docs in the language `language_name`, replacing any
+    /// existing rule of that name. `check` is called by `run_lints()` on every node of the doc (as
+    /// given by `lint_nodes()`), and should return a message if there's a problem with the node.
+    pub fn add_lint_rule(
+        &mut self,
+        language_name: &str,
+        name: String,
+        check: rhai::FnPtr,
+    ) -> Result<(), SynlessError> {
+        let language = self.engine.get_language(language_name)?;
+        let language_name = language.name(self.engine.raw_storage()).to_owned();
+        self.lint_rules
+            .retain(|rule| rule.language_name != language_name || rule.name != name);
+        self.lint_rules.push(LintRule {
+            language_name,
+            name,
+            check,
+        });
+        Ok(())
+    }
+
+    /// The lint rules for the visible doc's language, as `#{name, check}` maps.
+    pub fn lint_rules(&self) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        let doc = self
+            .engine
+            .visible_doc()
+            .ok_or_else(|| error!(Doc, "No visible doc"))?;
+        let s = self.engine.raw_storage();
+        let language_name = doc.cursor().root_node(s).language(s).name(s);
+        Ok(self
+            .lint_rules
+            .iter()
+            .filter(|rule| rule.language_name == language_name)
+            .map(|rule| {
+                let mut map = rhai::Map::new();
+                map.insert("name".into(), rule.name.clone().into());
+                map.insert("check".into(), rhai::Dynamic::from(rule.check.clone()));
+                map.into()
+            })
+            .collect())
+    }
+
+    /// Every node in the visible doc (except the root) in document order, as
+    /// `#{index, construct, text, parent, num_children}` maps for lint rules to check. `text` is
+    /// `()` if the node isn't texty, and `parent` is the name of its parent's construct.
+    pub fn lint_nodes(&self) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        let s = self.engine.raw_storage();
+        Ok(self
+            .engine
+            .visible_doc_nodes()?
+            .into_iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let mut map = rhai::Map::new();
+                let text = match node.text(s) {
+                    Some(text) => text.as_str().into(),
+                    None => rhai::Dynamic::UNIT,
+                };
+                let parent = match node.parent(s) {
+                    Some(parent) => parent.construct(s).name(s).into(),
+                    None => rhai::Dynamic::UNIT,
+                };
+                let num_children = node.num_children(s).unwrap_or(0) as rhai::INT;
+                map.insert("index".into(), (i as rhai::INT).into());
+                map.insert("construct".into(), node.construct(s).name(s).into());
+                map.insert("text".into(), text);
+                map.insert("parent".into(), parent);
+                map.insert("num_children".into(), num_children.into());
+                map.into()
+            })
+            .collect())
+    }
+
+    /// Replace the visible doc's diagnostics with the built-in ones plus `diagnostics`, which are
+    /// `#{node, rule, message}` maps where `node` is an index from `lint_nodes()`.
+    pub fn set_diagnostics(&mut self, diagnostics: rhai::Array) -> Result<(), SynlessError> {
+        let nodes = self.engine.visible_doc_nodes()?;
+        let s = self.engine.raw_storage();
+        let mut parsed = Vec::new();
+        for diagnostic in diagnostics {
+            let map = diagnostic
+                .try_cast::<rhai::Map>()
+                .ok_or_else(|| error!(Doc, "set_diagnostics: expected a map"))?;
+            let string_field = |name: &str| {
+                map.get(name)
+                    .and_then(|value| value.clone().into_string().ok())
+                    .ok_or_else(|| error!(Doc, "set_diagnostics: '{name}' must be a string"))
+            };
+            let node = map
+                .get("node")
+                .and_then(|value| value.as_int().ok())
+                .and_then(|i| usize::try_from(i).ok())
+                .and_then(|i| nodes.get(i).copied())
+                .ok_or_else(|| error!(Doc, "set_diagnostics: 'node' must be a node index"))?;
+            parsed.push(Diagnostic {
+                node: node.id(s),
+                rule: string_field("rule")?,
+                message: string_field("message")?,
+            });
+        }
+        let count = self.engine.set_diagnostics(parsed)?;
+        if count == 0 {
+            self.log_info("No problems found".to_owned());
+        } else {
+            self.log_info(format!("{count} problems found"));
+        }
+        Ok(())
+    }
+
+    /// The problems found in the visible doc by the last run of the lint rules, as
+    /// `#{label, index}` maps. Pass an `index` to `goto_diagnostic()` to jump to its node.
+    pub fn diagnostics(&self) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        let s = self.engine.raw_storage();
+        Ok(self
+            .engine
+            .diagnostics()?
+            .iter()
+            .enumerate()
+            .map(|(i, diag)| {
+                let place = match self.engine.visible_node_with_id(diag.node) {
+                    Some(node) => node.summary(s),
+                    None => "(deleted)".to_owned(),
+                };
+                let mut map = rhai::Map::new();
+                let label = format!("{place}: {}", diag.message);
+                map.insert("label".into(), label.into());
+                map.insert("index".into(), (i as rhai::INT).into());
+                map.into()
+            })
+            .collect())
+    }
+
+    /// Jump to the next problem after the cursor, wrapping around to the first one.
+    pub fn goto_next_diagnostic(&mut self) -> Result<(), SynlessError> {
+        let node = self
+            .engine
+            .next_diagnostic_node()?
+            .ok_or_else(|| error!(Edit, "No problems found"))?;
+        self.engine.execute(TreeNavCommand::GotoNode(node))
+    }
+
+    pub fn goto_diagnostic(&mut self, index: rhai::INT) -> Result<(), SynlessError> {
+        let node = usize::try_from(index)
+            .ok()
+            .and_then(|i| self.engine.diagnostics().ok()?.get(i).map(|diag| diag.node))
+            .and_then(|id| self.engine.visible_node_with_id(id))
+            .ok_or_else(|| error!(Edit, "No problem at index {index}"))?;
+        self.engine.execute(TreeNavCommand::GotoNode(node))
+    }
+
+    /// Validate the visible doc, which must be json, against the JSON schema in the file at
+    /// `path` whenever the lint rules are run.
+    pub fn set_json_schema(&mut self, path: &str) -> Result<(), SynlessError> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| error!(FileSystem, "Failed to read file at '{path}' ({err})"))?;
+        self.engine
+            .set_json_schema(Some(JsonSchema::parse(&source)?))
+    }
+
+    /// Stop validating the visible doc against a JSON schema.
+    pub fn clear_json_schema(&mut self) -> Result<(), SynlessError> {
+        self.engine.set_json_schema(None)
+    }
+
+    /// Toggle showing the problems found by the lint rules in a pane below the visible doc.
+    pub fn toggle_lint_results(&mut self) {
+        self.show_lint_results = !self.show_lint_results;
+        self.update_pane_notation();
+    }
+
+    /******************
+     * Spell Checking *
+     ******************/
+
+    /// Spell check prose with the words in the hunspell-style `.dic` file at `path`, using the
+    /// affix rules in the `.aff` file beside it, if there is one.
+    pub fn load_dictionary(&mut self, path: &str) -> Result<(), SynlessError> {
+        let dic_path = std::path::Path::new(path);
+        let aff_path = dic_path.with_extension("aff");
+        let dic_source = read_file(dic_path)?;
+        let aff_source = if aff_path.exists() {
+            Some(read_file(&aff_path)?)
+        } else {
+            None
+        };
+        let dictionary = Dictionary::parse(&dic_source, aff_source.as_deref());
+        self.log_info(format!(
+            "Loaded {} words from '{path}'",
+            dictionary.num_words()
+        ));
+        self.engine.set_dictionary(Some(dictionary));
+        Ok(())
+    }
+
+    /// Turn spell checking off.
+    pub fn clear_dictionary(&mut self) {
+        self.engine.set_dictionary(None);
+    }
+
+    /// Find the misspelled words in the visible doc's prose, and highlight the nodes they're in.
+    pub fn spell_check(&mut self) -> Result<(), SynlessError> {
+        let count = self.engine.spell_check()?;
+        if count == 0 {
+            self.log_info("No misspellings found".to_owned());
+        } else {
+            self.log_info(format!("{count} misspellings found"));
+        }
+        Ok(())
+    }
+
+    /// The suggested corrections for the misspelled word at the cursor, closest first.
+    pub fn spelling_suggestions(&self) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        let (_word, suggestions) = self.engine.spelling_suggestions()?;
+        Ok(suggestions.into_iter().map(Into::into).collect())
+    }
+
+    /// Replace the misspelled word at the cursor with `correction`.
+    pub fn correct_spelling(&mut self, correction: &str) -> Result<(), SynlessError> {
+        self.engine.correct_spelling(correction)
+    }
+
+    /// Jump to the next misspelling after the cursor, wrapping around to the first one.
+    pub fn goto_next_misspelling(&mut self) -> Result<(), SynlessError> {
+        let node = self
+            .engine
+            .next_misspelling_node()?
+            .ok_or_else(|| error!(Edit, "No misspellings found"))?;
+        self.engine.execute(TreeNavCommand::GotoNode(node))
+    }
+
+    /****************
+     * File Browser *
+     ****************/
+
+    /// Show the files in the current directory in a pane to the left of the visible doc, and move
+    /// the cursor there. The `file_browser` layer is added to its doc. If it's already open, close
+    /// it and return to the doc that was visible before.
+    pub fn toggle_file_browser(&mut self) -> Result<(), SynlessError> {
+        use std::path::PathBuf;
+
+        if self.file_browser.is_some() {
+            self.close_file_browser();
+            return Ok(());
+        }
+        if self.scratch_split.is_some() {
+            self.toggle_scratch()?;
+        }
+        let root_dir = PathBuf::from(self.current_dir()?);
+        let prev_doc_name = self.engine.visible_doc_name().cloned();
+        self.file_browser = Some(FileBrowser {
+            expanded: [root_dir.clone()].into_iter().collect(),
+            root_dir,
+            paths: HashMap::new(),
+            prev_doc_name,
+        });
+        let doc_name = DocName::Virtual(FILE_BROWSER_DOC_NAME.to_owned());
+        let result = self.refresh_file_browser().and_then(|()| {
+            self.layers
+                .add_local_layer(&doc_name, FILE_BROWSER_LAYER_NAME)
+        });
+        if let Err(err) = result {
+            self.file_browser = None;
+            let _ = self.engine.delete_doc(&doc_name);
+            return Err(err);
+        }
+        self.engine.set_visible_doc(&doc_name).bug();
+        self.update_pane_notation();
+        Ok(())
+    }
+
+    /// Close the file browser, if it's open, and return to the doc that was visible before.
+    pub fn close_file_browser(&mut self) {
+        let browser = match self.file_browser.take() {
+            Some(browser) => browser,
+            None => return,
+        };
+        let doc_name = DocName::Virtual(FILE_BROWSER_DOC_NAME.to_owned());
+        let _ = self
+            .layers
+            .remove_local_layer(&doc_name, FILE_BROWSER_LAYER_NAME);
+        let _ = self.engine.delete_doc(&doc_name);
+        if let Some(prev_doc_name) = browser.prev_doc_name {
+            let _ = self.engine.set_visible_doc(&prev_doc_name);
+        }
+        self.update_pane_notation();
+    }
+
+    /// If the cursor is on a file in the file browser, close the browser and open the file (or
+    /// switch to it, if it's already open). If it's on a directory, expand or collapse it.
+    pub fn file_browser_select(&mut self) -> Result<(), SynlessError> {
+        let path = self.file_browser_selected_path()?;
+        if path.is_dir() {
+            let browser = self.file_browser.as_mut().bug();
+            if !browser.expanded.remove(&path) {
+                browser.expanded.insert(path.clone());
+            }
+            self.refresh_file_browser()?;
+            self.goto_file_browser_path(&path)
+        } else {
+            let path_string = fs_util::path_to_string(&path)?;
+            self.close_file_browser();
+            self.open_or_switch_to_doc(&path_string)
+        }
+    }
+
+    /// Show the parent of the file browser's directory instead.
+    pub fn file_browser_up(&mut self) -> Result<(), SynlessError> {
+        let browser = self
+            .file_browser
+            .as_mut()
+            .ok_or_else(|| error!(Doc, "The file browser isn't open"))?;
+        let old_root_dir = browser.root_dir.clone();
+        let parent = old_root_dir
+            .parent()
+            .ok_or_else(|| error!(FileSystem, "'{}' has no parent", old_root_dir.display()))?;
+        browser.root_dir = parent.to_owned();
+        browser.expanded.insert(parent.to_owned());
+        self.refresh_file_browser()?;
+        self.goto_file_browser_path(&old_root_dir)
+    }
+
+    /// Rebuild the file browser's doc from the file system. The cursor moves to its start.
+    fn refresh_file_browser(&mut self) -> Result<(), SynlessError> {
+        let language = self.engine.get_language(FILE_BROWSER_LANGUAGE_NAME)?;
+        let browser = self
+            .file_browser
+            .as_mut()
+            .ok_or_else(|| error!(Doc, "The file browser isn't open"))?;
+        let s = self.engine.raw_storage_mut();
+        let mut paths = HashMap::new();
+        let root = build_file_tree(s, language, browser, &mut paths)?;
+        browser.paths = paths;
+        self.engine.set_virtual_doc(FILE_BROWSER_DOC_NAME, root)
+    }
+
+    /// The path of the file or directory under the cursor in the file browser.
+    fn file_browser_selected_path(&self) -> Result<std::path::PathBuf, SynlessError> {
+        let browser = self
+            .file_browser
+            .as_ref()
+            .ok_or_else(|| error!(Doc, "The file browser isn't open"))?;
+        let doc_name = DocName::Virtual(FILE_BROWSER_DOC_NAME.to_owned());
+        let s = self.engine.raw_storage();
+        let cursor = self
+            .engine
+            .get_doc(&doc_name)
+            .ok_or_else(|| error!(Doc, "The file browser's doc was closed"))?
+            .cursor();
+        let mut opt_node = cursor.at_node(s).or_else(|| cursor.in_text_node(s));
+        while let Some(node) = opt_node {
+            if let Some(path) = browser.paths.get(&node.id(s)) {
+                return Ok(path.clone());
+            }
+            opt_node = node.parent(s);
+        }
+        Err(error!(Doc, "No file under the cursor"))
+    }
+
+    fn goto_file_browser_path(&mut self, path: &std::path::Path) -> Result<(), SynlessError> {
+        let browser = self.file_browser.as_ref().bug();
+        let id = browser
+            .paths
+            .iter()
+            .find(|(_, node_path)| *node_path == path)
+            .map(|(id, _)| *id);
+        let doc_name = DocName::Virtual(FILE_BROWSER_DOC_NAME.to_owned());
+        let node = id.and_then(|id| {
+            let doc = self.engine.get_doc(&doc_name)?;
+            doc.find_node(self.engine.raw_storage(), id)
+        });
+        match node {
+            Some(node) => self.engine.execute(TreeNavCommand::GotoNode(node)),
+            None => Ok(()),
+        }
+    }
+
+    /*************
+     * Languages *
+     *************/
+
+    pub fn load_language(&mut self, path: &str) -> Result<String, SynlessError> {
+        use std::fs::read_to_string;
+        use std::path::Path;
+
+        let ron_string = read_to_string(path)
+            .map_err(|err| error!(FileSystem, "Failed to read file at '{path}' ({err})"))?;
+        self.engine.load_language_ron(Path::new(path), &ron_string)
+    }
+
+    pub fn get_language(&mut self, language_name: &str) -> Result<Language, SynlessError> {
+        self.engine.get_language(language_name)
+    }
+
+    pub fn language_constructs(&mut self, language: Language) -> Vec<rhai::Dynamic> {
+        language
+            .constructs(self.engine.raw_storage())
+            .map(rhai::Dynamic::from)
+            .collect()
+    }
+
+    pub fn construct_name(&self, construct: Construct) -> String {
+        construct.name(self.engine.raw_storage()).to_owned()
+    }
+
+    pub fn construct_key(&self, construct: Construct) -> String {
+        construct
+            .key(self.engine.raw_storage())
+            .map(|c| c.to_string())
+            .unwrap_or_default()
+    }
+
+    /// The constructs that can be inserted at the cursor, for the construct completion menu.
+    pub fn insertable_constructs(&self) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        Ok(self
+            .engine
+            .insertable_constructs()?
+            .into_iter()
+            .map(rhai::Dynamic::from)
+            .collect())
+    }
+
+    pub fn construct_preview(&mut self, construct: Construct) -> Result<String, SynlessError> {
+        self.engine.construct_preview(construct)
+    }
+
+    /// The constructs that the node at the cursor can be wrapped in, for the wrap menu.
+    pub fn wrapping_constructs(&self) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        Ok(self
+            .engine
+            .wrapping_constructs()?
+            .into_iter()
+            .map(rhai::Dynamic::from)
+            .collect())
+    }
+
+    /**************
+     * Navigation *
+     **************/
+
+    /// Move to the previous sibling, in the order given by the language's `listy_nav_order`.
+    pub fn tree_nav_prev(&mut self) -> Result<(), SynlessError> {
+        self.tree_nav_sibling(false)
+    }
+
+    /// Move to the next sibling, in the order given by the language's `listy_nav_order`.
+    pub fn tree_nav_next(&mut self) -> Result<(), SynlessError> {
+        self.tree_nav_sibling(true)
+    }
+
+    /// Move to the child of the node at the cursor that appears first on screen.
+    pub fn tree_nav_first_visible_child(&mut self) -> Result<(), SynlessError> {
+        let children = self.children_on_screen(self.node_at_cursor()?)?;
+        match children.first() {
+            Some(child) => self.engine.execute(TreeNavCommand::GotoNode(*child)),
+            None => Err(error!(Edit, "No children on screen")),
+        }
+    }
+
+    /// Move to the child of the node at the cursor that appears last on screen.
+    pub fn tree_nav_last_visible_child(&mut self) -> Result<(), SynlessError> {
+        let children = self.children_on_screen(self.node_at_cursor()?)?;
+        match children.last() {
+            Some(child) => self.engine.execute(TreeNavCommand::GotoNode(*child)),
+            None => Err(error!(Edit, "No children on screen")),
+        }
+    }
+
+    /// The path to the node at the cursor, like `root.2.0`.
+    pub fn path_at_cursor(&mut self) -> Result<String, SynlessError> {
+        self.engine.path_at_cursor()
+    }
+
+    /// Move to the node at `path` in the visible doc, given either as child indices like
+    /// `root.2.0` or with construct names like `root.Object.Pair[2]`.
+    pub fn goto_path(&mut self, path: String) -> Result<(), SynlessError> {
+        let doc_name = self
+            .engine
+            .visible_doc_name()
+            .ok_or_else(|| error!(Doc, "No visible doc"))?
+            .to_owned();
+        self.engine.goto_path(&doc_name, &path)
+    }
+
+    fn tree_nav_sibling(&mut self, forward: bool) -> Result<(), SynlessError> {
+        let (siblings, index) = match self.visual_siblings()? {
+            Some(siblings_and_index) => siblings_and_index,
+            None if forward => return self.engine.execute(TreeNavCommand::Next),
+            None => return self.engine.execute(TreeNavCommand::Prev),
+        };
+        let target = if forward {
+            siblings.get(index + 1)
+        } else {
+            index.checked_sub(1).map(|i| &siblings[i])
+        };
+        match target {
+            Some(target) => self.engine.execute(TreeNavCommand::GotoNode(*target)),
+            None => Err(error!(Edit, "Cannot move there")),
+        }
+    }
+
+    /// If the node at the cursor should be navigated among its siblings in visual order, returns
+    /// the siblings in that order, and the node's index among them.
+    fn visual_siblings(&self) -> Result<Option<(Vec<Node>, usize)>, SynlessError> {
+        use crate::language::{Arity, NavOrder};
+
+        let s = self.engine.raw_storage();
+        let node = match self.node_at_cursor() {
+            Ok(node) => node,
+            Err(_) => return Ok(None),
+        };
+        if node.language(s).listy_nav_order(s) != NavOrder::Visual {
+            return Ok(None);
+        }
+        let parent = match node.parent(s) {
+            Some(parent) if matches!(parent.arity(s), Arity::Listy(_)) => parent,
+            _ => return Ok(None),
+        };
+        let siblings = self.children_on_screen(parent)?;
+        if Some(siblings.len()) != parent.num_children(s) {
+            // Some are off screen, so their visual order is unknown.
+            return Ok(None);
+        }
+        Ok(siblings
+            .iter()
+            .position(|sibling| *sibling == node)
+            .map(|index| (siblings, index)))
+    }
+
+    /// The children of `parent` that were drawn in the last frame, in the order they appeared on
+    /// screen: top to bottom, then left to right.
+    fn children_on_screen(&self, parent: Node) -> Result<Vec<Node>, SynlessError> {
+        let s = self.engine.raw_storage();
+        let size = self
+            .frontend
+            .size()
+            .map_err(|err| error!(Frontend, "{}", err))?;
+        // Maps the id of each node under `parent` to the child of `parent` containing it.
+        let mut child_of_id = HashMap::new();
+        let mut opt_child = parent.first_child(s);
+        while let Some(child) = opt_child {
+            for node in child.preorder(s) {
+                child_of_id.insert(node.id(s), child);
+            }
+            opt_child = child.next_sibling(s);
+        }
+        let mut seen = HashSet::new();
+        let mut children = Vec::new();
+        for row in 0..size.height as Row {
+            for col in 0..size.width as Col {
+                let child = self
+                    .frontend
+                    .node_at(Pos { row, col })
+                    .and_then(|id| child_of_id.get(&id).copied());
+                if let Some(child) = child {
+                    if seen.insert(child.id(s)) {
+                        children.push(child);
+                    }
+                }
+            }
+        }
+        Ok(children)
+    }
+
+    fn node_at_cursor(&self) -> Result<Node, SynlessError> {
+        let doc = self
+            .engine
+            .visible_doc()
+            .ok_or_else(|| error!(Doc, "No visible doc"))?;
+        Ok(doc.node_at_cursor(self.engine.raw_storage())?)
+    }
+
+    /***********
+     * Editing *
+     ***********/
+
+    pub fn undo(&mut self) -> Result<(), SynlessError> {
+        self.engine.undo()
+    }
+
+    pub fn redo(&mut self) -> Result<(), SynlessError> {
+        self.engine.redo()
+    }
+
+    pub fn revert(&mut self) -> Result<(), SynlessError> {
+        self.engine.revert_undo_group()
+    }
+
+    /// Toggle showing the visible doc's undo history tree beside it.
+    pub fn toggle_undo_tree(&mut self) {
+        self.show_undo_tree = !self.show_undo_tree;
+        self.update_pane_notation();
+    }
+
+    /// Move to the same point on the previous branch of the undo history tree.
+    pub fn prev_undo_branch(&mut self) -> Result<(), SynlessError> {
+        self.engine.switch_undo_branch(false)
+    }
+
+    /// Move to the same point on the next branch of the undo history tree.
+    pub fn next_undo_branch(&mut self) -> Result<(), SynlessError> {
+        self.engine.switch_undo_branch(true)
+    }
+
+    /// Jump to the state with the given id in the undo history tree (as shown in its pane), even
+    /// if it's on another branch.
+    pub fn goto_undo_state(&mut self, id: rhai::INT) -> Result<(), SynlessError> {
+        let id = usize::try_from(id).map_err(|_| error!(Edit, "Invalid undo state {id}"))?;
+        self.engine.goto_undo_state(id)
+    }
+
+    /// The visible doc's undo history, newest first, as `#{label, position}` maps. Pass a
+    /// `position` to `goto_undo_history()` to jump to that state.
+    pub fn undo_history(&self) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        let (entries, current) = self.engine.undo_history()?;
+        let now = Instant::now();
+        let marker = |position| {
+            if position == current {
+                " (current)"
+            } else {
+                ""
+            }
+        };
+        let mut history = Vec::new();
+        for (i, entry) in entries.iter().enumerate().rev() {
+            let position = i + 1;
+            let label = format!(
+                "{:>3} {:>8}  {} tree, {} text edits{}",
+                position,
+                format_elapsed(now.duration_since(entry.time)),
+                entry.num_tree_edits,
+                entry.num_text_edits,
+                marker(position)
+            );
+            history.push(history_item(label, position));
+        }
+        let label = format!("{:>3} {:>8}  original{}", 0, "", marker(0));
+        history.push(history_item(label, 0));
+        Ok(history)
+    }
+
+    pub fn goto_undo_history(&mut self, position: rhai::INT) -> Result<(), SynlessError> {
+        let position = usize::try_from(position)
+            .map_err(|_| error!(Edit, "Invalid undo history position {position}"))?;
+        self.engine.goto_undo_history(position)
+    }
+
+    /// How many entries of the visible doc's undo history are currently applied.
+    pub fn undo_history_position(&self) -> Result<rhai::INT, SynlessError> {
+        let (_, position) = self.engine.undo_history()?;
+        Ok(position as rhai::INT)
+    }
+
+    /// The number of entries in the visible doc's undo history.
+    pub fn undo_history_length(&self) -> Result<rhai::INT, SynlessError> {
+        let (entries, _) = self.engine.undo_history()?;
+        Ok(entries.len() as rhai::INT)
+    }
+
+    /// Move `delta` steps forwards (or backwards, if negative) through the visible doc's undo
+    /// history, stopping at either end.
+    pub fn playback_step(&mut self, delta: rhai::INT) -> Result<(), SynlessError> {
+        let (entries, position) = self.engine.undo_history()?;
+        let new_position = (position as rhai::INT + delta).clamp(0, entries.len() as rhai::INT);
+        self.engine.goto_undo_history(new_position as usize)?;
+        self.log_info(format!("State {} of {}", new_position, entries.len()));
+        Ok(())
+    }
+
+    /// Step forwards through the visible doc's undo history, redisplaying after each step and
+    /// pausing for `interval_ms` milliseconds, until the end of the history is reached or any key
+    /// is pressed.
+    pub fn playback_play(&mut self, interval_ms: rhai::INT) -> Result<(), SynlessError> {
+        let interval_ms = u64::try_from(interval_ms)
+            .map_err(|_| error!(Edit, "Invalid playback interval {interval_ms}"))?;
+        let interval = Duration::from_millis(interval_ms);
+        loop {
+            let (entries, position) = self.engine.undo_history()?;
+            if position >= entries.len() {
+                return Ok(());
+            }
+            self.playback_step(1)?;
+            self.display()?;
+            match self.frontend.next_event(interval) {
+                Ok(None) => (),
+                // The event that stopped playback is otherwise ignored.
+                Ok(Some(_)) => return Ok(()),
+                Err(err) => return Err(error!(Frontend, "{}", err)),
+            }
+        }
+    }
+
+    /// The structural differences between the node at the cursor and the node at the cursor in
+    /// the open doc at `path`.
+    pub fn diff_with_doc(&self, path: &str) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        use std::path::PathBuf;
+
+        Ok(self
+            .engine
+            .diff_with_doc(&DocName::File(PathBuf::from(path)))?
+            .into_iter()
+            .map(rhai::Dynamic::from)
+            .collect())
+    }
+
+    /// Replace the node at the cursor with a copy of the node at the cursor in the open doc at
+    /// `path`.
+    pub fn replace_from_doc(&mut self, path: &str) -> Result<(), SynlessError> {
+        use std::path::PathBuf;
+
+        self.engine
+            .replace_from_doc(&DocName::File(PathBuf::from(path)))
+    }
+
+    pub fn clipboard_summaries(&self) -> Vec<rhai::Dynamic> {
+        self.engine
+            .clipboard_summaries()
+            .into_iter()
+            .map(rhai::Dynamic::from)
+            .collect()
+    }
+
+    /// A short label for the node at the cursor. See [`Node::summary`].
+    pub fn node_summary(&mut self) -> Result<String, SynlessError> {
+        let node = self.engine.node_at_cursor(false)?;
+        Ok(node.summary(self.engine.raw_storage()))
+    }
+
+    pub fn insert_node(&mut self, construct: Construct) -> Result<(), SynlessError> {
+        let node = Node::new_with_auto_fill(self.engine.raw_storage_mut(), construct);
+        self.engine.execute(TreeEdCommand::Insert(node))?;
+        self.engine.execute(TreeNavCommand::FirstInsertLoc)
+    }
+
+    /// Sort the children of the list at the cursor, comparing either their `"source"` or their
+    /// first `"text"` (like the keys of a JSON object).
+    pub fn sort_children(&mut self, by: &str) -> Result<(), SynlessError> {
+        let by = match by {
+            "source" => SortChildrenBy::Source,
+            "text" => SortChildrenBy::Text,
+            _ => return Err(error!(Edit, "Unknown sort key '{by}'")),
+        };
+        self.engine.sort_children(by)
+    }
+
+    /// Wrap the node at the cursor (or the selected nodes) in a new node of the given construct,
+    /// whose other children are left as holes.
+    pub fn wrap_node(&mut self, construct: Construct) -> Result<(), SynlessError> {
+        let wrapper = Node::new(self.engine.raw_storage_mut(), construct);
+        if let Err(err) = self.engine.execute(TreeEdCommand::Wrap(wrapper)) {
+            wrapper.delete_root(self.engine.raw_storage_mut()).bug();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// The names of the surrounds defined by the visible doc's language.
+    pub fn surround_names(&self) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        let doc = self
+            .engine
+            .visible_doc()
+            .ok_or_else(|| error!(Doc, "No visible doc"))?;
+        let s = self.engine.raw_storage();
+        let language = doc.cursor().root_node(s).language(s);
+        Ok(language
+            .surround_names(s)
+            .map(|name| rhai::Dynamic::from(name.to_owned()))
+            .collect())
+    }
+
+    /// Wrap the node at the cursor in the construct of the surround called `name`.
+    pub fn surround(&mut self, name: &str) -> Result<(), SynlessError> {
+        let node = self.engine.node_at_cursor(false)?;
+        let s = self.engine.raw_storage_mut();
+        let construct = lookup_surround(s, node, name)?;
+        let wrapper = Node::new(s, construct);
+        if let Err(err) = self.engine.execute(TreeEdCommand::Wrap(wrapper)) {
+            wrapper.delete_root(self.engine.raw_storage_mut()).bug();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Remove the nearest surround around the cursor, keeping what it contained.
+    pub fn delete_surround(&mut self) -> Result<(), SynlessError> {
+        let surround = self.nearest_surround()?;
+        self.engine.execute(TreeNavCommand::GotoNode(surround))?;
+        self.engine.execute(TreeEdCommand::Unwrap)
+    }
+
+    /// Replace the nearest surround around the cursor with the surround called `name`.
+    pub fn change_surround(&mut self, name: &str) -> Result<(), SynlessError> {
+        let surround = self.nearest_surround()?;
+        let s = self.engine.raw_storage_mut();
+        let old_construct = surround.construct(s);
+        let new_construct = lookup_surround(s, surround, name)?;
+        self.engine.execute(TreeNavCommand::GotoNode(surround))?;
+        self.engine.execute(TreeEdCommand::Unwrap)?;
+
+        let s = self.engine.raw_storage_mut();
+        let wrapper = Node::new(s, new_construct);
+        if let Err(err) = self.engine.execute(TreeEdCommand::Wrap(wrapper)) {
+            // Put the old surround back, so that a failed change leaves the doc as it was.
+            let s = self.engine.raw_storage_mut();
+            wrapper.delete_root(s).bug();
+            let old_wrapper = Node::new(s, old_construct);
+            self.engine.execute(TreeEdCommand::Wrap(old_wrapper))?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// The node at the cursor or its nearest ancestor that was made by a surround, and which
+    /// therefore can be unwrapped.
+    fn nearest_surround(&mut self) -> Result<Node, SynlessError> {
+        let mut node = self.engine.node_at_cursor(false)?;
+        let s = self.engine.raw_storage();
+        loop {
+            if node.language(s).is_surround_construct(s, node.construct(s))
+                && node.num_children(s) == Some(1)
+            {
+                return Ok(node);
+            }
+            node = node
+                .parent(s)
+                .ok_or_else(|| error!(Edit, "No surround around the cursor"))?;
+        }
+    }
+
+    /// Add a template called `name` to the language `language_name`, replacing any existing
+    /// template of that name. `tree` describes the template the same way as in
+    /// `set_virtual_doc()`; use the `$hole` construct for the parts left to fill in.
+    pub fn add_template(
+        &mut self,
+        language_name: &str,
+        name: String,
+        tree: rhai::Dynamic,
+    ) -> Result<(), SynlessError> {
+        let language = self.engine.get_language(language_name)?;
+        let s = self.engine.raw_storage_mut();
+        let template = build_virtual_node(s, language, tree, "add_template")?;
+        language.add_template(s, name, template);
+        Ok(())
+    }
+
+    /// The names of the templates defined for the visible doc's language.
+    pub fn template_names(&self) -> Result<Vec<rhai::Dynamic>, SynlessError> {
+        let doc = self
+            .engine
+            .visible_doc()
+            .ok_or_else(|| error!(Doc, "No visible doc"))?;
+        let s = self.engine.raw_storage();
+        let language = doc.cursor().root_node(s).language(s);
+        Ok(language
+            .template_names(s)
+            .map(|name| rhai::Dynamic::from(name.to_owned()))
+            .collect())
+    }
+
+    /// Insert a copy of the template called `name` at the cursor, then move to its first hole.
+    pub fn insert_template(&mut self, name: &str) -> Result<(), SynlessError> {
+        let doc = self
+            .engine
+            .visible_doc()
+            .ok_or_else(|| error!(Doc, "No visible doc"))?;
+        let s = self.engine.raw_storage();
+        let language = doc.cursor().root_node(s).language(s);
+        let template = language.template(s, name).ok_or_else(|| {
+            error!(
+                Edit,
+                "No template '{name}' in language '{}'",
+                language.name(s)
+            )
+        })?;
+        let node = template.deep_copy(self.engine.raw_storage_mut());
+        if let Err(err) = self.engine.execute(TreeEdCommand::Insert(node)) {
+            node.delete_root(self.engine.raw_storage_mut()).bug();
+            return Err(err);
+        }
+        match first_hole(self.engine.raw_storage(), node) {
+            Some(hole) => self.engine.execute(TreeNavCommand::GotoNode(hole)),
+            None => self.engine.execute(TreeNavCommand::FirstInsertLoc),
+        }
+    }
+
+    pub fn search_for_construct(&mut self, construct: Construct) -> Result<(), SynlessError> {
+        let search = Search::new_construct(construct);
+        self.engine.execute(SearchCommand::Set(search))
+    }
+
+    pub fn search_for_node_at_cursor(&mut self) -> Result<(), SynlessError> {
+        let node = self.engine.node_at_cursor(true)?; // deep copy
+        let search = Search::new_node(node);
+        self.engine.execute(SearchCommand::Set(search))
+    }
+
+    pub fn search_for_pattern_at_cursor(&mut self) -> Result<(), SynlessError> {
+        let node = self.engine.node_at_cursor(true)?; // deep copy
+        let search = Search::new_pattern(node);
+        self.engine.execute(SearchCommand::Set(search))
+    }
+
+    pub fn replace_search_match(&mut self) -> Result<(), SynlessError> {
+        self.engine.replace_search_match()
+    }
+
+    pub fn search_for_substring(&mut self, substring: String) -> Result<(), SynlessError> {
         let search = Search::new_substring(substring);
         self.engine.execute(SearchCommand::Set(search))
     }
@@ -506,6 +3825,327 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         self.engine.execute(SearchCommand::Set(search))
     }
 
+    /// Search for nodes matching a query like `Array > String and startswith("a")`, with construct
+    /// names from the visible doc's language.
+    pub fn search_for_query(&mut self, query: String) -> Result<(), SynlessError> {
+        let search = self.make_search(SearchKind::Query, &query)?;
+        self.engine.execute(SearchCommand::Set(search))
+    }
+
+    /// Begin searching the visible doc for what's typed into the input string menu that's about
+    /// to be opened, as it's typed: after each keypress, the matches are highlighted and the
+    /// cursor moves to the nearest one. `kind` is "substring", "regex", or "query", saying how the
+    /// input is searched for. End the search with `commit_incremental_search()` or
+    /// `cancel_incremental_search()`. Scripts will usually want to call
+    /// `s::incremental_search()` instead, which does all of this.
+    pub fn begin_incremental_search(&mut self, kind: &str) -> Result<(), SynlessError> {
+        let kind = match kind {
+            "substring" => SearchKind::Substring,
+            "regex" => SearchKind::Regex,
+            "query" => SearchKind::Query,
+            _ => {
+                return Err(error!(
+                    Edit,
+                    "Unknown search kind '{kind}'. Expected one of: substring, regex, query"
+                ))
+            }
+        };
+        self.engine.begin_incremental_search()?;
+        self.incremental_search = Some(IncrementalSearch {
+            kind,
+            input: String::new(),
+        });
+        Ok(())
+    }
+
+    /// End the incremental search by searching for `input`, leaving the cursor at its nearest
+    /// match. If `input` is empty, the search from before is kept. If `input` is invalid, the
+    /// incremental search is cancelled instead.
+    pub fn commit_incremental_search(&mut self, input: String) -> Result<(), SynlessError> {
+        let kind = match self.incremental_search.take() {
+            Some(incremental_search) => incremental_search.kind,
+            None => return Err(error!(Edit, "No incremental search in progress")),
+        };
+        let search = if input.is_empty() {
+            None
+        } else {
+            match self.make_search(kind, &input) {
+                Ok(search) => Some(search),
+                Err(err) => {
+                    self.engine.end_incremental_search(false)?;
+                    return Err(err);
+                }
+            }
+        };
+        let has_match = self.engine.preview_search(search)?;
+        self.engine.end_incremental_search(true)?;
+        if !has_match && !input.is_empty() {
+            return Err(error!(Edit, "No matches for '{input}'"));
+        }
+        Ok(())
+    }
+
+    /// End the incremental search, putting the cursor and the search back how they were before
+    /// it began.
+    pub fn cancel_incremental_search(&mut self) -> Result<(), SynlessError> {
+        self.incremental_search = None;
+        self.engine.end_incremental_search(false)
+    }
+
+    /// If an incremental search is in progress and its menu's input has changed, preview the new
+    /// search. This happens in the middle of handling a keypress, so errors are logged instead of
+    /// returned.
+    fn update_incremental_search(&mut self) {
+        let (kind, input) = match (&self.incremental_search, self.layers.menu_input_string()) {
+            (Some(incremental_search), Some(input)) if input != incremental_search.input => {
+                (incremental_search.kind, input.to_owned())
+            }
+            _ => return,
+        };
+        // A half-typed regex or query is often invalid, so that's treated like an empty input
+        // until it's committed.
+        let search = if input.is_empty() {
+            None
+        } else {
+            self.make_search(kind, &input).ok()
+        };
+        if let Err(err) = self.engine.preview_search(search) {
+            self.log_error(err.to_string());
+            return;
+        }
+        self.incremental_search.as_mut().bug().input = input;
+    }
+
+    fn make_search(&self, kind: SearchKind, input: &str) -> Result<Search, SynlessError> {
+        match kind {
+            SearchKind::Substring => Ok(Search::new_substring(input.to_owned())),
+            SearchKind::Regex => Ok(Search::new_regex(input)?),
+            SearchKind::Query => {
+                let doc = self
+                    .engine
+                    .visible_doc()
+                    .ok_or_else(|| error!(Doc, "No visible doc"))?;
+                let s = self.engine.raw_storage();
+                let language = doc.cursor().root_node(s).language(s);
+                Ok(Search::new_query(s, language, input)?)
+            }
+        }
+    }
+
+    /// Replace every match of the current search, as `replace_search_match()` does.
+    pub fn replace_all_search_matches(&mut self) -> Result<(), SynlessError> {
+        let count = self.engine.replace_all_search_matches()?;
+        self.log_info(format!("Replaced {count} matches"));
+        Ok(())
+    }
+
+    /*********
+     * Trees *
+     *********/
+
+    /// The root of the visible doc.
+    pub fn root_node(&self) -> Result<Node, SynlessError> {
+        self.engine.visible_doc_root()
+    }
+
+    pub fn cursor_node(&mut self) -> Result<Node, SynlessError> {
+        self.engine.node_at_cursor(false)
+    }
+
+    pub fn node_construct(&self, node: Node) -> Result<Construct, SynlessError> {
+        let s = self.engine.raw_storage();
+        Ok(self.valid_node(node)?.construct(s))
+    }
+
+    /// One of `"texty"`, `"fixed"`, or `"listy"`.
+    pub fn node_arity(&self, node: Node) -> Result<String, SynlessError> {
+        let s = self.engine.raw_storage();
+        Ok(match self.valid_node(node)?.arity(s) {
+            Arity::Texty => "texty",
+            Arity::Fixed(_) => "fixed",
+            Arity::Listy(_) => "listy",
+        }
+        .to_owned())
+    }
+
+    pub fn node_is_hole(&self, node: Node) -> Result<bool, SynlessError> {
+        Ok(self.valid_node(node)?.is_hole(self.engine.raw_storage()))
+    }
+
+    /// The node's text, or `()` if it isn't texty.
+    pub fn node_text(&self, node: Node) -> Result<rhai::Dynamic, SynlessError> {
+        let s = self.engine.raw_storage();
+        Ok(match self.valid_node(node)?.text(s) {
+            Some(text) => text.as_str().into(),
+            None => rhai::Dynamic::UNIT,
+        })
+    }
+
+    pub fn node_children(&self, node: Node) -> Result<rhai::Array, SynlessError> {
+        let s = self.engine.raw_storage();
+        let node = self.valid_node(node)?;
+        Ok(
+            std::iter::successors(node.first_child(s), |child| child.next_sibling(s))
+                .map(rhai::Dynamic::from)
+                .collect(),
+        )
+    }
+
+    /// The node's parent, or `()` if it's a root.
+    pub fn node_parent(&self, node: Node) -> Result<rhai::Dynamic, SynlessError> {
+        let s = self.engine.raw_storage();
+        Ok(match self.valid_node(node)?.parent(s) {
+            Some(parent) => rhai::Dynamic::from(parent),
+            None => rhai::Dynamic::UNIT,
+        })
+    }
+
+    /// A new node of the given construct, with holes for children if it's fixed, and no children
+    /// or text otherwise. It belongs to the script until it's placed with `replace_node()`.
+    pub fn new_node(&mut self, construct: Construct) -> Node {
+        let node = Node::new(self.engine.raw_storage_mut(), construct);
+        self.add_script_tree(node);
+        node
+    }
+
+    pub fn new_hole(&mut self, language: Language) -> Node {
+        let node = Node::new_hole(self.engine.raw_storage_mut(), language);
+        self.add_script_tree(node);
+        node
+    }
+
+    /// A copy of `node` and its descendants, which belongs to the script. Any node can be copied,
+    /// including those in docs.
+    pub fn copy_node(&mut self, node: Node) -> Result<Node, SynlessError> {
+        let node = self.valid_node(node)?;
+        let copy = node.deep_copy(self.engine.raw_storage_mut());
+        self.add_script_tree(copy);
+        Ok(copy)
+    }
+
+    pub fn set_node_text(&mut self, node: Node, text: String) -> Result<(), SynlessError> {
+        self.check_script_tree(node)?;
+        let s = self.engine.raw_storage_mut();
+        let construct_name = node.construct(s).name(s).to_owned();
+        let node_text = node
+            .text_mut(s)
+            .ok_or_else(|| error!(Edit, "'{construct_name}' is not texty"))?;
+        node_text.set(text);
+        Ok(())
+    }
+
+    /// Insert the root `child` into the listy `parent` so that it's at `index` among its
+    /// children. Both must belong to the script.
+    pub fn insert_child(
+        &mut self,
+        parent: Node,
+        index: rhai::INT,
+        child: Node,
+    ) -> Result<(), SynlessError> {
+        self.check_script_tree(parent)?;
+        self.check_script_tree(child)?;
+        let s = self.engine.raw_storage_mut();
+        let num_children = parent.num_children(s).unwrap_or(0);
+        let index = usize::try_from(index)
+            .ok()
+            .filter(|index| *index <= num_children)
+            .ok_or_else(|| error!(Edit, "insert_child: invalid index {index}"))?;
+        if index == 0 {
+            parent.insert_first_child(s, child)?;
+        } else {
+            parent
+                .nth_child(s, index - 1)
+                .bug()
+                .insert_after(s, child)?;
+        }
+        self.script_trees.remove(&child.id(s));
+        Ok(())
+    }
+
+    /// Remove `node` from its listy parent, making it the root of its own tree.
+    pub fn detach_node(&mut self, node: Node) -> Result<(), SynlessError> {
+        self.check_script_tree(node)?;
+        node.detach(self.engine.raw_storage_mut())?;
+        self.add_script_tree(node);
+        Ok(())
+    }
+
+    /// Swap two nodes (which may be in different trees), if the grammar allows each in the
+    /// other's place.
+    pub fn swap_nodes(&mut self, node: Node, other: Node) -> Result<(), SynlessError> {
+        self.check_script_tree(node)?;
+        self.check_script_tree(other)?;
+        let s = self.engine.raw_storage_mut();
+        node.swap(s, other)?;
+        for swapped in [node, other] {
+            if swapped.is_root(s) {
+                self.script_trees.insert(swapped.id(s));
+            } else {
+                self.script_trees.remove(&swapped.id(s));
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete a tree that belongs to the script. `node` must be its root.
+    pub fn delete_node(&mut self, node: Node) -> Result<(), SynlessError> {
+        self.check_script_tree(node)?;
+        let s = self.engine.raw_storage_mut();
+        let id = node.id(s);
+        node.delete_root(s)?;
+        self.script_trees.remove(&id);
+        Ok(())
+    }
+
+    /// Move the cursor to `node`, which must be in the visible doc.
+    pub fn goto_node(&mut self, node: Node) -> Result<(), SynlessError> {
+        self.engine.goto_node(node)
+    }
+
+    /// Replace `node`, which must be in the visible doc, with `new_node`, which must be the root of
+    /// a tree that belongs to the script. This is an ordinary edit that can be undone, and
+    /// afterwards the tree belongs to the doc.
+    pub fn replace_node(&mut self, node: Node, new_node: Node) -> Result<(), SynlessError> {
+        self.check_script_tree(new_node)?;
+        let s = self.engine.raw_storage();
+        if !new_node.is_root(s) {
+            return Err(error!(Edit, "replace_node: the new node must be a root"));
+        }
+        let id = new_node.id(s);
+        self.engine.replace_node(node, new_node)?;
+        self.script_trees.remove(&id);
+        Ok(())
+    }
+
+    fn valid_node(&self, node: Node) -> Result<Node, SynlessError> {
+        if node.is_valid(self.engine.raw_storage()) {
+            Ok(node)
+        } else {
+            Err(error!(Edit, "Node has been deleted"))
+        }
+    }
+
+    fn add_script_tree(&mut self, root: Node) {
+        let id = root.id(self.engine.raw_storage());
+        self.script_trees.insert(id);
+    }
+
+    /// Check that `node` is in a tree that belongs to the script, so that modifying it can't
+    /// corrupt a doc or its undo history.
+    fn check_script_tree(&self, node: Node) -> Result<(), SynlessError> {
+        let s = self.engine.raw_storage();
+        let node = self.valid_node(node)?;
+        if self.script_trees.contains(&node.root(s).id(s)) {
+            Ok(())
+        } else {
+            Err(error!(
+                Edit,
+                "Can't modify a node in a doc directly. Copy it with copy_node(), or use \
+                 replace_node()."
+            ))
+        }
+    }
+
     /*************
      * Clipboard *
      *************/
@@ -515,6 +4155,32 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         self.engine.execute(TreeEdCommand::Backspace)
     }
 
+    /// Pretty-print the node at the cursor in its source notation at `width`, and put the text on
+    /// the system clipboard. This is separate from the structural clipboard used by `copy()` and
+    /// `paste()`.
+    pub fn copy_as_text(&mut self, width: rhai::INT) -> Result<(), SynlessError> {
+        let width =
+            Width::try_from(width).map_err(|_| error!(Frontend, "Invalid width: {width}"))?;
+        let text = self.engine.print_node_at_cursor(Some(width))?;
+        self.frontend
+            .copy_to_system_clipboard(&text)
+            .map_err(|err| error!(Frontend, "{}", err))?;
+        let num_lines = text.lines().count();
+        self.log_info(format!("Copied {num_lines} lines as text"));
+        Ok(())
+    }
+
+    /// Paste `text` at the cursor. In tree mode it's parsed with the doc's parser, falling back to
+    /// a texty node if it doesn't parse into something that fits there. This is what happens when
+    /// text is pasted into the terminal.
+    pub fn paste_text(&mut self, text: String) -> Result<(), SynlessError> {
+        let num_nodes = self.engine.paste_text(&text)?;
+        if num_nodes > 1 {
+            self.log_info(format!("Pasted {num_nodes} nodes"));
+        }
+        Ok(())
+    }
+
     /**************************
      * Command Line Interface *
      **************************/
@@ -547,9 +4213,21 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
                 if mode != Mode::Text && !self.layers.has_open_menu() {
                     let _ = self.engine.end_undo_group();
                 }
+                // Keypresses in the command history itself would bury the entries being browsed.
+                self.recording_commands = self.command_history_view.is_none();
+                if self.recording_commands {
+                    self.push_command_record(CommandRecord {
+                        key,
+                        label: key_prog.label().to_owned(),
+                        menu: self.layers.menu_description().map(|s| s.to_owned()),
+                        prog: key_prog.clone(),
+                        commands: Vec::new(),
+                    });
+                }
                 Ok(Some(key_prog))
             }
             Some(KeyLookupResult::Redisplay) => {
+                self.update_incremental_search();
                 self.display()?;
                 Ok(None)
             }
@@ -561,62 +4239,582 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         }
     }
 
-    /// Block until the next input event.
-    fn next_event(&mut self) -> Result<Event, SynlessError> {
-        loop {
-            match self.frontend.next_event(Duration::from_secs(1)) {
-                Ok(None) => (), // continue waiting
-                Ok(Some(event)) => return Ok(event),
-                Err(err) => return Err(error!(Frontend, "{}", err)),
-            }
+    /// Clicking the left button on a node moves the cursor to it (in tree mode).
+    ///
+    /// Drag-and-drop: pressing the left button on a node picks it up, dragging highlights the
+    /// node under the mouse if it's a valid place to drop it, and releasing moves it there.
+    fn handle_mouse(&mut self, event: MouseEvent) -> Result<(), SynlessError> {
+        if !matches!(event.button, MouseButton::Left) {
+            return Ok(());
+        }
+        let opt_id = self.frontend.node_at(event.pos);
+        let opt_node = match event.kind {
+            MouseEventKind::Press => opt_id.and_then(|id| self.engine.visible_node_with_id(id)),
+            MouseEventKind::Drag | MouseEventKind::Release => {
+                // Looking up each id in the doc would take time proportional to its size, on
+                // every drag event.
+                let engine = &self.engine;
+                let drag_nodes = self.drag_nodes.get_or_insert_with(|| {
+                    let s = engine.raw_storage();
+                    engine
+                        .visible_doc_root()
+                        .map(|root| root.preorder(s).map(|node| (node.id(s), node)).collect())
+                        .unwrap_or_default()
+                });
+                opt_id.and_then(|id| drag_nodes.get(&id).copied())
+            }
+        };
+
+        match event.kind {
+            MouseEventKind::Press => {
+                self.drag_nodes = None;
+                if let Some(node) = opt_node {
+                    if self.engine.mode() == Mode::Tree && !node.is_root(self.engine.raw_storage())
+                    {
+                        self.engine.execute(TreeNavCommand::GotoNode(node))?;
+                        self.display()?;
+                    }
+                }
+                self.drag_source = opt_node;
+            }
+            MouseEventKind::Drag => {
+                let opt_target = self.drag_source.and_then(|source| {
+                    opt_node.filter(|target| self.engine.can_move_node(source, *target))
+                });
+                self.engine.set_drop_target(opt_target);
+                self.display()?;
+            }
+            MouseEventKind::Release => {
+                self.drag_nodes = None;
+                self.engine.set_drop_target(None);
+                if let (Some(source), Some(target)) = (self.drag_source.take(), opt_node) {
+                    if source != target {
+                        self.engine.move_node(source, target)?;
+                    }
+                }
+                self.display()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Block until the next input event, or until there's deferred work to do (see
+    /// `take_deferred()`), in which case returns `None`.
+    fn next_event(&mut self) -> Result<Option<Event>, SynlessError> {
+        loop {
+            self.poll_commands();
+            let now = Instant::now();
+            let next_due = self.timers.iter().map(|timer| timer.due).min();
+            // Wake up in time to redisplay without the next notification that expires.
+            let next_wakeup = match (next_due, self.next_notification_expiry()) {
+                (Some(due), Some(expiry)) => Some(due.min(expiry)),
+                (due, expiry) => due.or(expiry),
+            };
+            let timer_is_due = next_due.map(|due| due <= now).unwrap_or(false);
+            if timer_is_due || self.commands.iter().any(|cmd| cmd.output.is_some()) {
+                return Ok(None);
+            }
+            let mut timeout = match next_wakeup {
+                Some(wakeup) => wakeup.saturating_duration_since(now).min(MAX_EVENT_WAIT),
+                None => MAX_EVENT_WAIT,
+            };
+            if !self.commands.is_empty() || self.engine.has_background_parses() {
+                timeout = timeout.min(COMMAND_POLL_INTERVAL);
+            }
+            if self.engine.has_pending_loads() {
+                timeout = Duration::ZERO;
+            }
+            match self.frontend.next_event(timeout) {
+                Ok(None) => {
+                    let keys_expired = self.layers.expire_pending_keys();
+                    let config_changed = self.poll_config_file();
+                    let notifications_expired = self.expire_notifications();
+                    let docs_loaded = self.load_pending_docs();
+                    if self.poll_tracked_files()
+                        || config_changed
+                        || keys_expired
+                        || notifications_expired
+                        || docs_loaded
+                    {
+                        self.display()?;
+                    }
+                }
+                Ok(Some(event)) => return Ok(Some(event)),
+                Err(err) => return Err(error!(Frontend, "{}", err)),
+            }
+        }
+    }
+
+    /// Work that's ready to be done in place of a key's program: the callback of an external
+    /// command that's finished, or else of a timer that's due.
+    fn take_deferred(&mut self) -> Option<KeyProg> {
+        let prog = self
+            .take_finished_command()
+            .or_else(|| self.take_due_timer())?;
+        // Like a keypress, deferred work gets its own undo group.
+        if self.engine.mode() != Mode::Text && !self.layers.has_open_menu() {
+            let _ = self.engine.end_undo_group();
+        }
+        Some(prog)
+    }
+
+    /// If a timer is due, the callback of the one that was due first. It's removed, or if it
+    /// repeats, rescheduled for one interval from now (skipping any calls that were missed).
+    fn take_due_timer(&mut self) -> Option<KeyProg> {
+        let now = Instant::now();
+        let index = self
+            .timers
+            .iter()
+            .enumerate()
+            .filter(|(_, timer)| timer.due <= now)
+            .min_by_key(|(_, timer)| timer.due)
+            .map(|(index, _)| index)?;
+        let callback = self.timers[index].callback.clone();
+        match self.timers[index].interval {
+            Some(interval) => self.timers[index].due = now + interval,
+            None => {
+                self.timers.remove(index);
+            }
+        }
+        Some(KeyProg::deferred(callback, "Timer"))
+    }
+
+    /// Receive the output of any external commands that have finished.
+    fn poll_commands(&mut self) {
+        for command in &mut self.commands {
+            if command.output.is_none() {
+                match command.receiver.try_recv() {
+                    Ok(output) => command.output = Some(output),
+                    Err(mpsc::TryRecvError::Empty) => (),
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        let err = io::Error::new(io::ErrorKind::Other, "Lost track of the command");
+                        command.output = Some(Err(err));
+                    }
+                }
+            }
+        }
+    }
+
+    /// If an external command has finished, its callback, with its result curried in. See
+    /// `spawn_command()`.
+    fn take_finished_command(&mut self) -> Option<KeyProg> {
+        let index = self
+            .commands
+            .iter()
+            .position(|command| command.output.is_some())?;
+        let command = self.commands.remove(index);
+        let (status, stdout, stderr) = match command.output.bug() {
+            Ok(output) => (
+                match output.status.code() {
+                    Some(code) => (code as rhai::INT).into(),
+                    None => rhai::Dynamic::UNIT,
+                },
+                String::from_utf8_lossy(&output.stdout).into_owned(),
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ),
+            Err(err) => (rhai::Dynamic::UNIT, String::new(), err.to_string()),
+        };
+        let mut result = rhai::Map::new();
+        result.insert("id".into(), command.id.into());
+        result.insert("status".into(), status);
+        result.insert("stdout".into(), stdout.into());
+        result.insert("stderr".into(), stderr.into());
+        let mut callback = command.callback;
+        callback.add_curry(result.into());
+        Some(KeyProg::deferred(callback, "CommandFinished"))
+    }
+}
+
+/*********************
+ * Menu Construction *
+ *********************/
+
+#[derive(Debug, Clone)]
+pub struct MenuBuilder {
+    name: String,
+    description: String,
+    keymap: Option<Keymap>,
+    kind: MenuKind,
+}
+
+pub fn make_menu(menu_name: String, description: String) -> MenuBuilder {
+    MenuBuilder {
+        name: menu_name,
+        description,
+        keymap: None,
+        kind: MenuKind::Char,
+    }
+}
+
+pub fn set_menu_kind_to_candidate(menu: &mut MenuBuilder, default_to_custom_candidate: bool) {
+    menu.kind = MenuKind::Candidate {
+        default_to_custom_candidate,
+    };
+}
+
+pub fn set_menu_kind_to_input_string(menu: &mut MenuBuilder) {
+    menu.kind = MenuKind::InputString;
+}
+
+pub fn set_menu_kind_to_char(menu: &mut MenuBuilder) {
+    menu.kind = MenuKind::Char;
+}
+
+pub fn set_menu_keymap(menu: &mut MenuBuilder, keymap: Keymap) {
+    menu.keymap = Some(keymap);
+}
+
+/*************
+ * Surrounds *
+ *************/
+
+/// The construct of the surround called `name` in `node`'s language.
+fn lookup_surround(s: &Storage, node: Node, name: &str) -> Result<Construct, SynlessError> {
+    let language = node.language(s);
+    language.surround(s, name).ok_or_else(|| {
+        error!(
+            Language,
+            "Language '{}' has no surround named '{name}'",
+            language.name(s)
+        )
+    })
+}
+
+/****************
+ * Virtual Docs *
+ ****************/
+
+/// Build a (root) node from its description as a rhai map. See `Runtime::set_virtual_doc()`.
+fn build_virtual_node(
+    s: &mut Storage,
+    language: Language,
+    tree: rhai::Dynamic,
+    fn_name: &str,
+) -> Result<Node, SynlessError> {
+    let mut map = tree
+        .try_cast::<rhai::Map>()
+        .ok_or_else(|| error!(Doc, "{fn_name}: expected each node to be a map"))?;
+    let construct_name = map
+        .remove("construct")
+        .and_then(|name| name.into_string().ok())
+        .ok_or_else(|| error!(Doc, "{fn_name}: node is missing a 'construct' name"))?;
+    let construct = language.construct(s, &construct_name).ok_or_else(|| {
+        error!(
+            Doc,
+            "{fn_name}: no construct '{construct_name}' in language '{}'",
+            language.name(s)
+        )
+    })?;
+
+    if let Some(text) = map.remove("text") {
+        let text = text
+            .into_string()
+            .map_err(|_| error!(Doc, "{fn_name}: 'text' must be a string"))?;
+        return Node::with_text(s, construct, text).map_err(|err| error!(Doc, "{fn_name}: {err}"));
+    }
+
+    let children = match map.remove("children") {
+        None => Vec::new(),
+        Some(children) => children
+            .try_cast::<rhai::Array>()
+            .ok_or_else(|| error!(Doc, "{fn_name}: 'children' must be an array"))?,
+    };
+    let mut child_nodes = Vec::new();
+    for child in children {
+        match build_virtual_node(s, language, child, fn_name) {
+            Ok(node) => child_nodes.push(node),
+            Err(err) => {
+                for node in child_nodes {
+                    node.delete_root(s).bug();
+                }
+                return Err(err);
+            }
+        }
+    }
+    match Node::with_children(s, construct, child_nodes.iter().copied()) {
+        Ok(node) => Ok(node),
+        Err(err) => {
+            for node in child_nodes {
+                node.delete_root(s).bug();
+            }
+            Err(error!(
+                Doc,
+                "{fn_name}: invalid children for construct '{construct_name}' ({err})"
+            ))
+        }
+    }
+}
+
+fn notification_level(level: &str) -> Result<LogLevel, SynlessError> {
+    match level {
+        "info" => Ok(LogLevel::Info),
+        "warning" => Ok(LogLevel::Warn),
+        "error" => Ok(LogLevel::Error),
+        _ => Err(error!(
+            Frontend,
+            "Unknown notification level '{level}'. Expected info, warning, or error"
+        )),
+    }
+}
+
+fn timer_duration(ms: rhai::INT) -> Result<Duration, SynlessError> {
+    let ms = u64::try_from(ms).map_err(|_| error!(Edit, "Invalid timer delay: {ms}ms"))?;
+    Ok(Duration::from_millis(ms))
+}
+
+/// The first hole in document order at or under `node`, if any.
+fn first_hole(s: &Storage, node: Node) -> Option<Node> {
+    if node.is_hole(s) {
+        return Some(node);
+    }
+    let mut child = node.first_child(s);
+    while let Some(node) = child {
+        if let Some(hole) = first_hole(s, node) {
+            return Some(hole);
+        }
+        child = node.next_sibling(s);
+    }
+    None
+}
+
+/// Describe a node as a rhai map, in the form accepted by `build_virtual_node()`.
+fn virtual_node_to_dynamic(s: &Storage, node: Node) -> rhai::Dynamic {
+    let mut map = rhai::Map::new();
+    map.insert(
+        "construct".into(),
+        node.construct(s).name(s).to_owned().into(),
+    );
+    if let Some(text) = node.text(s) {
+        map.insert("text".into(), text.as_str().to_owned().into());
+    } else {
+        let mut children = rhai::Array::new();
+        let mut opt_child = node.first_child(s);
+        while let Some(child) = opt_child {
+            children.push(virtual_node_to_dynamic(s, child));
+            opt_child = child.next_sibling(s);
         }
+        map.insert("children".into(), children.into());
     }
+    map.into()
 }
 
 /*********************
- * Menu Construction *
+ * Pane Construction *
  *********************/
 
-#[derive(Debug, Clone)]
-pub struct MenuBuilder {
-    name: String,
-    description: String,
-    keymap: Option<Keymap>,
-    kind: MenuKind,
+/// A pane notation being built by a script, together with the size it should have when placed
+/// in a `pane_horz()` or `pane_vert()`.
+#[derive(Clone)]
+pub struct PaneBuilder {
+    notation: pane::PaneNotation<DocDisplayLabel, Style>,
+    size: pane::PaneSize,
+    /// The labels of all the docs this pane displays.
+    doc_labels: Vec<String>,
 }
 
-pub fn make_menu(menu_name: String, description: String) -> MenuBuilder {
-    MenuBuilder {
-        name: menu_name,
-        description,
-        keymap: None,
-        kind: MenuKind::Char,
+/// A pane showing the auxilliary doc with the given label. Its size defaults to `dynamic`.
+pub fn pane_doc(label: String) -> PaneBuilder {
+    PaneBuilder {
+        notation: pane::PaneNotation::Doc {
+            label: DocDisplayLabel::Auxilliary(label.clone()),
+        },
+        size: pane::PaneSize::Dynamic,
+        doc_labels: vec![label],
     }
 }
 
-pub fn set_menu_kind_to_candidate(menu: &mut MenuBuilder, default_to_custom_candidate: bool) {
-    menu.kind = MenuKind::Candidate {
-        default_to_custom_candidate,
-    };
+/// A pane showing the virtual doc with the given name. Its size defaults to `dynamic`.
+pub fn pane_virtual_doc(name: String) -> PaneBuilder {
+    PaneBuilder {
+        notation: pane::PaneNotation::Doc {
+            label: DocDisplayLabel::Virtual(name),
+        },
+        size: pane::PaneSize::Dynamic,
+        doc_labels: Vec::new(),
+    }
 }
 
-pub fn set_menu_kind_to_input_string(menu: &mut MenuBuilder) {
-    menu.kind = MenuKind::InputString;
+/// A pane filled with `ch`. Its size defaults to `proportional(1)`.
+pub fn pane_fill(ch: char) -> PaneBuilder {
+    PaneBuilder {
+        notation: pane::PaneNotation::Fill { ch },
+        size: pane::PaneSize::Proportional(1),
+        doc_labels: Vec::new(),
+    }
 }
 
-pub fn set_menu_kind_to_char(menu: &mut MenuBuilder) {
-    menu.kind = MenuKind::Char;
+/// Panes placed side by side, each given the space its own size says. Its size defaults to
+/// `proportional(1)`.
+pub fn pane_horz(panes: rhai::Array) -> Result<PaneBuilder, SynlessError> {
+    let (entries, doc_labels) = pane_entries("pane_horz", panes)?;
+    Ok(PaneBuilder {
+        notation: pane::PaneNotation::Horz(entries),
+        size: pane::PaneSize::Proportional(1),
+        doc_labels,
+    })
 }
 
-pub fn set_menu_keymap(menu: &mut MenuBuilder, keymap: Keymap) {
-    menu.keymap = Some(keymap);
+/// Panes placed one above the other, each given the space its own size says. Its size defaults
+/// to `proportional(1)`.
+pub fn pane_vert(panes: rhai::Array) -> Result<PaneBuilder, SynlessError> {
+    let (entries, doc_labels) = pane_entries("pane_vert", panes)?;
+    Ok(PaneBuilder {
+        notation: pane::PaneNotation::Vert(entries),
+        size: pane::PaneSize::Proportional(1),
+        doc_labels,
+    })
+}
+
+/// Style a pane. The style is a map with any of the keys `fg`, `bg`, and `underline_color`
+/// (Base16 color names like `"Base0A"`), and `bold`, `underlined`, `undercurled`, `italic`, `dim`,
+/// and `strikethrough` (bools).
+pub fn pane_style(pane: PaneBuilder, style_map: rhai::Map) -> Result<PaneBuilder, SynlessError> {
+    let style = style_from_map("pane_style", style_map, Priority::Low)?;
+    Ok(PaneBuilder {
+        notation: pane::PaneNotation::Style {
+            style,
+            notation: Box::new(pane.notation),
+        },
+        size: pane.size,
+        doc_labels: pane.doc_labels,
+    })
+}
+
+/// Parse a style map, as described in `pane_style()`. `fn_name` is used in error messages.
+fn style_from_map(
+    fn_name: &str,
+    style_map: rhai::Map,
+    priority: Priority,
+) -> Result<Style, SynlessError> {
+    let mut style = Style::default();
+    for (key, value) in style_map {
+        let type_error = || error!(Frontend, "{fn_name}: invalid value for '{key}'");
+        match key.as_str() {
+            "fg" | "bg" => {
+                let name = value.into_string().map_err(|_| type_error())?;
+                let color = Base16Color::from_name(&name)
+                    .ok_or_else(|| error!(Frontend, "{fn_name}: unknown color '{name}'"))?;
+                style = if key.as_str() == "fg" {
+                    style.with_fg(color, priority)
+                } else {
+                    style.with_bg(color, priority)
+                };
+            }
+            "bold" => {
+                let bold = value.as_bool().map_err(|_| type_error())?;
+                style = style.with_bold(bold, priority);
+            }
+            "underlined" => {
+                let underlined = value.as_bool().map_err(|_| type_error())?;
+                style = style.with_underlined(underlined, priority);
+            }
+            "undercurled" => {
+                let undercurled = value.as_bool().map_err(|_| type_error())?;
+                style = style.with_undercurled(undercurled, priority);
+            }
+            "underline_color" => {
+                let name = value.into_string().map_err(|_| type_error())?;
+                let color = Base16Color::from_name(&name)
+                    .ok_or_else(|| error!(Frontend, "{fn_name}: unknown color '{name}'"))?;
+                style = style.with_underline_color(color, priority);
+            }
+            "italic" => {
+                let italic = value.as_bool().map_err(|_| type_error())?;
+                style = style.with_italic(italic, priority);
+            }
+            "dim" => {
+                let dim = value.as_bool().map_err(|_| type_error())?;
+                style = style.with_dim(dim, priority);
+            }
+            "strikethrough" => {
+                let strikethrough = value.as_bool().map_err(|_| type_error())?;
+                style = style.with_strikethrough(strikethrough, priority);
+            }
+            _ => {
+                return Err(error!(
+                    Frontend,
+                    "{fn_name}: unknown style property '{key}'"
+                ))
+            }
+        }
+    }
+    Ok(style)
+}
+
+type PaneEntries = Vec<(pane::PaneSize, pane::PaneNotation<DocDisplayLabel, Style>)>;
+
+fn pane_entries(
+    fn_name: &str,
+    panes: rhai::Array,
+) -> Result<(PaneEntries, Vec<String>), SynlessError> {
+    let mut entries = Vec::new();
+    let mut doc_labels = Vec::new();
+    for pane in panes {
+        let pane = pane
+            .try_cast::<PaneBuilder>()
+            .ok_or_else(|| error!(Frontend, "{fn_name}: expected an array of panes"))?;
+        entries.push((pane.size, pane.notation));
+        doc_labels.extend(pane.doc_labels);
+    }
+    Ok((entries, doc_labels))
+}
+
+impl rhai::CustomType for PaneBuilder {
+    fn build(mut builder: rhai::TypeBuilder<Self>) {
+        fn size_arg(size: rhai::INT) -> Result<usize, Box<rhai::EvalAltResult>> {
+            usize::try_from(size).map_err(|_| error!(Frontend, "Invalid pane size: {size}").into())
+        }
+
+        builder
+            .with_name("PaneNotation")
+            .with_fn(
+                "fixed",
+                |pane: &mut PaneBuilder,
+                 size: rhai::INT|
+                 -> Result<PaneBuilder, Box<rhai::EvalAltResult>> {
+                    let mut pane = pane.clone();
+                    pane.size = pane::PaneSize::Fixed(size_arg(size)?);
+                    Ok(pane)
+                },
+            )
+            .with_fn(
+                "proportional",
+                |pane: &mut PaneBuilder,
+                 size: rhai::INT|
+                 -> Result<PaneBuilder, Box<rhai::EvalAltResult>> {
+                    let mut pane = pane.clone();
+                    pane.size = pane::PaneSize::Proportional(size_arg(size)?);
+                    Ok(pane)
+                },
+            )
+            .with_fn("dynamic", |pane: &mut PaneBuilder| -> PaneBuilder {
+                let mut pane = pane.clone();
+                pane.size = pane::PaneSize::Dynamic;
+                pane
+            });
+    }
 }
 
 /******************
  * Pane Notations *
  ******************/
 
-fn make_pane_notation(include_menu: bool) -> pane::PaneNotation<DocDisplayLabel, Style> {
+/// If `show_split`, the visible doc's pane is split in two, with the doc labeled
+/// `SCRATCH_SPLIT_LABEL` shown above the visible doc. If `show_lint_results`, the doc labeled
+/// `LINT_RESULTS_LABEL` is shown in a short pane below the visible doc. Each of the
+/// `dynamic_panes` is shown beside those, nearest first. If `show_diff`, the doc
+/// labeled `DIFF_SPLIT_LABEL` is shown to the left of the visible doc, at the same width. If `show_file_browser`, the visible doc
+/// (the file browser) is shown in a narrow pane to the left of the doc labeled
+/// `FILE_BROWSER_SPLIT_LABEL`.
+fn make_pane_notation(
+    status_bar: pane::PaneNotation<DocDisplayLabel, Style>,
+    show_split: bool,
+    show_undo_tree: bool,
+    show_lint_results: bool,
+    dynamic_panes: &[(String, PaneSide, usize)],
+    show_diff: bool,
+    show_file_browser: bool,
+) -> pane::PaneNotation<DocDisplayLabel, Style> {
     use crate::style::{Base16Color, Priority};
     use pane::{PaneNotation, PaneSize};
 
@@ -630,7 +4828,7 @@ fn make_pane_notation(include_menu: bool) -> pane::PaneNotation<DocDisplayLabel,
         .with_bold(true, Priority::Low);
 
     let divider = PaneNotation::Style {
-        style: bar_style.clone(),
+        style: bar_style,
         notation: Box::new(PaneNotation::Fill { ch: ' ' }),
     };
     let padding = PaneNotation::Fill { ch: ' ' };
@@ -647,20 +4845,137 @@ fn make_pane_notation(include_menu: bool) -> pane::PaneNotation<DocDisplayLabel,
     let main_doc = PaneNotation::Doc {
         label: DocDisplayLabel::Visible,
     };
-    let menu_doc = PaneNotation::Doc {
-        label: DocDisplayLabel::Auxilliary(CANDIDATE_SELECTION_DOC_LABEL.to_owned()),
+    let scrollbar_doc = PaneNotation::Doc {
+        label: DocDisplayLabel::Auxilliary(SCROLLBAR_LABEL.to_owned()),
     };
-    let menu_name = PaneNotation::Doc {
-        label: DocDisplayLabel::Auxilliary(MENU_NAME_LABEL.to_owned()),
+    let line_numbers_doc = PaneNotation::Doc {
+        label: DocDisplayLabel::Auxilliary(LINE_NUMBERS_LABEL.to_owned()),
+    };
+
+    let log_doc = PaneNotation::Doc {
+        label: DocDisplayLabel::Auxilliary(LAST_LOG_LABEL.to_owned()),
+    };
+    let notifications_doc = PaneNotation::Doc {
+        label: DocDisplayLabel::Auxilliary(NOTIFICATIONS_LABEL.to_owned()),
     };
-    let menu_bar = PaneNotation::Style {
-        style: bar_style.clone(),
+    let tab_bar_doc = PaneNotation::Doc {
+        label: DocDisplayLabel::Auxilliary(TAB_BAR_LABEL.to_owned()),
+    };
+    let tab_bar = PaneNotation::Style {
+        style: status_bar_style,
         notation: Box::new(PaneNotation::Horz(vec![
-            (PaneSize::Dynamic, menu_name),
-            (PaneSize::Proportional(1), padding.clone()),
+            (PaneSize::Dynamic, tab_bar_doc),
+            (PaneSize::Proportional(1), padding),
         ])),
     };
 
+    let main_pane = PaneNotation::Horz(vec![
+        (PaneSize::Dynamic, line_numbers_doc),
+        (PaneSize::Proportional(1), main_doc),
+        (PaneSize::Dynamic, scrollbar_doc),
+    ]);
+    let main_pane = if show_undo_tree {
+        let undo_tree_doc = PaneNotation::Doc {
+            label: DocDisplayLabel::Auxilliary(UNDO_TREE_LABEL.to_owned()),
+        };
+        PaneNotation::Horz(vec![
+            (PaneSize::Proportional(1), main_pane),
+            (PaneSize::Fixed(1), divider.clone()),
+            (PaneSize::Fixed(UNDO_TREE_PANE_WIDTH), undo_tree_doc),
+        ])
+    } else {
+        main_pane
+    };
+    let mut main_pane = if show_lint_results {
+        let lint_results_doc = PaneNotation::Doc {
+            label: DocDisplayLabel::Auxilliary(LINT_RESULTS_LABEL.to_owned()),
+        };
+        PaneNotation::Vert(vec![
+            (PaneSize::Proportional(1), main_pane),
+            (PaneSize::Fixed(1), divider.clone()),
+            (PaneSize::Fixed(LINT_RESULTS_PANE_HEIGHT), lint_results_doc),
+        ])
+    } else {
+        main_pane
+    };
+    for (name, side, size) in dynamic_panes {
+        let dynamic_doc = PaneNotation::Doc {
+            label: DocDisplayLabel::Virtual(name.clone()),
+        };
+        let dynamic_pane = (PaneSize::Fixed(*size), dynamic_doc);
+        let main = (PaneSize::Proportional(1), main_pane);
+        let divider = (PaneSize::Fixed(1), divider.clone());
+        main_pane = match side {
+            PaneSide::Left => PaneNotation::Horz(vec![dynamic_pane, divider, main]),
+            PaneSide::Right => PaneNotation::Horz(vec![main, divider, dynamic_pane]),
+            PaneSide::Top => PaneNotation::Vert(vec![dynamic_pane, divider, main]),
+            PaneSide::Bottom => PaneNotation::Vert(vec![main, divider, dynamic_pane]),
+        };
+    }
+    let main_pane = if show_diff {
+        let diff_doc = PaneNotation::Doc {
+            label: DocDisplayLabel::Auxilliary(DIFF_SPLIT_LABEL.to_owned()),
+        };
+        PaneNotation::Horz(vec![
+            (PaneSize::Proportional(1), diff_doc),
+            (PaneSize::Fixed(1), divider.clone()),
+            (PaneSize::Proportional(1), main_pane),
+        ])
+    } else {
+        main_pane
+    };
+    let main_pane = if show_split {
+        let split_doc = PaneNotation::Doc {
+            label: DocDisplayLabel::Auxilliary(SCRATCH_SPLIT_LABEL.to_owned()),
+        };
+        PaneNotation::Vert(vec![
+            (PaneSize::Proportional(1), split_doc),
+            (PaneSize::Fixed(1), divider.clone()),
+            (PaneSize::Proportional(1), main_pane),
+        ])
+    } else {
+        main_pane
+    };
+    let main_pane = if show_file_browser {
+        let split_doc = PaneNotation::Doc {
+            label: DocDisplayLabel::Auxilliary(FILE_BROWSER_SPLIT_LABEL.to_owned()),
+        };
+        PaneNotation::Horz(vec![
+            (PaneSize::Fixed(FILE_BROWSER_PANE_WIDTH), main_pane),
+            (PaneSize::Fixed(1), divider.clone()),
+            (PaneSize::Proportional(1), split_doc),
+        ])
+    } else {
+        main_pane
+    };
+
+    PaneNotation::Vert(vec![
+        (PaneSize::Fixed(1), tab_bar),
+        (
+            PaneSize::Proportional(1),
+            PaneNotation::Horz(vec![
+                (PaneSize::Proportional(1), main_pane),
+                (PaneSize::Fixed(1), divider),
+                (PaneSize::Fixed(KEYHINTS_PANE_WIDTH), keyhints),
+            ]),
+        ),
+        (PaneSize::Dynamic, notifications_doc),
+        (PaneSize::Fixed(1), status_bar),
+        (PaneSize::Fixed(1), log_doc),
+    ])
+}
+
+/// The status bar shown until one is set with `Runtime::set_status_bar()`.
+fn make_default_status_bar() -> pane::PaneNotation<DocDisplayLabel, Style> {
+    use crate::style::{Base16Color, Priority};
+    use pane::{PaneNotation, PaneSize};
+
+    let status_bar_style = Style::default()
+        .with_bg(Base16Color::Base06, Priority::Low)
+        .with_fg(Base16Color::Base00, Priority::Low)
+        .with_bold(true, Priority::Low);
+    let padding = PaneNotation::Fill { ch: ' ' };
+
     let mode_doc = PaneNotation::Doc {
         label: DocDisplayLabel::Auxilliary(MODE_LABEL.to_owned()),
     };
@@ -670,44 +4985,121 @@ fn make_pane_notation(include_menu: bool) -> pane::PaneNotation<DocDisplayLabel,
     let sibling_index_doc = PaneNotation::Doc {
         label: DocDisplayLabel::Auxilliary(SIBLING_INDEX_LABEL.to_owned()),
     };
-    let status_bar = PaneNotation::Style {
+    let hole_count_doc = PaneNotation::Doc {
+        label: DocDisplayLabel::Auxilliary(HOLE_COUNT_LABEL.to_owned()),
+    };
+    PaneNotation::Style {
         style: status_bar_style,
         notation: Box::new(PaneNotation::Horz(vec![
             (PaneSize::Dynamic, mode_doc),
             (PaneSize::Fixed(1), padding.clone()),
             (PaneSize::Dynamic, filename_doc),
             (PaneSize::Proportional(1), padding.clone()),
+            (PaneSize::Dynamic, hole_count_doc),
+            (PaneSize::Fixed(1), padding.clone()),
             (PaneSize::Dynamic, sibling_index_doc),
             (PaneSize::Fixed(1), padding),
         ])),
+    }
+}
+
+/// The notation for the menu, which floats over the main doc when open.
+fn make_menu_pane_notation() -> pane::PaneNotation<DocDisplayLabel, Style> {
+    use pane::{PaneNotation, PaneSize};
+
+    let menu_doc = PaneNotation::Doc {
+        label: DocDisplayLabel::Auxilliary(CANDIDATE_SELECTION_DOC_LABEL.to_owned()),
     };
-    let log_doc = PaneNotation::Doc {
-        label: DocDisplayLabel::Auxilliary(LAST_LOG_LABEL.to_owned()),
+    let candidate_keys_doc = PaneNotation::Doc {
+        label: DocDisplayLabel::Auxilliary(CANDIDATE_KEYS_LABEL.to_owned()),
     };
+    let candidate_annotations_doc = PaneNotation::Doc {
+        label: DocDisplayLabel::Auxilliary(CANDIDATE_ANNOTATIONS_LABEL.to_owned()),
+    };
+    // Each of these docs has one line per candidate, so putting them side by side lines the
+    // candidates up with their keys and annotations.
+    let menu_columns = PaneNotation::Horz(vec![
+        (PaneSize::Dynamic, candidate_keys_doc),
+        (PaneSize::Proportional(1), menu_doc),
+        (PaneSize::Dynamic, candidate_annotations_doc),
+    ]);
 
-    let mut main_doc_and_menu = vec![(PaneSize::Proportional(1), main_doc)];
-    if include_menu {
-        main_doc_and_menu.push((PaneSize::Fixed(1), menu_bar));
-        main_doc_and_menu.push((PaneSize::Dynamic, menu_doc));
-    }
+    PaneNotation::Vert(vec![
+        (PaneSize::Fixed(1), make_overlay_title_bar()),
+        (PaneSize::Proportional(1), menu_columns),
+    ])
+}
 
+fn make_tree_prompt_pane_notation() -> pane::PaneNotation<DocDisplayLabel, Style> {
+    use pane::{PaneNotation, PaneSize};
+
+    let prompt_doc = PaneNotation::Doc {
+        label: DocDisplayLabel::Visible,
+    };
     PaneNotation::Vert(vec![
-        (
-            PaneSize::Proportional(1),
-            PaneNotation::Horz(vec![
-                (
-                    PaneSize::Proportional(1),
-                    PaneNotation::Vert(main_doc_and_menu),
-                ),
-                (PaneSize::Fixed(1), divider),
-                (PaneSize::Fixed(KEYHINTS_PANE_WIDTH), keyhints),
-            ]),
-        ),
-        (PaneSize::Fixed(1), status_bar),
-        (PaneSize::Fixed(1), log_doc),
+        (PaneSize::Fixed(1), make_overlay_title_bar()),
+        (PaneSize::Proportional(1), prompt_doc),
     ])
 }
 
+/// A bar showing the description of the open menu or tree prompt.
+fn make_overlay_title_bar() -> pane::PaneNotation<DocDisplayLabel, Style> {
+    use crate::style::{Base16Color, Priority};
+    use pane::{PaneNotation, PaneSize};
+
+    let bar_style = Style::default()
+        .with_bg(Base16Color::Base04, Priority::Low)
+        .with_fg(Base16Color::Base00, Priority::Low)
+        .with_bold(true, Priority::Low);
+    let menu_name = PaneNotation::Doc {
+        label: DocDisplayLabel::Auxilliary(MENU_NAME_LABEL.to_owned()),
+    };
+    PaneNotation::Style {
+        style: bar_style,
+        notation: Box::new(PaneNotation::Horz(vec![
+            (PaneSize::Fixed(1), PaneNotation::Fill { ch: ' ' }),
+            (PaneSize::Dynamic, menu_name),
+            (PaneSize::Proportional(1), PaneNotation::Fill { ch: ' ' }),
+        ])),
+    }
+}
+
+/// The region of the window that holds the visible doc and the panes around it, given the size of
+/// the whole window and the number of notifications shown: below the tab bar, left of the
+/// keyhints, and above the notifications, status bar, and log. Must agree with the layout in
+/// `make_pane_notation()`.
+fn main_region_rect(window_size: Size, num_notifications: usize) -> (Pos, Size) {
+    // The tab bar above, and the status bar and log below.
+    const TOP_ROWS: Height = 1;
+    const BOTTOM_ROWS: Height = 2;
+
+    let width = window_size
+        .width
+        .saturating_sub(KEYHINTS_PANE_WIDTH as Width + 1);
+    let height = window_size
+        .height
+        .saturating_sub(TOP_ROWS + BOTTOM_ROWS)
+        .saturating_sub(num_notifications as Height);
+    let offset = Pos {
+        row: TOP_ROWS as Row,
+        col: 0,
+    };
+    (offset, Size { width, height })
+}
+
+/// Where to draw the floating menu, given the size of the whole window and the number of
+/// notifications shown: across the bottom of the main region (see `main_region_rect()`).
+fn menu_overlay_rect(window_size: Size, num_notifications: usize) -> (Pos, Size) {
+    let (region_offset, region_size) = main_region_rect(window_size, num_notifications);
+    let width = region_size.width.saturating_sub(2 * MENU_OVERLAY_MARGIN);
+    let height = region_size.height.min(MENU_OVERLAY_MAX_HEIGHT);
+    let offset = Pos {
+        row: region_offset.row + (region_size.height - height) as Row,
+        col: region_offset.col + MENU_OVERLAY_MARGIN.min(region_size.width) as Col,
+    };
+    (offset, Size { width, height })
+}
+
 /***********
  * Keymaps *
  ***********/
@@ -716,37 +5108,298 @@ fn escape() -> Result<(), SynlessError> {
     Err(error!(Escape, "Escape"))
 }
 
+fn history_item(label: String, position: usize) -> rhai::Dynamic {
+    let mut map = rhai::Map::new();
+    map.insert("label".into(), label.into());
+    map.insert("position".into(), (position as rhai::INT).into());
+    map.into()
+}
+
+/// Draw an undo history tree, one state per row, with the time it was made. Each branch is
+/// indented beneath the state it branches off of, except the newest, which continues in the same
+/// column. Returns each row's text, and whether it's the `current` state.
+/// Build the doc for the file browser: the contents of its root directory, and of each expanded
+/// directory within it. Hidden files and directories are left out. The path of each `Dir` and
+/// `File` node is recorded in `paths`.
+fn build_file_tree(
+    s: &mut Storage,
+    language: Language,
+    browser: &FileBrowser,
+    paths: &mut HashMap<NodeId, std::path::PathBuf>,
+) -> Result<Node, SynlessError> {
+    let construct = |name: &str| {
+        language
+            .construct(s, name)
+            .ok_or_else(|| error!(Language, "file_tree lang is missing '{name}'"))
+    };
+    let constructs = [
+        construct("Dir")?,
+        construct("DirName")?,
+        construct("DirEntries")?,
+        construct("File")?,
+    ];
+    let root = Node::new(s, language.root_construct(s));
+    if let Err(err) = add_file_tree_entries(s, constructs, browser, &browser.root_dir, root, paths)
+    {
+        root.delete_root(s).bug();
+        return Err(err);
+    }
+    Ok(root)
+}
+
+fn add_file_tree_entries(
+    s: &mut Storage,
+    constructs: [Construct; 4],
+    browser: &FileBrowser,
+    dir: &std::path::Path,
+    parent: Node,
+    paths: &mut HashMap<NodeId, std::path::PathBuf>,
+) -> Result<(), SynlessError> {
+    let [c_dir, c_dir_name, c_dir_entries, c_file] = constructs;
+    let file_name = |path: &std::path::Path| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.to_owned())
+    };
+    let (dirs, files) = fs_util::list_dir(dir)?;
+    for child_dir in dirs {
+        let name = match file_name(&child_dir) {
+            Some(name) if !name.starts_with('.') => name,
+            _ => continue,
+        };
+        let dir_name = Node::with_text(s, c_dir_name, name).bug();
+        let dir_entries = Node::new(s, c_dir_entries);
+        if browser.expanded.contains(&child_dir) {
+            // A directory that can't be read is just shown as empty.
+            let _ = add_file_tree_entries(s, constructs, browser, &child_dir, dir_entries, paths);
+        }
+        let node = Node::with_children(s, c_dir, [dir_name, dir_entries]).bug();
+        paths.insert(node.id(s), child_dir);
+        parent.insert_last_child(s, node).bug();
+    }
+    for file in files {
+        let name = match file_name(&file) {
+            Some(name) if !name.starts_with('.') => name,
+            _ => continue,
+        };
+        let node = Node::with_text(s, c_file, name).bug();
+        paths.insert(node.id(s), file);
+        parent.insert_last_child(s, node).bug();
+    }
+    Ok(())
+}
+
+fn undo_tree_rows(nodes: &[HistoryTreeNode], current: usize, now: Instant) -> Vec<(String, bool)> {
+    let mut children = vec![Vec::new(); nodes.len()];
+    for node in nodes {
+        if let Some(parent) = node.parent {
+            children[parent].push(node.id);
+        }
+    }
+
+    let mut rows = Vec::new();
+    // (state, prefix for its own row, prefix for the rows beneath it)
+    let mut stack = vec![(0, String::new(), String::new())];
+    while let Some((id, first_prefix, rest_prefix)) = stack.pop() {
+        let is_current = id == current;
+        let marker = if is_current { '@' } else { 'o' };
+        let time = match nodes[id].entry {
+            Some(entry) => format_elapsed(now.duration_since(entry.time)),
+            None => "original".to_owned(),
+        };
+        rows.push((format!("{first_prefix}{marker} {id} {time}"), is_current));
+
+        if let Some((newest, branches)) = children[id].split_last() {
+            // Pushed in reverse, so that the older branches are drawn first, oldest first.
+            stack.push((*newest, rest_prefix.clone(), rest_prefix.clone()));
+            for branch in branches.iter().rev() {
+                stack.push((
+                    *branch,
+                    format!("{rest_prefix}├ "),
+                    format!("{rest_prefix}│ "),
+                ));
+            }
+        }
+    }
+    rows
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{secs}s ago")
+    } else if secs < 60 * 60 {
+        format!("{}m ago", secs / 60)
+    } else {
+        format!("{}h ago", secs / (60 * 60))
+    }
+}
+
 /**************
  * Filesystem *
  **************/
 
-fn list_files_and_dirs(dir: &str) -> Result<rhai::Map, SynlessError> {
-    use std::fs::read_dir;
+/// The modification time and length of a file, used to tell whether it has changed.
+fn read_file(path: &std::path::Path) -> Result<String, SynlessError> {
+    std::fs::read_to_string(path).map_err(|err| {
+        error!(
+            FileSystem,
+            "Failed to read file at '{}' ({err})",
+            path.display()
+        )
+    })
+}
+
+/// The complete lines that were appended to the file at `path` after its first `start` bytes,
+/// which must have ended with a newline. Returns `None` if they didn't (say because the file was
+/// rewritten rather than appended to), or if the file can't be read.
+fn read_appended_lines(path: &std::path::Path, start: u64) -> Option<String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = std::fs::File::open(path).ok()?;
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(start.saturating_sub(1))).ok()?;
+    file.read_to_end(&mut bytes).ok()?;
+    if start > 0 {
+        if bytes.first() != Some(&b'\n') {
+            return None;
+        }
+        bytes.remove(0);
+    }
+    let end = bytes
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |i| i + 1);
+    bytes.truncate(end);
+    String::from_utf8(bytes).ok()
+}
 
-    let entries = read_dir(dir).map_err(|err| {
+fn file_stamp(path: &std::path::Path) -> Result<(SystemTime, u64), SynlessError> {
+    let metadata = std::fs::metadata(path).map_err(|err| {
+        error!(
+            FileSystem,
+            "Failed to read metadata of '{}' ({err})",
+            path.display()
+        )
+    })?;
+    let modified = metadata.modified().map_err(|err| {
         error!(
             FileSystem,
-            "Failed to list files in directory '{dir}' ({err})"
+            "Failed to read modification time of '{}' ({err})",
+            path.display()
         )
     })?;
+    Ok((modified, metadata.len()))
+}
 
-    let mut files = Vec::new();
-    let mut dirs = Vec::new();
-    for entry in entries {
-        if let Ok(path) = entry.and_then(|e| e.path().canonicalize()) {
-            if let Some(path_string) = path.to_str().map(|s| s.to_owned()) {
-                if path.is_dir() {
-                    dirs.push(path_string);
-                } else if path.is_file() {
-                    files.push(path_string);
-                }
+/// Load the theme called `name` from the theme directory, trying each theme file extension in
+/// turn.
+fn load_theme(name: &str) -> Result<ColorTheme, SynlessError> {
+    let dir = std::path::Path::new(THEME_DIR);
+    let path = THEME_FILE_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{name}.{ext}")))
+        .find(|path| path.is_file())
+        .ok_or_else(|| error!(FileSystem, "No theme named '{name}' in '{THEME_DIR}'"))?;
+    let source = std::fs::read_to_string(&path).map_err(|err| {
+        error!(
+            FileSystem,
+            "Failed to read file at '{}' ({err})",
+            path.display()
+        )
+    })?;
+    let theme = if path.extension().and_then(|ext| ext.to_str()) == Some("ron") {
+        ColorTheme::from_ron(&source)
+    } else {
+        ColorTheme::from_base16_yaml(&source)
+    };
+    theme.map_err(|err| error!(Frontend, "Invalid theme '{}': {err}", path.display()))
+}
+
+/// Synless's cache directory: `$XDG_CACHE_HOME/synless`, or `~/.cache/synless`. `None` if
+/// neither environment variable is set.
+fn cache_dir() -> Option<std::path::PathBuf> {
+    use std::path::PathBuf;
+
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_home.join("synless"))
+}
+
+/// Where to remember the undo histories of saved files.
+fn undo_cache_dir() -> Option<std::path::PathBuf> {
+    Some(cache_dir()?.join("undo"))
+}
+
+/// Synless's data directory: `$XDG_DATA_HOME/synless`, or `~/.local/share/synless`. `None` if
+/// neither environment variable is set.
+fn data_dir() -> Option<std::path::PathBuf> {
+    use std::path::PathBuf;
+
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+        })?;
+    Some(data_home.join("synless"))
+}
+
+/// Where to save scratch docs.
+fn scratch_dir() -> Option<std::path::PathBuf> {
+    Some(data_dir()?.join("scratch"))
+}
+
+/// Where to remember recently opened files, one path per line.
+fn recent_files_path() -> Option<std::path::PathBuf> {
+    Some(data_dir()?.join("recent_files"))
+}
+
+/// Read the recent files list, most recent first. Empty if it can't be read.
+fn load_recent_files(path: Option<&std::path::Path>) -> VecDeque<String> {
+    let contents = path
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .take(MAX_RECENT_FILES)
+        .map(|line| line.to_owned())
+        .collect()
+}
+
+/// Turn a command's label into something that can be part of a rhai identifier.
+fn rhai_ident(label: &str) -> String {
+    let ident = label
+        .chars()
+        .map(|ch| {
+            if ch.is_ascii_alphanumeric() {
+                ch.to_ascii_lowercase()
+            } else {
+                '_'
             }
-        }
+        })
+        .collect::<String>();
+    let ident = ident.trim_matches('_');
+    if ident.is_empty() {
+        "command".to_owned()
+    } else {
+        ident.to_owned()
     }
+}
+
+fn list_files_and_dirs(dir: &str) -> Result<rhai::Map, SynlessError> {
+    let (dirs, files) = fs_util::list_dir(std::path::Path::new(dir))?;
+    let to_strings = |paths: Vec<std::path::PathBuf>| {
+        paths
+            .into_iter()
+            .filter_map(|path| path.to_str().map(|s| s.to_owned()))
+            .collect::<Vec<_>>()
+    };
 
     let mut map = rhai::Map::new();
-    map.insert("files".into(), files.into());
-    map.insert("dirs".into(), dirs.into());
+    map.insert("files".into(), to_strings(files).into());
+    map.insert("dirs".into(), to_strings(dirs).into());
     Ok(map)
 }
 
@@ -799,8 +5452,12 @@ macro_rules! register {
     ($module:expr, $runtime:ident, $command:ident :: $variant:ident as $name:ident) => {
         let rt = $runtime.clone();
         let closure = move || {
-            rt.borrow_mut().engine.execute($command::$variant)
-                .map_err(|err| Box::<rhai::EvalAltResult>::from(err))
+            let mut rt = rt.borrow_mut();
+            let result = rt.engine.execute($command::$variant);
+            if result.is_ok() {
+                rt.record_command(format!("{}()", stringify!($name)));
+            }
+            result.map_err(|err| Box::<rhai::EvalAltResult>::from(err))
         };
         rhai::FuncRegistration::new(stringify!($name))
             .in_internal_namespace()
@@ -809,8 +5466,17 @@ macro_rules! register {
     ($module:expr, $runtime:ident, $command:ident :: $variant:ident ($( $param:ident : $type:ty ),*) as $name:ident) => {
         let rt = $runtime.clone();
         let closure = move | $( $param : $type ),* | {
-            rt.borrow_mut().engine.execute($command::$variant( $( $param ),* ))
-                .map_err(|err| Box::<rhai::EvalAltResult>::from(err))
+            let call = format!(
+                "{}({})",
+                stringify!($name),
+                [$( format!("{:?}", $param) ),*].join(", ")
+            );
+            let mut rt = rt.borrow_mut();
+            let result = rt.engine.execute($command::$variant( $( $param ),* ));
+            if result.is_ok() {
+                rt.record_command(call);
+            }
+            result.map_err(|err| Box::<rhai::EvalAltResult>::from(err))
         };
         rhai::FuncRegistration::new(stringify!($name))
             .in_internal_namespace()
@@ -829,7 +5495,7 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
     }
 
     pub fn register_external_methods(rt: Rc<RefCell<Runtime<F>>>, module: &mut rhai::Module) {
-        use fs_util::{join_path, path_file_name};
+        use fs_util::{file_size, join_path, path_file_name};
 
         // Keymaps
         register!(module, rt.register_layer(layer: Layer));
@@ -840,8 +5506,27 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         register!(module, set_menu_kind_to_candidate);
         register!(module, set_menu_kind_to_input_string);
         register!(module, set_menu_kind_to_char);
+
+        // Pane construction
+        register!(module, pane_doc);
+        register!(module, pane_virtual_doc);
+        register!(module, pane_fill);
+        register!(module, pane_horz(panes: rhai::Array)?);
+        register!(module, pane_vert(panes: rhai::Array)?);
+        register!(module, pane_style(pane: PaneBuilder, style_map: rhai::Map)?);
+        register!(module, rt.set_status_bar(status_bar: PaneBuilder)?);
+        register!(module, rt.add_pane_provider(name: &str, language_name: &str, provider: rhai::FnPtr)?);
+        register!(module, rt.show_pane(name: &str, side: &str, size: rhai::INT)?);
+        register!(module, rt.hide_pane(name: &str)?);
+        register!(module, rt.is_pane_shown(name: &str)?);
+        register!(module, rt.refresh_pane(name: &str)?);
+        register!(module, rt.take_stale_panes());
         register!(module, rt.open_menu(menu: MenuBuilder)?);
         register!(module, rt.close_menu());
+        register!(module, rt.open_tree_prompt(description: String, language_name: &str)?);
+        register!(module, rt.has_tree_prompt());
+        register!(module, rt.confirm_tree_prompt()?);
+        register!(module, rt.cancel_tree_prompt());
         register!(module, escape()?);
         register!(module, rt.menu_selection_up()?);
         register!(module, rt.menu_selection_down()?);
@@ -850,19 +5535,85 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         // Filesystem
         register!(module, list_files_and_dirs(dir: &str)?);
         register!(module, path_file_name(path: &str)?);
+        register!(module, file_size(path: &str)?);
         register!(module, join_path(path_1: &str, path_2: &str)?);
 
         // Doc management
         register!(module, rt.current_dir()?);
         register!(module, rt.new_doc(path: &str)?);
         register!(module, rt.open_doc(path: &str)?);
+        register!(module, rt.watch_doc(path: &str)?);
+        register!(module, rt.open_or_switch_to_doc(path: &str)?);
+        register!(module, rt.recent_files());
+        register!(module, rt.reload_changed_doc()?);
+        register!(module, rt.keep_changed_doc()?);
+        register!(module, rt.merge_changed_doc()?);
+        register!(module, rt.extract_to_doc(path: &str)?);
+        register!(module, rt.set_virtual_doc(name: &str, language_name: &str, tree: rhai::Dynamic)?);
+        register!(module, rt.show_virtual_doc(name: &str)?);
+        register!(module, rt.close_virtual_doc(name: &str)?);
+        register!(module, rt.toggle_scratch()?);
+        register!(module, rt.toggle_file_browser()?);
+        register!(module, rt.close_file_browser());
+        register!(module, rt.file_browser_select()?);
+        register!(module, rt.file_browser_up()?);
+        register!(module, rt.set_scratch_language(language_name: &str)?);
+        register!(module, rt.set_scratch_persistence(persist: bool));
+        register!(module, rt.open_command_history()?);
+        register!(module, rt.close_command_history());
+        register!(module, rt.rerun_command()?);
+        register!(module, rt.yank_command()?);
         register!(module, rt.doc_switching_candidates()?);
         register!(module, rt.switch_to_doc(path: &str)?);
+        register!(module, rt.next_doc()?);
+        register!(module, rt.prev_doc()?);
         register!(module, rt.has_visible_doc());
         register!(module, rt.has_unsaved_changes());
         register!(module, rt.force_close_visible_doc()?);
-        register!(module, rt.save_doc()?);
-        register!(module, rt.save_doc_as(path: String)?);
+        register!(module, rt.save_doc()? as save_doc_without_hooks);
+        register!(module, rt.save_doc_as(path: String)? as save_doc_as_without_hooks);
+        register!(module, rt.export_ansi(path: &str)?);
+        register!(module, rt.export_svg(path: &str, whole_doc: bool)?);
+        register!(module, rt.on_before_save(hook: rhai::FnPtr));
+        register!(module, rt.on_after_save(hook: rhai::FnPtr));
+        register!(module, rt.before_save_hooks());
+        register!(module, rt.after_save_hooks());
+        register!(module, rt.visible_doc_info()?);
+        register!(module, rt.on_event(event_name: &str, hook: rhai::FnPtr)?);
+        register!(module, rt.event_hooks(event_name: &str));
+        register!(module, rt.take_events());
+        register!(module, rt.set_timeout(ms: rhai::INT, callback: rhai::FnPtr)?);
+        register!(module, rt.set_interval(ms: rhai::INT, callback: rhai::FnPtr)?);
+        register!(module, rt.clear_timer(id: rhai::INT));
+        register!(module, rt.plugin_commands());
+        register!(module, rt.spawn_command(argv: rhai::Array, input: String, callback: rhai::FnPtr)?);
+        register!(module, rt.doc_source()?);
+        register!(module, rt.node_source()?);
+        register!(module, rt.replace_with_source(node: Node, source: String)?);
+        register!(module, rt.toggle_width_preview());
+        register!(module, rt.toggle_hidden_nodes());
+        register!(module, rt.toggle_git_changes());
+        register!(module, rt.open_diff(old_path: &str, new_path: &str)?);
+        register!(module, rt.close_diff());
+        register!(module, rt.open_merge(base_path: &str, ours_path: &str, theirs_path: &str, output_path: &str)?);
+        register!(module, rt.goto_next_conflict()?);
+        register!(module, rt.resolve_conflict(side: &str)?);
+        register!(module, rt.num_unresolved_conflicts()?);
+        register!(module, rt.toggle_scrollbar());
+        register!(module, rt.set_line_numbers(mode: &str)?);
+        register!(module, rt.set_shading(mode: &str, levels: rhai::INT)?);
+        register!(module, rt.set_layout_cache_size(size: rhai::INT)?);
+        register!(module, rt.set_lazy_load_size(size: rhai::INT)?);
+        register!(module, rt.set_language_settings(language_name: &str, settings: rhai::Map)?);
+        register!(module, rt.set_theme(name: &str)?);
+        register!(module, rt.set_construct_style(language_name: &str, construct_name: &str, style: rhai::Map)?);
+        register!(module, rt.set_construct_summary(language_name: &str, construct_name: &str, template: String)?);
+        register!(module, rt.clear_construct_style(language_name: &str, construct_name: &str)?);
+        register!(module, rt.theme_names());
+        register!(module, rt.reload_config()?);
+        register!(module, rt.config());
+        register!(module, rt.hidden_nodes()?);
+        register!(module, rt.goto_hidden_node(index: rhai::INT)?);
 
         // Languages
         register!(module, rt.load_language(path: &str)?);
@@ -870,11 +5621,18 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         register!(module, rt.language_constructs(language: Language));
         register!(module, rt.construct_name(construct: Construct));
         register!(module, rt.construct_key(construct: Construct));
+        register!(module, rt.insertable_constructs()?);
+        register!(module, rt.construct_preview(construct: Construct)?);
+        register!(module, rt.wrapping_constructs()?);
 
         // Editing: Tree Nav
-        register!(module, rt, TreeNavCommand::Prev as tree_nav_prev);
+        register!(module, rt.tree_nav_prev()?);
         register!(module, rt, TreeNavCommand::First as tree_nav_first);
-        register!(module, rt, TreeNavCommand::Next as tree_nav_next);
+        register!(module, rt.tree_nav_next()?);
+        register!(module, rt.tree_nav_first_visible_child()?);
+        register!(module, rt.tree_nav_last_visible_child()?);
+        register!(module, rt.path_at_cursor()?);
+        register!(module, rt.goto_path(path: String)?);
         register!(module, rt, TreeNavCommand::Last as tree_nav_last);
         register!(
             module,
@@ -890,6 +5648,10 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         register!(module, rt, TreeNavCommand::NextLeaf as tree_nav_next_leaf);
         register!(module, rt, TreeNavCommand::PrevText as tree_nav_prev_text);
         register!(module, rt, TreeNavCommand::NextText as tree_nav_next_text);
+        register!(module, rt, TreeNavCommand::PrevHole as tree_nav_prev_hole);
+        register!(module, rt, TreeNavCommand::NextHole as tree_nav_next_hole);
+        register!(module, rt, TreeNavCommand::CellAbove as tree_nav_cell_above);
+        register!(module, rt, TreeNavCommand::CellBelow as tree_nav_cell_below);
         register!(module, rt, TreeNavCommand::LastChild as tree_nav_last_child);
         register!(module, rt, TreeNavCommand::Parent as tree_nav_parent);
         register!(module, rt, TreeNavCommand::EnterText as tree_nav_enter_text);
@@ -897,18 +5659,47 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         // Editing: Tree Ed
         register!(module, rt, TreeEdCommand::Backspace as tree_ed_backspace);
         register!(module, rt, TreeEdCommand::Delete as tree_ed_delete);
+        register!(module, rt, TreeEdCommand::Unwrap as tree_ed_unwrap);
+        register!(module, rt, TreeEdCommand::FillColumn as tree_ed_fill_column);
+        register!(module, rt, TreeEdCommand::SwapPrev as tree_ed_swap_prev);
+        register!(module, rt, TreeEdCommand::SwapNext as tree_ed_swap_next);
+        register!(module, rt, TreeEdCommand::SplitList as tree_ed_split_list);
+        register!(module, rt, TreeEdCommand::JoinLists as tree_ed_join_lists);
         register!(module, rt.insert_node(construct: Construct)?);
+        register!(module, rt.wrap_node(construct: Construct)?);
+        register!(module, rt.sort_children(by: &str)?);
+        register!(module, rt.surround_names()?);
+        register!(module, rt.surround(name: &str)?);
+        register!(module, rt.delete_surround()?);
+        register!(module, rt.change_surround(name: &str)?);
+        register!(module, rt.add_template(language_name: &str, name: String, tree: rhai::Dynamic)?);
+        register!(module, rt.template_names()?);
+        register!(module, rt.insert_template(name: &str)?);
 
         // Editing: Text Nav
         register!(module, rt, TextNavCommand::Left as text_nav_left);
         register!(module, rt, TextNavCommand::Right as text_nav_right);
         register!(module, rt, TextNavCommand::Beginning as text_nav_beginning);
         register!(module, rt, TextNavCommand::End as text_nav_end);
+        register!(module, rt, TextNavCommand::WordLeft as text_nav_word_left);
+        register!(module, rt, TextNavCommand::WordRight as text_nav_word_right);
         register!(module, rt, TextNavCommand::ExitText as text_nav_exit);
 
         // Editing: Text Ed
         register!(module, rt, TextEdCommand::Backspace as text_ed_backspace);
         register!(module, rt, TextEdCommand::Delete as text_ed_delete);
+        register!(
+            module,
+            rt,
+            TextEdCommand::BackspaceChar as text_ed_backspace_char
+        );
+        register!(module, rt, TextEdCommand::DeleteChar as text_ed_delete_char);
+        register!(
+            module,
+            rt,
+            TextEdCommand::BackspaceWord as text_ed_backspace_word
+        );
+        register!(module, rt, TextEdCommand::DeleteWord as text_ed_delete_word);
         register!(module, rt, TextEdCommand::Insert(ch: char) as text_ed_insert);
 
         // Editing: Bookmark
@@ -918,6 +5709,8 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         // Editing: Search
         register!(module, rt.search_for_construct(construct: Construct)?);
         register!(module, rt.search_for_node_at_cursor()?);
+        register!(module, rt.search_for_pattern_at_cursor()?);
+        register!(module, rt.replace_search_match()?);
         register!(
             module,
             rt,
@@ -925,21 +5718,96 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         );
         register!(module, rt.search_for_substring(substring: String));
         register!(module, rt.search_for_regex(regex: String)?);
+        register!(module, rt.search_for_query(query: String)?);
+        register!(module, rt.begin_incremental_search(kind: &str)?);
+        register!(module, rt.commit_incremental_search(input: String)?);
+        register!(module, rt.cancel_incremental_search()?);
+        register!(module, rt.replace_all_search_matches()?);
         register!(module, rt, SearchCommand::Prev as search_prev);
         register!(module, rt, SearchCommand::Next as search_next);
 
+        // Selection
+        register!(module, rt, SelectionCommand::Begin as begin_selection);
+        register!(module, rt, SelectionCommand::Clear as clear_selection);
+
+        // Multiple cursors
+        register!(module, rt, CursorsCommand::Add as add_cursor);
+        register!(
+            module,
+            rt,
+            CursorsCommand::AddAtMatches as add_cursors_at_matches
+        );
+        register!(module, rt, CursorsCommand::Clear as clear_cursors);
+
+        // Editing: Trees
+        register!(module, rt.root_node()?);
+        register!(module, rt.cursor_node()?);
+        register!(module, rt.node_construct(node: Node)?);
+        register!(module, rt.node_arity(node: Node)?);
+        register!(module, rt.node_is_hole(node: Node)?);
+        register!(module, rt.node_text(node: Node)?);
+        register!(module, rt.node_children(node: Node)?);
+        register!(module, rt.node_parent(node: Node)?);
+        register!(module, rt.new_node(construct: Construct));
+        register!(module, rt.new_hole(language: Language));
+        register!(module, rt.copy_node(node: Node)?);
+        register!(module, rt.set_node_text(node: Node, text: String)?);
+        register!(module, rt.insert_child(parent: Node, index: rhai::INT, child: Node)?);
+        register!(module, rt.detach_node(node: Node)?);
+        register!(module, rt.swap_nodes(node: Node, other: Node)?);
+        register!(module, rt.delete_node(node: Node)?);
+        register!(module, rt.goto_node(node: Node)?);
+        register!(module, rt.replace_node(node: Node, new_node: Node)?);
+
         // Clipboard
         register!(module, rt.cut()?);
+        register!(module, rt.copy_as_text(width: rhai::INT)?);
+        register!(module, rt.paste_text(text: String)?);
         register!(module, rt, ClipboardCommand::Copy as copy);
         register!(module, rt, ClipboardCommand::Paste as paste);
         register!(module, rt, ClipboardCommand::PasteSwap as paste_swap);
+        register!(module, rt, ClipboardCommand::PasteReplace as paste_replace);
         register!(module, rt, ClipboardCommand::Dup as dup_clipboard);
         register!(module, rt, ClipboardCommand::Pop as pop_clipboard);
+        register!(module, rt.diff_with_doc(path: &str)?);
+        register!(module, rt.replace_from_doc(path: &str)?);
+        register!(module, rt.clipboard_summaries());
+        register!(module, rt.node_summary()?);
 
         // Editing: Meta
         register!(module, rt.undo()?);
         register!(module, rt.redo()?);
         register!(module, rt.revert()?);
+        register!(module, rt.toggle_undo_tree());
+        register!(module, rt.prev_undo_branch()?);
+        register!(module, rt.next_undo_branch()?);
+        register!(module, rt.goto_undo_state(id: rhai::INT)?);
+        register!(module, rt.undo_history()?);
+        register!(module, rt.goto_undo_history(position: rhai::INT)?);
+        register!(module, rt.undo_history_position()?);
+        register!(module, rt.undo_history_length()?);
+        register!(module, rt.playback_step(delta: rhai::INT)?);
+        register!(module, rt.playback_play(interval_ms: rhai::INT)?);
+
+        // Linting
+        register!(module, rt.add_lint_rule(language_name: &str, name: String, check: rhai::FnPtr)?);
+        register!(module, rt.lint_rules()?);
+        register!(module, rt.lint_nodes()?);
+        register!(module, rt.set_diagnostics(diagnostics: rhai::Array)?);
+        register!(module, rt.diagnostics()?);
+        register!(module, rt.goto_diagnostic(index: rhai::INT)?);
+        register!(module, rt.goto_next_diagnostic()?);
+        register!(module, rt.set_json_schema(path: &str)?);
+        register!(module, rt.clear_json_schema()?);
+        register!(module, rt.toggle_lint_results());
+
+        // Spell Checking
+        register!(module, rt.load_dictionary(path: &str)?);
+        register!(module, rt.clear_dictionary());
+        register!(module, rt.spell_check()?);
+        register!(module, rt.spelling_suggestions()?);
+        register!(module, rt.correct_spelling(correction: &str)?);
+        register!(module, rt.goto_next_misspelling()?);
 
         // Command Line Interface
         register!(module, rt.cli_args());
@@ -951,5 +5819,10 @@ impl<F: Frontend<Style = Style> + 'static> Runtime<F> {
         register!(module, rt.log_warn(msg: String));
         register!(module, rt.log_error(msg: String));
         register!(module, rt.clear_last_log());
+        register!(module, rt.notify(level: &str, message: String)?);
+        register!(module, rt.notify_for(level: &str, message: String, ms: rhai::INT)?);
+        register!(module, rt.dismiss_notifications());
+        register!(module, rt.timing_stats());
+        register!(module, rt.clear_timing_stats());
     }
 }
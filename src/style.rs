@@ -1,6 +1,13 @@
+use crate::tree::{NodeChange, NodeId};
 use crate::util::SynlessBug;
 use partial_pretty_printer as ppp;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const BASE16_NAMES: [&str; 16] = [
+    "base00", "base01", "base02", "base03", "base04", "base05", "base06", "base07", "base08",
+    "base09", "base0A", "base0B", "base0C", "base0D", "base0E", "base0F",
+];
 
 const HOLE_STYLE: Style = Style {
     fg_color: Some((Base16Color::Base0F, Priority::High)),
@@ -21,11 +28,22 @@ const HIGHLIGHT_STYLE: Style = Style {
     ..Style::const_default()
 };
 
+const SELECTION_STYLE: Style = Style {
+    bg_color: Some((Base16Color::Base03, Priority::High)),
+    ..Style::const_default()
+};
+
 const CURSOR_STYLE: Style = Style {
     bg_color: Some((Base16Color::Base02, Priority::High)),
     ..Style::const_default()
 };
 
+const DROP_TARGET_STYLE: Style = Style {
+    fg_color: Some((Base16Color::Base00, Priority::High)),
+    bg_color: Some((Base16Color::Base0D, Priority::High)),
+    ..Style::const_default()
+};
+
 const INVALID_TEXT_STYLE: Style = Style {
     fg_color: Some((Base16Color::Base08, Priority::High)),
     underlined: Some((true, Priority::High)),
@@ -33,6 +51,42 @@ const INVALID_TEXT_STYLE: Style = Style {
     ..Style::const_default()
 };
 
+const DIAGNOSTIC_STYLE: Style = Style {
+    undercurled: Some((true, Priority::High)),
+    underline_color: Some((Base16Color::Base08, Priority::High)),
+    ..Style::const_default()
+};
+
+const MISSPELLED_STYLE: Style = Style {
+    undercurled: Some((true, Priority::High)),
+    underline_color: Some((Base16Color::Base0D, Priority::High)),
+    ..Style::const_default()
+};
+
+const ADDED_STYLE: Style = Style {
+    fg_color: Some((Base16Color::Base00, Priority::High)),
+    bg_color: Some((Base16Color::Base0B, Priority::High)),
+    ..Style::const_default()
+};
+
+const MODIFIED_STYLE: Style = Style {
+    fg_color: Some((Base16Color::Base00, Priority::High)),
+    bg_color: Some((Base16Color::Base0C, Priority::High)),
+    ..Style::const_default()
+};
+
+const DELETED_STYLE: Style = Style {
+    fg_color: Some((Base16Color::Base00, Priority::High)),
+    bg_color: Some((Base16Color::Base08, Priority::High)),
+    ..Style::const_default()
+};
+
+const CONFLICT_STYLE: Style = Style {
+    fg_color: Some((Base16Color::Base00, Priority::High)),
+    bg_color: Some((Base16Color::Base0E, Priority::High)),
+    ..Style::const_default()
+};
+
 const FG_COLOR: Base16Color = Base16Color::Base05;
 const BG_COLOR: Base16Color = Base16Color::Base00;
 
@@ -42,6 +96,13 @@ pub struct ConcreteStyle {
     pub bg_color: Rgb,
     pub bold: bool,
     pub underlined: bool,
+    /// A curly underline, for terminals that support it. Takes precedence over `underlined`.
+    pub undercurled: bool,
+    /// The color of the underline, if it should differ from the foreground color.
+    pub underline_color: Option<Rgb>,
+    pub italic: bool,
+    pub dim: bool,
+    pub strikethrough: bool,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -50,10 +111,66 @@ pub struct Style {
     pub bg_color: Option<(Base16Color, Priority)>,
     pub bold: Option<(bool, Priority)>,
     pub underlined: Option<(bool, Priority)>,
+    pub undercurled: Option<(bool, Priority)>,
+    pub underline_color: Option<(Base16Color, Priority)>,
+    pub italic: Option<(bool, Priority)>,
+    pub dim: Option<(bool, Priority)>,
+    pub strikethrough: Option<(bool, Priority)>,
     pub cursor: Option<CursorKind>,
     pub is_hole: bool,
     pub is_highlighted: bool,
+    pub is_selected: bool,
+    /// Whether this text is part of a node under one of the doc's secondary cursors.
+    pub is_secondary_cursor: bool,
     pub is_invalid: bool,
+    pub is_drop_target: bool,
+    /// Whether this text is part of a node that a lint rule found a problem with.
+    pub has_diagnostic: bool,
+    /// Whether this text is part of a prose node that the spell checker found a misspelled word
+    /// in. The whole node is marked, since a node's text is all printed in one style.
+    pub is_misspelled: bool,
+    /// How the node this text is part of differs from the git `HEAD` version of the doc (or the
+    /// other side of a diff), if it does.
+    pub change: Option<NodeChange>,
+    /// The innermost document node that this text belongs to.
+    pub node: Option<NodeId>,
+    /// The background shade level of the innermost shaded node this text belongs to. See
+    /// [`Shading`]. Only used if nothing else sets the background color.
+    pub shade: Option<usize>,
+    /// If this text is a delimiter, the depth of the node it belongs to. Only used if nothing
+    /// else sets the foreground color.
+    pub delimiter_depth: Option<usize>,
+}
+
+/// Which nodes get a shaded background, to make the tree structure easier to see.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadingMode {
+    #[default]
+    Off,
+    /// Shade the ancestors of the cursor. The cursor's parent gets level 0, its grandparent
+    /// level 1, and so on.
+    CursorAncestors,
+    /// Shade every node by its depth in the tree, cycling through the levels. The root isn't
+    /// shaded, its children get level 0, and so on.
+    Depth,
+}
+
+/// How to shade the background of nodes. The color of each level is given by
+/// [`ColorTheme::shade_colors`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shading {
+    pub mode: ShadingMode,
+    /// The number of shade levels. With `CursorAncestors`, only this many ancestors are shaded.
+    pub levels: usize,
+}
+
+impl Default for Shading {
+    fn default() -> Shading {
+        Shading {
+            mode: ShadingMode::Off,
+            levels: 3,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
@@ -76,6 +193,9 @@ pub enum CursorKind {
 pub enum StyleLabel {
     Open,
     Close,
+    /// A bracket or other delimiter, colored by how deeply its node is nested (see
+    /// [`ColorTheme::delimiter_colors`]).
+    Delimiter,
     Properties {
         #[serde(default)]
         fg_color: Option<Base16Color>,
@@ -86,6 +206,16 @@ pub enum StyleLabel {
         #[serde(default)]
         underlined: Option<bool>,
         #[serde(default)]
+        undercurled: Option<bool>,
+        #[serde(default)]
+        underline_color: Option<Base16Color>,
+        #[serde(default)]
+        italic: Option<bool>,
+        #[serde(default)]
+        dim: Option<bool>,
+        #[serde(default)]
+        strikethrough: Option<bool>,
+        #[serde(default)]
         priority: Priority,
     },
 }
@@ -148,6 +278,33 @@ pub enum Base16Color {
     Base0F,
 }
 
+impl Base16Color {
+    /// Parse a color from its name, like "Base0A".
+    pub fn from_name(name: &str) -> Option<Base16Color> {
+        use Base16Color::*;
+
+        match name {
+            "Base00" => Some(Base00),
+            "Base01" => Some(Base01),
+            "Base02" => Some(Base02),
+            "Base03" => Some(Base03),
+            "Base04" => Some(Base04),
+            "Base05" => Some(Base05),
+            "Base06" => Some(Base06),
+            "Base07" => Some(Base07),
+            "Base08" => Some(Base08),
+            "Base09" => Some(Base09),
+            "Base0A" => Some(Base0A),
+            "Base0B" => Some(Base0B),
+            "Base0C" => Some(Base0C),
+            "Base0D" => Some(Base0D),
+            "Base0E" => Some(Base0E),
+            "Base0F" => Some(Base0F),
+            _ => None,
+        }
+    }
+}
+
 /// A color theme that maps [Base16](https://github.com/chriskempson/base16) color names to RGB
 /// color values.
 #[derive(Debug, Clone)]
@@ -185,6 +342,12 @@ pub struct ColorTheme {
     pub base0E: Rgb,
     /// Deprecated, Opening/Closing Embedded Language Tags, e.g. <?php ?>
     pub base0F: Rgb,
+    /// Background colors for each shade level (see [`Shading`]), starting from level 0. Levels
+    /// past the end use the last color.
+    pub shade_colors: Vec<Rgb>,
+    /// Foreground colors for delimiters, cycled through by nesting depth. If empty, delimiters
+    /// aren't colored specially.
+    pub delimiter_colors: Vec<Rgb>,
 }
 
 fn prioritize<T>(
@@ -207,11 +370,25 @@ impl ppp::Style for Style {
             bg_color: prioritize(outer.bg_color, inner.bg_color),
             bold: prioritize(outer.bold, inner.bold),
             underlined: prioritize(outer.underlined, inner.underlined),
+            undercurled: prioritize(outer.undercurled, inner.undercurled),
+            underline_color: prioritize(outer.underline_color, inner.underline_color),
+            italic: prioritize(outer.italic, inner.italic),
+            dim: prioritize(outer.dim, inner.dim),
+            strikethrough: prioritize(outer.strikethrough, inner.strikethrough),
 
             cursor: inner.cursor.or(outer.cursor),
             is_hole: outer.is_hole || inner.is_hole,
             is_highlighted: outer.is_highlighted || inner.is_highlighted,
+            is_selected: outer.is_selected || inner.is_selected,
+            is_secondary_cursor: outer.is_secondary_cursor || inner.is_secondary_cursor,
             is_invalid: outer.is_invalid || inner.is_invalid,
+            is_drop_target: outer.is_drop_target || inner.is_drop_target,
+            has_diagnostic: outer.has_diagnostic || inner.has_diagnostic,
+            is_misspelled: outer.is_misspelled || inner.is_misspelled,
+            change: inner.change.or(outer.change),
+            node: inner.node.or(outer.node),
+            shade: inner.shade.or(outer.shade),
+            delimiter_depth: inner.delimiter_depth.or(outer.delimiter_depth),
         }
     }
 }
@@ -223,10 +400,24 @@ impl Style {
             bg_color: None,
             bold: None,
             underlined: None,
+            undercurled: None,
+            underline_color: None,
+            italic: None,
+            dim: None,
+            strikethrough: None,
             cursor: None,
             is_hole: false,
             is_highlighted: false,
+            is_selected: false,
+            is_secondary_cursor: false,
             is_invalid: false,
+            is_drop_target: false,
+            has_diagnostic: false,
+            is_misspelled: false,
+            change: None,
+            node: None,
+            shade: None,
+            delimiter_depth: None,
         }
     }
 
@@ -249,6 +440,31 @@ impl Style {
         self.underlined = Some((underlined, priority));
         self
     }
+
+    pub fn with_undercurled(mut self, undercurled: bool, priority: Priority) -> Style {
+        self.undercurled = Some((undercurled, priority));
+        self
+    }
+
+    pub fn with_underline_color(mut self, color: Base16Color, priority: Priority) -> Style {
+        self.underline_color = Some((color, priority));
+        self
+    }
+
+    pub fn with_italic(mut self, italic: bool, priority: Priority) -> Style {
+        self.italic = Some((italic, priority));
+        self
+    }
+
+    pub fn with_dim(mut self, dim: bool, priority: Priority) -> Style {
+        self.dim = Some((dim, priority));
+        self
+    }
+
+    pub fn with_strikethrough(mut self, strikethrough: bool, priority: Priority) -> Style {
+        self.strikethrough = Some((strikethrough, priority));
+        self
+    }
 }
 
 impl ColorTheme {
@@ -272,6 +488,17 @@ impl ColorTheme {
             base0D: Rgb::from_hex("#7cafc2").bug(),
             base0E: Rgb::from_hex("#ba8baf").bug(),
             base0F: Rgb::from_hex("#a16946").bug(),
+            shade_colors: vec![
+                Rgb::from_hex("#2e2e2e").bug(),
+                Rgb::from_hex("#262626").bug(),
+                Rgb::from_hex("#1f1f1f").bug(),
+            ],
+            delimiter_colors: vec![
+                Rgb::from_hex("#f7ca88").bug(),
+                Rgb::from_hex("#ba8baf").bug(),
+                Rgb::from_hex("#7cafc2").bug(),
+                Rgb::from_hex("#a1b56c").bug(),
+            ],
         }
     }
 
@@ -281,15 +508,50 @@ impl ColorTheme {
         }
 
         let mut full_style = style.to_owned();
+        // Applied first, so that everything else takes precedence.
+        if style.cursor.is_none() && !style.is_selected {
+            match style.change {
+                Some(NodeChange::Added) => {
+                    full_style = ppp::Style::combine(&full_style, &ADDED_STYLE)
+                }
+                Some(NodeChange::Modified) => {
+                    full_style = ppp::Style::combine(&full_style, &MODIFIED_STYLE)
+                }
+                Some(NodeChange::Deleted) => {
+                    full_style = ppp::Style::combine(&full_style, &DELETED_STYLE)
+                }
+                Some(NodeChange::Conflict) => {
+                    full_style = ppp::Style::combine(&full_style, &CONFLICT_STYLE)
+                }
+                None => (),
+            }
+        }
         if style.is_hole {
             full_style = ppp::Style::combine(&full_style, &HOLE_STYLE);
         }
         if style.is_highlighted && style.cursor.is_none() {
             full_style = ppp::Style::combine(&full_style, &HIGHLIGHT_STYLE);
         }
+        if style.is_selected {
+            full_style = ppp::Style::combine(&full_style, &SELECTION_STYLE);
+        }
+        if style.is_secondary_cursor && style.cursor.is_none() {
+            // Use the shade closest to the cursor color, so that secondary cursors look like
+            // cursors but can't be confused with the primary one.
+            full_style.bg_color = None;
+            full_style.shade = Some(0);
+        }
+        if style.is_drop_target {
+            full_style = ppp::Style::combine(&full_style, &DROP_TARGET_STYLE);
+        }
         if style.is_invalid {
             full_style = ppp::Style::combine(&full_style, &INVALID_TEXT_STYLE);
         }
+        if style.has_diagnostic {
+            full_style = ppp::Style::combine(&full_style, &DIAGNOSTIC_STYLE);
+        } else if style.is_misspelled {
+            full_style = ppp::Style::combine(&full_style, &MISSPELLED_STYLE);
+        }
         if style.cursor == Some(CursorKind::AtNode) {
             full_style = ppp::Style::combine(&full_style, &CURSOR_STYLE);
         }
@@ -298,13 +560,37 @@ impl ColorTheme {
         }
 
         ConcreteStyle {
-            fg_color: self.color(unwrap_property(full_style.fg_color, FG_COLOR)),
-            bg_color: self.color(unwrap_property(full_style.bg_color, BG_COLOR)),
+            fg_color: match (full_style.fg_color, full_style.delimiter_depth) {
+                (None, Some(depth)) if !self.delimiter_colors.is_empty() => {
+                    self.delimiter_colors[depth % self.delimiter_colors.len()]
+                }
+                (fg_color, _) => self.color(unwrap_property(fg_color, FG_COLOR)),
+            },
+            bg_color: match (full_style.bg_color, full_style.shade) {
+                (None, Some(level)) => self.shade_color(level),
+                (bg_color, _) => self.color(unwrap_property(bg_color, BG_COLOR)),
+            },
             bold: unwrap_property(full_style.bold, false),
             underlined: unwrap_property(full_style.underlined, false),
+            undercurled: unwrap_property(full_style.undercurled, false),
+            underline_color: full_style
+                .underline_color
+                .map(|(color, _)| self.color(color)),
+            italic: unwrap_property(full_style.italic, false),
+            dim: unwrap_property(full_style.dim, false),
+            strikethrough: unwrap_property(full_style.strikethrough, false),
         }
     }
 
+    /// The background color of the given shade level.
+    pub fn shade_color(&self, level: usize) -> Rgb {
+        let last_level = self.shade_colors.len().saturating_sub(1);
+        self.shade_colors
+            .get(level.min(last_level))
+            .copied()
+            .unwrap_or_else(|| self.color(BG_COLOR))
+    }
+
     pub fn color(&self, color: Base16Color) -> Rgb {
         match color {
             Base16Color::Base00 => self.base00,
@@ -327,7 +613,133 @@ impl ColorTheme {
     }
 }
 
+/// A color theme as written in a RON theme file. Colors are hex strings like `"#181818"`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColorThemeSpec {
+    /// Maps each Base16 color name, `base00` through `base0F`, to its color.
+    pub base16: HashMap<String, String>,
+    /// Defaults to a ramp from the selection background to the default background.
+    #[serde(default)]
+    pub shade_colors: Option<Vec<String>>,
+    /// Defaults to the Base16 yellow, purple, blue, and green.
+    #[serde(default)]
+    pub delimiter_colors: Option<Vec<String>>,
+}
+
+impl ColorTheme {
+    /// Parse a theme written in the RON format given by [`ColorThemeSpec`].
+    pub fn from_ron(source: &str) -> Result<ColorTheme, String> {
+        let spec = ron::from_str::<ColorThemeSpec>(source).map_err(|err| err.to_string())?;
+        let parse_colors = |colors: Option<Vec<String>>| {
+            colors
+                .map(|colors| {
+                    colors
+                        .iter()
+                        .map(|hex| parse_hex(hex))
+                        .collect::<Result<Vec<Rgb>, String>>()
+                })
+                .transpose()
+        };
+        let shade_colors = parse_colors(spec.shade_colors)?;
+        let delimiter_colors = parse_colors(spec.delimiter_colors)?;
+        ColorTheme::from_base16(&spec.base16, shade_colors, delimiter_colors)
+    }
+
+    /// Parse a [Base16](https://github.com/chriskempson/base16) scheme file. These are YAML, but
+    /// only the flat `baseXX: "rrggbb"` entries are read (optionally nested under `palette:`), so
+    /// no general YAML parser is needed.
+    pub fn from_base16_yaml(source: &str) -> Result<ColorTheme, String> {
+        let mut colors = HashMap::new();
+        for line in source.lines() {
+            if let Some((key, value)) = line.trim().split_once(':') {
+                let key = key.trim();
+                if let Some(name) = BASE16_NAMES.iter().find(|n| n.eq_ignore_ascii_case(key)) {
+                    let value = value.trim();
+                    let value = match value.strip_prefix('"') {
+                        Some(quoted) => quoted.split('"').next().unwrap_or(""),
+                        None => value.split_whitespace().next().unwrap_or(""),
+                    };
+                    colors.insert((*name).to_owned(), value.to_owned());
+                }
+            }
+        }
+        ColorTheme::from_base16(&colors, None, None)
+    }
+
+    fn from_base16(
+        colors: &HashMap<String, String>,
+        shade_colors: Option<Vec<Rgb>>,
+        delimiter_colors: Option<Vec<Rgb>>,
+    ) -> Result<ColorTheme, String> {
+        if let Some(unknown) = colors
+            .keys()
+            .find(|name| !BASE16_NAMES.contains(&name.as_str()))
+        {
+            return Err(format!("Unknown Base16 color '{unknown}'"));
+        }
+        let color = |name: &str| -> Result<Rgb, String> {
+            let hex = colors
+                .get(name)
+                .ok_or_else(|| format!("Missing Base16 color '{name}'"))?;
+            parse_hex(hex)
+        };
+
+        let mut theme = ColorTheme {
+            base00: color("base00")?,
+            base01: color("base01")?,
+            base02: color("base02")?,
+            base03: color("base03")?,
+            base04: color("base04")?,
+            base05: color("base05")?,
+            base06: color("base06")?,
+            base07: color("base07")?,
+            base08: color("base08")?,
+            base09: color("base09")?,
+            base0A: color("base0A")?,
+            base0B: color("base0B")?,
+            base0C: color("base0C")?,
+            base0D: color("base0D")?,
+            base0E: color("base0E")?,
+            base0F: color("base0F")?,
+            shade_colors: Vec::new(),
+            delimiter_colors: Vec::new(),
+        };
+        theme.shade_colors = shade_colors.unwrap_or_else(|| {
+            (1..=3)
+                .rev()
+                .map(|quarters| theme.base00.mix(theme.base02, quarters, 4))
+                .collect()
+        });
+        theme.delimiter_colors = delimiter_colors
+            .unwrap_or_else(|| vec![theme.base0A, theme.base0E, theme.base0D, theme.base0B]);
+        Ok(theme)
+    }
+}
+
+/// Parse a color like `"#181818"`. The `#` is optional, as Base16 scheme files omit it.
+fn parse_hex(hex: &str) -> Result<Rgb, String> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if digits.len() != 6 {
+        return Err(format!("Invalid color '{hex}'"));
+    }
+    Rgb::from_hex(&format!("#{digits}")).ok_or_else(|| format!("Invalid color '{hex}'"))
+}
+
 impl Rgb {
+    /// The color `numer / denom` of the way from `self` to `other`.
+    fn mix(self, other: Rgb, numer: u32, denom: u32) -> Rgb {
+        let mix = |a: u8, b: u8| {
+            let (a, b) = (a as u32, b as u32);
+            ((a * (denom - numer) + b * numer) / denom) as u8
+        };
+        Rgb {
+            red: mix(self.red, other.red),
+            green: mix(self.green, other.green),
+            blue: mix(self.blue, other.blue),
+        }
+    }
+
     /// Construct an Rgb color from a string of the form "#FFFFFF".
     fn from_hex(hex_color: &str) -> Option<Rgb> {
         let to_int = |inclusive_range: (usize, usize)| -> Option<u8> {
@@ -340,4 +752,9 @@ impl Rgb {
             blue: to_int((5, 6))?,
         })
     }
+
+    /// Write this color as a string of the form "#ffffff".
+    pub fn to_hex(self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
 }
@@ -0,0 +1,97 @@
+//! Large made-up json docs, for measuring how printing performance scales with the shape of a
+//! doc. They're deterministic, so that measurements can be compared across changes.
+
+use crate::language::{Construct, Language, Storage};
+use crate::tree::Node;
+use crate::util::{SynlessBug, SynlessError};
+
+const JSON_LANGUAGE_NAME: &str = "json";
+const WORDS: &[&str] = &[
+    "tree", "editor", "syntax", "notation", "cursor", "printer", "layout", "node", "hole", "text",
+];
+
+/// The shape of a synthetic doc made by [`make_synthetic_json()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntheticShape {
+    /// Arrays and objects nested `depth` deep, alternating, with a number at the bottom.
+    DeepNesting { depth: usize },
+    /// An array of `len` small objects, each with a string, a number, and a bool.
+    LongList { len: usize },
+    /// A single string `len` characters long, made of words separated by spaces.
+    LongText { len: usize },
+}
+
+/// Make a json tree with the given shape, as a root that isn't in any doc. The json language (from
+/// `data/json_lang.ron`) must already be loaded into `s`.
+pub fn make_synthetic_json(s: &mut Storage, shape: SyntheticShape) -> Result<Node, SynlessError> {
+    let lang = s.language(JSON_LANGUAGE_NAME)?;
+    let value = match shape {
+        SyntheticShape::DeepNesting { depth } => {
+            let mut value = make_text(s, lang, "Number", depth.to_string());
+            for level in (0..depth).rev() {
+                value = if level % 2 == 0 {
+                    make_branch(s, lang, "Array", vec![value])
+                } else {
+                    let key = make_text(s, lang, "Key", format!("level{level}"));
+                    let pair = make_branch(s, lang, "ObjectPair", vec![key, value]);
+                    make_branch(s, lang, "Object", vec![pair])
+                };
+            }
+            value
+        }
+        SyntheticShape::LongList { len } => {
+            let items = (0..len).map(|i| make_list_item(s, lang, i)).collect();
+            make_branch(s, lang, "Array", items)
+        }
+        SyntheticShape::LongText { len } => make_text(s, lang, "String", words(len)),
+    };
+    Ok(make_branch(s, lang, "Root", vec![value]))
+}
+
+fn make_list_item(s: &mut Storage, lang: Language, i: usize) -> Node {
+    let even = if i % 2 == 0 { "True" } else { "False" };
+    let fields = [
+        ("name", make_text(s, lang, "String", words(8 + i % 24))),
+        ("id", make_text(s, lang, "Number", i.to_string())),
+        ("even", make_branch(s, lang, even, Vec::new())),
+    ];
+    let pairs = fields
+        .into_iter()
+        .map(|(key, value)| {
+            let key = make_text(s, lang, "Key", key.to_owned());
+            make_branch(s, lang, "ObjectPair", vec![key, value])
+        })
+        .collect();
+    make_branch(s, lang, "Object", pairs)
+}
+
+/// Words from `WORDS`, cycling, cut off at exactly `len` characters.
+fn words(len: usize) -> String {
+    let mut text = String::with_capacity(len + 16);
+    for word in WORDS.iter().cycle() {
+        if text.len() >= len {
+            break;
+        }
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(word);
+    }
+    text.truncate(len);
+    text
+}
+
+fn construct(s: &Storage, lang: Language, name: &str) -> Construct {
+    lang.construct(s, name)
+        .bug_msg("Synthetic docs need the json language from data/json_lang.ron")
+}
+
+fn make_text(s: &mut Storage, lang: Language, construct_name: &str, text: String) -> Node {
+    let construct = construct(s, lang, construct_name);
+    Node::with_text(s, construct, text).bug()
+}
+
+fn make_branch(s: &mut Storage, lang: Language, construct_name: &str, children: Vec<Node>) -> Node {
+    let construct = construct(s, lang, construct_name);
+    Node::with_children(s, construct, children).bug()
+}
@@ -0,0 +1,55 @@
+//! Fixtures shared by the unit tests. The integration tests in `tests/` can't see these, and have
+//! their own.
+
+use crate::engine::{Engine, Settings};
+use crate::language::{LanguageSpec, Storage};
+use crate::parsing::JsonParser;
+use std::path::{Path, PathBuf};
+
+pub const JSON_PATH: &str = "data/json_lang.ron";
+pub const JSON_LANGUAGE_NAME: &str = "json";
+
+/// A storage containing just the json language.
+pub fn json_storage() -> Storage {
+    let mut s = Storage::new();
+    let json_lang_ron = std::fs::read_to_string(JSON_PATH).unwrap();
+    s.add_language(ron::from_str::<LanguageSpec>(&json_lang_ron).unwrap())
+        .unwrap();
+    s
+}
+
+/// An engine with the json language and its parser, and no docs.
+pub fn json_engine() -> Engine {
+    let mut engine = Engine::new(Settings::default());
+    let json_lang_ron = std::fs::read_to_string(JSON_PATH).unwrap();
+    let language_name = engine
+        .load_language_ron(Path::new(JSON_PATH), &json_lang_ron)
+        .unwrap();
+    assert_eq!(language_name, JSON_LANGUAGE_NAME);
+    engine.add_parser(JSON_LANGUAGE_NAME, JsonParser);
+    engine
+}
+
+/// A fresh, empty directory for one test, which is deleted when this is dropped (including when
+/// the test fails).
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Tests run in parallel, so `test_name` must be unique among them.
+    pub fn new(test_name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("synless-{test_name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
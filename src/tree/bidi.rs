@@ -0,0 +1,241 @@
+//! A simplified version of the Unicode Bidirectional Algorithm (UAX #9), for displaying texty
+//! nodes that contain right-to-left scripts like Hebrew and Arabic.
+//!
+//! Each text is treated as an isolate: its direction is taken from its first strong character,
+//! and it's reordered on its own. Text can thus never visually reorder the structural delimiters
+//! around it. Explicit embedding characters aren't supported; they're treated as neutral.
+
+use unicode_width::UnicodeWidthChar;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BidiClass {
+    /// Strong left-to-right.
+    Left,
+    /// Strong right-to-left.
+    Right,
+    /// A digit. Follows the direction of the text around it, but is always written left-to-right.
+    Number,
+    /// Whitespace, punctuation, and so forth.
+    Neutral,
+}
+
+fn bidi_class(ch: char) -> BidiClass {
+    match ch as u32 {
+        0x30..=0x39 | 0x660..=0x669 | 0x6F0..=0x6F9 => BidiClass::Number,
+        0x590..=0x8FF
+        | 0xFB1D..=0xFDFF
+        | 0xFE70..=0xFEFF
+        | 0x10800..=0x10FFF
+        | 0x1E800..=0x1EFFF => {
+            if ch.is_alphabetic() {
+                BidiClass::Right
+            } else {
+                BidiClass::Neutral
+            }
+        }
+        _ if ch.is_alphabetic() => BidiClass::Left,
+        _ => BidiClass::Neutral,
+    }
+}
+
+/// Whether the text contains any right-to-left characters. If not, its display order is the same
+/// as its logical order.
+pub fn has_rtl(text: &str) -> bool {
+    text.chars().any(|ch| bidi_class(ch) == BidiClass::Right)
+}
+
+/// The embedding level of each character: even for left-to-right, odd for right-to-left.
+fn levels(chars: &[char]) -> Vec<u8> {
+    let classes = chars.iter().map(|ch| bidi_class(*ch)).collect::<Vec<_>>();
+    let is_strong = |class: BidiClass| matches!(class, BidiClass::Left | BidiClass::Right);
+    let base_is_rtl = classes.iter().find(|class| is_strong(**class)) == Some(&BidiClass::Right);
+    let base_direction = if base_is_rtl {
+        BidiClass::Right
+    } else {
+        BidiClass::Left
+    };
+
+    // Numbers take the direction of the preceding strong character, for the purpose of
+    // resolving neutrals (but are still written left-to-right).
+    let mut prev_strong = base_direction;
+    let mut directions = Vec::with_capacity(classes.len());
+    for class in &classes {
+        match class {
+            BidiClass::Left | BidiClass::Right => {
+                prev_strong = *class;
+                directions.push(Some(*class));
+            }
+            BidiClass::Number => directions.push(Some(prev_strong)),
+            BidiClass::Neutral => directions.push(None),
+        }
+    }
+
+    // Neutrals between two characters of the same direction take on that direction. Other
+    // neutrals take the base direction.
+    let mut resolved = directions.clone();
+    let mut i = 0;
+    while i < directions.len() {
+        if directions[i].is_some() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < directions.len() && directions[i].is_none() {
+            i += 1;
+        }
+        let before = start.checked_sub(1).and_then(|j| directions[j]);
+        let after = directions.get(i).copied().flatten();
+        let direction = match (before, after) {
+            (Some(before), Some(after)) if before == after => before,
+            _ => base_direction,
+        };
+        for dir in &mut resolved[start..i] {
+            *dir = Some(direction);
+        }
+    }
+
+    classes
+        .iter()
+        .zip(resolved)
+        .map(|(class, dir)| match (class, dir) {
+            // Numbers are always written left-to-right, even inside right-to-left text.
+            (BidiClass::Number, Some(BidiClass::Right)) => 2,
+            (_, Some(BidiClass::Right)) => 1,
+            // Left-to-right text inside right-to-left text.
+            (_, _) if base_is_rtl => 2,
+            (_, _) => 0,
+        })
+        .collect()
+}
+
+/// Split the text into clusters: a character together with any zero-width characters (like
+/// accents) that follow it. Clusters are never split up by reordering. Returns the index of the
+/// first char of each cluster.
+fn cluster_starts(chars: &[char]) -> Vec<usize> {
+    let mut starts = Vec::new();
+    for (i, ch) in chars.iter().enumerate() {
+        if i == 0 || ch.width() != Some(0) {
+            starts.push(i);
+        }
+    }
+    starts
+}
+
+/// The visual order of the clusters, as indices into `cluster_levels`, left to right.
+fn visual_order(cluster_levels: &[u8]) -> Vec<usize> {
+    let mut order = (0..cluster_levels.len()).collect::<Vec<_>>();
+    let max_level = cluster_levels.iter().copied().max().unwrap_or(0);
+    // From the highest level down to the lowest odd level, reverse every run of clusters at that
+    // level or higher.
+    for level in (1..=max_level).rev() {
+        let mut i = 0;
+        while i < order.len() {
+            if cluster_levels[order[i]] < level {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < order.len() && cluster_levels[order[i]] >= level {
+                i += 1;
+            }
+            order[start..i].reverse();
+        }
+    }
+    order
+}
+
+fn mirror(ch: char) -> char {
+    match ch {
+        '(' => ')',
+        ')' => '(',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        '}' => '{',
+        '<' => '>',
+        '>' => '<',
+        '«' => '»',
+        '»' => '«',
+        _ => ch,
+    }
+}
+
+/// Information about how to display a text in visual (left-to-right on screen) order.
+pub struct BidiLayout {
+    /// For each cluster in logical order: the index of its first char, its number of chars, and
+    /// its level.
+    clusters: Vec<(usize, usize, u8)>,
+    /// The clusters in visual order, as indices into `clusters`.
+    order: Vec<usize>,
+}
+
+impl BidiLayout {
+    pub fn new(text: &str) -> BidiLayout {
+        let chars = text.chars().collect::<Vec<_>>();
+        let char_levels = levels(&chars);
+        let starts = cluster_starts(&chars);
+        let clusters = starts
+            .iter()
+            .enumerate()
+            .map(|(i, start)| {
+                let end = starts.get(i + 1).copied().unwrap_or(chars.len());
+                (*start, end - start, char_levels[*start])
+            })
+            .collect::<Vec<_>>();
+        let cluster_levels = clusters
+            .iter()
+            .map(|(_, _, level)| *level)
+            .collect::<Vec<_>>();
+        let order = visual_order(&cluster_levels);
+        BidiLayout { clusters, order }
+    }
+
+    /// The text, reordered for display. Brackets in right-to-left runs are mirrored.
+    pub fn reorder(&self, text: &str) -> String {
+        let chars = text.chars().collect::<Vec<_>>();
+        let mut display = String::with_capacity(text.len());
+        for cluster_index in &self.order {
+            let (start, len, level) = self.clusters[*cluster_index];
+            for (i, ch) in chars[start..start + len].iter().enumerate() {
+                if i == 0 && level % 2 == 1 {
+                    display.push(mirror(*ch));
+                } else {
+                    display.push(*ch);
+                }
+            }
+        }
+        display
+    }
+
+    /// Where to show a text cursor that's just before the `char_index`th char (in logical order),
+    /// as a char index into the reordered text.
+    pub fn display_index(&self, char_index: usize) -> usize {
+        // The cursor goes at the leading edge of the cluster it's before. At the very end, it goes
+        // at the trailing edge of the last cluster.
+        let (cluster_index, at_end) = match self
+            .clusters
+            .iter()
+            .position(|(start, len, _)| char_index < start + len)
+        {
+            Some(cluster_index) => (cluster_index, false),
+            None => match self.clusters.len().checked_sub(1) {
+                Some(last) => (last, true),
+                None => return 0,
+            },
+        };
+        let mut display_start = 0;
+        for i in &self.order {
+            let (_, len, level) = self.clusters[*i];
+            if *i == cluster_index {
+                let is_rtl = level % 2 == 1;
+                return if is_rtl != at_end {
+                    display_start + len
+                } else {
+                    display_start
+                };
+            }
+            display_start += len;
+        }
+        char_index
+    }
+}
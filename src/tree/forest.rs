@@ -1,16 +1,46 @@
-use crate::util::{bug, SynlessBug};
+use crate::util::{bug, bug_assert, SynlessBug};
 use generational_arena::Arena;
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::ops::Range;
 
 /// An index into a Forest, which represents a node in a tree.
 pub type NodeIndex = generational_arena::Index;
 
+/// Why one of the `try_*` methods of a [`Forest`] failed. They fail instead of panicking when
+/// given a deleted node, and instead of returning `false` when an operation is invalid.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForestError {
+    #[error("Node has been deleted")]
+    DeletedNode,
+    #[error("Node must be a root, but it has a parent")]
+    NotRoot,
+    #[error("Node is a root, so it has no siblings")]
+    IsRoot,
+    #[error("Can't place a node inside of itself")]
+    Overlapping,
+}
+
+/// What [`Forest::visit()`] should do after visiting a node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    /// Go on to the node's children, then the rest of the subtree.
+    Continue,
+    /// Don't visit the node's descendants, but go on to the rest of the subtree.
+    SkipChildren,
+    /// Stop visiting nodes altogether.
+    Stop,
+}
+
 /// A collection of trees. Every node in a tree has additional data D.
 ///
 /// Deleting the ancestor of a node will delete the node.
 ///
 /// **Methods on `Forest` will panic if they're given an Index to a node that was deleted.** The
-/// one exception is the `is_valid()` method, which checks whether a node has been deleted.
+/// exceptions are the `is_valid()` method, which checks whether a node has been deleted, and the
+/// `try_*` methods, which return a [`ForestError`] instead.
 ///
 /// This library solves these problems:
 ///
@@ -23,7 +53,12 @@ pub type NodeIndex = generational_arena::Index;
 /// - Preventing "use after free" (see the note on deletion above).
 ///   Along the same lines, preventing cycles at compile time.
 /// - Removing the need to pass the `Forest` in to every method call.
+///
+/// With the `serialization` feature, a forest can be serialized and deserialized with serde. Every
+/// `NodeIndex` into the original forest is valid in the restored one, and refers to the same
+/// node.
 #[derive(Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
 pub struct Forest<D: Debug> {
     // TODO: Try making roots linked in a cycle internally
     arena: Arena<Node<D>>,
@@ -36,6 +71,7 @@ pub struct Forest<D: Debug> {
 /// a parent).  Parents store only their first child. The siblings are linked to
 /// each other in a circle.
 #[derive(Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
 struct Node<D: Debug> {
     parent: Option<NodeIndex>,
     /// The first child, if any.
@@ -50,7 +86,13 @@ struct Node<D: Debug> {
 impl<D: Debug> Forest<D> {
     /// Create a new empty forest. The `dummy_data` will never be used (don't worry about it).
     pub fn new(dummy_data: D) -> Forest<D> {
-        let mut arena = Arena::new();
+        Forest::with_capacity(dummy_data, 0)
+    }
+
+    /// Create a new empty forest with room for `capacity` nodes before it needs to reallocate.
+    pub fn with_capacity(dummy_data: D, capacity: usize) -> Forest<D> {
+        // Leave room for the swap_dummy node.
+        let mut arena = Arena::with_capacity(capacity + 1);
         let swap_dummy = arena.insert_with(|idx| Node {
             parent: None,
             child: None,
@@ -216,31 +258,51 @@ impl<D: Debug> Forest<D> {
         }
     }
 
-    /// Make a deep copy of the given node, including its descendants. The copy will become a root
-    /// node.
+    /// Make a deep copy of the given node, including its descendants, using `clone_data` to copy
+    /// each node's data. The copy will become a root node.
     pub fn deep_copy(
         &mut self,
-        old_node: NodeIndex,
+        old_root: NodeIndex,
         clone_data: &mut impl FnMut(&D) -> D,
     ) -> NodeIndex {
-        let new_node = self.new_node(clone_data(&self.arena[old_node].data));
-        if let Some(old_first_child) = self.arena[old_node].child {
-            let new_first_child = self.deep_copy(old_first_child, clone_data);
-            self.arena[new_first_child].parent = Some(new_node);
-            self.arena[new_node].child = Some(new_first_child);
-
-            let mut new_prev_child = new_first_child;
-            let mut old_child = self.arena[old_first_child].next;
-            while old_child != old_first_child {
-                let new_child = self.deep_copy(old_child, clone_data);
+        let new_root = self.new_node(clone_data(&self.arena[old_root].data));
+        // Stack of (old_node, new_node) whose children haven't been copied yet. It's a stack
+        // instead of recursion so that copying a very deep tree can't overflow the call stack.
+        let mut to_copy = vec![(old_root, new_root)];
+        while let Some((old_node, new_node)) = to_copy.pop() {
+            let old_first_child = match self.arena[old_node].child {
+                Some(child) => child,
+                None => continue,
+            };
+            let mut old_child = old_first_child;
+            let mut new_prev_child = None;
+            loop {
+                let new_child = self.new_node(clone_data(&self.arena[old_child].data));
                 self.arena[new_child].parent = Some(new_node);
-                self.link(new_prev_child, new_child);
-                new_prev_child = new_child;
+                match new_prev_child {
+                    None => self.arena[new_node].child = Some(new_child),
+                    Some(new_prev_child) => self.link(new_prev_child, new_child),
+                }
+                to_copy.push((old_child, new_child));
+                new_prev_child = Some(new_child);
                 old_child = self.arena[old_child].next;
+                if old_child == old_first_child {
+                    break;
+                }
             }
-            self.link(new_prev_child, new_first_child);
+            let new_first_child = self.arena[new_node].child.bug();
+            self.link(new_prev_child.bug(), new_first_child);
         }
-        new_node
+        new_root
+    }
+
+    /// Make a deep copy of the given node, including its descendants, by cloning their data. The
+    /// copy will become a root node.
+    pub fn clone_subtree(&mut self, node: NodeIndex) -> NodeIndex
+    where
+        D: Clone,
+    {
+        self.deep_copy(node, &mut D::clone)
     }
 
     /// True if the forest still contains this node (i.e. its tree hasn't been deleted).
@@ -336,11 +398,288 @@ impl<D: Debug> Forest<D> {
         true
     }
 
+    /// Insert the roots `nodes` into `parent`'s children, in order, so that the first of them
+    /// ends up at `index`. The nodes are linked in a single pass, rather than being inserted one
+    /// at a time. Returns false and does nothing if `index` is greater than the number of
+    /// children, or if any of `nodes` is not a root, is the root of `parent`, or appears twice.
+    #[must_use]
+    pub fn insert_children(
+        &mut self,
+        parent: NodeIndex,
+        index: usize,
+        nodes: &[NodeIndex],
+    ) -> bool {
+        let num_children = self.num_children(parent);
+        let parent_root = self.root(parent);
+        let mut seen = HashSet::with_capacity(nodes.len());
+        if index > num_children
+            || nodes.iter().any(|node| {
+                self.arena[*node].parent.is_some() || *node == parent_root || !seen.insert(*node)
+            })
+        {
+            return false;
+        }
+        let (first, last) = match (nodes.first(), nodes.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return true,
+        };
+
+        for (i, node) in nodes.iter().enumerate() {
+            self.arena[*node].parent = Some(parent);
+            if i > 0 {
+                self.link(nodes[i - 1], *node);
+            }
+        }
+        match self.arena[parent].child {
+            None => {
+                self.link(last, first);
+                self.arena[parent].child = Some(first);
+            }
+            Some(first_child) => {
+                // Siblings are linked in a cycle, so inserting at the end is the same as inserting
+                // before the first child, except that the first child stays the same.
+                let next = self.nth_child(parent, index).unwrap_or(first_child);
+                let prev = self.arena[next].prev;
+                self.link(prev, first);
+                self.link(last, next);
+                if index == 0 {
+                    self.arena[parent].child = Some(first);
+                }
+            }
+        }
+        true
+    }
+
+    /// Remove the children of `parent` whose indices are in `range`, in a single pass, and return
+    /// them in order. They become roots. Returns `None` and does nothing if `range` goes past the
+    /// last child.
+    pub fn drain_children(
+        &mut self,
+        parent: NodeIndex,
+        range: Range<usize>,
+    ) -> Option<Vec<NodeIndex>> {
+        let num_children = self.num_children(parent);
+        if range.start > range.end || range.end > num_children {
+            return None;
+        }
+        if range.is_empty() {
+            return Some(Vec::new());
+        }
+
+        let first = self.nth_child(parent, range.start).bug();
+        let mut drained = Vec::with_capacity(range.len());
+        let mut node = first;
+        for _ in range.clone() {
+            drained.push(node);
+            node = self.arena[node].next;
+        }
+        let last = *drained.last().bug();
+        if range.len() == num_children {
+            self.arena[parent].child = None;
+        } else {
+            let prev = self.arena[first].prev;
+            let next = self.arena[last].next;
+            self.link(prev, next);
+            if range.start == 0 {
+                self.arena[parent].child = Some(next);
+            }
+        }
+        for node in &drained {
+            self.arena[*node].parent = None;
+            self.link(*node, *node);
+        }
+        Some(drained)
+    }
+
+    /// Replace `node` with its children in its parent's list of children, keeping them in order,
+    /// and leave `node` as a root with no children. Returns false and does nothing if `node` is
+    /// a root.
+    #[must_use]
+    pub fn splice(&mut self, node: NodeIndex) -> bool {
+        if self.arena[node].parent.is_none() {
+            return false;
+        }
+        // Move the children out from last to first, each one right after `node`.
+        while let Some(first_child) = self.arena[node].child {
+            let child = self.last_sibling(first_child);
+            self.detach(child);
+            bug_assert!(self.insert_after(node, child));
+        }
+        self.detach(node);
+        true
+    }
+
+    /// Move the siblings from `first` through `last` into the root `parent`, after any children
+    /// it already has, and put `parent` where they were. This is the inverse of `splice()`.
+    /// Returns false and does nothing if `first` is a root, `last` is neither `first` nor one of
+    /// its later siblings, or `parent` is not a root or is the root of `first`.
+    #[must_use]
+    pub fn group(&mut self, first: NodeIndex, last: NodeIndex, parent: NodeIndex) -> bool {
+        if self.arena[first].parent.is_none()
+            || self.arena[parent].parent.is_some()
+            || self.root(first) == parent
+        {
+            return false;
+        }
+        let mut range = vec![first];
+        let mut node = first;
+        while node != last {
+            match self.next_sibling(node) {
+                Some(sibling) => {
+                    range.push(sibling);
+                    node = sibling;
+                }
+                None => return false,
+            }
+        }
+        bug_assert!(self.insert_before(first, parent));
+        for node in range {
+            self.detach(node);
+            bug_assert!(self.insert_last_child(parent, node));
+        }
+        true
+    }
+
     pub fn num_nodes(&self) -> usize {
         // Account for the swap_dummy node.
         self.arena.len() - 1
     }
 
+    /// How many nodes the forest can hold before it needs to reallocate. This never shrinks as
+    /// nodes are deleted; use `compact()` for that.
+    pub fn capacity(&self) -> usize {
+        self.arena.capacity() - 1
+    }
+
+    /// Make room for at least `additional` more nodes, so that they can be added without
+    /// reallocating.
+    pub fn reserve(&mut self, additional: usize) {
+        let free = self.arena.capacity() - self.arena.len();
+        if additional > free {
+            self.arena.reserve(additional - free);
+        }
+    }
+
+    /// Rebuild the arena without the slots of deleted nodes, releasing the memory they held.
+    /// Every node is moved to a new index: returns a map from each node's old index to its new
+    /// one.
+    ///
+    /// Every new index has a later generation than any index the forest handed out before, so
+    /// old indices that weren't remapped are reliably invalid (`is_valid()` returns false), as if
+    /// their nodes had been deleted.
+    pub fn compact(&mut self) -> HashMap<NodeIndex, NodeIndex> {
+        // Re-inserting the swap dummy gives it an index whose generation is at least as late as
+        // any index handed out so far.
+        let old_swap_dummy = self.swap_dummy;
+        let swap_dummy_node = self.arena.remove(old_swap_dummy).bug();
+        let latest = self.arena.insert(swap_dummy_node);
+        let (_, latest_generation) = latest.into_raw_parts();
+        let mut swap_dummy_node = self.arena.remove(latest).bug();
+
+        let old_indices = self.arena.iter().map(|(i, _)| i).collect::<Vec<_>>();
+        let mut arena = Arena::with_capacity(old_indices.len() + 1);
+        // A new arena starts over at generation 0, and only removing a node advances it. Cycle
+        // the swap dummy in and out until the arena's generation is past every old index's.
+        let swap_dummy = loop {
+            let index = arena.insert(swap_dummy_node);
+            if index.into_raw_parts().1 > latest_generation {
+                break index;
+            }
+            swap_dummy_node = arena.remove(index).bug();
+        };
+        arena[swap_dummy].prev = swap_dummy;
+        arena[swap_dummy].next = swap_dummy;
+
+        let mut remapping = HashMap::with_capacity(old_indices.len() + 1);
+        remapping.insert(old_swap_dummy, swap_dummy);
+        for old_index in old_indices {
+            let node = self.arena.remove(old_index).bug();
+            remapping.insert(old_index, arena.insert(node));
+        }
+        let remap = |index: NodeIndex| *remapping.get(&index).bug();
+        for (index, node) in arena.iter_mut() {
+            if index == swap_dummy {
+                continue;
+            }
+            node.parent = node.parent.map(remap);
+            node.child = node.child.map(remap);
+            node.prev = remap(node.prev);
+            node.next = remap(node.next);
+        }
+        self.swap_dummy = swap_dummy;
+        self.arena = arena;
+        remapping
+    }
+
+    /// Iterate over the subtree rooted at `node` in pre-order: each node comes before its
+    /// descendants, and children are visited from first to last. Starts with `node` itself.
+    pub fn preorder(&self, node: NodeIndex) -> Preorder<'_, D> {
+        Preorder {
+            forest: self,
+            top: node,
+            next: Some(node),
+        }
+    }
+
+    /// Iterate over the subtree rooted at `node` in post-order: each node comes after its
+    /// descendants, and children are visited from first to last. Ends with `node` itself.
+    pub fn postorder(&self, node: NodeIndex) -> Postorder<'_, D> {
+        Postorder {
+            forest: self,
+            top: node,
+            next: Some(self.first_leaf(node)),
+        }
+    }
+
+    /// Iterate over the nodes with no children in the subtree rooted at `node`, from first to
+    /// last. If `node` has no children, that's just `node`.
+    pub fn leaves(&self, node: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.preorder(node)
+            .filter(|descendant| self.arena[*descendant].child.is_none())
+    }
+
+    /// Call `visitor` on each node in the subtree rooted at `node`, in pre-order, letting it
+    /// skip over the children of the node it was just given or stop early. Returns `false` if
+    /// `visitor` stopped it, and `true` if it visited the whole subtree.
+    pub fn visit(&self, node: NodeIndex, mut visitor: impl FnMut(NodeIndex) -> Visit) -> bool {
+        let mut next = Some(node);
+        while let Some(current) = next {
+            next = match visitor(current) {
+                Visit::Continue => self.next_preorder(node, current, true),
+                Visit::SkipChildren => self.next_preorder(node, current, false),
+                Visit::Stop => return false,
+            };
+        }
+        true
+    }
+
+    /// The node after `node` in a pre-order traversal of the subtree rooted at `top`, skipping
+    /// over `node`'s children unless `descend` is true.
+    fn next_preorder(&self, top: NodeIndex, node: NodeIndex, descend: bool) -> Option<NodeIndex> {
+        if descend {
+            if let Some(child) = self.arena[node].child {
+                return Some(child);
+            }
+        }
+        let mut ancestor = node;
+        while ancestor != top {
+            if let Some(sibling) = self.next_sibling(ancestor) {
+                return Some(sibling);
+            }
+            ancestor = self.arena[ancestor].parent.bug();
+        }
+        None
+    }
+
+    /// The first node without children at or under `node`.
+    fn first_leaf(&self, node: NodeIndex) -> NodeIndex {
+        let mut leaf = node;
+        while let Some(child) = self.arena[leaf].child {
+            leaf = child;
+        }
+        leaf
+    }
+
     fn overlaps(&self, node_1: NodeIndex, node_2: NodeIndex) -> bool {
         self.is_ancestor_of(node_1, node_2) || self.is_ancestor_of(node_2, node_1)
     }
@@ -377,6 +716,188 @@ impl<D: Debug> Forest<D> {
             .filter(|(_, node)| node.parent.is_none())
             .map(|(i, _)| i)
     }
+
+    fn check_valid(&self, node: NodeIndex) -> Result<(), ForestError> {
+        if self.is_valid(node) {
+            Ok(())
+        } else {
+            Err(ForestError::DeletedNode)
+        }
+    }
+
+    fn check_root(&self, node: NodeIndex) -> Result<(), ForestError> {
+        self.check_valid(node)?;
+        if self.arena[node].parent.is_some() {
+            Err(ForestError::NotRoot)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Like `parent()`, but fails if `node` was deleted.
+    pub fn try_parent(&self, node: NodeIndex) -> Result<Option<NodeIndex>, ForestError> {
+        self.check_valid(node)?;
+        Ok(self.parent(node))
+    }
+
+    /// Like `first_child()`, but fails if `node` was deleted.
+    pub fn try_first_child(&self, node: NodeIndex) -> Result<Option<NodeIndex>, ForestError> {
+        self.check_valid(node)?;
+        Ok(self.first_child(node))
+    }
+
+    /// Like `prev_sibling()`, but fails if `node` was deleted.
+    pub fn try_prev_sibling(&self, node: NodeIndex) -> Result<Option<NodeIndex>, ForestError> {
+        self.check_valid(node)?;
+        Ok(self.prev_sibling(node))
+    }
+
+    /// Like `next_sibling()`, but fails if `node` was deleted.
+    pub fn try_next_sibling(&self, node: NodeIndex) -> Result<Option<NodeIndex>, ForestError> {
+        self.check_valid(node)?;
+        Ok(self.next_sibling(node))
+    }
+
+    /// The children of `node`, in order. Fails if `node` was deleted.
+    pub fn try_children(&self, node: NodeIndex) -> Result<Vec<NodeIndex>, ForestError> {
+        let mut children = Vec::new();
+        let mut child = self.try_first_child(node)?;
+        while let Some(node) = child {
+            children.push(node);
+            child = self.next_sibling(node);
+        }
+        Ok(children)
+    }
+
+    /// Like `data()`, but fails if `node` was deleted.
+    pub fn try_data(&self, node: NodeIndex) -> Result<&D, ForestError> {
+        self.check_valid(node)?;
+        Ok(self.data(node))
+    }
+
+    /// Like `data_mut()`, but fails if `node` was deleted.
+    pub fn try_data_mut(&mut self, node: NodeIndex) -> Result<&mut D, ForestError> {
+        self.check_valid(node)?;
+        Ok(self.data_mut(node))
+    }
+
+    /// Like `delete_root()`, but fails instead of panicking if `root` isn't a root.
+    pub fn try_delete_root(&mut self, root: NodeIndex) -> Result<(), ForestError> {
+        self.check_root(root)?;
+        self.delete_root(root);
+        Ok(())
+    }
+
+    /// Like `detach()`, but fails if `node` was deleted.
+    pub fn try_detach(&mut self, node: NodeIndex) -> Result<(), ForestError> {
+        self.check_valid(node)?;
+        self.detach(node);
+        Ok(())
+    }
+
+    /// Like `swap()`, but fails if either node was deleted, or if they overlap.
+    pub fn try_swap(&mut self, node_1: NodeIndex, node_2: NodeIndex) -> Result<(), ForestError> {
+        self.check_valid(node_1)?;
+        self.check_valid(node_2)?;
+        if self.swap(node_1, node_2) {
+            Ok(())
+        } else {
+            Err(ForestError::Overlapping)
+        }
+    }
+
+    /// Like `insert_before()`, but says why it failed.
+    pub fn try_insert_before(&mut self, at: NodeIndex, node: NodeIndex) -> Result<(), ForestError> {
+        self.check_insertable(at, node)?;
+        if self.insert_before(at, node) {
+            Ok(())
+        } else {
+            Err(ForestError::IsRoot)
+        }
+    }
+
+    /// Like `insert_after()`, but says why it failed.
+    pub fn try_insert_after(&mut self, at: NodeIndex, node: NodeIndex) -> Result<(), ForestError> {
+        self.check_insertable(at, node)?;
+        if self.insert_after(at, node) {
+            Ok(())
+        } else {
+            Err(ForestError::IsRoot)
+        }
+    }
+
+    /// Like `insert_first_child()`, but says why it failed.
+    pub fn try_insert_first_child(
+        &mut self,
+        parent: NodeIndex,
+        node: NodeIndex,
+    ) -> Result<(), ForestError> {
+        self.check_insertable(parent, node)?;
+        bug_assert!(self.insert_first_child(parent, node));
+        Ok(())
+    }
+
+    /// Like `insert_last_child()`, but says why it failed.
+    pub fn try_insert_last_child(
+        &mut self,
+        parent: NodeIndex,
+        node: NodeIndex,
+    ) -> Result<(), ForestError> {
+        self.check_insertable(parent, node)?;
+        bug_assert!(self.insert_last_child(parent, node));
+        Ok(())
+    }
+
+    /// Check that the root `node` can be inserted somewhere in the tree containing `at`.
+    fn check_insertable(&self, at: NodeIndex, node: NodeIndex) -> Result<(), ForestError> {
+        self.check_valid(at)?;
+        self.check_root(node)?;
+        if self.root(at) == node {
+            Err(ForestError::Overlapping)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A pre-order iterator over a subtree. See [`Forest::preorder()`].
+pub struct Preorder<'f, D: Debug> {
+    forest: &'f Forest<D>,
+    top: NodeIndex,
+    next: Option<NodeIndex>,
+}
+
+impl<D: Debug> Iterator for Preorder<'_, D> {
+    type Item = NodeIndex;
+
+    fn next(&mut self) -> Option<NodeIndex> {
+        let node = self.next?;
+        self.next = self.forest.next_preorder(self.top, node, true);
+        Some(node)
+    }
+}
+
+/// A post-order iterator over a subtree. See [`Forest::postorder()`].
+pub struct Postorder<'f, D: Debug> {
+    forest: &'f Forest<D>,
+    top: NodeIndex,
+    next: Option<NodeIndex>,
+}
+
+impl<D: Debug> Iterator for Postorder<'_, D> {
+    type Item = NodeIndex;
+
+    fn next(&mut self) -> Option<NodeIndex> {
+        let node = self.next?;
+        self.next = if node == self.top {
+            None
+        } else if let Some(sibling) = self.forest.next_sibling(node) {
+            Some(self.forest.first_leaf(sibling))
+        } else {
+            self.forest.parent(node)
+        };
+        Some(node)
+    }
 }
 
 // NOTE: Never create two adjacent cracks. It'll be like that episode of The Good Place.
@@ -614,6 +1135,231 @@ mod forest_tests {
         parent
     }
 
+    #[cfg(feature = "serialization")]
+    #[test]
+    fn test_serialization() {
+        let mut forest: Forest<u32> = Forest::new(0);
+        let tree = make_mirror(&mut forest, 3, 1);
+        let leaf = forest.new_node(100);
+        let deleted = forest.new_node(200);
+        forest.delete_root(deleted);
+
+        let json = serde_json::to_string(&forest).unwrap();
+        let restored: Forest<u32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(verify_and_print(&restored), verify_and_print(&forest));
+        assert_eq!(restored.num_children(tree), 3);
+        assert_eq!(*restored.data(leaf), 100);
+        assert!(!restored.is_valid(deleted));
+    }
+
+    #[test]
+    fn test_try_api() {
+        let mut forest = Forest::new("");
+        let parent = make_sisters(&mut forest);
+        let elder_sister = forest.first_child(parent).unwrap();
+        let younger_sister = forest.next_sibling(elder_sister).unwrap();
+        let orphan = forest.new_node("orphan");
+
+        assert_eq!(forest.try_parent(elder_sister), Ok(Some(parent)));
+        assert_eq!(
+            forest.try_children(parent),
+            Ok(vec![elder_sister, younger_sister])
+        );
+        assert_eq!(
+            forest.try_insert_before(parent, orphan),
+            Err(ForestError::IsRoot)
+        );
+        assert_eq!(
+            forest.try_insert_last_child(parent, younger_sister),
+            Err(ForestError::NotRoot)
+        );
+        assert_eq!(
+            forest.try_insert_last_child(parent, parent),
+            Err(ForestError::Overlapping)
+        );
+        assert_eq!(
+            forest.try_delete_root(elder_sister),
+            Err(ForestError::NotRoot)
+        );
+        assert_eq!(forest.try_insert_after(younger_sister, orphan), Ok(()));
+        assert_eq!(
+            verify_and_print(&forest),
+            "(parent (elderSister) (youngerSister) (orphan))"
+        );
+
+        assert_eq!(forest.try_delete_root(parent), Ok(()));
+        assert_eq!(forest.try_parent(orphan), Err(ForestError::DeletedNode));
+        assert_eq!(forest.try_children(parent), Err(ForestError::DeletedNode));
+        assert_eq!(forest.try_data(elder_sister), Err(ForestError::DeletedNode));
+        assert_eq!(forest.try_detach(orphan), Err(ForestError::DeletedNode));
+        assert_eq!(
+            forest.try_delete_root(parent),
+            Err(ForestError::DeletedNode)
+        );
+        assert_eq!(verify_and_print(&forest), "");
+    }
+
+    #[test]
+    fn test_traversal() {
+        // 1(2, 3(4), 5(6, 7(8)))
+        let mut forest: Forest<u32> = Forest::new(0);
+        let tree = make_mirror(&mut forest, 3, 1);
+        let data = |nodes: &mut dyn Iterator<Item = NodeIndex>| {
+            nodes.map(|node| *forest.data(node)).collect::<Vec<_>>()
+        };
+        assert_eq!(data(&mut forest.preorder(tree)), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(data(&mut forest.postorder(tree)), [2, 4, 3, 6, 8, 7, 5, 1]);
+        assert_eq!(data(&mut forest.leaves(tree)), [2, 4, 6, 8]);
+
+        // Traversals of a subtree stay inside of it.
+        let five = forest.nth_child(tree, 2).unwrap();
+        assert_eq!(data(&mut forest.preorder(five)), [5, 6, 7, 8]);
+        assert_eq!(data(&mut forest.postorder(five)), [6, 8, 7, 5]);
+        assert_eq!(data(&mut forest.leaves(five)), [6, 8]);
+        let two = forest.first_child(tree).unwrap();
+        assert_eq!(data(&mut forest.preorder(two)), [2]);
+        assert_eq!(data(&mut forest.postorder(two)), [2]);
+        assert_eq!(data(&mut forest.leaves(two)), [2]);
+
+        let mut visited = Vec::new();
+        let finished = forest.visit(tree, |node| {
+            visited.push(*forest.data(node));
+            match *forest.data(node) {
+                3 => Visit::SkipChildren,
+                7 => Visit::Stop,
+                _ => Visit::Continue,
+            }
+        });
+        assert!(!finished);
+        assert_eq!(visited, [1, 2, 3, 5, 6, 7]);
+        assert!(forest.visit(five, |_| Visit::Continue));
+    }
+
+    #[test]
+    fn test_clone_subtree() {
+        // 1(2, 3(4), 5(6, 7(8)))
+        let mut forest: Forest<u32> = Forest::new(0);
+        let tree = make_mirror(&mut forest, 3, 1);
+        let five = forest.nth_child(tree, 2).unwrap();
+        let copy = forest.clone_subtree(five);
+        assert_eq!(forest.parent(copy), None);
+        assert_eq!(
+            verify_and_print(&forest),
+            "(1 (2) (3 (4)) (5 (6) (7 (8))))(5 (6) (7 (8)))"
+        );
+        *forest.data_mut(forest.first_child(copy).unwrap()) = 60;
+        assert_eq!(*forest.data(forest.first_child(five).unwrap()), 6);
+        forest.delete_root(copy);
+
+        // Deep enough that copying recursively would overflow the stack.
+        let depth = 100_000;
+        let chain = forest.new_node(0);
+        let mut bottom = chain;
+        for i in 1..depth {
+            let child = forest.new_node(i);
+            assert!(forest.insert_first_child(bottom, child));
+            bottom = child;
+        }
+        let copy = forest.clone_subtree(chain);
+        assert!(forest
+            .preorder(copy)
+            .map(|node| *forest.data(node))
+            .eq(0..depth));
+        forest.delete_root(chain);
+        forest.delete_root(copy);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut forest: Forest<u32> = Forest::with_capacity(0, 4);
+        assert!(forest.capacity() >= 4);
+        let tree = make_mirror(&mut forest, 4, 1);
+        let garbage = (0..100).map(|i| forest.new_node(i)).collect::<Vec<_>>();
+        for node in garbage {
+            forest.delete_root(node);
+        }
+        let leaf = forest.new_node(200);
+        let peak_capacity = forest.capacity();
+        let before = verify_and_print(&forest);
+
+        let stale_leaf = leaf;
+        let remapping = forest.compact();
+        assert_eq!(verify_and_print(&forest), before);
+        assert_eq!(forest.num_nodes(), 17);
+        assert!(forest.capacity() < peak_capacity);
+        assert_eq!(remapping.len(), 18); // including the swap_dummy
+        let tree = remapping[&tree];
+        let leaf = remapping[&leaf];
+        assert_eq!(*forest.data(tree), 1);
+        assert_eq!(forest.num_children(tree), 4);
+        assert_eq!(*forest.data(leaf), 200);
+        // Old indices can't be mistaken for the nodes that took their places.
+        assert!(!forest.is_valid(stale_leaf));
+        assert!(remapping.keys().all(|old| !forest.is_valid(*old)));
+
+        // The compacted forest is still usable.
+        let child = forest.first_child(tree).unwrap();
+        assert!(forest.swap(child, leaf));
+        assert_eq!(forest.parent(leaf), Some(tree));
+        verify_and_print(&forest);
+        forest.reserve(10);
+        assert!(forest.capacity() >= forest.num_nodes() + 10);
+        forest.delete_root(tree);
+        forest.delete_root(child);
+        assert_eq!(forest.num_nodes(), 0);
+    }
+
+    #[test]
+    fn test_insert_and_drain_children() {
+        let mut forest: Forest<u32> = Forest::new(0);
+        let parent = forest.new_node(0);
+        let new_nodes = |forest: &mut Forest<u32>, data: &[u32]| {
+            data.iter().map(|d| forest.new_node(*d)).collect::<Vec<_>>()
+        };
+
+        let nodes = new_nodes(&mut forest, &[2, 3]);
+        assert!(forest.insert_children(parent, 0, &nodes));
+        assert_eq!(verify_and_print(&forest), "(0 (2) (3))");
+        let nodes = new_nodes(&mut forest, &[5, 6]);
+        assert!(forest.insert_children(parent, 2, &nodes));
+        let nodes = new_nodes(&mut forest, &[1]);
+        assert!(forest.insert_children(parent, 0, &nodes));
+        let nodes = new_nodes(&mut forest, &[4]);
+        assert!(forest.insert_children(parent, 3, &nodes));
+        assert!(forest.insert_children(parent, 6, &[]));
+        assert_eq!(verify_and_print(&forest), "(0 (1) (2) (3) (4) (5) (6))");
+
+        // Invalid insertions do nothing
+        let extra = forest.new_node(7);
+        let child = forest.first_child(parent).unwrap();
+        assert!(!forest.insert_children(parent, 8, &[extra]));
+        assert!(!forest.insert_children(parent, 0, &[extra, extra]));
+        assert!(!forest.insert_children(parent, 0, &[extra, child]));
+        assert!(!forest.insert_children(child, 0, &[parent]));
+        assert!(forest.drain_children(parent, 5..7).is_none());
+        forest.delete_root(extra);
+
+        let middle = forest.drain_children(parent, 1..4).unwrap();
+        let middle = middle
+            .iter()
+            .map(|node| *forest.data(*node))
+            .collect::<Vec<_>>();
+        assert_eq!(middle, [2, 3, 4]);
+        let first = forest.drain_children(parent, 0..1).unwrap();
+        assert_eq!(forest.drain_children(parent, 1..1), Some(Vec::new()));
+        let rest = forest.drain_children(parent, 0..2).unwrap();
+        assert_eq!(forest.num_children(parent), 0);
+        assert!(forest.is_valid(first[0]));
+        assert!(forest.insert_children(parent, 0, &rest));
+        assert!(forest.insert_children(parent, 0, &first));
+        verify_and_print(&forest);
+        let data = forest
+            .preorder(parent)
+            .map(|node| *forest.data(node))
+            .collect::<Vec<_>>();
+        assert_eq!(data, [0, 1, 5, 6]);
+    }
+
     #[test]
     fn test_leaf() {
         let mut forest = Forest::new("");
@@ -0,0 +1,70 @@
+//! Grapheme cluster segmentation (UAX #29), so that the text cursor moves over, and deletes,
+//! whole user-perceived characters: a letter with its accents, an emoji with its modifiers, a
+//! flag, or an Indic syllable with its vowel signs.
+//!
+//! The segmentation itself is done by `unicode-segmentation`. The text is always given in full,
+//! so its cursors never need more context, and can't fail.
+
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+use unicode_width::UnicodeWidthChar;
+
+const EMOJI_PRESENTATION_SELECTOR: char = '\u{FE0F}';
+
+/// Whether there's a grapheme cluster boundary at the given byte index into `text`. The start and
+/// end of the text are always boundaries.
+pub fn is_boundary(text: &str, byte_index: usize) -> bool {
+    GraphemeCursor::new(byte_index, text.len(), true)
+        .is_boundary(text, 0)
+        .unwrap_or(true)
+}
+
+/// The byte index of the grapheme cluster boundary before the given byte index into `text`, or 0
+/// if there isn't one.
+///
+/// This steps back a char at a time with `is_boundary`, instead of using
+/// `GraphemeCursor::prev_boundary`, which skips over the start of Indic conjuncts.
+pub fn prev_boundary(text: &str, byte_index: usize) -> usize {
+    text[..byte_index]
+        .char_indices()
+        .rev()
+        .map(|(i, _)| i)
+        .find(|i| is_boundary(text, *i))
+        .unwrap_or(0)
+}
+
+/// The byte index of the grapheme cluster boundary after the given byte index into `text`, or the
+/// length of the text if there isn't one.
+pub fn next_boundary(text: &str, byte_index: usize) -> usize {
+    GraphemeCursor::new(byte_index, text.len(), true)
+        .next_boundary(text, 0)
+        .ok()
+        .flatten()
+        .unwrap_or(text.len())
+}
+
+/// How many terminal columns `text` takes up, going by grapheme cluster.
+pub fn display_width(text: &str) -> usize {
+    text.graphemes(true).map(width).sum()
+}
+
+/// How many terminal columns a grapheme cluster takes up: as many as its widest character, except
+/// that emoji are always two columns wide.
+fn width(cluster: &str) -> usize {
+    let is_emoji = cluster
+        .chars()
+        .any(|ch| ch == EMOJI_PRESENTATION_SELECTOR || is_regional_indicator(ch));
+    if is_emoji {
+        2
+    } else {
+        cluster
+            .chars()
+            .map(|ch| ch.width().unwrap_or(0))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Half of a flag emoji.
+fn is_regional_indicator(ch: char) -> bool {
+    matches!(ch as u32, 0x1F1E6..=0x1F1FF)
+}
@@ -1,19 +1,29 @@
-use super::node::Node;
-use crate::language::{Arity, Storage};
+use super::node::{Node, NodeRemapping, TreeError, WeakNode};
+use crate::language::{Arity, Sort, Storage};
 use crate::util::{bug, SynlessBug};
 use partial_pretty_printer as ppp;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::str::FromStr;
 
-// The node in this LocationInner may not be valid (may have been deleted!)
+/// A location saved to return to later. Its node may have been deleted since, so it has to be
+/// checked with [`Location::validate_bookmark`] before it can be used.
 #[derive(Debug, Clone, Copy)]
-pub struct Bookmark(LocationInner);
+pub struct Bookmark(BookmarkInner);
 
-/// A location between nodes, or within text, where a cursor could go.
+/// Like [`LocationInner`], but with weak references to the nodes.
 #[derive(Debug, Clone, Copy)]
+enum BookmarkInner {
+    InText(WeakNode, usize),
+    AtNode(WeakNode),
+    BelowNode(WeakNode),
+}
+
+/// A location between nodes, or within text, where a cursor could go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Location(LocationInner);
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum LocationInner {
     /// The usize is an index between chars (so it can be equal to the len)
     InText(Node, usize),
@@ -23,6 +33,15 @@ enum LocationInner {
 }
 use LocationInner::{AtNode, BelowNode, InText};
 
+/// A location described by the child indices leading from the root to its node, so that it can be
+/// saved to disk and found again in an identical tree. See [`Location::save`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SavedLocation {
+    InText(Vec<usize>, usize),
+    AtNode(Vec<usize>),
+    BelowNode(Vec<usize>),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Mode {
     Tree,
@@ -81,6 +100,32 @@ impl Location {
         Some(Location(InText(node, text_len)))
     }
 
+    /// Find a location saved with [`Location::save`] in the tree rooted at `root`. Returns `None`
+    /// if the tree doesn't have that location.
+    pub fn restore(s: &Storage, root: Node, saved: &SavedLocation) -> Option<Location> {
+        let node_at_path = |path: &[usize]| {
+            path.iter()
+                .try_fold(root, |node, index| node.nth_child(s, *index))
+        };
+        match saved {
+            SavedLocation::InText(path, char_pos) => {
+                let node = node_at_path(path)?;
+                if *char_pos > node.text(s)?.num_chars() {
+                    return None;
+                }
+                Some(Location(InText(node, *char_pos)))
+            }
+            SavedLocation::AtNode(path) => Some(Location(AtNode(node_at_path(path)?))),
+            SavedLocation::BelowNode(path) => {
+                let node = node_at_path(path)?;
+                match node.arity(s) {
+                    Arity::Listy(_) => Some(Location(BelowNode(node))),
+                    Arity::Texty | Arity::Fixed(_) => None,
+                }
+            }
+        }
+    }
+
     /// Where to move the cursor after inserting this node.
     pub fn first_insert_loc(s: &Storage, node: Node) -> Location {
         match node.arity(s) {
@@ -137,7 +182,11 @@ impl Location {
                 }
             }
             AtNode(node) => (node, ppp::FocusTarget::Start),
-            InText(node, char_pos) => (node, ppp::FocusTarget::Text(char_pos)),
+            InText(node, char_pos) => {
+                // The text is displayed in visual order, which differs for right-to-left text.
+                let display_pos = node.text(s).bug().display_index(char_pos);
+                (node, ppp::FocusTarget::Text(display_pos))
+            }
         };
         while let Some(parent) = node.parent(s) {
             path_to_root.push(node.sibling_index(s));
@@ -151,6 +200,24 @@ impl Location {
         (path_from_root, target)
     }
 
+    /// Describe this location in a form that can be saved to disk.
+    pub fn save(self, s: &Storage) -> SavedLocation {
+        let path_to = |mut node: Node| {
+            let mut path = Vec::new();
+            while let Some(parent) = node.parent(s) {
+                path.push(node.sibling_index(s));
+                node = parent;
+            }
+            path.reverse();
+            path
+        };
+        match self.0 {
+            InText(node, char_pos) => SavedLocation::InText(path_to(node), char_pos),
+            AtNode(node) => SavedLocation::AtNode(path_to(node)),
+            BelowNode(node) => SavedLocation::BelowNode(path_to(node)),
+        }
+    }
+
     /// Return the _one-indexed_ sibling index and number of siblings of this location. Being in
     /// text is treated the same as being at the texty node.
     pub fn sibling_index_info(self, s: &Storage) -> (usize, usize) {
@@ -297,6 +364,36 @@ impl Location {
         None
     }
 
+    /// If this location is at a cell in a table (a fixed node in a listy sequence of fixed
+    /// nodes), the location of the cell in the same column of the previous row. Rows of a
+    /// different construct are skipped.
+    pub fn cell_above(self, s: &Storage) -> Option<Location> {
+        self.adjacent_cell(s, false)
+    }
+
+    /// If this location is at a cell in a table (a fixed node in a listy sequence of fixed
+    /// nodes), the location of the cell in the same column of the next row. Rows of a different
+    /// construct are skipped.
+    pub fn cell_below(self, s: &Storage) -> Option<Location> {
+        self.adjacent_cell(s, true)
+    }
+
+    fn adjacent_cell(self, s: &Storage, forward: bool) -> Option<Location> {
+        let (cell, row, _table) = self.table_cell(s)?;
+        let index = cell.sibling_index(s);
+        let mut other_row = row;
+        loop {
+            other_row = if forward {
+                other_row.next_sibling(s)?
+            } else {
+                other_row.prev_sibling(s)?
+            };
+            if other_row.construct(s) == row.construct(s) {
+                return Some(Location(AtNode(other_row.nth_child(s, index).bug())));
+            }
+        }
+    }
+
     /// Get the location at this node's parent.
     pub fn parent(self, s: &Storage) -> Option<Location> {
         let parent_node = self.parent_node(s)?;
@@ -345,6 +442,55 @@ impl Location {
         self.0.reference_node().root(s)
     }
 
+    /// If this location is at a cell in a table (a fixed node in a listy sequence of fixed
+    /// nodes), returns every cell in the same column, from each row that has the same construct
+    /// as this location's row. Includes the cell at this location.
+    pub fn column_cells(self, s: &Storage) -> Option<Vec<Node>> {
+        let (cell, row, table) = self.table_cell(s)?;
+        let index = cell.sibling_index(s);
+        let mut cells = Vec::new();
+        let mut opt_row = table.first_child(s);
+        while let Some(other_row) = opt_row {
+            if other_row.construct(s) == row.construct(s) {
+                cells.push(other_row.nth_child(s, index).bug());
+            }
+            opt_row = other_row.next_sibling(s);
+        }
+        Some(cells)
+    }
+
+    /// The contiguous range of siblings from `anchor` to the node at this location, inclusive and
+    /// in order. Returns `None` if this location isn't at a node, or `anchor` isn't one of its
+    /// siblings.
+    pub fn selection(self, s: &Storage, anchor: Node) -> Option<Vec<Node>> {
+        let node = self.at_node(s)?;
+        if !anchor.is_valid(s) || anchor.parent(s)? != node.parent(s)? {
+            return None;
+        }
+        let (mut first, last) = if anchor.sibling_index(s) <= node.sibling_index(s) {
+            (anchor, node)
+        } else {
+            (node, anchor)
+        };
+        let mut nodes = vec![first];
+        while first != last {
+            first = first.next_sibling(s).bug();
+            nodes.push(first);
+        }
+        Some(nodes)
+    }
+
+    /// Returns `(cell, row, table)`, if this location is at a cell in a table.
+    fn table_cell(self, s: &Storage) -> Option<(Node, Node, Node)> {
+        let cell = self.at_node(s)?;
+        let row = cell.parent(s)?;
+        let table = row.parent(s)?;
+        match (row.arity(s), table.arity(s)) {
+            (Arity::Fixed(_), Arity::Listy(_)) => Some((cell, row, table)),
+            _ => None,
+        }
+    }
+
     /************
      * Mutation *
      ************/
@@ -353,43 +499,73 @@ impl Location {
     /// `Ok(None)`. In a fixed sequence, replaces the node at this location with `new_node` and
     /// returns `Ok(Some(old_node))`. Either way, moves `self` to the new node.
     ///
-    /// If we cannot insert, returns `Err(())` and does not modify `self`. This can happen for any
+    /// If we cannot insert, returns an error and does not modify `self`. This can happen for any
     /// of the following reasons:
     ///
     /// - This location is in text.
     /// - This location is at the root.
-    /// - The new node does not match the required sort.
-    #[allow(clippy::result_unit_err)]
-    pub fn insert(&mut self, s: &mut Storage, new_node: Node) -> Result<Option<Node>, ()> {
-        let parent = self.parent_node(s).ok_or(())?;
+    /// - The new node is not a root, or does not match the required sort.
+    pub fn insert(&mut self, s: &mut Storage, new_node: Node) -> Result<Option<Node>, TreeError> {
+        let parent = self.parent_node(s).ok_or(TreeError::InvalidLocation)?;
 
         match parent.arity(s) {
             Arity::Texty => bug!("insert: texty parent"),
             Arity::Fixed(_) => {
-                let old_node = self.at_node(s).ok_or(())?;
-                if new_node.swap(s, old_node) {
-                    *self = Location(AtNode(new_node));
-                    Ok(Some(old_node))
-                } else {
-                    Err(())
-                }
+                let old_node = self.at_node(s).ok_or(TreeError::InvalidLocation)?;
+                new_node.check_root(s)?;
+                new_node.swap(s, old_node)?;
+                *self = Location(AtNode(new_node));
+                Ok(Some(old_node))
             }
             Arity::Listy(_) => {
-                let success = match self.0 {
+                match self.0 {
                     InText(_, _) => bug!("insert: bug in textiness check"),
-                    AtNode(node) => node.insert_after(s, new_node),
-                    BelowNode(_) => parent.insert_first_child(s, new_node),
+                    AtNode(node) => node.insert_after(s, new_node)?,
+                    BelowNode(_) => parent.insert_first_child(s, new_node)?,
                 };
-                if success {
-                    *self = Location(AtNode(new_node));
-                    Ok(None)
-                } else {
-                    Err(())
-                }
+                *self = Location(AtNode(new_node));
+                Ok(None)
             }
         }
     }
 
+    /// In a listy sequence, inserts `new_nodes` after this location, in order, and moves `self`
+    /// to the last of them. Fails and does nothing if this location isn't in a listy sequence,
+    /// if there are no `new_nodes`, or if any of them can't be inserted here.
+    pub fn insert_many(&mut self, s: &mut Storage, new_nodes: &[Node]) -> Result<(), TreeError> {
+        let parent = self.parent_node(s).ok_or(TreeError::InvalidLocation)?;
+        let last = *new_nodes.last().ok_or(TreeError::InvalidLocation)?;
+        let index = match self.0 {
+            InText(_, _) => return Err(TreeError::InvalidLocation),
+            AtNode(node) => node.sibling_index(s) + 1,
+            BelowNode(_) => 0,
+        };
+        parent.insert_children(s, index, new_nodes)?;
+        *self = Location(AtNode(last));
+        Ok(())
+    }
+
+    /// Whether `insert`ing `new_node` at this location would be allowed by the grammar.
+    pub fn can_insert(self, s: &Storage, new_node: Node) -> bool {
+        match self.insertion_sort(s) {
+            Some(sort) => sort.accepts(s, new_node.construct(s)),
+            None => false,
+        }
+    }
+
+    /// The sort that a node must have to be `insert`ed at this location, or `None` if nothing can
+    /// be inserted here.
+    pub fn insertion_sort(self, s: &Storage) -> Option<Sort> {
+        if self.mode() != Mode::Tree {
+            return None;
+        }
+        match self.parent_node(s)?.arity(s) {
+            Arity::Texty => bug!("insertion_sort: texty parent"),
+            Arity::Fixed(sorts) => Some(sorts.get(s, self.at_node(s)?.sibling_index(s)).bug()),
+            Arity::Listy(sort) => Some(sort),
+        }
+    }
+
     /// Deletes the node at the cursor. If in a listy sequence, attempts to move the cursor left or
     /// right. Returns the node that was deleted and the location where the undo command should be
     /// executed from.
@@ -401,17 +577,18 @@ impl Location {
             Arity::Texty => bug!("texty parent"),
             Arity::Fixed(_) => {
                 let hole = Node::new_hole(s, node.language(s));
-                if node.swap(s, hole) {
+                if node.swap(s, hole).is_ok() {
                     *self = Location(AtNode(hole));
                     Some((node, *self))
                 } else {
+                    hole.delete_root(s).bug();
                     None
                 }
             }
             Arity::Listy(_) => {
                 let prev_loc = self.prev_sibling(s).bug();
                 let opt_next_loc = self.next_sibling(s);
-                if node.detach(s) {
+                if node.detach(s).is_ok() {
                     *self = if move_left {
                         prev_loc
                     } else {
@@ -425,13 +602,165 @@ impl Location {
         }
     }
 
+    /// In a listy sequence, deletes `count` nodes starting with the one at this location and
+    /// going right, and moves `self` to just before where they were. Returns the deleted nodes,
+    /// and the location to insert them at to undo the deletion. Returns `None` and does nothing
+    /// if this location isn't at a node in a listy sequence, or if there aren't `count` nodes
+    /// (and at least one) to delete.
+    pub fn delete_many(&mut self, s: &mut Storage, count: usize) -> Option<(Vec<Node>, Location)> {
+        let node = self.at_node(s)?;
+        let parent = node.parent(s)?;
+        if count == 0 || !matches!(parent.arity(s), Arity::Listy(_)) {
+            return None;
+        }
+        let prev_loc = self.prev_sibling(s).bug();
+        let start = node.sibling_index(s);
+        let nodes = parent.drain_children(s, start..start + count).ok()?;
+        *self = prev_loc;
+        Some((nodes, prev_loc))
+    }
+
+    /// Replaces the node at this location with its only child, and moves `self` to that child.
+    /// For a fixed node, "only child" means its only child that isn't a hole. Returns the
+    /// replaced node, which is left with no children (if listy) or a hole in place of the child
+    /// (if fixed), together with the index the child had in it. Fails and does nothing if this
+    /// location isn't at a node, if that node does not have exactly one such child, or if that
+    /// child is not allowed in its parent's place.
+    pub fn unwrap_node(&mut self, s: &mut Storage) -> Result<(Node, usize), TreeError> {
+        let node = self.at_node(s).ok_or(TreeError::InvalidLocation)?;
+        node.parent(s).ok_or(TreeError::IsRoot)?;
+        let not_one_child = || TreeError::NotOneChild(node.construct(s).name(s).to_owned());
+        let (index, child) = match node.arity(s) {
+            Arity::Texty => return Err(not_one_child()),
+            Arity::Fixed(sorts) => {
+                let mut filled = (0..sorts.len(s))
+                    .map(|i| (i, node.nth_child(s, i).bug()))
+                    .filter(|(_, child)| !child.is_hole(s));
+                match (filled.next(), filled.next()) {
+                    (Some(only_child), None) => only_child,
+                    _ => return Err(not_one_child()),
+                }
+            }
+            Arity::Listy(_) => {
+                if node.num_children(s) != Some(1) {
+                    return Err(not_one_child());
+                }
+                (0, node.first_child(s).bug())
+            }
+        };
+
+        let opt_hole = match node.arity(s) {
+            Arity::Texty => bug!("unwrap_node: texty node with a child"),
+            Arity::Fixed(_) => {
+                let hole = Node::new_hole(s, node.language(s));
+                child
+                    .swap(s, hole)
+                    .bug_msg("unwrap_node: failed to swap in hole");
+                Some(hole)
+            }
+            Arity::Listy(_) => {
+                child
+                    .detach(s)
+                    .bug_msg("unwrap_node: failed to detach child");
+                None
+            }
+        };
+
+        if let Err(err) = node.swap(s, child) {
+            // Put everything back the way it was
+            if let Some(hole) = opt_hole {
+                hole.swap(s, child)
+                    .bug_msg("unwrap_node: failed to restore child");
+                hole.delete_root(s).bug();
+            } else {
+                node.insert_last_child(s, child)
+                    .bug_msg("unwrap_node: failed to restore child");
+            }
+            return Err(err);
+        }
+        *self = Location(AtNode(child));
+        Ok((node, index))
+    }
+
+    /// Replaces the node at this location with `wrapper`, places the replaced node inside of
+    /// `wrapper`, and moves `self` to `wrapper`. `wrapper` must be a root that is either fixed
+    /// with a hole that the replaced node is allowed in (the first such hole is used), or listy
+    /// with no children. Fails and does nothing if this is not possible.
+    pub fn wrap_node(&mut self, s: &mut Storage, wrapper: Node) -> Result<(), TreeError> {
+        let node = self.at_node(s).ok_or(TreeError::InvalidLocation)?;
+        let index = match wrapper.arity(s) {
+            Arity::Fixed(sorts) => (0..sorts.len(s))
+                .find(|i| {
+                    wrapper
+                        .nth_child(s, *i)
+                        .is_some_and(|child| child.is_hole(s))
+                        && sorts.get(s, *i).bug().accepts(s, node.construct(s))
+                })
+                .ok_or_else(|| {
+                    TreeError::InvalidWrapper(wrapper.construct(s).name(s).to_owned())
+                })?,
+            Arity::Texty | Arity::Listy(_) => 0,
+        };
+        self.wrap_node_at(s, wrapper, index)
+    }
+
+    /// Like `wrap_node`, but places the replaced node at `index` in `wrapper`. If `wrapper` is
+    /// fixed, its child at `index` must be a hole; if it's listy, `index` must be 0. This is the
+    /// inverse of `unwrap_node`.
+    pub fn wrap_node_at(
+        &mut self,
+        s: &mut Storage,
+        wrapper: Node,
+        index: usize,
+    ) -> Result<(), TreeError> {
+        let node = self.at_node(s).ok_or(TreeError::InvalidLocation)?;
+        wrapper.check_root(s)?;
+        let invalid_wrapper = TreeError::InvalidWrapper(wrapper.construct(s).name(s).to_owned());
+
+        match wrapper.arity(s) {
+            Arity::Texty => return Err(invalid_wrapper),
+            Arity::Fixed(_) => {
+                let hole = match wrapper.nth_child(s, index) {
+                    Some(hole) if hole.is_hole(s) => hole,
+                    _ => return Err(invalid_wrapper),
+                };
+                node.swap(s, wrapper)?;
+                if let Err(err) = hole.swap(s, node) {
+                    wrapper
+                        .swap(s, node)
+                        .bug_msg("wrap_node: failed to restore node");
+                    return Err(err);
+                }
+                hole.delete_root(s).bug();
+            }
+            Arity::Listy(_) => {
+                if wrapper.num_children(s) != Some(0) || index != 0 {
+                    return Err(invalid_wrapper);
+                }
+                node.swap(s, wrapper)?;
+                if let Err(err) = wrapper.insert_last_child(s, node) {
+                    wrapper
+                        .swap(s, node)
+                        .bug_msg("wrap_node: failed to restore node");
+                    return Err(err);
+                }
+            }
+        }
+        *self = Location(AtNode(wrapper));
+        Ok(())
+    }
+
     /*************
      * Bookmarks *
      *************/
 
     /// Save a bookmark to return to later.
     pub fn bookmark(self) -> Bookmark {
-        Bookmark(self.0)
+        Bookmark(match self.0 {
+            InText(node, i) => BookmarkInner::InText(node.downgrade(), i),
+            AtNode(node) => BookmarkInner::AtNode(node.downgrade()),
+            BelowNode(node) => BookmarkInner::BelowNode(node.downgrade()),
+        })
     }
 
     /// Get the location of a previously saved bookmark, as long as that
@@ -441,13 +770,39 @@ impl Location {
     /// has since been deleted, or if it is currently located in a
     /// different tree.
     pub fn validate_bookmark(self, s: &Storage, mark: Bookmark) -> Option<Location> {
-        let mark_node = mark.0.reference_node();
-        if mark_node.is_valid(s) && mark_node.root(s) == self.root_node(s) {
-            Some(Location(mark.0.normalize(s)))
+        let inner = match mark.0 {
+            BookmarkInner::InText(node, i) => InText(node.upgrade(s)?, i),
+            BookmarkInner::AtNode(node) => AtNode(node.upgrade(s)?),
+            BookmarkInner::BelowNode(node) => BelowNode(node.upgrade(s)?),
+        };
+        if inner.reference_node().root(s) == self.root_node(s) {
+            Some(Location(inner.normalize(s)))
         } else {
             None
         }
     }
+
+    /// The same location after the nodes were compacted, or `None` if its node had already been
+    /// deleted. See `Storage::compact_nodes()`.
+    pub fn remap(self, remapping: &NodeRemapping) -> Option<Location> {
+        Some(Location(match self.0 {
+            InText(node, i) => InText(remapping.get(node)?, i),
+            AtNode(node) => AtNode(remapping.get(node)?),
+            BelowNode(node) => BelowNode(remapping.get(node)?),
+        }))
+    }
+}
+
+impl Bookmark {
+    /// The same bookmark after the nodes were compacted, or `None` if its node had already been
+    /// deleted. See `Storage::compact_nodes()`.
+    pub fn remap(self, remapping: &NodeRemapping) -> Option<Bookmark> {
+        Some(Bookmark(match self.0 {
+            BookmarkInner::InText(node, i) => BookmarkInner::InText(remapping.get_weak(node)?, i),
+            BookmarkInner::AtNode(node) => BookmarkInner::AtNode(remapping.get_weak(node)?),
+            BookmarkInner::BelowNode(node) => BookmarkInner::BelowNode(remapping.get_weak(node)?),
+        }))
+    }
 }
 
 impl LocationInner {
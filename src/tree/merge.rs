@@ -0,0 +1,184 @@
+use super::node::{matching_children, Node, NodeId, NodeRemapping};
+use crate::language::{Arity, Storage};
+use crate::util::SynlessBug;
+use std::collections::HashMap;
+
+/// A place where `ours` and `theirs` both changed the base tree, in different ways. See
+/// [`merge`].
+#[derive(Debug, Clone, Copy)]
+pub struct MergeConflict {
+    /// The conflicting node in the merged tree. It starts out as a copy of `ours`.
+    pub node: NodeId,
+    /// A copy of our version of the node, as a root. Owned by the conflict.
+    pub ours: Node,
+    /// A copy of their version of the node, as a root. Owned by the conflict.
+    pub theirs: Node,
+}
+
+impl MergeConflict {
+    /// Delete the copies of both versions.
+    pub fn delete(self, s: &mut Storage) {
+        self.ours.delete_root(s).bug();
+        self.theirs.delete_root(s).bug();
+    }
+
+    /// Update the copies of both versions after the nodes were compacted. See
+    /// `Storage::compact_nodes()`.
+    pub fn remap(&mut self, remapping: &NodeRemapping) {
+        self.ours = remapping.get(self.ours).bug();
+        self.theirs = remapping.get(self.theirs).bug();
+    }
+}
+
+/// Merge two trees, `ours` and `theirs`, that were both edited starting from `base`. Returns the
+/// merged tree as a new root, together with the conflicts in it. None of the given trees are
+/// modified.
+///
+/// Subtrees that only one side changed take that side's version. The children of a list are
+/// matched up with the base's children, and edits to different parts of the list are combined; if
+/// both sides inserted children at the same place, ours go first. When both sides changed the same
+/// subtree (and it isn't a list whose edits can be combined), it's a conflict, and the merged tree
+/// gets our version.
+pub fn merge(s: &mut Storage, base: Node, ours: Node, theirs: Node) -> (Node, Vec<MergeConflict>) {
+    let mut conflicts = Vec::new();
+    let merged = merge_node(s, base, ours, theirs, &mut conflicts);
+    (merged, conflicts)
+}
+
+fn merge_node(
+    s: &mut Storage,
+    base: Node,
+    ours: Node,
+    theirs: Node,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Node {
+    if ours.equals(s, base) || ours.equals(s, theirs) {
+        return theirs.deep_copy(s);
+    }
+    if theirs.equals(s, base) {
+        return ours.deep_copy(s);
+    }
+    let construct = base.construct(s);
+    if ours.construct(s) != construct || theirs.construct(s) != construct {
+        return conflict(s, ours, theirs, conflicts);
+    }
+    let children = match base.arity(s) {
+        Arity::Texty => return conflict(s, ours, theirs, conflicts),
+        Arity::Fixed(_) => {
+            let triples = base
+                .children(s)
+                .into_iter()
+                .zip(ours.children(s))
+                .zip(theirs.children(s))
+                .collect::<Vec<_>>();
+            triples
+                .into_iter()
+                .map(|((base, ours), theirs)| merge_node(s, base, ours, theirs, conflicts))
+                .collect::<Vec<_>>()
+        }
+        Arity::Listy(_) => match merge_children(s, base, ours, theirs, conflicts) {
+            Some(children) => children,
+            None => return conflict(s, ours, theirs, conflicts),
+        },
+    };
+    // Every child is a copy of a child at the same position of the same construct, in `ours` or
+    // `theirs`, so the grammar allows it.
+    Node::with_children(s, construct, children).bug()
+}
+
+fn conflict(s: &mut Storage, ours: Node, theirs: Node, conflicts: &mut Vec<MergeConflict>) -> Node {
+    let node = ours.deep_copy(s);
+    conflicts.push(MergeConflict {
+        node: node.id(s),
+        ours: ours.deep_copy(s),
+        theirs: theirs.deep_copy(s),
+    });
+    node
+}
+
+/// Merge the children of three versions of a list. Returns `None`, having cleaned up after
+/// itself, if the edits can't be combined.
+fn merge_children(
+    s: &mut Storage,
+    base: Node,
+    ours: Node,
+    theirs: Node,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Option<Vec<Node>> {
+    let base = base.children(s);
+    let ours = ours.children(s);
+    let theirs = theirs.children(s);
+
+    // The base children that neither side touched split the lists into ranges that can be merged
+    // independently. The last range runs to the end.
+    let ours_by_base = matching_children(s, &ours, &base)
+        .into_iter()
+        .map(|(i, j)| (j, i))
+        .collect::<HashMap<_, _>>();
+    let mut anchors = matching_children(s, &theirs, &base)
+        .into_iter()
+        .filter_map(|(k, j)| ours_by_base.get(&j).map(|i| (j, *i, k)))
+        .collect::<Vec<_>>();
+    anchors.push((base.len(), ours.len(), theirs.len()));
+
+    let num_conflicts = conflicts.len();
+    let mut merged = Vec::new();
+    let (mut j, mut i, mut k) = (0, 0, 0);
+    for (anchor_j, anchor_i, anchor_k) in anchors {
+        let merged_range = merge_range(
+            s,
+            &base[j..anchor_j],
+            &ours[i..anchor_i],
+            &theirs[k..anchor_k],
+            &mut merged,
+            conflicts,
+        );
+        if !merged_range {
+            for node in merged {
+                node.delete_root(s).bug();
+            }
+            for conflict in conflicts.drain(num_conflicts..) {
+                conflict.delete(s);
+            }
+            return None;
+        }
+        if let Some(anchor) = base.get(anchor_j) {
+            merged.push(anchor.deep_copy(s));
+        }
+        (j, i, k) = (anchor_j + 1, anchor_i + 1, anchor_k + 1);
+    }
+    Some(merged)
+}
+
+/// Merge corresponding ranges of children, pushing the result onto `merged`. Returns false if the
+/// ranges can't be merged.
+fn merge_range(
+    s: &mut Storage,
+    base: &[Node],
+    ours: &[Node],
+    theirs: &[Node],
+    merged: &mut Vec<Node>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> bool {
+    let equals = |s: &Storage, a: &[Node], b: &[Node]| {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.equals(s, *b))
+    };
+    let copies: Vec<Node> = if equals(s, ours, base) || equals(s, ours, theirs) {
+        theirs.iter().map(|node| node.deep_copy(s)).collect()
+    } else if equals(s, theirs, base) {
+        ours.iter().map(|node| node.deep_copy(s)).collect()
+    } else if base.is_empty() {
+        ours.iter()
+            .chain(theirs)
+            .map(|node| node.deep_copy(s))
+            .collect()
+    } else if ours.len() == base.len() && theirs.len() == base.len() {
+        (0..base.len())
+            .map(|n| merge_node(s, base[n], ours[n], theirs[n], conflicts))
+            .collect()
+    } else {
+        return false;
+    };
+    merged.extend(copies);
+    true
+}
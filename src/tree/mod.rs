@@ -1,8 +1,13 @@
+mod bidi;
 mod forest;
+mod grapheme;
 mod location;
+mod merge;
 mod node;
 mod text;
 
-pub use location::{Bookmark, Location, Mode};
+pub use forest::Visit;
+pub use location::{Bookmark, Location, Mode, SavedLocation};
+pub use merge::{merge, MergeConflict};
 pub(crate) use node::NodeForest;
-pub use node::{Node, NodeId};
+pub use node::{DiffOp, Node, NodeChange, NodeId, NodeRemapping, SavedNode, TreeError, WeakNode};
@@ -1,12 +1,115 @@
-use super::forest;
+use super::forest::{self, ForestError, Visit};
 use super::text::Text;
 use crate::language::{Arity, Construct, Language, Storage};
-use crate::util::{bug, bug_assert, SynlessBug};
+use crate::util::{bug, bug_assert, error, SynlessBug, SynlessError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt;
+use std::ops::Range;
+
+/// The longest a node summary can be, in chars.
+const SUMMARY_MAX_CHARS: usize = 40;
+/// How deeply to follow summary templates into a node's descendants.
+const SUMMARY_MAX_DEPTH: usize = 3;
+
+/// Why a tree operation couldn't be performed. These are all things a caller can cause by
+/// misusing the API (like holding on to a deleted node); internal invariant violations are bugs
+/// instead.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum TreeError {
+    #[error("Node has been deleted")]
+    DeletedNode,
+    #[error("Node must be a root, but it has a parent")]
+    NotRoot,
+    #[error("Node is a root, so it has no parent")]
+    IsRoot,
+    #[error("Can't do that at this location")]
+    InvalidLocation,
+    #[error("Can't place a node inside of itself")]
+    Overlapping,
+    #[error("'{0}' is not allowed here")]
+    NotAllowedHere(String),
+    #[error("'{0}' is not listy, so children can't be added to or removed from it")]
+    NotListy(String),
+    #[error("'{0}' is not texty")]
+    NotTexty(String),
+    #[error("'{0}' is texty, so it can't have children")]
+    Texty(String),
+    #[error("'{0}' has {1} children, but was given {2}")]
+    WrongNumberOfChildren(String, usize, usize),
+    #[error("'{0}' can only be unwrapped if it has exactly one child (not counting holes)")]
+    NotOneChild(String),
+    #[error(
+        "'{0}' can't wrap this node: it must be fixed with a hole that fits it, or listy and empty"
+    )]
+    InvalidWrapper(String),
+    #[error("The nodes aren't a range of siblings")]
+    NotSiblings,
+    #[error("The same node can't be inserted twice")]
+    DuplicateNode,
+    #[error("No construct named '{0}'")]
+    UnknownConstruct(String),
+    #[error("Invalid path '{0}': it must start with 'root'")]
+    InvalidPath(String),
+    #[error("No node at '{1}' in path '{0}'")]
+    NoNodeAtPath(String, String),
+}
+
+/// A tree of nodes, described by construct name so that it can be saved to disk and rebuilt
+/// later. See [`Node::save`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedNode {
+    construct: String,
+    /// `Some` iff the node is texty.
+    text: Option<String>,
+    children: Vec<SavedNode>,
+}
+
+impl From<ForestError> for TreeError {
+    fn from(error: ForestError) -> TreeError {
+        match error {
+            ForestError::DeletedNode => TreeError::DeletedNode,
+            ForestError::NotRoot => TreeError::NotRoot,
+            ForestError::IsRoot => TreeError::IsRoot,
+            ForestError::Overlapping => TreeError::Overlapping,
+        }
+    }
+}
+
+impl From<TreeError> for SynlessError {
+    fn from(error: TreeError) -> SynlessError {
+        error!(Edit, "{}", error)
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct NodeId(usize);
 
+/// How a node differs from another version of its tree. See [`Node::changes_since`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeChange {
+    /// There's no corresponding node in the older tree.
+    Added,
+    /// The corresponding node in the other tree has a different construct or text.
+    Modified,
+    /// There's no corresponding node in the newer tree. Only used when marking the older side of
+    /// a diff.
+    Deleted,
+    /// A merge found conflicting changes to the node. See [`merge()`](super::merge()).
+    Conflict,
+}
+
+/// One step of turning a tree into another version of it. See [`Node::edit_script`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffOp {
+    /// This subtree of the new tree has no counterpart in the old tree.
+    Insert(Node),
+    /// This subtree of the old tree has no counterpart in the new tree.
+    Delete(Node),
+    /// The `old` subtree was replaced by the `new` one, which has a different construct or text.
+    Modify { old: Node, new: Node },
+}
+
 #[derive(Debug)]
 pub struct NodeForest {
     forest: forest::Forest<NodeData>,
@@ -27,12 +130,22 @@ struct NodeData {
 /// _Ownership model:_ There is one "primary" Node reference to each tree (anywhere in the tree).
 /// When a tree would have two primary references, it's copied instead.  When a tree would have
 /// zero primary references, it's deleted.  There can be "temporary" references as well, but they
-/// never outlive the primary reference. Bookmarks are neither "primary" nor "temporary"
-/// references: they may outlive the primary reference but need to be checked for validity before
-/// being used, as the node they reference may have been deleted.
+/// never outlive the primary reference. To keep a reference that may outlive the primary one,
+/// like a bookmark does, use a [`WeakNode`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Node(forest::NodeIndex);
 
+/// A reference to a node that's safe to keep around after the node has been deleted. Get it from
+/// [`Node::downgrade`], and turn it back into a `Node` with [`WeakNode::upgrade`], which checks
+/// whether the node still exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WeakNode(forest::NodeIndex);
+
+/// Where each node was moved to when the nodes in a `Storage` were compacted. See
+/// `Storage::compact_nodes()`.
+#[derive(Debug)]
+pub struct NodeRemapping(HashMap<forest::NodeIndex, forest::NodeIndex>);
+
 impl Storage {
     fn forest(&self) -> &forest::Forest<NodeData> {
         &self.node_forest.forest
@@ -57,7 +170,8 @@ impl Node {
         Node::new_impl(s, construct, false)
     }
 
-    /// Creates a new root node, filling in any children that can only be one construct.
+    /// Creates a new root node, filling in its children according to the construct's
+    /// `auto_fill`, or if it has none then with any children that can only be one construct.
     pub fn new_with_auto_fill(s: &mut Storage, construct: Construct) -> Node {
         Node::new_impl(s, construct, true)
     }
@@ -83,8 +197,7 @@ impl Node {
                 });
                 let hole_construct = construct.language().hole_construct(s);
                 for i in 0..sorts.len(s) {
-                    let sort = sorts.get(s, i).bug();
-                    let child = match sort.unique_construct(s) {
+                    let child = match sorts.auto_fill(s, i) {
                         Some(child_construct) if auto_fill => {
                             Node::new_impl(s, child_construct, auto_fill)
                         }
@@ -97,63 +210,79 @@ impl Node {
         }
     }
 
-    pub fn with_text(s: &mut Storage, construct: Construct, text: String) -> Option<Node> {
+    /// Creates a new root node containing `text`. Fails if the construct isn't texty.
+    pub fn with_text(
+        s: &mut Storage,
+        construct: Construct,
+        text: String,
+    ) -> Result<Node, TreeError> {
         if let Arity::Texty = construct.arity(s) {
             let id = inc_id(&mut s.node_forest.next_id);
             let mut contents = Text::new();
             contents.set(text);
-            Some(Node(s.forest_mut().new_node(NodeData {
+            Ok(Node(s.forest_mut().new_node(NodeData {
                 id,
                 construct,
                 text: Some(contents),
             })))
         } else {
-            None
+            Err(TreeError::NotTexty(construct.name(s).to_owned()))
         }
     }
 
+    /// Creates a new root node with the given children. Fails if the children aren't all valid
+    /// roots, or if they don't match the construct's arity.
     pub fn with_children(
         s: &mut Storage,
         construct: Construct,
         children: impl IntoIterator<Item = Node>,
-    ) -> Option<Node> {
+    ) -> Result<Node, TreeError> {
         let children = children.into_iter().collect::<Vec<_>>();
-        if children.iter().any(|child| !child.is_root(s)) {
-            return None;
+        for child in &children {
+            child.check_valid(s)?;
+            if !child.is_root(s) {
+                return Err(TreeError::NotRoot);
+            }
         }
-        let allowed = match construct.arity(s) {
-            Arity::Texty => false,
+        let rejected = match construct.arity(s) {
+            Arity::Texty => return Err(TreeError::Texty(construct.name(s).to_owned())),
             Arity::Listy(sort) => children
                 .iter()
-                .all(|child| sort.accepts(s, child.construct(s))),
+                .find(|child| !sort.accepts(s, child.construct(s))),
             Arity::Fixed(sorts) => {
                 if sorts.len(s) != children.len() {
-                    false
-                } else {
-                    children
-                        .iter()
-                        .enumerate()
-                        .all(|(i, child)| sorts.get(s, i).bug().accepts(s, child.construct(s)))
+                    return Err(TreeError::WrongNumberOfChildren(
+                        construct.name(s).to_owned(),
+                        sorts.len(s),
+                        children.len(),
+                    ));
                 }
+                children
+                    .iter()
+                    .enumerate()
+                    .find(|(i, child)| !sorts.get(s, *i).bug().accepts(s, child.construct(s)))
+                    .map(|(_, child)| child)
             }
         };
-        if allowed {
-            let id = inc_id(&mut s.node_forest.next_id);
-            let parent = s.forest_mut().new_node(NodeData {
-                id,
-                construct,
-                text: None,
-            });
-            for child in children {
-                // Requires that:
-                // - Each child is a root, validated above
-                // - No child is the root of `parent`, which was just created
-                bug_assert!(s.forest_mut().insert_last_child(parent, child.0));
-            }
-            Some(Node(parent))
-        } else {
-            None
+        if let Some(child) = rejected {
+            return Err(TreeError::NotAllowedHere(
+                child.construct(s).name(s).to_owned(),
+            ));
+        }
+
+        let id = inc_id(&mut s.node_forest.next_id);
+        let parent = s.forest_mut().new_node(NodeData {
+            id,
+            construct,
+            text: None,
+        });
+        for child in children {
+            // Requires that:
+            // - Each child is a root, validated above
+            // - No child is the root of `parent`, which was just created
+            bug_assert!(s.forest_mut().insert_last_child(parent, child.0));
         }
+        Ok(Node(parent))
     }
 
     /*************
@@ -194,6 +323,10 @@ impl Node {
         s.forest().data(self.0).construct.is_comment_or_ws(s)
     }
 
+    pub fn is_hidden(self, s: &Storage) -> bool {
+        s.forest().data(self.0).construct.is_hidden(s)
+    }
+
     pub fn is_texty(self, s: &Storage) -> bool {
         s.forest().data(self.0).text.is_some()
     }
@@ -220,6 +353,25 @@ impl Node {
         s.forest_mut().data_mut(self.0).text.as_mut()
     }
 
+    /// Like `text()`, but fails instead of panicking if this node was deleted, and fails if it
+    /// isn't texty.
+    pub fn try_text(self, s: &Storage) -> Result<&Text, TreeError> {
+        let data = s.forest().try_data(self.0)?;
+        data.text
+            .as_ref()
+            .ok_or_else(|| TreeError::NotTexty(data.construct.name(s).to_owned()))
+    }
+
+    /// Like `text_mut()`, but fails instead of panicking if this node was deleted, and fails if
+    /// it isn't texty.
+    pub fn try_text_mut(self, s: &mut Storage) -> Result<&mut Text, TreeError> {
+        let data = s.forest().try_data(self.0)?;
+        if data.text.is_none() {
+            return Err(TreeError::NotTexty(data.construct.name(s).to_owned()));
+        }
+        Ok(s.forest_mut().try_data_mut(self.0)?.text.as_mut().bug())
+    }
+
     /*************
      * Relatives *
      *************/
@@ -262,6 +414,20 @@ impl Node {
         s.forest().parent(self.0).map(Node)
     }
 
+    /// Like `parent()`, but fails instead of panicking if this node was deleted.
+    pub fn try_parent(self, s: &Storage) -> Result<Option<Node>, TreeError> {
+        Ok(s.forest().try_parent(self.0)?.map(Node))
+    }
+
+    /// This node's children, in order. Fails instead of panicking if this node was deleted.
+    pub fn try_children(self, s: &Storage) -> Result<Vec<Node>, TreeError> {
+        Ok(s.forest()
+            .try_children(self.0)?
+            .into_iter()
+            .map(Node)
+            .collect())
+    }
+
     pub fn first_child(self, s: &Storage) -> Option<Node> {
         s.forest().first_child(self.0).map(Node)
     }
@@ -284,6 +450,16 @@ impl Node {
         s.forest().next_sibling(self.0).map(Node)
     }
 
+    /// Like `prev_sibling()`, but fails instead of panicking if this node was deleted.
+    pub fn try_prev_sibling(self, s: &Storage) -> Result<Option<Node>, TreeError> {
+        Ok(s.forest().try_prev_sibling(self.0)?.map(Node))
+    }
+
+    /// Like `next_sibling()`, but fails instead of panicking if this node was deleted.
+    pub fn try_next_sibling(self, s: &Storage) -> Result<Option<Node>, TreeError> {
+        Ok(s.forest().try_next_sibling(self.0)?.map(Node))
+    }
+
     pub fn first_sibling(self, s: &Storage) -> Node {
         Node(s.forest().first_sibling(self.0))
     }
@@ -367,17 +543,90 @@ impl Node {
         Node(s.forest().root(self.0))
     }
 
+    /// The path from the root down to this node, like `root.2.0`: the index of the child to go to
+    /// at each step. See [`Node::at_path`].
+    pub fn path(self, s: &Storage) -> String {
+        let mut indices = Vec::new();
+        let mut node = self;
+        while let Some(parent) = node.parent(s) {
+            indices.push(node.sibling_index(s));
+            node = parent;
+        }
+        let mut path = "root".to_owned();
+        for index in indices.into_iter().rev() {
+            path.push('.');
+            path.push_str(&index.to_string());
+        }
+        path
+    }
+
+    /// Follow `path` down from this node, which it calls `root`. Each step after `root` is one of:
+    ///
+    /// - `N`: the child at index `N`.
+    /// - `Name`: the first child whose construct is called `Name`.
+    /// - `Name[N]`: the child at index `N` among the children whose construct is called `Name`.
+    ///
+    /// So in json, `root.0.Pair[2].1` is the value of the third key-value pair in the top-level
+    /// object.
+    pub fn at_path(self, s: &Storage, path: &str) -> Result<Node, TreeError> {
+        let mut steps = path.split('.');
+        if steps.next() != Some("root") {
+            return Err(TreeError::InvalidPath(path.to_owned()));
+        }
+        let mut node = self;
+        for step in steps {
+            let child = if let Ok(index) = step.parse::<usize>() {
+                node.nth_child(s, index)
+            } else {
+                let indexed_name = step.strip_suffix(']').and_then(|step| step.split_once('['));
+                let (name, index) = match indexed_name {
+                    Some((name, index)) => (name, index.parse::<usize>().ok()),
+                    None => (step, Some(0)),
+                };
+                index.and_then(|index| {
+                    std::iter::successors(node.first_child(s), |child| child.next_sibling(s))
+                        .filter(|child| child.construct(s).name(s) == name)
+                        .nth(index)
+                })
+            };
+            node =
+                child.ok_or_else(|| TreeError::NoNodeAtPath(path.to_owned(), step.to_owned()))?;
+        }
+        Ok(node)
+    }
+
     /// Check whether this node has been deleted.
     pub fn is_valid(self, s: &Storage) -> bool {
         s.forest().is_valid(self.0)
     }
 
+    /// A reference to this node that can be stored long-term. See [`WeakNode`].
+    pub fn downgrade(self) -> WeakNode {
+        WeakNode(self.0)
+    }
+
+    fn check_valid(self, s: &Storage) -> Result<(), TreeError> {
+        if self.is_valid(s) {
+            Ok(())
+        } else {
+            Err(TreeError::DeletedNode)
+        }
+    }
+
+    pub(super) fn check_root(self, s: &Storage) -> Result<(), TreeError> {
+        if self.is_root(s) {
+            Ok(())
+        } else {
+            Err(TreeError::NotRoot)
+        }
+    }
+
     /**************
      * Acceptance *
      **************/
 
     /// Check if `other` is allowed where `self` currently is, according to our parent's arity.
-    fn accepts_replacement(self, s: &Storage, other: Node) -> bool {
+    fn accepts_replacement(self, s: &Storage, other: Node) -> Result<(), TreeError> {
         if let Some(parent) = s.forest().parent(self.0) {
             let sort = match Node(parent).arity(s) {
                 Arity::Fixed(sorts) => sorts.get(s, self.sibling_index(s)).bug(),
@@ -385,135 +634,232 @@ impl Node {
                 Arity::Texty => bug!("Texty parent!"),
             };
             let other_construct = s.forest().data(other.0).construct;
-            sort.accepts(s, other_construct)
-        } else {
-            true
+            if !sort.accepts(s, other_construct) {
+                return Err(TreeError::NotAllowedHere(
+                    other_construct.name(s).to_owned(),
+                ));
+            }
         }
+        Ok(())
     }
 
-    fn is_listy_and_accepts_child(self, s: &Storage, other: Node) -> bool {
+    fn is_listy_and_accepts_child(self, s: &Storage, other: Node) -> Result<(), TreeError> {
         let other_construct = s.forest().data(other.0).construct;
         match self.arity(s) {
-            Arity::Fixed(_) => false,
-            Arity::Listy(sort) => sort.accepts(s, other_construct),
-            Arity::Texty => false,
+            Arity::Listy(sort) if sort.accepts(s, other_construct) => Ok(()),
+            Arity::Listy(_) => Err(TreeError::NotAllowedHere(
+                other_construct.name(s).to_owned(),
+            )),
+            Arity::Fixed(_) | Arity::Texty => {
+                Err(TreeError::NotListy(self.construct(s).name(s).to_owned()))
+            }
+        }
+    }
+
+    fn check_listy(self, s: &Storage) -> Result<(), TreeError> {
+        match self.arity(s) {
+            Arity::Listy(_) => Ok(()),
+            Arity::Fixed(_) | Arity::Texty => {
+                Err(TreeError::NotListy(self.construct(s).name(s).to_owned()))
+            }
         }
     }
 
+    /// Check that `new_node` can be inserted somewhere in the tree containing `self`: it must be
+    /// a root, and not the root of `self`'s own tree.
+    fn check_insertable(self, s: &Storage, new_node: Node) -> Result<(), TreeError> {
+        self.check_valid(s)?;
+        new_node.check_valid(s)?;
+        new_node.check_root(s)?;
+        if self.root(s) == new_node {
+            return Err(TreeError::Overlapping);
+        }
+        Ok(())
+    }
+
     /************
      * Mutation *
      ************/
 
-    /// Attempts to swap `self` and `other`, returning true if successful.
-    /// Returns false and does nothing if either:
+    /// Attempts to swap `self` and `other`. Fails and does nothing if any of:
     ///
+    /// - Either node has been deleted.
     /// - One node is an ancestor of another (so they would mangle the trees if swapped).
     /// - One of the nodes is incompatible with the arity of its new parent.
-    #[must_use]
-    pub fn swap(self, s: &mut Storage, other: Node) -> bool {
-        if self.accepts_replacement(s, other) && other.accepts_replacement(s, self) {
-            s.forest_mut().swap(self.0, other.0)
-        } else {
-            false
-        }
+    pub fn swap(self, s: &mut Storage, other: Node) -> Result<(), TreeError> {
+        self.check_valid(s)?;
+        other.check_valid(s)?;
+        self.accepts_replacement(s, other)?;
+        other.accepts_replacement(s, self)?;
+        Ok(s.forest_mut().try_swap(self.0, other.0)?)
     }
 
-    /// Attempts to insert `new_sibling` to the left of `self`.
-    /// Returns false and does nothing if either:
+    /// Attempts to insert `new_sibling` to the left of `self`. Fails and does nothing if any of:
     ///
+    /// - Either node has been deleted.
+    /// - `self` is a root.
     /// - The `new_sibling` is incompatible with the arity of the parent.
-    /// - The `new_sibling` is not a root.
-    #[must_use]
-    pub fn insert_before(self, s: &mut Storage, new_sibling: Node) -> bool {
-        if let Some(parent) = self.parent(s) {
-            if parent.is_listy_and_accepts_child(s, new_sibling) {
-                s.forest_mut().insert_before(self.0, new_sibling.0)
-            } else {
-                false
-            }
-        } else {
-            false
-        }
+    /// - The `new_sibling` is not a root, or is the root of `self`.
+    pub fn insert_before(self, s: &mut Storage, new_sibling: Node) -> Result<(), TreeError> {
+        self.check_insertable(s, new_sibling)?;
+        let parent = self.parent(s).ok_or(TreeError::IsRoot)?;
+        parent.is_listy_and_accepts_child(s, new_sibling)?;
+        Ok(s.forest_mut().try_insert_before(self.0, new_sibling.0)?)
     }
 
-    /// Attempts to insert `new_sibling` to the right of `self`.
-    /// Returns false and does nothing if either:
+    /// Attempts to insert `new_sibling` to the right of `self`. Fails and does nothing if any of:
     ///
+    /// - Either node has been deleted.
+    /// - `self` is a root.
     /// - The `new_sibling` is incompatible with the arity of the parent.
-    /// - The `new_sibling` is not a root.
-    #[must_use]
-    pub fn insert_after(self, s: &mut Storage, new_sibling: Node) -> bool {
-        if let Some(parent) = self.parent(s) {
-            if parent.is_listy_and_accepts_child(s, new_sibling) {
-                s.forest_mut().insert_after(self.0, new_sibling.0)
-            } else {
-                false
-            }
-        } else {
-            false
-        }
+    /// - The `new_sibling` is not a root, or is the root of `self`.
+    pub fn insert_after(self, s: &mut Storage, new_sibling: Node) -> Result<(), TreeError> {
+        self.check_insertable(s, new_sibling)?;
+        let parent = self.parent(s).ok_or(TreeError::IsRoot)?;
+        parent.is_listy_and_accepts_child(s, new_sibling)?;
+        Ok(s.forest_mut().try_insert_after(self.0, new_sibling.0)?)
     }
 
-    /// Attempts to insert `new_child` as the first child of `self`.
-    /// Returns false and does nothing if any of:
+    /// Attempts to insert `new_child` as the first child of `self`. Fails and does nothing if any
+    /// of:
     ///
+    /// - Either node has been deleted.
     /// - `self` is not listy.
     /// - The `new_child` is incompatible with the arity of `self`.
-    /// - The `new_child` is not a root.
-    /// - The `new_child` is the root of `self`.
-    #[must_use]
-    pub fn insert_first_child(self, s: &mut Storage, new_child: Node) -> bool {
-        if self.is_listy_and_accepts_child(s, new_child) {
-            s.forest_mut().insert_first_child(self.0, new_child.0)
-        } else {
-            false
-        }
+    /// - The `new_child` is not a root, or is the root of `self`.
+    pub fn insert_first_child(self, s: &mut Storage, new_child: Node) -> Result<(), TreeError> {
+        self.check_insertable(s, new_child)?;
+        self.is_listy_and_accepts_child(s, new_child)?;
+        Ok(s.forest_mut().try_insert_first_child(self.0, new_child.0)?)
     }
 
-    /// Attempts to insert `new_child` as the last child of `self`.
-    /// Returns false and does nothing if any of:
+    /// Attempts to insert `new_child` as the last child of `self`. Fails and does nothing if any
+    /// of:
     ///
+    /// - Either node has been deleted.
     /// - `self` is not listy.
     /// - The `new_child` is incompatible with the arity of `self`.
-    /// - The `new_child` is not a root.
-    /// - The `new_child` is the root of `self`.
-    #[must_use]
-    pub fn insert_last_child(self, s: &mut Storage, new_child: Node) -> bool {
-        if self.is_listy_and_accepts_child(s, new_child) {
-            s.forest_mut().insert_last_child(self.0, new_child.0)
+    /// - The `new_child` is not a root, or is the root of `self`.
+    pub fn insert_last_child(self, s: &mut Storage, new_child: Node) -> Result<(), TreeError> {
+        self.check_insertable(s, new_child)?;
+        self.is_listy_and_accepts_child(s, new_child)?;
+        Ok(s.forest_mut().try_insert_last_child(self.0, new_child.0)?)
+    }
+
+    /// Attempts to insert `new_children` into `self`, in order, so that the first of them becomes
+    /// the child at `index`. This is a single operation, so it's much faster than inserting them
+    /// one at a time. Fails and does nothing if any of:
+    ///
+    /// - Any of the nodes has been deleted.
+    /// - `self` is not listy, or one of the `new_children` is incompatible with its arity.
+    /// - `index` is greater than the number of children.
+    /// - One of the `new_children` is not a root, is the root of `self`, or is given twice.
+    pub fn insert_children(
+        self,
+        s: &mut Storage,
+        index: usize,
+        new_children: &[Node],
+    ) -> Result<(), TreeError> {
+        self.check_valid(s)?;
+        self.check_listy(s)?;
+        for child in new_children {
+            self.check_insertable(s, *child)?;
+            self.is_listy_and_accepts_child(s, *child)?;
+        }
+        if index > s.forest().num_children(self.0) {
+            return Err(TreeError::InvalidLocation);
+        }
+        let indices = new_children.iter().map(|child| child.0).collect::<Vec<_>>();
+        if s.forest_mut().insert_children(self.0, index, &indices) {
+            Ok(())
         } else {
-            false
+            Err(TreeError::DuplicateNode)
         }
     }
 
-    /// Attempts to remove `self` from its listy parent, making it a root.
-    /// Returns false and does nothing if either:
+    /// Attempts to remove the children of `self` whose indices are in `range`, making them roots.
+    /// Returns them in order. Fails and does nothing if `self` has been deleted or is not listy,
+    /// or if `range` goes past its last child.
+    pub fn drain_children(
+        self,
+        s: &mut Storage,
+        range: Range<usize>,
+    ) -> Result<Vec<Node>, TreeError> {
+        self.check_valid(s)?;
+        self.check_listy(s)?;
+        let drained = s
+            .forest_mut()
+            .drain_children(self.0, range)
+            .ok_or(TreeError::InvalidLocation)?;
+        Ok(drained.into_iter().map(Node).collect())
+    }
+
+    /// Attempts to remove `self` from its listy parent, making it a root. Fails and does nothing
+    /// if any of:
     ///
+    /// - `self` has been deleted.
+    /// - `self` is a root.
     /// - The parent is not listy.
+    pub fn detach(self, s: &mut Storage) -> Result<(), TreeError> {
+        self.check_valid(s)?;
+        let parent = self.parent(s).ok_or(TreeError::IsRoot)?;
+        parent.check_listy(s)?;
+        Ok(s.forest_mut().try_detach(self.0)?)
+    }
+
+    /// Attempts to replace `self` with its children in its parent's list of children, leaving
+    /// `self` as a root with no children. Fails and does nothing if any of:
+    ///
+    /// - `self` has been deleted.
     /// - `self` is a root.
-    #[must_use]
-    pub fn detach(self, s: &mut Storage) -> bool {
-        if let Some(parent) = self.parent(s) {
-            match parent.arity(s) {
-                Arity::Fixed(_) => false,
-                Arity::Texty => false,
-                Arity::Listy(_) => {
-                    s.forest_mut().detach(self.0);
-                    true
-                }
+    /// - `self` or its parent is not listy.
+    /// - One of the children is incompatible with the arity of the parent.
+    pub fn splice(self, s: &mut Storage) -> Result<(), TreeError> {
+        self.check_valid(s)?;
+        let parent = self.parent(s).ok_or(TreeError::IsRoot)?;
+        parent.check_listy(s)?;
+        self.check_listy(s)?;
+        for child in self.children(s) {
+            parent.is_listy_and_accepts_child(s, child)?;
+        }
+        bug_assert!(s.forest_mut().splice(self.0));
+        Ok(())
+    }
+
+    /// Attempts to move the siblings from `first` through `last` into `self`, after any children
+    /// it already has, and put `self` where they were. This is the inverse of `splice()`. Fails
+    /// and does nothing if any of:
+    ///
+    /// - Any of the nodes has been deleted.
+    /// - `self` is not a root, or is the root of `first`.
+    /// - `first` is a root, or its parent is not listy.
+    /// - `last` is neither `first` nor one of its later siblings.
+    /// - `self` is not listy, or one of the siblings is incompatible with its arity.
+    pub fn group(self, s: &mut Storage, first: Node, last: Node) -> Result<(), TreeError> {
+        first.check_insertable(s, self)?;
+        last.check_valid(s)?;
+        let parent = first.parent(s).ok_or(TreeError::IsRoot)?;
+        parent.is_listy_and_accepts_child(s, self)?;
+        let mut node = first;
+        loop {
+            self.is_listy_and_accepts_child(s, node)?;
+            if node == last {
+                break;
             }
-        } else {
-            false
+            node = node.next_sibling(s).ok_or(TreeError::NotSiblings)?;
         }
+        bug_assert!(s.forest_mut().group(first.0, last.0, self.0));
+        Ok(())
     }
 
     /******************
      * Entire Subtree *
      ******************/
 
-    /// Deletes this node and its descendants. Panics if `self` is not a root.
-    pub fn delete_root(self, s: &mut Storage) {
-        s.forest_mut().delete_root(self.0);
+    /// Deletes this node and its descendants. Fails and does nothing if `self` is not a root or
+    /// has already been deleted.
+    pub fn delete_root(self, s: &mut Storage) -> Result<(), TreeError> {
+        Ok(s.forest_mut().try_delete_root(self.0)?)
     }
 
     /// Make a deep copy of the given node, including its descendants. The copy will become a root
@@ -530,6 +876,55 @@ impl Node {
         Node(s.node_forest.forest.deep_copy(self.0, &mut clone_data))
     }
 
+    /// Describe this node and its descendants in a form that can be saved to disk.
+    pub fn save(self, s: &Storage) -> SavedNode {
+        let mut children = Vec::new();
+        let mut opt_child = self.first_child(s);
+        while let Some(child) = opt_child {
+            children.push(child.save(s));
+            opt_child = child.next_sibling(s);
+        }
+        SavedNode {
+            construct: self.construct(s).name(s).to_owned(),
+            text: self.text(s).map(|text| text.as_str().to_owned()),
+            children,
+        }
+    }
+
+    /// Rebuild a node saved with [`Node::save`], as a new root. Fails if it doesn't fit
+    /// `language`'s grammar.
+    pub fn restore(
+        s: &mut Storage,
+        language: Language,
+        saved: &SavedNode,
+    ) -> Result<Node, TreeError> {
+        let construct = language
+            .construct(s, &saved.construct)
+            .ok_or_else(|| TreeError::UnknownConstruct(saved.construct.clone()))?;
+        if let Some(text) = &saved.text {
+            return Node::with_text(s, construct, text.clone());
+        }
+        let mut children = Vec::new();
+        for saved_child in &saved.children {
+            match Node::restore(s, language, saved_child) {
+                Ok(child) => children.push(child),
+                Err(err) => {
+                    for child in children {
+                        child.delete_root(s).bug();
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Node::with_children(s, construct, children.clone()).map_err(|err| {
+            // The children are left as roots if this fails.
+            for child in children {
+                child.delete_root(s).bug();
+            }
+            err
+        })
+    }
+
     /// Deep structural equality check.
     pub fn equals(self, s: &Storage, other: Node) -> bool {
         let f = s.forest();
@@ -559,6 +954,237 @@ impl Node {
         true
     }
 
+    /// A short, one-line label for this node, following its construct's summary template (see
+    /// [`ConstructSpec::summary`]). Truncated to `SUMMARY_MAX_CHARS` characters.
+    ///
+    /// [`ConstructSpec::summary`]: crate::language::ConstructSpec::summary
+    pub fn summary(self, s: &Storage) -> String {
+        let summary = self.summary_impl(s, SUMMARY_MAX_DEPTH);
+        if summary.chars().count() <= SUMMARY_MAX_CHARS {
+            summary
+        } else {
+            let mut truncated = summary
+                .chars()
+                .take(SUMMARY_MAX_CHARS - 1)
+                .collect::<String>();
+            truncated.push('…');
+            truncated
+        }
+    }
+
+    fn summary_impl(self, s: &Storage, depth: usize) -> String {
+        let construct = self.construct(s);
+        let text = || {
+            self.text(s)
+                .map(|text| text.as_str().lines().next().unwrap_or("").to_owned())
+        };
+        let template = match construct.summary_template(s) {
+            Some(template) if depth > 0 => template,
+            _ => return text().unwrap_or_else(|| construct.name(s).to_owned()),
+        };
+
+        let mut summary = String::new();
+        let mut rest = template;
+        while let Some(i) = rest.find('$') {
+            summary.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+            if let Some(after) = rest.strip_prefix("text") {
+                summary.push_str(&text().unwrap_or_default());
+                rest = after;
+            } else if let Some(index) = rest.chars().next().and_then(|ch| ch.to_digit(10)) {
+                if let Some(child) = self.nth_child(s, index as usize) {
+                    summary.push_str(&child.summary_impl(s, depth - 1));
+                }
+                rest = &rest[1..];
+            } else {
+                summary.push('$');
+            }
+        }
+        summary.push_str(rest);
+        summary
+    }
+
+    /// Describe the structural differences between this tree and `other`, one line per
+    /// difference, each prefixed by the path to where it occurs. Empty iff the trees are equal.
+    pub fn diff(self, s: &Storage, other: Node) -> Vec<String> {
+        let mut diffs = Vec::new();
+        let path = self.construct(s).name(s).to_owned();
+        self.diff_impl(s, other, &path, &mut diffs);
+        diffs
+    }
+
+    fn diff_impl(self, s: &Storage, other: Node, path: &str, diffs: &mut Vec<String>) {
+        let (construct_a, construct_b) = (self.construct(s), other.construct(s));
+        if construct_a != construct_b {
+            diffs.push(format!(
+                "{}: {} -> {}",
+                path,
+                construct_a.name(s),
+                construct_b.name(s)
+            ));
+            return;
+        }
+        let text_a = self.text(s).map(|text| text.as_str());
+        let text_b = other.text(s).map(|text| text.as_str());
+        if text_a != text_b {
+            diffs.push(format!(
+                "{}: \"{}\" -> \"{}\"",
+                path,
+                text_a.unwrap_or_default(),
+                text_b.unwrap_or_default()
+            ));
+            return;
+        }
+        let mut child_a = self.first_child(s);
+        let mut child_b = other.first_child(s);
+        let mut i = 0;
+        while let (Some(a), Some(b)) = (child_a, child_b) {
+            let child_path = format!("{} > {}[{}]", path, a.construct(s).name(s), i);
+            a.diff_impl(s, b, &child_path, diffs);
+            child_a = a.next_sibling(s);
+            child_b = b.next_sibling(s);
+            i += 1;
+        }
+        let num_a = self.num_children(s).unwrap_or(0);
+        let num_b = other.num_children(s).unwrap_or(0);
+        if num_a != num_b {
+            diffs.push(format!("{path}: {num_a} children -> {num_b} children"));
+        }
+    }
+
+    /// Find the nodes in this tree that were added or modified since `old`, an older version of
+    /// it. Only the outermost changes are included: the descendants of an added node aren't
+    /// listed separately. Deleted nodes have nothing to attach to, so they aren't reported.
+    pub fn changes_since(self, s: &Storage, old: Node) -> Vec<(Node, NodeChange)> {
+        old.edit_script(s, self)
+            .into_iter()
+            .filter_map(|op| match op {
+                DiffOp::Insert(node) => Some((node, NodeChange::Added)),
+                DiffOp::Modify { new, .. } => Some((new, NodeChange::Modified)),
+                DiffOp::Delete(_) => None,
+            })
+            .collect()
+    }
+
+    /// Find a short sequence of subtree insertions, deletions, and modifications that turns this
+    /// tree into `new`, in document order. Each subtree is only mentioned once: the descendants
+    /// of an inserted subtree aren't inserted separately.
+    pub fn edit_script(self, s: &Storage, new: Node) -> Vec<DiffOp> {
+        let mut ops = Vec::new();
+        self.edit_script_impl(s, new, &mut ops);
+        ops
+    }
+
+    fn edit_script_impl(self, s: &Storage, new: Node, ops: &mut Vec<DiffOp>) {
+        if self.construct(s) != new.construct(s)
+            || self.text(s).map(|text| text.as_str()) != new.text(s).map(|text| text.as_str())
+        {
+            ops.push(DiffOp::Modify { old: self, new });
+            return;
+        }
+        let old_children = self.children(s);
+        let new_children = new.children(s);
+        if !matches!(self.arity(s), Arity::Listy(_)) {
+            for (old_child, new_child) in old_children.into_iter().zip(new_children) {
+                old_child.edit_script_impl(s, new_child, ops);
+            }
+            return;
+        }
+
+        // Match up the unchanged children. Between each match, each remaining new child is
+        // compared to the next remaining old child with the same construct, if there is one, and
+        // is otherwise inserted. Old children that don't get compared to anything were deleted.
+        let matches = matching_children(s, &new_children, &old_children);
+        let mut i = 0;
+        let mut j = 0;
+        for (match_i, match_j) in matches
+            .into_iter()
+            .chain([(new_children.len(), old_children.len())])
+        {
+            for new_child in &new_children[i..match_i] {
+                let construct = new_child.construct(s);
+                let old_child = old_children[j..match_j]
+                    .iter()
+                    .position(|old_child| old_child.construct(s) == construct);
+                match old_child {
+                    Some(k) => {
+                        for deleted in &old_children[j..j + k] {
+                            ops.push(DiffOp::Delete(*deleted));
+                        }
+                        old_children[j + k].edit_script_impl(s, *new_child, ops);
+                        j += k + 1;
+                    }
+                    None => ops.push(DiffOp::Insert(*new_child)),
+                }
+            }
+            for deleted in &old_children[j..match_j] {
+                ops.push(DiffOp::Delete(*deleted));
+            }
+            i = match_i + 1;
+            j = match_j + 1;
+        }
+    }
+
+    pub(super) fn children(self, s: &Storage) -> Vec<Node> {
+        let mut children = Vec::new();
+        let mut child = self.first_child(s);
+        while let Some(node) = child {
+            children.push(node);
+            child = node.next_sibling(s);
+        }
+        children
+    }
+
+    /// Every node in this node's subtree, starting with this node, with each node before its
+    /// descendants.
+    pub fn preorder(self, s: &Storage) -> impl Iterator<Item = Node> + '_ {
+        s.forest().preorder(self.0).map(Node)
+    }
+
+    /// Every node in this node's subtree, ending with this node, with each node after its
+    /// descendants.
+    pub fn postorder(self, s: &Storage) -> impl Iterator<Item = Node> + '_ {
+        s.forest().postorder(self.0).map(Node)
+    }
+
+    /// The nodes in this node's subtree that have no children, in document order.
+    pub fn leaves(self, s: &Storage) -> impl Iterator<Item = Node> + '_ {
+        s.forest().leaves(self.0).map(Node)
+    }
+
+    /// Call `visitor` on each node in this node's subtree in pre-order. It decides whether to
+    /// descend into the node's children, skip them, or stop. Returns `false` if it stopped.
+    pub fn visit(self, s: &Storage, mut visitor: impl FnMut(Node) -> Visit) -> bool {
+        s.forest().visit(self.0, |node| visitor(Node(node)))
+    }
+
+    /// Like [`Node::visit()`], but `callback` can edit the tree as it goes, like with
+    /// [`Node::walk_tree()`]. The node's first child and next sibling are looked up before
+    /// `callback` is called on it, so it may replace or delete the node it's given, but shouldn't
+    /// touch the rest of the tree. The children of a node that was deleted aren't visited, even if
+    /// `callback` said to continue. Returns `false` if it stopped.
+    pub fn visit_mut(
+        self,
+        s: &mut Storage,
+        mut callback: impl FnMut(&mut Storage, Node) -> Visit,
+    ) -> bool {
+        // Each node's next sibling is pushed before its first child, so that the walk is in
+        // pre-order.
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            let first_child = node.first_child(s);
+            if node != self {
+                stack.extend(node.next_sibling(s));
+            }
+            match callback(s, node) {
+                Visit::Continue if node.is_valid(s) => stack.extend(first_child),
+                Visit::Continue | Visit::SkipChildren => (),
+                Visit::Stop => return false,
+            }
+        }
+        true
+    }
+
     /// Invoke `callback` on every descendant of this node, in an unspecified order.
     pub fn walk_tree(self, s: &mut Storage, mut callback: impl FnMut(&mut Storage, Node)) {
         // Remaining nodes to walk are `n.first_child()` and `n.next_sibling()` for every `n` in
@@ -591,6 +1217,18 @@ impl Node {
     }
 }
 
+impl WeakNode {
+    /// The node, or `None` if it's been deleted. A node made after the deletion is never mistaken
+    /// for it, since the forest's indices are generational.
+    pub fn upgrade(self, s: &Storage) -> Option<Node> {
+        if s.forest().is_valid(self.0) {
+            Some(Node(self.0))
+        } else {
+            None
+        }
+    }
+}
+
 pub struct NodeDisplay<'s> {
     storage: &'s Storage,
     node: Node,
@@ -617,6 +1255,12 @@ impl fmt::Display for NodeDisplay<'_> {
     }
 }
 
+impl rhai::CustomType for Node {
+    fn build(mut builder: rhai::TypeBuilder<Self>) {
+        builder.with_name("Node");
+    }
+}
+
 impl NodeForest {
     pub fn new() -> NodeForest {
         // Must never use this node!
@@ -639,6 +1283,83 @@ impl NodeForest {
     pub fn num_nodes(&self) -> usize {
         self.forest.num_nodes()
     }
+
+    pub fn capacity(&self) -> usize {
+        self.forest.capacity()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.forest.reserve(additional);
+    }
+
+    pub fn compact(&mut self) -> NodeRemapping {
+        NodeRemapping(self.forest.compact())
+    }
+}
+
+impl NodeRemapping {
+    /// The node's new handle, or `None` if it had already been deleted before the compaction.
+    pub fn get(&self, node: Node) -> Option<Node> {
+        self.0.get(&node.0).map(|index| Node(*index))
+    }
+
+    /// Like `get()`, for a weak reference.
+    pub fn get_weak(&self, node: WeakNode) -> Option<WeakNode> {
+        self.0.get(&node.0).map(|index| WeakNode(*index))
+    }
+}
+
+/// Above this many comparisons, the children of a list are matched up by position instead of by
+/// longest common subsequence.
+const MAX_MATCHING_COST: usize = 1_000_000;
+
+/// Find the longest common subsequence of two lists of nodes, by structural equality. Returns the
+/// index pairs of the matched nodes, in increasing order.
+pub(super) fn matching_children(s: &Storage, new: &[Node], old: &[Node]) -> Vec<(usize, usize)> {
+    let prefix_len = new
+        .iter()
+        .zip(old)
+        .take_while(|(a, b)| a.equals(s, **b))
+        .count();
+    let suffix_len = new[prefix_len..]
+        .iter()
+        .rev()
+        .zip(old[prefix_len..].iter().rev())
+        .take_while(|(a, b)| a.equals(s, **b))
+        .count();
+    let new_mid = &new[prefix_len..new.len() - suffix_len];
+    let old_mid = &old[prefix_len..old.len() - suffix_len];
+
+    let mut matches = (0..prefix_len).map(|i| (i, i)).collect::<Vec<_>>();
+    if new_mid.len() * old_mid.len() <= MAX_MATCHING_COST {
+        // lcs[i][j] is the length of the longest common subsequence of new_mid[i..] and old_mid[j..]
+        let mut lcs = vec![vec![0; old_mid.len() + 1]; new_mid.len() + 1];
+        for i in (0..new_mid.len()).rev() {
+            for j in (0..old_mid.len()).rev() {
+                lcs[i][j] = if new_mid[i].equals(s, old_mid[j]) {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < new_mid.len() && j < old_mid.len() {
+            if new_mid[i].equals(s, old_mid[j]) {
+                matches.push((prefix_len + i, prefix_len + j));
+                i += 1;
+                j += 1;
+            } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+    let new_suffix_start = new.len() - suffix_len;
+    let old_suffix_start = old.len() - suffix_len;
+    matches.extend((0..suffix_len).map(|k| (new_suffix_start + k, old_suffix_start + k)));
+    matches
 }
 
 fn inc_id(id: &mut usize) -> NodeId {
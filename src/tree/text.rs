@@ -1,25 +1,150 @@
-use crate::util::SynlessBug;
-use std::iter;
+use super::bidi::{self, BidiLayout};
+use super::grapheme;
+use crate::util::bug;
+use ropey::Rope;
+use std::cell::OnceCell;
+use unicode_width::UnicodeWidthChar;
 
+/// The contents of a texty node.
+///
+/// The text is kept in a rope, so that inserting or deleting a character anywhere in a long text
+/// (like a minified blob) doesn't have to shift the rest of it over. The pretty printer needs the
+/// text as a single `&str`, though. A short text fits in one chunk of the rope and is used in
+/// place; a longer one is copied out into one string the first time it's needed after an edit.
 #[derive(Clone, Debug)]
-pub struct Text(String);
+pub struct Text {
+    rope: Rope,
+    /// The whole text in one string, if it doesn't fit in one chunk of `rope` and has been needed
+    /// since the last edit.
+    contiguous: OnceCell<String>,
+    /// The text in display order, or `None` if it has no right-to-left characters. Computed the
+    /// first time it's needed since the last edit. See [`Text::as_display_str`].
+    display: OnceCell<Option<String>>,
+}
 
 impl Text {
     pub fn new() -> Self {
-        Text(String::new())
+        Text {
+            rope: Rope::new(),
+            contiguous: OnceCell::new(),
+            display: OnceCell::new(),
+        }
     }
 
     pub fn as_str(&self) -> &str {
-        self.0.as_str()
+        match self.rope.slice(..).as_str() {
+            Some(string) => string,
+            None => self.contiguous.get_or_init(|| self.rope.to_string()),
+        }
+    }
+
+    /// The pieces that the text is stored in, in order. Unlike [`Text::as_str`], this never
+    /// copies the text.
+    pub fn chunks(&self) -> impl Iterator<Item = &str> + '_ {
+        self.rope.chunks()
+    }
+
+    /// The text as it should be shown on screen, left to right. This differs from
+    /// [`Text::as_str`] only if the text contains right-to-left characters (like Hebrew or
+    /// Arabic), in which case they're reordered as per the Unicode Bidirectional Algorithm.
+    pub fn as_display_str(&self) -> &str {
+        self.display().unwrap_or_else(|| self.as_str())
+    }
+
+    /// Where to show a text cursor at the given (logical) char index, as a char index into
+    /// [`Text::as_display_str`]. Moving the cursor always follows the logical order, so in
+    /// right-to-left text it moves leftwards on screen.
+    pub fn display_index(&self, char_index: usize) -> usize {
+        if self.display().is_some() {
+            BidiLayout::new(self.as_str()).display_index(char_index)
+        } else {
+            char_index
+        }
     }
 
     pub fn as_split_str(&self, char_index: usize) -> (&str, &str) {
-        self.0.as_str().split_at(self.byte_index(char_index))
+        self.as_str().split_at(self.byte_index(char_index))
+    }
+
+    /// The chars from `start` up to (but not including) `end`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either index is too large, or if `start > end`.
+    pub fn slice(&self, start: usize, end: usize) -> &str {
+        let start_byte = self.byte_index(start);
+        let end_byte = self.byte_index(end);
+        match self.rope.byte_slice(start_byte..end_byte).as_str() {
+            Some(slice) => slice,
+            None => &self.as_str()[start_byte..end_byte],
+        }
     }
 
     /// Return the length of the text in characters.
     pub fn num_chars(&self) -> usize {
-        self.0.chars().count()
+        self.rope.len_chars()
+    }
+
+    /// Whether the given index is between two grapheme clusters (user-perceived characters, like a
+    /// letter together with its accents). The text cursor should only ever sit at one, or it would
+    /// split a visible character in two. The start and end of the text are boundaries.
+    pub fn is_grapheme_boundary(&self, char_index: usize) -> bool {
+        if char_index >= self.num_chars() {
+            return true;
+        }
+        grapheme::is_boundary(self.as_str(), self.byte_index(char_index))
+    }
+
+    /// The index of the start of the grapheme cluster before the given index, or 0 if there isn't
+    /// one.
+    pub fn prev_grapheme_boundary(&self, char_index: usize) -> usize {
+        let i = char_index.min(self.num_chars());
+        let byte_index = grapheme::prev_boundary(self.as_str(), self.byte_index(i));
+        self.rope.byte_to_char(byte_index)
+    }
+
+    /// The index of the end of the grapheme cluster after the given index, or the length of the
+    /// text if there isn't one.
+    pub fn next_grapheme_boundary(&self, char_index: usize) -> usize {
+        let i = char_index.min(self.num_chars());
+        let byte_index = grapheme::next_boundary(self.as_str(), self.byte_index(i));
+        self.rope.byte_to_char(byte_index)
+    }
+
+    /// How many terminal columns the text takes up, going by grapheme cluster. Full-width
+    /// characters and emoji take two columns each, and accents take none.
+    pub fn display_width(&self) -> usize {
+        grapheme::display_width(self.as_str())
+    }
+
+    /// The index of the start of the word before the given index, skipping over any whitespace or
+    /// punctuation in between. Words are runs of alphanumeric characters and underscores.
+    pub fn prev_word_start(&self, char_index: usize) -> usize {
+        let i = char_index.min(self.num_chars());
+        let mut chars = self.rope.chars_at(i).reversed().peekable();
+        let mut num_skipped = 0;
+        while chars.next_if(|ch| !is_word_char(*ch)).is_some() {
+            num_skipped += 1;
+        }
+        while chars.next_if(|ch| is_word_char(*ch)).is_some() {
+            num_skipped += 1;
+        }
+        i - num_skipped
+    }
+
+    /// The index of the end of the word after the given index, skipping over any whitespace or
+    /// punctuation in between. Words are runs of alphanumeric characters and underscores.
+    pub fn next_word_end(&self, char_index: usize) -> usize {
+        let i = char_index.min(self.num_chars());
+        let mut chars = self.rope.chars_at(i).peekable();
+        let mut num_skipped = 0;
+        while chars.next_if(|ch| !is_word_char(*ch)).is_some() {
+            num_skipped += 1;
+        }
+        while chars.next_if(|ch| is_word_char(*ch)).is_some() {
+            num_skipped += 1;
+        }
+        i + num_skipped
     }
 
     /// Insert a new character at the given index.
@@ -28,7 +153,11 @@ impl Text {
     ///
     /// Panics if the index is too large.
     pub fn insert(&mut self, char_index: usize, character: char) {
-        self.0.insert(self.byte_index(char_index), character);
+        if char_index > self.num_chars() {
+            bug!("Text - character index is out of range");
+        }
+        self.rope.insert_char(char_index, character);
+        self.edited();
     }
 
     /// Remove and return the character at the given index.
@@ -37,25 +166,57 @@ impl Text {
     ///
     /// Panics if the index is too large.
     pub fn delete(&mut self, char_index: usize) -> char {
-        self.0.remove(self.byte_index(char_index))
+        if char_index >= self.num_chars() {
+            bug!("Text - character index is out of range");
+        }
+        let ch = self.rope.char(char_index);
+        self.rope.remove(char_index..char_index + 1);
+        self.edited();
+        ch
     }
 
     /// Set the text to the given string, replacing the current contents.
     pub fn set(&mut self, s: String) {
-        self.0 = s;
+        self.rope = Rope::from_str(&s);
+        self.edited();
+        // Save copying a long text back out of the rope.
+        if self.rope.slice(..).as_str().is_none() {
+            let _ = self.contiguous.set(s);
+        }
+    }
+
+    /// Forget everything computed from the old text.
+    fn edited(&mut self) {
+        self.contiguous.take();
+        self.display.take();
+    }
+
+    fn display(&self) -> Option<&str> {
+        self.display
+            .get_or_init(|| {
+                let string = self.as_str();
+                if bidi::has_rtl(string) {
+                    Some(BidiLayout::new(string).reorder(string))
+                } else {
+                    None
+                }
+            })
+            .as_deref()
     }
 
     fn byte_index(&self, char_index: usize) -> usize {
-        self.0
-            .char_indices()
-            .map(|(i, _)| i)
-            // The byte index at the end of the string
-            .chain(iter::once(self.0.len()))
-            .nth(char_index)
-            .bug_msg("Text - character index is out of range")
+        if char_index > self.num_chars() {
+            bug!("Text - character index is out of range");
+        }
+        self.rope.char_to_byte(char_index)
     }
 }
 
+/// Combining characters (like accents) count as part of the word they're attached to.
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_' || ch.width() == Some(0)
+}
+
 impl Default for Text {
     fn default() -> Self {
         Text::new()
@@ -96,6 +257,119 @@ mod tests {
         t.delete(2);
     }
 
+    #[test]
+    fn test_graphemes() {
+        let mut t = Text::new();
+        t.set("e\u{301}一".to_owned());
+        assert!(t.is_grapheme_boundary(0));
+        assert!(!t.is_grapheme_boundary(1));
+        assert!(t.is_grapheme_boundary(2));
+        assert!(t.is_grapheme_boundary(3));
+        assert_eq!(t.next_grapheme_boundary(0), 2);
+        assert_eq!(t.next_grapheme_boundary(2), 3);
+        assert_eq!(t.next_grapheme_boundary(3), 3);
+        assert_eq!(t.prev_grapheme_boundary(3), 2);
+        assert_eq!(t.prev_grapheme_boundary(2), 0);
+        assert_eq!(t.prev_grapheme_boundary(0), 0);
+        assert_eq!(t.display_width(), 3);
+
+        // A family emoji (three people joined by zero-width joiners), a thumbs up with a skin tone,
+        // and two flags.
+        t.set("👨\u{200D}👩\u{200D}👧👍🏽🇫🇷🇯🇵".to_owned());
+        assert_eq!(t.num_chars(), 11);
+        assert_eq!(t.next_grapheme_boundary(0), 5);
+        assert_eq!(t.next_grapheme_boundary(5), 7);
+        assert_eq!(t.next_grapheme_boundary(7), 9);
+        assert_eq!(t.prev_grapheme_boundary(11), 9);
+        assert!(!t.is_grapheme_boundary(8));
+        assert!(!t.is_grapheme_boundary(10));
+        assert_eq!(t.display_width(), 8);
+
+        // Devanagari "namaste": न, म, स with a virama and त (a conjunct), and the vowel sign े.
+        t.set("नमस्ते".to_owned());
+        assert_eq!(t.num_chars(), 6);
+        assert_eq!(t.next_grapheme_boundary(0), 1);
+        assert_eq!(t.next_grapheme_boundary(1), 2);
+        assert_eq!(t.next_grapheme_boundary(2), 6);
+        assert_eq!(t.prev_grapheme_boundary(6), 2);
+
+        t.set("a\r\nb".to_owned());
+        assert_eq!(t.next_grapheme_boundary(1), 3);
+        assert_eq!(t.prev_grapheme_boundary(3), 1);
+    }
+
+    #[test]
+    fn test_words() {
+        let mut t = Text::new();
+        t.set("foo_bar, baz  (e\u{301}t)".to_owned());
+        assert_eq!(t.next_word_end(0), 7);
+        assert_eq!(t.next_word_end(7), 12);
+        assert_eq!(t.next_word_end(12), 18);
+        assert_eq!(t.next_word_end(18), 19);
+        assert_eq!(t.next_word_end(19), 19);
+        assert_eq!(t.prev_word_start(19), 15);
+        assert_eq!(t.prev_word_start(15), 9);
+        assert_eq!(t.prev_word_start(9), 0);
+        assert_eq!(t.prev_word_start(3), 0);
+        assert_eq!(t.prev_word_start(0), 0);
+    }
+
+    #[test]
+    fn test_bidi() {
+        let mut t = Text::new();
+        t.set("abc".to_owned());
+        assert_eq!(t.as_display_str(), "abc");
+        assert_eq!(t.display_index(1), 1);
+
+        // Right-to-left text is reversed, and the cursor moves leftwards through it.
+        t.set("אבג".to_owned());
+        assert_eq!(t.as_display_str(), "גבא");
+        assert_eq!(t.display_index(0), 3);
+        assert_eq!(t.display_index(1), 2);
+        assert_eq!(t.display_index(3), 0);
+
+        // Left-to-right text and numbers keep their order inside right-to-left text.
+        t.set("אב abc 12".to_owned());
+        assert_eq!(t.as_display_str(), "abc 12 בא");
+
+        // Right-to-left runs inside left-to-right text are reversed in place, with their
+        // brackets mirrored.
+        t.set("x (אב) y".to_owned());
+        assert_eq!(t.as_display_str(), "x (בא) y");
+        t.set("x א(ב y".to_owned());
+        assert_eq!(t.as_display_str(), "x ב)א y");
+
+        t.insert(0, 'a');
+        t.delete(0);
+        t.set("plain".to_owned());
+        assert_eq!(t.as_display_str(), "plain");
+    }
+
+    #[test]
+    fn test_long_text() {
+        let mut t = Text::new();
+        t.set("aé一".repeat(1000));
+        assert_eq!(t.num_chars(), 3000);
+        assert!(t.chunks().count() > 1);
+
+        // Edit in the middle, and look at pieces spread across the rope's chunks.
+        t.insert(1500, 'x');
+        t.insert(1502, 'y');
+        assert_eq!(t.slice(1499, 1504), "一xayé");
+        assert_eq!(t.delete(1500), 'x');
+        assert_eq!(t.slice(1498, 1503), "é一ayé");
+        assert_eq!(t.slice(2, 4), "一a");
+        assert_eq!(t.slice(2998, 3001), "aé一");
+        assert_eq!(t.delete(1501), 'y');
+        assert_eq!(t.as_split_str(1500).0.chars().count(), 1500);
+        assert_eq!(t.as_str(), "aé一".repeat(1000));
+        assert_eq!(t.chunks().collect::<String>(), t.as_str());
+        assert_eq!(t.num_chars(), 3000);
+        assert_eq!(t.display_width(), 4000);
+        assert_eq!(t.prev_word_start(1500), 0);
+        assert_eq!(t.next_grapheme_boundary(1500), 1501);
+    }
+
     #[test]
     #[allow(clippy::cognitive_complexity)]
     fn test_edit() {
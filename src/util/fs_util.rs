@@ -44,3 +44,50 @@ pub fn path_file_name(path: &str) -> Result<String, SynlessError> {
 pub fn join_path(path_1: &str, path_2: &str) -> Result<String, SynlessError> {
     path_to_string(&Path::new(path_1).join(path_2))
 }
+
+/// The size of the file at `path`, in human-readable units (e.g. "2.1kb").
+pub fn file_size(path: &str) -> Result<String, SynlessError> {
+    let len = std::fs::metadata(path)
+        .map_err(|err| error!(FileSystem, "Failed to read metadata of '{path}' ({err})"))?
+        .len();
+    let mut size = len as f64;
+    for unit in ["b", "kb", "mb", "gb"] {
+        if size < 10.0 && unit != "b" {
+            return Ok(format!("{size:.1}{unit}"));
+        } else if size < 1000.0 {
+            return Ok(format!("{size:.0}{unit}"));
+        }
+        size /= 1000.0;
+    }
+    Ok(format!("{size:.0}tb"))
+}
+
+/// The directories and the files directly inside `dir`, each sorted by name. The paths are
+/// canonical. Entries that can't be read, and paths that aren't valid unicode, are skipped.
+pub fn list_dir(dir: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>), SynlessError> {
+    let entries = std::fs::read_dir(dir).map_err(|err| {
+        error!(
+            FileSystem,
+            "Failed to list files in directory '{}' ({err})",
+            dir.display()
+        )
+    })?;
+
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in entries {
+        if let Ok(path) = entry.and_then(|e| e.path().canonicalize()) {
+            if path.to_str().is_none() {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    dirs.sort();
+    files.sort();
+    Ok((dirs, files))
+}
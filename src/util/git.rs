@@ -0,0 +1,35 @@
+use crate::util::{error, SynlessError};
+use std::path::Path;
+use std::process::Command;
+
+/// The contents of the file at `path` as of the `HEAD` commit, found by running `git`. Returns
+/// `None` if the file isn't in a git repository, or wasn't in the `HEAD` commit (for example
+/// because it's new).
+pub fn head_source(path: &Path) -> Result<Option<String>, SynlessError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| error!(FileSystem, "Path has no file name: {}", path.display()))?;
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("show")
+        .arg(format!("HEAD:./{file_name}"))
+        .output()
+        .map_err(|err| error!(FileSystem, "Failed to run git ({err})"))?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let source = String::from_utf8(output.stdout).map_err(|_| {
+        error!(
+            FileSystem,
+            "HEAD version of {} is not valid unicode",
+            path.display()
+        )
+    })?;
+    Ok(Some(source))
+}
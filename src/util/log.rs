@@ -1,10 +1,18 @@
-use crate::util::SynlessBug;
+use crate::util::{error, SynlessBug, SynlessError};
 use std::default::Default;
 use std::fmt;
 use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::{Mutex, MutexGuard, OnceLock};
 
 const LOG_PATH: &str = "log.txt";
+/// The name of the log file in the state directory, for `Log::log_to_state_dir()`.
+const STATE_LOG_FILE_NAME: &str = "synless.log";
+/// Once a log file grows past this size, it's rotated.
+const MAX_LOG_FILE_SIZE: u64 = 1024 * 1024;
+/// How many rotated log files to keep, named like `synless.log.1` (the newest) through
+/// `synless.log.3`.
+const NUM_ROTATED_LOG_FILES: usize = 3;
 
 static LOG: OnceLock<Mutex<Log>> = OnceLock::new();
 
@@ -12,7 +20,14 @@ pub use crate::log;
 
 pub struct Log {
     entries: Vec<LogEntry>,
-    log_file: fs::File,
+    log_file: LogFile,
+}
+
+/// The file that log entries are written to, as well as being kept in memory.
+struct LogFile {
+    path: PathBuf,
+    file: fs::File,
+    size: u64,
 }
 
 // TODO: time stamps
@@ -40,17 +55,33 @@ impl fmt::Display for LogLevel {
 
 impl Log {
     fn new() -> Log {
-        let log_file = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(LOG_PATH)
-            .bug_msg("Failed to open log file for writing");
+        let log_file =
+            LogFile::open(PathBuf::from(LOG_PATH)).bug_msg("Failed to open log file for writing");
         Log {
             entries: Vec::new(),
             log_file,
         }
     }
 
+    /// Write log entries to `synless.log` in the state directory (`$XDG_STATE_HOME/synless` or
+    /// `~/.local/state/synless`) from now on, instead of to `log.txt` in the working directory.
+    /// The file is rotated whenever it gets large. Returns its path.
+    pub fn log_to_state_dir() -> Result<PathBuf, SynlessError> {
+        let dir = state_dir()
+            .ok_or_else(|| error!(FileSystem, "Neither XDG_STATE_HOME nor HOME is set"))?;
+        fs::create_dir_all(&dir).map_err(|err| {
+            error!(
+                FileSystem,
+                "Failed to create state directory '{}' ({err})",
+                dir.display()
+            )
+        })?;
+        let path = dir.join(STATE_LOG_FILE_NAME);
+        let log_file = LogFile::open(path.clone())?;
+        Log::with_log(|log| log.log_file = log_file);
+        Ok(path)
+    }
+
     #[doc(hidden)]
     pub fn with_log<R>(callback: impl FnOnce(&mut Log) -> R) -> R {
         let log_mutex: &'static Mutex<Log> = LOG.get_or_init(|| Mutex::new(Log::new()));
@@ -62,7 +93,7 @@ impl Log {
     pub fn push(&mut self, entry: LogEntry) {
         use std::io::Write;
 
-        let _ = writeln!(self.log_file, "{}", entry);
+        self.log_file.write_entry(&entry);
         self.entries.push(entry);
     }
 
@@ -71,6 +102,73 @@ impl Log {
     }
 }
 
+impl LogFile {
+    fn open(path: PathBuf) -> Result<LogFile, SynlessError> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|err| {
+                error!(
+                    FileSystem,
+                    "Failed to open log file '{}' ({err})",
+                    path.display()
+                )
+            })?;
+        let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        Ok(LogFile { path, file, size })
+    }
+
+    /// Append `entry` to the file, rotating it first if it's full. Errors are ignored, since
+    /// there's nowhere to report them.
+    fn write_entry(&mut self, entry: &LogEntry) {
+        use std::io::Write;
+
+        if self.size >= MAX_LOG_FILE_SIZE {
+            if let Ok(log_file) = self.rotate() {
+                *self = log_file;
+            }
+        }
+        let line = format!("{entry}\n");
+        if self.file.write_all(line.as_bytes()).is_ok() {
+            self.size += line.len() as u64;
+        }
+    }
+
+    /// Shift the rotated files along (dropping the oldest), rename this one to be the newest of
+    /// them, and start a new file in its place.
+    fn rotate(&self) -> Result<LogFile, SynlessError> {
+        for i in (1..NUM_ROTATED_LOG_FILES).rev() {
+            let _ = fs::rename(rotated_path(&self.path, i), rotated_path(&self.path, i + 1));
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1)).map_err(|err| {
+            error!(
+                FileSystem,
+                "Failed to rotate log file '{}' ({err})",
+                self.path.display()
+            )
+        })?;
+        LogFile::open(self.path.clone())
+    }
+}
+
+/// The path of the `n`th newest rotated log file, like `synless.log.1`.
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{n}"));
+    PathBuf::from(rotated)
+}
+
+/// Where to keep state that should outlive a session but isn't worth backing up, like logs.
+fn state_dir() -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(state_home.join("synless"))
+}
+
 impl Default for Log {
     fn default() -> Log {
         Log::new()
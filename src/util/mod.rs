@@ -4,8 +4,11 @@ mod fuzzy_search;
 mod indexed_map;
 mod log;
 mod ordered_map;
+mod profile;
+mod stable_hash;
 
 pub mod fs_util;
+pub mod git;
 
 pub use bug::{bug, bug_assert, format_bug, SynlessBug};
 pub use error::{error, ErrorCategory, SynlessError};
@@ -13,3 +16,5 @@ pub use fuzzy_search::fuzzy_search;
 pub use indexed_map::IndexedMap;
 pub use log::{log, Log, LogEntry, LogLevel};
 pub use ordered_map::OrderedMap;
+pub use profile::{Profiler, TimingStats};
+pub use stable_hash::StableHasher;
@@ -58,6 +58,16 @@ impl<K: Eq, V> OrderedMap<K, V> {
         }
     }
 
+    /// Remove the entry for `key`, if any, returning its value.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let index = self.index(key)?;
+        Some(self.0.remove(index).1)
+    }
+
     /// Iterate over the keys in insertion order.
     pub fn keys(&self) -> impl Iterator<Item = &K> {
         self.0.iter().map(|(key, _)| key)
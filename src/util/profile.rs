@@ -0,0 +1,69 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// How many of the most recent timings to keep for each label, to compute percentiles from.
+const MAX_SAMPLES: usize = 1000;
+
+/// Timings of the editor's hot paths (running commands, laying out the visible doc, redrawing),
+/// aggregated by label, so that slowdowns can be seen from inside the editor.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    timings: HashMap<&'static str, Timing>,
+}
+
+#[derive(Debug, Default)]
+struct Timing {
+    count: u64,
+    total: Duration,
+    recent: VecDeque<Duration>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimingStats {
+    pub label: &'static str,
+    /// How many times it's been timed.
+    pub count: u64,
+    /// The average of all of its timings.
+    pub mean: Duration,
+    /// The 95th percentile of its most recent timings.
+    pub p95: Duration,
+}
+
+impl Profiler {
+    pub fn record(&mut self, label: &'static str, duration: Duration) {
+        let timing = self.timings.entry(label).or_default();
+        timing.count += 1;
+        timing.total += duration;
+        if timing.recent.len() == MAX_SAMPLES {
+            timing.recent.pop_front();
+        }
+        timing.recent.push_back(duration);
+    }
+
+    /// The stats for each label, in alphabetical order.
+    pub fn stats(&self) -> Vec<TimingStats> {
+        let mut stats = self
+            .timings
+            .iter()
+            .map(|(label, timing)| {
+                let mut recent = timing.recent.iter().copied().collect::<Vec<_>>();
+                recent.sort();
+                let p95_index = (recent.len() * 95).div_ceil(100).saturating_sub(1);
+                TimingStats {
+                    label: *label,
+                    count: timing.count,
+                    mean: Duration::from_secs_f64(
+                        timing.total.as_secs_f64() / timing.count.max(1) as f64,
+                    ),
+                    p95: recent.get(p95_index).copied().unwrap_or_default(),
+                }
+            })
+            .collect::<Vec<_>>();
+        stats.sort_by_key(|stat| stat.label);
+        stats
+    }
+
+    pub fn clear(&mut self) {
+        self.timings.clear();
+    }
+}
@@ -1,24 +1,522 @@
 use std::fs;
 use std::path::Path;
-use synless::{parsing::JsonParser, DocName, Engine, Settings};
+use synless::{
+    parsing::{build_doc, JsonParser, Parse, ParsedTree},
+    BookmarkCommand, ClipboardCommand, Dictionary, DocName, Engine, JsonSchema, Node, Search,
+    Settings, Storage, SynlessError, TreeEdCommand,
+};
 
 const JSON_PATH: &str = "data/json_lang.ron";
+const LANGUAGE_NAME: &str = "json";
 
-#[test]
-fn test_json() {
+/// An engine with the json language and parser loaded.
+fn json_engine() -> Engine {
     let mut engine = Engine::new(Settings::default());
-
     let json_lang_ron = fs::read_to_string(JSON_PATH).unwrap();
     let language_name = engine
         .load_language_ron(Path::new(JSON_PATH), &json_lang_ron)
         .unwrap();
-    engine.add_parser(&language_name, JsonParser);
+    assert_eq!(language_name, LANGUAGE_NAME);
+    engine.add_parser(LANGUAGE_NAME, JsonParser);
+    engine
+}
 
-    let doc_name = DocName::Auxilliary("<testing>".to_owned());
-    let source = "{\"primitives\": [true, false, null, 5.3, \"string!\"]}";
+/// Load the json `source` into an auxilliary doc called `name`, and make it the visible doc.
+fn load_doc(engine: &mut Engine, name: &str, source: &str) -> DocName {
+    let doc_name = DocName::Auxilliary(name.to_owned());
     engine
-        .load_doc_from_source(doc_name.clone(), &language_name, source)
+        .load_doc_from_source(doc_name.clone(), LANGUAGE_NAME, source)
         .unwrap();
+    engine.set_visible_doc(&doc_name).unwrap();
+    doc_name
+}
+
+/// The first node in the visible doc with the text `text`.
+fn find_text(engine: &Engine, text: &str) -> Node {
+    engine
+        .visible_doc_nodes()
+        .unwrap()
+        .into_iter()
+        .find(|node| {
+            node.text(engine.raw_storage())
+                .is_some_and(|node_text| node_text.as_str() == text)
+        })
+        .unwrap()
+}
+
+/// The text of the node at the cursor in the visible doc.
+fn cursor_text(engine: &Engine) -> String {
+    let s = engine.raw_storage();
+    let node = engine.visible_doc().unwrap().node_at_cursor(s).unwrap();
+    node.text(s).unwrap().as_str().to_owned()
+}
+
+#[test]
+fn test_json() {
+    let mut engine = json_engine();
+
+    let source = "{\"primitives\": [true, false, null, 5.3, \"string!\"]}";
+    let doc_name = load_doc(&mut engine, "<testing>", source);
     let output = engine.print_source(&doc_name).unwrap();
     assert_eq!(output, source);
 }
+
+#[test]
+fn test_json_schema() {
+    let mut engine = json_engine();
+
+    let source = "{\"name\": \"\", \"port\": 80.5, \"tags\": [\"a\", \"a\"], \"extra\": null}";
+    let doc_name = load_doc(&mut engine, "<testing>", source);
+
+    let schema = JsonSchema::parse(
+        r#"{
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "minLength": 1 },
+                "port": { "type": "integer", "minimum": 1024 },
+                "tags": { "type": "array", "items": { "type": "string" }, "uniqueItems": true }
+            },
+            "required": ["name", "version"],
+            "additionalProperties": false
+        }"#,
+    )
+    .unwrap();
+    engine.set_json_schema(Some(schema)).unwrap();
+    engine.set_diagnostics(Vec::new()).unwrap();
+    let messages = engine
+        .diagnostics()
+        .unwrap()
+        .iter()
+        .map(|diag| diag.message.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        messages,
+        vec![
+            "Missing required property 'version'",
+            "Expected at least 1 characters",
+            "Expected integer, found number",
+            "Duplicate item",
+            "Property 'extra' is not allowed",
+        ]
+    );
+}
+
+#[test]
+fn test_spell_check() {
+    let mut engine = json_engine();
+
+    let source = "{\"greting\": \"Helo wrld, from Paris\", \"city\": \"paris\", \"id\": \"x86\"}";
+    let doc_name = load_doc(&mut engine, "<testing>", source);
+
+    assert!(engine.spell_check().is_err());
+    let dictionary = Dictionary::parse("5\nhello\nhelp\nworld/S\nfrom\nParis\n", None);
+    assert_eq!(dictionary.num_words(), 5);
+    engine.set_dictionary(Some(dictionary));
+
+    // Keys aren't prose, and lowercase names are misspelled.
+    assert_eq!(engine.spell_check().unwrap(), 3);
+    let misspelled = |engine: &Engine| {
+        engine
+            .misspellings()
+            .unwrap()
+            .iter()
+            .map(|misspelling| (misspelling.start, misspelling.word.clone()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        misspelled(&engine),
+        vec![
+            (0, "Helo".to_owned()),
+            (5, "wrld".to_owned()),
+            (0, "paris".to_owned())
+        ]
+    );
+
+    let id = engine.misspellings().unwrap()[0].node;
+    let node = engine.visible_node_with_id(id).unwrap();
+    engine.goto_node(node).unwrap();
+    let (word, suggestions) = engine.spelling_suggestions().unwrap();
+    assert_eq!(word, "Helo");
+    assert_eq!(suggestions, vec!["Hello", "Help"]);
+
+    engine.correct_spelling("Hello").unwrap();
+    assert_eq!(
+        engine.print_source(&doc_name).unwrap(),
+        "{\"greting\": \"Hello wrld, from Paris\", \"city\": \"paris\", \"id\": \"x86\"}"
+    );
+    assert_eq!(
+        misspelled(&engine),
+        vec![(6, "wrld".to_owned()), (0, "paris".to_owned())]
+    );
+
+    engine.set_dictionary(None);
+    assert!(engine.misspellings().unwrap().is_empty());
+}
+
+#[test]
+fn test_dictionary_affixes() {
+    let aff = "
+        # Comments and unsupported options are ignored.
+        TRY esianrtolcdugmphbyfvkwz
+        PFX U Y 1
+        PFX U   0     un         .
+        SFX D Y 3
+        SFX D   0     d          e
+        SFX D   y     ied        [^aeiou]y
+        SFX D   0     ed         [^ey]
+        SFX N N 1
+        SFX N   e     ion        e
+    ";
+    let dic = "4\ndo/U\ntie/UD\ncry/D\ncreate/NU\n";
+    let dictionary = Dictionary::parse(dic, Some(aff));
+    for word in [
+        "do", "undo", "tie", "tied", "untie", "untied", "cry", "cried", "create", "creation",
+        "uncreate",
+    ] {
+        assert!(dictionary.is_correct(word), "{word}");
+    }
+    // Conditions must match, and `N` doesn't combine with prefixes.
+    for word in ["doed", "cryed", "creatd", "uncreation", "uncry"] {
+        assert!(!dictionary.is_correct(word), "{word}");
+    }
+
+    // Without the `.aff` file, the flags are ignored.
+    let dictionary = Dictionary::parse(dic, None);
+    assert!(dictionary.is_correct("tie"));
+    assert!(!dictionary.is_correct("tied"));
+
+    // Long flags are pairs of chars.
+    let aff = "FLAG long\nSFX Aa Y 1\nSFX Aa 0 s .\n";
+    let dictionary = Dictionary::parse("cat/Aa\n", Some(aff));
+    assert!(dictionary.is_correct("cats"));
+}
+
+#[test]
+fn test_incremental_search() {
+    let mut engine = json_engine();
+
+    let source = "[\"apple\", \"banana\", \"cherry\", \"avocado\"]";
+    let doc_name = load_doc(&mut engine, "<testing>", source);
+
+    let banana = find_text(&engine, "banana");
+    engine.goto_node(banana).unwrap();
+
+    // Previews go to the first match after the cursor, or else the last one before it.
+    engine.begin_incremental_search().unwrap();
+    assert!(engine.begin_incremental_search().is_err());
+    let preview = |engine: &mut Engine, substring: &str| {
+        let search = Search::new_substring(substring.to_owned());
+        engine.preview_search(Some(search)).unwrap()
+    };
+    assert!(preview(&mut engine, "a"));
+    assert_eq!(cursor_text(&engine), "avocado");
+    assert!(preview(&mut engine, "app"));
+    assert_eq!(cursor_text(&engine), "apple");
+    assert!(!preview(&mut engine, "zzz"));
+    assert_eq!(cursor_text(&engine), "banana");
+    assert!(preview(&mut engine, "cherry"));
+    engine.end_incremental_search(true).unwrap();
+    assert_eq!(cursor_text(&engine), "cherry");
+
+    // Cancelling puts back the cursor and the search from before.
+    engine.begin_incremental_search().unwrap();
+    assert!(preview(&mut engine, "avo"));
+    assert_eq!(cursor_text(&engine), "avocado");
+    engine.end_incremental_search(false).unwrap();
+    assert_eq!(cursor_text(&engine), "cherry");
+    let s = engine.raw_storage();
+    let doc = engine.visible_doc().unwrap();
+    let cherry = doc.node_at_cursor(s).unwrap();
+    assert!(doc.search().unwrap().matches(s, cherry));
+    assert!(!doc.search().unwrap().matches(s, banana));
+
+    // Committing without a search keeps the search from before too.
+    engine.begin_incremental_search().unwrap();
+    assert!(!engine.preview_search(None).unwrap());
+    engine.end_incremental_search(true).unwrap();
+    let s = engine.raw_storage();
+    let doc = engine.visible_doc().unwrap();
+    assert!(doc.search().unwrap().matches(s, cherry));
+    assert!(engine.end_incremental_search(true).is_err());
+}
+
+#[test]
+fn test_paste_text() {
+    let mut engine = json_engine();
+
+    let doc_name = load_doc(&mut engine, "<testing>", "[1]");
+
+    // Text that parses replaces the value at the cursor
+    assert_eq!(engine.paste_text("[true, 2]").unwrap(), 1);
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[true, 2]");
+
+    // Text that doesn't parse becomes a string
+    assert_eq!(engine.paste_text("not json").unwrap(), 1);
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "\"not json\"");
+
+    engine.undo().unwrap();
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[true, 2]");
+}
+
+#[test]
+fn test_lazy_load() {
+    let mut engine = json_engine();
+    engine.set_lazy_load_size(1);
+
+    // Strings containing separators and brackets mustn't split elements.
+    let elements = (0..1000)
+        .map(|i| format!("{{\"id\": {i}, \"text\": \"a, [b] \\\"{{c}}\\\"\"}}"))
+        .collect::<Vec<_>>();
+    let source = format!("[{}]", elements.join(", "));
+
+    let eager_doc_name = load_doc(&mut engine, "eager", &source);
+    let lazy_doc_name = DocName::Auxilliary("lazy".to_owned());
+    let is_lazy = engine
+        .load_doc_lazily(lazy_doc_name.clone(), LANGUAGE_NAME, &source)
+        .unwrap();
+    assert!(is_lazy);
+    assert!(engine.is_loading(&lazy_doc_name));
+    assert!(engine.get_doc(&lazy_doc_name).unwrap().is_read_only());
+    assert!(engine.print_source(&lazy_doc_name).is_err());
+
+    engine.finish_loading(&lazy_doc_name).unwrap();
+    assert!(!engine.is_loading(&lazy_doc_name));
+    assert!(!engine.get_doc(&lazy_doc_name).unwrap().is_read_only());
+    assert_eq!(
+        engine.print_source(&lazy_doc_name).unwrap(),
+        engine.print_source(&eager_doc_name).unwrap()
+    );
+}
+
+#[test]
+fn test_background_parse() {
+    let mut engine = json_engine();
+    engine.set_lazy_load_size(1);
+
+    // Not a list, so it can't be loaded a few elements at a time.
+    let doc_name = DocName::Auxilliary("<testing>".to_owned());
+    let source = "\"a long string\"";
+    let is_loading = engine
+        .load_doc_lazily(doc_name.clone(), LANGUAGE_NAME, source)
+        .unwrap();
+    assert!(is_loading);
+    assert!(engine.get_doc(&doc_name).unwrap().is_read_only());
+
+    engine.finish_loading(&doc_name).unwrap();
+    assert!(!engine.is_loading(&doc_name));
+    assert!(!engine.get_doc(&doc_name).unwrap().is_read_only());
+    assert_eq!(engine.print_source(&doc_name).unwrap(), source);
+
+    let bad_doc_name = DocName::Auxilliary("<bad>".to_owned());
+    engine
+        .load_doc_lazily(bad_doc_name.clone(), LANGUAGE_NAME, "[1, 2")
+        .unwrap();
+    assert!(engine.finish_loading(&bad_doc_name).is_err());
+    assert!(engine.get_doc(&bad_doc_name).is_none());
+}
+
+/// A language whose root is a list of numbers, one per line, like a log file.
+const NUMBERS_LANG_RON: &str = r#"LanguageSpec(
+    name: "numbers",
+    file_extensions: [],
+    hole_syntax: Some(HoleSyntax(invalid: "?", valid: "?", text: "?")),
+    grammar: GrammarSpec(
+        constructs: [
+            ConstructSpec(name: "Root", arity: Listy(SortSpec(["Number"]))),
+            ConstructSpec(name: "Number", arity: Texty(None)),
+        ],
+        sorts: [],
+        root_construct: "Root",
+    ),
+    default_display_notation: "DefaultDisplay",
+    default_source_notation: None,
+    notations: [
+        NotationSetSpec(
+            name: "DefaultDisplay",
+            notations: [
+                ("Root", Fold(first: Child(0), join: Concat(Left, Concat(Newline, Right)))),
+                ("Number", Text),
+            ],
+        ),
+    ],
+)"#;
+
+/// Parses the "numbers" language: each word of the source is a number.
+#[derive(Debug)]
+struct NumbersParser;
+
+impl Parse for NumbersParser {
+    fn name(&self) -> &str {
+        "numbers_parser"
+    }
+
+    fn parse(
+        &mut self,
+        s: &mut Storage,
+        _file_name: &str,
+        source: &str,
+    ) -> Result<Node, SynlessError> {
+        let numbers = source
+            .split_whitespace()
+            .map(|word| ParsedTree::Texty("Number", word.to_owned()))
+            .collect();
+        let lang = s.language("numbers")?;
+        build_doc(s, lang, numbers)
+    }
+}
+
+#[test]
+fn test_append_doc_from_source() {
+    let mut engine = json_engine();
+
+    // A json doc's root holds a single value, so text appended to its file has to be reloaded
+    // along with the rest of it.
+    let doc_name = load_doc(&mut engine, "log", "[1, 2]\n");
+    assert!(engine
+        .append_doc_from_source(&doc_name, LANGUAGE_NAME, "[3]\n", true)
+        .is_err());
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[1, 2]");
+
+    // Whereas a doc whose root is a list gets the appended nodes added to the end of it.
+    engine
+        .load_language_ron(Path::new("numbers_lang.ron"), NUMBERS_LANG_RON)
+        .unwrap();
+    engine.add_parser("numbers", NumbersParser);
+    let doc_name = DocName::Auxilliary("numbers".to_owned());
+    engine
+        .load_doc_from_source(doc_name.clone(), "numbers", "1\n2\n")
+        .unwrap();
+    engine.set_visible_doc(&doc_name).unwrap();
+    engine
+        .append_doc_from_source(&doc_name, "numbers", "3\n4\n", true)
+        .unwrap();
+    let s = engine.raw_storage();
+    let root = engine.visible_doc_root().unwrap();
+    let children = (0..root.num_children(s).unwrap())
+        .map(|i| {
+            let child = root.nth_child(s, i).unwrap();
+            child.text(s).unwrap().as_str().to_owned()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(children, ["1", "2", "3", "4"]);
+}
+
+#[test]
+fn test_scrollbar() {
+    let mut engine = json_engine();
+
+    let numbers = (0..200).map(|i| i.to_string()).collect::<Vec<_>>();
+    load_doc(&mut engine, "numbers", &format!("[{}]", numbers.join(", ")));
+
+    // The rows of the scrollbar that the thumb covers.
+    let thumb_rows = |engine: &mut Engine| {
+        let scrollbar = engine.make_scrollbar_doc(20, 10).unwrap().unwrap();
+        let s = engine.raw_storage();
+        (0..10)
+            .filter(|i| scrollbar.nth_child(s, *i).unwrap().construct(s).name(s) == "Thumb")
+            .collect::<Vec<_>>()
+    };
+
+    assert!(engine.make_scrollbar_doc(20, 10).unwrap().is_none());
+    assert!(engine.toggle_scrollbar());
+    let rows = thumb_rows(&mut engine);
+    assert_eq!(rows.first(), Some(&0));
+    assert!(rows.len() < 10);
+
+    let s = engine.raw_storage();
+    let array = engine.visible_doc_root().unwrap().first_child(s).unwrap();
+    let last = array.last_child(s).unwrap();
+    engine.goto_node(last).unwrap();
+    let rows = thumb_rows(&mut engine);
+    assert_eq!(rows.last(), Some(&9));
+    assert!(rows.len() < 10);
+}
+
+#[test]
+fn test_compact_nodes() {
+    let mut engine = json_engine();
+
+    let numbers = (0..1000).map(|i| i.to_string()).collect::<Vec<_>>();
+    let big_doc_name = load_doc(&mut engine, "big", &format!("[{}]", numbers.join(", ")));
+    let doc_name = load_doc(&mut engine, "<testing>", "[1, 2, 3]");
+
+    // Leave nodes on the clipboard, in the undo history, and in a bookmark.
+    let two = find_text(&engine, "2");
+    engine.goto_node(two).unwrap();
+    engine.execute(BookmarkCommand::Save('a')).unwrap();
+    engine.execute(ClipboardCommand::Copy).unwrap();
+    let one = find_text(&engine, "1");
+    engine.goto_node(one).unwrap();
+    engine.execute(TreeEdCommand::Delete).unwrap();
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[2, 3]");
+
+    engine.set_visible_doc(&big_doc_name).unwrap();
+    engine.close_visible_doc().unwrap();
+    engine.set_visible_doc(&doc_name).unwrap();
+    let num_nodes = engine.raw_storage().num_nodes();
+    let capacity = engine.raw_storage().node_capacity();
+
+    let remapping = engine.compact_nodes();
+    let s = engine.raw_storage();
+    assert_eq!(s.num_nodes(), num_nodes);
+    assert!(s.node_capacity() < capacity);
+    assert!(!two.is_valid(s));
+    let two = remapping.get(two).unwrap();
+    assert_eq!(two.text(s).unwrap().as_str(), "2");
+
+    // Everything the engine held on to still works.
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[2, 3]");
+    engine.execute(BookmarkCommand::Goto('a')).unwrap();
+    assert_eq!(cursor_text(&engine), "2");
+    engine.undo().unwrap();
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[1, 2, 3]");
+    engine.execute(ClipboardCommand::Paste).unwrap();
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[1, 2, 2, 3]");
+}
+
+#[test]
+fn test_move_node() {
+    let mut engine = json_engine();
+
+    let doc_name = load_doc(&mut engine, "<testing>", "[1, 2, 3]");
+
+    // Moving doesn't disturb the clipboard.
+    engine.goto_node(find_text(&engine, "2")).unwrap();
+    engine.execute(ClipboardCommand::Copy).unwrap();
+
+    let one = find_text(&engine, "1");
+    let three = find_text(&engine, "3");
+    engine.move_node(one, three).unwrap();
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[2, 3, 1]");
+
+    // Can't move a node into itself.
+    let root = engine.visible_doc_root().unwrap();
+    let array = root.first_child(engine.raw_storage()).unwrap();
+    assert!(engine.move_node(array, three).is_err());
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[2, 3, 1]");
+
+    // The move is undone all at once.
+    engine.undo().unwrap();
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[1, 2, 3]");
+    engine.execute(ClipboardCommand::Paste).unwrap();
+    assert_eq!(engine.print_source(&doc_name).unwrap(), "[1, 2, 2, 3]");
+}
+
+#[test]
+fn test_replace_from_doc() {
+    let mut engine = json_engine();
+
+    let theirs = load_doc(&mut engine, "theirs", "[1, 2, 3]");
+    engine.goto_node(find_text(&engine, "2")).unwrap();
+    let ours = load_doc(&mut engine, "ours", "[1, 5, 3]");
+    engine.goto_node(find_text(&engine, "5")).unwrap();
+
+    assert!(!engine.diff_with_doc(&theirs).unwrap().is_empty());
+    engine.replace_from_doc(&theirs).unwrap();
+    assert_eq!(engine.print_source(&ours).unwrap(), "[1, 2, 3]");
+    assert_eq!(engine.print_source(&theirs).unwrap(), "[1, 2, 3]");
+
+    // It's an ordinary edit.
+    engine.undo().unwrap();
+    assert_eq!(engine.print_source(&ours).unwrap(), "[1, 5, 3]");
+}
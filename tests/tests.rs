@@ -1,7 +1,7 @@
 use partial_pretty_printer as ppp;
 use synless::{
-    AritySpec, ConstructSpec, DocRef, GrammarSpec, LanguageSpec, Node, NotationSetSpec, SortSpec,
-    Storage,
+    merge, AritySpec, ConstructSpec, DiffOp, DocRef, GrammarSpec, LanguageError, LanguageSpec,
+    Location, NavOrder, Node, NodeChange, NotationSetSpec, SortSpec, Storage, TreeError, Visit,
 };
 
 // e.g. example.com?p1=v1,p2=v2,p3,p4=v4
@@ -19,7 +19,11 @@ fn urllang() -> LanguageSpec {
                     name: "String".to_owned(),
                     arity: AritySpec::Texty(Some("[a-zA-Z.]+".to_owned())),
                     is_comment_or_ws: false,
+                    is_hidden: false,
+                    is_prose: false,
                     key: Some('s'),
+                    auto_fill: None,
+                    summary: None,
                 },
                 ConstructSpec {
                     name: "Equals".to_owned(),
@@ -28,13 +32,21 @@ fn urllang() -> LanguageSpec {
                         SortSpec(vec!["String".to_owned()]),
                     ]),
                     is_comment_or_ws: false,
+                    is_hidden: false,
+                    is_prose: false,
                     key: Some('='),
+                    auto_fill: None,
+                    summary: None,
                 },
                 ConstructSpec {
                     name: "Params".to_owned(),
                     arity: AritySpec::Listy(SortSpec(vec!["param".to_owned()])),
                     is_comment_or_ws: false,
+                    is_hidden: false,
+                    is_prose: false,
                     key: None,
+                    auto_fill: None,
+                    summary: None,
                 },
                 ConstructSpec {
                     name: "Url".to_owned(),
@@ -43,7 +55,11 @@ fn urllang() -> LanguageSpec {
                         SortSpec(vec!["Params".to_owned()]),
                     ]),
                     is_comment_or_ws: false,
+                    is_hidden: false,
+                    is_prose: false,
                     key: None,
+                    auto_fill: None,
+                    summary: None,
                 },
             ],
             sorts: vec![(
@@ -55,6 +71,9 @@ fn urllang() -> LanguageSpec {
         default_display_notation: "Testlang_notation".to_owned(),
         default_source_notation: None,
         hole_syntax: None,
+        include_construct: None,
+        listy_nav_order: NavOrder::Document,
+        surrounds: Vec::new(),
         notations: vec![NotationSetSpec {
             name: "Testlang_notation".to_owned(),
             notations: vec![
@@ -104,6 +123,23 @@ fn node_with_children(
     Node::with_children(s, construct, children).unwrap()
 }
 
+/// `example.com?p1=v1&p2=v2...`, with a value for each of `vals`, and with `extra` as a param on its
+/// own after the first one.
+fn make_url(s: &mut Storage, vals: &[&str], extra: Option<&str>) -> Node {
+    let mut params = Vec::new();
+    for (i, val) in vals.iter().enumerate() {
+        let param = node_with_text(s, "urllang", "String", &format!("p{}", i + 1));
+        let val = node_with_text(s, "urllang", "String", val);
+        params.push(node_with_children(s, "urllang", "Equals", [param, val]));
+        if let (0, Some(extra)) = (i, extra) {
+            params.push(node_with_text(s, "urllang", "String", extra));
+        }
+    }
+    let params = node_with_children(s, "urllang", "Params", params);
+    let domain = node_with_text(s, "urllang", "String", "example.com");
+    node_with_children(s, "urllang", "Url", [domain, params])
+}
+
 #[test]
 fn test_doc_ref() {
     let mut s = Storage::new();
@@ -121,7 +157,7 @@ fn test_doc_ref() {
     let params = node_with_children(&mut s, "urllang", "Params", [eq_1, eq_2, done]);
     let url = node_with_children(&mut s, "urllang", "Url", [domain, params]);
 
-    let doc_ref = DocRef::new_display(&s, None, url, &None);
+    let doc_ref = DocRef::new_display(&s, None, url, &None, None, false);
 
     let actual = match ppp::pretty_print_to_string(doc_ref, 80) {
         Ok(actual) => actual,
@@ -143,3 +179,612 @@ fn test_doc_ref() {
     assert!(bad_text.is_invalid_text(&s));
     assert!(!eq_1.is_invalid_text(&s));
 }
+
+#[test]
+fn test_node_summary() {
+    let mut s = Storage::new();
+    s.add_language(urllang()).unwrap();
+    let c_equals = s
+        .language("urllang")
+        .unwrap()
+        .construct(&s, "Equals")
+        .unwrap();
+
+    let param = node_with_text(&mut s, "urllang", "String", "param");
+    let val = node_with_text(&mut s, "urllang", "String", "val");
+    let eq = node_with_children(&mut s, "urllang", "Equals", [param, val]);
+    assert_eq!(param.summary(&s), "param");
+    assert_eq!(eq.summary(&s), "Equals");
+
+    c_equals.set_summary_template(&mut s, Some("$0 = $1 ($2, $$)".to_owned()));
+    assert_eq!(eq.summary(&s), "param = val (, $$)");
+
+    let long = node_with_text(&mut s, "urllang", "String", &"a".repeat(50));
+    assert_eq!(long.summary(&s), format!("{}…", "a".repeat(39)));
+}
+
+#[test]
+fn test_selection() {
+    let mut s = Storage::new();
+    s.add_language(urllang()).unwrap();
+
+    let params = ["a", "b", "c"].map(|text| node_with_text(&mut s, "urllang", "String", text));
+    let [a, b, c] = params;
+    let list = node_with_children(&mut s, "urllang", "Params", params);
+    let other = node_with_text(&mut s, "urllang", "String", "other");
+
+    assert_eq!(Location::at(&s, c).selection(&s, a), Some(vec![a, b, c]));
+    assert_eq!(Location::at(&s, a).selection(&s, b), Some(vec![a, b]));
+    assert_eq!(Location::at(&s, b).selection(&s, b), Some(vec![b]));
+    assert_eq!(Location::at(&s, a).selection(&s, other), None);
+    assert_eq!(Location::at(&s, a).selection(&s, list), None);
+}
+
+/// `urllang`, plus a listy `Group` construct that holds params, and is one.
+fn urllang_with_group() -> LanguageSpec {
+    use ppp::notation_constructors::{child, count, empty, fold, left, lit, right, Count, Fold};
+
+    let mut lang = urllang();
+    lang.grammar.constructs.push(ConstructSpec {
+        name: "Group".to_owned(),
+        arity: AritySpec::Listy(SortSpec(vec!["param".to_owned()])),
+        is_comment_or_ws: false,
+        is_hidden: false,
+        is_prose: false,
+        key: None,
+        auto_fill: None,
+        summary: None,
+    });
+    let (_, SortSpec(param_sort)) = &mut lang.grammar.sorts[0];
+    param_sort.push("Group".to_owned());
+    lang.notations[0].notations.push((
+        "Group".to_owned(),
+        lit("(")
+            + count(Count {
+                zero: empty(),
+                one: child(0),
+                many: fold(Fold {
+                    first: child(0),
+                    join: left() + lit("&") + right(),
+                }),
+            })
+            + lit(")"),
+    ));
+    lang
+}
+
+#[test]
+fn test_wrap_and_unwrap() {
+    let mut s = Storage::new();
+    s.add_language(urllang_with_group()).unwrap();
+    let lang = s.language("urllang").unwrap();
+    let c_url = lang.construct(&s, "Url").unwrap();
+    let c_equals = lang.construct(&s, "Equals").unwrap();
+    let c_group = lang.construct(&s, "Group").unwrap();
+
+    // example.com?k=v&c
+    let domain = node_with_text(&mut s, "urllang", "String", "example.com");
+    let key = node_with_text(&mut s, "urllang", "String", "k");
+    let val = node_with_text(&mut s, "urllang", "String", "v");
+    let eq = node_with_children(&mut s, "urllang", "Equals", [key, val]);
+    let c = node_with_text(&mut s, "urllang", "String", "c");
+    let params = node_with_children(&mut s, "urllang", "Params", [eq, c]);
+    let url = node_with_children(&mut s, "urllang", "Url", [domain, params]);
+
+    // A fixed wrapper takes the node in the first hole it fits in, or else the given one, and
+    // gets a hole back in its place when unwrapped.
+    let equals = Node::new(&mut s, c_equals);
+    let mut loc = Location::at(&s, c);
+    loc.wrap_node(&mut s, equals).unwrap();
+    assert_eq!(loc, Location::at(&s, equals));
+    assert_eq!(params.nth_child(&s, 1), Some(equals));
+    assert_eq!(equals.nth_child(&s, 0), Some(c));
+    assert!(equals.nth_child(&s, 1).unwrap().is_hole(&s));
+    assert_eq!(loc.unwrap_node(&mut s), Ok((equals, 0)));
+    assert_eq!(loc, Location::at(&s, c));
+    assert_eq!(params.nth_child(&s, 1), Some(c));
+    assert_eq!(equals.parent(&s), None);
+    assert!(equals.nth_child(&s, 0).unwrap().is_hole(&s));
+
+    loc.wrap_node_at(&mut s, equals, 1).unwrap();
+    assert!(equals.nth_child(&s, 0).unwrap().is_hole(&s));
+    assert_eq!(equals.nth_child(&s, 1), Some(c));
+    assert_eq!(loc.unwrap_node(&mut s), Ok((equals, 1)));
+    assert_eq!(params.nth_child(&s, 1), Some(c));
+
+    // A listy wrapper is left empty when unwrapped.
+    let group = Node::new(&mut s, c_group);
+    loc.wrap_node(&mut s, group).unwrap();
+    assert_eq!(loc, Location::at(&s, group));
+    assert_eq!(params.nth_child(&s, 1), Some(group));
+    assert_eq!(group.num_children(&s), Some(1));
+    assert_eq!(group.first_child(&s), Some(c));
+    assert_eq!(loc.unwrap_node(&mut s), Ok((group, 0)));
+    assert_eq!(loc, Location::at(&s, c));
+    assert_eq!(params.nth_child(&s, 1), Some(c));
+    assert_eq!(group.num_children(&s), Some(0));
+
+    // Unwrapping fails and changes nothing, unless the node has exactly one child (not counting
+    // holes) that fits in its place.
+    let not_one_child = |name: &str| Err(TreeError::NotOneChild(name.to_owned()));
+    let mut in_text = Location::start_of_text(&s, c).unwrap();
+    assert_eq!(in_text.unwrap_node(&mut s), Err(TreeError::InvalidLocation));
+    assert_eq!(
+        Location::at(&s, url).unwrap_node(&mut s),
+        Err(TreeError::IsRoot)
+    );
+    assert_eq!(
+        Location::at(&s, c).unwrap_node(&mut s),
+        not_one_child("String")
+    );
+    assert_eq!(
+        Location::at(&s, eq).unwrap_node(&mut s),
+        not_one_child("Equals")
+    );
+    params.insert_last_child(&mut s, equals).unwrap();
+    assert_eq!(
+        Location::at(&s, equals).unwrap_node(&mut s),
+        not_one_child("Equals")
+    );
+    params.insert_last_child(&mut s, group).unwrap();
+    assert_eq!(
+        Location::at(&s, group).unwrap_node(&mut s),
+        not_one_child("Group")
+    );
+    let [x, y] = ["x", "y"].map(|text| node_with_text(&mut s, "urllang", "String", text));
+    group.insert_last_child(&mut s, x).unwrap();
+    group.insert_last_child(&mut s, y).unwrap();
+    assert_eq!(
+        Location::at(&s, group).unwrap_node(&mut s),
+        not_one_child("Group")
+    );
+
+    // other.com?only
+    let other_domain = node_with_text(&mut s, "urllang", "String", "other.com");
+    let only = node_with_text(&mut s, "urllang", "String", "only");
+    let other_params = node_with_children(&mut s, "urllang", "Params", [only]);
+    let other_url = node_with_children(&mut s, "urllang", "Url", [other_domain, other_params]);
+    assert_eq!(
+        Location::at(&s, other_params).unwrap_node(&mut s),
+        Err(TreeError::NotAllowedHere("String".to_owned()))
+    );
+    assert_eq!(other_url.nth_child(&s, 1), Some(other_params));
+    assert_eq!(other_params.first_child(&s), Some(only));
+
+    // Wrapping fails and changes nothing, unless the wrapper is a root that's fixed with a hole
+    // that the node fits in, or listy and empty, and the wrapper fits in the node's place.
+    let invalid_wrapper = |name: &str| Err(TreeError::InvalidWrapper(name.to_owned()));
+    let new_equals = Node::new(&mut s, c_equals);
+    assert_eq!(
+        in_text.wrap_node(&mut s, new_equals),
+        Err(TreeError::InvalidLocation)
+    );
+    let text = node_with_text(&mut s, "urllang", "String", "text");
+    assert_eq!(loc.wrap_node(&mut s, text), invalid_wrapper("String"));
+    assert_eq!(loc.wrap_node(&mut s, eq), invalid_wrapper("Equals"));
+    assert_eq!(
+        Location::at(&s, eq).wrap_node(&mut s, new_equals),
+        invalid_wrapper("Equals")
+    );
+    assert_eq!(
+        loc.wrap_node_at(&mut s, new_equals, 2),
+        invalid_wrapper("Equals")
+    );
+    assert_eq!(loc.wrap_node(&mut s, group), Err(TreeError::NotRoot));
+    let new_group = Node::new(&mut s, c_group);
+    assert_eq!(
+        loc.wrap_node_at(&mut s, new_group, 1),
+        invalid_wrapper("Group")
+    );
+    assert_eq!(
+        Location::at(&s, other_params).wrap_node(&mut s, new_group),
+        Err(TreeError::NotAllowedHere("Group".to_owned()))
+    );
+    let new_url = Node::new(&mut s, c_url);
+    assert_eq!(
+        loc.wrap_node(&mut s, new_url),
+        Err(TreeError::NotAllowedHere("Url".to_owned()))
+    );
+    assert!(new_url.nth_child(&s, 0).unwrap().is_hole(&s));
+    new_group.insert_last_child(&mut s, text).unwrap();
+    assert_eq!(loc.wrap_node(&mut s, new_group), invalid_wrapper("Group"));
+
+    assert_eq!(loc, Location::at(&s, c));
+    assert_eq!(params.nth_child(&s, 1), Some(c));
+    assert_eq!(other_url.nth_child(&s, 1), Some(other_params));
+    assert_eq!(new_equals.parent(&s), None);
+    assert_eq!(new_group.first_child(&s), Some(text));
+}
+
+#[test]
+fn test_splice_and_group() {
+    let mut s = Storage::new();
+    s.add_language(urllang_with_group()).unwrap();
+    let c_group = s
+        .language("urllang")
+        .unwrap()
+        .construct(&s, "Group")
+        .unwrap();
+
+    let params = ["a", "b", "c"].map(|text| node_with_text(&mut s, "urllang", "String", text));
+    let [a, b, c] = params;
+    let inner = node_with_children(&mut s, "urllang", "Group", [a, b]);
+    let outer = node_with_children(&mut s, "urllang", "Params", [inner, c]);
+    let children = |s: &Storage, node: Node| {
+        std::iter::successors(node.first_child(s), |child| child.next_sibling(s))
+            .collect::<Vec<_>>()
+    };
+
+    // ?(a&b)&c -> ?a&b&c
+    inner.splice(&mut s).unwrap();
+    assert_eq!(children(&s, outer), params);
+    assert!(inner.is_root(&s));
+    assert_eq!(inner.num_children(&s), Some(0));
+
+    // ?a&b&c -> ?(a&b)&c
+    inner.group(&mut s, a, b).unwrap();
+    assert_eq!(children(&s, outer), [inner, c]);
+    assert_eq!(children(&s, inner), [a, b]);
+
+    let wrapper = Node::new(&mut s, c_group);
+    assert_eq!(outer.splice(&mut s), Err(TreeError::IsRoot));
+    assert_eq!(
+        c.splice(&mut s),
+        Err(TreeError::NotListy("String".to_owned()))
+    );
+    assert_eq!(wrapper.group(&mut s, c, inner), Err(TreeError::NotSiblings));
+    assert_eq!(inner.group(&mut s, c, c), Err(TreeError::NotRoot));
+    assert_eq!(children(&s, outer), [inner, c]);
+
+    outer.delete_root(&mut s).unwrap();
+    wrapper.delete_root(&mut s).unwrap();
+}
+
+/// Compiles `urllang`, after giving `construct_name` the auto_fill `fills`.
+fn urllang_with_auto_fill(
+    s: &mut Storage,
+    construct_name: &str,
+    fills: &[Option<&str>],
+) -> Result<(), LanguageError> {
+    let mut lang = urllang();
+    let construct = lang
+        .grammar
+        .constructs
+        .iter_mut()
+        .find(|construct| construct.name == construct_name)
+        .unwrap();
+    construct.auto_fill = Some(
+        fills
+            .iter()
+            .map(|fill| fill.map(|name| name.to_owned()))
+            .collect(),
+    );
+    s.add_language(lang)
+}
+
+#[test]
+fn test_auto_fill() {
+    // Without an auto_fill, children that can only be one construct are filled in.
+    let mut s = Storage::new();
+    s.add_language(urllang()).unwrap();
+    let c_url = s.language("urllang").unwrap().construct(&s, "Url").unwrap();
+    let url = Node::new_with_auto_fill(&mut s, c_url);
+    let child_names = url
+        .try_children(&s)
+        .unwrap()
+        .into_iter()
+        .map(|child| child.construct(&s).name(&s).to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(child_names, vec!["String", "Params"]);
+
+    // An explicit auto_fill overrides that, and `None` leaves a hole.
+    let mut s = Storage::new();
+    urllang_with_auto_fill(&mut s, "Url", &[None, Some("Params")]).unwrap();
+    let c_url = s.language("urllang").unwrap().construct(&s, "Url").unwrap();
+    let url = Node::new_with_auto_fill(&mut s, c_url);
+    assert!(url.nth_child(&s, 0).unwrap().is_hole(&s));
+    assert_eq!(
+        url.nth_child(&s, 1).unwrap().construct(&s).name(&s),
+        "Params"
+    );
+    // `Node::new()` never auto-fills.
+    let url = Node::new(&mut s, c_url);
+    assert!(url.nth_child(&s, 1).unwrap().is_hole(&s));
+}
+
+#[test]
+fn test_auto_fill_errors() {
+    assert!(matches!(
+        urllang_with_auto_fill(&mut Storage::new(), "Params", &[Some("String")]),
+        Err(LanguageError::AutoFillNotFixed(name)) if name == "Params"
+    ));
+    assert!(matches!(
+        urllang_with_auto_fill(&mut Storage::new(), "Url", &[None]),
+        Err(LanguageError::AutoFillWrongLength(name, 1, 2)) if name == "Url"
+    ));
+    assert!(matches!(
+        urllang_with_auto_fill(&mut Storage::new(), "Url", &[Some("Params"), None]),
+        Err(LanguageError::AutoFillWrongSort(name, fill)) if name == "Url" && fill == "Params"
+    ));
+    assert!(matches!(
+        urllang_with_auto_fill(&mut Storage::new(), "Url", &[Some("Nope"), None]),
+        Err(LanguageError::UndefinedConstruct(name)) if name == "Nope"
+    ));
+
+    // `Outer` and `Inner` can each only contain the other, so auto-filling either would never end.
+    let mut lang = urllang();
+    for (name, child_name) in [("Outer", "Inner"), ("Inner", "Outer")] {
+        lang.grammar.constructs.push(ConstructSpec {
+            name: name.to_owned(),
+            arity: AritySpec::Fixed(vec![SortSpec(vec![child_name.to_owned()])]),
+            is_comment_or_ws: false,
+            is_hidden: false,
+            is_prose: false,
+            key: None,
+            auto_fill: None,
+            summary: None,
+        });
+    }
+    assert!(matches!(
+        Storage::new().add_language(lang.clone()),
+        Err(LanguageError::AutoFillCycle(_))
+    ));
+
+    // Leaving one of them as a hole breaks the cycle.
+    lang.grammar.constructs.last_mut().unwrap().auto_fill = Some(vec![None]);
+    let notations = &mut lang.notations[0].notations;
+    notations.push(("Outer".to_owned(), ppp::notation_constructors::child(0)));
+    notations.push(("Inner".to_owned(), ppp::notation_constructors::child(0)));
+    Storage::new().add_language(lang).unwrap();
+}
+
+#[test]
+fn test_tree_errors() {
+    let mut s = Storage::new();
+    s.add_language(urllang()).unwrap();
+    let lang = s.language("urllang").unwrap();
+    let c_equals = lang.construct(&s, "Equals").unwrap();
+
+    let domain = node_with_text(&mut s, "urllang", "String", "example.com");
+    let params = node_with_children(&mut s, "urllang", "Params", []);
+    let url = node_with_children(&mut s, "urllang", "Url", [domain, params]);
+
+    // Arity violations
+    assert_eq!(
+        Node::with_text(&mut s, c_equals, "a".to_owned()),
+        Err(TreeError::NotTexty("Equals".to_owned()))
+    );
+    let param = node_with_text(&mut s, "urllang", "String", "param");
+    assert_eq!(
+        Node::with_children(&mut s, c_equals, [param]),
+        Err(TreeError::WrongNumberOfChildren("Equals".to_owned(), 2, 1))
+    );
+    let nested_params = node_with_children(&mut s, "urllang", "Params", []);
+    assert_eq!(
+        params.insert_last_child(&mut s, nested_params),
+        Err(TreeError::NotAllowedHere("Params".to_owned()))
+    );
+    assert_eq!(
+        domain.insert_after(&mut s, param),
+        Err(TreeError::NotListy("Url".to_owned()))
+    );
+    assert_eq!(
+        params.detach(&mut s),
+        Err(TreeError::NotListy("Url".to_owned()))
+    );
+
+    // Structural misuse
+    assert_eq!(
+        params.insert_last_child(&mut s, url),
+        Err(TreeError::Overlapping)
+    );
+    assert_eq!(url.detach(&mut s), Err(TreeError::IsRoot));
+    assert_eq!(domain.delete_root(&mut s), Err(TreeError::NotRoot));
+
+    // Stale nodes
+    assert_eq!(param.delete_root(&mut s), Ok(()));
+    assert_eq!(param.delete_root(&mut s), Err(TreeError::DeletedNode));
+    assert_eq!(
+        params.insert_last_child(&mut s, param),
+        Err(TreeError::DeletedNode)
+    );
+
+    // Nothing was changed by the failed operations
+    assert_eq!(url.nth_child(&s, 0), Some(domain));
+    assert_eq!(url.nth_child(&s, 1), Some(params));
+    assert_eq!(params.num_children(&s), Some(0));
+}
+
+#[test]
+fn test_weak_node() {
+    let mut s = Storage::new();
+    s.add_language(urllang()).unwrap();
+
+    let domain = node_with_text(&mut s, "urllang", "String", "example.com");
+    let params = node_with_children(&mut s, "urllang", "Params", []);
+    let url = node_with_children(&mut s, "urllang", "Url", [domain, params]);
+    let param = node_with_text(&mut s, "urllang", "String", "param");
+    params.insert_last_child(&mut s, param).unwrap();
+
+    let weak_param = param.downgrade();
+    assert_eq!(weak_param.upgrade(&s), Some(param));
+    let cursor = Location::at(&s, domain);
+    let mark = Location::at(&s, param).bookmark();
+    assert_eq!(
+        cursor.validate_bookmark(&s, mark),
+        Some(Location::at(&s, param))
+    );
+
+    // Once the node is deleted, neither it nor a node made after it can be reached.
+    param.detach(&mut s).unwrap();
+    param.delete_root(&mut s).unwrap();
+    let new_param = node_with_text(&mut s, "urllang", "String", "param");
+    assert_eq!(weak_param.upgrade(&s), None);
+    assert_eq!(cursor.validate_bookmark(&s, mark), None);
+    assert_eq!(new_param.downgrade().upgrade(&s), Some(new_param));
+    assert_eq!(url.downgrade().upgrade(&s), Some(url));
+}
+
+#[test]
+fn test_visit_mut() {
+    let mut s = Storage::new();
+    s.add_language(urllang()).unwrap();
+
+    let domain = node_with_text(&mut s, "urllang", "String", "example.com");
+    let p1 = node_with_text(&mut s, "urllang", "String", "pone");
+    let v1 = node_with_text(&mut s, "urllang", "String", "vone");
+    let equals = node_with_children(&mut s, "urllang", "Equals", [p1, v1]);
+    let p2 = node_with_text(&mut s, "urllang", "String", "ptwo");
+    let params = node_with_children(&mut s, "urllang", "Params", [equals, p2]);
+    let url = node_with_children(&mut s, "urllang", "Url", [domain, params]);
+
+    // Skip the insides of `Equals`, and replace the domain with a hole along the way.
+    let mut visited = Vec::new();
+    let finished = url.visit_mut(&mut s, |s, node| {
+        visited.push(node);
+        if node == equals {
+            Visit::SkipChildren
+        } else {
+            if node == domain {
+                let hole = Node::new_hole(s, node.language(s));
+                node.swap(s, hole).unwrap();
+            }
+            Visit::Continue
+        }
+    });
+    assert!(finished);
+    assert_eq!(visited, vec![url, domain, params, equals, p2]);
+    assert!(url.first_child(&s).unwrap().is_hole(&s));
+    assert!(domain.is_root(&s));
+
+    // Stop at the first hole.
+    let mut visited = Vec::new();
+    let finished = url.visit_mut(&mut s, |s, node| {
+        visited.push(node);
+        if node.is_hole(s) {
+            Visit::Stop
+        } else {
+            Visit::Continue
+        }
+    });
+    assert!(!finished);
+    assert_eq!(visited.len(), 2);
+
+    // Delete `Equals` and keep going. Its children were deleted with it, so they're skipped.
+    let mut visited = Vec::new();
+    let finished = url.visit_mut(&mut s, |s, node| {
+        visited.push(node);
+        if node == equals {
+            let hole = Node::new_hole(s, node.language(s));
+            node.swap(s, hole).unwrap();
+            node.delete_root(s).unwrap();
+        }
+        Visit::Continue
+    });
+    assert!(finished);
+    assert_eq!(visited.len(), 5);
+    assert_eq!(visited[3], equals);
+    assert_eq!(visited[4], p2);
+    assert!(!equals.is_valid(&s));
+    assert!(!p1.is_valid(&s));
+}
+
+#[test]
+fn test_node_changes() {
+    let mut s = Storage::new();
+    s.add_language(urllang()).unwrap();
+
+    // example.com?p1=v1&p2=v2&p3=v3
+    let old = make_url(&mut s, &["v1", "v2", "v3"], None);
+    // example.com?p1=v1&new&p2=changed&p3=v3
+    let new = make_url(&mut s, &["v1", "changed", "v3"], Some("new"));
+    assert!(old.changes_since(&s, old).is_empty());
+
+    let params = new.nth_child(&s, 1).unwrap();
+    let added = params.nth_child(&s, 1).unwrap();
+    let modified = params.nth_child(&s, 2).unwrap().nth_child(&s, 1).unwrap();
+    assert_eq!(
+        new.changes_since(&s, old),
+        vec![(added, NodeChange::Added), (modified, NodeChange::Modified)]
+    );
+
+    let old_val = old
+        .nth_child(&s, 1)
+        .unwrap()
+        .nth_child(&s, 1)
+        .unwrap()
+        .nth_child(&s, 1)
+        .unwrap();
+    assert_eq!(
+        new.edit_script(&s, old),
+        vec![
+            DiffOp::Delete(added),
+            DiffOp::Modify {
+                old: modified,
+                new: old_val
+            }
+        ]
+    );
+}
+
+#[test]
+fn test_node_path() {
+    let mut s = Storage::new();
+    s.add_language(urllang()).unwrap();
+
+    let domain = node_with_text(&mut s, "urllang", "String", "example.com");
+    let flag = node_with_text(&mut s, "urllang", "String", "flag");
+    let key = node_with_text(&mut s, "urllang", "String", "key");
+    let val = node_with_text(&mut s, "urllang", "String", "val");
+    let equals = node_with_children(&mut s, "urllang", "Equals", [key, val]);
+    let params = node_with_children(&mut s, "urllang", "Params", [flag, equals]);
+    let url = node_with_children(&mut s, "urllang", "Url", [domain, params]);
+
+    assert_eq!(url.path(&s), "root");
+    assert_eq!(val.path(&s), "root.1.1.1");
+    assert_eq!(url.at_path(&s, "root.1.1.1").unwrap(), val);
+    assert_eq!(url.at_path(&s, "root.Params.Equals.1").unwrap(), val);
+    assert_eq!(url.at_path(&s, "root.Params.String[0]").unwrap(), flag);
+    assert_eq!(url.at_path(&s, "root").unwrap(), url);
+    assert!(matches!(
+        url.at_path(&s, "root.1.2"),
+        Err(TreeError::NoNodeAtPath(_, _))
+    ));
+    assert!(matches!(
+        url.at_path(&s, "1.1"),
+        Err(TreeError::InvalidPath(_))
+    ));
+}
+
+#[test]
+fn test_merge() {
+    let mut s = Storage::new();
+    s.add_language(urllang()).unwrap();
+
+    // Edits to different parts of the tree are combined
+    let base = make_url(&mut s, &["v1", "v2", "v3"], None);
+    let ours = make_url(&mut s, &["ours", "v2", "v3"], Some("new"));
+    let theirs = make_url(&mut s, &["v1", "v2", "theirs"], None);
+    let (merged, conflicts) = merge(&mut s, base, ours, theirs);
+    assert!(conflicts.is_empty());
+    let expected = make_url(&mut s, &["ours", "v2", "theirs"], Some("new"));
+    assert!(merged.equals(&s, expected), "{}", merged.display(&s));
+
+    // Different edits to the same text conflict
+    let ours = make_url(&mut s, &["v1", "ours", "v3"], None);
+    let theirs = make_url(&mut s, &["v1", "theirs", "v3"], None);
+    let (merged, conflicts) = merge(&mut s, base, ours, theirs);
+    assert_eq!(conflicts.len(), 1);
+    let conflict = conflicts[0];
+    let val = merged
+        .nth_child(&s, 1)
+        .unwrap()
+        .nth_child(&s, 1)
+        .unwrap()
+        .nth_child(&s, 1)
+        .unwrap();
+    assert_eq!(val.id(&s), conflict.node);
+    assert_eq!(val.text(&s).unwrap().as_str(), "ours");
+    assert_eq!(conflict.ours.text(&s).unwrap().as_str(), "ours");
+    assert_eq!(conflict.theirs.text(&s).unwrap().as_str(), "theirs");
+}